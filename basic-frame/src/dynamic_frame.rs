@@ -59,6 +59,8 @@ macro_rules! convert_to_dynamic2 {
             PixFmt::BayerBG8 => DynamicFrame::BayerBG8($x),
             PixFmt::BayerBG32f => DynamicFrame::BayerBG32f($x),
             PixFmt::YUV422 => DynamicFrame::YUV422($x),
+            PixFmt::YUV444 => DynamicFrame::YUV444($x),
+            PixFmt::NV12 => DynamicFrame::NV12($x),
             _ => {
                 panic!("unsupported type {}", pixfmt);
             }
@@ -198,6 +200,10 @@ impl DynamicFrame {
                 image_data,
                 pixel_format: std::marker::PhantomData,
             })
+        } else if let DynamicFrame::NV12(nv12) = &self {
+            // Convert the planar data to packed YUV444 first, from which
+            // convert-image can produce the requested format.
+            DynamicFrame::YUV444(nv12_to_yuv444(nv12)).into_pixel_format()
         } else {
             let width = self.width();
             let height = self.height();
@@ -275,6 +281,37 @@ impl DynamicFrame {
     }
 }
 
+/// Upsample the chroma of an NV12 image to full resolution.
+#[cfg(feature = "convert-image")]
+fn nv12_to_yuv444(
+    frame: &BasicFrame<formats::pixel_format::NV12>,
+) -> BasicFrame<formats::pixel_format::YUV444> {
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    let stride = frame.stride as usize;
+    // The interleaved UV plane follows the Y plane, with the same stride.
+    let (y_plane, uv_plane) = frame.image_data.split_at(stride * height);
+    let mut image_data = vec![0u8; width * 3 * height];
+    for (row, dest_row) in image_data.chunks_exact_mut(width * 3).enumerate() {
+        let y_row = &y_plane[row * stride..];
+        let uv_row = &uv_plane[(row / 2) * stride..];
+        for (col, dest) in dest_row.chunks_exact_mut(3).enumerate() {
+            let uv = (col / 2) * 2;
+            dest[0] = y_row[col];
+            dest[1] = uv_row[uv];
+            dest[2] = uv_row[uv + 1];
+        }
+    }
+    BasicFrame {
+        width: frame.width,
+        height: frame.height,
+        stride: (width * 3) as u32,
+        image_data,
+        extra: frame.extra.clone(),
+        pixel_format: std::marker::PhantomData,
+    }
+}
+
 impl ExtraTimeData for DynamicFrame {
     fn extra(&self) -> &dyn HostTimeData {
         match_all_dynamic_fmts!(self, x, { x.extra.as_ref() })
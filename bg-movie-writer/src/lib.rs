@@ -1,12 +1,16 @@
 use std::{
     fs::File,
     io::{Seek, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
 };
 
 use basic_frame::{match_all_dynamic_fmts, DynamicFrame};
 use chrono::DateTime;
-use ci2_remote_control::FfmpegRecordingConfig;
+use ci2_remote_control::{FfmpegRecordingConfig, Mp4SegmentConfig};
 use machine_vision_formats::{ImageStride, PixelFormat};
 use mp4_writer::Mp4Writer;
 
@@ -33,6 +37,8 @@ pub enum Error {
     FilenameDoesNotEndWithMp4,
     #[error("ffmpeg writer error {0}")]
     FfmpegWriterError(#[from] ffmpeg_writer::Error),
+    #[error("writer thread panicked")]
+    ThreadPanicked,
 }
 
 impl From<channellib::SendError<Msg>> for Error {
@@ -44,6 +50,19 @@ impl From<channellib::SendError<Msg>> for Error {
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// Suffix added to the name of an MP4 file while it is being written.
+///
+/// The file is renamed to its final name when finished, so a file which still
+/// has this suffix was not finished, e.g. because the program crashed.
+pub const TMP_SUFFIX: &str = ".tmp";
+
+/// The name under which the file `path` is written until finished.
+pub fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(TMP_SUFFIX);
+    PathBuf::from(tmp)
+}
+
 macro_rules! async_err {
     ($rx: expr) => {
         match $rx.try_recv() {
@@ -59,10 +78,20 @@ macro_rules! async_err {
     };
 }
 
+/// Called with the path of each MP4 file once it is finished.
+pub type FileFinishedCallback = Box<dyn Fn(PathBuf) + Send>;
+
+/// Called with the timestamp of each frame once it is written.
+pub type FrameWrittenCallback = Box<dyn Fn(chrono::DateTime<chrono::Local>) + Send>;
+
 pub struct BgMovieWriter {
     tx: channellib::Sender<Msg>,
     is_done: bool,
     err_rx: channellib::Receiver<Error>,
+    postpone_segments: Arc<AtomicBool>,
+    on_file_finished: Arc<Mutex<Option<FileFinishedCallback>>>,
+    on_frame_written: Arc<Mutex<Option<FrameWrittenCallback>>>,
+    join_handle: Option<std::thread::JoinHandle<()>>,
 }
 
 impl BgMovieWriter {
@@ -71,22 +100,80 @@ impl BgMovieWriter {
         recording_config: ci2_remote_control::RecordingConfig,
         queue_size: usize,
         data_dir: Option<PathBuf>,
+    ) -> Self {
+        Self::new_segmented(
+            format_str_mp4,
+            recording_config,
+            Mp4SegmentConfig::default(),
+            queue_size,
+            data_dir,
+        )
+    }
+
+    /// Create a writer which splits the recording into multiple files.
+    ///
+    /// The first file is named according to `format_str_mp4`. Subsequent
+    /// files additionally have `_segNNN` inserted before the `.mp4`
+    /// extension.
+    pub fn new_segmented(
+        format_str_mp4: String,
+        recording_config: ci2_remote_control::RecordingConfig,
+        segment_config: Mp4SegmentConfig,
+        queue_size: usize,
+        data_dir: Option<PathBuf>,
     ) -> Self {
         let (err_tx, err_rx) = channellib::unbounded();
-        let tx = launch_runner(
+        let postpone_segments = Arc::new(AtomicBool::new(false));
+        let on_file_finished = Arc::new(Mutex::new(None));
+        let on_frame_written = Arc::new(Mutex::new(None));
+        let (tx, join_handle) = launch_runner(
             format_str_mp4,
             recording_config,
+            segment_config,
             queue_size,
             err_tx,
             data_dir,
+            postpone_segments.clone(),
+            on_file_finished.clone(),
+            on_frame_written.clone(),
         );
         Self {
             tx,
             is_done: false,
             err_rx,
+            postpone_segments,
+            on_file_finished,
+            on_frame_written,
+            join_handle: Some(join_handle),
         }
     }
 
+    /// Keep writing to the current segment while `postpone` is true.
+    ///
+    /// This avoids opening new files, e.g. when the limit on open files is
+    /// nearly reached. Once `postpone` is false again, a new segment is
+    /// started with the next frame if the current one is full.
+    pub fn set_postpone_segments(&self, postpone: bool) {
+        self.postpone_segments.store(postpone, Ordering::Relaxed);
+    }
+
+    /// Call `f` from the writer thread whenever an MP4 file is finished.
+    ///
+    /// With segmented recordings, `f` is called for each segment. `f` should
+    /// return quickly so that writing is not delayed.
+    pub fn set_on_file_finished(&self, f: FileFinishedCallback) {
+        *self.on_file_finished.lock().unwrap() = Some(f);
+    }
+
+    /// Call `f` from the writer thread whenever a frame is encoded and
+    /// written, e.g. to measure the latency of the recording.
+    ///
+    /// Frames skipped because of the maximum frame rate are not reported. `f`
+    /// should return quickly so that writing is not delayed.
+    pub fn set_on_frame_written(&self, f: FrameWrittenCallback) {
+        *self.on_frame_written.lock().unwrap() = Some(f);
+    }
+
     pub fn write<TS>(&mut self, frame: DynamicFrame, timestamp: TS) -> Result<()>
     where
         TS: Into<chrono::DateTime<chrono::Local>>,
@@ -106,6 +193,23 @@ impl BgMovieWriter {
         self.send(Msg::Finish)
     }
 
+    /// Finish the movie and block until all queued frames are written and the
+    /// file is closed.
+    pub fn finish_and_wait(&mut self) -> Result<()> {
+        // If finishing fails, the thread has already ended with an error, so
+        // it is still joined.
+        let finish_result = if self.is_done { Ok(()) } else { self.finish() };
+        if let Some(join_handle) = self.join_handle.take() {
+            join_handle.join().map_err(|_| Error::ThreadPanicked)?;
+        }
+        finish_result?;
+        // The thread has ended, so any error is already in the channel.
+        match self.err_rx.try_recv() {
+            Ok(e) => Err(e),
+            Err(_) => Ok(()),
+        }
+    }
+
     fn send(&mut self, msg: Msg) -> Result<()> {
         self.tx.send(msg)?;
         Ok(())
@@ -130,6 +234,43 @@ macro_rules! thread_try {
     };
 }
 
+/// A file which keeps track of its size while being written.
+struct SizeTrackingFile {
+    file: File,
+    pos: u64,
+    size: Arc<AtomicU64>,
+}
+
+impl SizeTrackingFile {
+    fn new(file: File) -> Self {
+        Self {
+            file,
+            pos: 0,
+            size: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl Write for SizeTrackingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.pos += n as u64;
+        self.size.fetch_max(self.pos, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for SizeTrackingFile {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.pos = self.file.seek(pos)?;
+        Ok(self.pos)
+    }
+}
+
 enum RawWriter<'lib, T>
 where
     T: Write + Seek,
@@ -171,21 +312,10 @@ impl MyFfmpegWriter {
             pre_codec_args: args.pre_codec_args.clone(),
             post_codec_args: args.post_codec_args.clone(),
         };
-        use ci2_remote_control::RecordingFrameRate::*;
-        let rate = match cfg.max_framerate {
-            Fps1 => Some((1, 1)),
-            Fps2 => Some((2, 1)),
-            Fps5 => Some((5, 1)),
-            Fps10 => Some((10, 1)),
-            Fps20 => Some((20, 1)),
-            Fps25 => Some((25, 1)),
-            Fps30 => Some((30, 1)),
-            Fps40 => Some((40, 1)),
-            Fps50 => Some((50, 1)),
-            Fps60 => Some((60, 1)),
-            Fps100 => Some((100, 1)),
-            Unlimited => None,
-        };
+        let rate = cfg
+            .max_framerate
+            .as_numerator_denominator()
+            .map(|(num, den)| (num as usize, den as usize));
         let fwtr = ffmpeg_writer::FfmpegWriter::new(mp4_filename, Some(ffmpeg_codec_args), rate)?;
         let out_fd = std::fs::File::create(&srt_filename)?;
         let swtr = srt_writer::BufferingSrtFrameWriter::new(Box::new(out_fd));
@@ -222,31 +352,176 @@ struct SrtMsg {
     timestamp: DateTime<chrono::Local>,
 }
 
+/// Filename of the segment with index `segment_index`.
+///
+/// `formatted_filename` is the name of the first segment.
+fn segment_filename(formatted_filename: &str, segment_index: u32) -> String {
+    if segment_index == 0 {
+        return formatted_filename.to_string();
+    }
+    match formatted_filename.strip_suffix(".mp4") {
+        Some(stem) => format!("{stem}_seg{segment_index:03}.mp4"),
+        None => format!("{formatted_filename}_seg{segment_index:03}"),
+    }
+}
+
+/// Tracks the current segment of a segmented recording.
+struct SegmentState {
+    config: Mp4SegmentConfig,
+    /// Index of the current segment.
+    index: u32,
+    /// Number of frames saved in previous segments.
+    first_frame: u64,
+    /// Number of frames saved in the current segment.
+    n_frames: u64,
+    start_stamp: Option<chrono::DateTime<chrono::Local>>,
+    /// Size of the current segment, if known.
+    size: Option<Arc<AtomicU64>>,
+    /// Filename of the first segment, from which the names of the following
+    /// segments are derived.
+    base_filename: Option<String>,
+}
+
+impl SegmentState {
+    fn new(config: Mp4SegmentConfig) -> Self {
+        Self {
+            config,
+            index: 0,
+            first_frame: 0,
+            n_frames: 0,
+            start_stamp: None,
+            size: None,
+            base_filename: None,
+        }
+    }
+
+    /// Return true if a new segment should be started before saving a frame
+    /// with timestamp `stamp`.
+    ///
+    /// The size of files written with ffmpeg is not known, so these are only
+    /// split by duration.
+    fn is_full(&self, stamp: chrono::DateTime<chrono::Local>) -> bool {
+        if self.n_frames == 0 {
+            return false;
+        }
+        if let (Some(max_secs), Some(start)) = (self.config.max_duration_secs, self.start_stamp) {
+            let elapsed = stamp - start;
+            if elapsed.num_microseconds().unwrap_or(i64::MAX) as f64 / 1e6 >= max_secs {
+                return true;
+            }
+        }
+        if let (Some(max_bytes), Some(size)) = (self.config.max_size_bytes, &self.size) {
+            if size.load(Ordering::Relaxed) >= max_bytes {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Advance to the next segment.
+    fn next(&mut self) {
+        self.index += 1;
+        self.first_frame += self.n_frames;
+        self.n_frames = 0;
+        self.start_stamp = None;
+        self.size = None;
+    }
+
+    /// Return the recording configuration for the current segment.
+    fn recording_config(
+        &self,
+        orig: &ci2_remote_control::RecordingConfig,
+    ) -> ci2_remote_control::RecordingConfig {
+        let mut cfg = orig.clone();
+        if self.config.is_enabled() {
+            if let ci2_remote_control::RecordingConfig::Mp4(ref mut c) = cfg {
+                if let Some(ref mut md) = c.h264_metadata {
+                    md.segment_index = Some(self.index);
+                    md.segment_first_frame = Some(self.first_frame);
+                }
+            }
+        }
+        cfg
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn launch_runner(
     format_str_mp4: String,
     recording_config: ci2_remote_control::RecordingConfig,
+    segment_config: Mp4SegmentConfig,
     size: usize,
     err_tx: channellib::Sender<Error>,
     data_dir: Option<PathBuf>,
-) -> channellib::Sender<Msg> {
+    postpone_segments: Arc<AtomicBool>,
+    on_file_finished: Arc<Mutex<Option<FileFinishedCallback>>>,
+    on_frame_written: Arc<Mutex<Option<FrameWrittenCallback>>>,
+) -> (channellib::Sender<Msg>, std::thread::JoinHandle<()>) {
     let (tx, rx) = channellib::bounded::<Msg>(size);
-    std::thread::spawn(move || {
+    let join_handle = std::thread::spawn(move || {
         // Load CUDA and nvidia-encode shared libs, but do not return error
         // (yet).
         let libs_result = nvenc::Dynlibs::new();
 
-        let mut raw: RawWriter<'_, File> = RawWriter::None;
+        let mut raw: RawWriter<'_, SizeTrackingFile> = RawWriter::None;
 
         let mut last_saved_stamp: Option<chrono::DateTime<chrono::Local>> = None;
 
+        let mut segment = SegmentState::new(segment_config);
+
+        // The temporary and final name of the MP4 file being written.
+        let mut pending_rename: Option<(PathBuf, PathBuf)> = None;
+
+        // Whether a full segment is being extended because new segments are
+        // postponed.
+        let mut is_postponing = false;
+
         loop {
             let msg = thread_try!(err_tx, rx.recv());
             match msg {
                 Msg::Write((frame, stamp)) => {
+                    let max_framerate = recording_config.max_framerate();
+                    let do_save = match last_saved_stamp {
+                        None => true,
+                        Some(last_stamp) => {
+                            let elapsed = stamp - last_stamp;
+                            elapsed >= chrono::Duration::from_std(max_framerate.interval()).unwrap()
+                        }
+                    };
+                    if !do_save {
+                        continue;
+                    }
+
+                    let start_next_segment = !raw.is_none() && segment.is_full(stamp) && {
+                        let postpone = postpone_segments.load(Ordering::Relaxed);
+                        if postpone && !is_postponing {
+                            log::warn!("postponing start of new MP4 segment");
+                        }
+                        is_postponing = postpone;
+                        !postpone
+                    };
+
+                    if start_next_segment {
+                        // Finish the current segment. The next one is opened
+                        // below.
+                        if let RawWriter::Mp4Writer(ref mut mp4_writer) = raw {
+                            thread_try!(err_tx, mp4_writer.finish());
+                        }
+                        if let Some((tmp, dest)) = pending_rename.take() {
+                            thread_try!(err_tx, std::fs::rename(tmp, &dest));
+                            notify_finished(&on_file_finished, dest);
+                        }
+                        raw = RawWriter::None;
+                        segment.next();
+                    }
+
                     if raw.is_none() {
-                        let local: chrono::DateTime<chrono::Local> =
-                            stamp.with_timezone(&chrono::Local);
-                        let formatted_filename = local.format(&format_str_mp4).to_string();
+                        let base_filename = segment.base_filename.get_or_insert_with(|| {
+                            let local: chrono::DateTime<chrono::Local> =
+                                stamp.with_timezone(&chrono::Local);
+                            local.format(&format_str_mp4).to_string()
+                        });
+                        let formatted_filename = segment_filename(base_filename, segment.index);
                         let mp4_filename = if let Some(data_dir) = &data_dir {
                             data_dir
                                 .join(formatted_filename)
@@ -258,10 +533,15 @@ fn launch_runner(
                         };
 
                         use ci2_remote_control::RecordingConfig::*;
-                        match &recording_config {
+                        segment.size = match segment.recording_config(&recording_config) {
                             Mp4(mp4_recording_config) => {
-                                let mp4_path = std::path::Path::new(&mp4_filename);
-                                let mp4_file = thread_try!(err_tx, std::fs::File::create(mp4_path));
+                                let mp4_path = Path::new(&mp4_filename);
+                                let mp4_tmp_path = tmp_path(mp4_path);
+                                let mp4_file = SizeTrackingFile::new(thread_try!(
+                                    err_tx,
+                                    std::fs::File::create(&mp4_tmp_path)
+                                ));
+                                let size = mp4_file.size.clone();
 
                                 let nv_enc = match &mp4_recording_config.codec {
                                     ci2_remote_control::Mp4Codec::H264NvEnc(_opts) => {
@@ -296,51 +576,63 @@ fn launch_runner(
 
                                 log::info!(
                                     "saving MP4 to {}",
-                                    std::fs::canonicalize(mp4_path).unwrap().display()
+                                    std::fs::canonicalize(&mp4_tmp_path).unwrap().display()
                                 );
 
                                 raw = RawWriter::Mp4Writer(thread_try!(
                                     err_tx,
                                     mp4_writer::Mp4Writer::new(
                                         mp4_file,
-                                        mp4_recording_config.clone(),
+                                        mp4_recording_config,
                                         nv_enc
                                     )
                                 ));
+                                pending_rename = Some((mp4_tmp_path, mp4_path.into()));
+                                Some(size)
                             }
                             Ffmpeg(c) => {
                                 raw = RawWriter::FfmpegWriter(Box::new(thread_try!(
                                     err_tx,
-                                    MyFfmpegWriter::new(&mp4_filename, c)
+                                    MyFfmpegWriter::new(&mp4_filename, &c)
                                 )));
+                                None
                             }
                         };
+                        segment.start_stamp = Some(stamp);
                     }
-                    let max_framerate = recording_config.max_framerate();
-                    let do_save = match last_saved_stamp {
-                        None => true,
-                        Some(last_stamp) => {
-                            let elapsed = stamp - last_stamp;
-                            elapsed >= chrono::Duration::from_std(max_framerate.interval()).unwrap()
-                        }
-                    };
-                    if do_save {
-                        match &mut raw {
-                            RawWriter::Mp4Writer(ref mut r) => {
-                                let result = match_all_dynamic_fmts!(&frame, x, r.write(x, stamp));
-                                thread_try!(err_tx, result);
-                                last_saved_stamp = Some(stamp);
-                            }
-                            RawWriter::FfmpegWriter(ref mut r) => {
-                                let result = match_all_dynamic_fmts!(&frame, x, r.write(x, stamp));
-                                thread_try!(err_tx, result);
-                                last_saved_stamp = Some(stamp);
-                            }
-                            RawWriter::None => {
-                                panic!("")
+
+                    match &mut raw {
+                        RawWriter::Mp4Writer(ref mut r) => {
+                            let result = match_all_dynamic_fmts!(&frame, x, r.write(x, stamp));
+                            if result.is_err() {
+                                // Save the frames written before the error.
+                                match r.finish() {
+                                    Ok(()) => {
+                                        if let Some((tmp, dest)) = pending_rename.take() {
+                                            thread_try!(err_tx, std::fs::rename(tmp, &dest));
+                                            notify_finished(&on_file_finished, dest);
+                                        }
+                                    }
+                                    Err(e) => {
+                                        log::error!("could not finish MP4 file after error: {e}");
+                                    }
+                                }
                             }
+                            thread_try!(err_tx, result);
+                        }
+                        RawWriter::FfmpegWriter(ref mut r) => {
+                            let result = match_all_dynamic_fmts!(&frame, x, r.write(x, stamp));
+                            thread_try!(err_tx, result);
                         }
+                        RawWriter::None => {
+                            panic!("")
+                        }
+                    }
+                    if let Some(f) = on_frame_written.lock().unwrap().as_ref() {
+                        f(stamp);
                     }
+                    last_saved_stamp = Some(stamp);
+                    segment.n_frames += 1;
                 }
                 Msg::Finish => {
                     match &mut raw {
@@ -348,14 +640,23 @@ fn launch_runner(
                             thread_try!(err_tx, mp4_writer.finish());
                         }
                         RawWriter::FfmpegWriter(_) => {}
-                        RawWriter::None => {
-                            panic!("")
-                        }
+                        // No frame was written, so there is no file.
+                        RawWriter::None => {}
+                    }
+                    if let Some((tmp, dest)) = pending_rename.take() {
+                        thread_try!(err_tx, std::fs::rename(tmp, &dest));
+                        notify_finished(&on_file_finished, dest);
                     }
                     return; // end the thread
                 }
             };
         }
     });
-    tx
+    (tx, join_handle)
+}
+
+fn notify_finished(on_file_finished: &Mutex<Option<FileFinishedCallback>>, path: PathBuf) {
+    if let Some(f) = on_file_finished.lock().unwrap().as_ref() {
+        f(path);
+    }
 }
@@ -2,7 +2,9 @@
 
 use serde::{Deserialize, Serialize};
 
-use flydra_types::{BraidCameraConfig, FakeSyncConfig, TriggerType, TriggerboxConfig};
+use flydra_types::{
+    BraidCameraConfig, FakeSyncConfig, NvencPoolConfig, TriggerType, TriggerboxConfig,
+};
 
 /// The Braid configuration error type.
 #[derive(thiserror::Error, Debug)]
@@ -144,6 +146,14 @@ pub struct MainbrainConfig {
     pub save_empty_data2d: bool,
     /// Secret to use for signing HTTP cookies (base64 encoded)
     pub secret_base64: Option<String>,
+    /// Require a JSON Web Token (JWT) for the JSON API at `/api/v1`.
+    ///
+    /// If true, requests to the API must have an `Authorization: Bearer
+    /// <token>` header with a token signed (HS256) with the base64-decoded
+    /// `secret_base64`, which must then be set. If false, the API uses the
+    /// same access token and cookie as the browser UI.
+    #[serde(default)]
+    pub api_jwt_auth: bool,
     /// For debugging: filename to store captured packet data.
     pub packet_capture_dump_fname: Option<std::path::PathBuf>,
     /// Threshold duration before logging error (msec).
@@ -158,6 +168,35 @@ pub struct MainbrainConfig {
     /// sending data to disk.
     #[serde(default = "default_write_buffer_size_num_messages")]
     pub write_buffer_size_num_messages: usize,
+    /// Free disk space thresholds for the output directory.
+    ///
+    /// Below `warn_below_bytes`, a warning is shown. Below `stop_below_bytes`,
+    /// saving of the `.braidz` file is stopped.
+    #[serde(default)]
+    pub disk_space: rust_cam_bui_types::DiskSpaceConfig,
+    /// Automatic resynchronization of the cameras, optional.
+    ///
+    /// If set, the cameras are synchronized again when the correspondence
+    /// between frame numbers and trigger pulses is found to be inconsistent.
+    /// Only used with a triggerbox.
+    pub auto_resync: Option<AutoResyncConfig>,
+    /// Encryption of the saved `.braidz` files, optional.
+    ///
+    /// If set, each `.braidz` file is encrypted when saving finishes.
+    pub braidz_encryption: Option<BraidzEncryptionConfig>,
+    /// Local directory for data which cannot be written to
+    /// `output_base_dirname`, optional.
+    ///
+    /// While the output directory is unwritable, e.g. because a network drive
+    /// dropped, the data is kept in memory and then in this directory until
+    /// the output directory is writable again. If it is not when saving
+    /// finishes, the remaining data is saved here. Defaults to `braidz-spill`
+    /// in the temporary directory.
+    pub braidz_spill_dir: Option<std::path::PathBuf>,
+    /// Stream of live 3D poses as UDP datagrams, optional.
+    ///
+    /// If set, each update of a Kalman estimate is sent as a JSON datagram.
+    pub live_pose_udp: Option<LivePoseUdpConfig>,
 }
 
 impl std::default::Default for MainbrainConfig {
@@ -174,14 +213,91 @@ impl std::default::Default for MainbrainConfig {
             model_server_addr: default_model_server_addr(),
             save_empty_data2d: true,
             secret_base64: None,
+            api_jwt_auth: false,
             packet_capture_dump_fname: None,
             acquisition_duration_allowed_imprecision_msec:
                 flydra_types::DEFAULT_ACQUISITION_DURATION_ALLOWED_IMPRECISION_MSEC,
             write_buffer_size_num_messages: default_write_buffer_size_num_messages(),
+            disk_space: Default::default(),
+            auto_resync: None,
+            braidz_encryption: None,
+            braidz_spill_dir: None,
+            live_pose_udp: None,
+        }
+    }
+}
+
+/// Parameters for the automatic resynchronization of the cameras.
+///
+/// For each camera, the time at which a frame was received minus the trigger
+/// time computed from its frame number with the clock model (the residual) is
+/// the latency of the camera, which is stable. The minimum residual is
+/// computed over windows of `window_frames` frames. If it differs from that of
+/// the previous window by more than `max_residual_change_frames` frame
+/// periods in `consecutive_windows` windows, or if the frame number of a
+/// camera decreases, the cameras are resynchronized.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutoResyncConfig {
+    #[serde(default = "default_window_frames")]
+    pub window_frames: usize,
+    #[serde(default = "default_max_residual_change_frames")]
+    pub max_residual_change_frames: f64,
+    #[serde(default = "default_consecutive_windows")]
+    pub consecutive_windows: usize,
+}
+
+fn default_window_frames() -> usize {
+    100
+}
+
+fn default_max_residual_change_frames() -> f64 {
+    0.5
+}
+
+fn default_consecutive_windows() -> usize {
+    3
+}
+
+impl Default for AutoResyncConfig {
+    fn default() -> Self {
+        Self {
+            window_frames: default_window_frames(),
+            max_residual_change_frames: default_max_residual_change_frames(),
+            consecutive_windows: default_consecutive_windows(),
         }
     }
 }
 
+/// Encryption of the saved `.braidz` files.
+///
+/// The key file is created with `braidz-cli keygen`. The same key is needed
+/// to decrypt the files later, so keep a copy of it in a safe place.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BraidzEncryptionConfig {
+    /// Path of the key file.
+    pub key_file: std::path::PathBuf,
+}
+
+/// Destination of the stream of live 3D poses.
+///
+/// The format of the datagrams is described in the documentation of
+/// `flydra2::LivePoseMessage`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LivePoseUdpConfig {
+    /// Unicast or multicast address, e.g. `239.255.42.99:4334`.
+    pub destination: std::net::SocketAddr,
+    /// Time-to-live of multicast datagrams. 1 keeps them on the local network.
+    #[serde(default = "default_multicast_ttl")]
+    pub multicast_ttl: u32,
+}
+
+fn default_multicast_ttl() -> u32 {
+    1
+}
+
 pub const fn default_write_buffer_size_num_messages() -> usize {
     10000
 }
@@ -202,6 +318,12 @@ pub struct BraidConfig {
     /// Triggerbox configuration.
     #[serde(default)]
     pub trigger: TriggerType,
+    /// Pool of NVENC encoder sessions for MP4 recording.
+    ///
+    /// If set, the cameras are balanced across the CUDA devices and
+    /// reassigned to another device if encoding fails.
+    #[serde(default)]
+    pub nvenc_pool: Option<NvencPoolConfig>,
     pub cameras: Vec<BraidCameraConfig>,
 }
 
@@ -214,6 +336,7 @@ impl From<BraidConfig1> for BraidConfig {
         BraidConfig {
             mainbrain: orig.mainbrain,
             trigger,
+            nvenc_pool: None,
             cameras: orig.cameras,
         }
     }
@@ -243,13 +366,29 @@ impl BraidConfig {
         // fixup self.mainbrain.output_base_dirname
         fixup_relative_path(&mut self.mainbrain.output_base_dirname, &dirname)?;
 
-        // fixup self.cameras.camera_settings_filename
+        // fixup self.mainbrain.braidz_encryption.key_file
+        if let Some(braidz_encryption) = self.mainbrain.braidz_encryption.as_mut() {
+            fixup_relative_path(&mut braidz_encryption.key_file, &dirname)?;
+        }
+
+        // fixup self.mainbrain.braidz_spill_dir
+        if let Some(braidz_spill_dir) = self.mainbrain.braidz_spill_dir.as_mut() {
+            fixup_relative_path(braidz_spill_dir, &dirname)?;
+        }
+
+        // fixup self.cameras.camera_settings_filename, profile_dir and working_dir
         for camera_config in self.cameras.iter_mut() {
             if let Some(ref mut camera_settings_filename) =
                 camera_config.camera_settings_filename.as_mut()
             {
                 fixup_relative_path(camera_settings_filename, &dirname)?;
             }
+            if let Some(profile_dir) = camera_config.profile_dir.as_mut() {
+                fixup_relative_path(profile_dir, &dirname)?;
+            }
+            if let Some(working_dir) = camera_config.working_dir.as_mut() {
+                fixup_relative_path(working_dir, &dirname)?;
+            }
         }
 
         Ok(())
@@ -265,6 +404,7 @@ impl std::default::Default for BraidConfig {
             // this with the `braid default-config` command) how to configure
             // the trigger box.
             trigger: TriggerType::TriggerboxV1(TriggerboxConfig::default()),
+            nvenc_pool: None,
             cameras: vec![
                 BraidCameraConfig::default_absdiff_config("fake-camera-1".to_string()),
                 BraidCameraConfig::default_absdiff_config("fake-camera-2".to_string()),
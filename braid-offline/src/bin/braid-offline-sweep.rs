@@ -0,0 +1,69 @@
+use clap::Parser;
+use color_eyre::eyre::{self as anyhow, WrapErr};
+use ordered_float::NotNan;
+
+use braid_offline::{
+    sweep::{sweep, SweepConfig, SweepOptions, SUMMARY_CSV_FNAME},
+    KalmanizeOptions,
+};
+
+/// Track a .braidz file with each combination of tracking parameters in a
+/// sweep file.
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Cli {
+    /// Input .braidz file
+    #[arg(short = 'd', long)]
+    data_src: std::path::PathBuf,
+    /// Sweep TOML file with the tracking parameters to vary
+    #[arg(short = 's', long)]
+    sweep: std::path::PathBuf,
+    /// Output directory (must not exist)
+    #[arg(short = 'o', long)]
+    output_dir: std::path::PathBuf,
+    /// Number of parameter sets tracked in parallel (0 uses all CPUs)
+    #[arg(short = 'j', long, default_value_t = 0)]
+    jobs: usize,
+    /// Set frames per second
+    #[arg(long)]
+    fps: Option<f64>,
+    /// Set start frame to start tracking
+    #[arg(long)]
+    start_frame: Option<u64>,
+    /// Set stop frame to stop tracking
+    #[arg(long)]
+    stop_frame: Option<u64>,
+}
+
+fn main() -> anyhow::Result<()> {
+    if std::env::var_os("RUST_LOG").is_none() {
+        std::env::set_var("RUST_LOG", "braid_offline=info,warn");
+    }
+    let _tracing_guard = env_tracing_logger::init();
+
+    let opt = Cli::parse();
+
+    let buf = std::fs::read_to_string(&opt.sweep)
+        .with_context(|| format!("loading sweep file {}", opt.sweep.display()))?;
+    let cfg: SweepConfig = toml::from_str(&buf)
+        .with_context(|| format!("parsing sweep file {}", opt.sweep.display()))?;
+
+    let opts = SweepOptions {
+        jobs: opt.jobs,
+        fps: opt.fps.map(NotNan::new).transpose()?,
+        kalmanize: KalmanizeOptions {
+            start_frame: opt.start_frame,
+            stop_frame: opt.stop_frame,
+            ..Default::default()
+        },
+    };
+
+    let results = sweep(&opt.data_src, &opt.output_dir, &cfg, &opts)?;
+    let n_failed = results.iter().filter(|r| r.outcome.is_err()).count();
+    println!(
+        "Tracked {} parameter sets ({n_failed} failed). Summary saved to {}.",
+        results.len(),
+        opt.output_dir.join(SUMMARY_CSV_FNAME).display()
+    );
+    Ok(())
+}
@@ -27,6 +27,8 @@ use groupby::{AscendingGroupIter, BufferedSortIter};
 #[cfg(feature = "backtrace")]
 use std::backtrace::Backtrace;
 
+pub mod sweep;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("{source}")]
@@ -109,6 +111,7 @@ fn to_point_info(row: &Data2dDistortedRow, idx: u8) -> NumberedRawUdpPoint {
             cur_val: row.cur_val,
             mean_val: row.mean_val,
             sumsqf_val: row.sumsqf_val,
+            appearance: None,
         },
     }
 }
@@ -208,6 +211,12 @@ pub struct KalmanizeOptions {
     pub start_frame: Option<u64>,
     pub stop_frame: Option<u64>,
     pub model_server_addr: Option<String>,
+    /// If set, track in determinism mode with these settings.
+    ///
+    /// The points of each camera are then processed in the order of their
+    /// index within the frame, regardless of the order of the rows in the 2D
+    /// data file. The settings are saved in the metadata of the output.
+    pub determinism: Option<flydra2::TrackingDeterminism>,
 }
 
 /// Perform offline tracking on the data
@@ -261,7 +270,15 @@ where
         output_dirname.display()
     );
 
-    let metadata_builder = flydra2::BraidMetadataBuilder::saving_program_name(saving_program_name);
+    let mut metadata_builder =
+        flydra2::BraidMetadataBuilder::saving_program_name(saving_program_name);
+    if let Some(determinism) = &opt2.determinism {
+        info!(
+            "tracking in determinism mode with seed {}",
+            determinism.seed
+        );
+        metadata_builder = metadata_builder.with_tracking_determinism(determinism.clone());
+    }
 
     let (local, metadata_fps, recon) = {
         let src_info = data_src.basic_info();
@@ -374,6 +391,8 @@ where
             mini_arena_debug_image_dir,
             write_buffer_size_num_messages:
                 braid_config_data::default_write_buffer_size_num_messages(),
+            braidz_encryption_key: None,
+            braidz_spill_dir: None,
         },
         cam_manager.clone(),
         Some(recon.clone()),
@@ -482,6 +501,8 @@ where
                         feature_detect_settings: Some(flydra_types::UpdateFeatureDetectSettings {
                             current_feature_detect_settings,
                         }),
+                        feature_detect_settings_overridden: false,
+                        provenance: None,
                     },
                 )
             })
@@ -651,7 +672,10 @@ where
                 pb.inc(1);
             }
 
-            for cam_rows in split_by_cam(rows).iter() {
+            for cam_rows in split_by_cam(rows).iter_mut() {
+                if opt.determinism.is_some() {
+                    cam_rows.sort_by_key(|row| row.frame_pt_idx);
+                }
                 let cam_name = orig_camn_to_cam_name
                     .get(&cam_rows[0].camn)
                     .expect("camn missing")
@@ -783,6 +807,29 @@ pub fn pick_csvgz_or_csv(csv_path: &Path) -> flydra2::Result<Box<dyn Read>> {
     }
 }
 
+/// The tracking parameters saved in the input or, if there are none, defaults
+/// for the number of cameras.
+fn saved_or_default_tracking_params(
+    parsed: &braidz_parser::incremental_parser::BasicInfoParsed,
+) -> anyhow::Result<TrackingParams> {
+    Ok(match parsed.tracking_params.clone() {
+        Some(tp) => tp,
+        None => {
+            let num_cams = parsed.cam_info.camid2camn.len();
+            match num_cams {
+                0 => {
+                    anyhow::bail!(
+                        "No tracking parameters specified, none found in \
+                    data_src, and no default is reasonable because zero cameras present."
+                    )
+                }
+                1 => flydra_types::default_tracking_params_flat_3d(),
+                _ => flydra_types::default_tracking_params_full_3d(),
+            }
+        }
+    })
+}
+
 /// This is our "real" main top-level function but we have some decoration we
 /// need to do in [main], so we name this differently.
 #[tracing::instrument(level = "debug", skip_all)]
@@ -822,29 +869,28 @@ pub async fn braid_offline_retrack(opt: Cli) -> anyhow::Result<()> {
             }
             tracking_params
         }
-        None => {
-            let parsed = data_src.basic_info();
-            match parsed.tracking_params.clone() {
-                Some(tp) => tp,
-                None => {
-                    let num_cams = data_src.basic_info().cam_info.camid2camn.len();
-                    match num_cams {
-                        0 => {
-                            anyhow::bail!(
-                                "No tracking parameters specified, none found in \
-                            data_src, and no default is reasonable because zero cameras present."
-                            )
-                        }
-                        1 => flydra_types::default_tracking_params_flat_3d(),
-                        _ => flydra_types::default_tracking_params_full_3d(),
-                    }
-                }
-            }
-        }
+        None => saved_or_default_tracking_params(data_src.basic_info())?,
+    };
+    let determinism = if opt.deterministic {
+        let seed = opt.seed.or_else(|| {
+            data_src
+                .basic_info()
+                .metadata
+                .tracking_determinism
+                .as_ref()
+                .map(|d| d.seed)
+        });
+        Some(match seed {
+            Some(seed) => flydra2::TrackingDeterminism { seed },
+            None => flydra2::TrackingDeterminism::new_random_seed(),
+        })
+    } else {
+        None
     };
     let opts = KalmanizeOptions {
         start_frame: opt.start_frame,
         stop_frame: opt.stop_frame,
+        determinism,
         ..Default::default()
     };
 
@@ -914,4 +960,12 @@ pub struct Cli {
     /// Disable display of progress indicator
     #[arg(long)]
     pub no_progress: bool,
+    /// Track in determinism mode, so that retracking gives bit-identical
+    /// results. The seed is taken from `--seed`, else from the input file if
+    /// it was tracked in determinism mode, else chosen randomly.
+    #[arg(long)]
+    pub deterministic: bool,
+    /// Seed for determinism mode
+    #[arg(long, requires = "deterministic")]
+    pub seed: Option<u64>,
 }
@@ -0,0 +1,311 @@
+//! Offline tracking with several sets of tracking parameters.
+//!
+//! A sweep file in the TOML format gives the parameters to vary:
+//!
+//! ```toml
+//! [grid]
+//! motion_noise_scale = [0.05, 0.1, 0.2]
+//! "hypothesis_test_params.minimum_pixel_abs_zscore" = [0.0, 1.0]
+//! ```
+//!
+//! Each key of `grid` is the name of a field of [TrackingParams], with dots
+//! separating nested fields, and all combinations of the values are tracked.
+//! The other fields are taken from the optional `[base]` table, a complete set
+//! of tracking parameters, or else from the input as in
+//! [braid_offline_retrack](crate::braid_offline_retrack).
+//!
+//! For each combination, [sweep] saves the tracking result `params_NNN.braidz`
+//! and the parameters used `params_NNN.toml`. The table [SUMMARY_CSV_FNAME]
+//! compares the results.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use color_eyre::eyre::{self as anyhow, WrapErr};
+use ordered_float::NotNan;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use braidz_parser::incremental_parser::IncrementalParser;
+use flydra_types::TrackingParams;
+
+use crate::{kalmanize, saved_or_default_tracking_params, KalmanizeOptions};
+
+/// The name of the table comparing the results in the output directory.
+pub const SUMMARY_CSV_FNAME: &str = "summary.csv";
+
+/// The contents of a sweep file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SweepConfig {
+    /// Tracking parameters which are not varied.
+    #[serde(default)]
+    pub base: Option<TrackingParams>,
+    /// The values of each varied parameter.
+    #[serde(default)]
+    pub grid: BTreeMap<String, Vec<toml::Value>>,
+}
+
+/// One combination of parameter values.
+#[derive(Debug, Clone)]
+pub struct ParameterSet {
+    /// The name of the outputs, `params_NNN`.
+    pub name: String,
+    /// The value of each varied parameter.
+    pub values: BTreeMap<String, toml::Value>,
+    pub tracking_params: TrackingParams,
+}
+
+impl SweepConfig {
+    /// All combinations of the values of [Self::grid], applied to `base`.
+    ///
+    /// The parameters are combined in alphabetical order, with the last
+    /// parameter varying fastest.
+    pub fn parameter_sets(&self, base: &TrackingParams) -> anyhow::Result<Vec<ParameterSet>> {
+        let base = toml::Value::try_from(base)?;
+        let mut combinations: Vec<BTreeMap<String, toml::Value>> = vec![BTreeMap::new()];
+        for (name, values) in self.grid.iter() {
+            if values.is_empty() {
+                anyhow::bail!("no values given for tracking parameter \"{name}\"");
+            }
+            let mut next = Vec::with_capacity(combinations.len() * values.len());
+            for combination in combinations.iter() {
+                for value in values.iter() {
+                    let mut combination = combination.clone();
+                    combination.insert(name.clone(), value.clone());
+                    next.push(combination);
+                }
+            }
+            combinations = next;
+        }
+
+        let mut sets = Vec::with_capacity(combinations.len());
+        for (i, values) in combinations.into_iter().enumerate() {
+            let mut params = base.clone();
+            for (name, value) in values.iter() {
+                set_parameter(&mut params, name, value.clone())?;
+            }
+            let tracking_params: TrackingParams = params
+                .try_into()
+                .with_context(|| format!("invalid tracking parameters {values:?}"))?;
+            sets.push(ParameterSet {
+                name: format!("params_{i:03}"),
+                values,
+                tracking_params,
+            });
+        }
+        Ok(sets)
+    }
+}
+
+/// Replace the value of the parameter `name`, with dots separating nested
+/// fields.
+fn set_parameter(params: &mut toml::Value, name: &str, value: toml::Value) -> anyhow::Result<()> {
+    let mut current = params;
+    for key in name.split('.') {
+        current = current.get_mut(key).ok_or_else(|| {
+            anyhow::anyhow!("unknown tracking parameter \"{name}\" (or it is not set in the base)")
+        })?;
+    }
+    *current = value;
+    Ok(())
+}
+
+/// Options for [sweep].
+#[derive(Debug, Clone, Default)]
+pub struct SweepOptions {
+    /// Number of parameter sets tracked in parallel. If zero, the number of
+    /// CPUs is used.
+    pub jobs: usize,
+    /// Set frames per second.
+    pub fps: Option<NotNan<f64>>,
+    pub kalmanize: KalmanizeOptions,
+}
+
+/// The statistics of a tracking result.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweepStats {
+    pub num_trajectories: usize,
+    pub num_kalman_estimates_rows: u64,
+    /// Mean distance, in pixels, between the detections and the projections of
+    /// the tracked positions.
+    pub mean_reprojection_distance_pixels: Option<f64>,
+}
+
+/// The result of tracking with one parameter set.
+#[derive(Debug, Clone)]
+pub struct SweepResult {
+    pub parameter_set: ParameterSet,
+    pub output_braidz: PathBuf,
+    /// The statistics of the result or, if tracking failed, the error.
+    pub outcome: Result<SweepStats, String>,
+}
+
+/// Track `data_src` with each parameter set of `cfg` and save the results in
+/// the new directory `output_dir`.
+///
+/// `data_src` is a `.braidz` file or a `.braid` directory. The parameter sets
+/// are tracked in parallel. A failure of one parameter set is recorded in its
+/// result and does not stop the others.
+pub fn sweep(
+    data_src: &Path,
+    output_dir: &Path,
+    cfg: &SweepConfig,
+    opts: &SweepOptions,
+) -> anyhow::Result<Vec<SweepResult>> {
+    let base = match &cfg.base {
+        Some(base) => base.clone(),
+        None => {
+            let src = IncrementalParser::open(data_src)
+                .with_context(|| format!("while opening file \"{}\"", data_src.display()))?
+                .parse_basics()
+                .with_context(|| format!("when parsing braidz file \"{}\"", data_src.display()))?;
+            saved_or_default_tracking_params(src.basic_info())?
+        }
+    };
+    let sets = cfg.parameter_sets(&base)?;
+
+    if output_dir.exists() {
+        anyhow::bail!("Path {} exists. Will not overwrite.", output_dir.display());
+    }
+    std::fs::create_dir_all(output_dir)?;
+    for set in sets.iter() {
+        // Convert to a `toml::Value` first, which saves tables after values.
+        let buf = toml::to_string(&toml::Value::try_from(&set.tracking_params)?)?;
+        std::fs::write(output_dir.join(format!("{}.toml", set.name)), buf)?;
+    }
+
+    let jobs = match opts.jobs {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        jobs => jobs,
+    };
+    info!(
+        "tracking {} parameter sets, {} in parallel",
+        sets.len(),
+        jobs.min(sets.len())
+    );
+
+    let next = AtomicUsize::new(0);
+    let outcomes = Mutex::new(vec![None; sets.len()]);
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(sets.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(set) = sets.get(i) else {
+                    break;
+                };
+                let output_braidz = output_dir.join(format!("{}.braidz", set.name));
+                let outcome = track_one(data_src, &output_braidz, set, opts);
+                match &outcome {
+                    Ok(_) => info!("finished {}", output_braidz.display()),
+                    Err(e) => warn!("tracking {} failed: {e:?}", set.name),
+                }
+                outcomes.lock().unwrap()[i] = Some(outcome.map_err(|e| format!("{e:#}")));
+            });
+        }
+    });
+
+    let results: Vec<SweepResult> = sets
+        .into_iter()
+        .zip(outcomes.into_inner().unwrap())
+        .map(|(parameter_set, outcome)| SweepResult {
+            output_braidz: output_dir.join(format!("{}.braidz", parameter_set.name)),
+            parameter_set,
+            outcome: outcome.unwrap(),
+        })
+        .collect();
+
+    write_summary(&output_dir.join(SUMMARY_CSV_FNAME), cfg, &results)?;
+    Ok(results)
+}
+
+fn track_one(
+    data_src: &Path,
+    output_braidz: &Path,
+    set: &ParameterSet,
+    opts: &SweepOptions,
+) -> anyhow::Result<SweepStats> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .worker_threads(2)
+        .thread_name(set.name.clone())
+        .build()?;
+    rt.block_on(async {
+        let src = IncrementalParser::open(data_src)?.parse_basics()?;
+        let save_performance_histograms = true;
+        let no_progress = true;
+        kalmanize(
+            src,
+            output_braidz,
+            opts.fps,
+            set.tracking_params.clone(),
+            opts.kalmanize.clone(),
+            save_performance_histograms,
+            "braid-offline-sweep",
+            no_progress,
+            None,
+        )
+        .await?;
+        Ok::<_, anyhow::Report>(())
+    })?;
+
+    let archive = braidz_parser::braidz_parse_path(output_braidz)?;
+    let (num_trajectories, num_kalman_estimates_rows) = archive
+        .kalman_estimates_info
+        .as_ref()
+        .map_or((0, 0), |info| (info.trajectories.len(), info.num_rows));
+    // The histogram stores 100 times the distance in pixels.
+    let mean_reprojection_distance_pixels = archive
+        .reprojection_distance_hlog
+        .as_ref()
+        .map(|hlog| braidz_types::HistogramSummary::from(hlog).mean / 100.0);
+    Ok(SweepStats {
+        num_trajectories,
+        num_kalman_estimates_rows,
+        mean_reprojection_distance_pixels,
+    })
+}
+
+fn write_summary(path: &Path, cfg: &SweepConfig, results: &[SweepResult]) -> anyhow::Result<()> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    let mut header = vec!["name"];
+    header.extend(cfg.grid.keys().map(String::as_str));
+    header.extend([
+        "num_trajectories",
+        "num_kalman_estimates_rows",
+        "mean_reprojection_distance_pixels",
+        "error",
+    ]);
+    wtr.write_record(&header)?;
+    for result in results.iter() {
+        let mut record = vec![result.parameter_set.name.clone()];
+        record.extend(
+            result
+                .parameter_set
+                .values
+                .values()
+                .map(ToString::to_string),
+        );
+        match &result.outcome {
+            Ok(stats) => record.extend([
+                stats.num_trajectories.to_string(),
+                stats.num_kalman_estimates_rows.to_string(),
+                stats
+                    .mean_reprojection_distance_pixels
+                    .map(|x| x.to_string())
+                    .unwrap_or_default(),
+                String::new(),
+            ]),
+            Err(e) => record.extend([String::new(), String::new(), String::new(), e.clone()]),
+        }
+        wtr.write_record(&record)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
@@ -245,6 +245,8 @@ where
         original_recording_time: None,
         save_empty_data2d: false, // We do filtering below, but is this correct?
         saving_program_name: env!("CARGO_PKG_NAME").to_string(),
+        provenance: vec![],
+        tracking_determinism: None,
     };
     let metadata_buf = serde_yaml::to_string(&metadata).unwrap();
 
@@ -78,3 +78,42 @@ async fn test_retrack() -> anyhow::Result<()> {
     braid_offline::braid_offline_retrack(opt).await?;
     Ok(())
 }
+
+#[tokio::test]
+async fn test_retrack_deterministic() -> anyhow::Result<()> {
+    const FNAME: &str = "20210608_164911_mainbrain_2d_only_short.braidz";
+    const SHA256SUM: &str = "6e453bc4c4e0ef8327ce47b3e30c8c0993ad77ff96c2ba79ca6c14eb76834835";
+
+    download_verify::download_verify(
+        format!("{}/{}", URL_BASE, FNAME).as_str(),
+        FNAME,
+        &download_verify::Hash::Sha256(SHA256SUM.into()),
+    )?;
+
+    let tmpdir = tempfile::tempdir()?; // cleanup on drop
+
+    let mut results = Vec::new();
+    for i in 0..2 {
+        let output = tmpdir.path().join(format!("test_retrack_{i}.braidz"));
+        let opt = braid_offline::Cli {
+            data_src: std::path::PathBuf::from(FNAME),
+            output: output.clone(),
+            no_progress: true,
+            deterministic: true,
+            seed: Some(1234),
+            ..Default::default()
+        };
+        braid_offline::braid_offline_retrack(opt).await?;
+
+        let archive = braidz_parser::braidz_parse_path(&output)?;
+        assert_eq!(
+            archive.metadata.tracking_determinism,
+            Some(flydra2::TrackingDeterminism { seed: 1234 })
+        );
+        // The `Debug` output of floats round-trips, so equal strings mean
+        // bit-identical values.
+        results.push(format!("{:?}", archive.kalman_estimates_table));
+    }
+    assert_eq!(results[0], results[1]);
+    Ok(())
+}
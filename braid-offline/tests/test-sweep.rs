@@ -0,0 +1,100 @@
+use color_eyre::eyre::{self as anyhow};
+
+use braid_offline::sweep::{sweep, SweepConfig, SweepOptions, SUMMARY_CSV_FNAME};
+
+const URL_BASE: &str = "https://strawlab-cdn.com/assets/";
+
+#[test]
+fn test_parameter_sets() -> anyhow::Result<()> {
+    let cfg: SweepConfig = toml::from_str(
+        r#"
+        [grid]
+        motion_noise_scale = [0.05, 0.1, 0.2]
+        "hypothesis_test_params.minimum_pixel_abs_zscore" = [0.0, 1.0]
+        "#,
+    )?;
+    let base = flydra_types::default_tracking_params_full_3d();
+    let sets = cfg.parameter_sets(&base)?;
+    assert_eq!(sets.len(), 6);
+    assert_eq!(sets[0].name, "params_000");
+    assert_eq!(sets[5].name, "params_005");
+    assert_eq!(sets[0].tracking_params.motion_noise_scale, 0.05);
+    assert_eq!(sets[5].tracking_params.motion_noise_scale, 0.2);
+    let zscore = |i: usize| {
+        sets[i]
+            .tracking_params
+            .hypothesis_test_params
+            .as_ref()
+            .unwrap()
+            .minimum_pixel_abs_zscore
+    };
+    assert_eq!(zscore(0), 0.0);
+    assert_eq!(zscore(1), 1.0);
+    // Unchanged parameters are from the base.
+    assert_eq!(
+        sets[3].tracking_params.initial_position_std_meters,
+        base.initial_position_std_meters
+    );
+
+    let cfg: SweepConfig = toml::from_str(
+        r#"
+        [grid]
+        no_such_parameter = [1.0]
+        "#,
+    )?;
+    assert!(cfg.parameter_sets(&base).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_sweep() -> anyhow::Result<()> {
+    const FNAME: &str = "20210608_164911_mainbrain_2d_only_short.braidz";
+    const SHA256SUM: &str = "6e453bc4c4e0ef8327ce47b3e30c8c0993ad77ff96c2ba79ca6c14eb76834835";
+
+    download_verify::download_verify(
+        format!("{}/{}", URL_BASE, FNAME).as_str(),
+        FNAME,
+        &download_verify::Hash::Sha256(SHA256SUM.into()),
+    )?;
+
+    let tmpdir = tempfile::tempdir()?; // cleanup on drop
+    let output_dir = tmpdir.path().join("sweep");
+
+    let cfg: SweepConfig = toml::from_str(
+        r#"
+        [grid]
+        motion_noise_scale = [0.1, 0.2]
+        "#,
+    )?;
+    let opts = SweepOptions {
+        jobs: 2,
+        ..Default::default()
+    };
+    let results = sweep(std::path::Path::new(FNAME), &output_dir, &cfg, &opts)?;
+    assert_eq!(results.len(), 2);
+    for result in results.iter() {
+        assert!(result.outcome.is_ok(), "{:?}", result.outcome);
+        assert!(result.output_braidz.exists());
+        let params_toml = output_dir.join(format!("{}.toml", result.parameter_set.name));
+        let _: flydra_types::TrackingParams =
+            toml::from_str(&std::fs::read_to_string(params_toml)?)?;
+    }
+
+    let mut rdr = csv::Reader::from_path(output_dir.join(SUMMARY_CSV_FNAME))?;
+    assert_eq!(
+        rdr.headers()?.iter().collect::<Vec<_>>(),
+        [
+            "name",
+            "motion_noise_scale",
+            "num_trajectories",
+            "num_kalman_estimates_rows",
+            "mean_reprojection_distance_pixels",
+            "error"
+        ]
+    );
+    assert_eq!(rdr.records().count(), 2);
+
+    // The output directory is not overwritten.
+    assert!(sweep(std::path::Path::new(FNAME), &output_dir, &cfg, &opts).is_err());
+    Ok(())
+}
@@ -36,6 +36,8 @@ pub fn auto_config<P: AsRef<std::path::Path>>(
                     input_video.push(VideoSourceConfig {
                         filename,
                         camera_name: None,
+                        intrinsics_override: None,
+                        mp4_track: None,
                     });
                     break;
                 }
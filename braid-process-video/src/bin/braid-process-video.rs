@@ -4,7 +4,10 @@ use color_eyre::{
     Result,
 };
 
-use braid_process_video::{auto_config, run_config, BraidRetrackVideoConfig, Validate};
+use braid_process_video::{auto_config, run_config, BraidRetrackVideoConfig, QcFailed, Validate};
+
+/// The exit code if the quality control thresholds were exceeded.
+const QC_FAILED_EXIT_CODE: i32 = 2;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -93,6 +96,14 @@ async fn main() -> Result<()> {
         cfg_as_string
     );
 
-    run_config(&cfg).await?;
+    if let Err(err) = run_config(&cfg).await {
+        // Exit with a distinct code if the outputs were written but the
+        // quality control failed.
+        if let Some(qc_failed) = err.downcast_ref::<QcFailed>() {
+            tracing::error!("{qc_failed}");
+            std::process::exit(QC_FAILED_EXIT_CODE);
+        }
+        return Err(err);
+    }
     Ok(())
 }
@@ -123,6 +123,7 @@ fn rows2result_no_video(
             timestamp,
             image: None,
             this_cam_this_frame,
+            camera_settings: None,
         });
     }
 
@@ -193,7 +194,8 @@ impl Iterator for BraidArchiveNoVideoData {
 
 struct BraidArchivePerCam<'a> {
     cam_name: String,
-    frame_reader: Peek2<Box<dyn Iterator<Item = Result<FrameData>>>>,
+    /// The reader of the video. `None` if the camera has no video.
+    frame_reader: Option<Peek2<Box<dyn Iterator<Item = Result<FrameData>>>>>,
     cam_num: CamNum,
     cam_rows_peek_iter: std::iter::Peekable<std::slice::Iter<'a, Data2dDistortedRow>>,
 }
@@ -207,10 +209,15 @@ pub(crate) fn as_ros_camid(raw_name: &str) -> String {
 
 /// Iterate across multiple movies with a simultaneously recorded .braidz file
 /// used to synchronize the frames.
+///
+/// Cameras of the .braidz file without a video contribute only their 2D
+/// detections.
 pub(crate) struct BraidArchiveSyncVideoData<'a> {
     recon: Option<flydra_mvg::FlydraMultiCameraSystem<f64>>,
     kests: IndexedKEsts,
     per_cam: Vec<BraidArchivePerCam<'a>>,
+    /// The number of cameras with a video.
+    n_video_cams: usize,
     sync_threshold: chrono::Duration,
     cur_braidz_frame: i64,
     did_have_all: bool,
@@ -221,7 +228,7 @@ impl<'a> BraidArchiveSyncVideoData<'a> {
         archive: braidz_parser::BraidzArchive<std::io::BufReader<std::fs::File>>,
         data2d: &'a BTreeMap<CamNum, Vec<Data2dDistortedRow>>,
         camera_names: &[&str],
-        frame_readers: Vec<Peek2<Box<dyn Iterator<Item = Result<FrameData>>>>>,
+        frame_readers: Vec<Option<Peek2<Box<dyn Iterator<Item = Result<FrameData>>>>>>,
         sync_threshold: chrono::Duration,
     ) -> Result<Self> {
         assert_eq!(camera_names.len(), frame_readers.len());
@@ -230,23 +237,28 @@ impl<'a> BraidArchiveSyncVideoData<'a> {
         // `approx_start_time` when this is called.
 
         // Get time of first frame for each reader.
-        let t0: Vec<DateTime<Utc>> = frame_readers
+        let (video_idx, t0): (Vec<usize>, Vec<DateTime<Utc>>) = frame_readers
             .iter()
-            .map(|x| {
-                x.peek1()
+            .enumerate()
+            .filter_map(|(i, x)| {
+                let t0 = x
+                    .as_ref()?
+                    .peek1()
                     .unwrap()
                     .as_ref()
                     .unwrap()
                     .decoded()
                     .unwrap()
                     .extra()
-                    .host_timestamp()
+                    .host_timestamp();
+                Some((i, t0))
             })
-            .collect();
+            .unzip();
+        let n_video_cams = video_idx.len();
 
         // Get earliest starting video
-        let i = t0.iter().argmin().unwrap();
-        let earliest_start_rdr = &frame_readers[i];
+        let i = video_idx[t0.iter().argmin().unwrap()];
+        let earliest_start_rdr = frame_readers[i].as_ref().unwrap();
         let earliest_start_cam_name = &camera_names[i];
 
         let camid2camn = &archive.cam_info.camid2camn;
@@ -338,6 +350,7 @@ impl<'a> BraidArchiveSyncVideoData<'a> {
             recon,
             kests,
             per_cam,
+            n_video_cams,
             cur_braidz_frame: found_frame,
             sync_threshold,
             did_have_all: false,
@@ -360,8 +373,9 @@ impl<'a> Iterator for BraidArchiveSyncVideoData<'a> {
 
             let mut trigger_timestamp = None;
 
-            // Iterate across all input mp4 cameras.
-            let camera_pictures: Vec<Result<crate::OutTimepointPerCamera>> = self
+            // Iterate across all input mp4 cameras. Cameras without video and
+            // without data in this frame are `None`.
+            let camera_pictures: Vec<Result<Option<crate::OutTimepointPerCamera>>> = self
                 .per_cam
                 .iter_mut()
                 .map(|this_cam| {
@@ -390,6 +404,18 @@ impl<'a> Iterator for BraidArchiveSyncVideoData<'a> {
                         }
                     }
 
+                    let Some(frame_reader) = this_cam.frame_reader.as_mut() else {
+                        // Without video, only the 2D detections are used.
+                        return Ok(this_cam_this_frame.first().map(|row0| {
+                            crate::OutTimepointPerCamera::new(
+                                (&row0.cam_received_timestamp).into(),
+                                None,
+                                this_cam_this_frame.clone(),
+                                None,
+                            )
+                        }));
+                    };
+
                     if this_cam_this_frame.is_empty() {
                         panic!(
                             "missing 2d data in braid archive for frame {}",
@@ -416,7 +442,7 @@ impl<'a> Iterator for BraidArchiveSyncVideoData<'a> {
                     let mut found = false;
 
                     // Now get the next MP4 frame and ensure its timestamp is correct.
-                    if let Some(peek1_frame) = this_cam.frame_reader.peek1() {
+                    if let Some(peek1_frame) = frame_reader.peek1() {
                         let frame_data = peek1_frame
                             .as_ref()
                             .unwrap();
@@ -444,11 +470,15 @@ impl<'a> Iterator for BraidArchiveSyncVideoData<'a> {
                     let mp4_frame = if found {
                         n_cams_this_frame += 1;
                         // Take this MP4 frame image data.
-                        this_cam.frame_reader.next()
+                        frame_reader.next()
                     } else {
                         None
                     };
 
+                    let camera_settings = match &mp4_frame {
+                        Some(Ok(f)) => f.camera_settings().cloned(),
+                        _ => None,
+                    };
                     let mp4_frame = match mp4_frame {
                         Some(Ok(f)) => f.take_decoded(),
                         Some(Err(e)) => {
@@ -457,16 +487,17 @@ impl<'a> Iterator for BraidArchiveSyncVideoData<'a> {
                         None => None,
                     };
 
-                    Ok(crate::OutTimepointPerCamera::new(
+                    Ok(Some(crate::OutTimepointPerCamera::new(
                         row0_pts_chrono,
                         mp4_frame,
                         this_cam_this_frame,
-                    ))
+                        camera_settings,
+                    )))
                 })
                 .collect();
 
             // All mp4 files done. End.
-            if n_cams_done == self.per_cam.len() {
+            if n_cams_done == self.n_video_cams {
                 return None;
             }
 
@@ -478,7 +509,7 @@ impl<'a> Iterator for BraidArchiveSyncVideoData<'a> {
                 kalman_estimates,
             });
 
-            let camera_pictures: Result<Vec<crate::OutTimepointPerCamera>> =
+            let camera_pictures: Result<Vec<Option<crate::OutTimepointPerCamera>>> =
                 camera_pictures.into_iter().collect();
 
             let camera_pictures = match camera_pictures {
@@ -488,7 +519,16 @@ impl<'a> Iterator for BraidArchiveSyncVideoData<'a> {
                 }
             };
 
-            let timestamp = camera_pictures[0].timestamp;
+            // Cameras with video always have data in the frame.
+            let timestamp = camera_pictures.iter().flatten().next().unwrap().timestamp;
+            let camera_pictures: Vec<crate::OutTimepointPerCamera> = camera_pictures
+                .into_iter()
+                .map(|cp| {
+                    cp.unwrap_or_else(|| {
+                        crate::OutTimepointPerCamera::new(timestamp, None, vec![], None)
+                    })
+                })
+                .collect();
 
             if self.did_have_all {
                 return Some(Ok(SyncedPictures {
@@ -500,7 +540,7 @@ impl<'a> Iterator for BraidArchiveSyncVideoData<'a> {
             } else {
                 // If we haven't yet had a frame with all cameras, check if this
                 // is the first such.
-                self.did_have_all = n_cams_this_frame == self.per_cam.len();
+                self.did_have_all = n_cams_this_frame == self.n_video_cams;
                 if self.did_have_all {
                     return Some(Ok(SyncedPictures {
                         timestamp,
@@ -1,10 +1,12 @@
 use color_eyre::{
-    eyre::{self as anyhow},
+    eyre::{self as anyhow, WrapErr},
     Result,
 };
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 
+use frame_source::mp4_source::Mp4TrackSelection;
+
 /// A wrapper newtype indicating the inner type has been validated.
 pub struct Valid<T>(T);
 
@@ -34,6 +36,10 @@ pub enum OutputConfig {
     DebugTxt(DebugOutputConfig),
     #[serde(rename = "braidz")]
     Braidz(BraidzOutputConfig),
+    #[serde(rename = "html_report")]
+    HtmlReport(HtmlReportOutputConfig),
+    #[serde(rename = "events_csv")]
+    EventsCsv(EventsCsvOutputConfig),
 }
 
 impl Default for OutputConfig {
@@ -48,6 +54,12 @@ impl Validate for OutputConfig {
             OutputConfig::Video(v) => Ok(Valid(OutputConfig::Video(v.validate(basedir)?.0))),
             OutputConfig::DebugTxt(d) => Ok(Valid(OutputConfig::DebugTxt(d.validate(basedir)?.0))),
             OutputConfig::Braidz(b) => Ok(Valid(OutputConfig::Braidz(b.validate(basedir)?.0))),
+            OutputConfig::HtmlReport(r) => {
+                Ok(Valid(OutputConfig::HtmlReport(r.validate(basedir)?.0)))
+            }
+            OutputConfig::EventsCsv(e) => {
+                Ok(Valid(OutputConfig::EventsCsv(e.validate(basedir)?.0)))
+            }
         }
     }
 }
@@ -58,6 +70,8 @@ impl OutputConfig {
             OutputConfig::Video(v) => &v.filename,
             OutputConfig::DebugTxt(d) => &d.filename,
             OutputConfig::Braidz(b) => &b.filename,
+            OutputConfig::HtmlReport(r) => &r.filename,
+            OutputConfig::EventsCsv(e) => &e.filename,
         }
     }
 }
@@ -85,12 +99,128 @@ impl Validate for BraidzOutputConfig {
     }
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, Eq)]
+/// A standalone HTML report with a JSON summary of the processing, plots of
+/// the synchronization quality and a thumbnail and preview clip of each camera.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HtmlReportOutputConfig {
+    /// The filename of the output desired.
+    pub filename: String,
+    /// The duration of the preview clip of each camera, in seconds, starting
+    /// at the first output frame. No clips are saved if zero.
+    #[serde(default = "default_preview_duration_secs")]
+    pub preview_duration_secs: f64,
+}
+
+fn default_preview_duration_secs() -> f64 {
+    2.0
+}
+
+impl Default for HtmlReportOutputConfig {
+    fn default() -> Self {
+        Self {
+            filename: "report.html".to_string(),
+            preview_duration_secs: default_preview_duration_secs(),
+        }
+    }
+}
+
+impl Validate for HtmlReportOutputConfig {
+    fn validate<P: AsRef<Path>>(self, basedir: Option<P>) -> Result<Valid<Self>> {
+        let filename = base_join_inner(self.filename, basedir)?;
+        if !(self.preview_duration_secs.is_finite() && self.preview_duration_secs >= 0.0) {
+            anyhow::bail!(
+                "HTML report `preview_duration_secs` must not be negative, but is {}.",
+                self.preview_duration_secs
+            );
+        }
+        Ok(Valid(Self { filename, ..self }))
+    }
+}
+
+/// A CSV file with the experiment events of the input braidz file, e.g.
+/// stimulus onsets, and the output frame at which each occurs.
+///
+/// Requires an input braidz file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct EventsCsvOutputConfig {
+    /// The filename of the output desired.
+    pub filename: String,
+}
+
+impl Default for EventsCsvOutputConfig {
+    fn default() -> Self {
+        Self {
+            filename: "events.csv".to_string(),
+        }
+    }
+}
+
+impl Validate for EventsCsvOutputConfig {
+    fn validate<P: AsRef<Path>>(self, basedir: Option<P>) -> Result<Valid<Self>> {
+        let filename = base_join_inner(self.filename, basedir)?;
+        Ok(Valid(Self { filename }))
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct ProcessingConfig {
     pub feature_detection_method: FeatureDetectionMethod,
     pub camera_calibration_source: CameraCalibrationSource,
     pub tracking_parameters_source: TrackingParametersSource,
+    /// If set, track with this air-water boundary instead of the one in the
+    /// camera calibration, e.g. `{ n_water = 1.333, z = -0.05 }`.
+    #[serde(default)]
+    pub refractive_boundary: Option<flydra_mvg::RefractiveBoundary<f64>>,
+}
+
+/// Thresholds of the automatic quality control.
+///
+/// Each threshold is checked for every camera after processing. If any is
+/// exceeded, [crate::run_config] returns a [crate::QcFailed] error.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct QcConfig {
+    /// The maximum fraction (from 0 to 1) of output frames without a frame of
+    /// a camera with video.
+    pub max_missing_frame_fraction: Option<f64>,
+    /// The maximum offset, in milliseconds, of a camera frame from the
+    /// synchronized time.
+    pub max_desync_msec: Option<f64>,
+    /// The minimum fraction (from 0 to 1) of the braidz rows of a camera with
+    /// video for which a frame of the camera was found.
+    pub min_braidz_rows_matched_fraction: Option<f64>,
+}
+
+impl QcConfig {
+    fn check(&self) -> Result<()> {
+        for (name, fraction) in [
+            (
+                "max_missing_frame_fraction",
+                self.max_missing_frame_fraction,
+            ),
+            (
+                "min_braidz_rows_matched_fraction",
+                self.min_braidz_rows_matched_fraction,
+            ),
+        ] {
+            if let Some(fraction) = fraction {
+                if !(0.0..=1.0).contains(&fraction) {
+                    anyhow::bail!("QC `{name}` must be between 0 and 1, but is {fraction}.");
+                }
+            }
+        }
+        if let Some(max_desync_msec) = self.max_desync_msec {
+            if !(max_desync_msec.is_finite() && max_desync_msec >= 0.0) {
+                anyhow::bail!(
+                    "QC `max_desync_msec` must not be negative, but is {max_desync_msec}."
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -183,10 +313,10 @@ impl Validate for VideoOutputConfig {
     /// filenames.
     fn validate<P: AsRef<Path>>(self, basedir: Option<P>) -> Result<Valid<Self>> {
         // Validate `filename`
-        let filename = base_join_inner(self.filename, basedir)?;
+        let filename = base_join_inner(self.filename, basedir.as_ref())?;
 
         // Validate `video_options`.
-        let video_options = self.video_options.validate()?.0;
+        let video_options = self.video_options.validate(basedir)?.0;
         Ok(Valid(Self {
             filename,
             video_options,
@@ -215,6 +345,59 @@ pub enum VideoCodecConfig {
     LessAvc,
 }
 
+/// The time zone in which times are shown in the video overlays.
+///
+/// This only affects the display: synchronization is always done in UTC. In
+/// the configuration, this is `"utc"`, `"local"` for the time zone of this
+/// computer, including its daylight saving time rules, or a fixed offset from
+/// UTC such as `"+02:00"`. Times other than UTC are shown with their offset,
+/// so that times around daylight saving time transitions are unambiguous.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
+pub enum DisplayTimezone {
+    #[default]
+    Utc,
+    Local,
+    Fixed(chrono::FixedOffset),
+}
+
+impl DisplayTimezone {
+    /// Format `time` in this time zone.
+    pub fn format(&self, time: chrono::DateTime<chrono::Utc>) -> String {
+        match self {
+            DisplayTimezone::Utc => time.to_string(),
+            DisplayTimezone::Local => time.with_timezone(&chrono::Local).to_string(),
+            DisplayTimezone::Fixed(offset) => time.with_timezone(offset).to_string(),
+        }
+    }
+}
+
+impl TryFrom<String> for DisplayTimezone {
+    type Error = anyhow::Report;
+    fn try_from(value: String) -> Result<Self> {
+        match value.as_str() {
+            "utc" => Ok(DisplayTimezone::Utc),
+            "local" => Ok(DisplayTimezone::Local),
+            _ => value.parse().map(DisplayTimezone::Fixed).map_err(|_| {
+                anyhow::eyre!(
+                    "Display time zone \"{value}\" is not \"utc\", \"local\" or an offset \
+                    such as \"+02:00\"."
+                )
+            }),
+        }
+    }
+}
+
+impl From<DisplayTimezone> for String {
+    fn from(value: DisplayTimezone) -> Self {
+        match value {
+            DisplayTimezone::Utc => "utc".into(),
+            DisplayTimezone::Local => "local".into(),
+            DisplayTimezone::Fixed(offset) => offset.to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct VideoOutputOptions {
@@ -253,6 +436,15 @@ pub struct VideoOutputOptions {
     ///
     /// The default value of `None` will resolve to [`crate::DEFAULT_CAMERA_TEXT_STYLE`].
     pub cam_text_style: Option<String>,
+    /// The SVG style string of the background behind the camera panels.
+    ///
+    /// The default value of `None` will resolve to [`crate::DEFAULT_BACKGROUND_STYLE`].
+    pub background_style: Option<String>,
+    /// The filename of a TOML theme file (see [`crate::Theme`]).
+    ///
+    /// Styles given directly in these options take precedence over the styles
+    /// in the theme. Relative filenames are relative to the configuration file.
+    pub theme: Option<String>,
     /// The title of the saved video, set in the segment metadata.
     ///
     /// The default value of `None` means this value will not be set in the
@@ -261,29 +453,250 @@ pub struct VideoOutputOptions {
     /// Save SVG and PNG intermediate images used to generate movies.
     #[serde(default)]
     pub save_debug_images: bool,
+    /// Copy the original H264 encoded frames into the output without
+    /// re-encoding.
+    ///
+    /// This is much faster than rendering but no overlays, camera text or
+    /// margins are drawn, so the styles above have no effect. Requires a single
+    /// H264 encoded input video. The output starts at the last keyframe before
+    /// the selected frame range, if the keyframes of the input are known, and
+    /// otherwise at the first keyframe within it.
+    #[serde(default)]
+    pub passthrough: bool,
+    /// Show the exposure time and gain of each frame after the camera name, if
+    /// stored in the input video.
+    #[serde(default)]
+    pub show_camera_settings: bool,
+    /// Show the signed offset, in milliseconds, of each camera frame from the
+    /// synchronized time of the output frame below the camera name.
+    ///
+    /// Offsets larger than `sync_threshold_microseconds` are drawn in red.
+    #[serde(default)]
+    pub show_sync_offset: bool,
+    /// The time zone in which the frame times are shown.
+    #[serde(default)]
+    pub display_timezone: DisplayTimezone,
+    /// Mark the experiment events of the input braidz file, e.g. stimulus
+    /// onsets, in the video.
+    ///
+    /// The frame at which an event occurs is framed and the message of the
+    /// event is shown for `event_display_secs`. Requires an input braidz file.
+    #[serde(default)]
+    pub show_events: bool,
+    /// The duration, in seconds of recording time, for which the message of
+    /// an event is shown.
+    ///
+    /// The default value of `None` will resolve to
+    /// [`crate::DEFAULT_EVENT_DISPLAY_SECS`].
+    pub event_display_secs: Option<f64>,
+    /// Save a time-lapse summary rather than every frame.
+    ///
+    /// Requires an input braidz file.
+    pub summary: Option<SummaryOptions>,
+    /// Show zoomed views following the tracked objects below the cameras.
+    ///
+    /// Requires an input braidz file with a calibration.
+    pub follow_cam: Option<FollowCamOptions>,
+}
+
+/// Options of a time-lapse summary of a long recording.
+///
+/// Frames are selected in proportion to the activity, i.e. the number of 2D
+/// detections and 3D estimates in the input braidz file, so that periods of
+/// high activity are shown in more detail. A timeline bar below the cameras
+/// shows the original time of each frame.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+pub struct SummaryOptions {
+    /// The duration of the summary video, in seconds.
+    ///
+    /// Because of the minimum and maximum rates below, the actual duration may
+    /// differ.
+    pub duration_secs: f64,
+    /// The frame rate of the summary video.
+    pub fps: f64,
+    /// The number of frames selected per minute of recording without any
+    /// activity.
+    pub min_frames_per_minute: f64,
+    /// The maximum number of frames selected per minute of recording.
+    pub max_frames_per_minute: f64,
+    /// The duration, in seconds, of the intervals over which the activity is
+    /// counted. Frames are evenly spaced within each interval.
+    pub bin_secs: f64,
+}
+
+impl Default for SummaryOptions {
+    fn default() -> Self {
+        Self {
+            duration_secs: 120.0,
+            fps: 25.0,
+            min_frames_per_minute: 1.0,
+            max_frames_per_minute: 600.0,
+            bin_secs: 60.0,
+        }
+    }
+}
+
+impl SummaryOptions {
+    fn validate(&self) -> Result<()> {
+        let positive = [
+            ("duration_secs", self.duration_secs),
+            ("fps", self.fps),
+            ("max_frames_per_minute", self.max_frames_per_minute),
+            ("bin_secs", self.bin_secs),
+        ];
+        for (name, value) in positive {
+            if !(value.is_finite() && value > 0.0) {
+                anyhow::bail!("Summary option `{name}` must be positive, but is {value}.");
+            }
+        }
+        if !(0.0..=self.max_frames_per_minute).contains(&self.min_frames_per_minute) {
+            anyhow::bail!(
+                "Summary option `min_frames_per_minute` must be between 0 and \
+                `max_frames_per_minute`."
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Options of the zoomed views following the tracked objects.
+///
+/// Below each camera, a row of panels shows the image of the camera magnified
+/// around the reprojected position of each 3D estimate within the image,
+/// labeled with the object id. The positions are smoothed over frames so that
+/// the panels do not jitter.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, default)]
+pub struct FollowCamOptions {
+    /// The width and height of each panel, in pixels.
+    pub panel_size: usize,
+    /// The magnification of the image in the panels.
+    pub zoom: f64,
+    /// The weight of the previous position when smoothing the position of an
+    /// object, from 0 (no smoothing) to below 1.
+    pub smoothing: f64,
+    /// The maximum number of panels below each camera. The objects with the
+    /// lowest ids are shown. Fewer panels are shown if they do not fit below
+    /// the camera.
+    pub max_objects: usize,
+}
+
+impl Default for FollowCamOptions {
+    fn default() -> Self {
+        Self {
+            panel_size: 200,
+            zoom: 3.0,
+            smoothing: 0.8,
+            max_objects: 4,
+        }
+    }
+}
+
+impl FollowCamOptions {
+    fn validate(&self) -> Result<()> {
+        if self.panel_size == 0 {
+            anyhow::bail!("Follow-cam option `panel_size` must be positive.");
+        }
+        if !(self.zoom.is_finite() && self.zoom > 0.0) {
+            anyhow::bail!(
+                "Follow-cam option `zoom` must be positive, but is {}.",
+                self.zoom
+            );
+        }
+        if !(0.0..1.0).contains(&self.smoothing) {
+            anyhow::bail!(
+                "Follow-cam option `smoothing` must be at least 0 and below 1, but is {}.",
+                self.smoothing
+            );
+        }
+        Ok(())
+    }
 }
 
 impl VideoOutputOptions {
-    fn validate(self) -> Result<Valid<Self>> {
+    fn validate<P: AsRef<Path>>(self, basedir: Option<P>) -> Result<Valid<Self>> {
         // Validate `time_dilation_factor`.
         let time_dilation_factor = if self.time_dilation_factor == Some(1.0) {
             None
         } else {
             self.time_dilation_factor
         };
+
+        // Validate `theme`.
+        let theme = base_join(self.theme, basedir)?;
+        if let Some(theme) = &theme {
+            crate::Theme::from_file(theme)?;
+        }
+
+        // Validate `event_display_secs`.
+        if let Some(secs) = self.event_display_secs {
+            if !(secs.is_finite() && secs >= 0.0) {
+                anyhow::bail!(
+                    "Video output `event_display_secs` must not be negative, but is {secs}."
+                );
+            }
+        }
+
+        // Validate `summary`.
+        if let Some(summary) = &self.summary {
+            summary.validate()?;
+            if self.passthrough {
+                anyhow::bail!("Video output `summary` cannot be used with `passthrough`.");
+            }
+            if time_dilation_factor.is_some() {
+                anyhow::bail!("Video output `summary` cannot be used with `time_dilation_factor`.");
+            }
+        }
+
+        // Validate `follow_cam`.
+        if let Some(follow_cam) = &self.follow_cam {
+            follow_cam.validate()?;
+            if self.passthrough {
+                anyhow::bail!("Video output `follow_cam` cannot be used with `passthrough`.");
+            }
+        }
+
         Ok(Valid(Self {
             time_dilation_factor,
+            theme,
             ..self
         }))
     }
 }
 
+/// What to do with a video frame whose timestamp equals or precedes that of
+/// the previous frame of the same video.
+///
+/// This only applies when the input videos, not a braidz file, are the source
+/// of synchronization. Such frames are counted and reported at the end of the
+/// processing.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub enum DuplicateTimestampPolicy {
+    /// Keep the first frame with a timestamp and skip later frames with the
+    /// same or an earlier timestamp.
+    #[default]
+    #[serde(rename = "keep-first")]
+    KeepFirst,
+    /// Skip all frames with a duplicated timestamp and later frames with an
+    /// earlier timestamp.
+    #[serde(rename = "drop")]
+    Drop,
+    /// Stop the processing with an error.
+    #[serde(rename = "error")]
+    Error,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct BraidRetrackVideoConfig {
     /// Specifies the maximum duration between frames to count as "synchronous",
     /// defaults to half of `frame_duration_microsecs`.
     pub sync_threshold_microseconds: Option<u64>,
+    /// What to do with duplicated or non-monotonic video frame timestamps.
+    #[serde(default)]
+    pub duplicate_timestamps: DuplicateTimestampPolicy,
     /// The interval between adjacent frames. Defaults to the value detected in
     /// the first frames of the given video inputs.
     pub frame_duration_microsecs: Option<u64>,
@@ -296,15 +709,23 @@ pub struct BraidRetrackVideoConfig {
     pub input_braidz: Option<String>,
     #[serde(default)]
     pub input_video: Vec<VideoSourceConfig>,
+    /// With a braidz input, show cameras of the braidz archive without an
+    /// input video as blank images with their 2D detections.
+    #[serde(default)]
+    pub include_cameras_without_video: bool,
     pub output: Vec<OutputConfig>,
     #[serde(default)]
     pub processing_config: ProcessingConfig,
+    /// Quality control thresholds. Processing fails if any is exceeded.
+    #[serde(default)]
+    pub qc: QcConfig,
 }
 
 impl Default for BraidRetrackVideoConfig {
     fn default() -> Self {
         Self {
             sync_threshold_microseconds: None,
+            duplicate_timestamps: DuplicateTimestampPolicy::default(),
             frame_duration_microsecs: None,
             skip_n_first_output_frames: None,
             max_num_frames: None,
@@ -316,7 +737,9 @@ impl Default for BraidRetrackVideoConfig {
                 VideoSourceConfig::new("b.mkv"),
                 VideoSourceConfig::new("c.mkv"),
             ],
+            include_cameras_without_video: false,
             processing_config: ProcessingConfig::default(),
+            qc: QcConfig::default(),
         }
     }
 }
@@ -334,7 +757,44 @@ impl Validate for BraidRetrackVideoConfig {
         // Validate `input_braidz`.
         let input_braidz = base_join(self.input_braidz, basedir.as_ref())?;
 
+        self.qc.check()?;
+
         // Validate `output`.
+        let n_passthrough = self
+            .output
+            .iter()
+            .filter(|o| matches!(o, OutputConfig::Video(v) if v.video_options.passthrough))
+            .count();
+        if n_passthrough > 0 && self.input_video.len() != 1 {
+            anyhow::bail!(
+                "Video output with `passthrough` requires exactly one input video, but {} given.",
+                self.input_video.len()
+            )
+        }
+        let has_summary = self
+            .output
+            .iter()
+            .any(|o| matches!(o, OutputConfig::Video(v) if v.video_options.summary.is_some()));
+        if has_summary && input_braidz.is_none() {
+            anyhow::bail!("Video output with `summary` requires `input_braidz`.")
+        }
+        let has_follow_cam = self
+            .output
+            .iter()
+            .any(|o| matches!(o, OutputConfig::Video(v) if v.video_options.follow_cam.is_some()));
+        if has_follow_cam && input_braidz.is_none() {
+            anyhow::bail!("Video output with `follow_cam` requires `input_braidz`.")
+        }
+        let has_events = self.output.iter().any(|o| match o {
+            OutputConfig::Video(v) => v.video_options.show_events,
+            OutputConfig::EventsCsv(_) => true,
+            _ => false,
+        });
+        if has_events && input_braidz.is_none() {
+            anyhow::bail!(
+                "Video output with `show_events` and `events_csv` output require `input_braidz`."
+            )
+        }
         let output = self
             .output
             .into_iter()
@@ -368,6 +828,16 @@ impl Validate for BraidRetrackVideoConfig {
 pub struct VideoSourceConfig {
     pub filename: String,
     pub camera_name: Option<String>,
+    /// Intrinsic parameters (including lens distortion) used for this camera
+    /// instead of those in the calibration of the braidz archive.
+    ///
+    /// The extrinsic parameters of the calibration are kept. This affects only
+    /// the reprojection of 3D points into this camera.
+    pub intrinsics_override: Option<IntrinsicsOverride>,
+    /// The video track of an MP4 file with more than one, e.g.
+    /// `mp4_track = { track_id = 2 }`, `mp4_track = { index = 0 }` or
+    /// `mp4_track = { camera_name = "Basler-1234" }`.
+    pub mp4_track: Option<Mp4TrackSelection>,
 }
 
 impl VideoSourceConfig {
@@ -375,6 +845,65 @@ impl VideoSourceConfig {
         Self {
             filename: filename.to_string(),
             camera_name: None,
+            intrinsics_override: None,
+            mp4_track: None,
+        }
+    }
+}
+
+/// Intrinsic parameters of a camera replacing those of the calibration.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, tag = "type")]
+pub enum IntrinsicsOverride {
+    /// A ROS `camera_info` YAML file, as saved by the Strand Camera
+    /// checkerboard calibration. Relative filenames are relative to the
+    /// configuration file.
+    #[serde(rename = "file")]
+    File { filename: String },
+    /// Parameters of the OpenCV camera model given directly.
+    #[serde(rename = "inline")]
+    Inline {
+        fx: f64,
+        fy: f64,
+        cx: f64,
+        cy: f64,
+        #[serde(default)]
+        skew: f64,
+        /// The distortion coefficients `[k1, k2, p1, p2, k3]`.
+        #[serde(default)]
+        distortion: [f64; 5],
+    },
+}
+
+impl IntrinsicsOverride {
+    /// Load the intrinsic parameters.
+    pub fn load(&self) -> Result<opencv_ros_camera::RosOpenCvIntrinsics<f64>> {
+        match self {
+            IntrinsicsOverride::File { filename } => {
+                let rdr = std::fs::File::open(filename)
+                    .with_context(|| format!("opening intrinsics file \"{filename}\""))?;
+                let named: opencv_ros_camera::NamedIntrinsicParameters<f64> =
+                    opencv_ros_camera::from_ros_yaml(std::io::BufReader::new(rdr))
+                        .map_err(|e| anyhow::anyhow!("parsing \"{filename}\": {e}"))?;
+                Ok(named.intrinsics)
+            }
+            IntrinsicsOverride::Inline {
+                fx,
+                fy,
+                cx,
+                cy,
+                skew,
+                distortion,
+            } => {
+                let distortion = opencv_ros_camera::Distortion::from_opencv_vec(
+                    nalgebra::Vector5::from_row_slice(distortion),
+                );
+                Ok(
+                    opencv_ros_camera::RosOpenCvIntrinsics::from_params_with_distortion(
+                        *fx, *skew, *fy, *cx, *cy, distortion,
+                    ),
+                )
+            }
         }
     }
 }
@@ -401,9 +930,30 @@ impl Validate for VideoSourceConfig {
                 VALID_VIDEO_SOURCES,
             )
         }
-        let filename = base_join_inner(self.filename, basedir)?;
+        if self.mp4_track.is_some() && !self.filename.to_lowercase().ends_with(".mp4") {
+            anyhow::bail!(
+                "Video source \"{}\" has an MP4 track selection, but is not an MP4 file.",
+                self.filename
+            );
+        }
+        let filename = base_join_inner(self.filename, basedir.as_ref())?;
 
-        Ok(Valid(Self { filename, ..self }))
+        // Validate `intrinsics_override`.
+        let intrinsics_override = match self.intrinsics_override {
+            Some(IntrinsicsOverride::File { filename }) => Some(IntrinsicsOverride::File {
+                filename: base_join_inner(filename, basedir)?,
+            }),
+            other => other,
+        };
+        if let Some(intrinsics_override) = &intrinsics_override {
+            intrinsics_override.load()?;
+        }
+
+        Ok(Valid(Self {
+            filename,
+            intrinsics_override,
+            ..self
+        }))
     }
 }
 
@@ -444,3 +994,138 @@ fn test_default_config_is_valid_and_serializable() -> Result<()> {
     toml::to_string_pretty(&cfg.valid())?;
     Ok(())
 }
+
+#[test]
+fn test_summary_options() -> Result<()> {
+    let buf = r#"
+        filename = "summary.mp4"
+
+        [video_options.summary]
+        duration_secs = 60.0
+        "#;
+    let cfg: VideoOutputConfig = toml::from_str(buf)?;
+    let cfg = cfg.validate(None::<&str>)?;
+    let summary = cfg.valid().video_options.summary.as_ref().unwrap();
+    assert_eq!(summary.duration_secs, 60.0);
+    assert_eq!(summary.fps, SummaryOptions::default().fps);
+
+    let buf = r#"
+        filename = "summary.mp4"
+
+        [video_options.summary]
+        min_frames_per_minute = 10.0
+        max_frames_per_minute = 5.0
+        "#;
+    let cfg: VideoOutputConfig = toml::from_str(buf)?;
+    assert!(cfg.validate(None::<&str>).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_follow_cam_options() -> Result<()> {
+    let buf = r#"
+        filename = "follow.mp4"
+
+        [video_options.follow_cam]
+        zoom = 4.0
+        "#;
+    let cfg: VideoOutputConfig = toml::from_str(buf)?;
+    let cfg = cfg.validate(None::<&str>)?;
+    let follow_cam = cfg.valid().video_options.follow_cam.as_ref().unwrap();
+    assert_eq!(follow_cam.zoom, 4.0);
+    assert_eq!(
+        follow_cam.panel_size,
+        FollowCamOptions::default().panel_size
+    );
+
+    let buf = r#"
+        filename = "follow.mp4"
+
+        [video_options.follow_cam]
+        smoothing = 1.0
+        "#;
+    let cfg: VideoOutputConfig = toml::from_str(buf)?;
+    assert!(cfg.validate(None::<&str>).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_intrinsics_override() -> Result<()> {
+    let buf = r#"
+        filename = "cam1.mp4"
+
+        [intrinsics_override]
+        type = "inline"
+        fx = 1000.0
+        fy = 1010.0
+        cx = 640.0
+        cy = 512.0
+        distortion = [-0.1, 0.01, 0.0, 0.0, 0.0]
+        "#;
+    let cfg: VideoSourceConfig = toml::from_str(buf)?;
+    let basedir: Option<String> = None;
+    let cfg = cfg.validate(basedir)?;
+    let intrinsics = cfg.valid().intrinsics_override.as_ref().unwrap().load()?;
+    assert_eq!(intrinsics.k[(0, 0)], 1000.0);
+    assert_eq!(intrinsics.k[(1, 2)], 512.0);
+    assert_eq!(intrinsics.distortion.radial1(), -0.1);
+    Ok(())
+}
+
+#[test]
+fn test_html_report_options() -> Result<()> {
+    let buf = r#"
+        type = "html_report"
+        filename = "report.html"
+        "#;
+    let cfg: OutputConfig = toml::from_str(buf)?;
+    let cfg = cfg.validate(None::<&str>)?;
+    let OutputConfig::HtmlReport(report) = cfg.valid() else {
+        panic!("expected html report");
+    };
+    assert_eq!(report.preview_duration_secs, 2.0);
+
+    let buf = r#"
+        type = "html_report"
+        filename = "report.html"
+        preview_duration_secs = -1.0
+        "#;
+    let cfg: OutputConfig = toml::from_str(buf)?;
+    assert!(cfg.validate(None::<&str>).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_display_timezone() -> Result<()> {
+    let buf = r#"
+        filename = "out.mp4"
+
+        [video_options]
+        display_timezone = "+01:00"
+        "#;
+    let cfg: VideoOutputConfig = toml::from_str(buf)?;
+    let tz = cfg.video_options.display_timezone;
+    assert_eq!(String::from(tz), "+01:00");
+
+    // A frame one second before and one after the daylight saving time
+    // transition in Central Europe. A fixed offset does not follow the
+    // transition.
+    let t0: chrono::DateTime<chrono::Utc> = "2024-03-31T00:59:59Z".parse()?;
+    let t1 = t0 + chrono::Duration::seconds(2);
+    assert_eq!(tz.format(t0), "2024-03-31 01:59:59 +01:00");
+    assert_eq!(tz.format(t1), "2024-03-31 02:00:01 +01:00");
+    let summer = DisplayTimezone::try_from("+02:00".to_string())?;
+    assert_eq!(summer.format(t1), "2024-03-31 03:00:01 +02:00");
+    assert_eq!(DisplayTimezone::Utc.format(t1), "2024-03-31 01:00:01 UTC");
+
+    assert_eq!(
+        DisplayTimezone::try_from("local".to_string())?,
+        DisplayTimezone::Local
+    );
+    assert_eq!(
+        VideoOutputOptions::default().display_timezone,
+        DisplayTimezone::Utc
+    );
+    assert!(DisplayTimezone::try_from("Europe/Berlin".to_string()).is_err());
+    Ok(())
+}
@@ -0,0 +1,145 @@
+//! Experiment events from the textlog of the input braidz archive.
+//!
+//! Events, e.g. stimulus onsets posted as notes to the Braid API, are assigned
+//! to the first output frame whose synchronized time is not before the time of
+//! the event. Both are in the clock of the Braid mainbrain.
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+
+use flydra_types::TextlogRow;
+
+/// The start of the textlog message with the frame rate, saved by Braid.
+const FPS_MESSAGE_START: &str = "MainBrain running at ";
+
+/// An event of the textlog.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Event {
+    /// The time the event was received by the Braid mainbrain.
+    pub(crate) timestamp: DateTime<Utc>,
+    /// The origin of the event, e.g. `mainbrain` or the name of a camera.
+    pub(crate) source: String,
+    pub(crate) message: String,
+}
+
+impl Event {
+    /// Convert a textlog row. Returns `None` for the metadata saved by Braid.
+    fn from_textlog(row: TextlogRow) -> Option<Self> {
+        if is_braid_metadata(&row.message) {
+            return None;
+        }
+        Some(Self {
+            timestamp: datetime_conversion::f64_to_datetime(row.mainbrain_timestamp),
+            source: row.cam_id,
+            message: row.message,
+        })
+    }
+}
+
+/// Whether a textlog message is the frame rate or the tracking parameters
+/// saved by Braid at the start of the recording.
+fn is_braid_metadata(message: &str) -> bool {
+    if message.starts_with(FPS_MESSAGE_START) {
+        return true;
+    }
+    let js_value: Option<serde_json::Value> = serde_json::from_str(message).ok();
+    js_value
+        .as_ref()
+        .and_then(|v| v.as_object())
+        .is_some_and(|o| o.contains_key("tracking_params"))
+}
+
+/// Read the events of a braidz archive, in order of time.
+pub(crate) fn read_events<R: std::io::Read + std::io::Seek>(
+    archive: &mut braidz_parser::BraidzArchive<R>,
+) -> Result<Vec<Event>> {
+    let mut events = Vec::new();
+    for row in archive.iter_textlog()? {
+        events.extend(Event::from_textlog(row?));
+    }
+    events.sort_by_key(|e| e.timestamp);
+    Ok(events)
+}
+
+/// Assigns events to consecutive output frames.
+pub(crate) struct EventQueue {
+    events: VecDeque<Event>,
+    started: bool,
+}
+
+impl EventQueue {
+    pub(crate) fn new(events: Vec<Event>) -> Self {
+        Self {
+            events: events.into(),
+            started: false,
+        }
+    }
+
+    /// Take the events of the output frame at `timestamp`, i.e. all remaining
+    /// events not after `timestamp`.
+    ///
+    /// Events before the first frame are dropped.
+    pub(crate) fn take_until(&mut self, timestamp: DateTime<Utc>) -> Vec<Event> {
+        if !self.started {
+            self.started = true;
+            let n_before = self
+                .events
+                .iter()
+                .take_while(|e| e.timestamp < timestamp)
+                .count();
+            if n_before > 0 {
+                tracing::info!("{n_before} events before the first output frame are not shown.");
+                self.events.drain(..n_before);
+            }
+        }
+        let n = self
+            .events
+            .iter()
+            .take_while(|e| e.timestamp <= timestamp)
+            .count();
+        self.events.drain(..n).collect()
+    }
+}
+
+#[test]
+fn test_event_queue() {
+    let t0 = datetime_conversion::f64_to_datetime(1_700_000_000.0);
+    let us = chrono::Duration::microseconds;
+    let row = |secs: f64, message: &str| TextlogRow {
+        mainbrain_timestamp: 1_700_000_000.0 + secs,
+        cam_id: "mainbrain".to_string(),
+        host_timestamp: 1_700_000_000.0 + secs,
+        message: message.to_string(),
+    };
+    let rows = vec![
+        row(
+            0.0,
+            "MainBrain running at 100.0 fps, (flydra_version 0.12.0, time_tzname0 UTC)",
+        ),
+        row(0.0, r#"{"tracking_params":{},"git_revision":"abc"}"#),
+        row(-1.0, "before"),
+        // Times exactly representable as `f64`.
+        row(0.015625, "stimulus on"),
+        row(0.03125, "exact"),
+        row(0.0390625, "stimulus off"),
+    ];
+    let events: Vec<Event> = rows.into_iter().filter_map(Event::from_textlog).collect();
+    assert_eq!(events.len(), 4);
+
+    let mut queue = EventQueue::new(events);
+    assert!(queue.take_until(t0).is_empty());
+    assert!(queue.take_until(t0 + us(10_000)).is_empty());
+    let messages =
+        |events: Vec<Event>| -> Vec<String> { events.into_iter().map(|e| e.message).collect() };
+    assert_eq!(
+        messages(queue.take_until(t0 + us(31_250))),
+        ["stimulus on", "exact"]
+    );
+    assert_eq!(
+        messages(queue.take_until(t0 + us(40_000))),
+        ["stimulus off"]
+    );
+    assert!(queue.take_until(t0 + us(50_000)).is_empty());
+}
@@ -1,4 +1,4 @@
-use std::{collections::BTreeMap, io::Write};
+use std::{collections::BTreeMap, sync::Arc};
 
 use chrono::{DateTime, Utc};
 use color_eyre::{
@@ -6,7 +6,7 @@ use color_eyre::{
     Result,
 };
 use flydra_mvg::FlydraMultiCameraSystem;
-use frame_source::{FrameData, FrameDataSource};
+use frame_source::{FrameCameraSettings, FrameData, FrameDataSource, ImageDataMode};
 use futures::future::join_all;
 use indicatif::{ProgressBar, ProgressStyle};
 use ordered_float::NotNan;
@@ -29,8 +29,11 @@ mod synced_iter;
 mod config;
 pub(crate) use config::FeatureDetectionMethod;
 pub use config::{
-    BraidRetrackVideoConfig, OutputConfig, Valid, Validate, VideoOutputConfig, VideoSourceConfig,
+    BraidRetrackVideoConfig, DisplayTimezone, DuplicateTimestampPolicy, HtmlReportOutputConfig,
+    IntrinsicsOverride, OutputConfig, QcConfig, Valid, Validate, VideoOutputConfig,
+    VideoSourceConfig,
 };
+pub use frame_source::mp4_source::Mp4TrackSelection;
 
 mod auto_config_generator;
 pub use auto_config_generator::auto_config;
@@ -42,8 +45,23 @@ use output_types::*;
 
 mod output_braidz;
 
+mod output_report;
+
 mod output_video;
 
+mod output_worker;
+use output_worker::{OutputFrame, OutputMsg, OutputWorker};
+
+mod qc;
+pub use qc::QcFailed;
+
+mod summary;
+
+mod events;
+
+mod theme;
+pub use theme::Theme;
+
 pub(crate) const DEFAULT_COMPOSITE_MARGIN_PIXELS: usize = 5;
 pub(crate) const DEFAULT_FEATURE_RADIUS: &str = "10";
 pub(crate) const DEFAULT_FEATURE_STYLE: &str = "fill: none; stroke: deepskyblue; stroke-width: 3;";
@@ -52,14 +70,18 @@ pub(crate) const DEFAULT_CAMERA_TEXT_STYLE: &str =
 
 pub(crate) const DEFAULT_REPROJECTED_RADIUS: &str = "12";
 pub(crate) const DEFAULT_REPROJECTED_STYLE: &str = "fill: none; stroke: white; stroke-width: 3;";
+pub(crate) const DEFAULT_BACKGROUND_STYLE: &str = "fill: white;";
+pub(crate) const DEFAULT_EVENT_DISPLAY_SECS: f64 = 1.0;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct OutTimepointPerCamera {
     timestamp: DateTime<Utc>,
     /// Camera image from MP4, MKV, or FMF file (if available).
     image: Option<DynamicFrame>,
     /// Braidz data. Empty if no braidz data available.
     this_cam_this_frame: Vec<Data2dDistortedRow>,
+    /// Exposure time and gain, if stored with the image.
+    camera_settings: Option<FrameCameraSettings>,
 }
 
 impl OutTimepointPerCamera {
@@ -67,17 +89,40 @@ impl OutTimepointPerCamera {
         timestamp: DateTime<Utc>,
         image: Option<DynamicFrame>,
         this_cam_this_frame: Vec<Data2dDistortedRow>,
+        camera_settings: Option<FrameCameraSettings>,
     ) -> Self {
         Self {
             timestamp,
             image,
             this_cam_this_frame,
+            camera_settings,
         }
     }
 }
 
+/// The provenance of this program, saved in its outputs.
+pub(crate) fn provenance() -> provenance::Provenance {
+    provenance::Provenance::current(
+        "braid-process-video",
+        env!("CARGO_PKG_VERSION"),
+        env!("GIT_HASH"),
+    )
+}
+
+/// Describe the exposure time and gain, e.g. `exposure 1000 us, gain 3.0 dB`.
+pub(crate) fn format_camera_settings(settings: &FrameCameraSettings) -> String {
+    let mut parts = Vec::new();
+    if let Some(exposure) = settings.exposure_time_usec {
+        parts.push(format!("exposure {exposure:.0} us"));
+    }
+    if let Some(gain) = settings.gain_db {
+        parts.push(format!("gain {gain:.1} dB"));
+    }
+    parts.join(", ")
+}
+
 /// An ordered `Vec` with one entry per camera.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct SyncedPictures {
     pub(crate) timestamp: DateTime<Utc>,
     pub(crate) camera_pictures: Vec<OutTimepointPerCamera>,
@@ -92,7 +137,7 @@ impl SyncedPictures {
         &self,
         cam: &CameraSource,
         recon: &Option<FlydraMultiCameraSystem<f64>>,
-    ) -> Vec<(NotNan<f64>, NotNan<f64>)> {
+    ) -> Vec<(u32, (NotNan<f64>, NotNan<f64>))> {
         let recon = match recon {
             Some(recon) => recon,
             None => {
@@ -119,7 +164,10 @@ impl SyncedPictures {
                     let x = pix2d.coords.x;
                     let y = pix2d.coords.y;
                     if x >= 0.0 && y >= 0.0 && x <= cam.width() as f64 && y <= cam.height() as f64 {
-                        Some((NotNan::new(x).unwrap(), NotNan::new(y).unwrap()))
+                        Some((
+                            kest_row.obj_id,
+                            (NotNan::new(x).unwrap(), NotNan::new(y).unwrap()),
+                        ))
                     } else {
                         None
                     }
@@ -132,13 +180,22 @@ impl SyncedPictures {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) struct BraidzFrameInfo {
     frame_num: i64,
     trigger_timestamp: Option<flydra_types::FlydraFloatTimestampLocal<flydra_types::Triggerbox>>,
     kalman_estimates: Vec<KalmanEstimatesRow>,
 }
 
+/// The latest of the start times of the videos, in UTC.
+///
+/// The start times may have different offsets from UTC, e.g. if the videos
+/// were started on either side of a daylight saving time transition, so all
+/// synchronization is done in UTC.
+fn latest_start_time(frame0_times: &[DateTime<chrono::FixedOffset>]) -> Option<DateTime<Utc>> {
+    frame0_times.iter().map(|t| t.with_timezone(&Utc)).max()
+}
+
 fn synchronize_readers_from(
     approx_start_time: DateTime<Utc>,
     readers: &mut [Peek2<Box<dyn Iterator<Item = Result<FrameData>>>>],
@@ -320,26 +377,32 @@ impl PerCamRender {
         }
     }
 
-    fn new_render_data(&self, pts_chrono: DateTime<Utc>) -> PerCamRenderFrame<'_> {
+    fn new_render_data(self: &Arc<Self>, pts_chrono: DateTime<Utc>) -> PerCamRenderFrame {
         PerCamRenderFrame {
-            p: self,
+            p: self.clone(),
             png_buf: None,
             points: vec![],
             reprojected_points: vec![],
+            reprojected_obj_ids: vec![],
             pts_chrono,
+            camera_settings: None,
         }
     }
 }
 
-pub(crate) struct PerCamRenderFrame<'a> {
-    pub(crate) p: &'a PerCamRender,
+#[derive(Clone)]
+pub(crate) struct PerCamRenderFrame {
+    pub(crate) p: Arc<PerCamRender>,
     pub(crate) png_buf: Option<Vec<u8>>,
     pub(crate) points: Vec<(NotNan<f64>, NotNan<f64>)>,
     pub(crate) reprojected_points: Vec<(NotNan<f64>, NotNan<f64>)>,
+    /// The object ids of `reprojected_points`, in the same order.
+    pub(crate) reprojected_obj_ids: Vec<u32>,
     pub(crate) pts_chrono: DateTime<Utc>,
+    pub(crate) camera_settings: Option<FrameCameraSettings>,
 }
 
-impl<'a> PerCamRenderFrame<'a> {
+impl PerCamRenderFrame {
     pub(crate) fn set_original_image(&mut self, frame: &DynamicFrame) -> Result<()> {
         let png_buf = match frame {
             basic_frame::DynamicFrame::Mono8(frame_mono8) => {
@@ -369,7 +432,7 @@ impl<'a> PerCamRenderFrame<'a> {
 #[derive(Debug)]
 struct CameraSource {
     cam_id: CameraIdentifier,
-    per_cam_render: PerCamRender,
+    per_cam_render: Arc<PerCamRender>,
 }
 
 impl CameraSource {
@@ -381,6 +444,32 @@ impl CameraSource {
             CameraIdentifier::BraidzOnly(_) => None,
         }
     }
+
+    fn output_camera(&self) -> OutputCamera {
+        let movie = match &self.cam_id {
+            CameraIdentifier::MovieOnly(m) | CameraIdentifier::Both((m, _)) => {
+                Some((m.full_path.clone(), m.mp4_track.clone()))
+            }
+            CameraIdentifier::BraidzOnly(_) => None,
+        };
+        OutputCamera {
+            per_cam_render: self.per_cam_render.clone(),
+            movie,
+        }
+    }
+}
+
+/// The information about a camera used to create the outputs.
+///
+/// Unlike [CameraSource], this can be sent to the threads of the outputs.
+#[derive(Debug, Clone)]
+pub(crate) struct OutputCamera {
+    pub(crate) per_cam_render: Arc<PerCamRender>,
+    /// The path and track of the input video, if any.
+    pub(crate) movie: Option<(
+        std::path::PathBuf,
+        frame_source::mp4_source::Mp4TrackSelection,
+    )>,
 }
 
 #[derive(Debug)]
@@ -420,7 +509,9 @@ impl CameraIdentifier {
 
 struct MovieCamId {
     /// Full path of the movie, including directory if given
-    _full_path: std::path::PathBuf,
+    full_path: std::path::PathBuf,
+    /// The video track, if an MP4 file
+    mp4_track: frame_source::mp4_source::Mp4TrackSelection,
     /// The file reader
     reader: Option<Peek2<Box<dyn Iterator<Item = Result<FrameData>>>>>,
     /// File name of the movie (without directory path)
@@ -508,8 +599,14 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
         .input_video
         .iter()
         .map(|s| {
-            let do_decode_h264 = true;
-            frame_source::from_path(&s.filename, do_decode_h264)
+            frame_source::from_path_with_options(
+                &s.filename,
+                frame_source::FromPathOptions {
+                    image_data_mode: ImageDataMode::Decoded,
+                    mp4_track: s.mp4_track.clone().unwrap_or_default(),
+                    ..Default::default()
+                },
+            )
         })
         .collect();
     let frame_sources: Result<Vec<_>> = frame_sources.into_iter().collect();
@@ -529,6 +626,13 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
 
             let title: Option<String> = frame_source.camera_name().map(Into::into);
 
+            if frame_source.pts_reordered() {
+                tracing::info!(
+                    "Video source {}: frames reordered from decode to presentation order",
+                    s.filename
+                );
+            }
+
             let reader = Some(Peek2::new(frame_source.iter()));
 
             let full_path = std::path::PathBuf::from(&s.filename);
@@ -541,7 +645,8 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
             );
 
             let cam_id = CameraIdentifier::MovieOnly(MovieCamId {
-                _full_path: full_path,
+                full_path: full_path,
+                mp4_track: s.mp4_track.clone().unwrap_or_default(),
                 filename,
                 timestamp_source,
                 cfg_name: s.camera_name.clone(),
@@ -551,7 +656,7 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
                 reader,
             });
 
-            let per_cam_render = PerCamRender::from_reader(&cam_id);
+            let per_cam_render = Arc::new(PerCamRender::from_reader(&cam_id));
 
             Ok(CameraSource {
                 cam_id,
@@ -612,14 +717,88 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
         }
     };
 
+    // Replace the intrinsic parameters of cameras with an override. The
+    // calibration copied into braidz outputs is not changed.
+    for (s, source) in cfg.input_video.iter().zip(sources.iter()) {
+        let Some(intrinsics_override) = &s.intrinsics_override else {
+            continue;
+        };
+        let raw_name = source.per_cam_render.raw_name.as_str();
+        let calibration = braid_archive
+            .as_mut()
+            .and_then(|archive| archive.calibration_info.as_mut());
+        let Some(calibration) = calibration else {
+            tracing::warn!(
+                "Intrinsics override for camera \"{raw_name}\" ignored: no calibration in braidz archive."
+            );
+            continue;
+        };
+        let mut cams = calibration.cameras.cams().clone();
+        let Some(cam) = cams.get_mut(raw_name) else {
+            tracing::warn!(
+                "Intrinsics override for camera \"{raw_name}\" ignored: camera not in calibration."
+            );
+            continue;
+        };
+        *cam = mvg::Camera::new(
+            cam.width(),
+            cam.height(),
+            cam.extrinsics().clone(),
+            intrinsics_override.load()?,
+        )?;
+        calibration.cameras =
+            mvg::MultiCameraSystem::new_inner(cams, calibration.cameras.comment().cloned());
+        tracing::warn!(
+            "Camera \"{raw_name}\" uses overridden intrinsic parameters instead of the braidz calibration."
+        );
+    }
+
+    // Cameras of the braidz archive without a video. (Without any video, all
+    // cameras of the braidz archive are used below.)
+    let braidz_and_archive = match (braidz_sources.as_ref(), braid_archive.as_ref()) {
+        (Some(braidz_sources), Some(archive)) if !sources.is_empty() => {
+            Some((braidz_sources, archive))
+        }
+        _ => None,
+    };
+    if let Some((braidz_sources, archive)) = braidz_and_archive {
+        let without_video: Vec<&BraidzCamId> = braidz_sources
+            .iter()
+            .filter(|bs| {
+                !sources.iter().any(
+                    |s| matches!(&s.cam_id, CameraIdentifier::Both((_, b)) if b.camn == bs.camn),
+                )
+            })
+            .collect();
+        if cfg.include_cameras_without_video {
+            for bs in without_video {
+                sources.push(CameraSource {
+                    cam_id: CameraIdentifier::BraidzOnly(bs.clone()),
+                    per_cam_render: Arc::new(PerCamRender::from_braidz(archive, bs)),
+                });
+            }
+        } else if !without_video.is_empty() {
+            let names: Vec<&str> = without_video
+                .iter()
+                .map(|bs| bs.cam_id_str.as_str())
+                .collect();
+            tracing::info!(
+                "Cameras without video not shown: {}. Set `include_cameras_without_video` to show their 2D detections.",
+                names.join(", ")
+            );
+        }
+    }
+
     // If we have no manually specified video sources but do have a braidz file, use that.
     let braidz_only = if sources.is_empty() {
         if let Some(braidz_sources) = braidz_sources {
             let mut cam_ids = braidz_sources
                 .into_iter()
                 .map(|bs| {
-                    let per_cam_render =
-                        PerCamRender::from_braidz(braid_archive.as_ref().unwrap(), &bs);
+                    let per_cam_render = Arc::new(PerCamRender::from_braidz(
+                        braid_archive.as_ref().unwrap(),
+                        &bs,
+                    ));
                     CameraSource {
                         cam_id: CameraIdentifier::BraidzOnly(bs),
                         per_cam_render,
@@ -645,6 +824,30 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
         }
     }
 
+    // The experiment events, if needed for an output.
+    let has_events = cfg.output.iter().any(|o| match o {
+        OutputConfig::Video(v) => v.video_options.show_events,
+        OutputConfig::EventsCsv(_) => true,
+        _ => false,
+    });
+    let mut event_queue = match braid_archive.as_mut() {
+        Some(archive) if has_events => Some(events::EventQueue::new(events::read_events(archive)?)),
+        _ => None,
+    };
+
+    // The activity in each frame, if needed for a summary.
+    let has_summary = cfg
+        .output
+        .iter()
+        .any(|o| matches!(o, OutputConfig::Video(v) if v.video_options.summary.is_some()));
+    let activity = match braid_archive.as_ref() {
+        Some(archive) if has_summary => Some(summary::activity(
+            &data2d,
+            archive.kalman_estimates_table.as_deref(),
+        )),
+        _ => None,
+    };
+
     let camera_names: Vec<String> = sources
         .iter()
         .map(|s| match &s.cam_id {
@@ -657,6 +860,11 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
 
     // Build iterator to iterate over output frames. This is equivalent to
     // iterating over synchronized input frames.
+    // The maximum offset of camera frames from the synchronized time. `None`
+    // if there are no videos.
+    let mut sync_threshold = None;
+    let mut timestamp_issue_counts = None;
+
     let moment_iter: Box<dyn Iterator<Item = _>> = if braidz_only {
         let braid_archive = braid_archive.unwrap();
         let boxed = Box::new(braid_archive);
@@ -673,17 +881,18 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
         let braid_archive = braidz_iter::BraidArchiveNoVideoData::new(statik, camns)?;
         Box::new(braid_archive)
     } else {
-        let mut frame_readers: Vec<_> = sources
-            .iter_mut()
-            .map(|s| s.take_reader().unwrap())
-            .collect();
+        // Cameras without video have no reader.
+        let frame_readers: Vec<_> = sources.iter_mut().map(|s| s.take_reader()).collect();
 
-        let frame0_times: Vec<chrono::DateTime<chrono::FixedOffset>> =
-            sources.iter().map(|s| s.cam_id.frame0_time()).collect();
+        let frame0_times: Vec<chrono::DateTime<chrono::FixedOffset>> = sources
+            .iter()
+            .filter(|s| !matches!(s.cam_id, CameraIdentifier::BraidzOnly(_)))
+            .map(|s| s.cam_id.frame0_time())
+            .collect();
 
         // Determine which video started last and what time was the last start time.
         // This time is where we will start from.
-        let approx_start_time: Option<DateTime<_>> = frame0_times.iter().max().map(Clone::clone);
+        let approx_start_time = latest_start_time(&frame0_times);
 
         if let Some(approx_start_time) = &approx_start_time {
             tracing::info!("start time determined from videos: {}", approx_start_time);
@@ -695,6 +904,7 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
             .unwrap_or_else(|| {
                 frame_readers
                     .iter()
+                    .flatten()
                     .map(|reader| {
                         let p1_pts_chrono = reader
                             .peek1()
@@ -720,14 +930,15 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
                     .unwrap()
             });
 
-        let sync_threshold = cfg
+        let threshold = cfg
             .sync_threshold_microseconds
             .map(|x| chrono::Duration::from_std(std::time::Duration::from_micros(x)).unwrap())
             .unwrap_or(frame_duration / 2);
+        sync_threshold = Some(threshold);
 
         tracing::info!(
             "sync_threshold: {} microseconds",
-            sync_threshold.num_microseconds().unwrap()
+            threshold.num_microseconds().unwrap()
         );
 
         if let Some(archive) = braid_archive {
@@ -741,18 +952,23 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
                 &data2d,
                 &camera_names_ref,
                 frame_readers,
-                sync_threshold,
+                threshold,
             )?)
         } else if let Some(approx_start_time) = approx_start_time {
             // In this path, we use the timestamps in the saved videos as the source
-            // of synchronization.
-            synchronize_readers_from(approx_start_time.into(), &mut frame_readers);
+            // of synchronization. Without braidz archive, all cameras have
+            // video.
+            let mut frame_readers: Vec<_> = frame_readers.into_iter().flatten().collect();
+            synchronize_readers_from(approx_start_time, &mut frame_readers);
 
-            Box::new(synced_iter::SyncedIter::new(
+            let synced_iter = synced_iter::SyncedIter::new(
                 frame_readers,
-                sync_threshold,
+                threshold,
                 frame_duration,
-            )?)
+                cfg.duplicate_timestamps,
+            )?;
+            timestamp_issue_counts = Some(synced_iter.issue_counts());
+            Box::new(synced_iter)
         } else {
             anyhow::bail!(
                 "Neither braidz archive nor input videos could be used as source of frame data."
@@ -760,13 +976,16 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
         }
     };
 
+    let cameras: Vec<OutputCamera> = sources.iter().map(CameraSource::output_camera).collect();
+
     let all_expected_cameras = camera_names
         .iter()
         .map(|x| RawCamName::new(x.clone()))
         .collect::<std::collections::BTreeSet<_>>();
 
-    // Initialize outputs
-    let output_storage: Vec<Result<OutputStorage, _>> =
+    // Initialize outputs. Each is returned with the frames it saves, if it is
+    // a summary.
+    let outputs: Vec<Result<(Output, Option<std::collections::BTreeSet<i64>>), _>> =
         join_all(cfg.output.clone().into_iter().map(|output| async {
             // Create output dirs if needed.
             let output_filename = std::path::PathBuf::from(output.filename());
@@ -774,14 +993,41 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
                 std::fs::create_dir_all(dest_dir)?;
             }
 
+            // Except for braidz, the storage of an output is created in its
+            // thread.
+            let cameras = cameras.clone();
             match output {
-                OutputConfig::Video(v) => Ok(OutputStorage::Video(Box::new(
-                    output_video::VideoStorage::new(&v, &output_filename, &sources)?,
-                ))),
-                OutputConfig::DebugTxt(_) => Ok(OutputStorage::Debug(DebugStorage {
-                    path: output_filename.clone(),
-                    fd: std::fs::File::create(&output_filename)?,
-                })),
+                OutputConfig::Video(v) => {
+                    let summary = match (&v.video_options.summary, &activity) {
+                        (Some(opts), Some(activity)) => Some(summary::Summary::new(
+                            opts,
+                            activity,
+                            expected_framerate.unwrap_or(f32::NAN).into(),
+                        )?),
+                        _ => None,
+                    };
+                    let summary_frames = summary.as_ref().map(|s| s.selected.clone());
+                    let worker = OutputWorker::spawn(output_filename.clone(), move || {
+                        Ok(OutputStorage::Video(Box::new(
+                            output_video::VideoStorage::new(
+                                &v,
+                                &output_filename,
+                                &cameras,
+                                summary,
+                                sync_threshold,
+                            )?,
+                        )))
+                    });
+                    Ok((Output::Worker(worker), summary_frames))
+                }
+                OutputConfig::DebugTxt(_) => {
+                    let worker = OutputWorker::spawn(output_filename.clone(), move || {
+                        Ok(OutputStorage::Debug(DebugStorage {
+                            fd: std::fs::File::create(&output_filename)?,
+                        }))
+                    });
+                    Ok((Output::Worker(worker), None))
+                }
                 OutputConfig::Braidz(b) => {
                     let (braidz_storage, coord_proc_fut) = output_braidz::BraidStorage::new(
                         cfg,
@@ -796,13 +1042,56 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
 
                     coord_proc_fut.await?.await??;
 
-                    Ok(OutputStorage::Braid(braidz_storage))
+                    Ok((Output::Braid(braidz_storage), None))
+                }
+                OutputConfig::HtmlReport(r) => {
+                    let cfg = cfg.clone();
+                    let worker = OutputWorker::spawn(output_filename.clone(), move || {
+                        Ok(OutputStorage::Report(Box::new(
+                            output_report::ReportStorage::new(
+                                &r,
+                                &output_filename,
+                                &cameras,
+                                &cfg,
+                            )?,
+                        )))
+                    });
+                    Ok((Output::Worker(worker), None))
+                }
+                OutputConfig::EventsCsv(_) => {
+                    let worker = OutputWorker::spawn(output_filename.clone(), move || {
+                        Ok(OutputStorage::Events(EventsStorage {
+                            wtr: csv::Writer::from_path(&output_filename)?,
+                        }))
+                    });
+                    Ok((Output::Worker(worker), None))
                 }
             }
         }))
         .await;
 
-    let mut output_storage: Vec<_> = output_storage.into_iter().collect::<Result<Vec<_>>>()?;
+    let (mut outputs, summary_frames): (Vec<Output>, Vec<_>) = outputs
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+
+    // If all outputs are summaries, frames which none of them saves are
+    // skipped without gathering their data.
+    let summary_frames: Option<std::collections::BTreeSet<i64>> = summary_frames
+        .into_iter()
+        .collect::<Option<Vec<_>>>()
+        .map(|all_frames| all_frames.into_iter().flatten().collect());
+
+    // The original images are only needed when rendering video frames.
+    let render_images = cfg
+        .output
+        .iter()
+        .any(|o| matches!(o, OutputConfig::Video(v) if !v.video_options.passthrough));
+    let has_debug = cfg
+        .output
+        .iter()
+        .any(|o| matches!(o, OutputConfig::DebugTxt(_)));
 
     // Trim to maximum number of frames.
     let moment_iter = match cfg.max_num_frames {
@@ -820,58 +1109,138 @@ pub async fn run_config(cfg: &Valid<BraidRetrackVideoConfig>) -> Result<Vec<std:
         None => ProgressBar::new_spinner(),
     };
 
+    let mut qc_stats = qc::QcStats::new(&sources);
+
     // Iterate over all output frames.
     for (out_fno, synced_data) in moment_iter.enumerate() {
         pb.set_position(out_fno.try_into().unwrap());
         let synced_data = synced_data?;
 
+        // Events of skipped frames are not saved.
+        let frame_events = event_queue
+            .as_mut()
+            .map(|q| q.take_until(synced_data.timestamp))
+            .unwrap_or_default();
+
         if let Some(start_frame) = cfg.skip_n_first_output_frames {
             if out_fno < start_frame {
                 continue;
             }
         }
 
-        for output in output_storage.iter_mut() {
-            if let OutputStorage::Debug(d) = output {
-                writeln!(d.fd, "output frame {} ----------", out_fno)?;
+        if let Some(summary_frames) = &summary_frames {
+            let frame_num = synced_data.braidz_info.as_ref().map(|b| b.frame_num);
+            if !frame_num.is_some_and(|f| summary_frames.contains(&f)) {
+                continue;
             }
         }
 
+        let mut debug_lines = has_debug.then(|| vec![format!("output frame {out_fno} ----------")]);
+
         if out_fno % cfg.log_interval_frames.unwrap_or(100) == 0 {
             tracing::info!("frame {}", out_fno);
         }
 
+        qc_stats.add_frame(&synced_data);
+
         // --- Collect input data for this timepoint. -----
-        let all_cam_render_data =
-            gather_frame_data(&synced_data, &sources, &mut output_storage, cfg)?;
+        let all_cam_render_data = gather_frame_data(
+            &synced_data,
+            &sources,
+            render_images,
+            debug_lines.as_mut(),
+            cfg,
+        )?;
 
         // --- Done collecting input data for this timepoint. -----
-        for output in output_storage.iter_mut() {
-            output
-                .render_frame(out_fno, &synced_data, &all_cam_render_data)
-                .await?;
+        for output in outputs.iter_mut() {
+            if let Output::Braid(b) = output {
+                b.render_frame(out_fno, &synced_data, &all_cam_render_data)
+                    .await?;
+            }
+        }
+
+        // Queue the frame for the output threads. Each gets its own copy.
+        let frame = OutputFrame {
+            out_fno,
+            synced_data,
+            all_cam_render_data,
+            debug_lines: debug_lines.unwrap_or_default(),
+            events: frame_events,
+        };
+        let mut workers: Vec<&mut OutputWorker> = outputs
+            .iter_mut()
+            .filter_map(|output| match output {
+                Output::Worker(w) => Some(w),
+                Output::Braid(_) => None,
+            })
+            .collect();
+        if let Some((last, others)) = workers.split_last_mut() {
+            for worker in others.iter_mut() {
+                worker.send(OutputMsg::Frame(frame.clone())).await?;
+            }
+            last.send(OutputMsg::Frame(frame)).await?;
         }
     }
 
     pb.finish_and_clear();
 
-    Ok(output_storage
-        .iter()
-        .map(|d| d.path().to_path_buf())
-        .collect())
+    if let Some(issue_counts) = timestamp_issue_counts {
+        let issue_counts = issue_counts.lock().unwrap();
+        for (source, counts) in sources.iter().zip(issue_counts.iter()) {
+            if counts.duplicated > 0 || counts.non_monotonic > 0 {
+                tracing::warn!(
+                    "Camera {}: skipped {} frames with duplicated and {} frames with \
+                    non-monotonic timestamps.",
+                    source.per_cam_render.best_name,
+                    counts.duplicated,
+                    counts.non_monotonic,
+                );
+            }
+        }
+    }
+
+    let qc_failures = qc_stats.check(&cfg.qc);
+
+    // Finish the outputs once all their queued frames are processed.
+    for output in outputs.iter_mut() {
+        if let Output::Worker(w) = output {
+            w.send(OutputMsg::Finish(qc_failures.clone())).await?;
+        }
+    }
+    for output in outputs.iter_mut() {
+        if let Output::Worker(w) = output {
+            w.join().await?;
+        }
+    }
+
+    if !qc_failures.is_empty() {
+        return Err(QcFailed {
+            reasons: qc_failures,
+        }
+        .into());
+    }
+
+    Ok(outputs.iter().map(|d| d.path().to_path_buf()).collect())
 }
 
-fn gather_frame_data<'a>(
+/// Collect the data of each camera for an output frame.
+///
+/// The original images are converted only if `render_images` is set. If
+/// `debug_lines` is given, lines for the debug outputs are appended to it.
+fn gather_frame_data(
     synced_data: &SyncedPictures,
-    sources: &'a [CameraSource],
-    output_storage: &mut [OutputStorage],
+    sources: &[CameraSource],
+    render_images: bool,
+    mut debug_lines: Option<&mut Vec<String>>,
     cfg: &BraidRetrackVideoConfig,
-) -> Result<Vec<PerCamRenderFrame<'a>>> {
+) -> Result<Vec<PerCamRenderFrame>> {
     let synced_pics: &[OutTimepointPerCamera] = &synced_data.camera_pictures;
 
     let n_pics = synced_pics.len();
     let mut all_cam_render_data = Vec::with_capacity(n_pics);
     assert_eq!(n_pics, sources.len());
+
     for (per_cam, source) in synced_pics.iter().zip(sources.iter()) {
         // Copy the default information for this camera and then we will
         // start adding information relevant for this frame in time.
@@ -879,32 +1248,39 @@ fn gather_frame_data<'a>(
 
         // Did we get an image from the MP4 file?
         if let Some(pic) = &per_cam.image {
-            cam_render_data.set_original_image(pic)?;
+            if render_images {
+                cam_render_data.set_original_image(pic)?;
+            }
         }
         let mut wrote_debug = false;
 
         cam_render_data.pts_chrono = per_cam.timestamp;
+        cam_render_data.camera_settings = per_cam.camera_settings.clone();
+
+        if let (Some(settings), Some(lines)) = (&per_cam.camera_settings, debug_lines.as_mut()) {
+            lines.push(format!(
+                "   Camera settings {}: {}",
+                source.cam_id.best_name(),
+                format_camera_settings(settings),
+            ));
+        }
 
-        cam_render_data
-            .reprojected_points
-            .extend(synced_data.project_kests(source, &synced_data.recon));
+        for (obj_id, xy) in synced_data.project_kests(source, &synced_data.recon) {
+            cam_render_data.reprojected_points.push(xy);
+            cam_render_data.reprojected_obj_ids.push(obj_id);
+        }
 
         for row_data2d in per_cam.this_cam_this_frame.iter() {
-            {
-                for output in output_storage.iter_mut() {
-                    if let OutputStorage::Debug(d) = output {
-                        writeln!(
-                            d.fd,
-                            "   Collect {}: {}, frame {}, {}, {}",
-                            source.cam_id.best_name(),
-                            per_cam.timestamp,
-                            row_data2d.frame,
-                            row_data2d.x,
-                            row_data2d.y,
-                        )?;
-                        wrote_debug = true;
-                    }
-                }
+            if let Some(lines) = debug_lines.as_mut() {
+                lines.push(format!(
+                    "   Collect {}: {}, frame {}, {}, {}",
+                    source.cam_id.best_name(),
+                    per_cam.timestamp,
+                    row_data2d.frame,
+                    row_data2d.x,
+                    row_data2d.y,
+                ));
+                wrote_debug = true;
             }
 
             match &cfg.processing_config.feature_detection_method {
@@ -918,19 +1294,12 @@ fn gather_frame_data<'a>(
         }
 
         if !wrote_debug {
-            for output in output_storage.iter_mut() {
-                if let OutputStorage::Debug(d) = output {
-                    writeln!(
-                        d.fd,
-                        "   Collect {}: {} no points",
-                        source.cam_id.best_name(),
-                        per_cam.timestamp,
-                    )?;
-                    #[allow(unused_assignments)]
-                    {
-                        wrote_debug = true;
-                    }
-                }
+            if let Some(lines) = debug_lines.as_mut() {
+                lines.push(format!(
+                    "   Collect {}: {} no points",
+                    source.cam_id.best_name(),
+                    per_cam.timestamp,
+                ));
             }
         }
 
@@ -938,3 +1307,26 @@ fn gather_frame_data<'a>(
     }
     Ok(all_cam_render_data)
 }
+
+#[test]
+fn test_latest_start_time() {
+    // The second video was started 30 minutes after the first, after the
+    // daylight saving time transition in Central Europe at 01:00 UTC. Its
+    // local time is 1.5 hours later, but the time between the starts is not.
+    let before = DateTime::parse_from_rfc3339("2024-03-31T01:50:00+01:00").unwrap();
+    let after = DateTime::parse_from_rfc3339("2024-03-31T03:20:00+02:00").unwrap();
+    let latest = latest_start_time(&[after, before]).unwrap();
+    assert_eq!(latest, after);
+    assert_eq!(latest.to_rfc3339(), "2024-03-31T01:20:00+00:00");
+    assert_eq!(
+        latest - before.with_timezone(&Utc),
+        chrono::Duration::minutes(30)
+    );
+
+    // When the clocks go back, a later video can have an earlier local time.
+    let before = DateTime::parse_from_rfc3339("2024-10-27T02:50:00+02:00").unwrap();
+    let after = DateTime::parse_from_rfc3339("2024-10-27T02:10:00+01:00").unwrap();
+    assert_eq!(latest_start_time(&[before, after]).unwrap(), after);
+
+    assert!(latest_start_time(&[]).is_none());
+}
@@ -45,7 +45,7 @@ impl BraidStorage {
                 anyhow::bail!("extension of braidz output file must be '.braidz'.");
             };
 
-        let recon = match cfg.processing_config.camera_calibration_source {
+        let mut recon = match cfg.processing_config.camera_calibration_source {
             CameraCalibrationSource::None => None,
             CameraCalibrationSource::CopyExisting => {
                 let braidz_types::CalibrationInfo { water, cameras } = braidz_calibration.unwrap();
@@ -55,6 +55,16 @@ impl BraidStorage {
             }
         };
 
+        if let Some(water) = cfg.processing_config.refractive_boundary {
+            match recon.as_mut() {
+                Some(recon) => recon.set_refractive_boundary(Some(water)),
+                None => anyhow::bail!(
+                    "'refractive_boundary' requires a camera calibration (set \
+                    'camera_calibration_source')."
+                ),
+            }
+        }
+
         let tracking_params: flydra_types::TrackingParams = match cfg
             .processing_config
             .tracking_parameters_source
@@ -90,6 +100,8 @@ impl BraidStorage {
                         current_image_png,
                         cam_settings_data: None,
                         feature_detect_settings: None,
+                        feature_detect_settings_overridden: false,
+                        provenance: None,
                     },
                 )
             })
@@ -122,16 +134,19 @@ impl BraidStorage {
                 mini_arena_debug_image_dir: None,
                 write_buffer_size_num_messages:
                     braid_config_data::default_write_buffer_size_num_messages(),
+                braidz_encryption_key: None,
+                braidz_spill_dir: None,
             },
             cam_manager.clone(),
             recon.clone(),
-            flydra2::BraidMetadataBuilder::saving_program_name("braid-process-video"),
+            flydra2::BraidMetadataBuilder::saving_program_name("braid-process-video")
+                .with_provenance(crate::provenance()),
         )?;
 
         let save_cfg = flydra2::StartSavingCsvConfig {
             out_dir: output_dirname.to_path_buf(),
             local: None,
-            git_rev: "<impossible git rev>".into(),
+            git_rev: env!("GIT_HASH").to_string(),
             fps: expected_framerate,
             per_cam_data: braidz_per_cam_save_data,
             print_stats: true,
@@ -159,7 +174,7 @@ impl BraidStorage {
         &mut self,
         out_fno: usize,
         synced_data: &crate::SyncedPictures,
-        all_cam_render_data: &[PerCamRenderFrame<'_>],
+        all_cam_render_data: &[PerCamRenderFrame],
     ) -> Result<()> {
         for cam_render_data in all_cam_render_data.iter() {
             let raw_cam_name = cam_render_data.p.raw_name.clone();
@@ -193,6 +208,7 @@ impl BraidStorage {
                         cur_val: 0,
                         mean_val: f64::NAN,
                         sumsqf_val: f64::NAN,
+                        appearance: None,
                     };
                     flydra2::NumberedRawUdpPoint {
                         idx: idx.try_into().unwrap(),
@@ -0,0 +1,535 @@
+//! A standalone HTML report of the processing.
+//!
+//! The report is a single HTML file which can be opened in any web browser and
+//! shared with collaborators. It contains a JSON summary of the processing,
+//! plots showing the quality of the synchronization, a thumbnail of each camera
+//! and a short preview clip of each camera. Images and clips are embedded as
+//! data URLs.
+
+use std::{
+    io::{Cursor, Seek, SeekFrom, Write},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use color_eyre::Result;
+use serde::Serialize;
+use timestamped_frame::ExtraTimeData;
+
+use ci2_remote_control::{Mp4Codec, Mp4RecordingConfig, OpenH264Options, OpenH264Preset};
+
+use crate::{config::HtmlReportOutputConfig, BraidRetrackVideoConfig};
+
+/// The maximum number of points per camera in the synchronization plots.
+///
+/// Longer recordings are shown with one point per bin of output frames.
+const MAX_PLOT_POINTS: usize = 2000;
+const PLOT_WIDTH: f64 = 800.0;
+const PLOT_HEIGHT: f64 = 200.0;
+const PLOT_MARGIN: f64 = 50.0;
+const MISSING_ROW_HEIGHT: f64 = 20.0;
+const CAMERA_COLORS: &[&str] = &[
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+];
+
+/// An in-memory MP4 file.
+///
+/// [mp4_writer::Mp4Writer] takes ownership of the file it writes, so the
+/// buffer is shared to get the data back when done.
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<Mutex<Cursor<Vec<u8>>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for SharedBuf {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.0.lock().unwrap().seek(pos)
+    }
+}
+
+/// The preview clip of a camera while it is being written.
+struct Preview<'lib> {
+    writer: mp4_writer::Mp4Writer<'lib, SharedBuf>,
+    buf: SharedBuf,
+}
+
+struct CameraReport<'lib> {
+    name: String,
+    /// The first frame, if the camera has a video.
+    thumbnail_png: Option<Vec<u8>>,
+    /// The offset of the camera frame from the synchronized time, in
+    /// milliseconds, for each output frame. `None` if the camera has no frame.
+    offsets_msec: Vec<Option<f64>>,
+    n_detections: usize,
+    preview: Option<Preview<'lib>>,
+    preview_mp4: Option<Vec<u8>>,
+}
+
+impl<'lib> CameraReport<'lib> {
+    fn finish_preview(&mut self) -> Result<()> {
+        if let Some(mut preview) = self.preview.take() {
+            preview.writer.finish()?;
+            let data = std::mem::take(preview.buf.0.lock().unwrap().get_mut());
+            self.preview_mp4 = Some(data);
+        }
+        Ok(())
+    }
+
+    fn summary(&self) -> CameraSummary {
+        let offsets: Vec<f64> = self
+            .offsets_msec
+            .iter()
+            .flatten()
+            .map(|x| x.abs())
+            .collect();
+        let (mean_abs_offset_msec, max_abs_offset_msec) = if offsets.is_empty() {
+            (None, None)
+        } else {
+            (
+                Some(offsets.iter().sum::<f64>() / offsets.len() as f64),
+                Some(offsets.iter().copied().fold(0.0, f64::max)),
+            )
+        };
+        CameraSummary {
+            name: self.name.clone(),
+            n_frames: offsets.len(),
+            n_missing_frames: self.offsets_msec.len() - offsets.len(),
+            n_detections: self.n_detections,
+            mean_abs_offset_msec,
+            max_abs_offset_msec,
+        }
+    }
+}
+
+/// The JSON summary of the processing.
+#[derive(Serialize)]
+struct ProcessingSummary<'a> {
+    provenance: provenance::Provenance,
+    config: &'a BraidRetrackVideoConfig,
+    n_output_frames: usize,
+    first_timestamp: Option<DateTime<Utc>>,
+    last_timestamp: Option<DateTime<Utc>>,
+    cameras: Vec<CameraSummary>,
+    qc: QcSummary<'a>,
+}
+
+/// The result of the quality control.
+#[derive(Serialize)]
+struct QcSummary<'a> {
+    passed: bool,
+    failures: &'a [String],
+}
+
+#[derive(Serialize)]
+struct CameraSummary {
+    name: String,
+    n_frames: usize,
+    n_missing_frames: usize,
+    n_detections: usize,
+    mean_abs_offset_msec: Option<f64>,
+    max_abs_offset_msec: Option<f64>,
+}
+
+pub(crate) struct ReportStorage<'lib> {
+    pub(crate) path: std::path::PathBuf,
+    fd: std::fs::File,
+    config: BraidRetrackVideoConfig,
+    preview_duration: chrono::Duration,
+    cameras: Vec<CameraReport<'lib>>,
+    first_timestamp: Option<DateTime<Utc>>,
+    last_timestamp: Option<DateTime<Utc>>,
+}
+
+impl<'lib> ReportStorage<'lib> {
+    pub(crate) fn new(
+        r: &HtmlReportOutputConfig,
+        output_filename: &std::path::Path,
+        cameras: &[crate::OutputCamera],
+        config: &BraidRetrackVideoConfig,
+    ) -> Result<Self> {
+        let fd = std::fs::File::create(output_filename)?;
+        let cameras = cameras
+            .iter()
+            .map(|s| CameraReport {
+                name: s.per_cam_render.best_name.clone(),
+                thumbnail_png: s
+                    .movie
+                    .as_ref()
+                    .map(|_| s.per_cam_render.frame0_png_buf.data.clone()),
+                offsets_msec: Vec::new(),
+                n_detections: 0,
+                preview: None,
+                preview_mp4: None,
+            })
+            .collect();
+        Ok(Self {
+            path: output_filename.to_path_buf(),
+            fd,
+            config: config.clone(),
+            preview_duration: chrono::Duration::from_std(std::time::Duration::from_secs_f64(
+                r.preview_duration_secs,
+            ))?,
+            cameras,
+            first_timestamp: None,
+            last_timestamp: None,
+        })
+    }
+
+    pub(crate) fn render_frame(&mut self, synced_data: &crate::SyncedPictures) -> Result<()> {
+        let timestamp = synced_data.timestamp;
+        let first_timestamp = *self.first_timestamp.get_or_insert(timestamp);
+        self.last_timestamp = Some(timestamp);
+        let in_preview = timestamp - first_timestamp < self.preview_duration;
+
+        for (cam, per_cam) in self
+            .cameras
+            .iter_mut()
+            .zip(synced_data.camera_pictures.iter())
+        {
+            cam.n_detections += per_cam
+                .this_cam_this_frame
+                .iter()
+                .filter(|row| row.x.is_finite())
+                .count();
+            let Some(image) = &per_cam.image else {
+                cam.offsets_msec.push(None);
+                continue;
+            };
+            let image_timestamp = image.extra().host_timestamp();
+            let offset = image_timestamp - timestamp;
+            cam.offsets_msec
+                .push(offset.num_microseconds().map(|x| x as f64 / 1000.0));
+
+            if !in_preview {
+                cam.finish_preview()?;
+                continue;
+            }
+            if cam.preview.is_none() && cam.preview_mp4.is_none() {
+                let buf = SharedBuf::default();
+                let mp4_cfg = Mp4RecordingConfig {
+                    codec: Mp4Codec::H264OpenH264(OpenH264Options {
+                        debug: false,
+                        preset: OpenH264Preset::AllFrames,
+                    }),
+                    max_framerate: Default::default(),
+                    h264_metadata: None,
+                    container_metadata: None,
+                    fragment_duration_secs: None,
+                };
+                let writer = mp4_writer::Mp4Writer::new(buf.clone(), mp4_cfg, None)?;
+                cam.preview = Some(Preview { writer, buf });
+            }
+            if let Some(preview) = cam.preview.as_mut() {
+                preview.writer.write_dynamic(image, image_timestamp)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the report.
+    ///
+    /// `qc_failures` are the reasons why the quality control failed, if any.
+    pub(crate) fn finish(&mut self, qc_failures: &[String]) -> Result<()> {
+        for cam in self.cameras.iter_mut() {
+            cam.finish_preview()?;
+        }
+        let summary = ProcessingSummary {
+            provenance: crate::provenance(),
+            config: &self.config,
+            n_output_frames: self
+                .cameras
+                .first()
+                .map(|cam| cam.offsets_msec.len())
+                .unwrap_or(0),
+            first_timestamp: self.first_timestamp,
+            last_timestamp: self.last_timestamp,
+            cameras: self.cameras.iter().map(CameraReport::summary).collect(),
+            qc: QcSummary {
+                passed: qc_failures.is_empty(),
+                failures: qc_failures,
+            },
+        };
+        let summary_json = serde_json::to_string_pretty(&summary)?;
+
+        let mut html = String::new();
+        html.push_str(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+            <title>braid-process-video report</title>\n<style>\n\
+            body { font-family: sans-serif; margin: 2em; }\n\
+            table { border-collapse: collapse; }\n\
+            td, th { border: 1px solid #ccc; padding: 0.5em; vertical-align: top; }\n\
+            img, video { max-width: 320px; }\n\
+            pre { background: #f4f4f4; padding: 1em; overflow-x: auto; }\n\
+            </style>\n</head>\n<body>\n<h1>braid-process-video report</h1>\n",
+        );
+        html.push_str(&format!(
+            "<p>{} output frames from {} to {}.</p>\n",
+            summary.n_output_frames,
+            format_timestamp(summary.first_timestamp),
+            format_timestamp(summary.last_timestamp),
+        ));
+        if !summary.qc.passed {
+            html.push_str("<p><strong>Quality control failed:</strong></p>\n<ul>\n");
+            for failure in summary.qc.failures {
+                html.push_str(&format!("<li>{}</li>\n", escape_html(failure)));
+            }
+            html.push_str("</ul>\n");
+        }
+
+        html.push_str("<h2>Cameras</h2>\n<table>\n<tr><th>Camera</th><th>Frames</th><th>Thumbnail</th><th>Preview</th></tr>\n");
+        for (cam, cam_summary) in self.cameras.iter().zip(summary.cameras.iter()) {
+            let thumbnail = match &cam.thumbnail_png {
+                Some(png) => format!(
+                    "<img src=\"data:image/png;base64,{}\" alt=\"first frame\">",
+                    base64::encode(png)
+                ),
+                None => "no video".to_string(),
+            };
+            let preview = match &cam.preview_mp4 {
+                Some(mp4) => format!(
+                    "<video controls loop muted src=\"data:video/mp4;base64,{}\"></video>",
+                    base64::encode(mp4)
+                ),
+                None => "no video".to_string(),
+            };
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{} frames<br>{} missing<br>{} detections</td><td>{thumbnail}</td><td>{preview}</td></tr>\n",
+                escape_html(&cam.name),
+                cam_summary.n_frames,
+                cam_summary.n_missing_frames,
+                cam_summary.n_detections,
+            ));
+        }
+        html.push_str("</table>\n");
+
+        html.push_str("<h2>Synchronization</h2>\n");
+        html.push_str(
+            "<p>Offset of the timestamp of each camera frame from the synchronized time.</p>\n",
+        );
+        html.push_str(&self.offset_plot()?);
+        html.push_str("<p>Output frames without a frame of the camera (red).</p>\n");
+        html.push_str(&self.missing_plot()?);
+
+        html.push_str("<h2>Processing summary</h2>\n<pre>");
+        html.push_str(&escape_html(&summary_json));
+        html.push_str("</pre>\n</body>\n</html>\n");
+
+        self.fd.write_all(html.as_bytes())?;
+        self.fd.flush()?;
+        Ok(())
+    }
+
+    /// The number of output frames per plotted point.
+    fn bin_size(&self) -> usize {
+        let n_frames = self.cameras.first().map_or(0, |c| c.offsets_msec.len());
+        n_frames.div_ceil(MAX_PLOT_POINTS).max(1)
+    }
+
+    /// Plot the offsets from the synchronized time of each camera.
+    ///
+    /// For each bin of output frames, the largest offset is shown.
+    fn offset_plot(&self) -> Result<String> {
+        let bin_size = self.bin_size();
+        let n_frames = self.cameras.first().map_or(0, |c| c.offsets_msec.len());
+        let max_offset = self
+            .cameras
+            .iter()
+            .flat_map(|c| c.offsets_msec.iter().flatten())
+            .fold(1.0, |acc: f64, x| acc.max(x.abs()));
+        let n_bins = n_frames.div_ceil(bin_size).max(1);
+        let x_scale = PLOT_WIDTH / n_bins as f64;
+        let y_of = |offset: f64| PLOT_MARGIN + PLOT_HEIGHT / 2.0 * (1.0 - offset / max_offset);
+
+        let mut wtr = tagger::new(tagger::upgrade_write(Vec::<u8>::new()));
+        let svg_width = PLOT_WIDTH + 2.0 * PLOT_MARGIN;
+        let svg_height = PLOT_HEIGHT + 2.0 * PLOT_MARGIN;
+        wtr.elem("svg", |d| {
+            d.attr("xmlns", "http://www.w3.org/2000/svg")?;
+            d.attr("width", svg_width)?;
+            d.attr("height", svg_height)
+        })?
+        .build(|w| {
+            plot_frame(w, n_frames)?;
+            // The zero line and the range of the offsets.
+            for (offset, label) in [
+                (max_offset, format!("{max_offset:.1} ms")),
+                (0.0, "0 ms".to_string()),
+                (-max_offset, format!("{:.1} ms", -max_offset)),
+            ] {
+                w.elem("text", |d| {
+                    d.attr("x", PLOT_MARGIN - 5.0)?;
+                    d.attr("y", y_of(offset))?;
+                    d.attr("text-anchor", "end")?;
+                    d.attr("style", "font-size: 12px;")
+                })?
+                .build(|w| w.put_raw(label))?;
+            }
+            w.single("line", |d| {
+                d.attr("x1", PLOT_MARGIN)?;
+                d.attr("x2", PLOT_MARGIN + PLOT_WIDTH)?;
+                d.attr("y1", y_of(0.0))?;
+                d.attr("y2", y_of(0.0))?;
+                d.attr("style", "stroke: #ccc;")
+            })?;
+
+            for (cam_idx, cam) in self.cameras.iter().enumerate() {
+                let color = CAMERA_COLORS[cam_idx % CAMERA_COLORS.len()];
+                // Lines are interrupted by bins without frames.
+                let mut segments: Vec<Vec<(f64, f64)>> = vec![Vec::new()];
+                for (bin_idx, bin) in cam.offsets_msec.chunks(bin_size).enumerate() {
+                    let largest = bin
+                        .iter()
+                        .flatten()
+                        .copied()
+                        .max_by(|a, b| a.abs().total_cmp(&b.abs()));
+                    match largest {
+                        Some(offset) => segments
+                            .last_mut()
+                            .unwrap()
+                            .push((PLOT_MARGIN + (bin_idx as f64 + 0.5) * x_scale, y_of(offset))),
+                        None => segments.push(Vec::new()),
+                    }
+                }
+                for segment in segments.iter().filter(|s| !s.is_empty()) {
+                    let points: Vec<String> = segment
+                        .iter()
+                        .map(|(x, y)| format!("{x:.1},{y:.1}"))
+                        .collect();
+                    w.single("polyline", |d| {
+                        d.attr("points", points.join(" "))?;
+                        d.attr("style", format!("fill: none; stroke: {color};"))
+                    })?;
+                }
+                legend(w, cam_idx, &cam.name, color)?;
+            }
+            Ok(())
+        })?;
+        svg_string(wtr)
+    }
+
+    /// Plot the output frames without a frame, one row per camera.
+    fn missing_plot(&self) -> Result<String> {
+        let bin_size = self.bin_size();
+        let n_frames = self.cameras.first().map_or(0, |c| c.offsets_msec.len());
+        let n_bins = n_frames.div_ceil(bin_size).max(1);
+        let x_scale = PLOT_WIDTH / n_bins as f64;
+
+        let mut wtr = tagger::new(tagger::upgrade_write(Vec::<u8>::new()));
+        let svg_width = PLOT_WIDTH + 2.0 * PLOT_MARGIN;
+        let svg_height = MISSING_ROW_HEIGHT * self.cameras.len() as f64 + 2.0 * PLOT_MARGIN;
+        wtr.elem("svg", |d| {
+            d.attr("xmlns", "http://www.w3.org/2000/svg")?;
+            d.attr("width", svg_width)?;
+            d.attr("height", svg_height)
+        })?
+        .build(|w| {
+            for (cam_idx, cam) in self.cameras.iter().enumerate() {
+                let y = PLOT_MARGIN + cam_idx as f64 * MISSING_ROW_HEIGHT;
+                w.single("rect", |d| {
+                    d.attr("x", PLOT_MARGIN)?;
+                    d.attr("y", y)?;
+                    d.attr("width", PLOT_WIDTH)?;
+                    d.attr("height", MISSING_ROW_HEIGHT - 4.0)?;
+                    d.attr("style", "fill: #eee;")
+                })?;
+                for (bin_idx, bin) in cam.offsets_msec.chunks(bin_size).enumerate() {
+                    if bin.iter().all(Option::is_some) {
+                        continue;
+                    }
+                    w.single("rect", |d| {
+                        d.attr(
+                            "x",
+                            format!("{:.1}", PLOT_MARGIN + bin_idx as f64 * x_scale),
+                        )?;
+                        d.attr("y", y)?;
+                        d.attr("width", format!("{:.1}", x_scale.max(1.0)))?;
+                        d.attr("height", MISSING_ROW_HEIGHT - 4.0)?;
+                        d.attr("style", "fill: #d62728;")
+                    })?;
+                }
+                w.elem("text", |d| {
+                    d.attr("x", PLOT_MARGIN - 5.0)?;
+                    d.attr("y", y + MISSING_ROW_HEIGHT / 2.0)?;
+                    d.attr("text-anchor", "end")?;
+                    d.attr("style", "font-size: 12px;")
+                })?
+                .build(|w| w.put_raw(escape_html(&cam.name)))?;
+            }
+            Ok(())
+        })?;
+        svg_string(wtr)
+    }
+}
+
+/// Draw the border and the x axis labels of a plot.
+fn plot_frame<W: std::fmt::Write>(
+    w: &mut tagger::ElemWriter<W>,
+    n_frames: usize,
+) -> std::fmt::Result {
+    w.single("rect", |d| {
+        d.attr("x", PLOT_MARGIN)?;
+        d.attr("y", PLOT_MARGIN)?;
+        d.attr("width", PLOT_WIDTH)?;
+        d.attr("height", PLOT_HEIGHT)?;
+        d.attr("style", "fill: none; stroke: black;")
+    })?;
+    for (x, label) in [
+        (PLOT_MARGIN, "0".to_string()),
+        (PLOT_MARGIN + PLOT_WIDTH, n_frames.to_string()),
+    ] {
+        w.elem("text", |d| {
+            d.attr("x", x)?;
+            d.attr("y", PLOT_MARGIN + PLOT_HEIGHT + 15.0)?;
+            d.attr("text-anchor", "middle")?;
+            d.attr("style", "font-size: 12px;")
+        })?
+        .build(|w| w.put_raw(label))?;
+    }
+    w.elem("text", |d| {
+        d.attr("x", PLOT_MARGIN + PLOT_WIDTH / 2.0)?;
+        d.attr("y", PLOT_MARGIN + PLOT_HEIGHT + 30.0)?;
+        d.attr("text-anchor", "middle")?;
+        d.attr("style", "font-size: 12px;")
+    })?
+    .build(|w| w.put_raw("output frame"))?;
+    Ok(())
+}
+
+fn legend<W: std::fmt::Write>(
+    w: &mut tagger::ElemWriter<W>,
+    cam_idx: usize,
+    name: &str,
+    color: &str,
+) -> std::fmt::Result {
+    w.elem("text", |d| {
+        d.attr("x", PLOT_MARGIN + cam_idx as f64 * 120.0)?;
+        d.attr("y", PLOT_MARGIN - 15.0)?;
+        d.attr("style", format!("font-size: 12px; fill: {color};"))
+    })?
+    .build(|w| w.put_raw(escape_html(name)))?;
+    Ok(())
+}
+
+fn svg_string(wtr: tagger::ElemWriter<tagger::WriteAdaptor<Vec<u8>>>) -> Result<String> {
+    let fmt_wtr = wtr.into_writer();
+    fmt_wtr.error?;
+    Ok(String::from_utf8(fmt_wtr.inner)?)
+}
+
+fn format_timestamp(timestamp: Option<DateTime<Utc>>) -> String {
+    timestamp.map_or_else(|| "-".to_string(), |t| t.to_string())
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -2,58 +2,88 @@ use color_eyre::Result;
 use ordered_float::NotNan;
 use std::io::Write;
 
-use crate::{output_braidz::BraidStorage, output_video::VideoStorage, PerCamRenderFrame};
+use crate::{
+    output_braidz::BraidStorage,
+    output_report::ReportStorage,
+    output_video::VideoStorage,
+    output_worker::{OutputFrame, OutputWorker},
+    PerCamRenderFrame,
+};
 
+/// An output of [crate::run_config].
+pub(crate) enum Output {
+    /// An output processed in its own thread.
+    Worker(OutputWorker),
+    Braid(BraidStorage),
+}
+
+impl Output {
+    pub(crate) fn path(&self) -> &std::path::Path {
+        match self {
+            Output::Worker(w) => &w.path,
+            Output::Braid(b) => &b.output_braidz_path,
+        }
+    }
+}
+
+/// The storage of an output processed by an [OutputWorker].
 pub(crate) enum OutputStorage<'lib> {
     Video(Box<VideoStorage<'lib>>),
     Debug(DebugStorage),
-    Braid(BraidStorage),
+    Report(Box<ReportStorage<'lib>>),
+    Events(EventsStorage),
 }
 
 impl<'lib> OutputStorage<'lib> {
-    pub(crate) async fn render_frame(
-        &mut self,
-        out_fno: usize,
-        synced_data: &crate::SyncedPictures,
-        all_cam_render_data: &[PerCamRenderFrame<'_>],
-    ) -> Result<()> {
+    pub(crate) fn render_frame(&mut self, frame: &OutputFrame) -> Result<()> {
         match self {
             OutputStorage::Debug(d) => {
-                d.render_frame(out_fno, synced_data, all_cam_render_data)?;
-            }
-            OutputStorage::Braid(b) => {
-                b.render_frame(out_fno, synced_data, all_cam_render_data)
-                    .await?;
+                d.render_frame(frame)?;
             }
             OutputStorage::Video(v) => {
-                v.render_frame(out_fno, synced_data, all_cam_render_data)
-                    .await?;
+                v.render_frame(
+                    frame.out_fno,
+                    &frame.synced_data,
+                    &frame.all_cam_render_data,
+                    &frame.events,
+                )?;
+            }
+            OutputStorage::Report(r) => {
+                r.render_frame(&frame.synced_data)?;
+            }
+            OutputStorage::Events(e) => {
+                e.render_frame(frame)?;
             }
         }
         Ok(())
     }
 
-    pub(crate) fn path(&self) -> &std::path::Path {
+    /// Complete the output after the last frame.
+    ///
+    /// `qc_failures` are the reasons why the quality control failed, if any.
+    pub(crate) fn finish(&mut self, qc_failures: &[String]) -> Result<()> {
         match self {
-            OutputStorage::Debug(d) => &d.path,
-            OutputStorage::Braid(b) => &b.output_braidz_path,
-            OutputStorage::Video(v) => &v.path,
+            OutputStorage::Debug(d) => d.fd.flush()?,
+            OutputStorage::Video(v) => v.finish()?,
+            OutputStorage::Report(r) => r.finish(qc_failures)?,
+            OutputStorage::Events(e) => e.wtr.flush()?,
         }
+        Ok(())
     }
 }
 
 pub(crate) struct DebugStorage {
     pub(crate) fd: std::fs::File,
-    pub(crate) path: std::path::PathBuf,
 }
 
 impl DebugStorage {
-    pub(crate) fn render_frame(
-        &mut self,
-        out_fno: usize,
-        synced_data: &crate::SyncedPictures,
-        all_cam_render_data: &[PerCamRenderFrame<'_>],
-    ) -> Result<()> {
+    pub(crate) fn render_frame(&mut self, frame: &OutputFrame) -> Result<()> {
+        let out_fno = frame.out_fno;
+        let synced_data = &frame.synced_data;
+        let all_cam_render_data: &[PerCamRenderFrame] = &frame.all_cam_render_data;
+        for line in frame.debug_lines.iter() {
+            writeln!(self.fd, "{line}")?;
+        }
         if let Some(braidz_info) = synced_data.braidz_info.as_ref() {
             if let Some(ts) = braidz_info.trigger_timestamp.as_ref() {
                 let dt: chrono::DateTime<chrono::Utc> = ts.into();
@@ -101,3 +131,37 @@ impl DebugStorage {
         Ok(())
     }
 }
+
+/// A row of the events CSV output.
+#[derive(serde::Serialize)]
+struct EventRow<'a> {
+    output_frame: usize,
+    /// The frame number in the braidz archive.
+    braidz_frame: Option<i64>,
+    /// The synchronized time of the output frame.
+    frame_timestamp: f64,
+    event_timestamp: f64,
+    source: &'a str,
+    message: &'a str,
+}
+
+pub(crate) struct EventsStorage {
+    pub(crate) wtr: csv::Writer<std::fs::File>,
+}
+
+impl EventsStorage {
+    pub(crate) fn render_frame(&mut self, frame: &OutputFrame) -> Result<()> {
+        let synced_data = &frame.synced_data;
+        for event in frame.events.iter() {
+            self.wtr.serialize(EventRow {
+                output_frame: frame.out_fno,
+                braidz_frame: synced_data.braidz_info.as_ref().map(|b| b.frame_num),
+                frame_timestamp: datetime_conversion::datetime_to_f64(&synced_data.timestamp),
+                event_timestamp: datetime_conversion::datetime_to_f64(&event.timestamp),
+                source: &event.source,
+                message: &event.message,
+            })?;
+        }
+        Ok(())
+    }
+}
@@ -3,11 +3,23 @@ use color_eyre::{
     eyre::{self as anyhow},
     Result,
 };
-use std::io::Write;
+use std::{collections::BTreeMap, io::Write};
 
-use ci2_remote_control::{Mp4Codec, Mp4RecordingConfig};
+use ci2_remote_control::{H264Metadata, Mp4Codec, Mp4RecordingConfig};
+use frame_source::{FrameData, FrameDataSource, ImageDataMode};
+use timestamped_frame::ExtraTimeData;
 
-use crate::{config::VideoOutputOptions, OutTimepointPerCamera, PerCamRenderFrame};
+use crate::{
+    config::{DisplayTimezone, FollowCamOptions, VideoOutputOptions},
+    events::Event,
+    summary::{Summary, TIMELINE_HEIGHT, TIMELINE_PROGRESS_STYLE, TIMELINE_TRACK_STYLE},
+    OutTimepointPerCamera, PerCamRenderFrame,
+};
+
+/// The color of the markers of experiment events.
+const EVENT_COLOR: &str = "orange";
+/// The SVG style of the frame drawn around the images at an event.
+const EVENT_FRAME_STYLE: &str = "fill: none; stroke: orange; stroke-width: 8;";
 
 pub(crate) struct VideoStorage<'lib> {
     pub(crate) path: std::path::PathBuf,
@@ -20,21 +32,104 @@ pub(crate) struct VideoStorage<'lib> {
     pub(crate) feature_style: String,
     pub(crate) reprojected_style: String,
     pub(crate) cam_text_style: String,
+    pub(crate) background_style: String,
     pub(crate) video_options: VideoOutputOptions,
     pub(crate) cum_width: usize,
     pub(crate) cum_height: usize,
     pub(crate) usvg_opt: usvg::Options,
+    passthrough: Option<Passthrough>,
+    summary: Option<Summary>,
+    /// The maximum offset of camera frames from the synchronized time, if
+    /// known.
+    sync_threshold: Option<chrono::Duration>,
+    /// The duration for which the message of an event is shown.
+    event_display: chrono::Duration,
+    /// The events whose message is currently shown.
+    shown_events: Vec<Event>,
+    follow_cam: Option<FollowCam>,
+}
+
+/// The zoomed views following the tracked objects.
+struct FollowCam {
+    opts: FollowCamOptions,
+    /// The smoothed position of each shown object, by camera index and object
+    /// id.
+    positions: BTreeMap<(usize, u32), (f64, f64)>,
+}
+
+impl FollowCam {
+    fn new(opts: FollowCamOptions) -> Self {
+        Self {
+            opts,
+            positions: BTreeMap::new(),
+        }
+    }
+
+    /// Update the smoothed positions with the reprojected points of a frame.
+    ///
+    /// Returns the object ids and smoothed positions of the objects shown
+    /// below each camera. An object which is not shown is forgotten, so its
+    /// position is not smoothed when it is shown again.
+    fn update(
+        &mut self,
+        all_cam_render_data: &[PerCamRenderFrame],
+        composite_margin_pixels: usize,
+    ) -> Vec<Vec<(u32, (f64, f64))>> {
+        let panel_size = self.opts.panel_size;
+        let smoothing = self.opts.smoothing;
+        let mut positions = BTreeMap::new();
+        let mut shown = Vec::with_capacity(all_cam_render_data.len());
+        for (cam_idx, cam_render_data) in all_cam_render_data.iter().enumerate() {
+            let n_fit = (cam_render_data.p.width + composite_margin_pixels)
+                / (panel_size + composite_margin_pixels);
+            let mut objects: Vec<(u32, (f64, f64))> = cam_render_data
+                .reprojected_obj_ids
+                .iter()
+                .zip(cam_render_data.reprojected_points.iter())
+                .map(|(obj_id, xy)| (*obj_id, (xy.0.into_inner(), xy.1.into_inner())))
+                .collect();
+            objects.sort_by_key(|(obj_id, _)| *obj_id);
+            objects.truncate(self.opts.max_objects.min(n_fit));
+            for (obj_id, xy) in objects.iter_mut() {
+                if let Some(prev) = self.positions.get(&(cam_idx, *obj_id)) {
+                    *xy = (
+                        smoothing * prev.0 + (1.0 - smoothing) * xy.0,
+                        smoothing * prev.1 + (1.0 - smoothing) * xy.1,
+                    );
+                }
+                positions.insert((cam_idx, *obj_id), *xy);
+            }
+            shown.push(objects);
+        }
+        self.positions = positions;
+        shown
+    }
+}
+
+/// Copies the encoded frames of the input video to the output.
+struct Passthrough {
+    /// Reader of the input video which does not decode the frames.
+    reader: Box<dyn Iterator<Item = Result<FrameData>>>,
+    frame0_time: DateTime<Utc>,
+    width: u32,
+    height: u32,
+    /// The indices of the keyframes of the input video, if known.
+    keyframe_indices: Vec<usize>,
+    /// Set once the first keyframe has been written.
+    started: bool,
 }
 
 impl<'lib> VideoStorage<'lib> {
     pub(crate) fn new(
         v: &crate::config::VideoOutputConfig,
         output_filename: &std::path::Path,
-        sources: &[crate::CameraSource],
+        cameras: &[crate::OutputCamera],
+        summary: Option<Summary>,
+        sync_threshold: Option<chrono::Duration>,
     ) -> Result<Self> {
         // compute output width and height
-        let cum_width: usize = sources.iter().map(|s| s.per_cam_render.width).sum();
-        let cum_height: usize = sources
+        let cum_width: usize = cameras.iter().map(|s| s.per_cam_render.width).sum();
+        let cum_height: usize = cameras
             .iter()
             .map(|s| s.per_cam_render.height)
             .max()
@@ -50,6 +145,10 @@ impl<'lib> VideoStorage<'lib> {
         }
         let fd = std::fs::File::create(output_filename)?;
 
+        let mut h264_metadata =
+            H264Metadata::new("braid-process-video", chrono::Local::now().into());
+        h264_metadata.provenance = Some(crate::provenance());
+
         let mp4_cfg = match v.video_options.codec {
             crate::config::VideoCodecConfig::OpenH264 => {
                 use ci2_remote_control::OpenH264Preset;
@@ -61,13 +160,17 @@ impl<'lib> VideoStorage<'lib> {
                 Mp4RecordingConfig {
                     codec,
                     max_framerate: Default::default(),
-                    h264_metadata: None,
+                    h264_metadata: Some(h264_metadata),
+                    container_metadata: None,
+                    fragment_duration_secs: None,
                 }
             }
             crate::config::VideoCodecConfig::LessAvc => Mp4RecordingConfig {
                 codec: Mp4Codec::H264LessAvc,
                 max_framerate: Default::default(),
-                h264_metadata: None,
+                h264_metadata: Some(h264_metadata),
+                container_metadata: None,
+                fragment_duration_secs: None,
             },
         };
 
@@ -77,44 +180,96 @@ impl<'lib> VideoStorage<'lib> {
             .composite_margin_pixels
             .unwrap_or(crate::DEFAULT_COMPOSITE_MARGIN_PIXELS);
 
-        let feature_radius = v
-            .video_options
-            .feature_radius
-            .as_ref()
-            .map(Clone::clone)
-            .unwrap_or_else(|| crate::DEFAULT_FEATURE_RADIUS.to_string());
-        let feature_style = v
-            .video_options
-            .feature_style
-            .as_ref()
-            .map(Clone::clone)
-            .unwrap_or_else(|| crate::DEFAULT_FEATURE_STYLE.to_string());
-
-        let reprojected_radius = v
-            .video_options
-            .reprojected_radius
-            .as_ref()
-            .map(Clone::clone)
-            .unwrap_or_else(|| crate::DEFAULT_REPROJECTED_RADIUS.to_string());
-        let reprojected_style = v
-            .video_options
-            .reprojected_style
-            .as_ref()
-            .map(Clone::clone)
-            .unwrap_or_else(|| crate::DEFAULT_REPROJECTED_STYLE.to_string());
+        let theme = match &v.video_options.theme {
+            Some(theme_fname) => crate::Theme::from_file(theme_fname)?,
+            None => crate::Theme::default(),
+        };
 
-        let cam_text_style = v
-            .video_options
-            .cam_text_style
-            .as_ref()
-            .map(Clone::clone)
-            .unwrap_or_else(|| crate::DEFAULT_CAMERA_TEXT_STYLE.to_string());
+        // Options set directly take precedence over the theme, which takes
+        // precedence over the defaults.
+        let resolve = |opt: &Option<String>, theme_value: Option<String>, default: &str| {
+            opt.clone()
+                .or(theme_value)
+                .unwrap_or_else(|| default.to_string())
+        };
+        let opts = &v.video_options;
+        let feature_radius = resolve(
+            &opts.feature_radius,
+            theme.feature_radius,
+            crate::DEFAULT_FEATURE_RADIUS,
+        );
+        let feature_style = resolve(
+            &opts.feature_style,
+            theme.feature_style,
+            crate::DEFAULT_FEATURE_STYLE,
+        );
+        let reprojected_radius = resolve(
+            &opts.reprojected_radius,
+            theme.reprojected_radius,
+            crate::DEFAULT_REPROJECTED_RADIUS,
+        );
+        let reprojected_style = resolve(
+            &opts.reprojected_style,
+            theme.reprojected_style,
+            crate::DEFAULT_REPROJECTED_STYLE,
+        );
+        let cam_text_style = resolve(
+            &opts.cam_text_style,
+            theme.cam_text_style,
+            crate::DEFAULT_CAMERA_TEXT_STYLE,
+        );
+        let background_style = resolve(
+            &opts.background_style,
+            theme.background_style,
+            crate::DEFAULT_BACKGROUND_STYLE,
+        );
+
+        let event_display_secs = opts
+            .event_display_secs
+            .unwrap_or(crate::DEFAULT_EVENT_DISPLAY_SECS);
+        let event_display =
+            chrono::Duration::microseconds((event_display_secs * 1e6).round() as i64);
 
         let mut usvg_opt = usvg::Options::default();
         // Get file's absolute directory.
         // usvg_opt.resources_dir = std::fs::canonicalize(&args[1]).ok().and_then(|p| p.parent().map(|p| p.to_path_buf()));
         usvg_opt.fontdb.load_system_fonts();
 
+        let passthrough = if v.video_options.passthrough {
+            let (full_path, mp4_track) = match cameras {
+                [crate::OutputCamera {
+                    movie: Some((full_path, mp4_track)),
+                    ..
+                }] => (full_path, mp4_track),
+                _ => anyhow::bail!("passthrough requires exactly one input video"),
+            };
+            let frame_source = frame_source::from_path_with_options(
+                full_path,
+                frame_source::FromPathOptions {
+                    image_data_mode: ImageDataMode::Encoded,
+                    mp4_track: mp4_track.clone(),
+                    ..Default::default()
+                },
+            )?;
+            let frame_source: &'static mut Box<dyn FrameDataSource> =
+                Box::leak(Box::new(frame_source));
+            let frame0_time = frame_source
+                .frame0_time()
+                .ok_or_else(|| anyhow::eyre!("no start time in {}", full_path.display()))?
+                .with_timezone(&Utc);
+            let keyframe_indices = frame_source.keyframe_indices().unwrap_or_default();
+            Some(Passthrough {
+                reader: frame_source.iter(),
+                frame0_time,
+                width: cameras[0].per_cam_render.width.try_into()?,
+                height: cameras[0].per_cam_render.height.try_into()?,
+                keyframe_indices,
+                started: false,
+            })
+        } else {
+            None
+        };
+
         Ok(Self {
             path: output_filename.to_path_buf(),
             mp4_writer,
@@ -125,19 +280,123 @@ impl<'lib> VideoStorage<'lib> {
             feature_style,
             reprojected_style,
             cam_text_style,
+            background_style,
             video_options: v.video_options.clone(),
             cum_width,
             cum_height,
             usvg_opt,
+            passthrough,
+            summary,
+            sync_threshold,
+            event_display,
+            shown_events: Vec::new(),
+            follow_cam: v.video_options.follow_cam.clone().map(FollowCam::new),
         })
     }
 
-    pub(crate) async fn render_frame(
+    /// Compute the timestamp at which a frame from time `ts` is saved.
+    fn save_timestamp(&mut self, ts: DateTime<Utc>) -> DateTime<Utc> {
+        if let Some(time_dilation_factor) = self.video_options.time_dilation_factor {
+            if self.first_timestamp.is_none() {
+                self.first_timestamp = Some(ts);
+            }
+
+            let actual_time_delta =
+                ts.signed_duration_since(*self.first_timestamp.as_ref().unwrap());
+            let actual_time_delta_micros = actual_time_delta.num_microseconds().unwrap();
+            let saved_time_delta =
+                (actual_time_delta_micros as f64 * time_dilation_factor as f64).round() as i64;
+            let saved_time_delta = chrono::Duration::microseconds(saved_time_delta);
+            ts + saved_time_delta
+        } else {
+            ts
+        }
+    }
+
+    /// Copy all encoded frames of the input video up to and including the
+    /// frame in `synced_data`.
+    ///
+    /// Copying starts at the last keyframe at or before the first frame to be
+    /// saved, so that this frame can be decoded. If the keyframes are not
+    /// known in advance, frames before the first keyframe are skipped.
+    fn write_passthrough_frames(&mut self, synced_data: &crate::SyncedPictures) -> Result<()> {
+        let target_idx = match &synced_data.camera_pictures[0].image {
+            Some(image) => image.extra().host_framenumber(),
+            // No new frame at this timepoint.
+            None => return Ok(()),
+        };
+
+        loop {
+            let pt = self.passthrough.as_mut().unwrap();
+            let frame = match pt.reader.next() {
+                Some(frame) => frame?,
+                None => anyhow::bail!("input video ended before frame {target_idx}"),
+            };
+            let idx = frame.idx();
+            let encoded = match frame.encoded_h264() {
+                Some(encoded) => encoded,
+                None => anyhow::bail!("passthrough requires H264 encoded input video"),
+            };
+
+            if !pt.started {
+                let start_idx = pt
+                    .keyframe_indices
+                    .iter()
+                    .rev()
+                    .find(|&&keyframe_idx| keyframe_idx <= target_idx)
+                    .copied()
+                    .unwrap_or(target_idx);
+                if idx < start_idx {
+                    continue;
+                }
+                if !frame.is_keyframe() {
+                    // Wait for a later timepoint.
+                    return Ok(());
+                }
+                pt.started = true;
+            }
+
+            let (width, height) = (pt.width, pt.height);
+            let ts =
+                pt.frame0_time + chrono::Duration::from_std(frame.timestamp().unwrap_duration())?;
+            let save_ts = self.save_timestamp(ts);
+            let first_ts = *self.first_timestamp.get_or_insert(save_ts);
+            self.mp4_writer.write_h264_buf(
+                &encoded.data,
+                width,
+                height,
+                save_ts,
+                first_ts,
+                !encoded.has_precision_timestamp,
+            )?;
+
+            if idx >= target_idx {
+                return Ok(());
+            }
+        }
+    }
+
+    pub(crate) fn render_frame(
         &mut self,
         out_fno: usize,
         synced_data: &crate::SyncedPictures,
-        all_cam_render_data: &[PerCamRenderFrame<'_>],
+        all_cam_render_data: &[PerCamRenderFrame],
+        events: &[Event],
     ) -> Result<()> {
+        let show_events = self.video_options.show_events;
+        if show_events {
+            // Keep the events whose message is still shown, also while the
+            // frames are not saved.
+            let event_display = self.event_display;
+            self.shown_events.extend(events.iter().cloned());
+            self.shown_events
+                .retain(|e| e.timestamp + event_display >= synced_data.timestamp);
+        }
+
+        if self.passthrough.is_some() {
+            return self.write_passthrough_frames(synced_data);
+        }
+
         let synced_pics: &[OutTimepointPerCamera] = &synced_data.camera_pictures;
         let n_pics = synced_pics.len();
 
@@ -147,44 +406,70 @@ impl<'lib> VideoStorage<'lib> {
         let feature_style = &self.feature_style;
         let reprojected_style = &self.reprojected_style;
         let cam_text_style = &self.cam_text_style;
-
-        let ts = &synced_data.timestamp;
+        let background_style = &self.background_style;
+        let show_camera_settings = self.video_options.show_camera_settings;
+        let show_sync_offset = self.video_options.show_sync_offset;
+        let display_timezone = self.video_options.display_timezone;
+        let sync_threshold = self.sync_threshold;
 
         // If there is no new data, we do not write a frame.
 
-        let save_ts = if let Some(time_dilation_factor) = self.video_options.time_dilation_factor {
-            if self.first_timestamp.is_none() {
-                self.first_timestamp = Some(*ts);
+        // For a summary, only the selected frames are saved and the position
+        // in the recording is shown on the timeline.
+        let (save_ts, timeline_position) = match self.summary.as_mut() {
+            Some(summary) => {
+                let frame_num = synced_data.braidz_info.as_ref().map(|b| b.frame_num);
+                let Some(frame_num) = frame_num.filter(|f| summary.selected.contains(f)) else {
+                    return Ok(());
+                };
+                (
+                    summary.save_timestamp(synced_data.timestamp),
+                    Some(summary.position(frame_num)),
+                )
             }
+            None => (self.save_timestamp(synced_data.timestamp), None),
+        };
 
-            let actual_time_delta =
-                ts.signed_duration_since(*self.first_timestamp.as_ref().unwrap());
-            let actual_time_delta_micros = actual_time_delta.num_microseconds().unwrap();
-            let saved_time_delta =
-                (actual_time_delta_micros as f64 * time_dilation_factor as f64).round() as i64;
-            let saved_time_delta = chrono::Duration::microseconds(saved_time_delta);
-            *ts + saved_time_delta
-        } else {
-            *ts
+        // The objects shown in the follow-cam panels below each camera.
+        let follow_cam_shown = self
+            .follow_cam
+            .as_mut()
+            .map(|follow_cam| follow_cam.update(all_cam_render_data, composite_margin_pixels));
+        let (panel_size, zoom) = match &self.follow_cam {
+            Some(follow_cam) => (follow_cam.opts.panel_size, follow_cam.opts.zoom),
+            None => (0, 1.0),
         };
 
+        let shown_events = &self.shown_events;
+
         // Draw SVG
         let mut wtr = tagger::new(tagger::upgrade_write(Vec::<u8>::new()));
         let svg_width = self.cum_width + n_pics * 2 * composite_margin_pixels;
-        let svg_height = self.cum_height + 2 * composite_margin_pixels;
+        let camera_height = self.cum_height + 2 * composite_margin_pixels;
+        // The height of the cameras and the follow-cam panels below them.
+        let mosaic_height = if follow_cam_shown.is_some() {
+            camera_height + panel_size + composite_margin_pixels
+        } else {
+            camera_height
+        };
+        let svg_height = if timeline_position.is_some() {
+            mosaic_height + TIMELINE_HEIGHT
+        } else {
+            mosaic_height
+        };
         wtr.elem("svg", |d| {
             d.attr("xmlns", "http://www.w3.org/2000/svg")?;
             d.attr("xmlns:xlink", "http://www.w3.org/1999/xlink")?;
             d.attr("viewBox", format_args!("0 0 {} {}", svg_width, svg_height))
         })?
         .build(|w| {
-            // Write a filled white rectangle for background.
+            // Write a filled rectangle for background.
             w.single("rect", |d| {
                 d.attr("x", 0)?;
                 d.attr("y", 0)?;
                 d.attr("width", svg_width)?;
                 d.attr("height", svg_height)?;
-                d.attr("style", "fill:white")
+                d.attr("style", background_style)
             })?;
 
             // Create an SVG group.
@@ -193,6 +478,10 @@ impl<'lib> VideoStorage<'lib> {
                 for (cam_idx, cam_render_data) in all_cam_render_data.iter().enumerate() {
                     curx += composite_margin_pixels;
 
+                    let data_url = cam_render_data.png_buf.as_ref().map(|png_buf| {
+                        format!("data:image/png;base64,{}", base64::encode(png_buf))
+                    });
+
                     // Create a clipPath for the camera image size.
                     w.elem("clipPath", |d| {
                         d.attr("id", format!("clip-path-{}", cam_idx))
@@ -219,9 +508,7 @@ impl<'lib> VideoStorage<'lib> {
                     })?
                     .build(|w| {
                         // Draw image from camera
-                        if let Some(png_buf) = &cam_render_data.png_buf {
-                            let png_base64_buf = base64::encode(&png_buf);
-                            let data_url = format!("data:image/png;base64,{}", png_base64_buf);
+                        if let Some(data_url) = &data_url {
                             w.single("image", |d| {
                                 d.attr("x", 0)?;
                                 d.attr("y", 0)?;
@@ -265,10 +552,19 @@ impl<'lib> VideoStorage<'lib> {
                     .build(|w| {
                         // Draw text annotation with camera names
                         {
-                            let cam_text = format!(
+                            let mut cam_text = format!(
                                 "{} {}",
-                                cam_render_data.p.best_name, cam_render_data.pts_chrono
+                                cam_render_data.p.best_name,
+                                display_timezone.format(cam_render_data.pts_chrono)
                             );
+                            if show_camera_settings {
+                                if let Some(settings) = &cam_render_data.camera_settings {
+                                    cam_text.push_str(&format!(
+                                        " ({})",
+                                        crate::format_camera_settings(settings)
+                                    ));
+                                }
+                            }
                             w.elem("text", |d| {
                                 d.attr("x", format!("{}", 10))?;
                                 d.attr("y", format!("{}", 10))?;
@@ -279,13 +575,158 @@ impl<'lib> VideoStorage<'lib> {
                             .build(|w| w.put_raw(cam_text))?;
                         }
 
+                        // Draw the offset of the camera frame from the
+                        // synchronized time.
+                        if show_sync_offset {
+                            let offset = synced_pics[cam_idx].image.as_ref().map(|image| {
+                                image.extra().host_timestamp() - synced_data.timestamp
+                            });
+                            let (offset_text, exceeded) =
+                                format_sync_offset(offset, sync_threshold);
+                            let style = if exceeded {
+                                format!(
+                                    "{}; fill: red;",
+                                    cam_text_style.trim_end().trim_end_matches(';')
+                                )
+                            } else {
+                                cam_text_style.to_string()
+                            };
+                            w.elem("text", |d| {
+                                d.attr("x", format!("{}", 10))?;
+                                d.attr("y", format!("{}", 10))?;
+                                d.attr("dy", "2.2em")?;
+                                d.attr("style", &style)?;
+                                Ok(())
+                            })?
+                            .build(|w| w.put_raw(offset_text))?;
+                        }
+
                         Ok(())
                     })?;
 
+                    // Draw the zoomed views following the objects below the
+                    // camera, each centered on the smoothed position of an
+                    // object.
+                    let shown = follow_cam_shown
+                        .as_ref()
+                        .map_or(&[][..], |s| s[cam_idx].as_slice());
+                    let half_crop = panel_size as f64 / (2.0 * zoom);
+                    for (i, (obj_id, (x, y))) in shown.iter().enumerate() {
+                        let panel_x = curx + i * (panel_size + composite_margin_pixels);
+                        let clip_id = format!("follow-clip-path-{}-{}", cam_idx, i);
+                        w.elem("clipPath", |d| d.attr("id", &clip_id))?.build(|w| {
+                            w.single("rect", |d| {
+                                d.attr("x", 0)?;
+                                d.attr("y", 0)?;
+                                d.attr("width", panel_size)?;
+                                d.attr("height", panel_size)?;
+                                Ok(())
+                            })?;
+                            Ok(())
+                        })?;
+                        w.elem("g", |d| {
+                            d.attr(
+                                "transform",
+                                format!("translate({},{})", panel_x, camera_height),
+                            )?;
+                            d.attr("clip-path", format!("url(#{})", clip_id))
+                        })?
+                        .build(|w| {
+                            w.elem("g", |d| {
+                                d.attr(
+                                    "transform",
+                                    format!(
+                                        "scale({}) translate({:.1},{:.1})",
+                                        zoom,
+                                        half_crop - x,
+                                        half_crop - y
+                                    ),
+                                )
+                            })?
+                            .build(|w| {
+                                if let Some(data_url) = &data_url {
+                                    w.single("image", |d| {
+                                        d.attr("x", 0)?;
+                                        d.attr("y", 0)?;
+                                        d.attr("width", cam_render_data.p.width)?;
+                                        d.attr("height", cam_render_data.p.height)?;
+                                        d.attr("xlink:href", data_url)
+                                    })?;
+                                }
+                                Ok(())
+                            })?;
+                            Ok(())
+                        })?;
+                        w.elem("text", |d| {
+                            d.attr("x", panel_x + 10)?;
+                            d.attr("y", camera_height + 10)?;
+                            d.attr("dy", "1em")?;
+                            d.attr("style", cam_text_style)?;
+                            Ok(())
+                        })?
+                        .build(|w| w.put_raw(format!("obj {}", obj_id)))?;
+                    }
+
                     curx += cam_render_data.p.width + composite_margin_pixels;
                 }
                 Ok(())
             })?;
+
+            // Mark the experiment events: a frame around the images at the
+            // frame of an event and the messages of the recent events.
+            if show_events {
+                if !events.is_empty() {
+                    w.single("rect", |d| {
+                        d.attr("x", 0)?;
+                        d.attr("y", 0)?;
+                        d.attr("width", svg_width)?;
+                        d.attr("height", camera_height)?;
+                        d.attr("style", EVENT_FRAME_STYLE)
+                    })?;
+                }
+                let event_text_style = format!(
+                    "{}; fill: {EVENT_COLOR};",
+                    cam_text_style.trim_end().trim_end_matches(';')
+                );
+                let n_shown = shown_events.len();
+                for (i, event) in shown_events.iter().enumerate() {
+                    w.elem("text", |d| {
+                        d.attr("x", composite_margin_pixels + 10)?;
+                        d.attr("y", camera_height - composite_margin_pixels - 10)?;
+                        d.attr("dy", format!("-{:.1}em", 1.2 * (n_shown - 1 - i) as f64))?;
+                        d.attr("style", &event_text_style)?;
+                        Ok(())
+                    })?
+                    .build(|w| w.put_raw(format_event(event, display_timezone)))?;
+                }
+            }
+
+            // Draw the timeline showing the position in the recording.
+            if let Some(position) = timeline_position {
+                let track_width = svg_width - 2 * composite_margin_pixels;
+                w.single("rect", |d| {
+                    d.attr("x", composite_margin_pixels)?;
+                    d.attr("y", mosaic_height + 10)?;
+                    d.attr("width", track_width)?;
+                    d.attr("height", 16)?;
+                    d.attr("style", TIMELINE_TRACK_STYLE)
+                })?;
+                w.single("rect", |d| {
+                    d.attr("x", composite_margin_pixels)?;
+                    d.attr("y", mosaic_height + 10)?;
+                    d.attr("width", format!("{:.1}", position * track_width as f64))?;
+                    d.attr("height", 16)?;
+                    d.attr("style", TIMELINE_PROGRESS_STYLE)
+                })?;
+                w.elem("text", |d| {
+                    d.attr("x", composite_margin_pixels)?;
+                    d.attr("y", mosaic_height + 30)?;
+                    d.attr("dy", "1em")?;
+                    d.attr("style", cam_text_style)?;
+                    Ok(())
+                })?
+                .build(|w| w.put_raw(synced_data.timestamp.to_string()))?;
+            }
             Ok(())
         })?;
         // Get the SVG file contents.
@@ -322,4 +763,112 @@ impl<'lib> VideoStorage<'lib> {
 
         Ok(())
     }
+
+    /// Flush the remaining frames and complete the MP4 file.
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        self.mp4_writer.finish()?;
+        Ok(())
+    }
+}
+
+/// Format the text of an event, escaped for SVG.
+fn format_event(event: &Event, display_timezone: DisplayTimezone) -> String {
+    let text = format!(
+        "{} {}: {}",
+        display_timezone.format(event.timestamp),
+        event.source,
+        event.message
+    );
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Format the offset of a camera frame from the synchronized time.
+///
+/// Returns the text and whether the offset exceeds `threshold`.
+fn format_sync_offset(
+    offset: Option<chrono::Duration>,
+    threshold: Option<chrono::Duration>,
+) -> (String, bool) {
+    match offset {
+        Some(offset) => {
+            let msec = offset
+                .num_microseconds()
+                .map_or(f64::INFINITY, |x| x as f64 / 1000.0);
+            let exceeded = threshold.is_some_and(|threshold| offset.abs() > threshold);
+            (format!("offset {msec:+.2} ms"), exceeded)
+        }
+        None => ("no frame".to_string(), false),
+    }
+}
+
+#[test]
+fn test_format_sync_offset() {
+    let threshold = Some(chrono::Duration::microseconds(5000));
+    assert_eq!(
+        format_sync_offset(Some(chrono::Duration::microseconds(-1250)), threshold),
+        ("offset -1.25 ms".to_string(), false)
+    );
+    assert_eq!(
+        format_sync_offset(Some(chrono::Duration::microseconds(7000)), threshold),
+        ("offset +7.00 ms".to_string(), true)
+    );
+    assert_eq!(
+        format_sync_offset(Some(chrono::Duration::microseconds(7000)), None),
+        ("offset +7.00 ms".to_string(), false)
+    );
+    assert_eq!(
+        format_sync_offset(None, threshold),
+        ("no frame".to_string(), false)
+    );
+}
+
+#[test]
+fn test_follow_cam_update() {
+    use ordered_float::NotNan;
+
+    let per_cam_render = std::sync::Arc::new(crate::PerCamRender {
+        best_name: "cam1".to_string(),
+        raw_name: flydra_types::RawCamName::new("cam1".to_string()),
+        frame0_png_buf: flydra_types::PngImageData { data: vec![] },
+        width: 640,
+        height: 480,
+    });
+    let frame = |objects: &[(u32, f64, f64)]| {
+        let mut frame = per_cam_render.new_render_data(Utc::now());
+        for (obj_id, x, y) in objects {
+            frame
+                .reprojected_points
+                .push((NotNan::new(*x).unwrap(), NotNan::new(*y).unwrap()));
+            frame.reprojected_obj_ids.push(*obj_id);
+        }
+        vec![frame]
+    };
+
+    // Three panels of 200 pixels fit below the camera.
+    let mut follow_cam = FollowCam::new(FollowCamOptions {
+        smoothing: 0.75,
+        max_objects: 10,
+        ..Default::default()
+    });
+    let shown = follow_cam.update(
+        &frame(&[
+            (4, 1.0, 1.0),
+            (2, 100.0, 200.0),
+            (3, 0.0, 0.0),
+            (1, 0.0, 0.0),
+        ]),
+        5,
+    );
+    assert_eq!(
+        shown,
+        vec![vec![(1, (0.0, 0.0)), (2, (100.0, 200.0)), (3, (0.0, 0.0))]]
+    );
+
+    // Object 1 is smoothed, object 5 appears and object 3 is forgotten.
+    let shown = follow_cam.update(&frame(&[(1, 100.0, 40.0), (5, 10.0, 10.0)]), 5);
+    assert_eq!(shown, vec![vec![(1, (25.0, 10.0)), (5, (10.0, 10.0))]]);
+    let shown = follow_cam.update(&frame(&[(3, 8.0, 8.0)]), 5);
+    assert_eq!(shown, vec![vec![(3, (8.0, 8.0))]]);
 }
@@ -0,0 +1,104 @@
+//! Threads processing the video, report and debug outputs.
+//!
+//! Each of these outputs runs in its own thread and is fed the output frames
+//! through a bounded queue, so a slow MP4 encoder does not stall the other
+//! outputs, while a full queue applies backpressure to the reading of the
+//! input. The storage of an output is created in its thread because the
+//! encoders need not be `Send`. The queues are async, so waiting for an output
+//! does not block the tokio runtime, which also runs the braidz tracking.
+//!
+//! The braidz output is not run here: its data is already processed by the
+//! tracking in a separate task.
+
+use color_eyre::{eyre as anyhow, Result};
+
+use crate::{events::Event, output_types::OutputStorage, PerCamRenderFrame, SyncedPictures};
+
+/// The number of frames queued for each output before the processing waits.
+const QUEUE_SIZE: usize = 10;
+
+/// The data of an output frame, as sent to each output thread.
+#[derive(Clone)]
+pub(crate) struct OutputFrame {
+    pub(crate) out_fno: usize,
+    pub(crate) synced_data: SyncedPictures,
+    pub(crate) all_cam_render_data: Vec<PerCamRenderFrame>,
+    /// The lines written to the debug outputs before the frame data.
+    pub(crate) debug_lines: Vec<String>,
+    /// The experiment events at this frame.
+    pub(crate) events: Vec<Event>,
+}
+
+pub(crate) enum OutputMsg {
+    Frame(OutputFrame),
+    /// All frames were sent. Contains the reasons why the quality control
+    /// failed, if any.
+    Finish(Vec<String>),
+}
+
+/// The thread of an output and the queue feeding it.
+pub(crate) struct OutputWorker {
+    pub(crate) path: std::path::PathBuf,
+    tx: Option<tokio::sync::mpsc::Sender<OutputMsg>>,
+    join_handle: Option<std::thread::JoinHandle<Result<()>>>,
+}
+
+impl OutputWorker {
+    /// Start the thread of the output saved to `path`, whose storage is
+    /// created by `create_storage`.
+    pub(crate) fn spawn<F>(path: std::path::PathBuf, create_storage: F) -> Self
+    where
+        F: FnOnce() -> Result<OutputStorage<'static>> + Send + 'static,
+    {
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<OutputMsg>(QUEUE_SIZE);
+        let join_handle = std::thread::Builder::new()
+            .name(format!("output {}", path.display()))
+            .spawn(move || {
+                let mut storage = create_storage()?;
+                // If the sender is dropped without finishing, the processing
+                // failed elsewhere and the output is abandoned.
+                while let Some(msg) = rx.blocking_recv() {
+                    match msg {
+                        OutputMsg::Frame(frame) => storage.render_frame(&frame)?,
+                        OutputMsg::Finish(qc_failures) => {
+                            storage.finish(&qc_failures)?;
+                            break;
+                        }
+                    }
+                }
+                Ok(())
+            })
+            .unwrap();
+        Self {
+            path,
+            tx: Some(tx),
+            join_handle: Some(join_handle),
+        }
+    }
+
+    /// Queue a message, waiting while the queue is full.
+    ///
+    /// If the thread has stopped, returns its error.
+    pub(crate) async fn send(&mut self, msg: OutputMsg) -> Result<()> {
+        let tx = self
+            .tx
+            .as_ref()
+            .ok_or_else(|| anyhow::eyre!("output {} already stopped", self.path.display()))?;
+        if tx.send(msg).await.is_err() {
+            self.join().await?;
+            anyhow::bail!("output {} stopped unexpectedly", self.path.display());
+        }
+        Ok(())
+    }
+
+    /// Wait until the thread has processed all queued messages and stopped.
+    pub(crate) async fn join(&mut self) -> Result<()> {
+        self.tx.take();
+        match self.join_handle.take() {
+            Some(join_handle) => tokio::task::spawn_blocking(move || join_handle.join())
+                .await?
+                .map_err(|_| anyhow::eyre!("output {} panicked", self.path.display()))?,
+            None => Ok(()),
+        }
+    }
+}
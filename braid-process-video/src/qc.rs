@@ -0,0 +1,194 @@
+//! Automatic quality control of the processing.
+//!
+//! Statistics of each camera are collected for every output frame and checked
+//! against the thresholds in [QcConfig] after processing, so that batch
+//! pipelines get an objective failure signal.
+
+use timestamped_frame::ExtraTimeData;
+
+use crate::{config::QcConfig, CameraIdentifier, CameraSource, SyncedPictures};
+
+/// The error returned by [crate::run_config] if a quality control threshold
+/// was exceeded.
+///
+/// The outputs are complete when this error is returned.
+#[derive(Debug, Clone)]
+pub struct QcFailed {
+    /// The reasons of the failure, one per exceeded threshold and camera.
+    pub reasons: Vec<String>,
+}
+
+impl std::fmt::Display for QcFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "quality control failed: {}", self.reasons.join("; "))
+    }
+}
+
+impl std::error::Error for QcFailed {}
+
+#[derive(Debug, Default)]
+struct CameraStats {
+    name: String,
+    /// Whether the camera has a video. Frames can be missing only if so.
+    has_video: bool,
+    n_output_frames: usize,
+    n_missing_frames: usize,
+    max_abs_offset_msec: Option<f64>,
+    n_braidz_rows: usize,
+    /// The number of braidz rows in output frames with a frame of the camera.
+    n_braidz_rows_matched: usize,
+}
+
+impl CameraStats {
+    /// Add an output frame, with the offset of the camera frame from the
+    /// synchronized time if there is one.
+    fn add(&mut self, offset_msec: Option<f64>, n_braidz_rows: usize) {
+        self.n_output_frames += 1;
+        self.n_braidz_rows += n_braidz_rows;
+        match offset_msec {
+            Some(offset) => {
+                let max = self.max_abs_offset_msec.get_or_insert(0.0);
+                *max = max.max(offset.abs());
+                self.n_braidz_rows_matched += n_braidz_rows;
+            }
+            None => {
+                self.n_missing_frames += 1;
+            }
+        }
+    }
+
+    fn check(&self, cfg: &QcConfig, reasons: &mut Vec<String>) {
+        let name = &self.name;
+        if let Some(max_fraction) = cfg.max_missing_frame_fraction {
+            if self.has_video && self.n_output_frames > 0 {
+                let fraction = self.n_missing_frames as f64 / self.n_output_frames as f64;
+                if fraction > max_fraction {
+                    reasons.push(format!(
+                        "camera {name}: {:.1}% of output frames missing (maximum {:.1}%)",
+                        fraction * 100.0,
+                        max_fraction * 100.0
+                    ));
+                }
+            }
+        }
+        if let (Some(max_desync), Some(offset)) = (cfg.max_desync_msec, self.max_abs_offset_msec) {
+            if offset > max_desync {
+                reasons.push(format!(
+                    "camera {name}: frame offset of {offset:.1} msec from the synchronized \
+                    time (maximum {max_desync:.1} msec)"
+                ));
+            }
+        }
+        if let Some(min_fraction) = cfg.min_braidz_rows_matched_fraction {
+            if self.has_video && self.n_braidz_rows > 0 {
+                let fraction = self.n_braidz_rows_matched as f64 / self.n_braidz_rows as f64;
+                if fraction < min_fraction {
+                    reasons.push(format!(
+                        "camera {name}: {:.1}% of braidz rows matched to a frame (minimum {:.1}%)",
+                        fraction * 100.0,
+                        min_fraction * 100.0
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Quality control statistics of all cameras.
+pub(crate) struct QcStats {
+    cameras: Vec<CameraStats>,
+}
+
+impl QcStats {
+    pub(crate) fn new(sources: &[CameraSource]) -> Self {
+        let cameras = sources
+            .iter()
+            .map(|s| CameraStats {
+                name: s.per_cam_render.best_name.clone(),
+                has_video: !matches!(s.cam_id, CameraIdentifier::BraidzOnly(_)),
+                ..Default::default()
+            })
+            .collect();
+        Self { cameras }
+    }
+
+    pub(crate) fn add_frame(&mut self, synced_data: &SyncedPictures) {
+        for (cam, per_cam) in self
+            .cameras
+            .iter_mut()
+            .zip(synced_data.camera_pictures.iter())
+        {
+            let offset_msec = per_cam.image.as_ref().map(|image| {
+                let offset = image.extra().host_timestamp() - synced_data.timestamp;
+                // An offset too large for microseconds certainly exceeds any
+                // threshold.
+                offset
+                    .num_microseconds()
+                    .map_or(f64::INFINITY, |x| x as f64 / 1000.0)
+            });
+            cam.add(offset_msec, per_cam.this_cam_this_frame.len());
+        }
+    }
+
+    /// Check the statistics against the thresholds.
+    ///
+    /// Returns the reasons of the failure, which are empty if all thresholds
+    /// are met.
+    pub(crate) fn check(&self, cfg: &QcConfig) -> Vec<String> {
+        let mut reasons = Vec::new();
+        for cam in self.cameras.iter() {
+            cam.check(cfg, &mut reasons);
+        }
+        reasons
+    }
+}
+
+#[test]
+fn test_qc_check() {
+    let mut cam = CameraStats {
+        name: "cam1".into(),
+        has_video: true,
+        ..Default::default()
+    };
+    for i in 0..10 {
+        let offset_msec = if i < 2 { None } else { Some(i as f64 - 5.0) };
+        cam.add(offset_msec, 1);
+    }
+    let stats = QcStats { cameras: vec![cam] };
+
+    assert!(stats.check(&QcConfig::default()).is_empty());
+
+    let passing = QcConfig {
+        max_missing_frame_fraction: Some(0.2),
+        max_desync_msec: Some(4.0),
+        min_braidz_rows_matched_fraction: Some(0.8),
+    };
+    assert!(stats.check(&passing).is_empty());
+
+    let failing = QcConfig {
+        max_missing_frame_fraction: Some(0.1),
+        max_desync_msec: Some(3.5),
+        min_braidz_rows_matched_fraction: Some(0.9),
+    };
+    let reasons = stats.check(&failing);
+    assert_eq!(reasons.len(), 3);
+    assert!(reasons.iter().all(|r| r.starts_with("camera cam1: ")));
+}
+
+#[test]
+fn test_qc_braidz_only() {
+    // Without video, frames are never missing.
+    let mut cam = CameraStats {
+        name: "cam1".into(),
+        has_video: false,
+        ..Default::default()
+    };
+    cam.add(None, 1);
+    let stats = QcStats { cameras: vec![cam] };
+    let cfg = QcConfig {
+        max_missing_frame_fraction: Some(0.0),
+        max_desync_msec: Some(0.0),
+        min_braidz_rows_matched_fraction: Some(1.0),
+    };
+    assert!(stats.check(&cfg).is_empty());
+}
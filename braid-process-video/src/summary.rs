@@ -0,0 +1,223 @@
+//! Time-lapse summaries of long recordings.
+//!
+//! The recording is divided into bins of [SummaryOptions::bin_secs]. Each bin
+//! gets a number of frames proportional to its activity, limited by the minimum
+//! and maximum rates. The factor of proportionality is chosen such that the
+//! summary has the requested duration.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+use color_eyre::{eyre as anyhow, Result};
+
+use flydra_types::{CamNum, Data2dDistortedRow, KalmanEstimatesRow};
+
+use crate::config::SummaryOptions;
+
+/// The height of the timeline bar below the cameras.
+pub(crate) const TIMELINE_HEIGHT: usize = 80;
+pub(crate) const TIMELINE_TRACK_STYLE: &str = "fill: lightgray;";
+pub(crate) const TIMELINE_PROGRESS_STYLE: &str = "fill: deepskyblue;";
+
+/// The number of 2D detections and 3D estimates in each frame.
+///
+/// Frames with data but without detections are included with zero activity.
+pub(crate) fn activity(
+    data2d: &BTreeMap<CamNum, Vec<Data2dDistortedRow>>,
+    kalman_estimates: Option<&[KalmanEstimatesRow]>,
+) -> BTreeMap<i64, u64> {
+    let mut activity = BTreeMap::new();
+    for row in data2d.values().flatten() {
+        *activity.entry(row.frame).or_insert(0) += u64::from(!row.x.is_nan());
+    }
+    for row in kalman_estimates.into_iter().flatten() {
+        *activity.entry(row.frame.0 as i64).or_insert(0) += 1;
+    }
+    activity
+}
+
+/// The frames selected for a summary and the timing of the output.
+pub(crate) struct Summary {
+    /// The frame numbers of the selected frames.
+    pub(crate) selected: BTreeSet<i64>,
+    first_frame: i64,
+    last_frame: i64,
+    fps: f64,
+    n_saved: u64,
+    first_timestamp: Option<DateTime<Utc>>,
+}
+
+impl Summary {
+    /// Select the frames given the `activity` of each frame and the frame rate
+    /// of the recording.
+    pub(crate) fn new(
+        opts: &SummaryOptions,
+        activity: &BTreeMap<i64, u64>,
+        input_fps: f64,
+    ) -> Result<Self> {
+        if !(input_fps.is_finite() && input_fps > 0.0) {
+            anyhow::bail!("Summary requires the frame rate of the braidz file.");
+        }
+        let (Some((first_frame, _)), Some((last_frame, _))) =
+            (activity.first_key_value(), activity.last_key_value())
+        else {
+            anyhow::bail!("Summary requires 2D data in the braidz file.");
+        };
+        let selected = select_frames(activity, input_fps, opts);
+        tracing::info!(
+            "Summary of {} frames selected from frames {first_frame} to {last_frame}.",
+            selected.len()
+        );
+        Ok(Self {
+            selected,
+            first_frame: *first_frame,
+            last_frame: *last_frame,
+            fps: opts.fps,
+            n_saved: 0,
+            first_timestamp: None,
+        })
+    }
+
+    /// The position of `frame` in the recording, from 0 to 1.
+    pub(crate) fn position(&self, frame: i64) -> f64 {
+        if self.last_frame == self.first_frame {
+            return 1.0;
+        }
+        (frame - self.first_frame) as f64 / (self.last_frame - self.first_frame) as f64
+    }
+
+    /// Compute the timestamp at which the next frame, from time `ts`, is saved.
+    pub(crate) fn save_timestamp(&mut self, ts: DateTime<Utc>) -> DateTime<Utc> {
+        let first_timestamp = *self.first_timestamp.get_or_insert(ts);
+        let offset_micros = (self.n_saved as f64 * 1e6 / self.fps).round() as i64;
+        self.n_saved += 1;
+        first_timestamp + chrono::Duration::microseconds(offset_micros)
+    }
+}
+
+struct Bin {
+    /// The frames with data.
+    frames: Vec<i64>,
+    activity: f64,
+    min_frames: f64,
+    max_frames: f64,
+}
+
+impl Bin {
+    /// The number of frames selected with the factor of proportionality `k`.
+    fn n_frames(&self, k: f64) -> f64 {
+        (k * self.activity).clamp(self.min_frames, self.max_frames)
+    }
+}
+
+/// Select the frames of a summary.
+///
+/// Only frames in `activity` are selected, as others may have no data.
+fn select_frames(
+    activity: &BTreeMap<i64, u64>,
+    input_fps: f64,
+    opts: &SummaryOptions,
+) -> BTreeSet<i64> {
+    let (Some((first, _)), Some((last, _))) =
+        (activity.first_key_value(), activity.last_key_value())
+    else {
+        return BTreeSet::new();
+    };
+    let (first, last) = (*first, *last);
+    let bin_frames = ((opts.bin_secs * input_fps).round() as i64).max(1);
+    let n_bins = ((last - first) / bin_frames + 1) as usize;
+
+    let mut bins: Vec<Bin> = (0..n_bins)
+        .map(|i| {
+            let start = first + i as i64 * bin_frames;
+            let minutes = (last + 1 - start).min(bin_frames) as f64 / input_fps / 60.0;
+            Bin {
+                frames: vec![],
+                activity: 0.0,
+                min_frames: opts.min_frames_per_minute * minutes,
+                max_frames: opts.max_frames_per_minute * minutes,
+            }
+        })
+        .collect();
+    for (frame, count) in activity.iter() {
+        let bin = &mut bins[((frame - first) / bin_frames) as usize];
+        bin.frames.push(*frame);
+        bin.activity += *count as f64;
+    }
+    for bin in bins.iter_mut() {
+        bin.max_frames = bin.max_frames.min(bin.frames.len() as f64);
+        bin.min_frames = bin.min_frames.min(bin.max_frames);
+    }
+
+    // Find the factor of proportionality giving the requested number of
+    // frames. The number of frames increases with the factor.
+    let target = opts.duration_secs * opts.fps;
+    let count = |k: f64| -> f64 { bins.iter().map(|bin| bin.n_frames(k)).sum() };
+    let k = if count(0.0) >= target {
+        0.0
+    } else {
+        let mut hi = 1.0;
+        while count(hi) < target && hi < 1e12 {
+            hi *= 2.0;
+        }
+        let mut lo = 0.0;
+        for _ in 0..60 {
+            let mid = (lo + hi) / 2.0;
+            if count(mid) < target {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        hi
+    };
+
+    // Select evenly spaced frames within each bin. The fractional parts are
+    // carried over to the next bin. The small tolerance avoids losing a frame
+    // to rounding errors.
+    let mut selected = BTreeSet::new();
+    let mut carry = 0.0;
+    for bin in bins.iter() {
+        carry += bin.n_frames(k);
+        let n = ((carry + 1e-9).floor() as usize).min(bin.frames.len());
+        carry -= n as f64;
+        let m = bin.frames.len();
+        for j in 0..n {
+            selected.insert(bin.frames[(2 * j + 1) * m / (2 * n)]);
+        }
+    }
+    selected
+}
+
+#[test]
+fn test_select_frames() {
+    // One hour at 100 fps with activity only during the 10th minute.
+    let input_fps = 100.0;
+    let activity: BTreeMap<i64, u64> = (0..360_000)
+        .map(|frame| (frame, u64::from((54_000..60_000).contains(&frame))))
+        .collect();
+    let opts = SummaryOptions {
+        duration_secs: 10.0,
+        fps: 10.0,
+        min_frames_per_minute: 1.0,
+        max_frames_per_minute: 600.0,
+        bin_secs: 60.0,
+    };
+    let selected = select_frames(&activity, input_fps, &opts);
+    assert_eq!(selected.len(), 100);
+    // One frame from each of the 59 minutes without activity, the others from
+    // the active minute.
+    let active = selected
+        .iter()
+        .filter(|frame| (54_000..60_000).contains(*frame))
+        .count();
+    assert_eq!(active, 41);
+
+    // The maximum rate limits the number of frames.
+    let opts = SummaryOptions {
+        max_frames_per_minute: 10.0,
+        ..opts
+    };
+    let selected = select_frames(&activity, input_fps, &opts);
+    assert_eq!(selected.len(), 69);
+}
@@ -1,13 +1,24 @@
+use std::sync::{Arc, Mutex};
+
 use chrono::{DateTime, Utc};
 use color_eyre::{
     eyre::{self as anyhow},
     Result,
 };
 
-use crate::{peek2::Peek2, SyncedPictures};
+use crate::{config::DuplicateTimestampPolicy, peek2::Peek2, SyncedPictures};
 use frame_source::FrameData;
 use timestamped_frame::ExtraTimeData;
 
+/// The number of frames of a video skipped because of their timestamp.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct TimestampIssueCounts {
+    /// Frames with the same timestamp as the previous frame.
+    pub(crate) duplicated: usize,
+    /// Frames with an earlier timestamp than the previous frame.
+    pub(crate) non_monotonic: usize,
+}
+
 /// Iterate across multiple movies using the frame timestamps to synchronize.
 ///
 /// There is no braidz source of truth in this case.
@@ -19,6 +30,11 @@ pub(crate) struct SyncedIter {
     frame_duration: chrono::Duration,
     previous_min: DateTime<Utc>,
     previous_max: DateTime<Utc>,
+    duplicate_policy: DuplicateTimestampPolicy,
+    /// The timestamp of the last frame taken from each video.
+    last_timestamps: Vec<Option<DateTime<Utc>>>,
+    /// The skipped frames of each video, shared to report them at the end.
+    issue_counts: Arc<Mutex<Vec<TimestampIssueCounts>>>,
 }
 
 impl SyncedIter {
@@ -26,6 +42,7 @@ impl SyncedIter {
         frame_readers: Vec<Peek2<Box<dyn Iterator<Item = Result<FrameData>>>>>,
         sync_threshold: chrono::Duration,
         frame_duration: chrono::Duration,
+        duplicate_policy: DuplicateTimestampPolicy,
     ) -> Result<Self> {
         if sync_threshold * 2 > frame_duration {
             anyhow::bail!(
@@ -58,14 +75,82 @@ impl SyncedIter {
         previous_min -= frame_duration;
         previous_max -= frame_duration;
 
+        let n_readers = frame_readers.len();
         Ok(Self {
             frame_readers,
             sync_threshold,
             frame_duration,
             previous_min,
             previous_max,
+            duplicate_policy,
+            last_timestamps: vec![None; n_readers],
+            issue_counts: Arc::new(Mutex::new(vec![Default::default(); n_readers])),
         })
     }
+
+    /// The number of skipped frames of each video, updated while iterating.
+    pub(crate) fn issue_counts(&self) -> Arc<Mutex<Vec<TimestampIssueCounts>>> {
+        self.issue_counts.clone()
+    }
+}
+
+fn frame_timestamp(frame: &Result<FrameData>) -> Option<DateTime<Utc>> {
+    let frame = frame.as_ref().ok()?;
+    Some(frame.decoded()?.extra().host_timestamp())
+}
+
+/// Skip the upcoming frames of `frame_reader` whose timestamp equals or
+/// precedes `last_timestamp`, the timestamp of the frame taken before.
+///
+/// With [DuplicateTimestampPolicy::Drop], the first of several frames with the
+/// same timestamp is skipped, too. With [DuplicateTimestampPolicy::Error], an
+/// error is returned instead of skipping.
+fn skip_bad_timestamps<T>(
+    frame_reader: &mut Peek2<impl Iterator<Item = T>>,
+    timestamp: impl Fn(&T) -> Option<DateTime<Utc>>,
+    last_timestamp: &mut Option<DateTime<Utc>>,
+    policy: DuplicateTimestampPolicy,
+    counts: &mut TimestampIssueCounts,
+) -> Result<()> {
+    loop {
+        let Some(timestamp1) = frame_reader.peek1().and_then(&timestamp) else {
+            return Ok(());
+        };
+        let is_duplicate = match *last_timestamp {
+            Some(last) if timestamp1 == last => true,
+            Some(last) if timestamp1 < last => false,
+            _ => {
+                let timestamp2 = frame_reader.peek2().and_then(&timestamp);
+                if policy == DuplicateTimestampPolicy::Drop && timestamp2 == Some(timestamp1) {
+                    // Skip this frame and, as duplicates, the following
+                    // frames with the same timestamp.
+                    *last_timestamp = Some(timestamp1);
+                    counts.duplicated += 1;
+                    frame_reader.next();
+                    continue;
+                }
+                return Ok(());
+            }
+        };
+        if policy == DuplicateTimestampPolicy::Error {
+            anyhow::bail!(
+                "frame with {} timestamp {} after frame with timestamp {}",
+                if is_duplicate {
+                    "duplicated"
+                } else {
+                    "non-monotonic"
+                },
+                timestamp1,
+                last_timestamp.unwrap(),
+            );
+        }
+        if is_duplicate {
+            counts.duplicated += 1;
+        } else {
+            counts.non_monotonic += 1;
+        }
+        frame_reader.next();
+    }
 }
 
 impl Iterator for SyncedIter {
@@ -78,16 +163,33 @@ impl Iterator for SyncedIter {
 
         let mut stamps = Vec::with_capacity(self.frame_readers.len());
 
+        let mut issue_counts = self.issue_counts.lock().unwrap();
+        let duplicate_policy = self.duplicate_policy;
+
         let camera_pictures: Vec<Result<crate::OutTimepointPerCamera>> = self
             .frame_readers
             .iter_mut()
-            .filter_map(|frame_reader| {
+            .zip(self.last_timestamps.iter_mut())
+            .zip(issue_counts.iter_mut())
+            .filter_map(|((frame_reader, last_timestamp), counts)| {
+                if let Err(e) = skip_bad_timestamps(
+                    frame_reader,
+                    frame_timestamp,
+                    last_timestamp,
+                    duplicate_policy,
+                    counts,
+                ) {
+                    have_more_data = true;
+                    return Some(Err(e));
+                }
+
                 let timestamp1 = frame_reader.peek1().map(|x| x.as_ref().unwrap().decoded().unwrap().extra().host_timestamp());
 
                 let mp4_frame = if let Some(timestamp1) = timestamp1 {
                     have_more_data = true;
                     if min_threshold <= timestamp1 && timestamp1 <= max_threshold {
                         stamps.push(timestamp1);
+                        *last_timestamp = Some(timestamp1);
                         frame_reader.next()
                     } else {
                         // The next frame is not within the range expected.
@@ -106,6 +208,7 @@ impl Iterator for SyncedIter {
                                 timestamp1,
                             );
                             frame_reader.next();
+                            *last_timestamp = frame_reader.peek1().and_then(frame_timestamp);
                             frame_reader.next()
                         } else {
                             // Hmmm
@@ -118,6 +221,10 @@ impl Iterator for SyncedIter {
                 };
 
                 if let Some(timestamp1) = timestamp1 {
+                    let camera_settings = match &mp4_frame {
+                        Some(Ok(f)) => f.camera_settings().cloned(),
+                        _ => None,
+                    };
                     let mp4_frame = match mp4_frame {
                         Some(Ok(f)) => Some(f.take_decoded().unwrap()),
                         Some(Err(e)) => {
@@ -130,12 +237,14 @@ impl Iterator for SyncedIter {
                         timestamp1,
                         mp4_frame,
                         vec![],
+                        camera_settings,
                     )))
                 } else {
                     None
                 }
             })
             .collect();
+        drop(issue_counts);
 
         self.previous_min = stamps
             .iter()
@@ -173,3 +282,55 @@ impl Iterator for SyncedIter {
         }
     }
 }
+
+#[test]
+fn test_skip_bad_timestamps() {
+    use chrono::TimeZone;
+
+    // Frames at these milliseconds, with a duplicate and a step backwards.
+    let msecs = [0, 10, 10, 20, 15, 30];
+    let run = |policy| -> Result<(Vec<i64>, TimestampIssueCounts)> {
+        let mut frame_reader = Peek2::new(msecs.iter().copied());
+        let timestamp = |msec: &i64| Utc.timestamp_millis_opt(*msec).single();
+        let mut last_timestamp = None;
+        let mut counts = TimestampIssueCounts::default();
+        let mut taken = Vec::new();
+        loop {
+            skip_bad_timestamps(
+                &mut frame_reader,
+                timestamp,
+                &mut last_timestamp,
+                policy,
+                &mut counts,
+            )?;
+            let Some(msec) = frame_reader.next() else {
+                break;
+            };
+            last_timestamp = timestamp(&msec);
+            taken.push(msec);
+        }
+        Ok((taken, counts))
+    };
+
+    let (taken, counts) = run(DuplicateTimestampPolicy::KeepFirst).unwrap();
+    assert_eq!(taken, [0, 10, 20, 30]);
+    assert_eq!(
+        counts,
+        TimestampIssueCounts {
+            duplicated: 1,
+            non_monotonic: 1
+        }
+    );
+
+    let (taken, counts) = run(DuplicateTimestampPolicy::Drop).unwrap();
+    assert_eq!(taken, [0, 20, 30]);
+    assert_eq!(
+        counts,
+        TimestampIssueCounts {
+            duplicated: 2,
+            non_monotonic: 1
+        }
+    );
+
+    assert!(run(DuplicateTimestampPolicy::Error).is_err());
+}
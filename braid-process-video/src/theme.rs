@@ -0,0 +1,77 @@
+use color_eyre::{eyre::WrapErr, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The look of rendered videos, loaded from a TOML file.
+///
+/// A theme file can be shared between many configuration files. Each value
+/// set here is used unless the corresponding value is also set in the
+/// [`crate::config::VideoOutputOptions`]. Values not set in either place
+/// resolve to the defaults given below.
+///
+/// Example theme file:
+///
+/// ```toml
+/// feature_style = "fill: none; stroke: orange; stroke-width: 2;"
+/// cam_text_style = "font-family: Helvetica; font-size: 30px; fill: orange;"
+/// background_style = "fill: black;"
+/// ```
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Theme {
+    /// The radius of the circle to overlay when drawing braidz 2D features.
+    ///
+    /// Defaults to [`crate::DEFAULT_FEATURE_RADIUS`].
+    pub feature_radius: Option<String>,
+    /// The radius of the circle to overlay when drawing reprojected points.
+    ///
+    /// Defaults to [`crate::DEFAULT_REPROJECTED_RADIUS`].
+    pub reprojected_radius: Option<String>,
+    /// The SVG style string of the point to overlay when drawing braidz 2D features.
+    ///
+    /// Defaults to [`crate::DEFAULT_FEATURE_STYLE`].
+    pub feature_style: Option<String>,
+    /// The SVG style string of the point to overlay when drawing reprojected 3D points.
+    ///
+    /// Defaults to [`crate::DEFAULT_REPROJECTED_STYLE`].
+    pub reprojected_style: Option<String>,
+    /// The SVG style string of the camera text, including the font.
+    ///
+    /// Defaults to [`crate::DEFAULT_CAMERA_TEXT_STYLE`].
+    pub cam_text_style: Option<String>,
+    /// The SVG style string of the background behind the camera panels.
+    ///
+    /// Defaults to [`crate::DEFAULT_BACKGROUND_STYLE`].
+    pub background_style: Option<String>,
+}
+
+impl Theme {
+    /// Load a theme from a TOML file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let buf = std::fs::read_to_string(path)
+            .with_context(|| format!("while reading theme file \"{}\"", path.display()))?;
+        let theme = toml::from_str(&buf)
+            .with_context(|| format!("while parsing theme file \"{}\"", path.display()))?;
+        Ok(theme)
+    }
+}
+
+#[test]
+fn test_parse_theme() -> Result<()> {
+    let buf = r#"
+        feature_style = "fill: none; stroke: orange; stroke-width: 2;"
+        background_style = "fill: black;"
+    "#;
+    let theme: Theme = toml::from_str(buf)?;
+    assert_eq!(
+        theme.feature_style.as_deref(),
+        Some("fill: none; stroke: orange; stroke-width: 2;")
+    );
+    assert_eq!(theme.background_style.as_deref(), Some("fill: black;"));
+    assert_eq!(theme.cam_text_style, None);
+
+    // Unknown keys are an error to catch typos.
+    assert!(toml::from_str::<Theme>("feature_colour = \"red\"").is_err());
+    Ok(())
+}
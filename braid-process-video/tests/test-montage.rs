@@ -14,8 +14,7 @@ async fn do_config(cfg: &Valid<BraidRetrackVideoConfig>) -> anyhow::Result<()> {
     let output_fname = output_fnames[0].clone();
 
     // start parsing output
-    let do_decode_h264 = false;
-    let _src = frame_source::from_path(&output_fname, do_decode_h264)?;
+    let _src = frame_source::from_path(&output_fname, frame_source::ImageDataMode::Encoded)?;
 
     // TODO: check output. How?
 
@@ -66,6 +65,8 @@ fn get_files(
             input_video.push(VideoSourceConfig {
                 filename: dest,
                 camera_name: Some(camera_name),
+                intrinsics_override: None,
+                mp4_track: None,
             });
         }
     }
@@ -0,0 +1,73 @@
+use clap::Parser;
+use color_eyre::eyre::{self as anyhow, WrapErr};
+
+use braid_self_cal::{load_intrinsics, self_calibrate, CalibrationData, SelfCalOptions};
+
+/// Calibrate the cameras of a .braidz file from detections of a single point,
+/// such as an LED, moved through the tracking volume.
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+struct Cli {
+    /// Input .braidz file
+    #[arg(short = 'd', long)]
+    data_src: std::path::PathBuf,
+    /// Output calibration XML file
+    #[arg(short = 'o', long)]
+    output: std::path::PathBuf,
+    /// Directory with known intrinsic parameters saved as `<camera name>.yaml`
+    /// (the intrinsic parameters of the other cameras are estimated)
+    #[arg(long)]
+    intrinsics_dir: Option<std::path::PathBuf>,
+    /// Minimum number of cameras detecting the point in a frame
+    #[arg(long, default_value_t = 2)]
+    min_cameras: usize,
+    /// Maximum number of frames used
+    #[arg(long, default_value_t = 1000)]
+    max_points: usize,
+    /// Reprojection distance, in pixels, above which detections are outliers
+    #[arg(long, default_value_t = 5.0)]
+    outlier_threshold: f64,
+    /// Estimate the radial distortion of cameras without known intrinsic
+    /// parameters
+    #[arg(long)]
+    estimate_distortion: bool,
+    /// Initial focal length, in pixels, of cameras without known intrinsic
+    /// parameters (defaults to the image width)
+    #[arg(long)]
+    initial_focal_length: Option<f64>,
+}
+
+fn main() -> anyhow::Result<()> {
+    if std::env::var_os("RUST_LOG").is_none() {
+        std::env::set_var("RUST_LOG", "braid_self_cal=info,warn");
+    }
+    let _tracing_guard = env_tracing_logger::init();
+
+    let opt = Cli::parse();
+
+    if opt.output.exists() {
+        anyhow::bail!("Path {} exists. Will not overwrite.", opt.output.display());
+    }
+
+    let data = CalibrationData::from_braidz(&opt.data_src, opt.min_cameras, Some(opt.max_points))?;
+    let intrinsics = match &opt.intrinsics_dir {
+        Some(dir) => load_intrinsics(dir)?,
+        None => Default::default(),
+    };
+    let opts = SelfCalOptions {
+        outlier_threshold_pixels: opt.outlier_threshold,
+        estimate_distortion: opt.estimate_distortion,
+        initial_focal_length: opt.initial_focal_length,
+    };
+    let result = self_calibrate(&data, &intrinsics, &opts)?;
+
+    std::fs::write(&opt.output, result.to_flydra_xml()?)
+        .with_context(|| format!("writing {}", opt.output.display()))?;
+    println!(
+        "Saved calibration of {} cameras from {} points to {}",
+        result.cam_system.cams_by_name().len(),
+        result.num_points,
+        opt.output.display()
+    );
+    Ok(())
+}
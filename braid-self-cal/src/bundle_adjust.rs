@@ -0,0 +1,362 @@
+//! Bundle adjustment: the joint refinement of camera parameters and 3D point
+//! positions by Levenberg-Marquardt minimization of the reprojection
+//! distances.
+//!
+//! The normal equations are solved with the Schur complement on the point
+//! parameters, so the cost grows with the number of cameras rather than with
+//! the number of points.
+
+use nalgebra::{
+    DMatrix, DVector, Dyn, Matrix2x3, Matrix3, Matrix3x4, OMatrix, Point2, Rotation3, Vector2,
+    Vector3, U3,
+};
+
+/// The block of the normal equations coupling a camera and a point.
+type CamPointBlock = OMatrix<f64, Dyn, U3>;
+
+/// Which intrinsic parameters of a camera are optimized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FreeIntrinsics {
+    /// The intrinsic parameters are fixed.
+    None,
+    /// The focal length, the same in both directions, and the principal
+    /// point are optimized.
+    Linear,
+    /// As [FreeIntrinsics::Linear] and also the radial distortion.
+    LinearAndDistortion,
+}
+
+impl FreeIntrinsics {
+    fn count(self) -> usize {
+        match self {
+            Self::None => 0,
+            Self::Linear => 3,
+            Self::LinearAndDistortion => 5,
+        }
+    }
+}
+
+/// A pinhole camera with two coefficients of radial distortion.
+#[derive(Debug, Clone)]
+pub(crate) struct CamParams {
+    /// The rotation from world to camera coordinates as an axis-angle vector.
+    pub(crate) rotation: Vector3<f64>,
+    /// The camera center in world coordinates.
+    pub(crate) center: Vector3<f64>,
+    pub(crate) fx: f64,
+    pub(crate) fy: f64,
+    pub(crate) cx: f64,
+    pub(crate) cy: f64,
+    pub(crate) skew: f64,
+    pub(crate) k1: f64,
+    pub(crate) k2: f64,
+    pub(crate) free_intrinsics: FreeIntrinsics,
+}
+
+impl CamParams {
+    /// A camera at the origin without distortion.
+    pub(crate) fn new(fx: f64, fy: f64, cx: f64, cy: f64, skew: f64) -> Self {
+        Self {
+            rotation: Vector3::zeros(),
+            center: Vector3::zeros(),
+            fx,
+            fy,
+            cx,
+            cy,
+            skew,
+            k1: 0.0,
+            k2: 0.0,
+            free_intrinsics: FreeIntrinsics::None,
+        }
+    }
+
+    pub(crate) fn rotation_matrix(&self) -> Rotation3<f64> {
+        Rotation3::new(self.rotation)
+    }
+
+    /// Set the pose from the `[R|t]` form.
+    pub(crate) fn set_pose(&mut self, r: &Rotation3<f64>, t: &Vector3<f64>) {
+        self.rotation = r.scaled_axis();
+        self.center = -(r.inverse() * t);
+    }
+
+    /// The `[R|t]` matrix of the camera.
+    pub(crate) fn pose_matrix(&self) -> Matrix3x4<f64> {
+        let r = self.rotation_matrix();
+        crate::geometry::pose_matrix(&r, &-(r * self.center))
+    }
+
+    /// The normalized image coordinates of a pixel, ignoring distortion.
+    pub(crate) fn normalize(&self, pixel: &Point2<f64>) -> Point2<f64> {
+        let y = (pixel.y - self.cy) / self.fy;
+        let x = (pixel.x - self.cx - self.skew * y) / self.fx;
+        Point2::new(x, y)
+    }
+
+    /// The pixel coordinates of a 3D point.
+    pub(crate) fn project(&self, point: &Vector3<f64>) -> Vector2<f64> {
+        let xc = self.rotation_matrix() * (point - self.center);
+        let x = xc.x / xc.z;
+        let y = xc.y / xc.z;
+        let r2 = x * x + y * y;
+        let d = 1.0 + self.k1 * r2 + self.k2 * r2 * r2;
+        let (x, y) = (x * d, y * d);
+        Vector2::new(self.fx * x + self.skew * y + self.cx, self.fy * y + self.cy)
+    }
+
+    /// The depth of a 3D point in front of the camera.
+    pub(crate) fn depth(&self, point: &Vector3<f64>) -> f64 {
+        (self.rotation_matrix() * (point - self.center)).z
+    }
+
+    fn num_params(&self) -> usize {
+        6 + self.free_intrinsics.count()
+    }
+
+    fn params(&self) -> Vec<f64> {
+        let mut params = Vec::with_capacity(self.num_params());
+        params.extend(self.rotation.iter());
+        params.extend(self.center.iter());
+        let intrinsics = [self.fx, self.cx, self.cy, self.k1, self.k2];
+        params.extend(&intrinsics[..self.free_intrinsics.count()]);
+        params
+    }
+
+    fn set_params(&mut self, params: &[f64]) {
+        self.rotation = Vector3::from_column_slice(&params[0..3]);
+        self.center = Vector3::from_column_slice(&params[3..6]);
+        if self.free_intrinsics != FreeIntrinsics::None {
+            self.fy = params[6];
+        }
+        let intrinsics = [
+            &mut self.fx,
+            &mut self.cx,
+            &mut self.cy,
+            &mut self.k1,
+            &mut self.k2,
+        ];
+        for (value, param) in intrinsics.into_iter().zip(&params[6..]) {
+            *value = *param;
+        }
+    }
+}
+
+/// The image of a point in a camera.
+#[derive(Debug, Clone)]
+pub(crate) struct Observation {
+    pub(crate) cam: usize,
+    pub(crate) point: usize,
+    pub(crate) pixel: Vector2<f64>,
+}
+
+/// The step used for the numerical derivative with respect to `value`.
+fn step(value: f64) -> f64 {
+    1e-6 * value.abs().max(1.0)
+}
+
+fn sum_of_squares(
+    cams: &[CamParams],
+    points: &[Vector3<f64>],
+    observations: &[Observation],
+) -> f64 {
+    observations
+        .iter()
+        .map(|o| (cams[o.cam].project(&points[o.point]) - o.pixel).norm_squared())
+        .sum()
+}
+
+/// Minimize the sum of squared reprojection distances of `observations` over
+/// the parameters of the observing cameras and the observed points.
+///
+/// Returns the root mean square reprojection distance.
+pub(crate) fn bundle_adjust(
+    cams: &mut [CamParams],
+    points: &mut [Vector3<f64>],
+    observations: &[Observation],
+    max_iterations: usize,
+) -> f64 {
+    if observations.is_empty() {
+        return 0.0;
+    }
+
+    // The offset of the parameters of each observing camera in the reduced
+    // system.
+    let mut offsets = vec![None; cams.len()];
+    let mut num_cam_params = 0;
+    for o in observations.iter() {
+        if offsets[o.cam].is_none() {
+            offsets[o.cam] = Some(num_cam_params);
+            num_cam_params += cams[o.cam].num_params();
+        }
+    }
+    let mut obs_by_point: Vec<Vec<usize>> = vec![Vec::new(); points.len()];
+    for (i, o) in observations.iter().enumerate() {
+        obs_by_point[o.point].push(i);
+    }
+
+    let mut cost = sum_of_squares(cams, points, observations);
+    let mut lambda = 1e-3;
+    for _ in 0..max_iterations {
+        // Jacobians of the residuals and the blocks of the normal equations.
+        let mut u = DMatrix::<f64>::zeros(num_cam_params, num_cam_params);
+        let mut g_cam = DVector::<f64>::zeros(num_cam_params);
+        let mut v = vec![Matrix3::<f64>::zeros(); points.len()];
+        let mut g_point = vec![Vector3::<f64>::zeros(); points.len()];
+        let mut w = Vec::with_capacity(observations.len());
+        for o in observations.iter() {
+            let cam = &cams[o.cam];
+            let point = &points[o.point];
+            let residual = cam.project(point) - o.pixel;
+
+            let params = cam.params();
+            let mut j_cam = DMatrix::<f64>::zeros(2, params.len());
+            let mut perturbed = cam.clone();
+            for k in 0..params.len() {
+                let h = step(params[k]);
+                let mut p = params.clone();
+                p[k] = params[k] + h;
+                perturbed.set_params(&p);
+                let plus = perturbed.project(point);
+                p[k] = params[k] - h;
+                perturbed.set_params(&p);
+                let minus = perturbed.project(point);
+                j_cam.set_column(k, &((plus - minus) / (2.0 * h)));
+            }
+            let mut j_point = Matrix2x3::<f64>::zeros();
+            for k in 0..3 {
+                let h = step(point[k]);
+                let mut p = *point;
+                p[k] = point[k] + h;
+                let plus = cam.project(&p);
+                p[k] = point[k] - h;
+                let minus = cam.project(&p);
+                j_point.set_column(k, &((plus - minus) / (2.0 * h)));
+            }
+
+            let offset = offsets[o.cam].unwrap();
+            let n = params.len();
+            let j_cam_t = j_cam.transpose();
+            let mut u_block = u.view_mut((offset, offset), (n, n));
+            u_block += &j_cam_t * &j_cam;
+            let mut g_block = g_cam.rows_mut(offset, n);
+            g_block += &j_cam_t * residual;
+            v[o.point] += j_point.transpose() * j_point;
+            g_point[o.point] += j_point.transpose() * residual;
+            w.push(j_cam_t * j_point);
+        }
+
+        let mut improved = false;
+        while lambda < 1e10 {
+            match solve_step(
+                &u,
+                &g_cam,
+                &v,
+                &g_point,
+                &w,
+                observations,
+                &offsets,
+                &obs_by_point,
+                lambda,
+            ) {
+                Some((delta_cam, delta_point)) => {
+                    let mut new_cams = cams.to_vec();
+                    for (cam, offset) in new_cams.iter_mut().zip(offsets.iter()) {
+                        if let Some(offset) = offset {
+                            let params: Vec<f64> = cam
+                                .params()
+                                .iter()
+                                .zip(delta_cam.rows(*offset, cam.num_params()).iter())
+                                .map(|(p, d)| p + d)
+                                .collect();
+                            cam.set_params(&params);
+                        }
+                    }
+                    let new_points: Vec<_> = points
+                        .iter()
+                        .zip(delta_point.iter())
+                        .map(|(p, d)| p + d)
+                        .collect();
+                    let new_cost = sum_of_squares(&new_cams, &new_points, observations);
+                    if new_cost < cost {
+                        let converged = cost - new_cost < 1e-10 * cost;
+                        cams.clone_from_slice(&new_cams);
+                        points.copy_from_slice(&new_points);
+                        cost = new_cost;
+                        lambda = (lambda / 10.0).max(1e-12);
+                        improved = !converged;
+                        break;
+                    }
+                    lambda *= 10.0;
+                }
+                None => lambda *= 10.0,
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+    (cost / observations.len() as f64).sqrt()
+}
+
+/// Solve the damped normal equations for the parameter updates.
+#[allow(clippy::too_many_arguments)]
+fn solve_step(
+    u: &DMatrix<f64>,
+    g_cam: &DVector<f64>,
+    v: &[Matrix3<f64>],
+    g_point: &[Vector3<f64>],
+    w: &[CamPointBlock],
+    observations: &[Observation],
+    offsets: &[Option<usize>],
+    obs_by_point: &[Vec<usize>],
+    lambda: f64,
+) -> Option<(DVector<f64>, Vec<Vector3<f64>>)> {
+    let mut s = u.clone();
+    for i in 0..s.nrows() {
+        s[(i, i)] += lambda * u[(i, i)].max(1e-12);
+    }
+    let mut b = -g_cam;
+
+    // Eliminate the points.
+    let mut v_inv = Vec::with_capacity(v.len());
+    for (point, obs) in obs_by_point.iter().enumerate() {
+        if obs.is_empty() {
+            v_inv.push(Matrix3::zeros());
+            continue;
+        }
+        let mut damped = v[point];
+        for i in 0..3 {
+            damped[(i, i)] += lambda * v[point][(i, i)].max(1e-12);
+        }
+        let inv = damped.try_inverse()?;
+        for &o1 in obs.iter() {
+            let off1 = offsets[observations[o1].cam].unwrap();
+            let y = &w[o1] * inv;
+            let mut b_block = b.rows_mut(off1, y.nrows());
+            b_block += &y * g_point[point];
+            for &o2 in obs.iter() {
+                let off2 = offsets[observations[o2].cam].unwrap();
+                let block = &y * w[o2].transpose();
+                let mut s_block = s.view_mut((off1, off2), block.shape());
+                s_block -= block;
+            }
+        }
+        v_inv.push(inv);
+    }
+
+    let delta_cam = s.cholesky()?.solve(&b);
+    let delta_point = obs_by_point
+        .iter()
+        .enumerate()
+        .map(|(point, obs)| {
+            let mut rhs = -g_point[point];
+            for &o in obs.iter() {
+                let off = offsets[observations[o].cam].unwrap();
+                let d = w[o].transpose() * delta_cam.rows(off, w[o].nrows());
+                rhs -= Vector3::new(d[0], d[1], d[2]);
+            }
+            v_inv[point] * rhs
+        })
+        .collect();
+    Some((delta_cam, delta_point))
+}
@@ -0,0 +1,383 @@
+//! Two-view geometry, triangulation and camera resection used to build the
+//! initial reconstruction.
+//!
+//! All image points here are normalized image coordinates, i.e. undistorted
+//! pixel coordinates multiplied by the inverse of the intrinsic matrix, and
+//! cameras are `[R|t]` matrices mapping world points to camera coordinates.
+
+use nalgebra::{DMatrix, Matrix3, Matrix3x4, Matrix4, Point2, Point3, Rotation3, Vector3};
+
+/// A small deterministic pseudo-random number generator (xorshift64*) so that
+/// the calibration is reproducible.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Choose `k` distinct indices less than `n`.
+    pub(crate) fn sample(&mut self, n: usize, k: usize) -> Vec<usize> {
+        debug_assert!(k <= n);
+        let mut chosen: Vec<usize> = Vec::with_capacity(k);
+        while chosen.len() < k {
+            let i = (self.next_u64() % n as u64) as usize;
+            if !chosen.contains(&i) {
+                chosen.push(i);
+            }
+        }
+        chosen
+    }
+}
+
+/// The transform moving the centroid of `points` to the origin and scaling
+/// their mean distance from it to `sqrt(2)` (Hartley normalization).
+fn normalizing_transform_2d(points: impl Iterator<Item = Point2<f64>> + Clone) -> Matrix3<f64> {
+    let n = points.clone().count() as f64;
+    let centroid = points
+        .clone()
+        .fold(Vector3::zeros(), |acc, p| acc + Vector3::new(p.x, p.y, 0.0))
+        / n;
+    let mean_dist = points
+        .map(|p| ((p.x - centroid.x).powi(2) + (p.y - centroid.y).powi(2)).sqrt())
+        .sum::<f64>()
+        / n;
+    let s = if mean_dist > 0.0 {
+        std::f64::consts::SQRT_2 / mean_dist
+    } else {
+        1.0
+    };
+    Matrix3::new(
+        s,
+        0.0,
+        -s * centroid.x,
+        0.0,
+        s,
+        -s * centroid.y,
+        0.0,
+        0.0,
+        1.0,
+    )
+}
+
+/// As [normalizing_transform_2d] for 3D points, with a mean distance of
+/// `sqrt(3)`.
+fn normalizing_transform_3d(points: impl Iterator<Item = Point3<f64>> + Clone) -> Matrix4<f64> {
+    let n = points.clone().count() as f64;
+    let centroid = points
+        .clone()
+        .fold(Vector3::zeros(), |acc, p| acc + p.coords)
+        / n;
+    let mean_dist = points.map(|p| (p.coords - centroid).norm()).sum::<f64>() / n;
+    let s = if mean_dist > 0.0 {
+        3.0f64.sqrt() / mean_dist
+    } else {
+        1.0
+    };
+    let mut t = Matrix4::identity() * s;
+    t[(3, 3)] = 1.0;
+    t.fixed_view_mut::<3, 1>(0, 3).copy_from(&(-s * centroid));
+    t
+}
+
+/// The right singular vector of `a` with the smallest singular value.
+fn null_vector(a: DMatrix<f64>) -> Option<Vec<f64>> {
+    let ncols = a.ncols();
+    // Pad with zero rows so that the SVD computes all right singular vectors.
+    let a = if a.nrows() < ncols {
+        a.resize_vertically(ncols, 0.0)
+    } else {
+        a
+    };
+    let svd = a.svd(false, true);
+    let v_t = svd.v_t?;
+    Some(v_t.row(ncols - 1).iter().copied().collect())
+}
+
+/// Estimate the essential matrix `E`, with `x2ᵀ E x1 = 0`, from at least 8
+/// correspondences with the normalized eight-point algorithm.
+pub(crate) fn essential_eight_point(pairs: &[(Point2<f64>, Point2<f64>)]) -> Option<Matrix3<f64>> {
+    if pairs.len() < 8 {
+        return None;
+    }
+    let t1 = normalizing_transform_2d(pairs.iter().map(|p| p.0));
+    let t2 = normalizing_transform_2d(pairs.iter().map(|p| p.1));
+    let mut a = DMatrix::zeros(pairs.len(), 9);
+    for (i, (x1, x2)) in pairs.iter().enumerate() {
+        let p1 = t1 * x1.to_homogeneous();
+        let p2 = t2 * x2.to_homogeneous();
+        for j in 0..3 {
+            for k in 0..3 {
+                a[(i, j * 3 + k)] = p2[j] * p1[k];
+            }
+        }
+    }
+    let e = t2.transpose() * Matrix3::from_row_slice(&null_vector(a)?) * t1;
+
+    // Enforce two equal singular values and a zero singular value.
+    let svd = e.svd(true, true);
+    let (u, v_t) = (svd.u?, svd.v_t?);
+    (svd.singular_values[0] > 0.0)
+        .then(|| u * Matrix3::from_diagonal(&Vector3::new(1.0, 1.0, 0.0)) * v_t)
+}
+
+/// The Sampson distance of a correspondence to the epipolar geometry `e`.
+pub(crate) fn sampson_distance(e: &Matrix3<f64>, x1: &Point2<f64>, x2: &Point2<f64>) -> f64 {
+    let x1 = x1.to_homogeneous();
+    let x2 = x2.to_homogeneous();
+    let ex1 = e * x1;
+    let etx2 = e.transpose() * x2;
+    let num = x2.dot(&ex1);
+    let den = ex1.x.powi(2) + ex1.y.powi(2) + etx2.x.powi(2) + etx2.y.powi(2);
+    if den > 0.0 {
+        (num * num / den).sqrt()
+    } else {
+        f64::INFINITY
+    }
+}
+
+/// Robustly estimate the essential matrix with RANSAC.
+///
+/// Returns the essential matrix refit to all inliers and which
+/// correspondences are inliers, i.e. have a Sampson distance less than
+/// `threshold`.
+pub(crate) fn ransac_essential(
+    pairs: &[(Point2<f64>, Point2<f64>)],
+    threshold: f64,
+    iterations: usize,
+    rng: &mut Rng,
+) -> Option<(Matrix3<f64>, Vec<bool>)> {
+    if pairs.len() < 8 {
+        return None;
+    }
+    let inliers_of = |e: &Matrix3<f64>| -> Vec<bool> {
+        pairs
+            .iter()
+            .map(|(x1, x2)| sampson_distance(e, x1, x2) < threshold)
+            .collect()
+    };
+    let mut best: Option<(usize, Vec<bool>)> = None;
+    for _ in 0..iterations {
+        let sample: Vec<_> = rng
+            .sample(pairs.len(), 8)
+            .into_iter()
+            .map(|i| pairs[i])
+            .collect();
+        let Some(e) = essential_eight_point(&sample) else {
+            continue;
+        };
+        let inliers = inliers_of(&e);
+        let count = inliers.iter().filter(|x| **x).count();
+        if !best.as_ref().is_some_and(|(n, _)| count <= *n) {
+            best = Some((count, inliers));
+        }
+    }
+    let (_, mut inliers) = best?;
+    // Refit to the inliers, twice, as the inlier set may grow.
+    let mut e = None;
+    for _ in 0..2 {
+        let inlier_pairs: Vec<_> = pairs
+            .iter()
+            .zip(inliers.iter())
+            .filter(|(_, is_inlier)| **is_inlier)
+            .map(|(pair, _)| *pair)
+            .collect();
+        let Some(refit) = essential_eight_point(&inlier_pairs) else {
+            break;
+        };
+        inliers = inliers_of(&refit);
+        e = Some(refit);
+    }
+    Some((e?, inliers))
+}
+
+/// The four possible relative poses `(R, t)` of the second camera of an
+/// essential matrix when the first camera is `[I|0]`.
+pub(crate) fn decompose_essential(e: &Matrix3<f64>) -> Option<[(Rotation3<f64>, Vector3<f64>); 4]> {
+    let svd = e.svd(true, true);
+    let mut u = svd.u?;
+    let mut v_t = svd.v_t?;
+    if u.determinant() < 0.0 {
+        u = -u;
+    }
+    if v_t.determinant() < 0.0 {
+        v_t = -v_t;
+    }
+    let w = Matrix3::new(0.0, -1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0);
+    let r1 = Rotation3::from_matrix_unchecked(u * w * v_t);
+    let r2 = Rotation3::from_matrix_unchecked(u * w.transpose() * v_t);
+    let t: Vector3<f64> = u.column(2).into();
+    Some([(r1, t), (r1, -t), (r2, t), (r2, -t)])
+}
+
+/// The `[R|t]` matrix of a camera.
+pub(crate) fn pose_matrix(r: &Rotation3<f64>, t: &Vector3<f64>) -> Matrix3x4<f64> {
+    let mut p = Matrix3x4::zeros();
+    p.fixed_view_mut::<3, 3>(0, 0).copy_from(r.matrix());
+    p.fixed_view_mut::<3, 1>(0, 3).copy_from(t);
+    p
+}
+
+/// Triangulate a point seen by at least two cameras with the linear (DLT)
+/// method.
+pub(crate) fn triangulate(views: &[(Matrix3x4<f64>, Point2<f64>)]) -> Option<Point3<f64>> {
+    if views.len() < 2 {
+        return None;
+    }
+    let mut a = DMatrix::zeros(views.len() * 2, 4);
+    for (i, (p, x)) in views.iter().enumerate() {
+        let r0 = x.x * p.row(2) - p.row(0);
+        let r1 = x.y * p.row(2) - p.row(1);
+        a.row_mut(i * 2).copy_from(&r0);
+        a.row_mut(i * 2 + 1).copy_from(&r1);
+    }
+    let h = null_vector(a)?;
+    if h[3].abs() < 1e-12 {
+        return None;
+    }
+    Some(Point3::new(h[0] / h[3], h[1] / h[3], h[2] / h[3]))
+}
+
+/// The depth of a point in a camera `[R|t]`.
+pub(crate) fn depth(p: &Matrix3x4<f64>, x: &Point3<f64>) -> f64 {
+    (p.row(2) * x.to_homogeneous())[0]
+}
+
+/// Estimate the camera matrix `P`, with `x ~ P X`, from at least 6 3D points
+/// and their images with the linear (DLT) method.
+///
+/// `P` is scaled so that points in front of the camera have a positive third
+/// coordinate.
+pub(crate) fn resect(points: &[(Point3<f64>, Point2<f64>)]) -> Option<Matrix3x4<f64>> {
+    if points.len() < 6 {
+        return None;
+    }
+    let t3 = normalizing_transform_3d(points.iter().map(|p| p.0));
+    let t2 = normalizing_transform_2d(points.iter().map(|p| p.1));
+    let mut a = DMatrix::zeros(points.len() * 2, 12);
+    for (i, (x3, x2)) in points.iter().enumerate() {
+        let xw = t3 * x3.to_homogeneous();
+        let xi = t2 * x2.to_homogeneous();
+        for j in 0..4 {
+            a[(i * 2, j)] = xw[j];
+            a[(i * 2, 8 + j)] = -xi.x * xw[j];
+            a[(i * 2 + 1, 4 + j)] = xw[j];
+            a[(i * 2 + 1, 8 + j)] = -xi.y * xw[j];
+        }
+    }
+    let p = Matrix3x4::from_row_slice(&null_vector(a)?);
+    let p = t2.try_inverse()? * p * t3;
+    let det = p.fixed_view::<3, 3>(0, 0).determinant();
+    if det == 0.0 {
+        return None;
+    }
+    Some(p * det.signum())
+}
+
+/// The image of a 3D point with the camera matrix `p`, if it is in front of
+/// the camera.
+fn project(p: &Matrix3x4<f64>, x: &Point3<f64>) -> Option<Point2<f64>> {
+    let h = p * x.to_homogeneous();
+    (h.z > 0.0).then(|| Point2::new(h.x / h.z, h.y / h.z))
+}
+
+/// Robustly estimate the camera matrix with RANSAC.
+///
+/// Returns the camera matrix refit to all inliers and which points are
+/// inliers, i.e. are in front of the camera and have a reprojection distance
+/// less than `threshold`.
+pub(crate) fn ransac_resect(
+    points: &[(Point3<f64>, Point2<f64>)],
+    threshold: f64,
+    iterations: usize,
+    rng: &mut Rng,
+) -> Option<(Matrix3x4<f64>, Vec<bool>)> {
+    if points.len() < 6 {
+        return None;
+    }
+    let inliers_of = |p: &Matrix3x4<f64>| -> Vec<bool> {
+        points
+            .iter()
+            .map(|(x3, x2)| project(p, x3).is_some_and(|x| (x - x2).norm() < threshold))
+            .collect()
+    };
+    let mut best: Option<(usize, Vec<bool>)> = None;
+    for _ in 0..iterations {
+        let sample: Vec<_> = rng
+            .sample(points.len(), 6)
+            .into_iter()
+            .map(|i| points[i])
+            .collect();
+        let Some(p) = resect(&sample) else {
+            continue;
+        };
+        let inliers = inliers_of(&p);
+        let count = inliers.iter().filter(|x| **x).count();
+        if !best.as_ref().is_some_and(|(n, _)| count <= *n) {
+            best = Some((count, inliers));
+        }
+    }
+    let (_, mut inliers) = best?;
+    let mut p = None;
+    for _ in 0..2 {
+        let inlier_points: Vec<_> = points
+            .iter()
+            .zip(inliers.iter())
+            .filter(|(_, is_inlier)| **is_inlier)
+            .map(|(point, _)| *point)
+            .collect();
+        let Some(refit) = resect(&inlier_points) else {
+            break;
+        };
+        inliers = inliers_of(&refit);
+        p = Some(refit);
+    }
+    Some((p?, inliers))
+}
+
+/// The pose `(R, t)` of a camera matrix `P ~ [R|t]` estimated from normalized
+/// image coordinates.
+pub(crate) fn pose_of_normalized(p: &Matrix3x4<f64>) -> Option<(Rotation3<f64>, Vector3<f64>)> {
+    let svd = p.fixed_view::<3, 3>(0, 0).into_owned().svd(true, true);
+    let r = svd.u? * svd.v_t?;
+    let scale = svd.singular_values.mean();
+    if scale <= 0.0 {
+        return None;
+    }
+    let t: Vector3<f64> = p.column(3) / scale;
+    Some((Rotation3::from_matrix_unchecked(r), t))
+}
+
+/// Decompose a camera matrix `P ~ K [R|t]` estimated from pixel coordinates
+/// into the upper triangular intrinsic matrix `K`, with `K[(2, 2)] == 1`, and
+/// the pose `(R, t)`.
+pub(crate) fn decompose_camera(
+    p: &Matrix3x4<f64>,
+) -> Option<(Matrix3<f64>, Rotation3<f64>, Vector3<f64>)> {
+    // RQ decomposition from the QR decomposition of the flipped matrix.
+    let m = p.fixed_view::<3, 3>(0, 0).into_owned();
+    let flip = Matrix3::new(0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0);
+    let qr = (flip * m).transpose().qr();
+    let mut k = flip * qr.r().transpose() * flip;
+    let mut r = flip * qr.q().transpose();
+    // Make the diagonal of K positive.
+    for i in 0..3 {
+        if k[(i, i)] < 0.0 {
+            k.column_mut(i).neg_mut();
+            r.row_mut(i).neg_mut();
+        }
+    }
+    if r.determinant() < 0.0 || k[(2, 2)] <= 0.0 {
+        return None;
+    }
+    let t = k.try_inverse()? * p.column(3);
+    let scale = k[(2, 2)];
+    Some((k / scale, Rotation3::from_matrix_unchecked(r), t))
+}
@@ -0,0 +1,345 @@
+//! Multi-camera calibration from synchronized 2D detections of a moving
+//! target.
+//!
+//! Like MultiCamSelfCal, this estimates the extrinsic parameters of all
+//! cameras, and optionally their intrinsic parameters, from frames in which
+//! several cameras detect a single point, such as an LED waved through the
+//! tracking volume. The resulting calibration is in an arbitrary coordinate
+//! frame and scale: the reconstructed target positions are centered on the
+//! origin with a root mean square distance of one from it. As with
+//! MultiCamSelfCal, align the calibration before tracking.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use eyre::{bail, eyre, Result, WrapErr};
+use nalgebra::{Point2, Point3, UnitQuaternion, Vector5};
+use opencv_ros_camera::{Distortion, NamedIntrinsicParameters, RosOpenCvIntrinsics};
+use tracing::info;
+
+mod bundle_adjust;
+mod geometry;
+mod reconstruct;
+
+use bundle_adjust::{CamParams, FreeIntrinsics};
+
+/// The detections of the calibration target in one frame.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CorrespondingPoints {
+    pub frame: i64,
+    /// The distorted pixel coordinates of the detection by camera name.
+    pub points: BTreeMap<String, [f64; 2]>,
+}
+
+/// The input to [self_calibrate].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CalibrationData {
+    /// The image width and height by camera name.
+    pub image_sizes: BTreeMap<String, (usize, usize)>,
+    pub points: Vec<CorrespondingPoints>,
+}
+
+impl CalibrationData {
+    /// Read the 2D detections of a `.braidz` file or `.braid` directory.
+    ///
+    /// Frames in which at least `min_cameras` cameras detect exactly one point
+    /// are used, ignoring cameras with several detections. If there are more
+    /// than `max_points` such frames, evenly spaced frames are chosen.
+    pub fn from_braidz<P: AsRef<Path>>(
+        path: P,
+        min_cameras: usize,
+        max_points: Option<usize>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let archive = braidz_parser::braidz_parse_path(path)
+            .with_context(|| format!("when parsing braidz file \"{}\"", path.display()))?;
+        let data2d = archive
+            .data2d_distorted
+            .as_ref()
+            .ok_or_else(|| eyre!("no 2D detections in \"{}\"", path.display()))?;
+
+        // The detections of each frame, with `None` for cameras with several.
+        let mut by_frame: BTreeMap<i64, BTreeMap<String, Option<[f64; 2]>>> = BTreeMap::new();
+        for (camn, seq) in data2d.qz.iter() {
+            let cam_name = archive
+                .cam_info
+                .camn2camid
+                .get(camn)
+                .ok_or_else(|| eyre!("no camera name for camera number {camn:?}"))?;
+            for ((frame, x), y) in seq.frame.iter().zip(seq.xdata.iter()).zip(seq.ydata.iter()) {
+                by_frame
+                    .entry(*frame)
+                    .or_default()
+                    .entry(cam_name.clone())
+                    .and_modify(|point| *point = None)
+                    .or_insert(Some([x.into_inner(), y.into_inner()]));
+            }
+        }
+        let mut points: Vec<CorrespondingPoints> = by_frame
+            .into_iter()
+            .map(|(frame, detections)| CorrespondingPoints {
+                frame,
+                points: detections
+                    .into_iter()
+                    .filter_map(|(cam_name, point)| Some((cam_name, point?)))
+                    .collect(),
+            })
+            .filter(|frame| frame.points.len() >= min_cameras.max(2))
+            .collect();
+        info!(
+            "{} frames in which {} or more cameras detected exactly one point",
+            points.len(),
+            min_cameras.max(2)
+        );
+        if let Some(max_points) = max_points {
+            if points.len() > max_points {
+                let step = points.len() as f64 / max_points as f64;
+                points = (0..max_points)
+                    .map(|i| points[(i as f64 * step) as usize].clone())
+                    .collect();
+            }
+        }
+
+        Ok(Self {
+            image_sizes: archive.image_sizes.clone().unwrap_or_default(),
+            points,
+        })
+    }
+}
+
+/// Load the intrinsic parameters saved as `<camera name>.yaml` files in the ROS
+/// camera_info format in the directory `dir`, as Strand Camera does.
+pub fn load_intrinsics<P: AsRef<Path>>(
+    dir: P,
+) -> Result<BTreeMap<String, NamedIntrinsicParameters<f64>>> {
+    let dir = dir.as_ref();
+    let mut intrinsics = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("reading directory \"{}\"", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("yaml") {
+            continue;
+        }
+        let Some(cam_name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let rdr = std::fs::File::open(&path)
+            .with_context(|| format!("opening intrinsics file \"{}\"", path.display()))?;
+        let named: NamedIntrinsicParameters<f64> =
+            opencv_ros_camera::from_ros_yaml(std::io::BufReader::new(rdr))
+                .map_err(|e| eyre!("parsing \"{}\": {e}", path.display()))?;
+        intrinsics.insert(cam_name.to_string(), named);
+    }
+    Ok(intrinsics)
+}
+
+/// Options for [self_calibrate].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfCalOptions {
+    /// Detections farther, in pixels, from the projection of the
+    /// reconstructed target are outliers.
+    pub outlier_threshold_pixels: f64,
+    /// Whether to estimate the radial distortion of cameras without known
+    /// intrinsic parameters.
+    pub estimate_distortion: bool,
+    /// The initial focal length, in pixels, of cameras without known intrinsic
+    /// parameters. If `None`, the image width.
+    pub initial_focal_length: Option<f64>,
+}
+
+impl Default for SelfCalOptions {
+    fn default() -> Self {
+        Self {
+            outlier_threshold_pixels: 5.0,
+            estimate_distortion: false,
+            initial_focal_length: None,
+        }
+    }
+}
+
+/// The result of [self_calibrate].
+#[derive(Debug)]
+pub struct SelfCalResult {
+    pub cam_system: mvg::MultiCameraSystem<f64>,
+    /// Mean distance, in pixels, between the inlier detections and the
+    /// projections of the reconstructed target by camera name.
+    pub mean_reproj_dist: BTreeMap<String, f64>,
+    /// The number of inlier detections by camera name.
+    pub num_inliers: BTreeMap<String, usize>,
+    /// The number of reconstructed target positions.
+    pub num_points: usize,
+}
+
+impl SelfCalResult {
+    pub fn to_flydra_xml(&self) -> Result<Vec<u8>> {
+        let flydra_cal =
+            flydra_mvg::FlydraMultiCameraSystem::<f64>::from_system(self.cam_system.clone(), None);
+        let mut xml_buf: Vec<u8> = Vec::new();
+        flydra_cal.to_flydra_xml(&mut xml_buf)?;
+        Ok(xml_buf)
+    }
+}
+
+/// Calibrate the cameras of `data`.
+///
+/// The intrinsic parameters of cameras in `intrinsics` are kept. Those of the
+/// other cameras are estimated, which needs their image size.
+pub fn self_calibrate(
+    data: &CalibrationData,
+    intrinsics: &BTreeMap<String, NamedIntrinsicParameters<f64>>,
+    opts: &SelfCalOptions,
+) -> Result<SelfCalResult> {
+    let cam_names: Vec<String> = data
+        .points
+        .iter()
+        .flat_map(|frame| frame.points.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .cloned()
+        .collect();
+
+    let mut sizes = Vec::with_capacity(cam_names.len());
+    let mut cams = Vec::with_capacity(cam_names.len());
+    for cam_name in cam_names.iter() {
+        match intrinsics.get(cam_name) {
+            Some(named) => {
+                let p = &named.intrinsics.p;
+                cams.push(CamParams::new(
+                    p[(0, 0)],
+                    p[(1, 1)],
+                    p[(0, 2)],
+                    p[(1, 2)],
+                    p[(0, 1)],
+                ));
+                sizes.push(
+                    data.image_sizes
+                        .get(cam_name)
+                        .copied()
+                        .unwrap_or((named.width, named.height)),
+                );
+            }
+            None => {
+                let Some((width, height)) = data.image_sizes.get(cam_name).copied() else {
+                    bail!("image size of camera {cam_name} unknown: give its intrinsic parameters");
+                };
+                let focal = opts.initial_focal_length.unwrap_or(width as f64);
+                let mut cam =
+                    CamParams::new(focal, focal, width as f64 / 2.0, height as f64 / 2.0, 0.0);
+                cam.free_intrinsics = if opts.estimate_distortion {
+                    FreeIntrinsics::LinearAndDistortion
+                } else {
+                    FreeIntrinsics::Linear
+                };
+                cams.push(cam);
+                sizes.push((width, height));
+            }
+        }
+    }
+
+    // Undistort the detections of cameras with known intrinsic parameters.
+    let tracks: Vec<reconstruct::Track> = data
+        .points
+        .iter()
+        .map(|frame| {
+            frame
+                .points
+                .iter()
+                .map(|(cam_name, [x, y])| {
+                    let cam = cam_names.binary_search(cam_name).unwrap();
+                    let pixel = match intrinsics.get(cam_name) {
+                        Some(named) => {
+                            let distorted = mvg::DistortedPixel {
+                                coords: Point2::new(*x, *y),
+                            };
+                            let undistorted: mvg::UndistortedPixel<f64> =
+                                named.intrinsics.undistort(&(&distorted).into()).into();
+                            undistorted.coords
+                        }
+                        None => Point2::new(*x, *y),
+                    };
+                    (cam, pixel)
+                })
+                .collect()
+        })
+        .filter(|track: &reconstruct::Track| track.len() >= 2)
+        .collect();
+
+    let recon = reconstruct::reconstruct(cams, &cam_names, &tracks, opts.outlier_threshold_pixels)?;
+
+    let mut cams_by_name = BTreeMap::new();
+    for ((cam_name, cam), (width, height)) in cam_names.iter().zip(recon.cams.iter()).zip(sizes) {
+        let cam_intrinsics = match intrinsics.get(cam_name) {
+            Some(named) => named.intrinsics.clone(),
+            None => RosOpenCvIntrinsics::from_params_with_distortion(
+                cam.fx,
+                cam.skew,
+                cam.fy,
+                cam.cx,
+                cam.cy,
+                Distortion::from_opencv_vec(Vector5::new(cam.k1, cam.k2, 0.0, 0.0, 0.0)),
+            ),
+        };
+        let extrinsics = cam_geom::ExtrinsicParameters::from_rotation_and_camcenter(
+            UnitQuaternion::from_rotation_matrix(&cam.rotation_matrix()),
+            Point3::from(cam.center),
+        );
+        let cam = mvg::Camera::new(width, height, extrinsics, cam_intrinsics)?;
+        cams_by_name.insert(cam_name.clone(), cam);
+    }
+    let cam_system = mvg::MultiCameraSystem::new(cams_by_name);
+
+    // Compare the original detections with the projections of the final
+    // cameras.
+    let mut sum_dist = vec![0.0; cam_names.len()];
+    let mut num_inliers = vec![0usize; cam_names.len()];
+    for ((frame, point), inliers) in data
+        .points
+        .iter()
+        .filter(|frame| frame.points.len() >= 2)
+        .zip(recon.points.iter())
+        .zip(recon.inliers.iter())
+    {
+        let Some(point) = point else {
+            continue;
+        };
+        let world = mvg::PointWorldFrame {
+            coords: Point3::from(*point),
+        };
+        for ((cam_name, [x, y]), is_inlier) in frame.points.iter().zip(inliers.iter()) {
+            if !is_inlier {
+                continue;
+            }
+            let cam = cam_names.binary_search(cam_name).unwrap();
+            let projected = cam_system
+                .cam_by_name(cam_name)
+                .unwrap()
+                .project_3d_to_distorted_pixel(&world);
+            sum_dist[cam] += nalgebra::distance(&projected.coords, &Point2::new(*x, *y));
+            num_inliers[cam] += 1;
+        }
+    }
+
+    let mut mean_reproj_dist = BTreeMap::new();
+    let mut num_inliers_by_name = BTreeMap::new();
+    for (i, cam_name) in cam_names.iter().enumerate() {
+        if num_inliers[i] > 0 {
+            mean_reproj_dist.insert(cam_name.clone(), sum_dist[i] / num_inliers[i] as f64);
+        }
+        num_inliers_by_name.insert(cam_name.clone(), num_inliers[i]);
+        info!(
+            "camera {cam_name}: {} inlier detections, mean reprojection distance {:.3} pixels",
+            num_inliers[i],
+            mean_reproj_dist.get(cam_name).copied().unwrap_or(f64::NAN)
+        );
+    }
+
+    Ok(SelfCalResult {
+        cam_system,
+        mean_reproj_dist,
+        num_inliers: num_inliers_by_name,
+        num_points: recon.points.iter().flatten().count(),
+    })
+}
@@ -0,0 +1,420 @@
+//! Incremental reconstruction of the cameras and of the positions of the
+//! calibration target.
+//!
+//! The two cameras sharing the most points are reconstructed from their
+//! essential matrix. The other cameras are then added one at a time, each by
+//! resection from the points already reconstructed, with bundle adjustment
+//! after each step. Finally, outliers are removed and, if requested, the
+//! intrinsic parameters are estimated.
+
+use eyre::{bail, eyre, Result};
+use nalgebra::{Point2, Point3, Rotation3, Vector3};
+use tracing::{debug, info};
+
+use crate::{
+    bundle_adjust::{bundle_adjust, CamParams, FreeIntrinsics, Observation},
+    geometry::{
+        decompose_camera, decompose_essential, depth, pose_matrix, pose_of_normalized,
+        ransac_essential, ransac_resect, triangulate, Rng,
+    },
+};
+
+/// The images of one position of the calibration target as camera indices and
+/// pixel coordinates.
+pub(crate) type Track = Vec<(usize, Point2<f64>)>;
+
+/// The number of points a camera must share with the others to be
+/// reconstructed.
+pub(crate) const MIN_POINTS: usize = 10;
+
+const RANSAC_ITERATIONS: usize = 1000;
+const BUNDLE_ADJUST_ITERATIONS: usize = 100;
+const MAX_OUTLIER_ROUNDS: usize = 10;
+
+/// The factor by which the outlier threshold is enlarged until the intrinsic
+/// parameters are estimated.
+const UNKNOWN_INTRINSICS_THRESHOLD_FACTOR: f64 = 4.0;
+
+pub(crate) struct Reconstruction {
+    pub(crate) cams: Vec<CamParams>,
+    /// The position of each track, if it was reconstructed.
+    pub(crate) points: Vec<Option<Vector3<f64>>>,
+    /// Whether each observation of each track is an inlier.
+    pub(crate) inliers: Vec<Vec<bool>>,
+}
+
+/// Reconstruct the cameras and the tracks.
+///
+/// `cams` give the initial intrinsic parameters and which of them are
+/// estimated. Observations with a reprojection distance larger than
+/// `threshold` pixels are outliers.
+pub(crate) fn reconstruct(
+    mut cams: Vec<CamParams>,
+    cam_names: &[String],
+    tracks: &[Track],
+    threshold: f64,
+) -> Result<Reconstruction> {
+    let num_cams = cams.len();
+    let mut rng = Rng::new(1);
+
+    // Distortion is only estimated once all cameras are reconstructed, and the
+    // other intrinsic parameters once three cameras are, as two views do not
+    // constrain them.
+    let free_intrinsics: Vec<FreeIntrinsics> = cams.iter().map(|cam| cam.free_intrinsics).collect();
+    for cam in cams.iter_mut() {
+        cam.free_intrinsics = FreeIntrinsics::None;
+    }
+    let final_threshold = threshold;
+    let threshold = if free_intrinsics
+        .iter()
+        .any(|free| *free != FreeIntrinsics::None)
+    {
+        threshold * UNKNOWN_INTRINSICS_THRESHOLD_FACTOR
+    } else {
+        threshold
+    };
+
+    // Start with the pair of cameras sharing the most points.
+    let mut shared = vec![vec![0usize; num_cams]; num_cams];
+    for track in tracks.iter() {
+        for (i, (cam_a, _)) in track.iter().enumerate() {
+            for (cam_b, _) in track[i + 1..].iter() {
+                shared[*cam_a][*cam_b] += 1;
+                shared[*cam_b][*cam_a] += 1;
+            }
+        }
+    }
+    let (a, b) = (0..num_cams)
+        .flat_map(|a| (a + 1..num_cams).map(move |b| (a, b)))
+        .max_by_key(|&(a, b)| shared[a][b])
+        .ok_or_else(|| eyre!("at least two cameras are required"))?;
+    if shared[a][b] < MIN_POINTS {
+        bail!("no pair of cameras shares at least {MIN_POINTS} points");
+    }
+    info!(
+        "starting with cameras {} and {} sharing {} points",
+        cam_names[a], cam_names[b], shared[a][b]
+    );
+
+    let pairs: Vec<_> = tracks
+        .iter()
+        .filter_map(|track| {
+            let pixel_a = track.iter().find(|(cam, _)| *cam == a)?.1;
+            let pixel_b = track.iter().find(|(cam, _)| *cam == b)?.1;
+            Some((cams[a].normalize(&pixel_a), cams[b].normalize(&pixel_b)))
+        })
+        .collect();
+    let focal = (cams[a].fx + cams[a].fy + cams[b].fx + cams[b].fy) / 4.0;
+    let (e, pair_inliers) =
+        ransac_essential(&pairs, threshold / focal, RANSAC_ITERATIONS, &mut rng).ok_or_else(
+            || {
+                eyre!(
+                    "could not estimate the relative pose of cameras {} and {}",
+                    cam_names[a],
+                    cam_names[b]
+                )
+            },
+        )?;
+
+    // Of the four poses of the essential matrix, use the one with the most
+    // points in front of both cameras.
+    let p_a = pose_matrix(&Rotation3::identity(), &Vector3::zeros());
+    let (r, t) = decompose_essential(&e)
+        .ok_or_else(|| eyre!("could not decompose the essential matrix"))?
+        .into_iter()
+        .max_by_key(|(r, t)| {
+            let p_b = pose_matrix(r, t);
+            pairs
+                .iter()
+                .zip(pair_inliers.iter())
+                .filter(|(_, is_inlier)| **is_inlier)
+                .filter_map(|((x_a, x_b), _)| triangulate(&[(p_a, *x_a), (p_b, *x_b)]))
+                .filter(|x| depth(&p_a, x) > 0.0 && depth(&p_b, x) > 0.0)
+                .count()
+        })
+        .unwrap();
+    cams[a].set_pose(&Rotation3::identity(), &Vector3::zeros());
+    cams[b].set_pose(&r, &t);
+    let mut registered = vec![false; num_cams];
+    registered[a] = true;
+    registered[b] = true;
+
+    let mut points;
+    loop {
+        points = tracks
+            .iter()
+            .map(|track| triangulate_track(&cams, &registered, track, threshold))
+            .collect::<Vec<_>>();
+        let inliers = classify(&cams, &registered, &points, tracks, threshold);
+        refine(&mut cams, &mut points, tracks, &inliers);
+
+        // Add the camera sharing the most reconstructed points.
+        let next = (0..num_cams)
+            .filter(|cam| !registered[*cam])
+            .map(|cam| {
+                let correspondences: Vec<_> = tracks
+                    .iter()
+                    .zip(points.iter())
+                    .filter_map(|(track, point)| {
+                        let point = (*point)?;
+                        let pixel = track.iter().find(|(c, _)| *c == cam)?.1;
+                        Some((Point3::from(point), pixel))
+                    })
+                    .collect();
+                (cam, correspondences)
+            })
+            .max_by_key(|(_, correspondences)| correspondences.len());
+        let Some((cam, correspondences)) = next else {
+            break;
+        };
+        let not_registered = || {
+            (0..num_cams)
+                .filter(|cam| !registered[*cam])
+                .map(|cam| cam_names[cam].as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        if correspondences.len() < MIN_POINTS {
+            bail!(
+                "cameras {} share fewer than {MIN_POINTS} points with the other cameras",
+                not_registered()
+            );
+        }
+        let pose_error = || eyre!("could not estimate the pose of camera {}", cam_names[cam]);
+        // Estimate the intrinsic parameters too if they are unknown.
+        let unknown_intrinsics = free_intrinsics[cam] != FreeIntrinsics::None;
+        let (correspondences, cam_threshold) = if unknown_intrinsics {
+            (correspondences, threshold)
+        } else {
+            let normalized: Vec<_> = correspondences
+                .iter()
+                .map(|(x, pixel)| (*x, cams[cam].normalize(pixel)))
+                .collect();
+            (
+                normalized,
+                threshold / ((cams[cam].fx + cams[cam].fy) / 2.0),
+            )
+        };
+        let (p, inliers) =
+            ransac_resect(&correspondences, cam_threshold, RANSAC_ITERATIONS, &mut rng)
+                .filter(|(_, inliers)| inliers.iter().filter(|x| **x).count() >= MIN_POINTS)
+                .ok_or_else(pose_error)?;
+        info!(
+            "adding camera {} with {} of {} shared points",
+            cam_names[cam],
+            inliers.iter().filter(|x| **x).count(),
+            correspondences.len()
+        );
+        if unknown_intrinsics {
+            let (k, r, t) = decompose_camera(&p).ok_or_else(pose_error)?;
+            let cam = &mut cams[cam];
+            let focal = (k[(0, 0)] + k[(1, 1)]) / 2.0;
+            (cam.fx, cam.fy, cam.cx, cam.cy) = (focal, focal, k[(0, 2)], k[(1, 2)]);
+            cam.set_pose(&r, &t);
+        } else {
+            let (r, t) = pose_of_normalized(&p).ok_or_else(pose_error)?;
+            cams[cam].set_pose(&r, &t);
+        }
+        registered[cam] = true;
+        if registered.iter().filter(|x| **x).count() >= 3 {
+            for (cam, free) in cams.iter_mut().zip(free_intrinsics.iter()) {
+                if *free != FreeIntrinsics::None {
+                    cam.free_intrinsics = FreeIntrinsics::Linear;
+                }
+            }
+        }
+    }
+
+    for (cam, free) in cams.iter_mut().zip(free_intrinsics) {
+        cam.free_intrinsics = free;
+    }
+    if threshold != final_threshold {
+        remove_outliers(&mut cams, &mut points, tracks, threshold);
+    }
+    let inliers = remove_outliers(&mut cams, &mut points, tracks, final_threshold);
+    normalize_gauge(&mut cams, &mut points);
+
+    Ok(Reconstruction {
+        cams,
+        points,
+        inliers,
+    })
+}
+
+/// Triangulate a track from the observations in the reconstructed cameras.
+///
+/// If some observations do not fit, the largest set of observations fitting
+/// the triangulation from a pair of them is used.
+fn triangulate_track(
+    cams: &[CamParams],
+    registered: &[bool],
+    track: &Track,
+    threshold: f64,
+) -> Option<Vector3<f64>> {
+    let views: Vec<_> = track
+        .iter()
+        .filter(|(cam, _)| registered[*cam])
+        .map(|(cam, pixel)| (&cams[*cam], pixel))
+        .collect();
+    let triangulate_views = |subset: &[usize]| {
+        let views: Vec<_> = subset
+            .iter()
+            .map(|i| {
+                (
+                    views[*i].0.pose_matrix(),
+                    views[*i].0.normalize(views[*i].1),
+                )
+            })
+            .collect();
+        triangulate(&views).map(|x| x.coords)
+    };
+    let fitting = |x: &Vector3<f64>| -> Vec<usize> {
+        (0..views.len())
+            .filter(|i| {
+                let (cam, pixel) = views[*i];
+                cam.depth(x) > 0.0 && (cam.project(x) - pixel.coords).norm() < threshold
+            })
+            .collect()
+    };
+
+    let all: Vec<usize> = (0..views.len()).collect();
+    let x = triangulate_views(&all)?;
+    let mut best = fitting(&x);
+    if best.len() == views.len() {
+        return Some(x);
+    }
+    for i in 0..views.len() {
+        for j in i + 1..views.len() {
+            if let Some(x) = triangulate_views(&[i, j]) {
+                let candidate = fitting(&x);
+                if candidate.len() > best.len() {
+                    best = candidate;
+                }
+            }
+        }
+    }
+    if best.len() < 2 {
+        return None;
+    }
+    let x = triangulate_views(&best)?;
+    (fitting(&x).len() >= 2).then_some(x)
+}
+
+/// Which observations of each track are inliers.
+fn classify(
+    cams: &[CamParams],
+    registered: &[bool],
+    points: &[Option<Vector3<f64>>],
+    tracks: &[Track],
+    threshold: f64,
+) -> Vec<Vec<bool>> {
+    tracks
+        .iter()
+        .zip(points.iter())
+        .map(|(track, point)| {
+            track
+                .iter()
+                .map(|(cam, pixel)| {
+                    let Some(point) = point else {
+                        return false;
+                    };
+                    let cam_params = &cams[*cam];
+                    registered[*cam]
+                        && cam_params.depth(point) > 0.0
+                        && (cam_params.project(point) - pixel.coords).norm() < threshold
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Bundle adjust the inlier observations of points seen by at least two
+/// cameras.
+fn refine(
+    cams: &mut [CamParams],
+    points: &mut [Option<Vector3<f64>>],
+    tracks: &[Track],
+    inliers: &[Vec<bool>],
+) -> f64 {
+    let mut index = Vec::new();
+    let mut adjusted = Vec::new();
+    let mut observations = Vec::new();
+    for (i, (track, track_inliers)) in tracks.iter().zip(inliers.iter()).enumerate() {
+        if track_inliers.iter().filter(|x| **x).count() < 2 {
+            points[i] = None;
+            continue;
+        }
+        let Some(point) = points[i] else {
+            continue;
+        };
+        for ((cam, pixel), _) in track
+            .iter()
+            .zip(track_inliers.iter())
+            .filter(|(_, is_inlier)| **is_inlier)
+        {
+            observations.push(Observation {
+                cam: *cam,
+                point: adjusted.len(),
+                pixel: pixel.coords,
+            });
+        }
+        index.push(i);
+        adjusted.push(point);
+    }
+    let rms = bundle_adjust(cams, &mut adjusted, &observations, BUNDLE_ADJUST_ITERATIONS);
+    for (i, point) in index.into_iter().zip(adjusted) {
+        points[i] = Some(point);
+    }
+    debug!(
+        "bundle adjustment of {} observations: RMS reprojection distance {rms:.3} pixels",
+        observations.len()
+    );
+    rms
+}
+
+/// Alternate bundle adjustment and outlier classification until the inliers
+/// do not change.
+fn remove_outliers(
+    cams: &mut [CamParams],
+    points: &mut [Option<Vector3<f64>>],
+    tracks: &[Track],
+    threshold: f64,
+) -> Vec<Vec<bool>> {
+    let registered = vec![true; cams.len()];
+    let mut inliers = classify(cams, &registered, points, tracks, threshold);
+    for _ in 0..MAX_OUTLIER_ROUNDS {
+        let rms = refine(cams, points, tracks, &inliers);
+        let new_inliers = classify(cams, &registered, points, tracks, threshold);
+        let num_inliers = new_inliers.iter().flatten().filter(|x| **x).count();
+        info!("{num_inliers} inlier observations, RMS reprojection distance {rms:.3} pixels");
+        if new_inliers == inliers {
+            break;
+        }
+        inliers = new_inliers;
+    }
+    inliers
+}
+
+/// Move the centroid of the points to the origin and scale the root mean
+/// square distance of the points from it to one.
+fn normalize_gauge(cams: &mut [CamParams], points: &mut [Option<Vector3<f64>>]) {
+    let reconstructed: Vec<_> = points.iter().flatten().collect();
+    if reconstructed.is_empty() {
+        return;
+    }
+    let n = reconstructed.len() as f64;
+    let centroid = reconstructed.iter().copied().sum::<Vector3<f64>>() / n;
+    let rms = (reconstructed
+        .iter()
+        .map(|x| (*x - centroid).norm_squared())
+        .sum::<f64>()
+        / n)
+        .sqrt();
+    let scale = if rms > 0.0 { 1.0 / rms } else { 1.0 };
+    for cam in cams.iter_mut() {
+        cam.center = (cam.center - centroid) * scale;
+    }
+    for point in points.iter_mut().flatten() {
+        *point = (*point - centroid) * scale;
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+
+use nalgebra::{Matrix3, Point2, Point3, Rotation3, UnitQuaternion, Vector3, Vector5};
+use opencv_ros_camera::{Distortion, NamedIntrinsicParameters, RosOpenCvIntrinsics};
+
+use braid_self_cal::{self_calibrate, CalibrationData, CorrespondingPoints, SelfCalOptions};
+
+const WIDTH: usize = 1280;
+const HEIGHT: usize = 1024;
+
+/// A camera at `center` looking at `target` with the image y axis pointing
+/// down.
+fn look_at(center: Point3<f64>, target: Point3<f64>) -> UnitQuaternion<f64> {
+    let z = (target - center).normalize();
+    let x = z.cross(&-Vector3::z()).normalize();
+    let y = z.cross(&x);
+    let rmat = Matrix3::from_rows(&[x.transpose(), y.transpose(), z.transpose()]);
+    UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(rmat))
+}
+
+/// Cameras in a ring around the origin.
+fn cameras(distortion: bool) -> BTreeMap<String, mvg::Camera<f64>> {
+    (0..5)
+        .map(|i| {
+            let angle = i as f64 * 2.0 * std::f64::consts::PI / 5.0;
+            let center = Point3::new(1.5 * angle.cos(), 1.5 * angle.sin(), 0.5 + 0.2 * i as f64);
+            let extrinsics = cam_geom::ExtrinsicParameters::from_rotation_and_camcenter(
+                look_at(center, Point3::new(0.0, 0.0, 0.1)),
+                center,
+            );
+            let k1 = if distortion { -0.05 } else { 0.0 };
+            let focal = 1000.0 + 50.0 * i as f64;
+            let intrinsics = RosOpenCvIntrinsics::from_params_with_distortion(
+                focal,
+                0.0,
+                focal,
+                640.0,
+                512.0,
+                Distortion::from_opencv_vec(Vector5::new(k1, 0.0, 0.0, 0.0, 0.0)),
+            );
+            let cam = mvg::Camera::new(WIDTH, HEIGHT, extrinsics, intrinsics).unwrap();
+            (format!("cam{i}"), cam)
+        })
+        .collect()
+}
+
+/// Images of random points with noise, missed detections and outliers.
+fn calibration_data(
+    cams: &BTreeMap<String, mvg::Camera<f64>>,
+    num_points: usize,
+) -> CalibrationData {
+    // A linear congruential generator keeps the test deterministic.
+    let mut state: u64 = 12345;
+    let mut uniform = move || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 11) as f64 / (1u64 << 53) as f64
+    };
+
+    let mut points = Vec::new();
+    for frame in 0..num_points {
+        let world = mvg::PointWorldFrame {
+            coords: Point3::new(
+                0.8 * (uniform() - 0.5),
+                0.8 * (uniform() - 0.5),
+                0.3 * uniform(),
+            ),
+        };
+        let mut detections = BTreeMap::new();
+        for (name, cam) in cams.iter() {
+            let pixel = cam.project_3d_to_distorted_pixel(&world).coords;
+            let missed = uniform() < 0.2;
+            let outlier = uniform() < 0.02;
+            let noise = Point2::new(uniform() - 0.5, uniform() - 0.5) * 0.5;
+            let pixel = if outlier {
+                Point2::new(uniform() * WIDTH as f64, uniform() * HEIGHT as f64)
+            } else {
+                pixel + noise.coords
+            };
+            if missed || pixel.x < 0.0 || pixel.y < 0.0 {
+                continue;
+            }
+            if pixel.x >= WIDTH as f64 || pixel.y >= HEIGHT as f64 {
+                continue;
+            }
+            detections.insert(name.clone(), [pixel.x, pixel.y]);
+        }
+        points.push(CorrespondingPoints {
+            frame: frame as i64,
+            points: detections,
+        });
+    }
+    CalibrationData {
+        image_sizes: cams
+            .iter()
+            .map(|(name, cam)| (name.clone(), (cam.width(), cam.height())))
+            .collect(),
+        points,
+    }
+}
+
+/// Check the calibration against the true cameras, which are only determined
+/// up to a similarity transform.
+fn check(
+    result: &braid_self_cal::SelfCalResult,
+    truth: &BTreeMap<String, mvg::Camera<f64>>,
+    tolerance: f64,
+) {
+    for (name, dist) in result.mean_reproj_dist.iter() {
+        assert!(
+            *dist < 0.5,
+            "camera {name}: mean reprojection distance {dist}"
+        );
+    }
+    assert_eq!(result.cam_system.cams_by_name().len(), truth.len());
+
+    let centers = |cams: &BTreeMap<String, mvg::Camera<f64>>| -> Vec<Point3<f64>> {
+        cams.values()
+            .map(|cam| *cam.extrinsics().camcenter())
+            .collect()
+    };
+    let expected = centers(truth);
+    let actual = centers(result.cam_system.cams_by_name());
+    let expected_scale = (expected[0] - expected[1]).norm();
+    let actual_scale = (actual[0] - actual[1]).norm();
+    for i in 0..expected.len() {
+        for j in i + 1..expected.len() {
+            let expected_ratio = (expected[i] - expected[j]).norm() / expected_scale;
+            let actual_ratio = (actual[i] - actual[j]).norm() / actual_scale;
+            assert!(
+                (expected_ratio - actual_ratio).abs() < tolerance,
+                "cameras {i} and {j}: distance ratio {actual_ratio}, expected {expected_ratio}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_known_intrinsics() {
+    let truth = cameras(true);
+    let data = calibration_data(&truth, 500);
+    let intrinsics: BTreeMap<String, NamedIntrinsicParameters<f64>> = truth
+        .iter()
+        .map(|(name, cam)| {
+            let named = NamedIntrinsicParameters {
+                name: name.clone(),
+                width: cam.width(),
+                height: cam.height(),
+                intrinsics: cam.intrinsics().clone(),
+            };
+            (name.clone(), named)
+        })
+        .collect();
+
+    let result = self_calibrate(&data, &intrinsics, &SelfCalOptions::default()).unwrap();
+    check(&result, &truth, 0.01);
+    assert!(result.num_points > 450);
+    // Most detections are inliers.
+    let num_inliers: usize = result.num_inliers.values().sum();
+    let num_detections: usize = data.points.iter().map(|frame| frame.points.len()).sum();
+    assert!(num_inliers as f64 > 0.95 * num_detections as f64);
+
+    let xml = result.to_flydra_xml().unwrap();
+    let reloaded =
+        flydra_mvg::FlydraMultiCameraSystem::<f64>::from_flydra_xml(xml.as_slice()).unwrap();
+    assert_eq!(reloaded.system().cams_by_name().len(), truth.len());
+}
+
+#[test]
+fn test_unknown_intrinsics() {
+    let truth = cameras(false);
+    let data = calibration_data(&truth, 300);
+    let result = self_calibrate(&data, &BTreeMap::new(), &SelfCalOptions::default()).unwrap();
+    check(&result, &truth, 0.05);
+    for (name, cam) in result.cam_system.cams_by_name() {
+        let focal = cam.intrinsics().fx();
+        let expected = truth[name].intrinsics().fx();
+        assert!(
+            (focal - expected).abs() < 0.05 * expected,
+            "camera {name}: focal length {focal}, expected {expected}"
+        );
+    }
+}
@@ -11,9 +11,10 @@ use wasm_bindgen_futures::JsFuture;
 use web_sys::{EventSource, MessageEvent};
 
 use flydra_types::{
-    BraidHttpApiCallback, BraidHttpApiSharedState, BuiServerInfo, CamInfo, TriggerType,
+    BraidHttpApiCallback, BraidHttpApiSharedState, BuiServerInfo, CamHeartbeat, CamInfo,
+    TriggerType,
 };
-use rust_cam_bui_types::RecordingPath;
+use rust_cam_bui_types::{DiskSpaceLevel, RecordingPath};
 
 use yew::{html, Component, Context, Event, Html};
 use yew_tincture::components::{Button, CheckboxLabel, TypedInput, TypedInputStorage};
@@ -314,9 +315,12 @@ impl Model {
                     {fake_sync_warning}
                     <div>
                         {record_widget}
+                        {view_disk_space(&value)}
+                        {view_self_tests(&value)}
                         {view_clock_model(&value)}
+                        {view_last_resync(&value)}
                         {view_calibration(&value.calibration_filename)}
-                        {view_cam_list(&value.connected_cameras)}
+                        {view_cam_list(&value.connected_cameras, &value.camera_health)}
                         {view_model_server_link(&value.model_server_addr)}
                     </div>
                 </div>
@@ -350,6 +354,70 @@ impl Model {
     }
 }
 
+fn view_self_tests(shared: &BraidHttpApiSharedState) -> Html {
+    if shared.self_tests.is_empty() {
+        return html! {};
+    }
+    let all_passed = shared
+        .self_tests
+        .values()
+        .all(|report| report.blocks_recording().is_none());
+    let warning = if all_passed {
+        html! {}
+    } else {
+        html! {
+            <p>{"⚠ Saving is disabled until all camera self-tests passed. ⚠"}</p>
+        }
+    };
+    html! {
+        <div>
+            {warning}
+            <ul>
+                { for shared.self_tests.iter().map(|(name, report)| {
+                    let failures: Vec<String> = report
+                        .checks
+                        .iter()
+                        .flatten()
+                        .filter(|check| !check.passed)
+                        .map(|check| format!("{}: {}", check.name, check.message))
+                        .collect();
+                    html! {
+                        <li>{format!("{}: {} {}", name, report.summary(), failures.join("; "))}</li>
+                    }
+                }) }
+            </ul>
+        </div>
+    }
+}
+
+fn view_disk_space(shared: &BraidHttpApiSharedState) -> Html {
+    let error = if let Some(ref msg) = shared.disk_space_error {
+        html! {
+            <p>{format!("⚠ {} ⚠", msg)}</p>
+        }
+    } else {
+        html! {}
+    };
+    let status = if let Some(ref status) = shared.disk_space {
+        let warning = match status.level {
+            DiskSpaceLevel::Ok => "",
+            DiskSpaceLevel::Low => " Warning: disk space is low.",
+            DiskSpaceLevel::Critical => " Saving will be stopped: disk almost full.",
+        };
+        html! {
+            <p>{format!("{}.{}", status.summary(), warning)}</p>
+        }
+    } else {
+        html! {}
+    };
+    html! {
+        <div>
+            {error}
+            {status}
+        </div>
+    }
+}
+
 fn view_clock_model(shared: &BraidHttpApiSharedState) -> Html {
     if shared.needs_clock_model {
         if let Some(ref cm) = shared.clock_model {
@@ -374,6 +442,20 @@ fn view_clock_model(shared: &BraidHttpApiSharedState) -> Html {
     }
 }
 
+fn view_last_resync(shared: &BraidHttpApiSharedState) -> Html {
+    if let Some(ref resync) = shared.last_resync {
+        html! {
+            <div>
+                <p>
+                    {format!("Cameras last resynchronized at {} because {}.", resync.started, resync.reason)}
+                </p>
+            </div>
+        }
+    } else {
+        html! {}
+    }
+}
+
 fn view_calibration(calibration_filename: &Option<String>) -> Html {
     if let Some(ref fname) = calibration_filename {
         html! {
@@ -394,7 +476,10 @@ fn view_calibration(calibration_filename: &Option<String>) -> Html {
     }
 }
 
-fn view_cam_list(cams: &[CamInfo]) -> Html {
+fn view_cam_list(
+    cams: &[CamInfo],
+    health: &std::collections::BTreeMap<String, CamHeartbeat>,
+) -> Html {
     let n_cams_msg = if cams.len() == 1 {
         "1 camera:".to_string()
     } else {
@@ -415,6 +500,10 @@ fn view_cam_list(cams: &[CamInfo]) -> Html {
             };
             let state = format!("{:?}", cci.state);
             let stats = format!("{:?}", cci.recent_stats);
+            let health = match health.get(cci.name.as_str()) {
+                Some(heartbeat) => heartbeat.summary(),
+                None => "no heartbeat".to_string(),
+            };
             html! {
                 <li>
                     <a href={cam_url}>{cci.name.as_str()}</a>
@@ -422,6 +511,8 @@ fn view_cam_list(cams: &[CamInfo]) -> Html {
                     {state}
                     {" "}
                     {stats}
+                    <br/>
+                    {health}
                 </li>
             }
         })
@@ -0,0 +1,209 @@
+//! JSON API for experiment control.
+//!
+//! This allows scripts to do what is otherwise done in the browser UI: start
+//! and stop saving the `.braidz` file, query the connected cameras and their
+//! synchronization, add experiment metadata and switch the configuration
+//! profile of the cameras, e.g. for each phase of an experiment. The Strand
+//! Camera instances found on the network and the latency of the processing
+//! pipeline are also available. Changes of the state are also pushed to
+//! clients of the `/braid-events` event stream.
+//!
+//! All routes are below [flydra_types::braid_http::API_PATH]. By default,
+//! they are protected in the same way as the browser UI. With
+//! `api_jwt_auth` in the mainbrain configuration, a JSON Web Token is
+//! required instead (see [require_jwt]).
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::IntoResponse,
+};
+use http::StatusCode;
+use serde::Deserialize;
+use tracing::{debug, error};
+
+use event_stream_types::TolerantJson;
+use flydra_types::{
+    BraidApiCamera, BraidApiExperimentInfo, BraidApiProfile, BraidApiRecording, BraidApiStatus,
+    LatencyStageStats, TextlogRow,
+};
+
+use crate::mainbrain::{self_test_blocks_saving, toggle_saving_csv_tables, BraidAppState};
+
+fn current_status(app_state: &BraidAppState) -> BraidApiStatus {
+    let tracker = app_state.shared_store.read();
+    let shared = tracker.as_ref();
+    BraidApiStatus {
+        saving_braidz: shared.csv_tables_dirname.as_ref().map(|p| p.path()),
+        saving_mp4: shared.fake_mp4_recording_path.is_some(),
+        all_expected_cameras_are_synced: shared.all_expected_cameras_are_synced,
+        disk_space_error: shared.disk_space_error.clone(),
+        cameras: shared
+            .connected_cameras
+            .iter()
+            .map(|cam| BraidApiCamera {
+                health: shared.camera_health.get(cam.name.as_str()).cloned(),
+                ..BraidApiCamera::from(cam)
+            })
+            .collect(),
+    }
+}
+
+/// `GET /api/v1/status`
+pub(crate) async fn status_handler(
+    State(app_state): State<BraidAppState>,
+) -> axum::Json<BraidApiStatus> {
+    axum::Json(current_status(&app_state))
+}
+
+/// `GET /api/v1/latency`
+///
+/// Returns the latency percentiles of each stage of the processing pipeline
+/// over the last minute.
+pub(crate) async fn latency_handler(
+    State(app_state): State<BraidAppState>,
+) -> axum::Json<Vec<LatencyStageStats>> {
+    axum::Json(app_state.latency_budget.stats())
+}
+
+/// `GET /api/v1/discovered-cameras`
+///
+/// Returns the Strand Camera instances advertised on the local network.
+pub(crate) async fn discovered_cameras_handler(
+    State(app_state): State<BraidAppState>,
+) -> axum::Json<Vec<strand_cam_discovery::CameraNode>> {
+    axum::Json(
+        app_state
+            .discovered_cameras
+            .read()
+            .values()
+            .cloned()
+            .collect(),
+    )
+}
+
+/// `POST /api/v1/recording`
+///
+/// Returns the status after the change.
+pub(crate) async fn recording_handler(
+    State(app_state): State<BraidAppState>,
+    TolerantJson(payload): TolerantJson<BraidApiRecording>,
+) -> impl IntoResponse {
+    debug!("API recording request {payload:?}");
+    let is_saving = app_state
+        .shared_store
+        .read()
+        .as_ref()
+        .csv_tables_dirname
+        .is_some();
+    if payload.save_braidz && is_saving {
+        return Err((StatusCode::CONFLICT, "Already saving."));
+    }
+    if payload.save_braidz {
+        let tracker = app_state.shared_store.read();
+        if let Some(reason) = self_test_blocks_saving((*tracker).as_ref()) {
+            error!("Not saving because {reason}.");
+            return Err((StatusCode::CONFLICT, "Camera self-test has not passed."));
+        }
+    }
+    if payload.save_braidz && app_state.braidz_write_tx_weak.upgrade().is_none() {
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "Data writing thread lost."));
+    }
+    toggle_saving_csv_tables(
+        payload.save_braidz,
+        app_state.expected_framerate_arc.clone(),
+        app_state.output_base_dirname.clone(),
+        app_state.braidz_write_tx_weak.clone(),
+        app_state.per_cam_data_arc.clone(),
+        app_state.shared_store.clone(),
+        app_state.clock.clone(),
+    )
+    .await;
+    Ok(axum::Json(current_status(&app_state)))
+}
+
+/// `POST /api/v1/experiment-info`
+pub(crate) async fn experiment_info_handler(
+    State(app_state): State<BraidAppState>,
+    TolerantJson(payload): TolerantJson<BraidApiExperimentInfo>,
+) -> impl IntoResponse {
+    debug!("API experiment info {payload:?}");
+    let Some(braidz_write_tx) = app_state.braidz_write_tx_weak.upgrade() else {
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "Data writing thread lost."));
+    };
+    if let Some(uuid) = payload.uuid {
+        braidz_write_tx
+            .send(flydra2::SaveToDiskMsg::SetExperimentUuid(uuid))
+            .await
+            .map_err(|_| (StatusCode::SERVICE_UNAVAILABLE, "Data writing thread lost."))?;
+    }
+    if let Some(message) = payload.notes {
+        let now = datetime_conversion::datetime_to_f64(&app_state.clock.now());
+        let row = TextlogRow {
+            mainbrain_timestamp: now,
+            cam_id: "mainbrain".to_string(),
+            host_timestamp: now,
+            message,
+        };
+        braidz_write_tx
+            .send(flydra2::SaveToDiskMsg::Textlog(row))
+            .await
+            .map_err(|_| (StatusCode::SERVICE_UNAVAILABLE, "Data writing thread lost."))?;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `POST /api/v1/profile`
+///
+/// Each camera applies the profile from its own profile directory. Cameras
+/// without this profile log an error and keep their settings.
+pub(crate) async fn profile_handler(
+    State(app_state): State<BraidAppState>,
+    TolerantJson(payload): TolerantJson<BraidApiProfile>,
+) -> Result<StatusCode, (StatusCode, &'static str)> {
+    debug!("API profile request {payload:?}");
+    app_state
+        .strand_cam_http_session_handler
+        .apply_profile_all(&payload.name)
+        .await
+        .map_err(|_e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "apply_profile_all failed",
+            )
+        })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The claims checked in the JSON Web Token. Only the expiration time is
+/// required.
+#[derive(Deserialize)]
+struct Claims {
+    #[allow(dead_code)]
+    exp: u64,
+}
+
+/// Middleware requiring an `Authorization: Bearer <token>` header with a JSON
+/// Web Token signed (HS256) with `key`.
+pub(crate) async fn require_jwt(
+    State(key): State<Arc<jsonwebtoken::DecodingKey>>,
+    req: Request,
+    next: Next,
+) -> impl IntoResponse {
+    let token = req
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let Some(token) = token else {
+        return Err((StatusCode::UNAUTHORIZED, "Bearer token required"));
+    };
+    let validation = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256);
+    if let Err(err) = jsonwebtoken::decode::<Claims>(token, &key, &validation) {
+        error!("Invalid API token: {err}");
+        return Err((StatusCode::UNAUTHORIZED, "Request is not authorized"));
+    }
+    Ok(next.run(req).await)
+}
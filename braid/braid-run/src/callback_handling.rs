@@ -1,8 +1,8 @@
 use axum::response::IntoResponse;
-use tracing::debug;
+use tracing::{debug, error, warn};
 
 use event_stream_types::TolerantJson;
-use flydra_types::{BraidHttpApiCallback, PerCamSaveData};
+use flydra_types::{BraidHttpApiCallback, PerCamSaveData, RawCamName};
 use http::StatusCode;
 use rust_cam_bui_types::RecordingPath;
 
@@ -19,6 +19,19 @@ fn start_saving_mp4s_all_cams(app_state: &BraidAppState, start_saving: bool) {
     });
 }
 
+/// Return true if `settings` differ from the `point_detection_config` of the
+/// camera in the Braid configuration.
+fn is_feature_detect_overridden(
+    app_state: &BraidAppState,
+    raw_cam_name: &RawCamName,
+    settings: &flydra_types::UpdateFeatureDetectSettings,
+) -> bool {
+    match app_state.camera_configs.get(raw_cam_name) {
+        Some(cfg) => cfg.point_detection_config != settings.current_feature_detect_settings,
+        None => false,
+    }
+}
+
 pub(crate) async fn callback_handler(
     axum::extract::State(app_state): axum::extract::State<crate::mainbrain::BraidAppState>,
     session_key: axum_token_auth::SessionKey,
@@ -43,14 +56,38 @@ pub(crate) async fn callback_handler(
                     )
                     .map_err(|msg| (StatusCode::BAD_REQUEST, msg))?;
 
+                // Older versions of Strand Camera do not report their settings
+                // and use those from the Braid configuration.
+                let feature_detect_settings = cam_info.feature_detect_settings.or_else(|| {
+                    app_state
+                        .camera_configs
+                        .get(&cam_info.raw_cam_name)
+                        .map(|cfg| flydra_types::UpdateFeatureDetectSettings {
+                            current_feature_detect_settings: cfg.point_detection_config.clone(),
+                        })
+                });
+                let feature_detect_settings_overridden =
+                    feature_detect_settings.as_ref().is_some_and(|settings| {
+                        is_feature_detect_overridden(&app_state, &cam_info.raw_cam_name, settings)
+                    });
+                if feature_detect_settings_overridden {
+                    warn!(
+                        "Camera \"{}\" uses a local point detection configuration which \
+                        differs from the Braid configuration.",
+                        cam_info.raw_cam_name.as_str()
+                    );
+                }
+
                 let mut current_cam_data = app_state.per_cam_data_arc.write();
                 if current_cam_data
                     .insert(
                         cam_info.raw_cam_name.clone(),
                         PerCamSaveData {
                             cam_settings_data: Some(cam_settings_data),
-                            feature_detect_settings: None,
+                            feature_detect_settings,
+                            feature_detect_settings_overridden,
                             current_image_png: cam_info.current_image_png,
+                            provenance: cam_info.provenance,
                         },
                     )
                     .is_some()
@@ -78,14 +115,33 @@ pub(crate) async fn callback_handler(
                     .cam_settings_data = Some(cam_settings.inner);
             }
             UpdateFeatureDetectSettings(feature_detect_settings) => {
+                let raw_cam_name = &feature_detect_settings.raw_cam_name;
+                let overridden = is_feature_detect_overridden(
+                    &app_state,
+                    raw_cam_name,
+                    &feature_detect_settings.inner,
+                );
                 let mut current_cam_data = app_state.per_cam_data_arc.write();
-                current_cam_data
-                    .get_mut(&feature_detect_settings.raw_cam_name)
-                    .unwrap()
-                    .feature_detect_settings = Some(feature_detect_settings.inner);
+                let cam_data = current_cam_data.get_mut(raw_cam_name).unwrap();
+                if overridden && !cam_data.feature_detect_settings_overridden {
+                    warn!(
+                        "Point detection configuration of camera \"{}\" was changed and \
+                        differs from the Braid configuration.",
+                        raw_cam_name.as_str()
+                    );
+                }
+                cam_data.feature_detect_settings = Some(feature_detect_settings.inner);
+                cam_data.feature_detect_settings_overridden = overridden;
             }
             DoRecordCsvTables(value) => {
                 debug!("got DoRecordCsvTables({})", value);
+                if value {
+                    let tracker = app_state.shared_store.read();
+                    if let Some(reason) = self_test_blocks_saving((*tracker).as_ref()) {
+                        error!("Not saving because {reason}.");
+                        return Err((StatusCode::CONFLICT, "Camera self-test has not passed."));
+                    }
+                }
                 toggle_saving_csv_tables(
                     value,
                     app_state.expected_framerate_arc.clone(),
@@ -93,6 +149,7 @@ pub(crate) async fn callback_handler(
                     app_state.braidz_write_tx_weak.clone(),
                     app_state.per_cam_data_arc.clone(),
                     app_state.shared_store.clone(),
+                    app_state.clock.clone(),
                 )
                 .await;
             }
@@ -168,6 +225,47 @@ pub(crate) async fn callback_handler(
                     debug!("Already saving, not initiating again.");
                 }
             }
+            UpdateSelfTest(report) => {
+                debug!(
+                    "got self-test result {:?} for camera \"{}\"",
+                    report.inner.passed(),
+                    report.raw_cam_name.as_str()
+                );
+                let mut tracker = app_state.shared_store.write();
+                tracker.modify(|store| {
+                    store
+                        .self_tests
+                        .insert(report.raw_cam_name.as_str().to_string(), report.inner);
+                });
+            }
+            Heartbeat(heartbeat) => {
+                let mut tracker = app_state.shared_store.write();
+                tracker.modify(|store| {
+                    store
+                        .camera_health
+                        .insert(heartbeat.raw_cam_name.as_str().to_string(), heartbeat.inner);
+                });
+            }
+            AddTextlog(msg) => {
+                debug!(
+                    "got textlog message for camera \"{}\": {}",
+                    msg.raw_cam_name.as_str(),
+                    msg.inner
+                );
+                if let Some(braidz_write_tx) = app_state.braidz_write_tx_weak.upgrade() {
+                    let now = datetime_conversion::datetime_to_f64(&app_state.clock.now());
+                    let row = flydra_types::TextlogRow {
+                        mainbrain_timestamp: now,
+                        cam_id: msg.raw_cam_name.as_str().to_string(),
+                        host_timestamp: now,
+                        message: msg.inner,
+                    };
+                    braidz_write_tx
+                        .send(flydra2::SaveToDiskMsg::Textlog(row))
+                        .await
+                        .unwrap();
+                }
+            }
         }
         Ok::<_, (StatusCode, &'static str)>(())
     };
@@ -0,0 +1,166 @@
+//! Clock model of the master camera.
+//!
+//! When the cameras are triggered by a master camera (see
+//! [flydra_types::TriggerType::CameraMaster]), there is no triggerbox whose
+//! clock relates frame numbers to time. Instead, [MasterClockFitter] builds
+//! the clock model from the frames of the master camera. The frame period
+//! (the gain of the model) is fit to the device timestamps of the frames,
+//! which are precise. The device clock is not the clock of the host, so the
+//! offset of the model is the earliest time at which a frame could have been
+//! taken given when it was received, i.e. the frame received with the lowest
+//! latency. If the master camera does not provide device timestamps, the gain
+//! is fit to the times at which the frames were received.
+
+use std::collections::VecDeque;
+
+use tracing::warn;
+
+use braid_triggerbox::ClockModel;
+use flydra_types::SyncFno;
+
+/// Number of recent master frames used to fit the clock model.
+const MAX_SAMPLES: usize = 1000;
+/// Minimum number of frames to fit the clock model.
+const MIN_SAMPLES: usize = 10;
+/// The clock model is fit again after this many new frames.
+const REFIT_INTERVAL: usize = 100;
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    frame: f64,
+    /// Device timestamp in seconds, if available.
+    device: Option<f64>,
+    /// Time received in seconds since the epoch.
+    received: f64,
+}
+
+/// Fits the clock model to the frames of the master camera.
+pub(crate) struct MasterClockFitter {
+    samples: VecDeque<Sample>,
+    n_since_fit: usize,
+    last_frame: Option<u64>,
+    warned_no_device_timestamp: bool,
+}
+
+impl MasterClockFitter {
+    pub(crate) fn new() -> Self {
+        Self {
+            samples: VecDeque::new(),
+            n_since_fit: 0,
+            last_frame: None,
+            warned_no_device_timestamp: false,
+        }
+    }
+
+    /// Add a synchronized frame of the master camera.
+    ///
+    /// `device_timestamp` is in nanoseconds and `received` in seconds since the
+    /// epoch. Returns a new clock model every [REFIT_INTERVAL] frames.
+    pub(crate) fn push(
+        &mut self,
+        synced_frame: SyncFno,
+        device_timestamp: Option<std::num::NonZeroU64>,
+        received: f64,
+    ) -> Option<ClockModel> {
+        if self.last_frame.is_some_and(|last| synced_frame.0 <= last) {
+            // The cameras were synchronized again and frames are numbered anew.
+            self.samples.clear();
+            self.n_since_fit = 0;
+        }
+        self.last_frame = Some(synced_frame.0);
+
+        if self.samples.len() >= MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            frame: synced_frame.0 as f64,
+            device: device_timestamp.map(|ts| ts.get() as f64 * 1e-9),
+            received,
+        });
+        self.n_since_fit += 1;
+
+        // Fit as soon as possible and then every REFIT_INTERVAL frames.
+        if self.samples.len() < MIN_SAMPLES
+            || (self.n_since_fit < REFIT_INTERVAL && self.samples.len() != MIN_SAMPLES)
+        {
+            return None;
+        }
+        self.n_since_fit = 0;
+        self.fit()
+    }
+
+    fn fit(&mut self) -> Option<ClockModel> {
+        let have_device_timestamps = self.samples.iter().all(|s| s.device.is_some());
+        if !have_device_timestamps && !self.warned_no_device_timestamp {
+            warn!(
+                "Master camera frames have no device timestamps. Fitting clock model to the \
+                times the frames were received."
+            );
+            self.warned_no_device_timestamp = true;
+        }
+        let points: Vec<(f64, f64)> = self
+            .samples
+            .iter()
+            .map(|s| {
+                let t = if have_device_timestamps {
+                    s.device.unwrap()
+                } else {
+                    s.received
+                };
+                (s.frame, t)
+            })
+            .collect();
+        let (gain, _, residuals) = fit_line(&points)?;
+        let offset = self
+            .samples
+            .iter()
+            .map(|s| s.received - gain * s.frame)
+            .fold(f64::INFINITY, f64::min);
+        Some(ClockModel {
+            gain,
+            offset,
+            n_measurements: self.samples.len().try_into().unwrap(),
+            residuals,
+        })
+    }
+}
+
+/// Least squares fit of `y = gain * x + offset`.
+///
+/// Returns the gain, the offset and the sum of squared residuals.
+fn fit_line(points: &[(f64, f64)]) -> Option<(f64, f64, f64)> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let sxx: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    if sxx <= 0.0 {
+        return None;
+    }
+    let gain = sxy / sxx;
+    let offset = mean_y - gain * mean_x;
+    let residuals = points
+        .iter()
+        .map(|p| (p.1 - (gain * p.0 + offset)).powi(2))
+        .sum();
+    Some((gain, offset, residuals))
+}
+
+#[test]
+fn test_master_clock_fitter() {
+    let period = 0.01;
+    let t0 = 1.7e9;
+    let mut fitter = MasterClockFitter::new();
+    let mut model = None;
+    for i in 0..MIN_SAMPLES as u64 {
+        // The device clock starts at 5 seconds, and latency is 2 to 4 msec.
+        let device = std::num::NonZeroU64::new(((5.0 + i as f64 * period) * 1e9) as u64);
+        let latency = if i % 3 == 0 { 0.002 } else { 0.004 };
+        let received = t0 + i as f64 * period + latency;
+        model = fitter.push(SyncFno(i + 2), device, received);
+    }
+    let model = model.unwrap();
+    assert!((model.gain - period).abs() < 1e-9);
+    // The offset is from the frames received with the lowest latency.
+    assert!((model.offset - (t0 - 2.0 * period + 0.002)).abs() < 1e-6);
+}
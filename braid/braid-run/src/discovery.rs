@@ -0,0 +1,56 @@
+//! Discovery of Strand Camera instances on the local network.
+//!
+//! Strand Camera advertises its browser UI with mDNS (see
+//! [strand_cam_discovery]). The instances found are listed at
+//! `GET /api/v1/discovered-cameras`, which helps to find cameras which did not
+//! connect to Braid. Instances with cameras not in the configuration are
+//! logged.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use parking_lot::RwLock;
+use tracing::{debug, info, warn};
+
+use flydra_types::RawCamName;
+use strand_cam_discovery::{Browser, CameraNode, DiscoveryEvent};
+
+/// The Strand Camera instances found, by camera name.
+pub(crate) type DiscoveredCameras = Arc<RwLock<BTreeMap<String, CameraNode>>>;
+
+/// Keep `discovered` up to date with the advertised Strand Camera instances.
+pub(crate) async fn run(discovered: DiscoveredCameras, configured: BTreeSet<RawCamName>) {
+    let mut browser = match Browser::new() {
+        Ok(browser) => browser,
+        Err(e) => {
+            warn!("Not discovering Strand Camera instances via mDNS: {e}");
+            return;
+        }
+    };
+    while let Some(event) = browser.next().await {
+        match event {
+            DiscoveryEvent::Found(node) => {
+                let name = node.info.camera_name.clone();
+                let is_new = !discovered.read().contains_key(&name);
+                if is_new {
+                    let urls = node.urls.join(", ");
+                    if configured.contains(&RawCamName::new(name.clone())) {
+                        debug!("Found Strand Camera for camera \"{name}\" at {urls}");
+                    } else {
+                        info!(
+                            "Found Strand Camera for camera \"{name}\", which is not in the \
+                            configuration, at {urls}"
+                        );
+                    }
+                }
+                discovered.write().insert(name, node);
+            }
+            DiscoveryEvent::Removed(name) => {
+                debug!("Strand Camera for camera \"{name}\" is gone");
+                discovered.write().remove(&name);
+            }
+        }
+    }
+}
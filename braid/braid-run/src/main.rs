@@ -10,12 +10,17 @@ use tracing::debug;
 use braid::braid_start;
 use braid_config_data::parse_config_file;
 use flydra_types::{
-    BraidCameraConfig, BuiServerAddrInfo, RawCamName, StartCameraBackend, TriggerType,
+    BraidCameraConfig, BuiServerAddrInfo, NvencPoolConfig, RawCamName, StartCameraBackend,
+    TriggerType,
 };
 
+mod api;
 mod callback_handling;
+mod camera_master;
+mod discovery;
 mod mainbrain;
 mod multicam_http_session_handler;
+mod resync;
 
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
@@ -27,8 +32,14 @@ struct BraidRunCliArgs {
     disable_console: bool,
 }
 
+/// Compute the command line arguments of Strand Camera.
+///
+/// `slot` is the index of the camera among the cameras started on the same
+/// host, by which it is assigned a CUDA device of the NVENC pool.
 fn compute_strand_cam_args(
     camera: &BraidCameraConfig,
+    nvenc_pool: Option<&NvencPoolConfig>,
+    slot: usize,
     mainbrain_internal_addr: &BuiServerAddrInfo,
 ) -> Result<Vec<String>> {
     let urls = mainbrain_internal_addr.build_urls()?;
@@ -36,17 +47,32 @@ fn compute_strand_cam_args(
         .first()
         .ok_or_else(|| eyre::eyre!("need at least one URL"))?;
     let url_string = format!("{url}");
-    Ok(vec![
+    let mut args = vec![
         "--camera-name".into(),
         camera.name.clone(),
         "--braid-url".into(),
         url_string,
-    ])
+    ];
+    if let Some(nvenc_pool) = nvenc_pool {
+        args.push("--nvenc-pool-slot".into());
+        args.push(slot.to_string());
+        args.push("--nvenc-pool-max-failures".into());
+        args.push(nvenc_pool.max_failures.to_string());
+        if !nvenc_pool.devices.is_empty() {
+            let devices: Vec<String> = nvenc_pool.devices.iter().map(|d| d.to_string()).collect();
+            args.push("--nvenc-pool-devices".into());
+            args.push(devices.join(","));
+        }
+    }
+    args.extend(camera.extra_args.iter().cloned());
+    Ok(args)
 }
 
 fn launch_strand_cam(
     strand_cam_set: &mut tokio::task::JoinSet<()>,
     camera: &BraidCameraConfig,
+    nvenc_pool: Option<&NvencPoolConfig>,
+    slot: usize,
     mainbrain_internal_addr: &BuiServerAddrInfo,
 ) -> Result<()> {
     // On initial startup strand cam queries for
@@ -71,8 +97,27 @@ fn launch_strand_cam(
     let cam_name = camera.name.clone();
 
     let mut exec = std::process::Command::new(&exe);
-    let args = compute_strand_cam_args(camera, mainbrain_internal_addr)?;
+    let args = compute_strand_cam_args(camera, nvenc_pool, slot, mainbrain_internal_addr)?;
     exec.args(&args);
+    exec.envs(&camera.env);
+    if let Some(working_dir) = &camera.working_dir {
+        exec.current_dir(working_dir);
+    }
+    // Log everything needed to reproduce the launch.
+    tracing::info!(
+        "Launching Strand Cam for {cam_name}: \"{}\" {}",
+        exe.display(),
+        args.join(" ")
+    );
+    for (key, value) in camera.env.iter() {
+        tracing::info!("  environment variable for {cam_name}: {key}={value}");
+    }
+    if let Some(working_dir) = &camera.working_dir {
+        tracing::info!(
+            "  working directory for {cam_name}: \"{}\"",
+            working_dir.display()
+        );
+    }
     debug!("exec: {:?}", exec);
     let mut obj = exec.spawn().context(format!(
         "Starting Strand Cam executable \"{}\"",
@@ -140,6 +185,9 @@ async fn main() -> Result<()> {
         TriggerType::PtpSync(_) | TriggerType::DeviceTimestamp => {
             (false, flydra_types::StartSoftwareFrameRateLimit::NoChange)
         }
+        // These are the settings of the slave cameras. The master camera is
+        // configured in `remote_camera_info_handler`.
+        TriggerType::CameraMaster(_) => (true, flydra_types::StartSoftwareFrameRateLimit::NoChange),
     };
     let show_tracking_params = false;
 
@@ -156,14 +204,29 @@ async fn main() -> Result<()> {
 
     let cfg_cameras = cfg.cameras;
     let mut strand_cam_set = tokio::task::JoinSet::new();
+    let nvenc_pool = cfg.nvenc_pool.as_ref();
+    // The NVENC slots are counted separately for the cameras on this host and
+    // the remote cameras, which use the encoders of their own hosts.
+    let mut n_local = 0;
+    let mut n_remote = 0;
     for camera in cfg_cameras.into_iter() {
         if camera.start_backend != StartCameraBackend::Remote {
-            launch_strand_cam(&mut strand_cam_set, &camera, &mainbrain_internal_addr)?;
+            let slot = n_local;
+            n_local += 1;
+            launch_strand_cam(
+                &mut strand_cam_set,
+                &camera,
+                nvenc_pool,
+                slot,
+                &mainbrain_internal_addr,
+            )?;
         } else {
+            let slot = n_remote;
+            n_remote += 1;
             tracing::info!(
                 "Not starting remote camera \"{}\". Use args: {}",
                 camera.name,
-                compute_strand_cam_args(&camera, &mainbrain_internal_addr)
+                compute_strand_cam_args(&camera, nvenc_pool, slot, &mainbrain_internal_addr)
                     .unwrap()
                     .join(" ")
             );
@@ -193,6 +256,7 @@ async fn main() -> Result<()> {
         listener,
         mainbrain_server_info,
         strand_cam_set,
+        flydra2::Clock::Wall,
     )
     .await?;
 
@@ -20,16 +20,16 @@ use preferences_serde1::{AppInfo, Preferences};
 use serde::Serialize;
 use tokio::net::UdpSocket;
 use tower_http::trace::TraceLayer;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 use bui_backend_session_types::AccessToken;
 use event_stream_types::{AcceptsEventStream, EventBroadcaster};
 use flydra2::{CoordProcessor, CoordProcessorConfig, FrameDataAndPoints, StreamItem};
 use flydra_types::{
-    braid_http::{CAM_PROXY_PATH, REMOTE_CAMERA_INFO_PATH},
+    braid_http::{API_PATH, CAM_PROXY_PATH, REMOTE_CAMERA_INFO_PATH},
     BraidHttpApiSharedState, BuiServerAddrInfo, CamInfo, CborPacketCodec, FakeSyncConfig,
-    FlydraFloatTimestampLocal, HostClock, PerCamSaveData, RawCamName, SyncFno, TriggerType,
-    Triggerbox, BRAID_EVENTS_URL_PATH, BRAID_EVENT_NAME, TRIGGERBOX_SYNC_SECONDS,
+    FlydraFloatTimestampLocal, HostClock, PerCamSaveData, RawCamName, ResyncEvent, SyncFno,
+    TriggerType, Triggerbox, BRAID_EVENTS_URL_PATH, BRAID_EVENT_NAME, TRIGGERBOX_SYNC_SECONDS,
 };
 use rust_cam_bui_types::{ClockModel, RecordingPath};
 
@@ -38,7 +38,11 @@ use color_eyre::{
     Result,
 };
 
-use crate::multicam_http_session_handler::{MaybeSession, StrandCamHttpSessionHandler};
+use crate::{
+    camera_master::MasterClockFitter,
+    multicam_http_session_handler::{MaybeSession, StrandCamHttpSessionHandler},
+    resync::{self, ResyncMonitor},
+};
 
 #[cfg(feature = "bundle_files")]
 static ASSETS_DIR: include_dir::Dir<'static> =
@@ -100,12 +104,18 @@ pub(crate) struct BraidAppState {
     event_broadcaster: EventBroadcaster<usize>,
     pub(crate) per_cam_data_arc: Arc<RwLock<BTreeMap<RawCamName, PerCamSaveData>>>,
     pub(crate) expected_framerate_arc: Arc<RwLock<Option<f32>>>,
-    camera_configs: BTreeMap<RawCamName, flydra_types::BraidCameraConfig>,
+    pub(crate) camera_configs: BTreeMap<RawCamName, flydra_types::BraidCameraConfig>,
     next_connection_id: Arc<RwLock<usize>>,
     pub(crate) strand_cam_http_session_handler: StrandCamHttpSessionHandler,
     pub(crate) cam_manager: flydra2::ConnectedCamerasManager,
     pub(crate) output_base_dirname: PathBuf,
     pub(crate) braidz_write_tx_weak: tokio::sync::mpsc::WeakSender<flydra2::SaveToDiskMsg>,
+    /// The source of the current time.
+    pub(crate) clock: flydra2::Clock,
+    /// Strand Camera instances found on the network.
+    pub(crate) discovered_cameras: crate::discovery::DiscoveredCameras,
+    /// Latency of the stages of the processing pipeline.
+    pub(crate) latency_budget: Arc<flydra2::LatencyBudget>,
 }
 
 async fn events_handler(
@@ -174,14 +184,25 @@ async fn remote_camera_info_handler(
         .get(&RawCamName::new(raw_cam_name.clone()));
 
     if let Some(config) = cam_cfg {
-        let software_limit_framerate = app_state.software_limit_framerate.clone();
+        let mut force_camera_sync_mode = app_state.force_camera_sync_mode;
+        let mut software_limit_framerate = app_state.software_limit_framerate.clone();
 
         let trig_config = app_state.shared_store.read().as_ref().trigger_type.clone();
 
+        if let TriggerType::CameraMaster(mcfg) = &trig_config {
+            if mcfg.master_camera == raw_cam_name {
+                // The master camera runs freely at the configured frame rate
+                // and triggers the other cameras.
+                force_camera_sync_mode = false;
+                software_limit_framerate =
+                    flydra_types::StartSoftwareFrameRateLimit::Enable(mcfg.framerate);
+            }
+        }
+
         let msg = flydra_types::RemoteCameraInfoResponse {
             camdata_udp_port: app_state.lowlatency_camdata_udp_addr.port(),
             config: config.clone(),
-            force_camera_sync_mode: app_state.force_camera_sync_mode,
+            force_camera_sync_mode,
             software_limit_framerate,
             trig_config,
         };
@@ -273,10 +294,14 @@ async fn cam_proxy_handler(
 
 async fn launch_braid_http_backend(
     secret_base64: Option<String>,
+    api_jwt_auth: bool,
     listener: tokio::net::TcpListener,
     mainbrain_server_info: BuiServerAddrInfo,
     app_state: BraidAppState,
 ) -> Result<impl futures::Future<Output = Result<()>>> {
+    if api_jwt_auth && secret_base64.is_none() {
+        eyre::bail!("`api_jwt_auth` requires `secret_base64` to be set");
+    }
     let persistent_secret_base64 = if let Some(secret) = secret_base64 {
         secret
     } else {
@@ -293,6 +318,12 @@ async fn launch_braid_http_backend(
     };
 
     let persistent_secret = base64::decode(persistent_secret_base64)?;
+    // The JSON Web Tokens for the API are signed with the same secret.
+    let api_jwt_key = api_jwt_auth.then(|| {
+        Arc::new(jsonwebtoken::DecodingKey::from_secret(
+            persistent_secret.as_slice(),
+        ))
+    });
     let persistent_secret = cookie::Key::try_from(persistent_secret.as_slice())?;
 
     // Setup our auth layer.
@@ -326,9 +357,27 @@ async fn launch_braid_http_backend(
     assert_eq!(BRAID_EVENTS_URL_PATH, "braid-events");
     assert_eq!(REMOTE_CAMERA_INFO_PATH, "remote-camera-info");
     assert_eq!(CAM_PROXY_PATH, "cam-proxy");
+    assert_eq!(API_PATH, "api/v1");
+
+    let api_router = axum::Router::new()
+        .route("/status", get(crate::api::status_handler))
+        .route("/latency", get(crate::api::latency_handler))
+        .route(
+            "/discovered-cameras",
+            get(crate::api::discovered_cameras_handler),
+        )
+        .route(
+            "/recording",
+            axum::routing::post(crate::api::recording_handler),
+        )
+        .route(
+            "/experiment-info",
+            axum::routing::post(crate::api::experiment_info_handler),
+        )
+        .route("/profile", axum::routing::post(crate::api::profile_handler));
 
     // Create axum router.
-    let router = axum::Router::new()
+    let mut router = axum::Router::new()
         .route("/braid-events", get(events_handler))
         .route(
             "/remote-camera-info/:encoded_cam_name",
@@ -347,19 +396,33 @@ async fn launch_braid_http_backend(
             "/callback",
             axum::routing::post(crate::callback_handling::callback_handler)
                 .layer(axum::extract::DefaultBodyLimit::max(100_000_000)),
-        )
-        .nest_service("/", serve_dir)
-        .layer(
-            tower::ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http())
-                // Auth layer will produce an error if the request cannot be
-                // authorized so we must handle that.
-                .layer(axum::error_handling::HandleErrorLayer::new(
-                    handle_auth_error,
-                ))
-                .layer(auth_layer),
-        )
-        .with_state(app_state);
+        );
+    if api_jwt_key.is_none() {
+        // Without JWT auth, the API is protected like the rest of the server.
+        router = router.nest("/api/v1", api_router.clone());
+    }
+    let mut router = router.nest_service("/", serve_dir).layer(
+        tower::ServiceBuilder::new()
+            .layer(TraceLayer::new_for_http())
+            // Auth layer will produce an error if the request cannot be
+            // authorized so we must handle that.
+            .layer(axum::error_handling::HandleErrorLayer::new(
+                handle_auth_error,
+            ))
+            .layer(auth_layer),
+    );
+    if let Some(key) = api_jwt_key {
+        // Added after the auth layer, so only the JWT is checked.
+        router = router.nest(
+            "/api/v1",
+            api_router.layer(axum::middleware::from_fn_with_state(
+                key,
+                crate::api::require_jwt,
+            )),
+        );
+        info!("JSON API at /api/v1 requires a JSON Web Token.");
+    }
+    let router = router.with_state(app_state);
 
     // create future for our app
     let http_serve_future = {
@@ -513,6 +576,7 @@ pub(crate) async fn do_run_forever(
     listener: tokio::net::TcpListener,
     mainbrain_server_info: BuiServerAddrInfo,
     mut strand_cam_set: tokio::task::JoinSet<()>,
+    clock: flydra2::Clock,
 ) -> Result<()> {
     let cal_fname: Option<std::path::PathBuf> = mainbrain_config.cal_fname.clone();
     let output_base_dirname: std::path::PathBuf = mainbrain_config.output_base_dirname.clone();
@@ -530,10 +594,28 @@ pub(crate) async fn do_run_forever(
     }
 
     let save_empty_data2d: bool = mainbrain_config.save_empty_data2d;
+    let api_jwt_auth = mainbrain_config.api_jwt_auth;
     let write_buffer_size_num_messages = mainbrain_config.write_buffer_size_num_messages;
 
     info!("saving to directory: {}", output_base_dirname.display());
 
+    let braidz_encryption_key = if let Some(cfg) = &mainbrain_config.braidz_encryption {
+        let key = braidz_crypt::BraidzKey::read_file(&cfg.key_file).with_context(|| {
+            format!(
+                "reading braidz encryption key in file \"{}\"",
+                cfg.key_file.display()
+            )
+        })?;
+        info!(
+            "encrypting .braidz files with key {} from {}",
+            key.id(),
+            cfg.key_file.display()
+        );
+        Some(key)
+    } else {
+        None
+    };
+
     // Create `stream_cancel::Valve` for shutting everything down. Note this is
     // `Clone`, so we can (and should) shut down everything with it.
     let (quit_trigger, valve) = stream_cancel::Valve::new();
@@ -554,17 +636,26 @@ pub(crate) async fn do_run_forever(
     let signal_all_cams_synced = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
 
     let periodic_signal_period_usec = if let TriggerType::PtpSync(ptpcfg) = &trigger_cfg {
-        ptpcfg.periodic_signal_period_usec
+        // Frame numbers are computed from the PTP timestamps of the frames
+        // with the period of the periodic signal triggering the cameras.
+        let period = ptpcfg.periodic_signal_period_usec.ok_or_else(|| {
+            eyre::eyre!(
+                "PTP synchronization requires `periodic_signal_period_usec` in the \
+                `[trigger]` configuration"
+            )
+        })?;
+        Some(period)
     } else {
         None
     };
 
-    let mut cam_manager = flydra2::ConnectedCamerasManager::new(
+    let mut cam_manager = flydra2::ConnectedCamerasManager::new_with_clock(
         &recon,
         all_expected_cameras,
         signal_all_cams_present.clone(),
         signal_all_cams_synced.clone(),
         periodic_signal_period_usec,
+        clock.clone(),
     );
 
     let jar: cookie_store::CookieStore = match Preferences::load(&APP_INFO, STRAND_CAM_COOKIE_KEY) {
@@ -588,7 +679,8 @@ pub(crate) async fn do_run_forever(
         std::process::exit(0);
     }
 
-    let ignore_latency = false;
+    // Latency relative to a simulated clock is meaningless.
+    let ignore_latency = clock.is_simulated();
     let mut coord_processor = CoordProcessor::new(
         CoordProcessorConfig {
             tracking_params,
@@ -596,12 +688,24 @@ pub(crate) async fn do_run_forever(
             ignore_latency,
             mini_arena_debug_image_dir: None,
             write_buffer_size_num_messages,
+            braidz_encryption_key,
+            braidz_spill_dir: mainbrain_config.braidz_spill_dir.clone(),
         },
         cam_manager.clone(),
         recon.clone(),
-        flydra2::BraidMetadataBuilder::saving_program_name(saving_program_name),
+        flydra2::BraidMetadataBuilder::saving_program_name(saving_program_name).with_provenance(
+            provenance::Provenance::current(
+                saving_program_name,
+                env!("CARGO_PKG_VERSION"),
+                env!("GIT_HASH"),
+            ),
+        ),
     )?;
 
+    // Latency of the stages of the processing pipeline.
+    let latency_budget = Arc::new(flydra2::LatencyBudget::with_clock(clock.clone()));
+    coord_processor.set_latency_budget(latency_budget.clone());
+
     // Here is what we do on quit:
     // 1) Stop saving data, convert .braid dir to .braidz, close files.
     // 2) Fire a DoQuit message to all cameras and wait for them to quit.
@@ -647,17 +751,31 @@ pub(crate) async fn do_run_forever(
             let (tx, rx) = tokio::sync::mpsc::channel(20);
             (Some(tx), Some(rx))
         }
-        TriggerType::FakeSync(_) | TriggerType::PtpSync(_) | TriggerType::DeviceTimestamp => {
-            (None, None)
-        }
+        TriggerType::FakeSync(_)
+        | TriggerType::PtpSync(_)
+        | TriggerType::DeviceTimestamp
+        | TriggerType::CameraMaster(_) => (None, None),
     };
 
     let needs_clock_model = match &trigger_cfg {
-        TriggerType::TriggerboxV1(_) | TriggerType::FakeSync(_) => true,
+        TriggerType::TriggerboxV1(_) | TriggerType::FakeSync(_) | TriggerType::CameraMaster(_) => {
+            true
+        }
         TriggerType::PtpSync(_) | TriggerType::DeviceTimestamp => false,
     };
 
-    let sync_pulse_pause_started: Option<std::time::Instant> = None;
+    let resync_monitor = match (&trigger_cfg, &mainbrain_config.auto_resync) {
+        (TriggerType::TriggerboxV1(_), Some(cfg)) => {
+            Some(Arc::new(ResyncMonitor::new(cfg.clone())))
+        }
+        (_, Some(_)) => {
+            warn!("Ignoring `auto_resync` configuration, which is only used with a triggerbox.");
+            None
+        }
+        (_, None) => None,
+    };
+
+    let sync_pulse_pause_started: Option<flydra2::ClockInstant> = None;
     let sync_pulse_pause_started_arc = Arc::new(RwLock::new(sync_pulse_pause_started));
 
     let flydra_app_name = "Braid".to_string();
@@ -674,6 +792,11 @@ pub(crate) async fn do_run_forever(
         flydra_app_name,
         all_expected_cameras_are_synced: false,
         needs_clock_model,
+        disk_space: None,
+        disk_space_error: None,
+        self_tests: Default::default(),
+        camera_health: Default::default(),
+        last_resync: None,
     };
     let shared_store = ChangeTracker::new(shared);
     let mut shared_store_changes_rx = shared_store.get_changes(1);
@@ -726,8 +849,65 @@ pub(crate) async fn do_run_forever(
 
     let braidz_write_tx_weak = coord_processor.braidz_write_tx.downgrade();
 
+    {
+        // Monitor free disk space and stop saving before the disk is full.
+        let shared_store = shared_store.clone();
+        let expected_framerate_arc = expected_framerate_arc.clone();
+        let output_base_dirname2 = output_base_dirname.clone();
+        let braidz_write_tx_weak = braidz_write_tx_weak.clone();
+        let per_cam_data_arc = per_cam_data_arc.clone();
+        let clock = clock.clone();
+        let watcher = disk_space_watcher::watch(
+            output_base_dirname.clone(),
+            mainbrain_config.disk_space.clone(),
+            disk_space_watcher::DEFAULT_INTERVAL,
+            move |status| {
+                let is_critical = status.level == disk_space_watcher::DiskSpaceLevel::Critical;
+                let mut tracker = shared_store.write();
+                let is_saving = {
+                    let store: &BraidHttpApiSharedState = tracker.as_ref();
+                    store.csv_tables_dirname.is_some()
+                };
+                let stop_msg = if is_critical && is_saving {
+                    Some(format!(
+                        "Saving stopped because only {:.1} GB of free space remains on \"{}\".",
+                        status.available_bytes as f64 / 1e9,
+                        status.path
+                    ))
+                } else {
+                    None
+                };
+                tracker.modify(|store| {
+                    store.disk_space = Some(status);
+                    if stop_msg.is_some() {
+                        store.disk_space_error = stop_msg.clone();
+                    }
+                });
+                if let Some(msg) = stop_msg {
+                    error!("{msg}");
+                    tokio::spawn(toggle_saving_csv_tables(
+                        false,
+                        expected_framerate_arc.clone(),
+                        output_base_dirname2.clone(),
+                        braidz_write_tx_weak.clone(),
+                        per_cam_data_arc.clone(),
+                        shared_store.clone(),
+                        clock.clone(),
+                    ));
+                }
+            },
+        );
+        tokio::spawn(watcher);
+    }
+
     let time_model_arc = Arc::new(RwLock::new(None));
 
+    let discovered_cameras = crate::discovery::DiscoveredCameras::default();
+    tokio::spawn(crate::discovery::run(
+        discovered_cameras.clone(),
+        camera_configs.keys().cloned().collect(),
+    ));
+
     // Create our app state.
     let app_state = BraidAppState {
         shared_store: shared_store.clone(),
@@ -743,6 +923,9 @@ pub(crate) async fn do_run_forever(
         cam_manager: cam_manager.clone(),
         output_base_dirname,
         strand_cam_http_session_handler: strand_cam_http_session_handler.clone(),
+        clock: clock.clone(),
+        discovered_cameras,
+        latency_budget: latency_budget.clone(),
     };
 
     // This future will send state updates to all connected event listeners.
@@ -754,9 +937,14 @@ pub(crate) async fn do_run_forever(
         }
     };
 
-    let http_serve_future =
-        launch_braid_http_backend(secret_base64, listener, mainbrain_server_info, app_state)
-            .await?;
+    let http_serve_future = launch_braid_http_backend(
+        secret_base64,
+        api_jwt_auth,
+        listener,
+        mainbrain_server_info,
+        app_state,
+    )
+    .await?;
 
     let signal_triggerbox_connected = Arc::new(AtomicBool::new(false));
 
@@ -771,6 +959,19 @@ pub(crate) async fn do_run_forever(
     let (triggerbox_data_tx, mut triggerbox_data_rx) =
         tokio::sync::mpsc::channel::<braid_triggerbox::TriggerClockInfoRow>(20);
 
+    // With a master camera, the clock model is fit to its frames.
+    let (master_clock_model_tx, master_clock_model_rx) =
+        tokio::sync::mpsc::channel::<braid_triggerbox::ClockModel>(5);
+    let mut master_clock_model_rx = Some(master_clock_model_rx);
+    let master_clock = match &trigger_cfg {
+        TriggerType::CameraMaster(mcfg) => Some((
+            RawCamName::new(mcfg.master_camera.clone()),
+            Arc::new(parking_lot::Mutex::new(MasterClockFitter::new())),
+            master_clock_model_tx,
+        )),
+        _ => None,
+    };
+
     match &trigger_cfg {
         TriggerType::TriggerboxV1(_) | TriggerType::FakeSync(_) => {
             let braidz_write_tx_weak = coord_processor.braidz_write_tx.downgrade();
@@ -822,7 +1023,9 @@ pub(crate) async fn do_run_forever(
         let trigger_cfg = trigger_cfg.clone();
         Box::new(move |tm1: Option<braid_triggerbox::ClockModel>| {
             match &trigger_cfg {
-                TriggerType::FakeSync(_) | TriggerType::TriggerboxV1(_) => {
+                TriggerType::FakeSync(_)
+                | TriggerType::TriggerboxV1(_)
+                | TriggerType::CameraMaster(_) => {
                     let tm = tm1.map(|x| rust_cam_bui_types::ClockModel {
                         gain: x.gain,
                         offset: x.offset,
@@ -933,7 +1136,7 @@ pub(crate) async fn do_run_forever(
 
             let gain = 1.0 / framerate;
 
-            let now: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
+            let now: chrono::DateTime<chrono::Utc> = clock.now();
             let offset = datetime_conversion::datetime_to_f64(&now);
 
             (on_new_clock_model)(Some(braid_triggerbox::ClockModel {
@@ -955,6 +1158,27 @@ pub(crate) async fn do_run_forever(
         TriggerType::DeviceTimestamp => {
             signal_triggerbox_connected.store(true, Ordering::SeqCst);
         }
+        TriggerType::CameraMaster(mcfg) => {
+            info!(
+                "Using master camera \"{}\" at {} fps to trigger the other cameras.",
+                mcfg.master_camera, mcfg.framerate
+            );
+            signal_triggerbox_connected.store(true, Ordering::SeqCst);
+
+            {
+                let mut expected_framerate = expected_framerate_arc.write();
+                *expected_framerate = Some(mcfg.framerate as f32);
+            }
+
+            // The clock model is fit to the frames of the master camera as they
+            // arrive.
+            let mut clock_model_rx = master_clock_model_rx.take().unwrap();
+            tokio::spawn(async move {
+                while let Some(clock_model) = clock_model_rx.recv().await {
+                    (on_new_clock_model)(Some(clock_model));
+                }
+            });
+        }
     };
 
     let expected_framerate_arc9 = expected_framerate_arc.clone();
@@ -973,6 +1197,7 @@ pub(crate) async fn do_run_forever(
     let valve2 = valve.clone();
     let triggerbox_cmd2 = triggerbox_cmd.clone();
     let fake_sync = matches!(trigger_cfg, TriggerType::FakeSync(_));
+    let clock2 = clock.clone();
     let _sync_start_jh = tokio::spawn(async move {
         let interval_stream = tokio_stream::wrappers::IntervalStream::new(tokio::time::interval(
             std::time::Duration::from_secs(1),
@@ -991,6 +1216,7 @@ pub(crate) async fn do_run_forever(
                     sync_pulse_pause_started_arc2.clone(),
                     cam_manager2.clone(),
                     time_model_arc2.clone(),
+                    &clock2,
                 )
                 .await
                 .unwrap();
@@ -999,6 +1225,50 @@ pub(crate) async fn do_run_forever(
         }
     });
 
+    // Resynchronize cameras when requested by the resync monitor.
+    let resync = resync_monitor.map(|resync_monitor| {
+        let (resync_tx, mut resync_rx) = tokio::sync::mpsc::channel::<String>(1);
+        let resync_monitor2 = resync_monitor.clone();
+        let triggerbox_cmd = triggerbox_cmd.clone().unwrap();
+        let sync_pulse_pause_started_arc = sync_pulse_pause_started_arc.clone();
+        let cam_manager = cam_manager.clone();
+        let time_model_arc = time_model_arc.clone();
+        let braidz_write_tx_weak = coord_processor.braidz_write_tx.downgrade();
+        let tracker = tracker.clone();
+        let clock = clock.clone();
+        tokio::spawn(async move {
+            while let Some(reason) = resync_rx.recv().await {
+                error!("Resynchronizing cameras because {reason}.");
+                let started = clock.now();
+                tracker.write().modify(|shared| {
+                    shared.last_resync = Some(ResyncEvent {
+                        started,
+                        reason: reason.clone(),
+                    })
+                });
+                resync::log_to_braidz(
+                    &braidz_write_tx_weak,
+                    &clock,
+                    format!("Resynchronizing cameras because {reason}. Tracking paused."),
+                )
+                .await;
+                if let Err(e) = resynchronize_cameras(
+                    triggerbox_cmd.clone(),
+                    &sync_pulse_pause_started_arc,
+                    &cam_manager,
+                    &time_model_arc,
+                    &resync_monitor2,
+                    &clock,
+                )
+                .await
+                {
+                    error!("Error resynchronizing cameras: {e}");
+                }
+            }
+        });
+        (resync_monitor, resync_tx)
+    });
+
     // Signal cameras are synchronized
 
     let valve2 = valve.clone();
@@ -1007,15 +1277,47 @@ pub(crate) async fn do_run_forever(
             std::time::Duration::from_secs(1),
         ));
         let mut interval_stream = valve2.wrap(interval_stream);
+        let mut all_synced = false;
         while let Some(_now) = interval_stream.next().await {
+            // This changes again when the cameras are resynchronized.
             let sync_done = signal_all_cams_synced.load(Ordering::SeqCst);
-            if sync_done {
-                info!("All cameras done synchronizing.");
+            if sync_done != all_synced {
+                all_synced = sync_done;
+                if sync_done {
+                    info!("All cameras done synchronizing.");
+                }
 
                 // Send message to listeners.
                 let mut tracker = shared_store.write();
-                tracker.modify(|shared| shared.all_expected_cameras_are_synced = true);
-                break;
+                tracker.modify(|shared| shared.all_expected_cameras_are_synced = sync_done);
+            }
+        }
+    });
+
+    // Periodically save the latency budget.
+    let valve2 = valve.clone();
+    let latency_budget2 = latency_budget.clone();
+    let braidz_write_tx_weak = coord_processor.braidz_write_tx.downgrade();
+    let _latency_budget_jh = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(flydra2::LATENCY_WINDOW);
+        // The first tick completes immediately.
+        interval.tick().await;
+        let interval_stream = tokio_stream::wrappers::IntervalStream::new(interval);
+        let mut interval_stream = valve2.wrap(interval_stream);
+        while let Some(_now) = interval_stream.next().await {
+            let stats = latency_budget2.stats();
+            if stats.is_empty() {
+                continue;
+            }
+            let Some(braidz_write_tx) = braidz_write_tx_weak.upgrade() else {
+                continue;
+            };
+            if braidz_write_tx
+                .send(flydra2::SaveToDiskMsg::LatencyStats(stats))
+                .await
+                .is_err()
+            {
+                error!("data writing thread lost. Could not save latency budget.");
             }
         }
     });
@@ -1023,6 +1325,9 @@ pub(crate) async fn do_run_forever(
     let strand_cam_http_session_handler2 = strand_cam_http_session_handler.clone();
     let cam_manager2 = cam_manager.clone();
     let live_stats_collector2 = live_stats_collector.clone();
+    let braidz_write_tx_weak = coord_processor.braidz_write_tx.downgrade();
+    let clock3 = clock.clone();
+    let latency_budget2 = latency_budget.clone();
 
     let packet_filter = move |r| {
         let live_stats_collector2 = live_stats_collector2.clone();
@@ -1036,6 +1341,11 @@ pub(crate) async fn do_run_forever(
         let mut raw_packet_logger =
             RawPacketLogger::new(mainbrain_config.packet_capture_dump_fname.as_deref()).unwrap();
         let time_model_arc = time_model_arc.clone();
+        let resync = resync.clone();
+        let master_clock = master_clock.clone();
+        let braidz_write_tx_weak = braidz_write_tx_weak.clone();
+        let clock = clock3.clone();
+        let latency_budget = latency_budget2.clone();
         async move {
             // vvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvvv
             // Start of closure for on each incoming packet.
@@ -1085,6 +1395,50 @@ pub(crate) async fn do_run_forever(
                 &trigger_cfg,
             );
 
+            if let (Some((master_name, fitter, clock_model_tx)), Some(synced_frame)) =
+                (&master_clock, synced_frame)
+            {
+                if &raw_cam_name == master_name {
+                    let clock_model = fitter.lock().push(
+                        synced_frame,
+                        packet.device_timestamp,
+                        packet.cam_received_time.as_f64(),
+                    );
+                    if let Some(clock_model) = clock_model {
+                        if clock_model_tx.try_send(clock_model).is_err() {
+                            warn!("Could not update clock model of master camera.");
+                        }
+                    }
+                }
+            }
+
+            if let Some((resync_monitor, resync_tx)) = &resync {
+                // Check the correspondence of frames and trigger pulses.
+                let (residual, frame_period) = {
+                    let time_model = time_model_arc.read();
+                    let residual = synced_frame
+                        .and_then(|synced_frame| {
+                            compute_trigger_timestamp(&time_model, synced_frame)
+                        })
+                        .map(|trigger_timestamp| {
+                            packet.cam_received_time.as_f64() - trigger_timestamp.as_f64()
+                        });
+                    (residual, time_model.as_ref().map(|model| model.gain))
+                };
+                if let Some(reason) = resync_monitor.check(
+                    &raw_cam_name,
+                    packet.framenumber,
+                    residual,
+                    frame_period,
+                    clock.now(),
+                ) {
+                    // At most one resynchronization is requested at a time.
+                    if resync_tx.try_send(reason).is_err() {
+                        error!("Could not request resynchronization of cameras.");
+                    }
+                }
+            }
+
             let cam_num = cam_manager.cam_num(&raw_cam_name);
 
             raw_packet_logger
@@ -1114,7 +1468,9 @@ pub(crate) async fn do_run_forever(
             let (synced_frame, trigger_timestamp) = match synced_frame {
                 Some(synced_frame) => {
                     let trigger_timestamp = match &trigger_cfg {
-                        TriggerType::TriggerboxV1(_) | TriggerType::FakeSync(_) => {
+                        TriggerType::TriggerboxV1(_)
+                        | TriggerType::FakeSync(_)
+                        | TriggerType::CameraMaster(_) => {
                             let time_model = time_model_arc.read();
                             compute_trigger_timestamp(&time_model, synced_frame)
                         }
@@ -1135,6 +1491,22 @@ pub(crate) async fn do_run_forever(
                             todo!();
                         }
                     };
+                    let synced_frame = match &resync {
+                        Some((resync_monitor, _)) => {
+                            let (synced_frame, discontinuity) =
+                                resync_monitor.continuous_frame(synced_frame, clock.now());
+                            if let Some(msg) = discontinuity {
+                                info!("{msg}");
+                                let braidz_write_tx_weak = braidz_write_tx_weak.clone();
+                                let clock = clock.clone();
+                                tokio::spawn(async move {
+                                    resync::log_to_braidz(&braidz_write_tx_weak, &clock, msg).await;
+                                });
+                            }
+                            synced_frame
+                        }
+                        None => synced_frame,
+                    };
                     (synced_frame, trigger_timestamp)
                 }
                 None => {
@@ -1143,6 +1515,8 @@ pub(crate) async fn do_run_forever(
                 }
             };
 
+            latency_budget.packet_received(&packet, synced_frame);
+
             let frame_data = flydra2::FrameData::new(
                 raw_cam_name,
                 cam_num,
@@ -1176,7 +1550,11 @@ pub(crate) async fn do_run_forever(
     let (data_tx, data_rx) = tokio::sync::mpsc::channel(50);
 
     let model_pose_server_addr = mainbrain_config.model_server_addr;
-    tokio::spawn(flydra2::new_model_server(data_rx, model_pose_server_addr));
+    tokio::spawn(flydra2::new_model_server_with_clock(
+        data_rx,
+        model_pose_server_addr,
+        clock.clone(),
+    ));
 
     {
         let mut tracker = tracker2.write();
@@ -1187,6 +1565,23 @@ pub(crate) async fn do_run_forever(
     info!("expected_framerate: {:?}", expected_framerate);
 
     coord_processor.add_listener(data_tx);
+
+    if let Some(live_pose_cfg) = &mainbrain_config.live_pose_udp {
+        let sender =
+            flydra2::PoseUdpSender::new(live_pose_cfg.destination, live_pose_cfg.multicast_ttl)
+                .await
+                .with_context(|| {
+                    format!(
+                        "creating socket for live 3D poses to {}",
+                        live_pose_cfg.destination
+                    )
+                })?
+                .with_clock(clock.clone());
+        let (pose_tx, pose_rx) = tokio::sync::mpsc::channel(50);
+        tokio::spawn(sender.run(pose_rx));
+        coord_processor.add_listener(pose_tx);
+    }
+
     let coord_proc_fut = coord_processor.consume_stream(flydra2_stream, expected_framerate);
 
     // We "block" (in an async way) here for the entire runtime of the program.
@@ -1292,6 +1687,16 @@ impl LiveStatsCollector {
     }
 }
 
+/// The reason saving must not be started, if the startup self-test of a
+/// camera has not passed.
+pub(crate) fn self_test_blocks_saving(shared: &BraidHttpApiSharedState) -> Option<String> {
+    shared.self_tests.iter().find_map(|(name, report)| {
+        report
+            .blocks_recording()
+            .map(|reason| format!("{reason} on camera \"{name}\""))
+    })
+}
+
 pub(crate) async fn toggle_saving_csv_tables(
     start_saving: bool,
     expected_framerate_arc: Arc<RwLock<Option<f32>>>,
@@ -1299,10 +1704,11 @@ pub(crate) async fn toggle_saving_csv_tables(
     braidz_write_tx_weak: tokio::sync::mpsc::WeakSender<flydra2::SaveToDiskMsg>,
     per_cam_data_arc: Arc<RwLock<BTreeMap<RawCamName, PerCamSaveData>>>,
     shared_data: SharedStore,
+    clock: flydra2::Clock,
 ) {
     if start_saving {
         let expected_framerate: Option<f32> = *expected_framerate_arc.read();
-        let local: chrono::DateTime<chrono::Local> = chrono::Local::now();
+        let local: chrono::DateTime<chrono::Local> = clock.now_local();
         let dirname = local.format("%Y%m%d_%H%M%S.braid").to_string();
         let mut my_dir = output_base_dirname.clone();
         my_dir.push(dirname);
@@ -1336,6 +1742,7 @@ pub(crate) async fn toggle_saving_csv_tables(
             let mut tracker = shared_data.write();
             tracker.modify(|store| {
                 store.csv_tables_dirname = Some(RecordingPath::new(my_dir.display().to_string()));
+                store.disk_space_error = None;
             });
         }
     } else {
@@ -1362,16 +1769,17 @@ pub(crate) async fn toggle_saving_csv_tables(
 async fn synchronize_cameras(
     triggerbox_cmd: Option<tokio::sync::mpsc::Sender<braid_triggerbox::Cmd>>,
     fake_sync: bool,
-    sync_pulse_pause_started_arc: Arc<RwLock<Option<std::time::Instant>>>,
+    sync_pulse_pause_started_arc: Arc<RwLock<Option<flydra2::ClockInstant>>>,
     mut cam_manager: flydra2::ConnectedCamerasManager,
     time_model_arc: Arc<RwLock<Option<rust_cam_bui_types::ClockModel>>>,
+    clock: &flydra2::Clock,
 ) -> Result<()> {
     info!("preparing to synchronize cameras");
 
     // This time must be prior to actually resetting sync data.
     {
         let mut sync_pulse_pause_started = sync_pulse_pause_started_arc.write();
-        *sync_pulse_pause_started = Some(std::time::Instant::now());
+        *sync_pulse_pause_started = Some(clock.instant());
     }
 
     // Now we can reset the sync data.
@@ -1392,6 +1800,38 @@ async fn synchronize_cameras(
     Ok(())
 }
 
+/// Synchronize the cameras again while running.
+///
+/// Unlike [synchronize_cameras], this keeps the camera numbers.
+async fn resynchronize_cameras(
+    triggerbox_cmd: tokio::sync::mpsc::Sender<braid_triggerbox::Cmd>,
+    sync_pulse_pause_started_arc: &RwLock<Option<flydra2::ClockInstant>>,
+    cam_manager: &flydra2::ConnectedCamerasManager,
+    time_model_arc: &RwLock<Option<rust_cam_bui_types::ClockModel>>,
+    resync_monitor: &ResyncMonitor,
+    clock: &flydra2::Clock,
+) -> Result<()> {
+    info!("preparing to resynchronize cameras");
+
+    // This time must be prior to actually resetting sync data.
+    let now = clock.now();
+    {
+        let mut sync_pulse_pause_started = sync_pulse_pause_started_arc.write();
+        *sync_pulse_pause_started = Some(clock.instant());
+    }
+
+    // Frames are dropped until the cameras are synchronized again.
+    cam_manager.reset_sync_state();
+    resync_monitor.sync_started(now);
+
+    {
+        let mut guard = time_model_arc.write();
+        *guard = None;
+    }
+
+    begin_cam_sync_triggerbox_in_process(triggerbox_cmd).await
+}
+
 async fn begin_cam_sync_triggerbox_in_process(
     tx: tokio::sync::mpsc::Sender<braid_triggerbox::Cmd>,
 ) -> Result<()> {
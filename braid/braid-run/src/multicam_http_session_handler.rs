@@ -304,6 +304,16 @@ impl StrandCamHttpSessionHandler {
         Ok(())
     }
 
+    pub(crate) async fn apply_profile_all(&self, name: &str) -> MainbrainResult<()> {
+        let cam_names = self.cam_manager.all_raw_cam_names();
+        for cam_name in cam_names.iter() {
+            debug!("for cam {}, applying profile {name}", cam_name.as_str());
+            let args = ci2_remote_control::CamArg::ApplyProfile(name.to_string());
+            self.post(cam_name, args).await?;
+        }
+        Ok(())
+    }
+
     pub(crate) async fn initiate_post_trigger_mp4_all(&self) -> MainbrainResult<()> {
         let cam_names = self.cam_manager.all_raw_cam_names();
         for cam_name in cam_names.iter() {
@@ -0,0 +1,242 @@
+//! Automatic resynchronization of the cameras.
+//!
+//! If the triggerbox drops pulses or a camera resets, the correspondence
+//! between camera frame numbers and triggerbox pulses silently becomes wrong.
+//! [ResyncMonitor] detects this from the residuals of the clock model: the time
+//! at which a frame was received minus the trigger time computed from its
+//! frame number. This is the latency of the camera, which is stable. If it
+//! changes by a fraction of the frame period, or if the frame number of a
+//! camera decreases, the cameras are synchronized again.
+//!
+//! Synchronizing restarts the frame count of the triggerbox. To keep the frame
+//! numbers used for tracking and saved in the `.braidz` file increasing, the
+//! frames after a resynchronization are numbered from the last frame before
+//! it plus the number of frames which elapsed meanwhile.
+
+use std::collections::BTreeMap;
+
+use parking_lot::Mutex;
+use tracing::error;
+
+use braid_config_data::AutoResyncConfig;
+use flydra_types::{RawCamName, SyncFno, TextlogRow};
+
+/// Minimum time between two automatic resynchronizations.
+const MIN_RESYNC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Checking the frames for consistency.
+    Monitoring,
+    /// A resynchronization was requested but has not started.
+    Requested,
+    /// The cameras are being synchronized. The next synchronized frame
+    /// starts the new frame numbering.
+    Synchronizing,
+}
+
+/// The residuals of one camera.
+#[derive(Default)]
+struct CamResiduals {
+    /// The frame number of the previous frame from the camera.
+    last_cam_frame: Option<i32>,
+    /// Number of residuals in the current window.
+    n: usize,
+    /// Minimum residual in the current window, in seconds.
+    window_min: f64,
+    /// Minimum residual of the last consistent window, in seconds.
+    baseline: Option<f64>,
+    /// Number of consecutive inconsistent windows.
+    n_inconsistent: usize,
+}
+
+struct ResyncState {
+    phase: Phase,
+    /// Start of the most recent resynchronization.
+    last_started: Option<chrono::DateTime<chrono::Utc>>,
+    /// Added to the synchronized frame numbers of the camera manager.
+    frame_offset: u64,
+    /// The most recent frame number returned and when it was received.
+    last_frame: Option<(u64, chrono::DateTime<chrono::Utc>)>,
+    /// Frame period of the most recent clock model, in seconds.
+    frame_period: Option<f64>,
+    per_cam: BTreeMap<RawCamName, CamResiduals>,
+}
+
+/// Detects an inconsistent correspondence of frames and trigger pulses and
+/// keeps frame numbers increasing across resynchronizations.
+pub(crate) struct ResyncMonitor {
+    cfg: AutoResyncConfig,
+    state: Mutex<ResyncState>,
+}
+
+impl ResyncMonitor {
+    pub(crate) fn new(cfg: AutoResyncConfig) -> Self {
+        Self {
+            cfg,
+            state: Mutex::new(ResyncState {
+                phase: Phase::Monitoring,
+                last_started: None,
+                frame_offset: 0,
+                last_frame: None,
+                frame_period: None,
+                per_cam: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Check a frame from a camera.
+    ///
+    /// `residual` is the time at which the frame was received minus its
+    /// trigger time, in seconds, and `frame_period` the gain of the clock
+    /// model. Both are `None` if the frame is not synchronized or there is no
+    /// clock model.
+    ///
+    /// Returns the reason to resynchronize the cameras, if they should be.
+    /// Until [Self::sync_started] is called, no further reason is returned.
+    pub(crate) fn check(
+        &self,
+        raw_cam_name: &RawCamName,
+        cam_frame: i32,
+        residual: Option<f64>,
+        frame_period: Option<f64>,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Option<String> {
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+        if frame_period.is_some() {
+            state.frame_period = frame_period;
+        }
+        let cam = state.per_cam.entry(raw_cam_name.clone()).or_default();
+        let last_cam_frame = cam.last_cam_frame.replace(cam_frame);
+        if state.phase != Phase::Monitoring || state.last_frame.is_none() {
+            // Not synchronized yet or already resynchronizing.
+            return None;
+        }
+
+        let mut reason = None;
+        if last_cam_frame.is_some_and(|last| cam_frame < last) {
+            reason = Some(format!(
+                "the frame number of camera \"{}\" decreased (camera reset?)",
+                raw_cam_name.as_str()
+            ));
+        } else if let (Some(residual), Some(frame_period)) = (residual, frame_period) {
+            if cam.n == 0 || residual < cam.window_min {
+                cam.window_min = residual;
+            }
+            cam.n += 1;
+            if cam.n >= self.cfg.window_frames {
+                cam.n = 0;
+                match cam.baseline {
+                    Some(baseline)
+                        if (cam.window_min - baseline).abs()
+                            > self.cfg.max_residual_change_frames * frame_period =>
+                    {
+                        cam.n_inconsistent += 1;
+                        if cam.n_inconsistent >= self.cfg.consecutive_windows {
+                            reason = Some(format!(
+                                "the latency of camera \"{}\" changed by {:.1} frames",
+                                raw_cam_name.as_str(),
+                                (cam.window_min - baseline) / frame_period
+                            ));
+                        }
+                    }
+                    _ => {
+                        cam.baseline = Some(cam.window_min);
+                        cam.n_inconsistent = 0;
+                    }
+                }
+            }
+        }
+
+        let reason = reason?;
+        // Start again from scratch for all cameras.
+        for cam in state.per_cam.values_mut() {
+            *cam = CamResiduals {
+                last_cam_frame: cam.last_cam_frame,
+                ..Default::default()
+            };
+        }
+        if let Some(last_started) = state.last_started {
+            if (now - last_started).to_std().unwrap_or_default() < MIN_RESYNC_INTERVAL {
+                error!(
+                    "Not resynchronizing cameras although {reason} because they were \
+                    resynchronized less than {} seconds ago.",
+                    MIN_RESYNC_INTERVAL.as_secs()
+                );
+                return None;
+            }
+        }
+        state.phase = Phase::Requested;
+        Some(reason)
+    }
+
+    /// The cameras are no longer synchronized and will be synchronized again.
+    pub(crate) fn sync_started(&self, now: chrono::DateTime<chrono::Utc>) {
+        let mut state = self.state.lock();
+        state.phase = Phase::Synchronizing;
+        state.last_started = Some(now);
+    }
+
+    /// Compute the frame number used for tracking and saving from the
+    /// synchronized frame number of the camera manager.
+    ///
+    /// For the first frame after a resynchronization, this also returns a
+    /// description of the discontinuity of the synchronized frame numbers.
+    pub(crate) fn continuous_frame(
+        &self,
+        synced_frame: SyncFno,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> (SyncFno, Option<String>) {
+        let mut state = self.state.lock();
+        let mut discontinuity = None;
+        if state.phase == Phase::Synchronizing {
+            state.phase = Phase::Monitoring;
+            if let Some((last, last_time)) = state.last_frame {
+                let elapsed = (now - last_time).to_std().unwrap_or_default();
+                let elapsed_frames = state
+                    .frame_period
+                    .map(|period| (elapsed.as_secs_f64() / period).round() as u64)
+                    .unwrap_or_default()
+                    .max(1);
+                let first = last + elapsed_frames;
+                state.frame_offset = first.saturating_sub(synced_frame.0);
+                discontinuity = Some(format!(
+                    "Cameras resynchronized. Frame {first} follows frame {last} after {:.1} \
+                    seconds. Triggerbox frame counts restarted.",
+                    elapsed.as_secs_f64()
+                ));
+            }
+        }
+        let frame = synced_frame.0 + state.frame_offset;
+        if state.last_frame.map_or(true, |(last, _)| frame > last) {
+            state.last_frame = Some((frame, now));
+        }
+        (SyncFno(frame), discontinuity)
+    }
+}
+
+/// Save a message in the textlog of the `.braidz` file, if saving.
+pub(crate) async fn log_to_braidz(
+    braidz_write_tx_weak: &tokio::sync::mpsc::WeakSender<flydra2::SaveToDiskMsg>,
+    clock: &flydra2::Clock,
+    message: String,
+) {
+    let Some(braidz_write_tx) = braidz_write_tx_weak.upgrade() else {
+        return;
+    };
+    let now = datetime_conversion::datetime_to_f64(&clock.now());
+    let row = TextlogRow {
+        mainbrain_timestamp: now,
+        cam_id: "mainbrain".to_string(),
+        host_timestamp: now,
+        message,
+    };
+    if braidz_write_tx
+        .send(flydra2::SaveToDiskMsg::Textlog(row))
+        .await
+        .is_err()
+    {
+        error!("data writing thread lost. Could not save textlog message.");
+    }
+}
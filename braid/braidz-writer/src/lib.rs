@@ -1,7 +1,13 @@
+//! Writing `.braidz` files.
+
 use std::{io::Write, path::Path};
 
+mod resilient_file;
+mod writer;
 mod zip_dir;
 
+pub use writer::{braidz_path_for_dir, BraidzWriter, CsvWriter};
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("IO error: {source}")]
@@ -14,6 +20,44 @@ pub enum Error {
         #[from]
         source: zip::result::ZipError,
     },
+    #[error("CSV error: {source}")]
+    CsvError {
+        #[from]
+        source: csv::Error,
+    },
+    #[error("YAML error: {source}")]
+    YamlError {
+        #[from]
+        source: serde_yaml::Error,
+    },
+    #[error("JSON error: {source}")]
+    JsonError {
+        #[from]
+        source: serde_json::Error,
+    },
+    #[error("time zone error: {source}")]
+    GetTimezoneError {
+        #[from]
+        source: iana_time_zone::GetTimezoneError,
+    },
+    #[error(
+        "data could not be written to {}, the remaining data is saved to {}",
+        dirname.display(),
+        recovery_dir.display()
+    )]
+    Incomplete {
+        dirname: std::path::PathBuf,
+        recovery_dir: std::path::PathBuf,
+    },
+    #[error(
+        "data could not be written to {}, a partial copy is saved to {}",
+        dirname.display(),
+        braidz_path.display()
+    )]
+    SavedLocally {
+        dirname: std::path::PathBuf,
+        braidz_path: std::path::PathBuf,
+    },
 }
 
 // zip the output_dirname directory
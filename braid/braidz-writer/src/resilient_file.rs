@@ -0,0 +1,585 @@
+//! Files which survive a temporarily unwritable destination.
+//!
+//! The tables of a `.braidz` file are streamed to disk during the whole
+//! recording, often to a network drive. If writing fails, e.g. because the
+//! connection to the server dropped, the unwritten bytes are kept in memory
+//! and, beyond [MAX_MEMORY_BYTES], in a spill file in a local directory.
+//! Writing is retried with exponential backoff by reopening the file and
+//! continuing after the last byte written, so the file is complete once the
+//! destination is writable again.
+//!
+//! If the destination is still unwritable when a file is closed, the unwritten
+//! bytes are saved to a recovery directory in the local directory. When saving
+//! finishes, a partial copy of the `.braid` directory is assembled locally from
+//! the files in the destination and the saved bytes. If the destination cannot
+//! even be read, a `RECOVERY.md` file explaining how to complete the `.braid`
+//! directory by hand is saved to the recovery directory instead.
+//!
+//! The failure is detected below the gzip compression, so the compressed
+//! streams stay valid.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tracing::{error, info, warn};
+
+/// The maximum number of unwritten bytes kept in memory per file.
+const MAX_MEMORY_BYTES: usize = 64 * 1024 * 1024;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The number of attempts to write the remaining bytes when closing a file.
+const FINAL_ATTEMPTS: usize = 3;
+const FINAL_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The size of the chunks copied from a spill file.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Used to name the spill files uniquely.
+static SPILL_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The local directory used by default for spill files and recovery.
+pub(crate) fn default_spill_dir() -> PathBuf {
+    std::env::temp_dir().join("braidz-spill")
+}
+
+/// The state shared by all files of a `.braid` directory.
+pub(crate) struct Destination {
+    /// The `.braid` directory.
+    dirname: PathBuf,
+    /// The local directory for spill files and recovery.
+    spill_dir: PathBuf,
+    state: Mutex<DestinationState>,
+}
+
+struct DestinationState {
+    /// The number of open files with unwritten bytes.
+    n_failing: usize,
+    failing_since: Option<Instant>,
+    next_retry: Instant,
+    backoff: Duration,
+    /// The files closed with unwritten bytes.
+    given_up: Vec<GivenUpFile>,
+}
+
+/// A file closed with unwritten bytes, which were saved to the recovery
+/// directory.
+struct GivenUpFile {
+    /// The path relative to the `.braid` directory.
+    relname: PathBuf,
+    /// The name of the file with the unwritten bytes in the recovery directory.
+    tail_fname: String,
+    /// The number of bytes written to the destination.
+    offset: u64,
+}
+
+impl Destination {
+    pub(crate) fn new(dirname: PathBuf, spill_dir: PathBuf) -> Self {
+        Self {
+            dirname,
+            spill_dir,
+            state: Mutex::new(DestinationState {
+                n_failing: 0,
+                failing_since: None,
+                next_retry: Instant::now(),
+                backoff: INITIAL_BACKOFF,
+                given_up: Vec::new(),
+            }),
+        }
+    }
+
+    /// The directory to which unwritten bytes are saved when files are
+    /// closed.
+    pub(crate) fn recovery_dir(&self) -> PathBuf {
+        let name = self.dirname.file_name().unwrap_or_default();
+        self.spill_dir.join(name)
+    }
+
+    /// Whether all bytes of the closed files were written to the destination.
+    pub(crate) fn is_complete(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.n_failing == 0 && state.given_up.is_empty()
+    }
+
+    fn retry_due(&self) -> bool {
+        Instant::now() >= self.state.lock().unwrap().next_retry
+    }
+
+    fn gave_up(&self) -> bool {
+        !self.state.lock().unwrap().given_up.is_empty()
+    }
+
+    /// Writing to `path` failed for the first time.
+    fn file_failed(&self, path: &Path, err: &std::io::Error) {
+        let mut state = self.state.lock().unwrap();
+        state.n_failing += 1;
+        if state.n_failing == 1 {
+            error!(
+                "Cannot write to \"{}\": {err}. Keeping the data until the destination is \
+                writable again.",
+                path.display()
+            );
+            let now = Instant::now();
+            state.failing_since = Some(now);
+            state.next_retry = now + state.backoff;
+        }
+    }
+
+    /// Writing to `path` failed again.
+    fn retry_failed(&self, path: &Path, err: &std::io::Error, n_pending: u64) {
+        let mut state = self.state.lock().unwrap();
+        let elapsed = state.failing_since.map(|t| t.elapsed()).unwrap_or_default();
+        warn!(
+            "Destination still unwritable after {:.0} s, {n_pending} bytes of \"{}\" pending: \
+            {err}. Retrying in {} s.",
+            elapsed.as_secs_f64(),
+            path.display(),
+            state.backoff.as_secs()
+        );
+        state.next_retry = Instant::now() + state.backoff;
+        state.backoff = (state.backoff * 2).min(MAX_BACKOFF);
+    }
+
+    /// All bytes of a failing file were written.
+    fn file_recovered(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.n_failing -= 1;
+        // The other failing files are retried right away.
+        state.next_retry = Instant::now();
+        if state.n_failing == 0 {
+            let since = state.failing_since.take().unwrap_or_else(Instant::now);
+            info!(
+                "Destination writable again after {:.0} s. All kept data was written.",
+                since.elapsed().as_secs_f64()
+            );
+            state.backoff = INITIAL_BACKOFF;
+        }
+    }
+
+    /// A failing file was closed, and its remaining bytes saved as
+    /// `tail_fname` in the recovery directory.
+    fn file_given_up(&self, relname: PathBuf, tail_fname: String, offset: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.n_failing -= 1;
+        state.given_up.push(GivenUpFile {
+            relname,
+            tail_fname,
+            offset,
+        });
+    }
+
+    /// The local directory in which a partial copy of the `.braid` directory
+    /// is assembled.
+    pub(crate) fn partial_dir(&self) -> PathBuf {
+        let stem = self.dirname.file_stem().unwrap_or_default();
+        let mut name = stem.to_os_string();
+        name.push(".partial.braid");
+        self.spill_dir.join(name)
+    }
+
+    /// Copy the files of the destination to [Self::partial_dir], completing
+    /// the files closed with unwritten bytes with the bytes saved to the
+    /// recovery directory.
+    ///
+    /// Fails if the destination cannot be read. The recovery directory is
+    /// not changed.
+    pub(crate) fn assemble_locally(&self) -> std::io::Result<PathBuf> {
+        let state = self.state.lock().unwrap();
+        let recovery_dir = self.recovery_dir();
+        let partial_dir = self.partial_dir();
+        for entry in walkdir::WalkDir::new(&self.dirname) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relname = entry.path().strip_prefix(&self.dirname).unwrap();
+            let path = partial_dir.join(relname);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            match state.given_up.iter().find(|f| f.relname == relname) {
+                Some(given_up) => {
+                    let mut file = File::create(&path)?;
+                    let mut head = File::open(entry.path())?.take(given_up.offset);
+                    if std::io::copy(&mut head, &mut file)? != given_up.offset {
+                        return Err(std::io::ErrorKind::UnexpectedEof.into());
+                    }
+                    let mut tail = File::open(recovery_dir.join(&given_up.tail_fname))?;
+                    std::io::copy(&mut tail, &mut file)?;
+                }
+                None => {
+                    std::fs::copy(entry.path(), &path)?;
+                }
+            }
+        }
+        // A file which could not be created in the destination consists of
+        // the saved bytes only.
+        for given_up in state.given_up.iter() {
+            let path = partial_dir.join(&given_up.relname);
+            if !path.exists() {
+                if given_up.offset > 0 {
+                    return Err(std::io::ErrorKind::NotFound.into());
+                }
+                std::fs::create_dir_all(path.parent().unwrap())?;
+                std::fs::copy(recovery_dir.join(&given_up.tail_fname), &path)?;
+            }
+        }
+        Ok(partial_dir)
+    }
+
+    /// Save `RECOVERY.md` to the recovery directory with instructions to
+    /// complete the `.braid` directory by hand.
+    pub(crate) fn write_recovery_readme(&self) -> std::io::Result<()> {
+        let state = self.state.lock().unwrap();
+        let mut readme = File::create(self.recovery_dir().join("RECOVERY.md"))?;
+        writeln!(
+            readme,
+            "The destination \"{}\" could not be written to when saving finished.\n\n\
+            To complete it, append each file here to the file of the same name in \
+            that directory, truncated to the given size, e.g. with \
+            `truncate -s <size> <file>; cat <tail> >> <file>`. Then convert the \
+            directory with `braidz-writer-cli`.\n",
+            self.dirname.display()
+        )?;
+        for given_up in state.given_up.iter() {
+            writeln!(
+                readme,
+                "* `{}`: append to `{}` of {} bytes.",
+                given_up.tail_fname,
+                given_up.relname.display(),
+                given_up.offset
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The bytes not yet written to the destination, in order.
+enum Pending {
+    Memory(Vec<u8>),
+    /// The bytes from `read_pos` in the spill file.
+    Spill {
+        file: File,
+        path: PathBuf,
+        read_pos: u64,
+        len: u64,
+    },
+}
+
+impl Pending {
+    fn len(&self) -> u64 {
+        match self {
+            Pending::Memory(buf) => buf.len() as u64,
+            Pending::Spill { read_pos, len, .. } => len - read_pos,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push(&mut self, buf: &[u8], spill_dir: &Path) -> std::io::Result<()> {
+        if let Pending::Memory(mem) = self {
+            if mem.len() + buf.len() <= MAX_MEMORY_BYTES {
+                mem.extend_from_slice(buf);
+                return Ok(());
+            }
+            // Move the bytes in memory to a new spill file.
+            std::fs::create_dir_all(spill_dir)?;
+            let path = spill_dir.join(format!(
+                "{}-{}.spill",
+                std::process::id(),
+                SPILL_COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let mut file = File::options()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path)?;
+            file.write_all(mem)?;
+            let len = mem.len() as u64;
+            warn!("Keeping {len} bytes in spill file \"{}\".", path.display());
+            *self = Pending::Spill {
+                file,
+                path,
+                read_pos: 0,
+                len,
+            };
+        }
+        if let Pending::Spill { file, len, .. } = self {
+            file.seek(SeekFrom::Start(*len))?;
+            file.write_all(buf)?;
+            *len += buf.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Write all bytes to `dest`, removing those written.
+    ///
+    /// `written` is increased by the number of bytes written.
+    fn write_to(&mut self, dest: &mut File, written: &mut u64) -> std::io::Result<()> {
+        match self {
+            Pending::Memory(buf) => {
+                let start = *written;
+                let result = write_counted(dest, buf, written);
+                buf.drain(..(*written - start) as usize);
+                result
+            }
+            Pending::Spill {
+                file,
+                path,
+                read_pos,
+                len,
+            } => {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                while *read_pos < *len {
+                    let n = CHUNK_SIZE.min((*len - *read_pos) as usize);
+                    file.seek(SeekFrom::Start(*read_pos))?;
+                    file.read_exact(&mut chunk[..n])?;
+                    let start = *written;
+                    let result = write_counted(dest, &chunk[..n], written);
+                    *read_pos += *written - start;
+                    result?;
+                }
+                std::fs::remove_file(&path)?;
+                *self = Pending::Memory(Vec::new());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Write all of `buf`, increasing `written` by the number of bytes written,
+/// also if an error occurs.
+fn write_counted(dest: &mut File, mut buf: &[u8], written: &mut u64) -> std::io::Result<()> {
+    while !buf.is_empty() {
+        match dest.write(buf) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                *written += n as u64;
+                buf = &buf[n..];
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// A file of a `.braid` directory, written through temporary failures of the
+/// destination.
+pub(crate) struct ResilientFile {
+    path: PathBuf,
+    /// `None` after a failure, until the file is reopened.
+    file: Option<File>,
+    /// The number of bytes written to the file.
+    written: u64,
+    pending: Pending,
+    destination: std::sync::Arc<Destination>,
+}
+
+impl ResilientFile {
+    pub(crate) fn new(path: PathBuf, file: File, destination: std::sync::Arc<Destination>) -> Self {
+        Self {
+            path,
+            file: Some(file),
+            written: 0,
+            pending: Pending::Memory(Vec::new()),
+            destination,
+        }
+    }
+
+    /// Open the file again, truncated to the bytes written before.
+    fn reopen(&mut self) -> std::io::Result<&mut File> {
+        if self.file.is_none() {
+            let mut file = File::options().write(true).open(&self.path)?;
+            file.set_len(self.written)?;
+            file.seek(SeekFrom::Start(self.written))?;
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    /// Try to write the pending bytes. Returns whether all were written.
+    fn retry(&mut self) -> bool {
+        let result = self.reopen().map(|_| ()).and_then(|()| {
+            let file = self.file.as_mut().unwrap();
+            self.pending.write_to(file, &mut self.written)
+        });
+        match result {
+            Ok(()) => {
+                self.destination.file_recovered();
+                true
+            }
+            Err(e) => {
+                self.file = None;
+                self.destination
+                    .retry_failed(&self.path, &e, self.pending.len());
+                false
+            }
+        }
+    }
+
+    /// Save the pending bytes to the recovery directory.
+    fn save_locally(&mut self) -> std::io::Result<PathBuf> {
+        let relname = self
+            .path
+            .strip_prefix(&self.destination.dirname)
+            .unwrap_or(&self.path)
+            .to_path_buf();
+        let tail_fname = format!(
+            "{}.from-byte-{}",
+            relname.to_string_lossy().replace(['/', '\\'], "_"),
+            self.written
+        );
+        let recovery_dir = self.destination.recovery_dir();
+        std::fs::create_dir_all(&recovery_dir)?;
+        let tail_path = recovery_dir.join(&tail_fname);
+        let mut tail = File::create(&tail_path)?;
+        let mut n_saved = 0;
+        self.pending.write_to(&mut tail, &mut n_saved)?;
+        self.destination
+            .file_given_up(relname, tail_fname, self.written);
+        Ok(tail_path)
+    }
+}
+
+impl Write for ResilientFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            let start = self.written;
+            let result = self.reopen().map(|_| ()).and_then(|()| {
+                let file = self.file.as_mut().unwrap();
+                write_counted(file, buf, &mut self.written)
+            });
+            match result {
+                Ok(()) => return Ok(buf.len()),
+                Err(e) => {
+                    self.file = None;
+                    self.destination.file_failed(&self.path, &e);
+                    let n = (self.written - start) as usize;
+                    self.pending.push(&buf[n..], &self.destination.spill_dir)?;
+                    return Ok(buf.len());
+                }
+            }
+        }
+        self.pending.push(buf, &self.destination.spill_dir)?;
+        if self.destination.retry_due() {
+            self.retry();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.pending.is_empty() && self.destination.retry_due() {
+            self.retry();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ResilientFile {
+    fn drop(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        // Once a file was given up, the destination is not waited for again.
+        let n_attempts = if self.destination.gave_up() {
+            1
+        } else {
+            FINAL_ATTEMPTS
+        };
+        for i in 0..n_attempts {
+            if i > 0 {
+                std::thread::sleep(FINAL_RETRY_INTERVAL);
+            }
+            if self.retry() {
+                return;
+            }
+        }
+        match self.save_locally() {
+            Ok(tail_path) => error!(
+                "Could not complete \"{}\". The data after byte {} is saved to \"{}\".",
+                self.path.display(),
+                self.written,
+                tail_path.display()
+            ),
+            Err(e) => error!(
+                "Could not complete \"{}\" or save the remaining data locally: {e}",
+                self.path.display()
+            ),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_resilient_file() -> anyhow::Result<()> {
+    let root = tempfile::tempdir()?;
+    let dirname = root.path().join("test.braid");
+    std::fs::create_dir_all(&dirname)?;
+    let destination =
+        std::sync::Arc::new(Destination::new(dirname.clone(), root.path().join("spill")));
+
+    // Writing to /dev/full fails, as does writing to a full disk.
+    let path = dirname.join("a.csv");
+    std::fs::write(&path, b"")?;
+    let full = File::options().write(true).open("/dev/full")?;
+    let mut wtr = ResilientFile::new(path.clone(), full, destination.clone());
+    wtr.write_all(b"hello ")?;
+    wtr.write_all(b"world")?;
+    assert!(!destination.is_complete());
+
+    // The file is reopened and completed.
+    assert!(wtr.retry());
+    wtr.write_all(b"!")?;
+    drop(wtr);
+    assert_eq!(std::fs::read(&path)?, b"hello world!");
+    assert!(destination.is_complete());
+
+    // The file does not exist and cannot be reopened, so the data is saved
+    // locally.
+    let path = dirname.join("b.csv");
+    let full = File::options().write(true).open("/dev/full")?;
+    let mut wtr = ResilientFile::new(path, full, destination.clone());
+    wtr.write_all(b"lost?")?;
+    drop(wtr);
+    assert!(!destination.is_complete());
+    let recovery_dir = destination.recovery_dir();
+    assert_eq!(
+        std::fs::read(recovery_dir.join("b.csv.from-byte-0"))?,
+        b"lost?"
+    );
+
+    // A file whose first 7 bytes were written before the destination failed,
+    // followed by the bytes of an incomplete write.
+    std::fs::write(dirname.join("c.csv"), b"partly sa")?;
+    std::fs::write(recovery_dir.join("c.csv.from-byte-7"), b"saved")?;
+    destination
+        .state
+        .lock()
+        .unwrap()
+        .given_up
+        .push(GivenUpFile {
+            relname: "c.csv".into(),
+            tail_fname: "c.csv.from-byte-7".into(),
+            offset: 7,
+        });
+
+    // A partial copy is assembled from the destination and the saved bytes.
+    let partial_dir = destination.assemble_locally()?;
+    assert_eq!(std::fs::read(partial_dir.join("a.csv"))?, b"hello world!");
+    assert_eq!(std::fs::read(partial_dir.join("b.csv"))?, b"lost?");
+    assert_eq!(std::fs::read(partial_dir.join("c.csv"))?, b"partly saved");
+
+    destination.write_recovery_readme()?;
+    assert!(recovery_dir.join("RECOVERY.md").exists());
+    Ok(())
+}
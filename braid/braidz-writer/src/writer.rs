@@ -0,0 +1,374 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use libflate::{finish::AutoFinishUnchecked, gzip::Encoder};
+use serde::Serialize;
+use tracing::error;
+
+use braidz_types::BraidMetadata;
+use flydra_types::{
+    TextlogRow, TrackingParams, BRAID_METADATA_YML_FNAME, CAM_INFO_CSV_FNAME,
+    DATA2D_DISTORTED_CSV_FNAME, IMAGES_DIRNAME, README_MD_FNAME, TEXTLOG_CSV_FNAME,
+};
+
+use crate::{
+    dir_to_braidz,
+    resilient_file::{default_spill_dir, Destination, ResilientFile},
+    Error,
+};
+
+/// A CSV writer into a file of a [BraidzWriter].
+pub type CsvWriter = csv::Writer<Box<dyn Write + Send>>;
+
+/// The tracking parameters as saved in the textlog.
+#[derive(Serialize)]
+struct TrackingParamsSaver {
+    tracking_params: TrackingParams,
+    git_revision: String,
+}
+
+/// Creates a `.braidz` file.
+///
+/// The contents are written to a `.braid` directory, which [Self::finish]
+/// converts to the `.braidz` file and removes. Until then, the directory is
+/// locked by keeping its `README.md` file open. If the writer is dropped
+/// without calling [Self::finish], the directory is kept, and can be converted
+/// later with `braidz-writer-cli`.
+///
+/// The README and the metadata are written on creation. Tables are written with
+/// [Self::write_table] or streamed with [Self::csv_writer] and
+/// [Self::csv_gz_writer] using the file names in [flydra_types], e.g.
+/// [flydra_types::KALMAN_ESTIMATES_CSV_FNAME]. Any other file, such as the
+/// calibration, can be created with [Self::create_file].
+///
+/// The streamed tables are written through temporary failures of the
+/// destination, e.g. a dropped network drive. Their data is kept in memory or
+/// in a local spill directory until the destination is writable again. If it
+/// is not when saving finishes, [Self::finish] keeps the incomplete directory
+/// and saves a partial `.braidz` file with all data to the spill directory.
+pub struct BraidzWriter {
+    dirname: PathBuf,
+    /// The README file in the directory.
+    ///
+    /// In theory, we might prefer an open reference to the directory itself,
+    /// but this does not seem possible. So we have a potential slight race
+    /// condition when we have our directory but not yet the file handle on
+    /// readme.
+    #[allow(dead_code)]
+    readme_fd: Option<File>,
+    destination: Arc<Destination>,
+}
+
+impl BraidzWriter {
+    /// Create the `.braid` directory for a new `.braidz` file.
+    ///
+    /// If `path` ends with `.braidz`, the directory is named with `.braid`
+    /// instead. Otherwise, `path` is the directory and the name of the
+    /// `.braidz` file is given by [braidz_path_for_dir].
+    pub fn new<P: AsRef<Path>>(path: P, metadata: &BraidMetadata) -> Result<Self, Error> {
+        Self::new_with_spill_dir(path, metadata, default_spill_dir())
+    }
+
+    /// Create the `.braid` directory, with `spill_dir` as the local directory
+    /// for data which cannot be written to the directory.
+    ///
+    /// [Self::new] uses `braidz-spill` in the temporary directory.
+    pub fn new_with_spill_dir<P: AsRef<Path>>(
+        path: P,
+        metadata: &BraidMetadata,
+        spill_dir: PathBuf,
+    ) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let dirname = if path.extension().is_some_and(|ext| ext == "braidz") {
+            path.with_extension("braid")
+        } else {
+            path.to_path_buf()
+        };
+
+        std::fs::create_dir_all(&dirname)?;
+
+        // Until we obtain the readme file handle, we have a small race
+        // condition where another process could also open this directory.
+        let mut readme_fd = File::create(dirname.join(README_MD_FNAME))?;
+        // Start and end it with some newlines so the text is more readable.
+        readme_fd.write_all(
+            "\n\nThis is data saved by the braid program. \
+            See https://strawlab.org/braid for more information.\n\n"
+                .as_bytes(),
+        )?;
+
+        let metadata_buf = serde_yaml::to_string(metadata)?;
+        std::fs::write(dirname.join(BRAID_METADATA_YML_FNAME), metadata_buf)?;
+
+        std::fs::create_dir_all(dirname.join(IMAGES_DIRNAME))?;
+
+        Ok(Self {
+            destination: Arc::new(Destination::new(dirname.clone(), spill_dir)),
+            dirname,
+            readme_fd: Some(readme_fd),
+        })
+    }
+
+    /// The `.braid` directory being written.
+    pub fn dirname(&self) -> &Path {
+        &self.dirname
+    }
+
+    /// The `.braidz` file created by [Self::finish].
+    pub fn braidz_path(&self) -> PathBuf {
+        braidz_path_for_dir(&self.dirname)
+    }
+
+    /// Create a file in the directory, including its parent directories.
+    ///
+    /// `relname` is relative to the directory.
+    pub fn create_file<P: AsRef<Path>>(&self, relname: P) -> Result<File, Error> {
+        let path = self.dirname.join(relname);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(File::create(path)?)
+    }
+
+    /// Save the PNG image of a camera, shown e.g. as background of the 2D
+    /// detections.
+    pub fn add_image(&self, raw_cam_name: &str, png_buf: &[u8]) -> Result<(), Error> {
+        let mut fd =
+            self.create_file(Path::new(IMAGES_DIRNAME).join(format!("{raw_cam_name}.png")))?;
+        fd.write_all(png_buf)?;
+        Ok(())
+    }
+
+    /// Create a file which is written through temporary failures of the
+    /// destination.
+    fn create_resilient_file(&self, relname: &str) -> Result<ResilientFile, Error> {
+        let fd = self.create_file(relname)?;
+        Ok(ResilientFile::new(
+            self.dirname.join(relname),
+            fd,
+            self.destination.clone(),
+        ))
+    }
+
+    /// Create an uncompressed CSV table `fname`.
+    pub fn csv_writer(&self, fname: &str) -> Result<CsvWriter, Error> {
+        let fd = self.create_resilient_file(fname)?;
+        Ok(csv::Writer::from_writer(Box::new(fd)))
+    }
+
+    /// Create the gzip-compressed CSV table `fname` (saved as `<fname>.gz`).
+    pub fn csv_gz_writer(&self, fname: &str) -> Result<CsvWriter, Error> {
+        let fd = self.create_resilient_file(&format!("{fname}.gz"))?;
+        let fd: Box<dyn Write + Send> = Box::new(AutoFinishUnchecked::new(Encoder::new(fd)?));
+        Ok(csv::Writer::from_writer(fd))
+    }
+
+    /// Create the textlog table, starting with the frame rate and the tracking
+    /// parameters.
+    ///
+    /// The tracking parameters are required to read the `kalman_estimates`
+    /// table. Further [flydra_types::TextlogRow] rows can be written to the
+    /// returned writer.
+    pub fn textlog_writer(
+        &self,
+        fps: Option<f32>,
+        git_revision: &str,
+        tracking_params: &TrackingParams,
+    ) -> Result<CsvWriter, Error> {
+        let timestamp = datetime_conversion::datetime_to_f64(&chrono::Local::now());
+
+        let fps = match fps {
+            Some(fps) => format!("{}", fps),
+            None => "unknown".to_string(),
+        };
+        let version = "2.0.0";
+        let tzname = iana_time_zone::get_timezone()?;
+        let message = format!(
+            "MainBrain running at {} fps, (\
+            flydra_version {}, git_revision {}, time_tzname0 {})",
+            fps, version, git_revision, tzname
+        );
+
+        let tps = TrackingParamsSaver {
+            tracking_params: tracking_params.clone(),
+            git_revision: git_revision.to_string(),
+        };
+        let message2 = serde_json::to_string(&tps)?;
+
+        // We do not stream this to .gz because we want to maximize chances
+        // that it is completely flushed to disk even in event of a panic.
+        let mut textlog_wtr = self.csv_writer(TEXTLOG_CSV_FNAME)?;
+        for message in [message, message2] {
+            textlog_wtr.serialize(TextlogRow {
+                mainbrain_timestamp: timestamp,
+                cam_id: "mainbrain".to_string(),
+                host_timestamp: timestamp,
+                message,
+            })?;
+        }
+        // Flush to disk. In case braid crashes, at least we want to recover this info.
+        textlog_wtr.flush()?;
+        Ok(textlog_wtr)
+    }
+
+    /// Write all rows of the gzip-compressed CSV table `fname`.
+    pub fn write_table<T, I>(&self, fname: &str, rows: I) -> Result<(), Error>
+    where
+        T: Serialize,
+        I: IntoIterator<Item = T>,
+    {
+        let mut wtr = self.csv_gz_writer(fname)?;
+        for row in rows {
+            wtr.serialize(row)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Convert the directory to the `.braidz` file and remove the directory.
+    ///
+    /// All files in the directory must be closed. Returns the path of the
+    /// `.braidz` file.
+    ///
+    /// If data could not be written to the directory, it is kept. A partial
+    /// `.braidz` file is then created in the spill directory from the
+    /// directory and the data saved locally, and [Error::SavedLocally] is
+    /// returned. If the directory cannot be read for this, the instructions
+    /// to complete it by hand are saved with the data saved locally, and
+    /// [Error::Incomplete] is returned.
+    pub fn finish(mut self) -> Result<PathBuf, Error> {
+        if !self.destination.is_complete() {
+            return Err(self.finish_locally());
+        }
+
+        add_required_tables(&self.dirname)?;
+
+        let output_zipfile = self.braidz_path();
+        dir_to_braidz(&self.dirname, &output_zipfile)?;
+
+        // Release the file so we no longer have exclusive access to the
+        // directory. (Until we remove the directory, we have a small race
+        // condition where another process could open the directory without
+        // obtaining the readme file handle.)
+        self.readme_fd = None;
+
+        // Once the original directory is written successfully to a zip file,
+        // we remove it.
+        std::fs::remove_dir_all(&self.dirname)?;
+        Ok(output_zipfile)
+    }
+
+    /// Create the partial `.braidz` file in the spill directory.
+    fn finish_locally(&self) -> Error {
+        let partial_dir = match self.destination.assemble_locally() {
+            Ok(partial_dir) => partial_dir,
+            Err(e) => {
+                error!(
+                    "Could not assemble a partial copy of \"{}\": {e}",
+                    self.dirname.display()
+                );
+                let _ = std::fs::remove_dir_all(self.destination.partial_dir());
+                if let Err(e) = self.destination.write_recovery_readme() {
+                    error!("Could not save recovery instructions: {e}");
+                }
+                return Error::Incomplete {
+                    dirname: self.dirname.clone(),
+                    recovery_dir: self.destination.recovery_dir(),
+                };
+            }
+        };
+        let braidz_path = braidz_path_for_dir(&partial_dir);
+        let result = add_required_tables(&partial_dir)
+            .and_then(|()| dir_to_braidz(&partial_dir, &braidz_path));
+        if let Err(e) = result {
+            return e;
+        }
+        // All data is in the `.braidz` file now.
+        let _ = std::fs::remove_dir_all(&partial_dir);
+        let _ = std::fs::remove_dir_all(self.destination.recovery_dir());
+        Error::SavedLocally {
+            dirname: self.dirname.clone(),
+            braidz_path,
+        }
+    }
+}
+
+/// Create the tables which Braid always saves and readers expect, if missing
+/// in `dirname`.
+fn add_required_tables(dirname: &Path) -> Result<(), Error> {
+    for fname in [CAM_INFO_CSV_FNAME, DATA2D_DISTORTED_CSV_FNAME] {
+        let gz_fname = format!("{fname}.gz");
+        if !dirname.join(fname).exists() && !dirname.join(&gz_fname).exists() {
+            let fd = File::create(dirname.join(gz_fname))?;
+            Encoder::new(fd)?.finish().into_result()?;
+        }
+    }
+    Ok(())
+}
+
+/// The name of the `.braidz` file for the `.braid` directory `dirname`.
+///
+/// The extension `.braid` is replaced with `.braidz`. Other names get
+/// `.braidz` appended.
+pub fn braidz_path_for_dir<P: AsRef<Path>>(dirname: P) -> PathBuf {
+    let dirname = dirname.as_ref();
+    if dirname.extension().is_some_and(|ext| ext == "braid") {
+        dirname.with_extension("braidz")
+    } else {
+        let mut tmp = dirname.as_os_str().to_os_string();
+        tmp.push(".braidz");
+        tmp.into()
+    }
+}
+
+#[test]
+fn test_braidz_writer() -> anyhow::Result<()> {
+    use flydra_types::CamInfoRow;
+
+    let output_root = tempfile::tempdir()?; // will cleanup on drop
+    let metadata = BraidMetadata {
+        schema: flydra_types::BRAID_SCHEMA,
+        git_revision: "abc".to_string(),
+        original_recording_time: None,
+        save_empty_data2d: true,
+        saving_program_name: "test".to_string(),
+        provenance: vec![],
+        tracking_determinism: None,
+    };
+    let wtr = BraidzWriter::new(output_root.path().join("test.braidz"), &metadata)?;
+    assert_eq!(wtr.dirname(), output_root.path().join("test.braid"));
+    wtr.add_image("cam1", b"not really a png")?;
+    wtr.write_table(
+        CAM_INFO_CSV_FNAME,
+        [CamInfoRow {
+            camn: flydra_types::CamNum(0),
+            cam_id: "cam1".to_string(),
+        }],
+    )?;
+    let braidz_path = wtr.finish()?;
+    assert_eq!(braidz_path, output_root.path().join("test.braidz"));
+    assert!(!output_root.path().join("test.braid").exists());
+
+    let zip_archive = zip::ZipArchive::new(File::open(&braidz_path)?)?;
+    let fnames: std::collections::BTreeSet<&str> = zip_archive.file_names().collect();
+    assert!(fnames.contains(README_MD_FNAME));
+    assert!(fnames.contains(BRAID_METADATA_YML_FNAME));
+    assert!(fnames.contains("images/cam1.png"));
+    assert!(fnames.contains(format!("{CAM_INFO_CSV_FNAME}.gz").as_str()));
+    // Required tables are created if not written.
+    assert!(fnames.contains(format!("{DATA2D_DISTORTED_CSV_FNAME}.gz").as_str()));
+    Ok(())
+}
+
+#[test]
+fn test_braidz_path_for_dir() {
+    assert_eq!(
+        braidz_path_for_dir("a/b.braid"),
+        PathBuf::from("a/b.braidz")
+    );
+    assert_eq!(braidz_path_for_dir("a/b"), PathBuf::from("a/b.braidz"));
+}
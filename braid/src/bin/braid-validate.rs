@@ -0,0 +1,311 @@
+use std::path::{Path, PathBuf};
+
+use braid::braid_start;
+use braid_config_data::BraidConfig;
+use clap::Parser;
+use color_eyre::{eyre::WrapErr, Result};
+use flydra_types::TriggerType;
+use serde::Serialize;
+
+/// check a configuration file without starting Braid
+///
+/// A report of the checks is printed in YAML format. The exit code is non-zero
+/// if any check failed.
+#[derive(Debug, Parser)]
+#[command(author, version)]
+struct BraidValidateCliArgs {
+    /// Input configuration file
+    config_file: PathBuf,
+    /// Also check that each camera launched by Braid is found by its camera
+    /// backend. The cameras must not be in use by another program.
+    #[arg(long)]
+    probe_cameras: bool,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+    name: String,
+    status: Status,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ValidationReport {
+    config_file: PathBuf,
+    passed: bool,
+    checks: Vec<Check>,
+}
+
+#[derive(Default)]
+struct Checks(Vec<Check>);
+
+impl Checks {
+    fn push(&mut self, name: impl Into<String>, status: Status, message: impl Into<String>) {
+        self.0.push(Check {
+            name: name.into(),
+            status,
+            message: message.into(),
+        });
+    }
+    fn pass(&mut self, name: impl Into<String>, message: impl Into<String>) {
+        self.push(name, Status::Pass, message)
+    }
+    fn warn(&mut self, name: impl Into<String>, message: impl Into<String>) {
+        self.push(name, Status::Warn, message)
+    }
+    fn fail(&mut self, name: impl Into<String>, message: impl Into<String>) {
+        self.push(name, Status::Fail, message)
+    }
+}
+
+fn check_camera_names(cfg: &BraidConfig, checks: &mut Checks) {
+    let mut seen = std::collections::BTreeSet::new();
+    let mut problems = Vec::new();
+    for camera in cfg.cameras.iter() {
+        if camera.name.trim().is_empty() {
+            problems.push("empty camera name".to_string());
+        } else if !seen.insert(camera.name.as_str()) {
+            problems.push(format!(
+                "camera name \"{}\" used more than once",
+                camera.name
+            ));
+        }
+    }
+    if cfg.cameras.is_empty() {
+        checks.warn("camera names", "no cameras configured");
+    } else if problems.is_empty() {
+        checks.pass(
+            "camera names",
+            format!("{} unique camera names", cfg.cameras.len()),
+        );
+    } else {
+        checks.fail("camera names", problems.join("; "));
+    }
+}
+
+fn check_file_exists(checks: &mut Checks, name: String, path: &Path) {
+    if path.is_file() {
+        checks.pass(name, format!("\"{}\" found", path.display()));
+    } else {
+        checks.fail(name, format!("\"{}\" not found", path.display()));
+    }
+}
+
+fn check_files(cfg: &BraidConfig, checks: &mut Checks) {
+    if let Some(cal_fname) = &cfg.mainbrain.cal_fname {
+        check_file_exists(checks, "calibration file".into(), cal_fname);
+    }
+    for camera in cfg.cameras.iter() {
+        if let Some(fname) = &camera.camera_settings_filename {
+            check_file_exists(
+                checks,
+                format!("camera settings of \"{}\"", camera.name),
+                fname,
+            );
+        }
+    }
+}
+
+fn check_trigger(cfg: &BraidConfig, checks: &mut Checks) {
+    let name = "trigger";
+    match &cfg.trigger {
+        TriggerType::TriggerboxV1(tcfg) => {
+            if !(tcfg.framerate.is_finite() && tcfg.framerate > 0.0) {
+                checks.fail(name, format!("invalid framerate {}", tcfg.framerate));
+            } else if cfg!(target_os = "windows") {
+                // Serial ports such as `COM3` are not files.
+                checks.pass(
+                    name,
+                    format!(
+                        "triggerbox at {}, {} fps",
+                        tcfg.device_fname, tcfg.framerate
+                    ),
+                );
+            } else if !Path::new(&tcfg.device_fname).exists() {
+                checks.fail(
+                    name,
+                    format!("triggerbox device \"{}\" not found", tcfg.device_fname),
+                );
+            } else {
+                checks.pass(
+                    name,
+                    format!(
+                        "triggerbox at {}, {} fps",
+                        tcfg.device_fname, tcfg.framerate
+                    ),
+                );
+            }
+        }
+        TriggerType::PtpSync(pcfg) => match pcfg.periodic_signal_period_usec {
+            Some(period) if !(period.is_finite() && period > 0.0) => {
+                checks.fail(name, format!("invalid periodic signal period {period}"));
+            }
+            Some(period) => checks.pass(
+                name,
+                format!("PTP synchronization, {:.1} fps", 1e6 / period),
+            ),
+            None => checks.fail(
+                name,
+                "PTP synchronization requires periodic_signal_period_usec",
+            ),
+        },
+        TriggerType::DeviceTimestamp => checks.pass(name, "device timestamps"),
+        TriggerType::FakeSync(fcfg) => {
+            if fcfg.framerate.is_finite() && fcfg.framerate > 0.0 {
+                checks.warn(
+                    name,
+                    format!(
+                        "cameras are not synchronized (fake sync at {} fps)",
+                        fcfg.framerate
+                    ),
+                );
+            } else {
+                checks.fail(name, format!("invalid framerate {}", fcfg.framerate));
+            }
+        }
+        TriggerType::CameraMaster(mcfg) => {
+            if !(mcfg.framerate.is_finite() && mcfg.framerate > 0.0) {
+                checks.fail(name, format!("invalid framerate {}", mcfg.framerate));
+            } else if !cfg
+                .cameras
+                .iter()
+                .any(|camera| camera.name == mcfg.master_camera)
+            {
+                checks.fail(
+                    name,
+                    format!("master camera \"{}\" not configured", mcfg.master_camera),
+                );
+            } else {
+                checks.pass(
+                    name,
+                    format!(
+                        "master camera \"{}\", {} fps",
+                        mcfg.master_camera, mcfg.framerate
+                    ),
+                );
+            }
+        }
+    }
+}
+
+fn check_output_dir(cfg: &BraidConfig, checks: &mut Checks) {
+    let name = "output directory";
+    let dirname = &cfg.mainbrain.output_base_dirname;
+    // The directory is created when Braid starts, so check the nearest
+    // existing ancestor.
+    let Some(existing) = dirname.ancestors().find(|p| p.is_dir()) else {
+        checks.fail(
+            name,
+            format!("no parent of \"{}\" exists", dirname.display()),
+        );
+        return;
+    };
+    let probe = existing.join(format!(".braid-validate-{}", std::process::id()));
+    match std::fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            checks.pass(name, format!("\"{}\" is writable", existing.display()));
+        }
+        Err(err) => checks.fail(
+            name,
+            format!("\"{}\" is not writable: {err}", existing.display()),
+        ),
+    }
+}
+
+fn check_cameras_visible(cfg: &BraidConfig, checks: &mut Checks) -> Result<()> {
+    let current_exe = std::env::current_exe()?;
+    let exe_dir = current_exe
+        .parent()
+        .ok_or_else(|| color_eyre::eyre::eyre!("executable must be in directory"))?;
+    #[cfg(target_os = "windows")]
+    let ext = ".exe";
+    #[cfg(not(target_os = "windows"))]
+    let ext = "";
+    for camera in cfg.cameras.iter() {
+        let name = format!("camera \"{}\" visible", camera.name);
+        let Some(exe_name) = camera.start_backend.strand_cam_exe_name() else {
+            checks.pass(name, "remote camera, not probed");
+            continue;
+        };
+        let exe = exe_dir.join(format!("{exe_name}{ext}"));
+        let mut exec = std::process::Command::new(&exe);
+        exec.arg("--list-cameras");
+        exec.envs(&camera.env);
+        if let Some(working_dir) = &camera.working_dir {
+            exec.current_dir(working_dir);
+        }
+        tracing::debug!("exec: {:?}", exec);
+        let output = match exec.output() {
+            Ok(output) => output,
+            Err(err) => {
+                checks.fail(name, format!("running \"{}\": {err}", exe.display()));
+                continue;
+            }
+        };
+        if !output.status.success() {
+            checks.fail(
+                name,
+                format!(
+                    "\"{}\" failed: {}",
+                    exe.display(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            );
+            continue;
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if stdout.lines().any(|line| line.trim() == camera.name) {
+            checks.pass(name, format!("found by {exe_name}"));
+        } else {
+            checks.fail(name, format!("not found by {exe_name}"));
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    braid_start("validate").with_context(|| "launching validate command".to_string())?;
+
+    env_tracing_logger::init();
+
+    let version = format!("{} (git {})", env!("CARGO_PKG_VERSION"), env!("GIT_HASH"));
+    tracing::info!("{} {}", env!("CARGO_PKG_NAME"), version);
+
+    let args = BraidValidateCliArgs::parse();
+    tracing::debug!("{:?}", args);
+
+    let mut checks = Checks::default();
+    match braid_config_data::parse_config_file(&args.config_file) {
+        Ok(cfg) => {
+            checks.pass("parse", "configuration file parsed");
+            check_camera_names(&cfg, &mut checks);
+            check_files(&cfg, &mut checks);
+            check_trigger(&cfg, &mut checks);
+            check_output_dir(&cfg, &mut checks);
+            if args.probe_cameras {
+                check_cameras_visible(&cfg, &mut checks)?;
+            }
+        }
+        Err(err) => checks.fail("parse", format!("{err}")),
+    }
+
+    let report = ValidationReport {
+        config_file: args.config_file,
+        passed: !checks.0.iter().any(|c| c.status == Status::Fail),
+        checks: checks.0,
+    };
+    print!("{}", serde_yaml::to_string(&report)?);
+    if !report.passed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
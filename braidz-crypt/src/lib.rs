@@ -0,0 +1,475 @@
+//! Encryption of `.braidz` files.
+//!
+//! An encrypted `.braidz` file contains a complete `.braidz` file encrypted
+//! with AES-256-GCM. It starts with a header consisting of [MAGIC], the format
+//! version (one byte), the [KeyId] of the key (8 bytes) and a random nonce
+//! prefix (7 bytes). The encrypted data follows in chunks of [CHUNK_SIZE]
+//! bytes, each with a 16 byte authentication tag. The chunks are encrypted
+//! with the STREAM construction, which detects modified, reordered and missing
+//! chunks. The header is authenticated with each chunk.
+//!
+//! A key is 32 random bytes. Key files contain the hex encoded key and
+//! optional comment lines starting with `#`. A [Keyring] holds several keys,
+//! for example all key files in a directory. The key to decrypt a file is
+//! found in the keyring by its [KeyId].
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use aes_gcm::{
+    aead::{
+        rand_core::RngCore,
+        stream::{DecryptorBE32, EncryptorBE32},
+        KeyInit, OsRng, Payload,
+    },
+    Aes256Gcm,
+};
+use sha2::{Digest, Sha256};
+
+/// The first bytes of an encrypted `.braidz` file.
+pub const MAGIC: &[u8] = b"BRAIDZ-ENCRYPTED\n";
+
+/// Size of the unencrypted chunks.
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The filename extension of key files in a keyring directory.
+pub const KEY_FILE_EXTENSION: &str = "key";
+
+const VERSION: u8 = 1;
+const KEY_LEN: usize = 32;
+const KEY_ID_LEN: usize = 8;
+const NONCE_PREFIX_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + 1 + KEY_ID_LEN + NONCE_PREFIX_LEN;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("IO error: {source}")]
+    Io {
+        #[from]
+        source: std::io::Error,
+    },
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+    #[error("not an encrypted braidz file")]
+    NotEncrypted,
+    #[error("unsupported encrypted braidz file version {0}")]
+    UnsupportedVersion(u8),
+    #[error(
+        "file is encrypted with key {key_id}, which is not available (available keys: {available})"
+    )]
+    NoMatchingKey { key_id: KeyId, available: String },
+    #[error("integrity check failed at byte {offset}: the file is corrupt or was modified")]
+    IntegrityCheckFailed { offset: u64 },
+    #[error("encrypted file is truncated")]
+    Truncated,
+    #[error("encryption failed")]
+    EncryptionFailed,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Identifies a key without revealing it.
+///
+/// This is the beginning of the SHA-256 hash of the key.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyId([u8; KEY_ID_LEN]);
+
+impl std::fmt::Display for KeyId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl std::fmt::Debug for KeyId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "KeyId({self})")
+    }
+}
+
+/// A key to encrypt and decrypt `.braidz` files.
+#[derive(Clone)]
+pub struct BraidzKey([u8; KEY_LEN]);
+
+impl std::fmt::Debug for BraidzKey {
+    // Do not print the key itself.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BraidzKey({})", self.id())
+    }
+}
+
+impl BraidzKey {
+    /// Generate a new random key.
+    pub fn generate() -> Self {
+        let mut key = [0u8; KEY_LEN];
+        OsRng.fill_bytes(&mut key);
+        Self(key)
+    }
+
+    /// Parse a hex encoded key.
+    pub fn from_hex(hex_str: &str) -> Result<Self> {
+        let bytes = hex::decode(hex_str.trim()).map_err(|e| Error::InvalidKey(e.to_string()))?;
+        let key = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            Error::InvalidKey(format!("expected {KEY_LEN} bytes, found {}", bytes.len()))
+        })?;
+        Ok(Self(key))
+    }
+
+    /// The hex encoded key.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    pub fn id(&self) -> KeyId {
+        let hash = Sha256::new()
+            .chain_update(b"braidz key id\0")
+            .chain_update(self.0)
+            .finalize();
+        let mut id = [0u8; KEY_ID_LEN];
+        id.copy_from_slice(&hash[..KEY_ID_LEN]);
+        KeyId(id)
+    }
+
+    /// Read a key file.
+    pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        let hex_str: String = contents
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .collect();
+        Self::from_hex(&hex_str).map_err(|e| match e {
+            Error::InvalidKey(msg) => Error::InvalidKey(format!("{msg} in \"{}\"", path.display())),
+            e => e,
+        })
+    }
+
+    /// Write a new key file, which is readable only by the user on Unix.
+    ///
+    /// Fails if the file exists.
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut fd = options.open(path)?;
+        writeln!(fd, "# braidz encryption key {}", self.id())?;
+        writeln!(fd, "{}", self.to_hex())?;
+        Ok(())
+    }
+}
+
+/// A set of keys to decrypt `.braidz` files.
+#[derive(Debug, Default, Clone)]
+pub struct Keyring {
+    keys: BTreeMap<KeyId, BraidzKey>,
+}
+
+impl From<BraidzKey> for Keyring {
+    fn from(key: BraidzKey) -> Self {
+        let mut keyring = Self::default();
+        keyring.add(key);
+        keyring
+    }
+}
+
+impl Keyring {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, key: BraidzKey) {
+        self.keys.insert(key.id(), key);
+    }
+
+    /// Add the key files (with extension [KEY_FILE_EXTENSION]) in `dir`.
+    pub fn add_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension() == Some(KEY_FILE_EXTENSION.as_ref()) {
+                self.add(BraidzKey::read_file(&path)?);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key_id: &KeyId) -> Option<&BraidzKey> {
+        self.keys.get(key_id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    fn describe(&self) -> String {
+        if self.keys.is_empty() {
+            return "none".to_string();
+        }
+        self.keys
+            .keys()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Read until `buf` is full or the end of the input. Returns the number of
+/// bytes read.
+fn read_full<R: Read>(rdr: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match rdr.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(n_read) => n += n_read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+struct Header([u8; HEADER_LEN]);
+
+impl Header {
+    fn new(key_id: KeyId, nonce_prefix: &[u8; NONCE_PREFIX_LEN]) -> Self {
+        let mut bytes = [0u8; HEADER_LEN];
+        let (magic, rest) = bytes.split_at_mut(MAGIC.len());
+        magic.copy_from_slice(MAGIC);
+        rest[0] = VERSION;
+        rest[1..1 + KEY_ID_LEN].copy_from_slice(&key_id.0);
+        rest[1 + KEY_ID_LEN..].copy_from_slice(nonce_prefix);
+        Self(bytes)
+    }
+
+    fn read<R: Read>(rdr: &mut R) -> Result<Self> {
+        let mut bytes = [0u8; HEADER_LEN];
+        let n = read_full(rdr, &mut bytes)?;
+        if n < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(Error::NotEncrypted);
+        }
+        if n < HEADER_LEN {
+            return Err(Error::Truncated);
+        }
+        let version = bytes[MAGIC.len()];
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        Ok(Self(bytes))
+    }
+
+    fn key_id(&self) -> KeyId {
+        let start = MAGIC.len() + 1;
+        let mut id = [0u8; KEY_ID_LEN];
+        id.copy_from_slice(&self.0[start..start + KEY_ID_LEN]);
+        KeyId(id)
+    }
+
+    fn nonce_prefix(&self) -> &[u8] {
+        &self.0[HEADER_LEN - NONCE_PREFIX_LEN..]
+    }
+}
+
+/// Check if the data starts like an encrypted `.braidz` file.
+pub fn is_encrypted<R: Read>(mut rdr: R) -> std::io::Result<bool> {
+    let mut buf = [0u8; MAGIC.len()];
+    let n = read_full(&mut rdr, &mut buf)?;
+    Ok(n == MAGIC.len() && buf == MAGIC)
+}
+
+/// Check if `path` is an encrypted `.braidz` file. Directories are not.
+pub fn is_encrypted_path<P: AsRef<Path>>(path: P) -> std::io::Result<bool> {
+    let path = path.as_ref();
+    if !path.is_file() {
+        return Ok(false);
+    }
+    is_encrypted(File::open(path)?)
+}
+
+/// The ID of the key with which the data was encrypted.
+pub fn key_id<R: Read>(mut rdr: R) -> Result<KeyId> {
+    Ok(Header::read(&mut rdr)?.key_id())
+}
+
+/// Encrypt the data from `src` to `dest`.
+pub fn encrypt<R: Read, W: Write>(mut src: R, mut dest: W, key: &BraidzKey) -> Result<()> {
+    let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+    OsRng.fill_bytes(&mut nonce_prefix);
+    let header = Header::new(key.id(), &nonce_prefix);
+    dest.write_all(&header.0)?;
+
+    let cipher = Aes256Gcm::new(&key.0.into());
+    let mut encryptor = EncryptorBE32::from_aead(cipher, &nonce_prefix.into());
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = read_full(&mut src, &mut buf)?;
+        let payload = Payload {
+            msg: &buf[..n],
+            aad: &header.0,
+        };
+        if n < CHUNK_SIZE {
+            // The last chunk is shorter, possibly empty.
+            let encrypted = encryptor
+                .encrypt_last(payload)
+                .map_err(|_| Error::EncryptionFailed)?;
+            dest.write_all(&encrypted)?;
+            break;
+        }
+        let encrypted = encryptor
+            .encrypt_next(payload)
+            .map_err(|_| Error::EncryptionFailed)?;
+        dest.write_all(&encrypted)?;
+    }
+    dest.flush()?;
+    Ok(())
+}
+
+/// Decrypt the data from `src` to `dest` with the matching key in `keyring`.
+///
+/// Data is written to `dest` before the integrity of all data is verified.
+/// Discard it if an error is returned.
+pub fn decrypt<R: Read, W: Write>(mut src: R, mut dest: W, keyring: &Keyring) -> Result<()> {
+    let header = Header::read(&mut src)?;
+    let key_id = header.key_id();
+    let key = keyring.get(&key_id).ok_or_else(|| Error::NoMatchingKey {
+        key_id,
+        available: keyring.describe(),
+    })?;
+
+    let cipher = Aes256Gcm::new(&key.0.into());
+    let mut decryptor = DecryptorBE32::from_aead(cipher, header.nonce_prefix().into());
+    let mut buf = vec![0u8; CHUNK_SIZE + TAG_LEN];
+    let mut offset = HEADER_LEN as u64;
+    loop {
+        let n = read_full(&mut src, &mut buf)?;
+        if n < TAG_LEN {
+            // The last chunk, with at least the tag, is missing.
+            return Err(Error::Truncated);
+        }
+        let payload = Payload {
+            msg: &buf[..n],
+            aad: &header.0,
+        };
+        if n < buf.len() {
+            let decrypted = decryptor
+                .decrypt_last(payload)
+                .map_err(|_| Error::IntegrityCheckFailed { offset })?;
+            dest.write_all(&decrypted)?;
+            break;
+        }
+        let decrypted = decryptor
+            .decrypt_next(payload)
+            .map_err(|_| Error::IntegrityCheckFailed { offset })?;
+        dest.write_all(&decrypted)?;
+        offset += n as u64;
+    }
+    dest.flush()?;
+    Ok(())
+}
+
+/// Decrypt the data from `src` into memory.
+pub fn decrypt_to_vec<R: Read>(src: R, keyring: &Keyring) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    decrypt(src, &mut buf, keyring)?;
+    Ok(buf)
+}
+
+/// Replace the file at `path` with its encrypted version.
+pub fn encrypt_file_in_place<P: AsRef<Path>>(path: P, key: &BraidzKey) -> Result<()> {
+    let path = path.as_ref();
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".encrypting");
+    {
+        let src = BufReader::new(File::open(path)?);
+        let dest = BufWriter::new(File::create(&tmp_path)?);
+        encrypt(src, dest, key)?;
+    }
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encrypted(data: &[u8], key: &BraidzKey) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encrypt(data, &mut buf, key).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let key = BraidzKey::generate();
+        let keyring = Keyring::from(key.clone());
+        for len in [0, 10, CHUNK_SIZE, 2 * CHUNK_SIZE + 12345] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let buf = encrypted(&data, &key);
+            assert!(is_encrypted(buf.as_slice()).unwrap());
+            assert_eq!(key_id(buf.as_slice()).unwrap(), key.id());
+            assert_eq!(decrypt_to_vec(buf.as_slice(), &keyring).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let buf = encrypted(b"some data", &BraidzKey::generate());
+        let keyring = Keyring::from(BraidzKey::generate());
+        assert!(matches!(
+            decrypt_to_vec(buf.as_slice(), &keyring),
+            Err(Error::NoMatchingKey { .. })
+        ));
+    }
+
+    #[test]
+    fn test_modified_and_truncated() {
+        let key = BraidzKey::generate();
+        let keyring = Keyring::from(key.clone());
+        let data = vec![42u8; CHUNK_SIZE + 100];
+        let buf = encrypted(&data, &key);
+
+        let mut modified = buf.clone();
+        modified[HEADER_LEN + CHUNK_SIZE + TAG_LEN + 5] ^= 1;
+        assert!(matches!(
+            decrypt_to_vec(modified.as_slice(), &keyring),
+            Err(Error::IntegrityCheckFailed { .. })
+        ));
+
+        let truncated = &buf[..HEADER_LEN + CHUNK_SIZE + TAG_LEN];
+        assert!(matches!(
+            decrypt_to_vec(truncated, &keyring),
+            Err(Error::Truncated)
+        ));
+
+        let truncated = &buf[..buf.len() - 1];
+        assert!(matches!(
+            decrypt_to_vec(truncated, &keyring),
+            Err(Error::IntegrityCheckFailed { .. })
+        ));
+
+        assert!(matches!(
+            decrypt_to_vec(data.as_slice(), &keyring),
+            Err(Error::NotEncrypted)
+        ));
+    }
+
+    #[test]
+    fn test_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let key = BraidzKey::generate();
+        let path = dir.path().join(format!("lab.{KEY_FILE_EXTENSION}"));
+        key.write_file(&path).unwrap();
+        assert!(key.write_file(&path).is_err());
+        assert_eq!(BraidzKey::read_file(&path).unwrap().id(), key.id());
+
+        let mut keyring = Keyring::new();
+        keyring.add_dir(dir.path()).unwrap();
+        assert!(keyring.get(&key.id()).is_some());
+    }
+}
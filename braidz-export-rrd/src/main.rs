@@ -2,7 +2,7 @@ use basic_frame::DynamicFrame;
 use braidz_types::{camera_name_from_filename, CamNum};
 use clap::{Parser, ValueEnum};
 use color_eyre::eyre::{self as anyhow, WrapErr};
-use frame_source::{ImageData, Timestamp};
+use frame_source::{ImageData, ImageDataMode, Timestamp};
 use mp4_writer::Mp4Writer;
 use mvg::rerun_io::{cam_geom_to_rr_pinhole_archetype as to_pinhole, AsRerunTransform3D};
 use rayon::prelude::*;
@@ -281,8 +281,7 @@ impl OfflineBraidzRerunLogger {
             None
         };
 
-        let do_decode_h264 = true;
-        let mut src = frame_source::from_path(&mp4_filename, do_decode_h264)?;
+        let mut src = frame_source::from_path(&mp4_filename, ImageDataMode::Decoded)?;
         tracing::info!("Frame size: {}x{}", src.width(), src.height());
         let start_time = src.frame0_time().unwrap();
         let frametimes = self.frametimes.get(&cam_data.camn).unwrap();
@@ -487,7 +486,7 @@ fn to_rr_image(
     undist_cache: Option<&UndistortionCache>,
 ) -> anyhow::Result<(rerun::EncodedImage, DynamicFrame)> {
     let decoded = match im {
-        ImageData::Decoded(decoded) => decoded,
+        ImageData::Decoded(decoded) | ImageData::Both(decoded, _) => decoded,
         _ => anyhow::bail!("image not decoded"),
     };
 
@@ -661,6 +660,8 @@ fn main() -> anyhow::Result<()> {
                 codec,
                 max_framerate: Default::default(),
                 h264_metadata: None,
+                container_metadata: None,
+                fragment_duration_secs: None,
             };
 
             let my_mp4_writer = mp4_writer::Mp4Writer::new(out_fd, cfg, None).unwrap();
@@ -0,0 +1,95 @@
+//! Compare reading rows with `csv::Reader::into_deserialize` and with
+//! `braidz_parser::rows::RowIter` (parallel with the `rayon` feature).
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use braidz_parser::rows::RowIter;
+use flydra_types::{
+    CamNum, Data2dDistortedRow, FlydraFloatTimestampLocal, KalmanEstimatesRow, SyncFno,
+};
+
+const N_ROWS: usize = 100_000;
+
+fn data2d_csv() -> Vec<u8> {
+    let mut wtr = csv::Writer::from_writer(Vec::new());
+    for i in 0..N_ROWS {
+        let t = 1.6e9 + i as f64 * 0.01;
+        wtr.serialize(Data2dDistortedRow {
+            camn: CamNum((i % 4) as u8),
+            frame: (i / 4) as i64,
+            timestamp: Some(FlydraFloatTimestampLocal::from_f64(t)),
+            cam_received_timestamp: FlydraFloatTimestampLocal::from_f64(t + 0.005),
+            device_timestamp: std::num::NonZeroU64::new(1 + i as u64 * 10_000_000),
+            block_id: std::num::NonZeroU64::new(1 + i as u64),
+            x: 100.0 + (i % 100) as f64 * 1.25,
+            y: 200.0 - (i % 50) as f64 * 0.75,
+            area: 12.5,
+            slope: 0.1,
+            eccentricity: 1.5,
+            frame_pt_idx: 0,
+            cur_val: 200,
+            mean_val: 20.0,
+            sumsqf_val: 400.0,
+        })
+        .unwrap();
+    }
+    wtr.into_inner().unwrap()
+}
+
+fn kalman_estimates_csv() -> Vec<u8> {
+    let mut wtr = csv::Writer::from_writer(Vec::new());
+    for i in 0..N_ROWS {
+        let f = i as f64;
+        wtr.serialize(KalmanEstimatesRow {
+            obj_id: (i / 1000) as u32,
+            frame: SyncFno(i as u64),
+            timestamp: Some(FlydraFloatTimestampLocal::from_f64(1.6e9 + f * 0.01)),
+            x: f * 1e-4,
+            y: -f * 2e-4,
+            z: 0.1,
+            xvel: 0.01,
+            yvel: -0.02,
+            zvel: 0.0,
+            P00: 1e-5,
+            P01: 0.0,
+            P02: 0.0,
+            P11: 1e-5,
+            P12: 0.0,
+            P22: 1e-5,
+            P33: 0.1,
+            P44: 0.1,
+            P55: 0.1,
+        })
+        .unwrap();
+    }
+    wtr.into_inner().unwrap()
+}
+
+fn bench_table<T: serde::de::DeserializeOwned + Send>(c: &mut Criterion, name: &str, buf: &[u8]) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(N_ROWS as u64));
+    group.bench_function("into_deserialize", |b| {
+        b.iter(|| {
+            let rdr = csv::Reader::from_reader(black_box(buf));
+            rdr.into_deserialize::<T>().map(|row| row.unwrap()).count()
+        })
+    });
+    group.bench_function("RowIter", |b| {
+        b.iter(|| {
+            let rdr = csv::Reader::from_reader(black_box(buf));
+            RowIter::<_, T>::new(rdr)
+                .unwrap()
+                .map(|row| row.unwrap())
+                .count()
+        })
+    });
+    group.finish();
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    bench_table::<Data2dDistortedRow>(c, "data2d_distorted", &data2d_csv());
+    bench_table::<KalmanEstimatesRow>(c, "kalman_estimates", &kalman_estimates_csv());
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -1,29 +1,266 @@
 use anyhow::Context;
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+
+use braidz_parser::braidtraj::BRAIDTRAJ_EXTENSION;
 
 #[derive(Debug, Parser)]
-#[command(author, version)]
+#[command(
+    author,
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Opt {
-    /// Input braidz filename
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input braidz (or braidtraj) filename
+    #[arg(required = true)]
+    input: Option<PathBuf>,
 
     /// print all data in the `data2d_distorted` table
     #[arg(short, long)]
     data2d_distorted: bool,
+
+    /// Export the trajectory with this object id to a `.braidtraj` file
+    #[arg(long)]
+    export_traj: Option<u32>,
+
+    /// Output filename of the exported trajectory
+    ///
+    /// Defaults to `<input>.obj<id>.braidtraj`.
+    #[arg(short, long, requires = "export_traj")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Project the 3D estimates into each camera of the calibration and save
+    /// the 2D positions to one CSV file per camera.
+    Reproject {
+        /// Input braidz filename
+        input: PathBuf,
+
+        /// Output directory
+        ///
+        /// Defaults to `<input>.reprojected`.
+        #[arg(short, long)]
+        output_dir: Option<PathBuf>,
+
+        /// Apply the lens distortion, giving positions in raw image
+        /// coordinates like in the `data2d_distorted` table.
+        #[arg(long)]
+        distorted: bool,
+    },
+    /// Export the `data2d_distorted` and `kalman_estimates` tables to HDF5 or
+    /// Apache Parquet files.
+    Export {
+        /// Input braidz filename
+        input: PathBuf,
+
+        /// Output file format
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Parquet)]
+        format: ExportFormat,
+
+        /// Output filename (HDF5) or directory (Parquet)
+        ///
+        /// Defaults to `<input>.h5` or `<input>.parquet`.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Print statistics of each trajectory and quality metrics of the 2D
+    /// data.
+    Stats {
+        /// Input braidz filename
+        input: PathBuf,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = StatsFormat::Table)]
+        format: StatsFormat,
+    },
+    /// Stitch trajectories broken into several obj_ids and save the result to
+    /// a new braidz file.
+    ///
+    /// A trajectory is continued by one starting shortly after it ends near
+    /// the position predicted from its last position and velocity. The
+    /// `kalman_estimates` table of the output has the new obj_ids and an
+    /// `original_obj_id` column.
+    Stitch {
+        /// Input braidz filename
+        input: PathBuf,
+
+        /// Output braidz filename
+        ///
+        /// Defaults to `<input stem>.stitched.braidz`.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Maximum number of frames between two trajectories
+        #[arg(long, default_value_t = braidz_parser::stitch::StitchParams::default().max_gap_frames)]
+        max_gap_frames: u64,
+
+        /// Maximum distance, in meters, from the predicted position
+        #[arg(long, default_value_t = braidz_parser::stitch::StitchParams::default().max_distance)]
+        max_distance: f64,
+    },
+    /// Smooth the trajectories with a forward-backward (RTS) smoother and
+    /// optionally resample them to a uniform rate.
+    ///
+    /// If the output ends with `.braidz`, the smoothed (and resampled) tables
+    /// are saved in a copy of the input. Otherwise, the output is a CSV file
+    /// with the smoothed, or if `--resample-fps` is given the resampled, rows.
+    Smooth {
+        /// Input braidz filename
+        input: PathBuf,
+
+        /// Output braidz or CSV filename
+        ///
+        /// Defaults to `<input>.smoothed.csv`.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Resample the smoothed trajectories at this rate (in frames per
+        /// second)
+        #[arg(long)]
+        resample_fps: Option<f64>,
+
+        /// Scale of the process noise of the motion model
+        ///
+        /// Defaults to the value used for tracking.
+        #[arg(long)]
+        motion_noise_scale: Option<f64>,
+
+        /// Standard deviation of the saved positions, in meters
+        ///
+        /// Defaults to the saved covariance of each position.
+        #[arg(long)]
+        observation_std: Option<f64>,
+    },
+    /// Convert a legacy flydra HDF5 file, saved by the flydra mainbrain, to a
+    /// braidz file (requires the `hdf5` feature).
+    ///
+    /// The `cam_info`, `data2d_distorted`, `kalman_estimates` and `textlog`
+    /// tables are converted. A report of what could not be converted is
+    /// printed and saved in the braidz file.
+    ConvertH5 {
+        /// Input HDF5 filename
+        input: PathBuf,
+
+        /// Output braidz filename
+        ///
+        /// Defaults to `<input stem>.braidz`.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Generate a new key for encrypting braidz files.
+    Keygen {
+        /// Output key filename. Must not exist.
+        key_file: PathBuf,
+    },
+    /// Decrypt an encrypted braidz file.
+    Decrypt {
+        /// Input encrypted braidz filename
+        input: PathBuf,
+
+        /// Output braidz filename. Must not exist.
+        output: PathBuf,
+
+        /// Key file. May be given multiple times.
+        #[arg(short, long)]
+        key_file: Vec<PathBuf>,
+
+        /// Directory with key files (named `*.key`).
+        #[arg(long)]
+        keyring: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum ExportFormat {
+    /// HDF5 file in the pytables layout of flydra (requires the `hdf5`
+    /// feature)
+    Hdf5,
+    /// Directory with one Apache Parquet file per table
+    Parquet,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum StatsFormat {
+    /// Human readable tables
+    Table,
+    /// JSON
+    Json,
 }
 
 fn main() -> anyhow::Result<()> {
     env_tracing_logger::init();
     let opt = Opt::parse();
-    let attr = std::fs::metadata(&opt.input)
-        .with_context(|| format!("Getting file metadata for {}", opt.input.display()))?;
 
-    let mut archive = braidz_parser::braidz_parse_path(&opt.input)
-        .with_context(|| format!("Parsing file {}", opt.input.display()))?;
+    match opt.command {
+        Some(Command::Reproject {
+            input,
+            output_dir,
+            distorted,
+        }) => return reproject(&input, output_dir, distorted),
+        Some(Command::Export {
+            input,
+            format,
+            output,
+        }) => return export(&input, format, output),
+        Some(Command::Stats { input, format }) => return stats(&input, format),
+        Some(Command::Stitch {
+            input,
+            output,
+            max_gap_frames,
+            max_distance,
+        }) => {
+            let params = braidz_parser::stitch::StitchParams {
+                max_gap_frames,
+                max_distance,
+            };
+            return stitch(&input, output, &params);
+        }
+        Some(Command::Smooth {
+            input,
+            output,
+            resample_fps,
+            motion_noise_scale,
+            observation_std,
+        }) => {
+            return smooth(
+                &input,
+                output,
+                resample_fps,
+                motion_noise_scale,
+                observation_std,
+            )
+        }
+        Some(Command::ConvertH5 { input, output }) => return convert_h5(&input, output),
+        Some(Command::Keygen { key_file }) => return keygen(&key_file),
+        Some(Command::Decrypt {
+            input,
+            output,
+            key_file,
+            keyring,
+        }) => return decrypt(&input, &output, &key_file, keyring.as_deref()),
+        None => {}
+    }
+    // Without a subcommand, the input is required.
+    let input = opt.input.unwrap();
+
+    if input.extension() == Some(std::ffi::OsStr::new(BRAIDTRAJ_EXTENSION)) {
+        return show_braidtraj(&input);
+    }
+
+    let attr = std::fs::metadata(&input)
+        .with_context(|| format!("Getting file metadata for {}", input.display()))?;
+
+    let mut archive = braidz_parser::braidz_parse_path(&input)
+        .with_context(|| format!("Parsing file {}", input.display()))?;
 
     let summary =
-        braidz_parser::summarize_braidz(&archive, opt.input.display().to_string(), attr.len());
+        braidz_parser::summarize_braidz(&archive, input.display().to_string(), attr.len());
 
     let yaml_buf = serde_yaml::to_string(&summary)?;
     println!("{}", yaml_buf);
@@ -35,5 +272,317 @@ fn main() -> anyhow::Result<()> {
         }
     }
 
+    if let Some(obj_id) = opt.export_traj {
+        let traj = braidz_parser::braidtraj::export_trajectory(&archive, obj_id)
+            .with_context(|| format!("Exporting trajectory {obj_id}"))?;
+        let output = opt.output.unwrap_or_else(|| {
+            let mut output = input.clone().into_os_string();
+            output.push(format!(".obj{obj_id}.{BRAIDTRAJ_EXTENSION}"));
+            output.into()
+        });
+        let fd = std::fs::File::create(&output)
+            .with_context(|| format!("Creating file {}", output.display()))?;
+        braidz_parser::braidtraj::write_braidtraj(std::io::BufWriter::new(fd), &traj)?;
+        println!(
+            "Saved {} rows of trajectory {obj_id} to {}",
+            traj.rows.len(),
+            output.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Verify and summarize a `.braidtraj` file.
+fn show_braidtraj(path: &Path) -> anyhow::Result<()> {
+    let traj = braidz_parser::braidtraj::braidtraj_parse_path(path)
+        .with_context(|| format!("Reading file {}", path.display()))?;
+    println!("{}", serde_yaml::to_string(&traj.source)?);
+    println!("obj_id: {}", traj.obj_id);
+    println!("num_rows: {}", traj.rows.len());
+    if let (Some(first), Some(last)) = (traj.rows.first(), traj.rows.last()) {
+        println!("frame_limits: [{}, {}]", first.frame, last.frame);
+    }
+    println!("rows_sha256: {} (verified)", traj.rows_sha256);
+    Ok(())
+}
+
+/// Save the 3D estimates projected into each camera.
+fn reproject(input: &Path, output_dir: Option<PathBuf>, distorted: bool) -> anyhow::Result<()> {
+    let archive = braidz_parser::braidz_parse_path(input)
+        .with_context(|| format!("Parsing file {}", input.display()))?;
+    let per_cam = braidz_parser::reproject::reproject(&archive, distorted)
+        .with_context(|| format!("Reprojecting {}", input.display()))?;
+
+    let output_dir = output_dir.unwrap_or_else(|| {
+        let mut output = input.to_path_buf().into_os_string();
+        output.push(".reprojected");
+        output.into()
+    });
+    std::fs::create_dir_all(&output_dir)
+        .with_context(|| format!("Creating directory {}", output_dir.display()))?;
+
+    for (cam_name, rows) in per_cam.iter() {
+        let path = output_dir.join(format!("{cam_name}.csv"));
+        let mut wtr = csv::Writer::from_path(&path)
+            .with_context(|| format!("Creating file {}", path.display()))?;
+        for row in rows.iter() {
+            wtr.serialize(row)?;
+        }
+        wtr.flush()?;
+        println!("Saved {} rows to {}", rows.len(), path.display());
+    }
+    Ok(())
+}
+
+/// Export the tables to HDF5 or Parquet files.
+fn export(input: &Path, format: ExportFormat, output: Option<PathBuf>) -> anyhow::Result<()> {
+    let mut archive = braidz_parser::braidz_parse_path_streaming(input)
+        .with_context(|| format!("Parsing file {}", input.display()))?;
+    let output = output.unwrap_or_else(|| {
+        let mut output = input.to_path_buf().into_os_string();
+        output.push(match format {
+            ExportFormat::Hdf5 => ".h5",
+            ExportFormat::Parquet => ".parquet",
+        });
+        output.into()
+    });
+
+    match format {
+        ExportFormat::Hdf5 => {
+            #[cfg(feature = "hdf5")]
+            {
+                braidz_parser::export::export_hdf5(&mut archive, &output)
+                    .with_context(|| format!("Exporting to {}", output.display()))?;
+                println!("Saved {}", output.display());
+            }
+            #[cfg(not(feature = "hdf5"))]
+            anyhow::bail!("HDF5 export requires braidz-cli to be built with the `hdf5` feature.");
+        }
+        ExportFormat::Parquet => {
+            let written = braidz_parser::export::export_parquet(&mut archive, &output)
+                .with_context(|| format!("Exporting to {}", output.display()))?;
+            for path in written.iter() {
+                println!("Saved {}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Print trajectory statistics and data quality metrics.
+fn stats(input: &Path, format: StatsFormat) -> anyhow::Result<()> {
+    let mut archive = braidz_parser::braidz_parse_path(input)
+        .with_context(|| format!("Parsing file {}", input.display()))?;
+    let stats = braidz_parser::stats::compute_stats(&mut archive)
+        .with_context(|| format!("Computing statistics of {}", input.display()))?;
+
+    if let StatsFormat::Json = format {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    let fmt_opt = |v: Option<f64>, precision: usize| match v {
+        Some(v) => format!("{v:.precision$}"),
+        None => "-".to_string(),
+    };
+
+    println!("Trajectories: {}", stats.trajectories.len());
+    println!(
+        "{:>8} {:>10} {:>10} {:>8} {:>10} {:>10} {:>10} {:>8} {:>6} {:>10} {:>10}",
+        "obj_id",
+        "start",
+        "end",
+        "rows",
+        "dur (s)",
+        "path (m)",
+        "speed",
+        "obs",
+        "cams",
+        "reproj",
+        "reproj max",
+    );
+    for t in stats.trajectories.iter() {
+        println!(
+            "{:>8} {:>10} {:>10} {:>8} {:>10} {:>10.3} {:>10} {:>8} {:>6} {:>10} {:>10}",
+            t.obj_id,
+            t.start_frame,
+            t.end_frame,
+            t.num_rows,
+            fmt_opt(t.duration, 3),
+            t.path_length,
+            fmt_opt(t.mean_speed, 3),
+            t.num_observations,
+            t.num_cameras,
+            fmt_opt(t.reprojection_error.as_ref().map(|e| e.mean), 2),
+            fmt_opt(t.reprojection_error.as_ref().map(|e| e.max), 2),
+        );
+    }
+    println!("(speed in m/s, reprojection error in pixels)");
+    println!();
+
+    println!("Cameras:");
+    println!("{:>20} {:>10} {:>10}", "cam_id", "frames", "missing");
+    for cam in stats.cameras.iter() {
+        println!(
+            "{:>20} {:>10} {:>10}",
+            cam.cam_id, cam.num_frames, cam.missing_frames
+        );
+    }
+    if !stats.save_empty_data2d {
+        println!("(empty 2D data not saved: frames without detections count as missing)");
+    }
+    println!();
+
+    let total_gap: u64 = stats.sync_gaps.iter().map(|g| g.num_frames).sum();
+    println!(
+        "Gaps without 2D data from any camera: {} ({total_gap} frames)",
+        stats.sync_gaps.len()
+    );
+    for gap in stats.sync_gaps.iter() {
+        println!(
+            "  frames {}-{} ({} frames)",
+            gap.start_frame,
+            gap.start_frame + gap.num_frames as i64 - 1,
+            gap.num_frames
+        );
+    }
+    Ok(())
+}
+
+/// Stitch broken trajectories and save a new `.braidz` file.
+fn stitch(
+    input: &Path,
+    output: Option<PathBuf>,
+    params: &braidz_parser::stitch::StitchParams,
+) -> anyhow::Result<()> {
+    let output = output.unwrap_or_else(|| input.with_extension("stitched.braidz"));
+    let result = braidz_parser::stitch::stitch_braidz(input, &output, params)
+        .with_context(|| format!("Stitching {}", input.display()))?;
+    println!(
+        "Stitched {} trajectories into {}. Saved {}",
+        result.num_trajectories_before,
+        result.num_trajectories_after,
+        output.display()
+    );
+    Ok(())
+}
+
+/// Smooth and resample the trajectories.
+fn smooth(
+    input: &Path,
+    output: Option<PathBuf>,
+    resample_fps: Option<f64>,
+    motion_noise_scale: Option<f64>,
+    observation_std: Option<f64>,
+) -> anyhow::Result<()> {
+    use braidz_parser::smooth::{resample, smooth_braidz, SmoothParams};
+
+    let archive = braidz_parser::braidz_parse_path(input)
+        .with_context(|| format!("Parsing file {}", input.display()))?;
+    let mut params = SmoothParams::from_archive(&archive)?;
+    if let Some(motion_noise_scale) = motion_noise_scale {
+        params.motion_noise_scale = motion_noise_scale;
+    }
+    params.observation_std = observation_std;
+
+    let output = output.unwrap_or_else(|| {
+        let mut output = input.to_path_buf().into_os_string();
+        output.push(".smoothed.csv");
+        output.into()
+    });
+    if output.extension().is_some_and(|ext| ext == "braidz") {
+        smooth_braidz(input, &output, &params, resample_fps)
+            .with_context(|| format!("Smoothing {}", input.display()))?;
+        println!("Saved {}", output.display());
+        return Ok(());
+    }
+
+    let table = archive
+        .kalman_estimates_table
+        .as_ref()
+        .context("No Kalman estimates in archive")?;
+    let smoothed = braidz_parser::smooth::smooth(table, archive.expected_fps, &params)?;
+    let mut wtr = csv::Writer::from_path(&output)
+        .with_context(|| format!("Creating file {}", output.display()))?;
+    let num_rows = if let Some(resample_fps) = resample_fps {
+        let resampled = resample(&smoothed, archive.expected_fps, resample_fps);
+        for row in resampled.iter() {
+            wtr.serialize(row)?;
+        }
+        resampled.len()
+    } else {
+        for row in smoothed.iter() {
+            wtr.serialize(row)?;
+        }
+        smoothed.len()
+    };
+    wtr.flush()?;
+    println!("Saved {num_rows} rows to {}", output.display());
+    Ok(())
+}
+
+/// Convert a legacy flydra HDF5 file to a `.braidz` file.
+#[cfg(feature = "hdf5")]
+fn convert_h5(input: &Path, output: Option<PathBuf>) -> anyhow::Result<()> {
+    let output = output.unwrap_or_else(|| input.with_extension("braidz"));
+    let report = braidz_parser::flydra_h5::convert_flydra_h5(input, &output)
+        .with_context(|| format!("Converting {}", input.display()))?;
+    print!("{}", serde_yaml::to_string(&report)?);
+    println!("Saved {}", output.display());
+    Ok(())
+}
+
+/// Convert a legacy flydra HDF5 file to a `.braidz` file.
+#[cfg(not(feature = "hdf5"))]
+fn convert_h5(_input: &Path, _output: Option<PathBuf>) -> anyhow::Result<()> {
+    anyhow::bail!("HDF5 conversion requires braidz-cli to be built with the `hdf5` feature.");
+}
+
+/// Generate a new encryption key and save it.
+fn keygen(key_file: &Path) -> anyhow::Result<()> {
+    let key = braidz_crypt::BraidzKey::generate();
+    key.write_file(key_file)
+        .with_context(|| format!("Writing key file {}", key_file.display()))?;
+    println!("Saved key {} to {}", key.id(), key_file.display());
+    Ok(())
+}
+
+/// Decrypt an encrypted `.braidz` file.
+fn decrypt(
+    input: &Path,
+    output: &Path,
+    key_files: &[PathBuf],
+    keyring_dir: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut keyring = braidz_crypt::Keyring::new();
+    for key_file in key_files.iter() {
+        let key = braidz_crypt::BraidzKey::read_file(key_file)
+            .with_context(|| format!("Reading key file {}", key_file.display()))?;
+        keyring.add(key);
+    }
+    if let Some(dir) = keyring_dir {
+        keyring
+            .add_dir(dir)
+            .with_context(|| format!("Reading keys in {}", dir.display()))?;
+    }
+    if keyring.is_empty() {
+        anyhow::bail!("No key given. Use `--key-file` or `--keyring`.");
+    }
+
+    let rdr = std::io::BufReader::new(
+        std::fs::File::open(input).with_context(|| format!("Opening {}", input.display()))?,
+    );
+    let fd = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(output)
+        .with_context(|| format!("Creating file {}", output.display()))?;
+    if let Err(e) = braidz_crypt::decrypt(rdr, std::io::BufWriter::new(fd), &keyring) {
+        // Do not leave partially decrypted data.
+        let _ = std::fs::remove_file(output);
+        return Err(e).with_context(|| format!("Decrypting {}", input.display()));
+    }
+    println!("Saved decrypted file to {}", output.display());
     Ok(())
 }
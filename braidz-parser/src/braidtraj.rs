@@ -0,0 +1,177 @@
+//! Export and import of single trajectories as `.braidtraj` files.
+//!
+//! A `.braidtraj` file is a JSON serialization of [BraidTraj]. It contains
+//! the `kalman_estimates` rows of a single trajectory together with the
+//! metadata and SHA-256 hash of the source braidz file. The rows are
+//! checksummed so that corrupted or edited files are detected on import.
+
+use std::io::{Read, Seek, Write};
+
+use sha2::Digest;
+
+use braidz_types::{
+    BraidTraj, BraidTrajSource, KalmanEstimatesRow, BRAIDTRAJ_FORMAT, BRAIDTRAJ_FORMAT_VERSION,
+};
+
+use crate::{BraidzArchive, Error};
+
+/// The filename extension of braidtraj files (without leading dot).
+pub const BRAIDTRAJ_EXTENSION: &str = "braidtraj";
+
+/// Extract the trajectory with `obj_id` from a braidz archive.
+///
+/// If the archive is a braidz file, the file is hashed to record its
+/// provenance. This reads the entire file.
+pub fn export_trajectory<R: Read + Seek>(
+    archive: &BraidzArchive<R>,
+    obj_id: u32,
+) -> Result<BraidTraj, Error> {
+    let (Some(kalman_estimates_info), Some(table)) = (
+        archive.kalman_estimates_info.as_ref(),
+        archive.kalman_estimates_table.as_ref(),
+    ) else {
+        return Err(Error::MissingKalmanEstimates);
+    };
+
+    let rows: Vec<KalmanEstimatesRow> = table
+        .iter()
+        .filter(|row| row.obj_id == obj_id)
+        .cloned()
+        .collect();
+    if rows.is_empty() {
+        return Err(Error::UnknownObjId(obj_id));
+    }
+
+    let path = archive.path();
+    let sha256 = if path.is_file() {
+        let mut hasher = sha2::Sha256::new();
+        std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+        Some(hex::encode(hasher.finalize()))
+    } else {
+        None
+    };
+    let filename = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_else(|| archive.display().to_string());
+
+    Ok(BraidTraj {
+        format: BRAIDTRAJ_FORMAT.to_string(),
+        format_version: BRAIDTRAJ_FORMAT_VERSION,
+        obj_id,
+        source: BraidTrajSource {
+            filename,
+            sha256,
+            metadata: archive.metadata.clone(),
+        },
+        expected_fps: archive.expected_fps,
+        tracking_parameters: kalman_estimates_info.tracking_parameters.clone(),
+        rows_sha256: rows_sha256(&rows)?,
+        rows,
+    })
+}
+
+/// Write a trajectory as JSON.
+pub fn write_braidtraj<W: Write>(wtr: W, traj: &BraidTraj) -> Result<(), Error> {
+    serde_json::to_writer(wtr, traj)?;
+    Ok(())
+}
+
+/// Read a trajectory and verify its checksum.
+pub fn read_braidtraj<R: Read>(rdr: R) -> Result<BraidTraj, Error> {
+    let traj: BraidTraj = serde_json::from_reader(rdr)?;
+    if traj.format != BRAIDTRAJ_FORMAT || traj.format_version > BRAIDTRAJ_FORMAT_VERSION {
+        return Err(Error::UnsupportedBraidtraj {
+            format: traj.format,
+            format_version: traj.format_version,
+        });
+    }
+    let found = rows_sha256(&traj.rows)?;
+    if found != traj.rows_sha256 {
+        return Err(Error::ChecksumMismatch {
+            expected: traj.rows_sha256,
+            found,
+        });
+    }
+    Ok(traj)
+}
+
+/// Read a trajectory from a `.braidtraj` file and verify its checksum.
+pub fn braidtraj_parse_path<P: AsRef<std::path::Path>>(path: P) -> Result<BraidTraj, Error> {
+    let rdr = std::io::BufReader::new(std::fs::File::open(path)?);
+    read_braidtraj(rdr)
+}
+
+fn rows_sha256(rows: &[KalmanEstimatesRow]) -> Result<String, Error> {
+    let buf = serde_json::to_vec(rows)?;
+    Ok(hex::encode(sha2::Sha256::digest(&buf)))
+}
+
+#[test]
+fn test_braidtraj_roundtrip() {
+    use braidz_types::BraidMetadata;
+    use flydra_types::{FlydraFloatTimestampLocal, SyncFno};
+
+    let rows: Vec<KalmanEstimatesRow> = (0..3)
+        .map(|i| KalmanEstimatesRow {
+            obj_id: 7,
+            frame: SyncFno(100 + i),
+            timestamp: if i == 0 {
+                None
+            } else {
+                Some(FlydraFloatTimestampLocal::from_f64(1.7e9 + 0.01 * i as f64))
+            },
+            x: 0.1 * i as f64,
+            y: -0.2,
+            z: 1.0 / 3.0,
+            xvel: 0.0,
+            yvel: 0.0,
+            zvel: 0.0,
+            P00: 1e-5,
+            P01: 0.0,
+            P02: 0.0,
+            P11: 1e-5,
+            P12: 0.0,
+            P22: 1e-5,
+            P33: 0.1,
+            P44: 0.1,
+            P55: 0.1,
+        })
+        .collect();
+    let traj = BraidTraj {
+        format: BRAIDTRAJ_FORMAT.to_string(),
+        format_version: BRAIDTRAJ_FORMAT_VERSION,
+        obj_id: 7,
+        source: BraidTrajSource {
+            filename: "test.braidz".to_string(),
+            sha256: None,
+            metadata: BraidMetadata {
+                schema: 3,
+                git_revision: "abc".to_string(),
+                original_recording_time: None,
+                save_empty_data2d: true,
+                saving_program_name: "braid".to_string(),
+                provenance: vec![],
+                tracking_determinism: None,
+            },
+        },
+        expected_fps: 100.0,
+        tracking_parameters: flydra_types::default_tracking_params_full_3d(),
+        rows_sha256: rows_sha256(&rows).unwrap(),
+        rows,
+    };
+
+    let mut buf = Vec::new();
+    write_braidtraj(&mut buf, &traj).unwrap();
+    let loaded = read_braidtraj(buf.as_slice()).unwrap();
+    assert_eq!(loaded.rows.len(), 3);
+    assert!(loaded.rows[0].timestamp.is_none());
+    assert_eq!(loaded.rows[2].x, traj.rows[2].x);
+
+    // Modify the data without updating the checksum.
+    let tampered = String::from_utf8(buf).unwrap().replace("-0.2", "-0.3");
+    assert!(matches!(
+        read_braidtraj(tampered.as_bytes()),
+        Err(Error::ChecksumMismatch { .. })
+    ));
+}
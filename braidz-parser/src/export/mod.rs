@@ -0,0 +1,132 @@
+//! Export the tables of a `.braidz` file to other formats.
+//!
+//! The `data2d_distorted` and `kalman_estimates` tables can be exported to
+//! HDF5 in the pytables layout of the original flydra (with the `hdf5`
+//! feature) and to Apache Parquet (with the `parquet` feature) for analysis
+//! with Python or R. The units of each column and the metadata of the
+//! `.braidz` file are saved with the tables. The rows are read in chunks and
+//! are not all kept in memory.
+
+use std::io::{Read, Seek};
+
+use crate::{BraidzArchive, Error};
+
+#[cfg(feature = "hdf5")]
+mod to_hdf5;
+#[cfg(feature = "hdf5")]
+pub use to_hdf5::export_hdf5;
+
+#[cfg(feature = "parquet")]
+mod to_parquet;
+#[cfg(feature = "parquet")]
+pub use to_parquet::export_parquet;
+
+/// A column of an exported table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColumnInfo {
+    pub name: &'static str,
+    /// The units of the values. Empty if the values have no units.
+    pub units: &'static str,
+}
+
+const fn col(name: &'static str, units: &'static str) -> ColumnInfo {
+    ColumnInfo { name, units }
+}
+
+/// The columns of the exported `data2d_distorted` table.
+///
+/// Timestamps are seconds since the UNIX epoch. In HDF5 files, missing values
+/// are NaN (floating point columns) or 0 (integer columns). In Parquet files,
+/// they are null.
+pub const DATA2D_DISTORTED_COLUMNS: &[ColumnInfo] = &[
+    col("camn", ""),
+    col("frame", "frames"),
+    col("timestamp", "s"),
+    col("cam_received_timestamp", "s"),
+    col("device_timestamp", ""),
+    col("block_id", "frames"),
+    col("x", "pixels"),
+    col("y", "pixels"),
+    col("area", "pixels^2"),
+    col("slope", ""),
+    col("eccentricity", ""),
+    col("frame_pt_idx", ""),
+    col("cur_val", "intensity"),
+    col("mean_val", "intensity"),
+    col("sumsqf_val", "intensity^2"),
+];
+
+/// The columns of the exported `kalman_estimates` table.
+///
+/// Missing values are as in [DATA2D_DISTORTED_COLUMNS].
+pub const KALMAN_ESTIMATES_COLUMNS: &[ColumnInfo] = &[
+    col("obj_id", ""),
+    col("frame", "frames"),
+    col("timestamp", "s"),
+    col("x", "m"),
+    col("y", "m"),
+    col("z", "m"),
+    col("xvel", "m/s"),
+    col("yvel", "m/s"),
+    col("zvel", "m/s"),
+    col("P00", "m^2"),
+    col("P01", "m^2"),
+    col("P02", "m^2"),
+    col("P11", "m^2"),
+    col("P12", "m^2"),
+    col("P22", "m^2"),
+    col("P33", "(m/s)^2"),
+    col("P44", "(m/s)^2"),
+    col("P55", "(m/s)^2"),
+];
+
+/// Number of rows written at once.
+#[cfg(any(feature = "hdf5", feature = "parquet"))]
+const CHUNK_SIZE: usize = 100_000;
+
+/// The metadata of the `.braidz` file saved with the exported tables.
+pub fn metadata_attributes<R: Read + Seek>(
+    archive: &BraidzArchive<R>,
+) -> Result<Vec<(&'static str, String)>, Error> {
+    let md = &archive.metadata;
+    let mut attrs = vec![
+        ("source", archive.path().display().to_string()),
+        ("braid_schema", md.schema.to_string()),
+        ("git_revision", md.git_revision.clone()),
+        ("saving_program_name", md.saving_program_name.clone()),
+        ("expected_fps", archive.expected_fps.to_string()),
+    ];
+    if let Some(t) = &md.original_recording_time {
+        attrs.push(("original_recording_time", t.to_rfc3339()));
+    }
+    if let Some(info) = &archive.kalman_estimates_info {
+        attrs.push((
+            "tracking_params",
+            serde_json::to_string(&info.tracking_parameters)?,
+        ));
+    }
+    Ok(attrs)
+}
+
+/// Call `f` with consecutive chunks of up to [CHUNK_SIZE] rows.
+#[cfg(any(feature = "hdf5", feature = "parquet"))]
+fn for_each_chunk<T, I, F>(rows: I, mut f: F) -> Result<(), Error>
+where
+    I: Iterator<Item = Result<T, csv::Error>>,
+    F: FnMut(Vec<T>) -> Result<(), Error>,
+{
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    for row in rows {
+        chunk.push(row?);
+        if chunk.len() == CHUNK_SIZE {
+            f(std::mem::replace(
+                &mut chunk,
+                Vec::with_capacity(CHUNK_SIZE),
+            ))?;
+        }
+    }
+    if !chunk.is_empty() {
+        f(chunk)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,233 @@
+//! Export to HDF5 files in the pytables layout of the original flydra.
+//!
+//! Each table is a one-dimensional dataset of a compound type at the root of
+//! the file, with the attributes pytables uses to recognize tables. The units
+//! of column `i` are in the attribute `FIELD_<i>_UNITS`. The metadata of the
+//! `.braidz` file are attributes of the root group.
+
+use std::{
+    io::{Read, Seek},
+    path::Path,
+};
+
+use hdf5::{types::VarLenUnicode, H5Type, Location};
+
+use braidz_types::{Data2dDistortedRow, KalmanEstimatesRow};
+
+use super::{
+    for_each_chunk, metadata_attributes, ColumnInfo, CHUNK_SIZE, DATA2D_DISTORTED_COLUMNS,
+    KALMAN_ESTIMATES_COLUMNS,
+};
+use crate::{BraidzArchive, Error};
+
+#[derive(H5Type, Clone, Copy, Debug)]
+#[repr(C)]
+struct H5Data2dDistortedRow {
+    camn: u8,
+    frame: i64,
+    timestamp: f64,
+    cam_received_timestamp: f64,
+    device_timestamp: u64,
+    block_id: u64,
+    x: f64,
+    y: f64,
+    area: f64,
+    slope: f64,
+    eccentricity: f64,
+    frame_pt_idx: u8,
+    cur_val: u8,
+    mean_val: f64,
+    sumsqf_val: f64,
+}
+
+impl From<Data2dDistortedRow> for H5Data2dDistortedRow {
+    fn from(row: Data2dDistortedRow) -> Self {
+        Self {
+            camn: row.camn.0,
+            frame: row.frame,
+            timestamp: row.timestamp.map(|t| t.as_f64()).unwrap_or(f64::NAN),
+            cam_received_timestamp: row.cam_received_timestamp.as_f64(),
+            device_timestamp: row.device_timestamp.map(|v| v.get()).unwrap_or(0),
+            block_id: row.block_id.map(|v| v.get()).unwrap_or(0),
+            x: row.x,
+            y: row.y,
+            area: row.area,
+            slope: row.slope,
+            eccentricity: row.eccentricity,
+            frame_pt_idx: row.frame_pt_idx,
+            cur_val: row.cur_val,
+            mean_val: row.mean_val,
+            sumsqf_val: row.sumsqf_val,
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(H5Type, Clone, Copy, Debug)]
+#[repr(C)]
+struct H5KalmanEstimatesRow {
+    obj_id: u32,
+    frame: i64,
+    timestamp: f64,
+    x: f64,
+    y: f64,
+    z: f64,
+    xvel: f64,
+    yvel: f64,
+    zvel: f64,
+    P00: f64,
+    P01: f64,
+    P02: f64,
+    P11: f64,
+    P12: f64,
+    P22: f64,
+    P33: f64,
+    P44: f64,
+    P55: f64,
+}
+
+impl From<KalmanEstimatesRow> for H5KalmanEstimatesRow {
+    fn from(row: KalmanEstimatesRow) -> Self {
+        Self {
+            obj_id: row.obj_id,
+            frame: row.frame.0 as i64,
+            timestamp: row.timestamp.map(|t| t.as_f64()).unwrap_or(f64::NAN),
+            x: row.x,
+            y: row.y,
+            z: row.z,
+            xvel: row.xvel,
+            yvel: row.yvel,
+            zvel: row.zvel,
+            P00: row.P00,
+            P01: row.P01,
+            P02: row.P02,
+            P11: row.P11,
+            P12: row.P12,
+            P22: row.P22,
+            P33: row.P33,
+            P44: row.P44,
+            P55: row.P55,
+        }
+    }
+}
+
+fn write_str_attr(loc: &Location, name: &str, value: &str) -> Result<(), Error> {
+    // Strings with interior nul bytes cannot be saved.
+    let value: VarLenUnicode = value.replace('\0', "").parse().unwrap();
+    loc.new_attr::<VarLenUnicode>()
+        .create(name)?
+        .write_scalar(&value)?;
+    Ok(())
+}
+
+/// Incrementally write a pytables table.
+struct TableWriter {
+    ds: hdf5::Dataset,
+    nrows: usize,
+}
+
+impl TableWriter {
+    fn new<T: H5Type>(
+        file: &hdf5::File,
+        name: &str,
+        title: &str,
+        columns: &[ColumnInfo],
+    ) -> Result<Self, Error> {
+        let ds = file
+            .new_dataset::<T>()
+            .chunk(CHUNK_SIZE)
+            .shape(0..)
+            .create(name)?;
+        write_str_attr(&ds, "CLASS", "TABLE")?;
+        write_str_attr(&ds, "VERSION", "2.7")?;
+        write_str_attr(&ds, "TITLE", title)?;
+        for (i, column) in columns.iter().enumerate() {
+            write_str_attr(&ds, &format!("FIELD_{i}_NAME"), column.name)?;
+            write_str_attr(&ds, &format!("FIELD_{i}_UNITS"), column.units)?;
+        }
+        Ok(Self { ds, nrows: 0 })
+    }
+
+    fn append<T: H5Type>(&mut self, rows: &[T]) -> Result<(), Error> {
+        let start = self.nrows;
+        self.nrows += rows.len();
+        self.ds.resize(self.nrows)?;
+        self.ds.write_slice(rows, start..self.nrows)?;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        self.ds
+            .new_attr::<i64>()
+            .create("NROWS")?
+            .write_scalar(&(self.nrows as i64))?;
+        Ok(())
+    }
+}
+
+/// Export the `data2d_distorted` and (if present) `kalman_estimates` tables
+/// to the HDF5 file `output`.
+///
+/// The file can be read with pytables, e.g.
+/// `tables.open_file(output).root.kalman_estimates[:]`.
+pub fn export_hdf5<R: Read + Seek, P: AsRef<Path>>(
+    archive: &mut BraidzArchive<R>,
+    output: P,
+) -> Result<(), Error> {
+    let file = hdf5::File::create(output)?;
+    for (name, value) in metadata_attributes(archive)? {
+        write_str_attr(&file, name, &value)?;
+    }
+
+    let mut table = TableWriter::new::<H5Data2dDistortedRow>(
+        &file,
+        "data2d_distorted",
+        "2d data",
+        DATA2D_DISTORTED_COLUMNS,
+    )?;
+    for_each_chunk(archive.iter_data2d_distorted()?, |rows| {
+        let rows: Vec<H5Data2dDistortedRow> = rows.into_iter().map(Into::into).collect();
+        table.append(&rows)
+    })?;
+    table.finish()?;
+
+    if archive.kalman_estimates_info.is_some() {
+        let mut table = TableWriter::new::<H5KalmanEstimatesRow>(
+            &file,
+            "kalman_estimates",
+            "Kalman a posteriori estimates of tracked object",
+            KALMAN_ESTIMATES_COLUMNS,
+        )?;
+        for_each_chunk(archive.iter_kalman_estimates()?, |rows| {
+            let rows: Vec<H5KalmanEstimatesRow> = rows.into_iter().map(Into::into).collect();
+            table.append(&rows)
+        })?;
+        table.finish()?;
+    }
+
+    file.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_hdf5_columns() {
+    use hdf5::types::TypeDescriptor;
+
+    fn field_names<T: H5Type>() -> Vec<String> {
+        match T::type_descriptor() {
+            TypeDescriptor::Compound(c) => c.fields.into_iter().map(|f| f.name).collect(),
+            _ => panic!("not a compound type"),
+        }
+    }
+    let names = |columns: &[ColumnInfo]| -> Vec<String> {
+        columns.iter().map(|c| c.name.to_string()).collect()
+    };
+    assert_eq!(
+        field_names::<H5Data2dDistortedRow>(),
+        names(DATA2D_DISTORTED_COLUMNS)
+    );
+    assert_eq!(
+        field_names::<H5KalmanEstimatesRow>(),
+        names(KALMAN_ESTIMATES_COLUMNS)
+    );
+}
@@ -0,0 +1,207 @@
+//! Export to Apache Parquet files.
+//!
+//! Each table is saved to its own file. The units of each column are in the
+//! `units` metadata of the field and the metadata of the `.braidz` file are
+//! in the metadata of the schema.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arrow_array::{
+    ArrayRef, Float64Array, Int64Array, RecordBatch, UInt32Array, UInt64Array, UInt8Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+
+use braidz_types::{Data2dDistortedRow, KalmanEstimatesRow};
+
+use super::{
+    for_each_chunk, metadata_attributes, ColumnInfo, DATA2D_DISTORTED_COLUMNS,
+    KALMAN_ESTIMATES_COLUMNS,
+};
+use crate::{BraidzArchive, Error};
+
+/// The type and nullability of each column in [DATA2D_DISTORTED_COLUMNS].
+fn data2d_distorted_types() -> Vec<(DataType, bool)> {
+    use DataType::*;
+    vec![
+        (UInt8, false),
+        (Int64, false),
+        (Float64, true),
+        (Float64, false),
+        (UInt64, true),
+        (UInt64, true),
+        (Float64, false),
+        (Float64, false),
+        (Float64, false),
+        (Float64, false),
+        (Float64, false),
+        (UInt8, false),
+        (UInt8, false),
+        (Float64, false),
+        (Float64, false),
+    ]
+}
+
+/// The type and nullability of each column in [KALMAN_ESTIMATES_COLUMNS].
+fn kalman_estimates_types() -> Vec<(DataType, bool)> {
+    let mut types = vec![
+        (DataType::UInt32, false),
+        (DataType::Int64, false),
+        (DataType::Float64, true),
+    ];
+    types.resize(KALMAN_ESTIMATES_COLUMNS.len(), (DataType::Float64, false));
+    types
+}
+
+fn make_schema(
+    columns: &[ColumnInfo],
+    types: Vec<(DataType, bool)>,
+    metadata: HashMap<String, String>,
+) -> SchemaRef {
+    debug_assert_eq!(columns.len(), types.len());
+    let fields: Vec<Field> = columns
+        .iter()
+        .zip(types)
+        .map(|(column, (data_type, nullable))| {
+            let mut field_metadata = HashMap::new();
+            if !column.units.is_empty() {
+                field_metadata.insert("units".to_string(), column.units.to_string());
+            }
+            Field::new(column.name, data_type, nullable).with_metadata(field_metadata)
+        })
+        .collect();
+    Arc::new(Schema::new(fields).with_metadata(metadata))
+}
+
+fn f64_col<T>(rows: &[T], f: impl Fn(&T) -> f64) -> ArrayRef {
+    Arc::new(Float64Array::from_iter_values(rows.iter().map(f)))
+}
+
+fn data2d_distorted_batch(
+    schema: &SchemaRef,
+    rows: &[Data2dDistortedRow],
+) -> Result<RecordBatch, Error> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt8Array::from_iter_values(rows.iter().map(|r| r.camn.0))),
+        Arc::new(Int64Array::from_iter_values(rows.iter().map(|r| r.frame))),
+        Arc::new(Float64Array::from_iter(
+            rows.iter()
+                .map(|r| r.timestamp.as_ref().map(|t| t.as_f64())),
+        )),
+        f64_col(rows, |r| r.cam_received_timestamp.as_f64()),
+        Arc::new(UInt64Array::from_iter(
+            rows.iter().map(|r| r.device_timestamp.map(|v| v.get())),
+        )),
+        Arc::new(UInt64Array::from_iter(
+            rows.iter().map(|r| r.block_id.map(|v| v.get())),
+        )),
+        f64_col(rows, |r| r.x),
+        f64_col(rows, |r| r.y),
+        f64_col(rows, |r| r.area),
+        f64_col(rows, |r| r.slope),
+        f64_col(rows, |r| r.eccentricity),
+        Arc::new(UInt8Array::from_iter_values(
+            rows.iter().map(|r| r.frame_pt_idx),
+        )),
+        Arc::new(UInt8Array::from_iter_values(rows.iter().map(|r| r.cur_val))),
+        f64_col(rows, |r| r.mean_val),
+        f64_col(rows, |r| r.sumsqf_val),
+    ];
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+fn kalman_estimates_batch(
+    schema: &SchemaRef,
+    rows: &[KalmanEstimatesRow],
+) -> Result<RecordBatch, Error> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.obj_id))),
+        Arc::new(Int64Array::from_iter_values(
+            rows.iter().map(|r| r.frame.0 as i64),
+        )),
+        Arc::new(Float64Array::from_iter(
+            rows.iter()
+                .map(|r| r.timestamp.as_ref().map(|t| t.as_f64())),
+        )),
+        f64_col(rows, |r| r.x),
+        f64_col(rows, |r| r.y),
+        f64_col(rows, |r| r.z),
+        f64_col(rows, |r| r.xvel),
+        f64_col(rows, |r| r.yvel),
+        f64_col(rows, |r| r.zvel),
+        f64_col(rows, |r| r.P00),
+        f64_col(rows, |r| r.P01),
+        f64_col(rows, |r| r.P02),
+        f64_col(rows, |r| r.P11),
+        f64_col(rows, |r| r.P12),
+        f64_col(rows, |r| r.P22),
+        f64_col(rows, |r| r.P33),
+        f64_col(rows, |r| r.P44),
+        f64_col(rows, |r| r.P55),
+    ];
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+fn create_writer(path: &Path, schema: &SchemaRef) -> Result<ArrowWriter<File>, Error> {
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    Ok(ArrowWriter::try_new(
+        File::create(path)?,
+        schema.clone(),
+        Some(props),
+    )?)
+}
+
+/// Export the `data2d_distorted` and (if present) `kalman_estimates` tables
+/// to the files `data2d_distorted.parquet` and `kalman_estimates.parquet` in
+/// the directory `output_dir`.
+///
+/// Returns the paths of the files written. The files can be read, e.g., with
+/// `pandas.read_parquet()` in Python or `arrow::read_parquet()` in R.
+pub fn export_parquet<R: Read + Seek, P: AsRef<Path>>(
+    archive: &mut BraidzArchive<R>,
+    output_dir: P,
+) -> Result<Vec<PathBuf>, Error> {
+    let output_dir = output_dir.as_ref();
+    std::fs::create_dir_all(output_dir)?;
+    let metadata: HashMap<String, String> = metadata_attributes(archive)?
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+    let mut written = Vec::new();
+
+    let schema = make_schema(
+        DATA2D_DISTORTED_COLUMNS,
+        data2d_distorted_types(),
+        metadata.clone(),
+    );
+    let path = output_dir.join("data2d_distorted.parquet");
+    let mut wtr = create_writer(&path, &schema)?;
+    for_each_chunk(archive.iter_data2d_distorted()?, |rows| {
+        wtr.write(&data2d_distorted_batch(&schema, &rows)?)?;
+        Ok(())
+    })?;
+    wtr.close()?;
+    written.push(path);
+
+    if archive.kalman_estimates_info.is_some() {
+        let schema = make_schema(KALMAN_ESTIMATES_COLUMNS, kalman_estimates_types(), metadata);
+        let path = output_dir.join("kalman_estimates.parquet");
+        let mut wtr = create_writer(&path, &schema)?;
+        for_each_chunk(archive.iter_kalman_estimates()?, |rows| {
+            wtr.write(&kalman_estimates_batch(&schema, &rows)?)?;
+            Ok(())
+        })?;
+        wtr.close()?;
+        written.push(path);
+    }
+
+    Ok(written)
+}
@@ -0,0 +1,494 @@
+//! Conversion of legacy flydra HDF5 files to `.braidz` files.
+//!
+//! The mainbrain of the original flydra saved its data to an HDF5 file in the
+//! pytables layout, in which each table is a one-dimensional dataset of a
+//! compound type at the root of the file. [convert_flydra_h5] saves the
+//! `cam_info`, `data2d_distorted`, `kalman_estimates` and `textlog` tables to
+//! a `.braidz` file. Columns are matched by name, so that files of different
+//! flydra versions can be converted. Everything which could not be converted,
+//! such as columns without equivalent in `.braidz` files or the calibration,
+//! is listed in the returned [FlydraH5ConversionReport], which is also saved
+//! in the `.braidz` file as [CONVERSION_REPORT_FNAME].
+
+use std::{collections::BTreeMap, io::Write, ops::Range, path::Path};
+
+use hdf5::{
+    types::{FixedAscii, TypeDescriptor},
+    H5Type,
+};
+
+use braidz_types::{BraidMetadata, FlydraH5ConversionReport};
+use flydra_types::{
+    CamInfoRow, CamNum, Data2dDistortedRow, FlydraFloatTimestampLocal, KalmanEstimatesRow, SyncFno,
+    TextlogRow, CAM_INFO_CSV_FNAME, DATA2D_DISTORTED_CSV_FNAME, KALMAN_ESTIMATES_CSV_FNAME,
+};
+
+use crate::{BraidzWriter, Error};
+
+/// The name of the conversion report in the `.braidz` file.
+pub const CONVERSION_REPORT_FNAME: &str = "flydra_h5_conversion.yml";
+
+/// Number of rows read at once.
+const CHUNK_SIZE: usize = 100_000;
+
+const TEXTLOG: &str = "textlog";
+const CAM_INFO: &str = "cam_info";
+const DATA2D_DISTORTED: &str = "data2d_distorted";
+const KALMAN_ESTIMATES: &str = "kalman_estimates";
+
+/// The start of the textlog message with the frame rate.
+const FPS_MESSAGE_START: &str = "MainBrain running at ";
+
+/// Define `$fn_name`, which reads the column `name` (one of `$field`) of the
+/// rows `range` of a table.
+///
+/// HDF5 converts compound types by member name, so reading a compound type
+/// with a single member reads this column of the table, converting its values
+/// to `$ty`.
+macro_rules! column_reader {
+    ($fn_name:ident, $ty:ty, [$($field:ident),* $(,)?]) => {
+        fn $fn_name(ds: &hdf5::Dataset, name: &str, range: Range<usize>) -> Result<Vec<$ty>, Error> {
+            $(
+                if name == stringify!($field) {
+                    #[allow(non_snake_case)]
+                    #[derive(H5Type, Clone, Copy)]
+                    #[repr(C)]
+                    struct Column {
+                        $field: $ty,
+                    }
+                    let column = ds.read_slice_1d::<Column, _>(range)?;
+                    return Ok(column.iter().map(|c| c.$field).collect());
+                }
+            )*
+            unreachable!("no reader for column {name}")
+        }
+    };
+}
+
+column_reader!(
+    read_f64_column,
+    f64,
+    [
+        camn,
+        frame,
+        timestamp,
+        cam_received_timestamp,
+        x,
+        y,
+        z,
+        area,
+        slope,
+        eccentricity,
+        frame_pt_idx,
+        cur_val,
+        mean_val,
+        sumsqf_val,
+        obj_id,
+        xvel,
+        yvel,
+        zvel,
+        P00,
+        P01,
+        P02,
+        P11,
+        P12,
+        P22,
+        P33,
+        P44,
+        P55,
+        mainbrain_timestamp,
+        host_timestamp,
+    ]
+);
+
+column_reader!(read_string_column, FixedAscii<1024>, [cam_id, message]);
+
+/// Rows of a table, by column.
+struct Chunk {
+    len: usize,
+    numbers: BTreeMap<&'static str, Vec<f64>>,
+    strings: BTreeMap<&'static str, Vec<String>>,
+}
+
+impl Chunk {
+    /// The value of `column` in row `i`, NaN if the table has no such column.
+    fn get(&self, column: &str, i: usize) -> f64 {
+        self.numbers.get(column).map_or(f64::NAN, |v| v[i])
+    }
+
+    /// The value of the string `column` in row `i`, empty if the table has no
+    /// such column.
+    fn get_str(&self, column: &str, i: usize) -> String {
+        self.strings
+            .get(column)
+            .map_or_else(String::new, |v| v[i].clone())
+    }
+}
+
+/// A table of the HDF5 file.
+struct Table {
+    name: &'static str,
+    ds: hdf5::Dataset,
+    /// The names of the columns in the HDF5 file.
+    columns: Vec<String>,
+}
+
+impl Table {
+    /// Open the table `name`, or return `None` if the file does not contain
+    /// it.
+    fn open(file: &hdf5::File, name: &'static str) -> Result<Option<Self>, Error> {
+        if !file.link_exists(name) {
+            return Ok(None);
+        }
+        let ds = file.dataset(name)?;
+        let columns = match ds.dtype()?.to_descriptor()? {
+            TypeDescriptor::Compound(c) => c.fields.into_iter().map(|f| f.name).collect(),
+            _ => {
+                return Err(Error::FlydraH5(format!(
+                    "\"{name}\" is not a table with columns"
+                )))
+            }
+        };
+        Ok(Some(Self { name, ds, columns }))
+    }
+
+    fn has(&self, column: &str) -> bool {
+        self.columns.iter().any(|c| c == column)
+    }
+
+    /// Check that the `required` columns exist and report the columns which
+    /// are not `converted` and those which are missing.
+    fn check_columns(
+        &self,
+        converted: &[&'static str],
+        required: &[&'static str],
+        report: &mut FlydraH5ConversionReport,
+    ) -> Result<(), Error> {
+        if let Some(column) = required.iter().find(|c| !self.has(c)) {
+            return Err(Error::FlydraH5(format!(
+                "table \"{}\" has no column \"{column}\"",
+                self.name
+            )));
+        }
+        let unconverted: Vec<String> = self
+            .columns
+            .iter()
+            .filter(|c| !converted.iter().any(|k| *k == c.as_str()))
+            .cloned()
+            .collect();
+        if !unconverted.is_empty() {
+            report
+                .unconverted_columns
+                .insert(self.name.to_string(), unconverted);
+        }
+        let missing: Vec<String> = converted
+            .iter()
+            .filter(|c| !self.has(c))
+            .map(|c| c.to_string())
+            .collect();
+        if !missing.is_empty() {
+            report
+                .missing_columns
+                .insert(self.name.to_string(), missing);
+        }
+        Ok(())
+    }
+
+    /// Call `f` with consecutive chunks of up to [CHUNK_SIZE] rows of the
+    /// `numbers` and `strings` columns. Returns the number of rows.
+    fn for_each_chunk<F>(
+        &self,
+        numbers: &[&'static str],
+        strings: &[&'static str],
+        mut f: F,
+    ) -> Result<u64, Error>
+    where
+        F: FnMut(&Chunk) -> Result<(), Error>,
+    {
+        let nrows = self.ds.size();
+        let mut start = 0;
+        while start < nrows {
+            let range = start..nrows.min(start + CHUNK_SIZE);
+            let mut chunk = Chunk {
+                len: range.len(),
+                numbers: BTreeMap::new(),
+                strings: BTreeMap::new(),
+            };
+            for column in numbers.iter().filter(|c| self.has(c)) {
+                let values = read_f64_column(&self.ds, column, range.clone())?;
+                chunk.numbers.insert(*column, values);
+            }
+            for column in strings.iter().filter(|c| self.has(c)) {
+                let values = read_string_column(&self.ds, column, range.clone())?;
+                let values = values.iter().map(|v| v.as_str().to_string()).collect();
+                chunk.strings.insert(*column, values);
+            }
+            f(&chunk)?;
+            start = range.end;
+        }
+        Ok(nrows as u64)
+    }
+}
+
+fn finite(value: f64) -> Option<f64> {
+    value.is_finite().then_some(value)
+}
+
+fn to_camn(value: f64) -> Result<CamNum, Error> {
+    if (0.0..=u8::MAX as f64).contains(&value) {
+        Ok(CamNum(value as u8))
+    } else {
+        Err(Error::FlydraH5(format!("invalid camn {value}")))
+    }
+}
+
+/// Parse the frame rate from a textlog message.
+fn parse_fps(message: &str) -> Option<f32> {
+    let rest = message.strip_prefix(FPS_MESSAGE_START)?;
+    rest.split(' ').next()?.parse().ok()
+}
+
+/// Read the textlog table.
+fn read_textlog(
+    file: &hdf5::File,
+    report: &mut FlydraH5ConversionReport,
+) -> Result<Vec<TextlogRow>, Error> {
+    let Some(table) = Table::open(file, TEXTLOG)? else {
+        report.notes.push(format!("no {TEXTLOG} table"));
+        return Ok(vec![]);
+    };
+    let numbers = ["mainbrain_timestamp", "host_timestamp"];
+    let strings = ["cam_id", "message"];
+    table.check_columns(&[numbers, strings].concat(), &["message"], report)?;
+    let mut rows = vec![];
+    table.for_each_chunk(&numbers, &strings, |chunk| {
+        for i in 0..chunk.len {
+            rows.push(TextlogRow {
+                mainbrain_timestamp: chunk.get("mainbrain_timestamp", i),
+                cam_id: chunk.get_str("cam_id", i),
+                host_timestamp: chunk.get("host_timestamp", i),
+                message: chunk.get_str("message", i),
+            });
+        }
+        Ok(())
+    })?;
+    Ok(rows)
+}
+
+/// Convert the legacy flydra HDF5 file `input` to the `.braidz` file
+/// `output`.
+///
+/// The tracking parameters are not saved in flydra HDF5 files, so the
+/// defaults are saved instead. Returns the report of what was converted.
+pub fn convert_flydra_h5<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+) -> Result<FlydraH5ConversionReport, Error> {
+    let input = input.as_ref();
+    let output = output.as_ref();
+    if output.extension().map_or(true, |ext| ext != "braidz") {
+        return Err(Error::OutputNotBraidz);
+    }
+    let file = hdf5::File::open(input)?;
+    let mut report = FlydraH5ConversionReport {
+        source: input.display().to_string(),
+        ..Default::default()
+    };
+    let converted_nodes = [TEXTLOG, CAM_INFO, DATA2D_DISTORTED, KALMAN_ESTIMATES];
+    report.unconverted_nodes = file
+        .member_names()?
+        .into_iter()
+        .filter(|name| !converted_nodes.iter().any(|k| *k == name.as_str()))
+        .collect();
+
+    let textlog = read_textlog(&file, &mut report)?;
+    let fps = textlog.iter().find_map(|row| parse_fps(&row.message));
+    if fps.is_none() {
+        report.notes.push("frame rate unknown".to_string());
+    }
+    let metadata = BraidMetadata {
+        schema: flydra_types::BRAID_SCHEMA,
+        git_revision: "unknown".to_string(),
+        original_recording_time: textlog
+            .first()
+            .and_then(|row| finite(row.mainbrain_timestamp))
+            .map(|t| datetime_conversion::f64_to_datetime(t).with_timezone(&chrono::Local)),
+        // flydra saved a row with NaN coordinates for frames without
+        // detections.
+        save_empty_data2d: true,
+        saving_program_name: "flydra".to_string(),
+        provenance: vec![],
+        tracking_determinism: None,
+    };
+    let wtr = BraidzWriter::new(output, &metadata)?;
+
+    // The writer saves the frame rate and the tracking parameters at the start
+    // of the textlog.
+    let mut textlog_wtr = wtr.textlog_writer(
+        fps,
+        &metadata.git_revision,
+        &flydra_types::default_tracking_params_full_3d(),
+    )?;
+    for row in textlog.iter() {
+        if !row.message.starts_with(FPS_MESSAGE_START) {
+            textlog_wtr.serialize(row)?;
+        }
+    }
+    textlog_wtr.flush()?;
+    drop(textlog_wtr);
+    report
+        .num_rows
+        .insert(TEXTLOG.to_string(), textlog.len() as u64);
+
+    if let Some(table) = Table::open(&file, CAM_INFO)? {
+        table.check_columns(&["camn", "cam_id"], &["camn", "cam_id"], &mut report)?;
+        let mut csv_wtr = wtr.csv_gz_writer(CAM_INFO_CSV_FNAME)?;
+        let nrows = table.for_each_chunk(&["camn"], &["cam_id"], |chunk| {
+            for i in 0..chunk.len {
+                csv_wtr.serialize(CamInfoRow {
+                    camn: to_camn(chunk.get("camn", i))?,
+                    cam_id: chunk.get_str("cam_id", i),
+                })?;
+            }
+            Ok(())
+        })?;
+        csv_wtr.flush()?;
+        report.num_rows.insert(CAM_INFO.to_string(), nrows);
+    } else {
+        report.notes.push(format!("no {CAM_INFO} table"));
+    }
+
+    if let Some(table) = Table::open(&file, DATA2D_DISTORTED)? {
+        let columns = [
+            "camn",
+            "frame",
+            "timestamp",
+            "cam_received_timestamp",
+            "x",
+            "y",
+            "area",
+            "slope",
+            "eccentricity",
+            "frame_pt_idx",
+            "cur_val",
+            "mean_val",
+            "sumsqf_val",
+        ];
+        let required = ["camn", "frame", "cam_received_timestamp", "x", "y"];
+        table.check_columns(&columns, &required, &mut report)?;
+        let mut csv_wtr = wtr.csv_gz_writer(DATA2D_DISTORTED_CSV_FNAME)?;
+        let nrows = table.for_each_chunk(&columns, &[], |chunk| {
+            for i in 0..chunk.len {
+                // Missing integer values are 0.
+                csv_wtr.serialize(Data2dDistortedRow {
+                    camn: to_camn(chunk.get("camn", i))?,
+                    frame: chunk.get("frame", i) as i64,
+                    timestamp: finite(chunk.get("timestamp", i))
+                        .map(FlydraFloatTimestampLocal::from_f64),
+                    cam_received_timestamp: FlydraFloatTimestampLocal::from_f64(
+                        chunk.get("cam_received_timestamp", i),
+                    ),
+                    device_timestamp: None,
+                    block_id: None,
+                    x: chunk.get("x", i),
+                    y: chunk.get("y", i),
+                    area: chunk.get("area", i),
+                    slope: chunk.get("slope", i),
+                    eccentricity: chunk.get("eccentricity", i),
+                    frame_pt_idx: chunk.get("frame_pt_idx", i) as u8,
+                    cur_val: chunk.get("cur_val", i) as u8,
+                    mean_val: chunk.get("mean_val", i),
+                    sumsqf_val: chunk.get("sumsqf_val", i),
+                })?;
+            }
+            Ok(())
+        })?;
+        csv_wtr.flush()?;
+        report.num_rows.insert(DATA2D_DISTORTED.to_string(), nrows);
+        report.notes.push(format!(
+            "{DATA2D_DISTORTED}: device_timestamp and block_id are not saved by flydra"
+        ));
+    } else {
+        report.notes.push(format!("no {DATA2D_DISTORTED} table"));
+    }
+
+    if let Some(table) = Table::open(&file, KALMAN_ESTIMATES)? {
+        let columns = [
+            "obj_id",
+            "frame",
+            "timestamp",
+            "x",
+            "y",
+            "z",
+            "xvel",
+            "yvel",
+            "zvel",
+            "P00",
+            "P01",
+            "P02",
+            "P11",
+            "P12",
+            "P22",
+            "P33",
+            "P44",
+            "P55",
+        ];
+        let required = ["obj_id", "frame", "x", "y", "z"];
+        table.check_columns(&columns, &required, &mut report)?;
+        let mut csv_wtr = wtr.csv_gz_writer(KALMAN_ESTIMATES_CSV_FNAME)?;
+        let nrows = table.for_each_chunk(&columns, &[], |chunk| {
+            for i in 0..chunk.len {
+                let frame = chunk.get("frame", i);
+                if frame.is_nan() || frame < 0.0 {
+                    return Err(Error::FlydraH5(format!(
+                        "invalid frame {frame} in {KALMAN_ESTIMATES}"
+                    )));
+                }
+                csv_wtr.serialize(KalmanEstimatesRow {
+                    obj_id: chunk.get("obj_id", i) as u32,
+                    frame: SyncFno(frame as u64),
+                    timestamp: finite(chunk.get("timestamp", i))
+                        .map(FlydraFloatTimestampLocal::from_f64),
+                    x: chunk.get("x", i),
+                    y: chunk.get("y", i),
+                    z: chunk.get("z", i),
+                    xvel: chunk.get("xvel", i),
+                    yvel: chunk.get("yvel", i),
+                    zvel: chunk.get("zvel", i),
+                    P00: chunk.get("P00", i),
+                    P01: chunk.get("P01", i),
+                    P02: chunk.get("P02", i),
+                    P11: chunk.get("P11", i),
+                    P12: chunk.get("P12", i),
+                    P22: chunk.get("P22", i),
+                    P33: chunk.get("P33", i),
+                    P44: chunk.get("P44", i),
+                    P55: chunk.get("P55", i),
+                })?;
+            }
+            Ok(())
+        })?;
+        csv_wtr.flush()?;
+        report.num_rows.insert(KALMAN_ESTIMATES.to_string(), nrows);
+        report.notes.push(format!(
+            "{KALMAN_ESTIMATES}: the tracking parameters are not saved by flydra, \
+            the defaults were saved instead"
+        ));
+    }
+
+    let mut fd = wtr.create_file(CONVERSION_REPORT_FNAME)?;
+    fd.write_all(serde_yaml::to_string(&report)?.as_bytes())?;
+    drop(fd);
+    wtr.finish()?;
+    Ok(report)
+}
+
+#[test]
+fn test_parse_fps() {
+    assert_eq!(
+        parse_fps("MainBrain running at 100.0 fps, (flydra_version 0.6.7, time_tzname0 CET)"),
+        Some(100.0)
+    );
+    assert_eq!(parse_fps("MainBrain running at unknown fps, ()"), None);
+    assert_eq!(parse_fps("cam1 connected"), None);
+}
@@ -1,6 +1,7 @@
 //! This is an incremental parser for braid archives.
 
 use crate::*;
+use csv_eof::EarlyEofOk;
 
 /// The implementation specifies in what state we are in terms of parsing an archive.
 pub trait ParseState {}
@@ -183,6 +184,8 @@ impl<R: Read + Seek> IncrementalParser<R, ArchiveOpened> {
                                     saving_program_name: "flydra".to_string(),
                                     schema: flydra_types::BRAID_SCHEMA,
                                     save_empty_data2d: false,
+                                    provenance: vec![],
+                                    tracking_determinism: None,
                                 });
                             }
 
@@ -247,7 +250,7 @@ impl<R: Read + Seek> IncrementalParser<R, ArchiveOpened> {
                     let system =
                         flydra_mvg::FlydraMultiCameraSystem::from_flydra_reconstructor(&recon)?;
                     Some(CalibrationInfo {
-                        water: recon.water,
+                        water: system.refractive_boundary().copied(),
                         cameras: system.to_system(),
                     })
                 }
@@ -331,7 +334,23 @@ impl<R: Read + Seek> IncrementalParser<R, ArchiveOpened> {
 
 impl<R: Read + Seek> IncrementalParser<R, BasicInfoParsed> {
     /// Parse the remaining aspects of the archive.
-    pub fn parse_rest(mut self) -> Result<IncrementalParser<R, FullyParsed>, Error> {
+    pub fn parse_rest(self) -> Result<IncrementalParser<R, FullyParsed>, Error> {
+        self.parse_rest_inner(true)
+    }
+
+    /// Parse the remaining aspects of the archive except for loading the
+    /// `kalman_estimates` table to memory.
+    ///
+    /// The `kalman_estimates_table` field of the result is `None`. Its rows can
+    /// be read with [BraidzArchive::iter_kalman_estimates].
+    pub fn parse_rest_streaming(self) -> Result<IncrementalParser<R, FullyParsed>, Error> {
+        self.parse_rest_inner(false)
+    }
+
+    fn parse_rest_inner(
+        mut self,
+        load_kalman_estimates_table: bool,
+    ) -> Result<IncrementalParser<R, FullyParsed>, Error> {
         let basics = self.state;
 
         let mut num_rows = 0;
@@ -345,7 +364,7 @@ impl<R: Read + Seek> IncrementalParser<R, BasicInfoParsed> {
             let d2d_reader = csv::Reader::from_reader(rdr);
             let mut qz = BTreeMap::new();
 
-            for row in d2d_reader.into_deserialize().early_eof_ok() {
+            for row in RowIter::new(d2d_reader)? {
                 num_rows += 1;
                 let row: Data2dDistortedRow = row?;
                 let entry = qz.entry(row.camn).or_insert_with(Seq2d::new);
@@ -366,7 +385,12 @@ impl<R: Read + Seek> IncrementalParser<R, BasicInfoParsed> {
                 if let Some((ref mut f_lim, ref mut time_lim)) = limits {
                     f_lim[0] = std::cmp::min(f_lim[0], this_frame);
                     f_lim[1] = std::cmp::max(f_lim[1], this_frame);
-                    time_lim[1] = this_time;
+                    // The host timestamps are not necessarily monotonic.
+                    if this_time.as_f64() < time_lim[0].as_f64() {
+                        time_lim[0] = this_time;
+                    } else if this_time.as_f64() > time_lim[1].as_f64() {
+                        time_lim[1] = this_time;
+                    }
                 } else {
                     // Initialize with the first row of data.
                     limits = Some(([this_frame, this_frame], [this_time.clone(), this_time]));
@@ -399,7 +423,7 @@ impl<R: Read + Seek> IncrementalParser<R, BasicInfoParsed> {
                     let mut zlim = [inf, -inf];
                     let mut num_rows = 0;
 
-                    for row in kest_reader.into_deserialize().early_eof_ok() {
+                    for row in RowIter::new(kest_reader)? {
                         let row: KalmanEstimatesRow = row?;
                         let entry =
                             trajectories
@@ -422,7 +446,9 @@ impl<R: Read + Seek> IncrementalParser<R, BasicInfoParsed> {
                         zlim[0] = min(zlim[0], row.z);
                         zlim[1] = max(zlim[1], row.z);
                         num_rows += 1;
-                        kalman_estimates_table.push(row);
+                        if load_kalman_estimates_table {
+                            kalman_estimates_table.push(row);
+                        }
                     }
 
                     let mut total_distance: f64 = 0.0;
@@ -460,7 +486,7 @@ impl<R: Read + Seek> IncrementalParser<R, BasicInfoParsed> {
                             tracking_parameters,
                             total_distance,
                         }),
-                        Some(kalman_estimates_table),
+                        load_kalman_estimates_table.then_some(kalman_estimates_table),
                     )
                 }
                 Err(e) =>
@@ -16,14 +16,30 @@ use flydra_types::{FlydraFloatTimestampLocal, HostClock, TextlogRow, TrackingPar
 
 use braidz_types::{
     BraidMetadata, BraidzSummary, CalibrationInfo, CamInfo, CamInfoRow, CamNum, Data2dDistortedRow,
-    Data2dSummary, HistogramSummary, KalmanEstimatesRow, KalmanEstimatesSummary,
+    Data2dSummary, HistogramSummary, KalmanEstimatesRow, KalmanEstimatesSummary, Provenance,
 };
 
 use groupby::{AscendingGroupIter, BufferedSortIter, GroupedRows};
 
-use csv_eof::EarlyEofOk;
-
+pub mod braidtraj;
+pub mod export;
+#[cfg(feature = "hdf5")]
+pub mod flydra_h5;
 pub mod incremental_parser;
+pub mod reproject;
+pub mod rows;
+pub mod smooth;
+pub mod stats;
+pub mod stitch;
+
+use rows::RowIter;
+
+mod trajectory_iter;
+pub use trajectory_iter::{ObjTrajectory, TrajectoryIter};
+
+/// Creates `.braidz` files, e.g. from synthetic data or the output of other
+/// tracking systems.
+pub use braidz_writer::BraidzWriter;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -97,6 +113,49 @@ pub enum Error {
         #[cfg(feature = "backtrace")]
         backtrace: Backtrace,
     },
+    #[error("{source}")]
+    Writer {
+        #[from]
+        source: braidz_writer::Error,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[error("{source}")]
+    Crypt {
+        #[from]
+        source: braidz_crypt::Error,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[cfg(feature = "hdf5")]
+    #[error("{source}")]
+    Hdf5 {
+        #[from]
+        source: hdf5::Error,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[cfg(feature = "parquet")]
+    #[error("{source}")]
+    Parquet {
+        #[from]
+        source: parquet::errors::ParquetError,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[cfg(feature = "parquet")]
+    #[error("{source}")]
+    Arrow {
+        #[from]
+        source: arrow_schema::ArrowError,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[error(
+        "The braidz file is encrypted with key {key_id}. Decrypt it with \
+        `braidz-cli decrypt` or open it with `braidz_parse_encrypted_path`."
+    )]
+    Encrypted { key_id: braidz_crypt::KeyId },
     #[error("Compressed and uncompressed data copies exist simultaneously")]
     DualData,
     #[error("textlog data could not be parsed")]
@@ -105,6 +164,22 @@ pub enum Error {
     MultipleTrackingParameters,
     #[error("Missing tracking parameters")]
     MissingTrackingParameters,
+    #[error("No Kalman estimates in archive")]
+    MissingKalmanEstimates,
+    #[error("No calibration in archive")]
+    MissingCalibration,
+    #[error("Frame rate unknown")]
+    UnknownFrameRate,
+    #[error("Cannot convert flydra HDF5 file: {0}")]
+    FlydraH5(String),
+    #[error("Output filename must end with '.braidz'")]
+    OutputNotBraidz,
+    #[error("No trajectory with obj_id {0}")]
+    UnknownObjId(u32),
+    #[error("Unsupported braidtraj file (format \"{format}\", version {format_version})")]
+    UnsupportedBraidtraj { format: String, format_version: u16 },
+    #[error("Checksum mismatch in braidtraj file (expected: {expected}, found: {found})")]
+    ChecksumMismatch { expected: String, found: String },
     #[error("Error opening {filename}: {source}")]
     FileError {
         what: &'static str,
@@ -175,6 +250,13 @@ impl<R: Read + Seek> BraidzArchive<R> {
     pub fn path(&self) -> &std::path::Path {
         self.archive.path()
     }
+
+    /// The programs which produced the data.
+    ///
+    /// This is empty for archives saved before schema 4.
+    pub fn provenance(&self) -> &[Provenance] {
+        &self.metadata.provenance
+    }
 }
 
 pub struct D2DInfo {
@@ -324,20 +406,70 @@ pub fn braidz_parse_reader<R: Read + Seek>(
 pub fn braidz_parse_path<P: AsRef<std::path::Path>>(
     path: P,
 ) -> Result<BraidzArchive<BufReader<File>>, Error> {
+    if braidz_crypt::is_encrypted_path(&path)? {
+        let key_id = braidz_crypt::key_id(File::open(&path)?)?;
+        return Err(Error::Encrypted { key_id });
+    }
     let zs = zip_or_dir::ZipDirArchive::auto_from_path(&path)?;
     let parsed = braidz_parse(zs)?;
     Ok(parsed)
 }
 
+/// Parse a `.braidz` file which may be encrypted.
+///
+/// The file is read into memory. An encrypted file is decrypted with the
+/// matching key from `keyring`. Unlike [braidz_parse_path], this does not
+/// support unzipped `.braid` directories.
+pub fn braidz_parse_encrypted_path<P: AsRef<std::path::Path>>(
+    path: P,
+    keyring: &braidz_crypt::Keyring,
+) -> Result<BraidzArchive<std::io::Cursor<Vec<u8>>>, Error> {
+    let path = path.as_ref();
+    let mut rdr = BufReader::new(File::open(path)?);
+    let buf = if braidz_crypt::is_encrypted_path(path)? {
+        braidz_crypt::decrypt_to_vec(rdr, keyring)?
+    } else {
+        let mut buf = Vec::new();
+        rdr.read_to_end(&mut buf)?;
+        buf
+    };
+    braidz_parse_reader(std::io::Cursor::new(buf), path.display().to_string())
+}
+
+/// Parse a `.braidz` file without loading the `kalman_estimates` table to
+/// memory.
+///
+/// This is for large archives. The `kalman_estimates_table` field of the
+/// result is `None`. Use [BraidzArchive::iter_kalman_estimates] or
+/// [BraidzArchive::iter_trajectories] to read the rows.
+pub fn braidz_parse_path_streaming<P: AsRef<std::path::Path>>(
+    path: P,
+) -> Result<BraidzArchive<BufReader<File>>, Error> {
+    if braidz_crypt::is_encrypted_path(&path)? {
+        let key_id = braidz_crypt::key_id(File::open(&path)?)?;
+        return Err(Error::Encrypted { key_id });
+    }
+    let zs = zip_or_dir::ZipDirArchive::auto_from_path(&path)?;
+    let ip = incremental_parser::IncrementalParser::from_archive(zs);
+    let ip = ip.parse_basics()?.parse_rest_streaming()?;
+    Ok(from_fully_parsed(ip))
+}
+
 pub fn braidz_parse<R: Read + Seek>(
     archive: zip_or_dir::ZipDirArchive<R>,
 ) -> Result<BraidzArchive<R>, Error> {
     let ip = incremental_parser::IncrementalParser::from_archive(archive);
     let ip = ip.parse_everything()?;
+    Ok(from_fully_parsed(ip))
+}
+
+fn from_fully_parsed<R: Read + Seek>(
+    ip: incremental_parser::IncrementalParser<R, incremental_parser::FullyParsed>,
+) -> BraidzArchive<R> {
     let state = ip.state;
     let archive = ip.archive;
 
-    Ok(BraidzArchive {
+    BraidzArchive {
         archive,
         metadata: state.metadata,
         expected_fps: state.expected_fps,
@@ -349,7 +481,7 @@ pub fn braidz_parse<R: Read + Seek>(
         reconstruction_latency_hlog: state.reconstruction_latency_hlog,
         reprojection_distance_hlog: state.reprojection_distance_hlog,
         image_sizes: state.image_sizes,
-    })
+    }
 }
 
 impl<'a, R: Read + Seek> BraidzArchive<R> {
@@ -371,8 +503,59 @@ impl<'a, R: Read + Seek> BraidzArchive<R> {
             .path_starter()
             .join(flydra_types::DATA2D_DISTORTED_CSV_FNAME);
         let rdr = open_maybe_gzipped(data_fname)?;
-        let rdr2 = csv::Reader::from_reader(rdr);
-        Ok(rdr2.into_deserialize().early_eof_ok())
+        Ok(RowIter::new(csv::Reader::from_reader(rdr))?)
+    }
+
+    /// Iterate over the rows of the `kalman_estimates` table.
+    ///
+    /// The rows are read from the archive while iterating and are not kept in
+    /// memory. They are in ascending order of frame.
+    ///
+    /// This takes a mutable reference because the read location in the archive
+    /// is changed during operation.
+    pub fn iter_kalman_estimates(
+        &'a mut self,
+    ) -> Result<impl Iterator<Item = Result<KalmanEstimatesRow, csv::Error>> + 'a, Error> {
+        let data_fname = self
+            .archive
+            .path_starter()
+            .join(flydra_types::KALMAN_ESTIMATES_CSV_FNAME);
+        let rdr = open_maybe_gzipped(data_fname)?;
+        Ok(RowIter::new(csv::Reader::from_reader(rdr))?)
+    }
+
+    /// Iterate over the rows of the `textlog` table.
+    ///
+    /// Besides messages of Braid itself, e.g. its frame rate and tracking
+    /// parameters, the textlog contains experiment events such as notes posted
+    /// to the Braid API. The rows are in the order they were saved.
+    ///
+    /// This takes a mutable reference because the read location in the archive
+    /// is changed during operation.
+    pub fn iter_textlog(
+        &'a mut self,
+    ) -> Result<impl Iterator<Item = Result<TextlogRow, csv::Error>> + 'a, Error> {
+        let data_fname = self
+            .archive
+            .path_starter()
+            .join(flydra_types::TEXTLOG_CSV_FNAME);
+        let rdr = open_maybe_gzipped(data_fname)?;
+        Ok(RowIter::new(csv::Reader::from_reader(rdr))?)
+    }
+
+    /// Iterate over the trajectories of the `kalman_estimates` table.
+    ///
+    /// Only the trajectories of objects alive at the current frame are kept in
+    /// memory. An object is considered dead if it has no rows in more than
+    /// `max_gap_frames` frames. See [TrajectoryIter].
+    pub fn iter_trajectories(
+        &'a mut self,
+        max_gap_frames: u64,
+    ) -> Result<impl Iterator<Item = Result<ObjTrajectory, Error>> + 'a, Error> {
+        let rows = self
+            .iter_kalman_estimates()?
+            .map(|res| res.map_err(Error::from));
+        Ok(TrajectoryIter::new(rows, max_gap_frames))
     }
 
     /// Iterate over synchronized frames in `data2d_distorted` table.
@@ -522,6 +705,43 @@ fn test_append_to_path() {
     assert!(append_to_path(foo_csv, ".gz") == std::path::Path::new("foo.csv.gz"));
 }
 
+/// Whether the archive contains the table, compressed or not.
+pub(crate) fn has_table<R: Read + Seek>(archive: &mut BraidzArchive<R>, fname: &str) -> bool {
+    archive.archive.path_starter().join(fname).exists()
+        || archive
+            .archive
+            .path_starter()
+            .join(format!("{fname}.gz"))
+            .exists()
+}
+
+/// Copy the contents of the `.braidz` file `input` to the `.braid` directory of
+/// `output`, which must end with `.braidz`, and return a writer for it.
+///
+/// The tables `skip` are not copied. [BraidzWriter::finish] then saves the
+/// output file.
+pub(crate) fn copy_to_writer(
+    input: &std::path::Path,
+    output: &std::path::Path,
+    metadata: &BraidMetadata,
+    skip: &[&str],
+) -> Result<BraidzWriter, Error> {
+    if output.extension().map_or(true, |ext| ext != "braidz") {
+        return Err(Error::OutputNotBraidz);
+    }
+    let dirname = output.with_extension("braid");
+    let mut zip_archive = zip::ZipArchive::new(File::open(input)?)?;
+    zip_archive.extract(&dirname)?;
+    for fname in skip.iter() {
+        for path in [dirname.join(fname), dirname.join(format!("{fname}.gz"))] {
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+    }
+    Ok(BraidzWriter::new(&dirname, metadata)?)
+}
+
 /// Pick the `.csv` file (if it exists) as first choice, else pick `.csv.gz`.
 pub fn open_maybe_gzipped<R: Read + Seek>(
     mut path_like: zip_or_dir::PathLike<R>,
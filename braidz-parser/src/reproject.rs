@@ -0,0 +1,65 @@
+//! Projection of the 3D estimates into the cameras.
+//!
+//! For each camera of the calibration saved in the archive, this computes the
+//! 2D position at which each row of the `kalman_estimates` table is seen. A
+//! refractive boundary (water) in the calibration is taken into account.
+
+use std::{
+    collections::BTreeMap,
+    io::{Read, Seek},
+};
+
+use braidz_types::{CalibrationInfo, ReprojectedRow};
+
+use crate::{BraidzArchive, Error};
+
+/// Project the 3D estimates into each camera, by camera name.
+///
+/// If `distorted` is true, the lens distortion is applied such that the
+/// positions are in raw image coordinates, like those of the
+/// `data2d_distorted` table. Otherwise, undistorted positions are returned.
+pub fn reproject<R: Read + Seek>(
+    archive: &BraidzArchive<R>,
+    distorted: bool,
+) -> Result<BTreeMap<String, Vec<ReprojectedRow>>, Error> {
+    let Some(table) = archive.kalman_estimates_table.as_ref() else {
+        return Err(Error::MissingKalmanEstimates);
+    };
+    let Some(CalibrationInfo { water, cameras }) = archive.calibration_info.as_ref() else {
+        return Err(Error::MissingCalibration);
+    };
+    let recon = flydra_mvg::FlydraMultiCameraSystem::from_system(cameras.clone(), *water);
+
+    let mut result = BTreeMap::new();
+    for cam_name in recon.cam_names() {
+        let cam = recon.cam_by_name(cam_name).unwrap();
+        let rows = table
+            .iter()
+            .map(|row| {
+                let pt3d = mvg::PointWorldFrame {
+                    coords: nalgebra::Point3::new(row.x, row.y, row.z),
+                };
+                let distorted_px = cam.project_3d_to_distorted_pixel(&pt3d).coords;
+                let in_image = distorted_px.x >= 0.0
+                    && distorted_px.y >= 0.0
+                    && distorted_px.x <= cam.width() as f64
+                    && distorted_px.y <= cam.height() as f64;
+                let px = if distorted {
+                    distorted_px
+                } else {
+                    cam.project_3d_to_pixel(&pt3d).coords
+                };
+                ReprojectedRow {
+                    obj_id: row.obj_id,
+                    frame: row.frame,
+                    timestamp: row.timestamp.clone(),
+                    x: px.x,
+                    y: px.y,
+                    in_image,
+                }
+            })
+            .collect();
+        result.insert(cam_name.to_string(), rows);
+    }
+    Ok(result)
+}
@@ -0,0 +1,169 @@
+//! Fast reading of the rows of CSV tables.
+//!
+//! [RowIter] deserializes the rows directly from the bytes of a reused
+//! [csv::ByteRecord]. Unlike [csv::Reader::into_deserialize], this allocates
+//! no `String` per row and does not validate UTF-8, which dominated the time
+//! to load the large `data2d_distorted` and `kalman_estimates` tables.
+//!
+//! With the `rayon` feature, the records are read in chunks of
+//! [CHUNK_SIZE] rows, which are then deserialized in parallel. The rows are
+//! still returned in the order of the table.
+//!
+//! As with [csv_eof::EarlyEofOk], a table which ends abruptly (e.g. because
+//! Braid quit unexpectedly) ends the iteration without error.
+
+use std::io::Read;
+
+use serde::de::DeserializeOwned;
+
+/// Number of rows deserialized in parallel with the `rayon` feature.
+pub const CHUNK_SIZE: usize = 4096;
+
+/// Iterator over the rows of a CSV table with headers.
+pub struct RowIter<R: Read, T> {
+    rdr: csv::Reader<R>,
+    headers: csv::ByteRecord,
+    #[cfg(not(feature = "rayon"))]
+    record: csv::ByteRecord,
+    #[cfg(not(feature = "rayon"))]
+    _row: std::marker::PhantomData<T>,
+    /// Records of the current chunk, reused between chunks.
+    #[cfg(feature = "rayon")]
+    records: Vec<csv::ByteRecord>,
+    /// Deserialized rows of the current chunk, in reverse order.
+    #[cfg(feature = "rayon")]
+    parsed: Vec<Result<T, csv::Error>>,
+    /// An error which ended reading, returned after the rows before it.
+    #[cfg(feature = "rayon")]
+    pending_error: Option<csv::Error>,
+    done: bool,
+}
+
+impl<R: Read, T: DeserializeOwned + Send> RowIter<R, T> {
+    /// Read the rows of `rdr`, which must have headers.
+    pub fn new(mut rdr: csv::Reader<R>) -> Result<Self, csv::Error> {
+        let headers = match rdr.byte_headers() {
+            Ok(headers) => headers.clone(),
+            Err(e) if csv_eof::is_early_eof(&e) => csv::ByteRecord::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            rdr,
+            headers,
+            #[cfg(not(feature = "rayon"))]
+            record: csv::ByteRecord::new(),
+            #[cfg(not(feature = "rayon"))]
+            _row: std::marker::PhantomData,
+            #[cfg(feature = "rayon")]
+            records: Vec::new(),
+            #[cfg(feature = "rayon")]
+            parsed: Vec::new(),
+            #[cfg(feature = "rayon")]
+            pending_error: None,
+            done: false,
+        })
+    }
+
+    /// Read the next record into `record`. Returns `Ok(false)` at the end of
+    /// the table.
+    fn read_record(
+        rdr: &mut csv::Reader<R>,
+        record: &mut csv::ByteRecord,
+    ) -> Result<bool, csv::Error> {
+        match rdr.read_byte_record(record) {
+            Err(e) if csv_eof::is_early_eof(&e) => Ok(false),
+            other => other,
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    fn read_chunk(&mut self) {
+        use rayon::prelude::*;
+
+        if self.records.len() < CHUNK_SIZE {
+            self.records.resize_with(CHUNK_SIZE, csv::ByteRecord::new);
+        }
+        let mut n = 0;
+        while n < CHUNK_SIZE {
+            match Self::read_record(&mut self.rdr, &mut self.records[n]) {
+                Ok(true) => n += 1,
+                Ok(false) => {
+                    self.done = true;
+                    break;
+                }
+                Err(e) => {
+                    self.pending_error = Some(e);
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+        let headers = &self.headers;
+        self.parsed = self.records[..n]
+            .par_iter()
+            .rev()
+            .map(|record| record.deserialize(Some(headers)))
+            .collect();
+    }
+}
+
+impl<R: Read, T: DeserializeOwned + Send> Iterator for RowIter<R, T> {
+    type Item = Result<T, csv::Error>;
+
+    #[cfg(not(feature = "rayon"))]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match Self::read_record(&mut self.rdr, &mut self.record) {
+            Ok(true) => Some(self.record.deserialize(Some(&self.headers))),
+            Ok(false) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.parsed.is_empty() && !self.done {
+            self.read_chunk();
+        }
+        self.parsed
+            .pop()
+            .or_else(|| self.pending_error.take().map(Err))
+    }
+}
+
+#[test]
+fn test_row_iter() {
+    use flydra_types::DataAssocRow;
+
+    let buf = b"obj_id,frame,cam_num,pt_idx\n1,10,0,0\n1,11,1,2\n2,11,0,1\n";
+    let rows: Vec<DataAssocRow> = RowIter::new(csv::Reader::from_reader(&buf[..]))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[1].frame.0, 11);
+    assert_eq!(rows[1].pt_idx, 2);
+    assert_eq!(rows[2].obj_id, 2);
+
+    // An invalid row is an error.
+    let buf = b"obj_id,frame,cam_num,pt_idx\n1,10,0,0\nx,11,1,2\n";
+    let rows: Vec<Result<DataAssocRow, _>> = RowIter::new(csv::Reader::from_reader(&buf[..]))
+        .unwrap()
+        .collect();
+    assert_eq!(rows.len(), 2);
+    assert!(rows[0].is_ok());
+    assert!(rows[1].is_err());
+
+    // A table without rows.
+    let buf = b"obj_id,frame,cam_num,pt_idx\n";
+    let mut rows = RowIter::<_, DataAssocRow>::new(csv::Reader::from_reader(&buf[..])).unwrap();
+    assert!(rows.next().is_none());
+}
@@ -0,0 +1,375 @@
+//! Smoothing and resampling of the 3D trajectories.
+//!
+//! The estimates in the `kalman_estimates` table are computed online, so each
+//! uses only the observations up to its frame. [smooth] additionally uses the
+//! later estimates with a forward-backward (Rauch-Tung-Striebel) smoother. The
+//! saved positions are taken as observations, with the variance of their saved
+//! covariance unless [SmoothParams::observation_std] is given, and the motion
+//! model is the constant velocity model of Braid (see
+//! [flydra_types::TrackingParams]). As this model is independent for the x, y
+//! and z axes, each axis is smoothed separately and the covariances between
+//! the axes (`P01`, `P02` and `P12`) of the result are zero.
+//!
+//! [resample] then computes the smoothed trajectories at a uniform rate by
+//! cubic Hermite interpolation of the smoothed positions and velocities.
+
+use std::{
+    collections::BTreeMap,
+    io::{Read, Seek},
+    path::Path,
+};
+
+use nalgebra::{Matrix2, Vector2};
+
+use braidz_types::ResampledRow;
+use flydra_types::{FlydraFloatTimestampLocal, KalmanEstimatesRow};
+
+use crate::{copy_to_writer, BraidzArchive, Error};
+
+/// The table of smoothed estimates saved in a `.braidz` file.
+pub const SMOOTHED_CSV_FNAME: &str = "kalman_estimates_smoothed.csv";
+
+/// The table of resampled estimates saved in a `.braidz` file.
+pub const RESAMPLED_CSV_FNAME: &str = "kalman_estimates_resampled.csv";
+
+/// Parameters for smoothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmoothParams {
+    /// Scale of the process noise covariance of the motion model, as
+    /// [flydra_types::TrackingParams::motion_noise_scale].
+    pub motion_noise_scale: f64,
+    /// Standard deviation, in meters, of the saved positions. If `None`, the
+    /// saved covariance of each position is used.
+    pub observation_std: Option<f64>,
+}
+
+impl SmoothParams {
+    /// The parameters with the motion model used for tracking.
+    pub fn from_archive<R: Read + Seek>(archive: &BraidzArchive<R>) -> Result<Self, Error> {
+        let Some(info) = archive.kalman_estimates_info.as_ref() else {
+            return Err(Error::MissingKalmanEstimates);
+        };
+        Ok(Self {
+            motion_noise_scale: info.tracking_parameters.motion_noise_scale,
+            observation_std: None,
+        })
+    }
+}
+
+/// State and covariance of one axis: position and velocity.
+type AxisState = (Vector2<f64>, Matrix2<f64>);
+
+fn transition(dt: f64) -> Matrix2<f64> {
+    Matrix2::new(1.0, dt, 0.0, 1.0)
+}
+
+fn process_noise(dt: f64, motion_noise_scale: f64) -> Matrix2<f64> {
+    let t22 = dt * dt / 2.0;
+    let t33 = dt * dt * dt / 3.0;
+    Matrix2::new(t33, t22, t22, dt) * motion_noise_scale
+}
+
+/// Smooth one axis given the times `t`, observed positions `z` with variances
+/// `r` and the initial state.
+fn smooth_axis(t: &[f64], z: &[f64], r: &[f64], init: AxisState, q: f64) -> Vec<AxisState> {
+    let n = t.len();
+    let mut predicted: Vec<AxisState> = Vec::with_capacity(n);
+    let mut filtered: Vec<AxisState> = Vec::with_capacity(n);
+    for k in 0..n {
+        let (xp, pp) = if k == 0 {
+            init
+        } else {
+            let dt = t[k] - t[k - 1];
+            let f = transition(dt);
+            let (xf, pf) = filtered[k - 1];
+            (f * xf, f * pf * f.transpose() + process_noise(dt, q))
+        };
+        let s = pp[(0, 0)] + r[k];
+        let gain: Vector2<f64> = pp.column(0) / s;
+        let x = xp + gain * (z[k] - xp[0]);
+        let p = pp - gain * pp.row(0);
+        predicted.push((xp, pp));
+        filtered.push((x, p));
+    }
+
+    let mut smoothed = filtered.clone();
+    for k in (0..n.saturating_sub(1)).rev() {
+        let f = transition(t[k + 1] - t[k]);
+        let (xf, pf) = filtered[k];
+        let (xp, pp) = predicted[k + 1];
+        let (xs, ps) = smoothed[k + 1];
+        let Some(pp_inv) = pp.try_inverse() else {
+            continue;
+        };
+        let c = pf * f.transpose() * pp_inv;
+        smoothed[k] = (xf + c * (xs - xp), pf + c * (ps - pp) * c.transpose());
+    }
+    smoothed
+}
+
+/// Smooth one trajectory. The rows must be of the same object and in
+/// ascending order of frame.
+pub fn smooth_trajectory(
+    rows: &[KalmanEstimatesRow],
+    fps: f64,
+    params: &SmoothParams,
+) -> Vec<KalmanEstimatesRow> {
+    let Some(first) = rows.first() else {
+        return vec![];
+    };
+    let t: Vec<f64> = rows
+        .iter()
+        .map(|row| (row.frame.0 - first.frame.0) as f64 / fps)
+        .collect();
+    let observation_var = params.observation_std.map(|std| std * std);
+
+    let mut axes = vec![];
+    for axis in 0..3 {
+        let pos = |row: &KalmanEstimatesRow| [row.x, row.y, row.z][axis];
+        let vel = |row: &KalmanEstimatesRow| [row.xvel, row.yvel, row.zvel][axis];
+        let pos_var = |row: &KalmanEstimatesRow| [row.P00, row.P11, row.P22][axis];
+        let vel_var = |row: &KalmanEstimatesRow| [row.P33, row.P44, row.P55][axis];
+
+        let z: Vec<f64> = rows.iter().map(pos).collect();
+        let r: Vec<f64> = rows
+            .iter()
+            .map(|row| observation_var.unwrap_or_else(|| pos_var(row)).max(1e-12))
+            .collect();
+        let init = (
+            Vector2::new(pos(first), vel(first)),
+            Matrix2::new(pos_var(first).max(1e-12), 0.0, 0.0, vel_var(first)),
+        );
+        axes.push(smooth_axis(&t, &z, &r, init, params.motion_noise_scale));
+    }
+
+    rows.iter()
+        .enumerate()
+        .map(|(k, row)| {
+            let (x, px) = axes[0][k];
+            let (y, py) = axes[1][k];
+            let (z, pz) = axes[2][k];
+            KalmanEstimatesRow {
+                obj_id: row.obj_id,
+                frame: row.frame,
+                timestamp: row.timestamp.clone(),
+                x: x[0],
+                y: y[0],
+                z: z[0],
+                xvel: x[1],
+                yvel: y[1],
+                zvel: z[1],
+                P00: px[(0, 0)],
+                P01: 0.0,
+                P02: 0.0,
+                P11: py[(0, 0)],
+                P12: 0.0,
+                P22: pz[(0, 0)],
+                P33: px[(1, 1)],
+                P44: py[(1, 1)],
+                P55: pz[(1, 1)],
+            }
+        })
+        .collect()
+}
+
+/// Smooth all trajectories of the `kalman_estimates` table.
+///
+/// The result is ordered by `obj_id` and frame.
+pub fn smooth(
+    rows: &[KalmanEstimatesRow],
+    fps: f64,
+    params: &SmoothParams,
+) -> Result<Vec<KalmanEstimatesRow>, Error> {
+    if !(fps.is_finite() && fps > 0.0) {
+        return Err(Error::UnknownFrameRate);
+    }
+    let mut by_obj_id: BTreeMap<u32, Vec<KalmanEstimatesRow>> = BTreeMap::new();
+    for row in rows.iter() {
+        by_obj_id.entry(row.obj_id).or_default().push(row.clone());
+    }
+    let mut result = Vec::with_capacity(rows.len());
+    for (_obj_id, mut traj) in by_obj_id.into_iter() {
+        traj.sort_by_key(|row| row.frame);
+        result.extend(smooth_trajectory(&traj, fps, params));
+    }
+    Ok(result)
+}
+
+/// Resample trajectories at `resample_fps`, starting at the first frame of
+/// each trajectory.
+///
+/// `rows` are smoothed rows as returned by [smooth], ordered by `obj_id` and
+/// frame. `fps` is the frame rate of the frame numbers.
+pub fn resample(rows: &[KalmanEstimatesRow], fps: f64, resample_fps: f64) -> Vec<ResampledRow> {
+    let mut result = vec![];
+    let mut start = 0;
+    while start < rows.len() {
+        let len = rows[start..]
+            .iter()
+            .take_while(|row| row.obj_id == rows[start].obj_id)
+            .count();
+        let traj = &rows[start..start + len];
+        start += len;
+        let first = &traj[0];
+        let t0 = first.frame.0 as f64 / fps;
+        let t_end = traj[traj.len() - 1].frame.0 as f64 / fps;
+        let mut k = 0;
+        for j in 0u64.. {
+            let t = t0 + j as f64 / resample_fps;
+            if t > t_end + 1e-9 {
+                break;
+            }
+            // Find the rows before and after `t`.
+            while k + 1 < traj.len() && (traj[k + 1].frame.0 as f64 / fps) < t {
+                k += 1;
+            }
+            let a = &traj[k];
+            let b = traj.get(k + 1).unwrap_or(a);
+            let ta = a.frame.0 as f64 / fps;
+            let h = b.frame.0 as f64 / fps - ta;
+            let s = if h > 0.0 {
+                ((t - ta) / h).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let axis = |p0: f64, v0: f64, p1: f64, v1: f64| hermite(p0, v0, p1, v1, h, s);
+            let (x, xvel) = axis(a.x, a.xvel, b.x, b.xvel);
+            let (y, yvel) = axis(a.y, a.yvel, b.y, b.yvel);
+            let (z, zvel) = axis(a.z, a.zvel, b.z, b.zvel);
+            let timestamp = first
+                .timestamp
+                .as_ref()
+                .map(|ts| FlydraFloatTimestampLocal::from_f64(ts.as_f64() + (t - t0)));
+            result.push(ResampledRow {
+                obj_id: first.obj_id,
+                frame: t * fps,
+                timestamp,
+                x,
+                y,
+                z,
+                xvel,
+                yvel,
+                zvel,
+            });
+        }
+    }
+    result
+}
+
+/// Cubic Hermite interpolation at fraction `s` of an interval of duration `h`.
+/// Returns the position and velocity.
+fn hermite(p0: f64, v0: f64, p1: f64, v1: f64, h: f64, s: f64) -> (f64, f64) {
+    if h <= 0.0 {
+        return (p0, v0);
+    }
+    let (s2, s3) = (s * s, s * s * s);
+    let pos = (2.0 * s3 - 3.0 * s2 + 1.0) * p0
+        + (s3 - 2.0 * s2 + s) * h * v0
+        + (-2.0 * s3 + 3.0 * s2) * p1
+        + (s3 - s2) * h * v1;
+    let vel = ((6.0 * s2 - 6.0 * s) * p0
+        + (3.0 * s2 - 4.0 * s + 1.0) * h * v0
+        + (-6.0 * s2 + 6.0 * s) * p1
+        + (3.0 * s2 - 2.0 * s) * h * v1)
+        / h;
+    (pos, vel)
+}
+
+/// Smooth and optionally resample the trajectories of a `.braidz` file and
+/// save the result as a new table in a copy of it.
+///
+/// `output` must end with `.braidz`. The smoothed estimates are saved in the
+/// table [SMOOTHED_CSV_FNAME] and, if `resample_fps` is given, the resampled
+/// estimates in [RESAMPLED_CSV_FNAME].
+pub fn smooth_braidz<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    params: &SmoothParams,
+    resample_fps: Option<f64>,
+) -> Result<(), Error> {
+    let archive = crate::braidz_parse_path(&input)?;
+    let Some(table) = archive.kalman_estimates_table.as_ref() else {
+        return Err(Error::MissingKalmanEstimates);
+    };
+    let smoothed = smooth(table, archive.expected_fps, params)?;
+
+    let wtr = copy_to_writer(
+        input.as_ref(),
+        output.as_ref(),
+        &archive.metadata,
+        &[SMOOTHED_CSV_FNAME, RESAMPLED_CSV_FNAME],
+    )?;
+    wtr.write_table(SMOOTHED_CSV_FNAME, smoothed.iter())?;
+    if let Some(resample_fps) = resample_fps {
+        let resampled = resample(&smoothed, archive.expected_fps, resample_fps);
+        wtr.write_table(RESAMPLED_CSV_FNAME, resampled.iter())?;
+    }
+    wtr.finish()?;
+    Ok(())
+}
+
+#[test]
+fn test_smooth() {
+    let fps = 100.0;
+    let params = SmoothParams {
+        motion_noise_scale: 0.1,
+        observation_std: Some(0.01),
+    };
+    // Noisy observations of an object moving at 1 m/s along x.
+    let noise = [0.01, -0.01, 0.005, -0.005];
+    let rows: Vec<KalmanEstimatesRow> = (0..100u64)
+        .map(|i| KalmanEstimatesRow {
+            obj_id: 1,
+            frame: flydra_types::SyncFno(1000 + i),
+            timestamp: None,
+            x: i as f64 / fps + noise[i as usize % 4],
+            y: 0.0,
+            z: 0.1,
+            xvel: 1.0,
+            yvel: 0.0,
+            zvel: 0.0,
+            P00: 1e-4,
+            P01: 0.0,
+            P02: 0.0,
+            P11: 1e-4,
+            P12: 0.0,
+            P22: 1e-4,
+            P33: 1e-2,
+            P44: 1e-2,
+            P55: 1e-2,
+        })
+        .collect();
+    let smoothed = smooth(&rows, fps, &params).unwrap();
+    assert_eq!(smoothed.len(), rows.len());
+
+    let rms = |rows: &[KalmanEstimatesRow]| {
+        let sum: f64 = rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| (row.x - i as f64 / fps).powi(2))
+            .sum();
+        (sum / rows.len() as f64).sqrt()
+    };
+    assert!(rms(&smoothed) < 0.5 * rms(&rows));
+    assert!(smoothed.iter().all(|row| (row.xvel - 1.0).abs() < 0.2));
+    assert!(smoothed.iter().all(|row| (row.z - 0.1).abs() < 1e-9));
+
+    // Resample at half the frame rate.
+    let resampled = resample(&smoothed, fps, 50.0);
+    assert_eq!(resampled.len(), 50);
+    assert!((resampled[1].frame - 1002.0).abs() < 1e-6);
+    assert!((resampled[1].x - smoothed[2].x).abs() < 1e-6);
+
+    assert!(matches!(
+        smooth(&rows, f64::NAN, &params),
+        Err(Error::UnknownFrameRate)
+    ));
+}
+
+#[test]
+fn test_hermite() {
+    // A cubic is reproduced exactly: p(t) = t^3 on [1, 3].
+    let (p, v) = hermite(1.0, 3.0, 27.0, 27.0, 2.0, 0.5);
+    assert!((p - 8.0).abs() < 1e-12);
+    assert!((v - 12.0).abs() < 1e-12);
+}
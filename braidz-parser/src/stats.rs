@@ -0,0 +1,262 @@
+//! Trajectory statistics and data quality metrics.
+//!
+//! For each trajectory of the `kalman_estimates` table, this computes its
+//! duration, path length and mean speed. The 2D detections associated with the
+//! trajectory (from the `data_association` table) give the number of
+//! contributing cameras and, if the archive has a calibration, the
+//! reprojection error.
+//!
+//! The quality of the 2D data is summarized by the frames missing from each
+//! camera and the gaps in which no camera has any data. Unless
+//! `save_empty_data2d` is set in the metadata, frames in which a camera
+//! detected nothing are not saved and thus also count as missing.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{Read, Seek},
+};
+
+use braidz_types::{
+    BraidzStats, CalibrationInfo, CameraStats, ErrorStats, FrameGap, TrajectoryStats,
+};
+use csv_eof::EarlyEofOk;
+use flydra_types::{CamNum, DataAssocRow, KalmanEstimatesRow};
+
+use crate::{has_table, open_maybe_gzipped, BraidzArchive, Error};
+
+/// A 2D detection associated with a trajectory.
+struct Observation {
+    camn: CamNum,
+    x: f64,
+    y: f64,
+}
+
+/// Compute the statistics of the trajectories and the 2D data.
+///
+/// The `data2d_distorted` table is read once and only the associated
+/// detections are kept in memory.
+pub fn compute_stats<R: Read + Seek>(archive: &mut BraidzArchive<R>) -> Result<BraidzStats, Error> {
+    // Which trajectory, if any, each detection is associated with.
+    let mut assoc: BTreeMap<(i64, CamNum, u8), u32> = BTreeMap::new();
+    if has_table(archive, flydra_types::DATA_ASSOCIATE_CSV_FNAME) {
+        let data_fname = archive
+            .archive
+            .path_starter()
+            .join(flydra_types::DATA_ASSOCIATE_CSV_FNAME);
+        let rdr = csv::Reader::from_reader(open_maybe_gzipped(data_fname)?);
+        for row in rdr.into_deserialize().early_eof_ok() {
+            let row: DataAssocRow = row?;
+            let frame: i64 = row.frame.0.try_into().unwrap();
+            assoc.insert((frame, row.cam_num, row.pt_idx), row.obj_id);
+        }
+    }
+
+    let mut cam_frames: BTreeMap<CamNum, BTreeSet<i64>> = BTreeMap::new();
+    let mut observations: BTreeMap<(u32, i64), Vec<Observation>> = BTreeMap::new();
+    if archive.data2d_distorted.is_some() {
+        for row in archive.iter_data2d_distorted()? {
+            let row = row?;
+            cam_frames.entry(row.camn).or_default().insert(row.frame);
+            if row.x.is_nan() {
+                continue;
+            }
+            if let Some(obj_id) = assoc.get(&(row.frame, row.camn, row.frame_pt_idx)) {
+                observations
+                    .entry((*obj_id, row.frame))
+                    .or_default()
+                    .push(Observation {
+                        camn: row.camn,
+                        x: row.x,
+                        y: row.y,
+                    });
+            }
+        }
+    }
+
+    let all_frames: BTreeSet<i64> = cam_frames.values().flatten().copied().collect();
+    let frame_range = match (all_frames.first(), all_frames.last()) {
+        (Some(first), Some(last)) => Some((*first, *last)),
+        _ => None,
+    };
+
+    let cameras = cam_frames
+        .iter()
+        .map(|(camn, frames)| {
+            let num_frames = frames.len() as u64;
+            let total = frame_range.map_or(0, |(first, last)| (last - first + 1) as u64);
+            let cam_id = archive
+                .cam_info
+                .camn2camid
+                .get(camn)
+                .cloned()
+                .unwrap_or_else(|| format!("camn {}", camn.0));
+            CameraStats {
+                cam_id,
+                num_frames,
+                missing_frames: total - num_frames,
+            }
+        })
+        .collect();
+
+    let trajectories = match archive.kalman_estimates_table.as_ref() {
+        Some(table) => trajectory_stats(archive, table, &observations),
+        None => vec![],
+    };
+
+    Ok(BraidzStats {
+        trajectories,
+        cameras,
+        sync_gaps: find_gaps(&all_frames),
+        save_empty_data2d: archive.metadata.save_empty_data2d,
+    })
+}
+
+fn trajectory_stats<R: Read + Seek>(
+    archive: &BraidzArchive<R>,
+    table: &[KalmanEstimatesRow],
+    observations: &BTreeMap<(u32, i64), Vec<Observation>>,
+) -> Vec<TrajectoryStats> {
+    let recon = archive
+        .calibration_info
+        .as_ref()
+        .map(|CalibrationInfo { water, cameras }| {
+            flydra_mvg::FlydraMultiCameraSystem::from_system(cameras.clone(), *water)
+        });
+
+    let mut by_obj_id: BTreeMap<u32, Vec<&KalmanEstimatesRow>> = BTreeMap::new();
+    for row in table {
+        by_obj_id.entry(row.obj_id).or_default().push(row);
+    }
+
+    by_obj_id
+        .into_iter()
+        .map(|(obj_id, rows)| {
+            let first = rows.first().unwrap();
+            let last = rows.last().unwrap();
+            let start_frame = first.frame.0;
+            let end_frame = last.frame.0;
+
+            let path_length: f64 = rows
+                .windows(2)
+                .map(|w| {
+                    let (a, b) = (w[0], w[1]);
+                    ((b.x - a.x).powi(2) + (b.y - a.y).powi(2) + (b.z - a.z).powi(2)).sqrt()
+                })
+                .sum();
+            let duration = match (&first.timestamp, &last.timestamp) {
+                (Some(t0), Some(t1)) => Some(t1.as_f64() - t0.as_f64()),
+                _ if archive.expected_fps.is_finite() && archive.expected_fps > 0.0 => {
+                    Some((end_frame - start_frame) as f64 / archive.expected_fps)
+                }
+                _ => None,
+            };
+            let mean_speed = duration
+                .filter(|duration| *duration > 0.0)
+                .map(|duration| path_length / duration);
+
+            let mut num_observations = 0;
+            let mut cams = BTreeSet::new();
+            let mut errors = vec![];
+            for row in rows.iter() {
+                let frame: i64 = row.frame.0.try_into().unwrap();
+                let Some(obs) = observations.get(&(obj_id, frame)) else {
+                    continue;
+                };
+                num_observations += obs.len();
+                let pt3d = mvg::PointWorldFrame {
+                    coords: nalgebra::Point3::new(row.x, row.y, row.z),
+                };
+                for ob in obs {
+                    cams.insert(ob.camn);
+                    let cam = recon.as_ref().and_then(|recon| {
+                        let cam_id = archive.cam_info.camn2camid.get(&ob.camn)?;
+                        recon.cam_by_name(cam_id)
+                    });
+                    if let Some(cam) = cam {
+                        let px = cam.project_3d_to_distorted_pixel(&pt3d).coords;
+                        errors.push(((px.x - ob.x).powi(2) + (px.y - ob.y).powi(2)).sqrt());
+                    }
+                }
+            }
+
+            TrajectoryStats {
+                obj_id,
+                start_frame,
+                end_frame,
+                num_rows: rows.len(),
+                duration,
+                path_length,
+                mean_speed,
+                num_observations,
+                num_cameras: cams.len(),
+                reprojection_error: error_stats(errors),
+            }
+        })
+        .collect()
+}
+
+/// The mean, median and maximum. Returns `None` if there are no errors.
+fn error_stats(mut errors: Vec<f64>) -> Option<ErrorStats> {
+    if errors.is_empty() {
+        return None;
+    }
+    errors.sort_by(|a, b| a.total_cmp(b));
+    let n = errors.len();
+    let median = if n % 2 == 0 {
+        (errors[n / 2 - 1] + errors[n / 2]) / 2.0
+    } else {
+        errors[n / 2]
+    };
+    Some(ErrorStats {
+        mean: errors.iter().sum::<f64>() / n as f64,
+        median,
+        max: errors[n - 1],
+    })
+}
+
+/// The ranges of frames missing between the first and last frame.
+fn find_gaps(frames: &BTreeSet<i64>) -> Vec<FrameGap> {
+    let mut gaps = vec![];
+    let mut prev: Option<i64> = None;
+    for frame in frames.iter() {
+        if let Some(prev) = prev {
+            if *frame > prev + 1 {
+                gaps.push(FrameGap {
+                    start_frame: prev + 1,
+                    num_frames: (*frame - prev - 1) as u64,
+                });
+            }
+        }
+        prev = Some(*frame);
+    }
+    gaps
+}
+
+#[test]
+fn test_stats_helpers() {
+    let frames: BTreeSet<i64> = [3, 4, 5, 8, 9, 20].into_iter().collect();
+    assert_eq!(
+        find_gaps(&frames),
+        vec![
+            FrameGap {
+                start_frame: 6,
+                num_frames: 2
+            },
+            FrameGap {
+                start_frame: 10,
+                num_frames: 10
+            },
+        ]
+    );
+    assert!(find_gaps(&BTreeSet::new()).is_empty());
+
+    assert_eq!(error_stats(vec![]), None);
+    assert_eq!(
+        error_stats(vec![4.0, 1.0, 2.0, 1.0]),
+        Some(ErrorStats {
+            mean: 2.0,
+            median: 1.5,
+            max: 4.0
+        })
+    );
+}
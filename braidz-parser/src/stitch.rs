@@ -0,0 +1,254 @@
+//! Stitching of trajectories broken into several `obj_id`s.
+//!
+//! After an occlusion, tracking often loses an object and starts a new
+//! trajectory when the object is seen again. Here, a trajectory ending at frame
+//! `f` is linked to a trajectory starting at most [StitchParams::max_gap_frames]
+//! later if the start of the latter is within [StitchParams::max_distance] of
+//! the position predicted from the last position and velocity of the former.
+//! Candidate links are accepted in order of increasing distance, and each
+//! trajectory is linked to at most one predecessor and one successor. A chain
+//! of linked trajectories gets the `obj_id` of its first trajectory.
+//!
+//! [stitch_braidz] saves a copy of a `.braidz` file in which the
+//! `kalman_estimates` table has the stitched `obj_id`s and an
+//! `original_obj_id` column (see [StitchedKalmanEstimatesRow]), and the
+//! `data_association` table refers to the stitched `obj_id`s.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+use braidz_types::StitchedKalmanEstimatesRow;
+use csv_eof::EarlyEofOk;
+use flydra_types::{DataAssocRow, KalmanEstimatesRow};
+
+use crate::{copy_to_writer, has_table, open_maybe_gzipped, Error};
+
+/// Parameters for stitching trajectories.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StitchParams {
+    /// The maximum number of frames between the end of a trajectory and the
+    /// start of the next.
+    pub max_gap_frames: u64,
+    /// The maximum distance, in meters, between the start of a trajectory and
+    /// the position predicted from the end of the previous.
+    pub max_distance: f64,
+}
+
+impl Default for StitchParams {
+    fn default() -> Self {
+        Self {
+            max_gap_frames: 50,
+            max_distance: 0.05,
+        }
+    }
+}
+
+/// The result of stitching.
+#[derive(Debug, Clone, Default)]
+pub struct StitchResult {
+    /// The new `obj_id` of each original `obj_id`.
+    pub obj_id_map: BTreeMap<u32, u32>,
+    /// The number of trajectories before stitching.
+    pub num_trajectories_before: usize,
+    /// The number of trajectories after stitching.
+    pub num_trajectories_after: usize,
+}
+
+/// The first and last row of a trajectory.
+struct Ends<'a> {
+    first: &'a KalmanEstimatesRow,
+    last: &'a KalmanEstimatesRow,
+}
+
+/// Find which trajectories of the `kalman_estimates` table to stitch.
+///
+/// `fps` is the frame rate used to extrapolate the position over a gap. If it
+/// is not finite, the last position is used as the prediction.
+pub fn stitch(rows: &[KalmanEstimatesRow], fps: f64, params: &StitchParams) -> StitchResult {
+    let mut ends: BTreeMap<u32, Ends> = BTreeMap::new();
+    for row in rows.iter() {
+        let entry = ends.entry(row.obj_id).or_insert(Ends {
+            first: row,
+            last: row,
+        });
+        if row.frame < entry.first.frame {
+            entry.first = row;
+        }
+        if row.frame >= entry.last.frame {
+            entry.last = row;
+        }
+    }
+
+    let mut by_start: Vec<(u64, u32)> = ends
+        .iter()
+        .map(|(obj_id, e)| (e.first.frame.0, *obj_id))
+        .collect();
+    by_start.sort();
+
+    // All candidate links with their distance.
+    let mut candidates = vec![];
+    for (prev_id, prev) in ends.iter() {
+        let end_frame = prev.last.frame.0;
+        let start_idx = by_start.partition_point(|(start, _)| *start <= end_frame);
+        for (start_frame, next_id) in by_start[start_idx..].iter() {
+            let gap = start_frame - end_frame;
+            if gap > params.max_gap_frames {
+                break;
+            }
+            let next = &ends[next_id];
+            let dt = if fps.is_finite() && fps > 0.0 {
+                gap as f64 / fps
+            } else {
+                0.0
+            };
+            let p = prev.last;
+            let predicted = [p.x + p.xvel * dt, p.y + p.yvel * dt, p.z + p.zvel * dt];
+            let n = next.first;
+            let dist = ((n.x - predicted[0]).powi(2)
+                + (n.y - predicted[1]).powi(2)
+                + (n.z - predicted[2]).powi(2))
+            .sqrt();
+            if dist <= params.max_distance {
+                candidates.push((dist, *prev_id, *next_id));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    // Accept the closest links first. As links always go forward in time, they
+    // cannot form cycles.
+    let mut successor: BTreeMap<u32, u32> = BTreeMap::new();
+    let mut has_predecessor: BTreeSet<u32> = BTreeSet::new();
+    for (_dist, prev_id, next_id) in candidates {
+        if successor.contains_key(&prev_id) || has_predecessor.contains(&next_id) {
+            continue;
+        }
+        successor.insert(prev_id, next_id);
+        has_predecessor.insert(next_id);
+    }
+
+    let mut obj_id_map = BTreeMap::new();
+    for obj_id in ends.keys() {
+        if has_predecessor.contains(obj_id) {
+            continue;
+        }
+        let mut current = *obj_id;
+        obj_id_map.insert(current, *obj_id);
+        while let Some(next) = successor.get(&current) {
+            obj_id_map.insert(*next, *obj_id);
+            current = *next;
+        }
+    }
+
+    StitchResult {
+        num_trajectories_before: ends.len(),
+        num_trajectories_after: ends.len() - has_predecessor.len(),
+        obj_id_map,
+    }
+}
+
+/// Stitch the trajectories of a `.braidz` file and save the result to a new
+/// `.braidz` file.
+///
+/// `output` must end with `.braidz`. The `kalman_estimates` and
+/// `data_association` tables are replaced, and the other contents of `input`
+/// are copied.
+pub fn stitch_braidz<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    params: &StitchParams,
+) -> Result<StitchResult, Error> {
+    let mut archive = crate::braidz_parse_path(&input)?;
+    let Some(table) = archive.kalman_estimates_table.as_ref() else {
+        return Err(Error::MissingKalmanEstimates);
+    };
+    let result = stitch(table, archive.expected_fps, params);
+    let new_rows: Vec<StitchedKalmanEstimatesRow> = table
+        .iter()
+        .map(|row| StitchedKalmanEstimatesRow::new(row, result.obj_id_map[&row.obj_id]))
+        .collect();
+
+    let assoc_rows = if has_table(&mut archive, flydra_types::DATA_ASSOCIATE_CSV_FNAME) {
+        let data_fname = archive
+            .archive
+            .path_starter()
+            .join(flydra_types::DATA_ASSOCIATE_CSV_FNAME);
+        let rdr = csv::Reader::from_reader(open_maybe_gzipped(data_fname)?);
+        let mut rows = vec![];
+        for row in rdr.into_deserialize().early_eof_ok() {
+            let mut row: DataAssocRow = row?;
+            if let Some(obj_id) = result.obj_id_map.get(&row.obj_id) {
+                row.obj_id = *obj_id;
+            }
+            rows.push(row);
+        }
+        Some(rows)
+    } else {
+        None
+    };
+
+    let wtr = copy_to_writer(
+        input.as_ref(),
+        output.as_ref(),
+        &archive.metadata,
+        &[
+            flydra_types::KALMAN_ESTIMATES_CSV_FNAME,
+            flydra_types::DATA_ASSOCIATE_CSV_FNAME,
+        ],
+    )?;
+    wtr.write_table(flydra_types::KALMAN_ESTIMATES_CSV_FNAME, new_rows.iter())?;
+    if let Some(assoc_rows) = assoc_rows {
+        wtr.write_table(flydra_types::DATA_ASSOCIATE_CSV_FNAME, assoc_rows.iter())?;
+    }
+    wtr.finish()?;
+    Ok(result)
+}
+
+#[test]
+fn test_stitch() {
+    let row = |obj_id: u32, frame: u64, x: f64| KalmanEstimatesRow {
+        obj_id,
+        frame: flydra_types::SyncFno(frame),
+        timestamp: None,
+        x,
+        y: 0.0,
+        z: 0.0,
+        xvel: 1.0,
+        yvel: 0.0,
+        zvel: 0.0,
+        P00: 0.0,
+        P01: 0.0,
+        P02: 0.0,
+        P11: 0.0,
+        P12: 0.0,
+        P22: 0.0,
+        P33: 0.0,
+        P44: 0.0,
+        P55: 0.0,
+    };
+    // Moving at 1 m/s with 100 fps. Object 2 continues object 1 after a gap
+    // of 10 frames, object 3 starts too far away and object 4 too late.
+    let mut rows = vec![];
+    for i in 0..10 {
+        rows.push(row(1, i, i as f64 * 0.01));
+    }
+    for i in 20..30 {
+        rows.push(row(2, i, i as f64 * 0.01));
+        rows.push(row(3, i, 1.0 + i as f64 * 0.01));
+    }
+    for i in 200..210 {
+        rows.push(row(4, i, i as f64 * 0.01));
+    }
+
+    let result = stitch(&rows, 100.0, &StitchParams::default());
+    assert_eq!(result.num_trajectories_before, 4);
+    assert_eq!(result.num_trajectories_after, 3);
+    let expected: BTreeMap<u32, u32> = [(1, 1), (2, 1), (3, 3), (4, 4)].into_iter().collect();
+    assert_eq!(result.obj_id_map, expected);
+
+    // Without the velocity, object 2 starts too far away.
+    let result = stitch(&rows, f64::NAN, &StitchParams::default());
+    assert_eq!(result.num_trajectories_after, 4);
+}
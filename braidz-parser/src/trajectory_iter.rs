@@ -0,0 +1,162 @@
+//! Iteration over the trajectories of the `kalman_estimates` table with
+//! bounded memory.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use flydra_types::KalmanEstimatesRow;
+
+use crate::Error;
+
+/// The rows of the `kalman_estimates` table of one object.
+#[derive(Debug, Clone)]
+pub struct ObjTrajectory {
+    pub obj_id: u32,
+    /// The rows of the object in ascending order of frame.
+    pub rows: Vec<KalmanEstimatesRow>,
+}
+
+impl ObjTrajectory {
+    fn last_frame(&self) -> u64 {
+        // A trajectory is only created with a row.
+        self.rows.last().unwrap().frame.0
+    }
+}
+
+/// Groups rows of the `kalman_estimates` table into trajectories.
+///
+/// The rows must be in ascending order of frame, as they are saved by Braid. A
+/// trajectory is yielded once no row of its object occurred in more than
+/// `max_gap_frames` frames, and the remaining trajectories are yielded at the
+/// end of the rows. Thus, only the trajectories of objects which may still be
+/// alive are kept in memory. Trajectories are yielded in the order they ended.
+///
+/// If `max_gap_frames` is shorter than a gap within the trajectory of an
+/// object, the object is yielded as several trajectories with the same
+/// `obj_id`.
+pub struct TrajectoryIter<I> {
+    rows: I,
+    max_gap_frames: u64,
+    current_frame: Option<u64>,
+    live: BTreeMap<u32, ObjTrajectory>,
+    done: VecDeque<ObjTrajectory>,
+}
+
+impl<I> TrajectoryIter<I>
+where
+    I: Iterator<Item = Result<KalmanEstimatesRow, Error>>,
+{
+    pub fn new(rows: I, max_gap_frames: u64) -> Self {
+        Self {
+            rows,
+            max_gap_frames,
+            current_frame: None,
+            live: BTreeMap::new(),
+            done: VecDeque::new(),
+        }
+    }
+
+    /// Move the trajectories which ended before `frame` to `self.done`.
+    fn finish_before(&mut self, frame: u64) {
+        let max_gap_frames = self.max_gap_frames;
+        let ended: Vec<u32> = self
+            .live
+            .values()
+            .filter(|traj| frame.saturating_sub(traj.last_frame()) > max_gap_frames)
+            .map(|traj| traj.obj_id)
+            .collect();
+        for obj_id in ended {
+            self.done.extend(self.live.remove(&obj_id));
+        }
+    }
+}
+
+impl<I> Iterator for TrajectoryIter<I>
+where
+    I: Iterator<Item = Result<KalmanEstimatesRow, Error>>,
+{
+    type Item = Result<ObjTrajectory, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(traj) = self.done.pop_front() {
+                return Some(Ok(traj));
+            }
+            match self.rows.next() {
+                Some(Ok(row)) => {
+                    let frame = row.frame.0;
+                    if self.current_frame.map_or(true, |current| frame > current) {
+                        self.current_frame = Some(frame);
+                        self.finish_before(frame);
+                    }
+                    self.live
+                        .entry(row.obj_id)
+                        .or_insert_with(|| ObjTrajectory {
+                            obj_id: row.obj_id,
+                            rows: Vec::new(),
+                        })
+                        .rows
+                        .push(row);
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    if self.live.is_empty() {
+                        return None;
+                    }
+                    let live = std::mem::take(&mut self.live);
+                    self.done.extend(live.into_values());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_trajectory_iter() {
+    use flydra_types::SyncFno;
+
+    let row = |obj_id: u32, frame: u64| KalmanEstimatesRow {
+        obj_id,
+        frame: SyncFno(frame),
+        timestamp: None,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+        xvel: 0.0,
+        yvel: 0.0,
+        zvel: 0.0,
+        P00: 0.0,
+        P01: 0.0,
+        P02: 0.0,
+        P11: 0.0,
+        P12: 0.0,
+        P22: 0.0,
+        P33: 0.0,
+        P44: 0.0,
+        P55: 0.0,
+    };
+    let rows = vec![
+        row(1, 10),
+        row(1, 11),
+        row(2, 11),
+        row(1, 13),
+        row(2, 20),
+        row(3, 21),
+        row(2, 22),
+    ];
+    let trajs: Vec<(u32, Vec<u64>)> = TrajectoryIter::new(rows.into_iter().map(Ok), 2)
+        .map(|traj| {
+            let traj = traj.unwrap();
+            (traj.obj_id, traj.rows.iter().map(|r| r.frame.0).collect())
+        })
+        .collect();
+    assert_eq!(
+        trajs,
+        vec![
+            (1, vec![10, 11, 13]),
+            // The gap of obj_id 2 is longer than 2 frames.
+            (2, vec![11]),
+            (2, vec![20, 22]),
+            (3, vec![21]),
+        ]
+    );
+}
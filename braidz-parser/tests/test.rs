@@ -45,3 +45,571 @@ fn test_20191125_104254() {
     let archive = braidz_parser::braidz_parse_path(FILE2_FNAME).unwrap();
     let _summary = braidz_parser::summarize_braidz(&archive, FILE2_FNAME.to_string(), attr.len());
 }
+
+#[test]
+fn test_encrypted() {
+    const FILE1_FNAME: &str = "20201104_174158.braidz";
+    const FILE1_SHA256SUM: &str =
+        "d9e742336cf924f378e49055f3a709e52817ed90385c4f777f443952cf0557d6";
+    const ENCRYPTED_FNAME: &str = "20201104_174158-encrypted.braidz";
+
+    init();
+
+    download_verify::download_verify(
+        format!("{}/{}", URL_BASE, FILE1_FNAME).as_str(),
+        FILE1_FNAME,
+        &download_verify::Hash::Sha256(FILE1_SHA256SUM.into()),
+    )
+    .unwrap();
+
+    let key = braidz_crypt::BraidzKey::generate();
+    braidz_crypt::encrypt(
+        std::fs::File::open(FILE1_FNAME).unwrap(),
+        std::fs::File::create(ENCRYPTED_FNAME).unwrap(),
+        &key,
+    )
+    .unwrap();
+
+    // Without the key, the file cannot be opened.
+    match braidz_parser::braidz_parse_path(ENCRYPTED_FNAME) {
+        Err(braidz_parser::Error::Encrypted { key_id }) => assert_eq!(key_id, key.id()),
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+
+    let keyring = braidz_crypt::Keyring::from(key);
+    let archive = braidz_parser::braidz_parse_encrypted_path(ENCRYPTED_FNAME, &keyring).unwrap();
+    let expected = braidz_parser::braidz_parse_path(FILE1_FNAME).unwrap();
+    assert_eq!(archive.expected_fps, expected.expected_fps);
+    assert_eq!(archive.cam_info.camid2camn, expected.cam_info.camid2camn);
+
+    std::fs::remove_file(ENCRYPTED_FNAME).unwrap();
+}
+
+#[test]
+fn test_streaming_kalman_estimates() {
+    const FILE1_FNAME: &str = "20201104_174158.braidz";
+    const FILE1_SHA256SUM: &str =
+        "d9e742336cf924f378e49055f3a709e52817ed90385c4f777f443952cf0557d6";
+
+    init();
+
+    download_verify::download_verify(
+        format!("{}/{}", URL_BASE, FILE1_FNAME).as_str(),
+        FILE1_FNAME,
+        &download_verify::Hash::Sha256(FILE1_SHA256SUM.into()),
+    )
+    .unwrap();
+
+    let expected = braidz_parser::braidz_parse_path(FILE1_FNAME).unwrap();
+    let expected = expected.kalman_estimates_table.unwrap();
+
+    let mut archive = braidz_parser::braidz_parse_path_streaming(FILE1_FNAME).unwrap();
+    assert!(archive.kalman_estimates_table.is_none());
+    assert!(archive.kalman_estimates_info.is_some());
+
+    let rows: Vec<_> = archive
+        .iter_kalman_estimates()
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(rows.len(), expected.len());
+    for (row, expected_row) in rows.iter().zip(expected.iter()) {
+        assert_eq!(row.obj_id, expected_row.obj_id);
+        assert_eq!(row.frame, expected_row.frame);
+        assert_eq!(row.x, expected_row.x);
+    }
+
+    // With a gap longer than the recording, each object is one trajectory.
+    let trajs: Vec<_> = archive
+        .iter_trajectories(u64::MAX)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let mut obj_ids: Vec<u32> = expected.iter().map(|row| row.obj_id).collect();
+    obj_ids.sort();
+    obj_ids.dedup();
+    assert_eq!(trajs.len(), obj_ids.len());
+    for traj in trajs.iter() {
+        let n_expected = expected
+            .iter()
+            .filter(|row| row.obj_id == traj.obj_id)
+            .count();
+        assert_eq!(traj.rows.len(), n_expected);
+    }
+}
+
+#[test]
+fn test_braidz_writer_roundtrip() {
+    use braidz_types::{BraidMetadata, CamInfoRow, CamNum, KalmanEstimatesRow};
+    use flydra_types::SyncFno;
+
+    init();
+
+    let output_root = tempfile::tempdir().unwrap();
+    let braidz_fname = output_root.path().join("synthetic.braidz");
+
+    let metadata = BraidMetadata {
+        schema: flydra_types::BRAID_SCHEMA,
+        git_revision: "synthetic".to_string(),
+        original_recording_time: None,
+        save_empty_data2d: false,
+        saving_program_name: "test_braidz_writer_roundtrip".to_string(),
+        provenance: vec![braidz_types::Provenance {
+            program: "test_braidz_writer_roundtrip".to_string(),
+            version: "0.0.0".to_string(),
+            git_revision: "synthetic".to_string(),
+            build_profile: "debug".to_string(),
+            host_os: "linux".to_string(),
+            host_arch: "x86_64".to_string(),
+            hostname: Some("localhost".to_string()),
+        }],
+        tracking_determinism: None,
+    };
+    let rows: Vec<KalmanEstimatesRow> = (0..10)
+        .map(|i| KalmanEstimatesRow {
+            obj_id: 1 + i as u32 / 5,
+            frame: SyncFno(100 + i),
+            timestamp: None,
+            x: 0.01 * i as f64,
+            y: 0.0,
+            z: 0.1,
+            xvel: 1.0,
+            yvel: 0.0,
+            zvel: 0.0,
+            P00: 1e-5,
+            P01: 0.0,
+            P02: 0.0,
+            P11: 1e-5,
+            P12: 0.0,
+            P22: 1e-5,
+            P33: 0.1,
+            P44: 0.1,
+            P55: 0.1,
+        })
+        .collect();
+
+    let wtr = braidz_parser::BraidzWriter::new(&braidz_fname, &metadata).unwrap();
+    wtr.write_table(
+        flydra_types::CAM_INFO_CSV_FNAME,
+        [CamInfoRow {
+            camn: CamNum(0),
+            cam_id: "cam1".to_string(),
+        }],
+    )
+    .unwrap();
+    wtr.textlog_writer(
+        Some(100.0),
+        "synthetic",
+        &flydra_types::default_tracking_params_full_3d(),
+    )
+    .unwrap();
+    wtr.write_table(flydra_types::KALMAN_ESTIMATES_CSV_FNAME, rows.iter())
+        .unwrap();
+    assert_eq!(wtr.finish().unwrap(), braidz_fname);
+
+    let archive = braidz_parser::braidz_parse_path(&braidz_fname).unwrap();
+    assert_eq!(
+        archive.metadata.saving_program_name,
+        metadata.saving_program_name
+    );
+    assert_eq!(archive.provenance(), metadata.provenance.as_slice());
+    assert_eq!(archive.expected_fps, 100.0);
+    assert_eq!(archive.cam_info.camid2camn["cam1"], CamNum(0));
+    assert!(archive.data2d_distorted.is_none());
+    let kests = archive.kalman_estimates_info.unwrap();
+    assert_eq!(kests.trajectories.len(), 2);
+    assert_eq!(archive.kalman_estimates_table.unwrap().len(), rows.len());
+}
+
+/// Write a synthetic archive recorded across a daylight saving time transition
+/// and check that its times are parsed as UTC instants.
+#[test]
+fn test_dst_transition() {
+    use braidz_types::{BraidMetadata, CamInfoRow, CamNum};
+    use chrono::{DateTime, Duration, Utc};
+    use flydra_types::{Data2dDistortedRow, FlydraFloatTimestampLocal};
+
+    init();
+
+    // The transition to summer time in Central Europe at 01:00 UTC and back to
+    // winter time at 01:00 UTC, when the local time repeats an hour.
+    for (start, start_local) in [
+        ("2024-03-31T00:59:58Z", "2024-03-31T01:59:58+01:00"),
+        ("2024-10-27T00:59:58Z", "2024-10-27T02:59:58+02:00"),
+    ] {
+        let start: DateTime<Utc> = start.parse().unwrap();
+
+        let output_root = tempfile::tempdir().unwrap();
+        let braidz_fname = output_root.path().join("dst.braidz");
+
+        let metadata = BraidMetadata {
+            schema: flydra_types::BRAID_SCHEMA,
+            git_revision: "synthetic".to_string(),
+            original_recording_time: Some(
+                DateTime::parse_from_rfc3339(start_local)
+                    .unwrap()
+                    .with_timezone(&chrono::Local),
+            ),
+            save_empty_data2d: false,
+            saving_program_name: "test_dst_transition".to_string(),
+            provenance: vec![],
+            tracking_determinism: None,
+        };
+
+        // Four seconds at 1 fps. The host timestamps are not monotonic: the
+        // last row is from before the third.
+        let offsets_msec = [0, 1000, 3000, 2000];
+        let rows: Vec<Data2dDistortedRow> = offsets_msec
+            .iter()
+            .enumerate()
+            .map(|(i, &msec)| {
+                let t = start + Duration::milliseconds(msec);
+                Data2dDistortedRow {
+                    camn: CamNum(0),
+                    frame: i as i64,
+                    timestamp: Some(FlydraFloatTimestampLocal::from_dt(&t)),
+                    cam_received_timestamp: FlydraFloatTimestampLocal::from_dt(&t),
+                    device_timestamp: None,
+                    block_id: None,
+                    x: 10.0,
+                    y: 20.0,
+                    area: 1.0,
+                    slope: 0.0,
+                    eccentricity: 0.0,
+                    frame_pt_idx: 0,
+                    cur_val: 255,
+                    mean_val: 0.0,
+                    sumsqf_val: 0.0,
+                }
+            })
+            .collect();
+
+        let wtr = braidz_parser::BraidzWriter::new(&braidz_fname, &metadata).unwrap();
+        wtr.write_table(
+            flydra_types::CAM_INFO_CSV_FNAME,
+            [CamInfoRow {
+                camn: CamNum(0),
+                cam_id: "cam1".to_string(),
+            }],
+        )
+        .unwrap();
+        wtr.textlog_writer(
+            Some(1.0),
+            "synthetic",
+            &flydra_types::default_tracking_params_full_3d(),
+        )
+        .unwrap();
+        wtr.write_table(flydra_types::DATA2D_DISTORTED_CSV_FNAME, rows.iter())
+            .unwrap();
+        wtr.finish().unwrap();
+
+        let archive = braidz_parser::braidz_parse_path(&braidz_fname).unwrap();
+        assert_eq!(
+            archive
+                .metadata
+                .original_recording_time
+                .unwrap()
+                .with_timezone(&Utc),
+            start
+        );
+        let d2d = archive.data2d_distorted.as_ref().unwrap();
+        assert_eq!(d2d.frame_lim, [0, 3]);
+        assert_eq!(d2d.time_limits[0], start);
+        // Three seconds, regardless of the change of the local time.
+        assert_eq!(
+            d2d.time_limits[1] - d2d.time_limits[0],
+            Duration::seconds(3)
+        );
+        let seq = &d2d.qz[&CamNum(0)];
+        let times: Vec<DateTime<Utc>> = seq.timestamp_host.iter().map(|t| t.into()).collect();
+        let expected: Vec<DateTime<Utc>> = offsets_msec
+            .iter()
+            .map(|&msec| start + Duration::milliseconds(msec))
+            .collect();
+        assert_eq!(times, expected);
+    }
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn test_export_parquet() {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    const FILE1_FNAME: &str = "20201104_174158.braidz";
+    const FILE1_SHA256SUM: &str =
+        "d9e742336cf924f378e49055f3a709e52817ed90385c4f777f443952cf0557d6";
+
+    init();
+
+    download_verify::download_verify(
+        format!("{}/{}", URL_BASE, FILE1_FNAME).as_str(),
+        FILE1_FNAME,
+        &download_verify::Hash::Sha256(FILE1_SHA256SUM.into()),
+    )
+    .unwrap();
+
+    let mut archive = braidz_parser::braidz_parse_path_streaming(FILE1_FNAME).unwrap();
+    let num_kalman_rows = archive.kalman_estimates_info.as_ref().unwrap().num_rows;
+
+    let output_dir = tempfile::tempdir().unwrap();
+    let written = braidz_parser::export::export_parquet(&mut archive, output_dir.path()).unwrap();
+    assert_eq!(written.len(), 2);
+
+    let reader = SerializedFileReader::new(std::fs::File::open(&written[1]).unwrap()).unwrap();
+    let md = reader.metadata().file_metadata();
+    assert_eq!(md.num_rows() as u64, num_kalman_rows);
+    assert_eq!(
+        md.schema_descr().num_columns(),
+        braidz_parser::export::KALMAN_ESTIMATES_COLUMNS.len()
+    );
+}
+
+#[test]
+fn test_stats() {
+    const FILE1_FNAME: &str = "20201104_174158.braidz";
+    const FILE1_SHA256SUM: &str =
+        "d9e742336cf924f378e49055f3a709e52817ed90385c4f777f443952cf0557d6";
+
+    init();
+
+    download_verify::download_verify(
+        format!("{}/{}", URL_BASE, FILE1_FNAME).as_str(),
+        FILE1_FNAME,
+        &download_verify::Hash::Sha256(FILE1_SHA256SUM.into()),
+    )
+    .unwrap();
+
+    let mut archive = braidz_parser::braidz_parse_path(FILE1_FNAME).unwrap();
+    let stats = braidz_parser::stats::compute_stats(&mut archive).unwrap();
+
+    let kests = archive.kalman_estimates_info.as_ref().unwrap();
+    assert_eq!(stats.trajectories.len(), kests.trajectories.len());
+    let num_rows: usize = stats.trajectories.iter().map(|t| t.num_rows).sum();
+    assert_eq!(num_rows as u64, kests.num_rows);
+    let total_distance: f64 = stats.trajectories.iter().map(|t| t.path_length).sum();
+    // The summary computes the distance with single precision positions.
+    assert!((total_distance - kests.total_distance).abs() < 1e-3 * kests.total_distance);
+    for t in stats.trajectories.iter() {
+        assert!(t.start_frame <= t.end_frame);
+        assert!(t.num_cameras <= archive.cam_info.camn2camid.len());
+        if let Some(err) = &t.reprojection_error {
+            assert!(err.mean <= err.max);
+        }
+    }
+
+    assert_eq!(stats.cameras.len(), archive.cam_info.camn2camid.len());
+}
+
+#[test]
+fn test_stitch_braidz() {
+    use braidz_types::StitchedKalmanEstimatesRow;
+
+    const FILE1_FNAME: &str = "20201104_174158.braidz";
+    const FILE1_SHA256SUM: &str =
+        "d9e742336cf924f378e49055f3a709e52817ed90385c4f777f443952cf0557d6";
+
+    init();
+
+    download_verify::download_verify(
+        format!("{}/{}", URL_BASE, FILE1_FNAME).as_str(),
+        FILE1_FNAME,
+        &download_verify::Hash::Sha256(FILE1_SHA256SUM.into()),
+    )
+    .unwrap();
+
+    let output_root = tempfile::tempdir().unwrap();
+    let output = output_root.path().join("stitched.braidz");
+    let params = braidz_parser::stitch::StitchParams::default();
+    let result = braidz_parser::stitch::stitch_braidz(FILE1_FNAME, &output, &params).unwrap();
+    assert!(result.num_trajectories_after <= result.num_trajectories_before);
+
+    // The stitched file can be read as usual.
+    let archive = braidz_parser::braidz_parse_path(&output).unwrap();
+    let kests = archive.kalman_estimates_info.as_ref().unwrap();
+    assert_eq!(kests.trajectories.len(), result.num_trajectories_after);
+
+    // The provenance of each row is kept.
+    let orig = braidz_parser::braidz_parse_path(FILE1_FNAME).unwrap();
+    let orig_rows = orig.kalman_estimates_table.unwrap();
+    let mut zip_archive = archive.into_inner();
+    let data_fname = zip_archive
+        .path_starter()
+        .join(flydra_types::KALMAN_ESTIMATES_CSV_FNAME);
+    let rdr = csv::Reader::from_reader(braidz_parser::open_maybe_gzipped(data_fname).unwrap());
+    let rows: Vec<StitchedKalmanEstimatesRow> =
+        rdr.into_deserialize().collect::<Result<_, _>>().unwrap();
+    assert_eq!(rows.len(), orig_rows.len());
+    for (row, orig_row) in rows.iter().zip(orig_rows.iter()) {
+        assert_eq!(row.original_obj_id, orig_row.obj_id);
+        assert_eq!(row.obj_id, result.obj_id_map[&orig_row.obj_id]);
+    }
+}
+
+#[cfg(feature = "hdf5")]
+#[test]
+fn test_convert_flydra_h5() {
+    use braidz_types::CamNum;
+    use hdf5::{types::FixedAscii, H5Type};
+
+    // Tables as saved by the flydra mainbrain, with 32 bit floats and columns
+    // which have no equivalent in braidz files.
+    #[derive(H5Type, Clone, Copy)]
+    #[repr(C)]
+    struct CamSyncInfo {
+        cam_id: FixedAscii<256>,
+        camn: u16,
+        hostname: FixedAscii<256>,
+    }
+    #[derive(H5Type, Clone, Copy)]
+    #[repr(C)]
+    struct Info2D {
+        camn: u16,
+        frame: i64,
+        timestamp: f64,
+        cam_received_timestamp: f64,
+        x: f32,
+        y: f32,
+        area: f32,
+        slope: f32,
+        eccentricity: f32,
+        frame_pt_idx: u8,
+        cur_val: u8,
+        mean_val: f32,
+        sumsqf_val: f32,
+    }
+    #[allow(non_snake_case)]
+    #[derive(H5Type, Clone, Copy)]
+    #[repr(C)]
+    struct KalmanEstimates {
+        obj_id: u32,
+        frame: i64,
+        timestamp: f64,
+        x: f32,
+        y: f32,
+        z: f32,
+        xvel: f32,
+        yvel: f32,
+        zvel: f32,
+        P00: f32,
+        P11: f32,
+        P22: f32,
+        P03: f32,
+    }
+    #[derive(H5Type, Clone, Copy)]
+    #[repr(C)]
+    struct TextLogDescription {
+        mainbrain_timestamp: f64,
+        cam_id: FixedAscii<255>,
+        host_timestamp: f64,
+        message: FixedAscii<255>,
+    }
+
+    init();
+
+    let output_root = tempfile::tempdir().unwrap();
+    let h5_fname = output_root.path().join("legacy.h5");
+    let braidz_fname = output_root.path().join("legacy.braidz");
+
+    let t0 = 1.2e9;
+    let file = hdf5::File::create(&h5_fname).unwrap();
+    let cam_info: Vec<CamSyncInfo> = (0..2)
+        .map(|i| CamSyncInfo {
+            cam_id: FixedAscii::from_ascii(&format!("cam{}_0", i + 1)).unwrap(),
+            camn: i,
+            hostname: FixedAscii::from_ascii("host").unwrap(),
+        })
+        .collect();
+    file.new_dataset_builder()
+        .with_data(&cam_info)
+        .create("cam_info")
+        .unwrap();
+    let data2d: Vec<Info2D> = (0..20)
+        .map(|i| Info2D {
+            camn: (i % 2) as u16,
+            frame: 100 + i / 2,
+            timestamp: t0 + (i / 2) as f64 * 0.01,
+            cam_received_timestamp: t0 + (i / 2) as f64 * 0.01 + 0.005,
+            x: 10.0 + i as f32,
+            y: 20.0,
+            area: 5.0,
+            slope: 0.5,
+            eccentricity: 1.5,
+            frame_pt_idx: 0,
+            cur_val: 200,
+            mean_val: 10.0,
+            sumsqf_val: 100.0,
+        })
+        .collect();
+    file.new_dataset_builder()
+        .with_data(&data2d)
+        .create("data2d_distorted")
+        .unwrap();
+    let kest: Vec<KalmanEstimates> = (0..10)
+        .map(|i| KalmanEstimates {
+            obj_id: 1,
+            frame: 100 + i,
+            timestamp: t0 + i as f64 * 0.01,
+            x: 0.01 * i as f32,
+            y: 0.0,
+            z: 0.1,
+            xvel: 1.0,
+            yvel: 0.0,
+            zvel: 0.0,
+            P00: 1e-5,
+            P11: 1e-5,
+            P22: 1e-5,
+            P03: 0.0,
+        })
+        .collect();
+    file.new_dataset_builder()
+        .with_data(&kest)
+        .create("kalman_estimates")
+        .unwrap();
+    let textlog: Vec<TextLogDescription> = [
+        "MainBrain running at 100.0 fps, (flydra_version 0.4.28, time_tzname0 CET)",
+        "cam1_0 connected",
+    ]
+    .iter()
+    .map(|message| TextLogDescription {
+        mainbrain_timestamp: t0,
+        cam_id: FixedAscii::from_ascii("mainbrain").unwrap(),
+        host_timestamp: t0,
+        message: FixedAscii::from_ascii(message).unwrap(),
+    })
+    .collect();
+    file.new_dataset_builder()
+        .with_data(&textlog)
+        .create("textlog")
+        .unwrap();
+    file.create_group("calibration").unwrap();
+    file.close().unwrap();
+
+    let report = braidz_parser::flydra_h5::convert_flydra_h5(&h5_fname, &braidz_fname).unwrap();
+    assert_eq!(report.num_rows["data2d_distorted"], 20);
+    assert_eq!(report.num_rows["kalman_estimates"], 10);
+    assert_eq!(report.unconverted_columns["cam_info"], vec!["hostname"]);
+    assert_eq!(report.unconverted_columns["kalman_estimates"], vec!["P03"]);
+    assert_eq!(
+        report.missing_columns["kalman_estimates"],
+        vec!["P01", "P02", "P12", "P33", "P44", "P55"]
+    );
+    assert_eq!(report.unconverted_nodes, vec!["calibration"]);
+
+    let mut archive = braidz_parser::braidz_parse_path(&braidz_fname).unwrap();
+    assert_eq!(archive.expected_fps, 100.0);
+    assert_eq!(archive.metadata.saving_program_name, "flydra");
+    assert_eq!(archive.cam_info.camid2camn["cam2_0"], CamNum(1));
+    let kest_table = archive.kalman_estimates_table.take().unwrap();
+    assert_eq!(kest_table.len(), 10);
+    assert_eq!(kest_table[3].frame, flydra_types::SyncFno(103));
+    assert!((kest_table[3].x - 0.03).abs() < 1e-6);
+    assert!(kest_table[3].P01.is_nan());
+    let rows: Vec<_> = archive
+        .iter_data2d_distorted()
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(rows.len(), 20);
+    assert_eq!(rows[5].camn, CamNum(1));
+    assert_eq!(rows[5].x, 15.0);
+}
@@ -3,7 +3,7 @@ use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
 
 pub use flydra_types::{
-    CamInfoRow, CamNum, Data2dDistortedRow, KalmanEstimatesRow, TrackingParams,
+    CamInfoRow, CamNum, Data2dDistortedRow, KalmanEstimatesRow, Provenance, TrackingParams,
 };
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -19,6 +19,38 @@ pub struct BraidMetadata {
     /// when loading old files is "".
     #[serde(default = "default_saving_program_name")]
     pub saving_program_name: String,
+    /// The programs which produced the data.
+    ///
+    /// This is new in schema 4 and is empty when loading old files.
+    #[serde(default)]
+    pub provenance: Vec<Provenance>,
+    /// The settings of determinism mode, if tracking ran in this mode.
+    ///
+    /// This is new in schema 5 and is `None` when loading old files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracking_determinism: Option<TrackingDeterminism>,
+}
+
+/// Settings of the tracker's determinism mode.
+///
+/// In determinism mode, tracking the same 2D data with the same tracking
+/// parameters and seed gives bit-identical results.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TrackingDeterminism {
+    /// Seed for any random numbers used during tracking.
+    pub seed: u64,
+}
+
+impl TrackingDeterminism {
+    /// Determinism mode with a newly chosen random seed.
+    pub fn new_random_seed() -> Self {
+        use std::hash::{BuildHasher, Hasher};
+        // The standard library seeds `RandomState` randomly.
+        let seed = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        Self { seed }
+    }
 }
 
 fn default_saving_program_name() -> String {
@@ -50,8 +82,8 @@ pub struct BraidzSummary {
 /// A summary of a multi-camera calibration
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CalibrationSummary {
-    /// If `Some(n)`, material with refractive index `n` at z<0.
-    pub water: Option<f64>,
+    /// If `Some(_)`, the boundary between air and water.
+    pub water: Option<flydra_mvg::RefractiveBoundary<f64>>,
     /// All the cameras in this system.
     pub cameras: Vec<CameraSummary>,
 }
@@ -104,8 +136,8 @@ pub struct HistogramSummary {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CalibrationInfo {
-    /// If `Some(n)`, material with refractive index `n` at z<0.
-    pub water: Option<f64>,
+    /// If `Some(_)`, the boundary between air and water.
+    pub water: Option<flydra_mvg::RefractiveBoundary<f64>>,
     /// All the cameras in this system.
     pub cameras: mvg::MultiCameraSystem<f64>,
 }
@@ -130,6 +162,133 @@ pub struct KalmanEstimatesSummary {
     pub total_distance: f64,
 }
 
+/// A 3D estimate (a row of the `kalman_estimates` table) projected into one
+/// camera.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReprojectedRow {
+    pub obj_id: u32,
+    pub frame: flydra_types::SyncFno,
+    #[serde(with = "flydra_types::timestamp_opt_f64")]
+    pub timestamp: Option<flydra_types::FlydraFloatTimestampLocal<flydra_types::Triggerbox>>,
+    /// Horizontal pixel coordinate.
+    pub x: f64,
+    /// Vertical pixel coordinate.
+    pub y: f64,
+    /// Whether the point is within the image.
+    pub in_image: bool,
+}
+
+/// A row of the `kalman_estimates` table after stitching trajectories.
+///
+/// The columns are those of [flydra_types::KalmanEstimatesRow] followed by
+/// `original_obj_id`, the `obj_id` of the row before stitching. Thus the table
+/// can also be read as a `kalman_estimates` table.
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StitchedKalmanEstimatesRow {
+    pub obj_id: u32,
+    pub frame: flydra_types::SyncFno,
+    #[serde(with = "flydra_types::timestamp_opt_f64")]
+    pub timestamp: Option<flydra_types::FlydraFloatTimestampLocal<flydra_types::Triggerbox>>,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub xvel: f64,
+    pub yvel: f64,
+    pub zvel: f64,
+    pub P00: f64,
+    pub P01: f64,
+    pub P02: f64,
+    pub P11: f64,
+    pub P12: f64,
+    pub P22: f64,
+    pub P33: f64,
+    pub P44: f64,
+    pub P55: f64,
+    pub original_obj_id: u32,
+}
+
+impl StitchedKalmanEstimatesRow {
+    /// The row `orig` assigned to the trajectory `obj_id`.
+    pub fn new(orig: &flydra_types::KalmanEstimatesRow, obj_id: u32) -> Self {
+        Self {
+            obj_id,
+            frame: orig.frame,
+            timestamp: orig.timestamp.clone(),
+            x: orig.x,
+            y: orig.y,
+            z: orig.z,
+            xvel: orig.xvel,
+            yvel: orig.yvel,
+            zvel: orig.zvel,
+            P00: orig.P00,
+            P01: orig.P01,
+            P02: orig.P02,
+            P11: orig.P11,
+            P12: orig.P12,
+            P22: orig.P22,
+            P33: orig.P33,
+            P44: orig.P44,
+            P55: orig.P55,
+            original_obj_id: orig.obj_id,
+        }
+    }
+}
+
+/// A 3D estimate of a trajectory resampled to a uniform rate.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResampledRow {
+    pub obj_id: u32,
+    /// The synchronized frame number, which is in general not an integer.
+    pub frame: f64,
+    #[serde(with = "flydra_types::timestamp_opt_f64")]
+    pub timestamp: Option<flydra_types::FlydraFloatTimestampLocal<flydra_types::Triggerbox>>,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub xvel: f64,
+    pub yvel: f64,
+    pub zvel: f64,
+}
+
+/// The value of [BraidTraj::format].
+pub const BRAIDTRAJ_FORMAT: &str = "braidtraj";
+
+/// The current value of [BraidTraj::format_version].
+pub const BRAIDTRAJ_FORMAT_VERSION: u16 = 1;
+
+/// A single trajectory exported from a braidz file.
+///
+/// This is saved as JSON in `.braidtraj` files so that individual trajectories
+/// can be shared without the entire archive.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BraidTraj {
+    /// Always [BRAIDTRAJ_FORMAT].
+    pub format: String,
+    pub format_version: u16,
+    pub obj_id: u32,
+    /// Where this trajectory came from.
+    pub source: BraidTrajSource,
+    pub expected_fps: f64,
+    pub tracking_parameters: TrackingParams,
+    /// SHA-256 of the compact JSON serialization of `rows`, hex encoded.
+    pub rows_sha256: String,
+    /// The rows of the `kalman_estimates` table with this `obj_id`.
+    pub rows: Vec<KalmanEstimatesRow>,
+}
+
+/// Provenance of a [BraidTraj].
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BraidTrajSource {
+    /// The filename of the braidz file or braid directory.
+    pub filename: String,
+    /// SHA-256 of the braidz file, hex encoded.
+    ///
+    /// This is `None` if the source was a braid directory.
+    pub sha256: Option<String>,
+    pub metadata: BraidMetadata,
+}
+
 pub fn camera_name_from_filename<P: AsRef<std::path::Path>>(
     full_path: P,
 ) -> (String, Option<String>) {
@@ -151,6 +310,86 @@ pub fn camera_name_from_filename<P: AsRef<std::path::Path>>(
     (filename, cam_from_filename)
 }
 
+/// Per-trajectory statistics and data quality metrics of a braidz file.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BraidzStats {
+    pub trajectories: Vec<TrajectoryStats>,
+    pub cameras: Vec<CameraStats>,
+    /// Ranges of frames without 2D data from any camera.
+    pub sync_gaps: Vec<FrameGap>,
+    /// Whether frames without detections are saved in the `data2d_distorted`
+    /// table. If not, frames without detections are counted as missing.
+    pub save_empty_data2d: bool,
+}
+
+/// Statistics of one trajectory.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrajectoryStats {
+    pub obj_id: u32,
+    pub start_frame: u64,
+    pub end_frame: u64,
+    pub num_rows: usize,
+    /// Duration in seconds, if the frame rate or timestamps are known.
+    pub duration: Option<f64>,
+    /// Length of the path in meters.
+    pub path_length: f64,
+    /// Mean speed in meters per second, if the duration is known and not zero.
+    pub mean_speed: Option<f64>,
+    /// Number of 2D detections associated with the trajectory.
+    pub num_observations: usize,
+    /// Number of cameras with detections associated with the trajectory.
+    pub num_cameras: usize,
+    /// Distance between the detections and the projection of the 3D estimate,
+    /// in pixels. Requires the calibration.
+    pub reprojection_error: Option<ErrorStats>,
+}
+
+/// Summary statistics of errors.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ErrorStats {
+    pub mean: f64,
+    pub median: f64,
+    pub max: f64,
+}
+
+/// The 2D data of one camera.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CameraStats {
+    pub cam_id: String,
+    /// Number of frames with rows in the `data2d_distorted` table.
+    pub num_frames: u64,
+    /// Number of frames without rows, within the frames with 2D data from any
+    /// camera.
+    pub missing_frames: u64,
+}
+
+/// A range of frames.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct FrameGap {
+    pub start_frame: i64,
+    pub num_frames: u64,
+}
+
+/// What was and was not converted from a legacy flydra HDF5 file.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FlydraH5ConversionReport {
+    /// The HDF5 filename.
+    pub source: String,
+    /// Number of rows converted, by table.
+    pub num_rows: BTreeMap<String, u64>,
+    /// Columns of the HDF5 tables without equivalent in the braidz file, by
+    /// table.
+    pub unconverted_columns: BTreeMap<String, Vec<String>>,
+    /// Columns of the braidz tables missing in the HDF5 file, by table. Their
+    /// values are empty (or NaN).
+    pub missing_columns: BTreeMap<String, Vec<String>>,
+    /// Datasets and groups at the root of the HDF5 file which were not
+    /// converted.
+    pub unconverted_nodes: Vec<String>,
+    /// Further information, e.g. about substituted values.
+    pub notes: Vec<String>,
+}
+
 #[test]
 fn test_cam_from_filename() {
     // prior to adding subseconds
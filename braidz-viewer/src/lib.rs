@@ -557,7 +557,7 @@ fn detail_table_valid(fd: &ValidBraidzFile) -> Html {
 
     let cal = match &summary.calibration_info {
         Some(ci) => match &ci.water {
-            Some(n) => format!("present (water below z=0 with n={})", n),
+            Some(w) => format!("present (water below z={} with n={})", w.z, w.n_water),
             None => "present".to_string(),
         },
         None => "not present".to_string(),
@@ -1,6 +1,10 @@
 use nalgebra::RealField;
+use opencv_ros_camera::NamedIntrinsicParameters;
 use serde::{Deserialize, Serialize};
 
+mod opencv_storage;
+pub use opencv_storage::OpenCvCalibration;
+
 type Coords3D = (f64, f64, f64);
 type Coords2D = (f64, f64);
 
@@ -70,6 +74,98 @@ pub fn save_yaml<P: AsRef<std::path::Path>>(
     Ok(())
 }
 
+/// Save intrinsics as a ROS camera_info YAML file.
+pub fn save_ros_yaml<P: AsRef<std::path::Path>>(
+    path: P,
+    named_intrinsics: &NamedIntrinsicParameters<f64>,
+) -> eyre::Result<()> {
+    let ci: opencv_ros_camera::RosCameraInfo<f64> = named_intrinsics.clone().into();
+    let f = std::fs::File::create(path)?;
+    serde_yaml::to_writer(f, &ci)?;
+    Ok(())
+}
+
+/// Save intrinsics as an OpenCV YAML file.
+pub fn save_opencv_yaml<P: AsRef<std::path::Path>>(
+    path: P,
+    named_intrinsics: &NamedIntrinsicParameters<f64>,
+) -> eyre::Result<()> {
+    std::fs::write(path, to_opencv(named_intrinsics).to_yaml())?;
+    Ok(())
+}
+
+/// Save intrinsics as an OpenCV XML file.
+pub fn save_opencv_xml<P: AsRef<std::path::Path>>(
+    path: P,
+    named_intrinsics: &NamedIntrinsicParameters<f64>,
+) -> eyre::Result<()> {
+    std::fs::write(path, to_opencv(named_intrinsics).to_xml())?;
+    Ok(())
+}
+
+/// Load intrinsics from a ROS camera_info YAML, OpenCV YAML or OpenCV XML
+/// file.
+///
+/// The format is detected from the contents of the file. The OpenCV formats do
+/// not store the camera name, so the file stem is used as name.
+pub fn load_intrinsics<P: AsRef<std::path::Path>>(
+    path: P,
+) -> eyre::Result<NamedIntrinsicParameters<f64>> {
+    let path = path.as_ref();
+    let buf = std::fs::read_to_string(path)?;
+    let opencv = if buf.trim_start().starts_with('<') {
+        Some(OpenCvCalibration::from_xml(&buf)?)
+    } else if buf.starts_with("%YAML") || buf.contains("!!opencv-matrix") {
+        Some(OpenCvCalibration::from_yaml(&buf)?)
+    } else {
+        None
+    };
+    match opencv {
+        Some(cal) => Ok(NamedIntrinsicParameters {
+            intrinsics: intrinsics_from_opencv(&cal.camera_matrix, &cal.distortion_coeffs),
+            width: cal.image_width as usize,
+            height: cal.image_height as usize,
+            name: path
+                .file_stem()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        }),
+        None => {
+            let ci: opencv_ros_camera::RosCameraInfo<f64> = serde_yaml::from_str(&buf)?;
+            ci.try_into()
+                .map_err(|e| eyre::eyre!("invalid camera_info: {e}"))
+        }
+    }
+}
+
+fn to_opencv(named_intrinsics: &NamedIntrinsicParameters<f64>) -> OpenCvCalibration {
+    let k = &named_intrinsics.intrinsics.k;
+    let d = &named_intrinsics.intrinsics.distortion;
+    OpenCvCalibration {
+        image_width: named_intrinsics.width as u32,
+        image_height: named_intrinsics.height as u32,
+        camera_matrix: [
+            k[(0, 0)],
+            k[(0, 1)],
+            k[(0, 2)],
+            k[(1, 0)],
+            k[(1, 1)],
+            k[(1, 2)],
+            k[(2, 0)],
+            k[(2, 1)],
+            k[(2, 2)],
+        ],
+        distortion_coeffs: [
+            d.radial1(),
+            d.radial2(),
+            d.tangential1(),
+            d.tangential2(),
+            d.radial3(),
+        ],
+        avg_reprojection_error: None,
+    }
+}
+
 /// Given some checkerboard corner locations, compute intrinsics using OpenCV.
 pub fn compute_intrinsics_with_raw_opencv<R: RealField>(
     size: PixelSize,
@@ -113,17 +209,27 @@ pub fn compute_intrinsics_with_raw_opencv<R: RealField>(
 pub fn convert_to_cam_geom<R: RealField>(
     opencv_results: &opencv_calibrate::CalibrationResult,
 ) -> opencv_ros_camera::RosOpenCvIntrinsics<R> {
-    let fx = nalgebra::convert(opencv_results.camera_matrix[0]);
-    let skew = nalgebra::convert(opencv_results.camera_matrix[1]);
-    let fy = nalgebra::convert(opencv_results.camera_matrix[4]);
-    let cx = nalgebra::convert(opencv_results.camera_matrix[2]);
-    let cy = nalgebra::convert(opencv_results.camera_matrix[5]);
+    intrinsics_from_opencv(
+        &opencv_results.camera_matrix,
+        &opencv_results.distortion_coeffs,
+    )
+}
+
+fn intrinsics_from_opencv<R: RealField>(
+    camera_matrix: &[f64; 9],
+    distortion_coeffs: &[f64; 5],
+) -> opencv_ros_camera::RosOpenCvIntrinsics<R> {
+    let fx = nalgebra::convert(camera_matrix[0]);
+    let skew = nalgebra::convert(camera_matrix[1]);
+    let fy = nalgebra::convert(camera_matrix[4]);
+    let cx = nalgebra::convert(camera_matrix[2]);
+    let cy = nalgebra::convert(camera_matrix[5]);
     let dist = nalgebra::Vector5::new(
-        nalgebra::convert(opencv_results.distortion_coeffs[0]),
-        nalgebra::convert(opencv_results.distortion_coeffs[1]),
-        nalgebra::convert(opencv_results.distortion_coeffs[2]),
-        nalgebra::convert(opencv_results.distortion_coeffs[3]),
-        nalgebra::convert(opencv_results.distortion_coeffs[4]),
+        nalgebra::convert(distortion_coeffs[0]),
+        nalgebra::convert(distortion_coeffs[1]),
+        nalgebra::convert(distortion_coeffs[2]),
+        nalgebra::convert(distortion_coeffs[3]),
+        nalgebra::convert(distortion_coeffs[4]),
     );
     let dist = opencv_ros_camera::Distortion::from_opencv_vec(dist);
 
@@ -0,0 +1,230 @@
+//! Camera intrinsics in the YAML and XML file formats of OpenCV's
+//! `cv::FileStorage`.
+//!
+//! The files use the node names of the OpenCV camera calibration sample:
+//! `image_width`, `image_height`, `camera_matrix`, `distortion_coefficients`
+//! and, optionally, `avg_reprojection_error`. Other nodes are ignored when
+//! reading.
+
+use serde::Deserialize;
+
+/// Camera intrinsics as stored by OpenCV.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenCvCalibration {
+    pub image_width: u32,
+    pub image_height: u32,
+    /// camera calibration matrix, row major order
+    pub camera_matrix: [f64; 9],
+    /// non-linear distortion coefficients (k1, k2, p1, p2, k3)
+    pub distortion_coeffs: [f64; 5],
+    /// mean reprojection distance, in pixels, if known
+    pub avg_reprojection_error: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct Storage<M> {
+    image_width: u32,
+    image_height: u32,
+    camera_matrix: M,
+    distortion_coefficients: M,
+    #[serde(default)]
+    avg_reprojection_error: Option<f64>,
+}
+
+/// A matrix in a YAML file, tagged `!!opencv-matrix`.
+#[derive(Deserialize)]
+struct YamlMatrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<f64>,
+}
+
+/// A matrix in an XML file, with `type_id="opencv-matrix"`.
+#[derive(Deserialize)]
+struct XmlMatrix {
+    rows: usize,
+    cols: usize,
+    /// Whitespace separated values.
+    data: String,
+}
+
+impl YamlMatrix {
+    fn into_values(self) -> eyre::Result<Vec<f64>> {
+        check_len(self.rows, self.cols, self.data)
+    }
+}
+
+impl XmlMatrix {
+    fn into_values(self) -> eyre::Result<Vec<f64>> {
+        let data = self
+            .data
+            .split_whitespace()
+            .map(|x| x.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()?;
+        check_len(self.rows, self.cols, data)
+    }
+}
+
+fn check_len(rows: usize, cols: usize, data: Vec<f64>) -> eyre::Result<Vec<f64>> {
+    if rows * cols != data.len() {
+        eyre::bail!("matrix of size {rows}x{cols} has {} elements", data.len());
+    }
+    Ok(data)
+}
+
+impl OpenCvCalibration {
+    fn from_values(
+        image_width: u32,
+        image_height: u32,
+        camera_matrix: Vec<f64>,
+        distortion_coeffs: Vec<f64>,
+        avg_reprojection_error: Option<f64>,
+    ) -> eyre::Result<Self> {
+        let camera_matrix: [f64; 9] = camera_matrix
+            .try_into()
+            .map_err(|_| eyre::eyre!("camera matrix must be 3x3"))?;
+        // OpenCV uses 4, 5, 8, 12 or 14 distortion coefficients. Only the
+        // first five are supported.
+        if distortion_coeffs.len() < 4 || distortion_coeffs.iter().skip(5).any(|x| *x != 0.0) {
+            eyre::bail!(
+                "unsupported distortion model with {} coefficients",
+                distortion_coeffs.len()
+            );
+        }
+        let mut d = [0.0; 5];
+        for (dest, src) in d.iter_mut().zip(distortion_coeffs.iter()) {
+            *dest = *src;
+        }
+        Ok(Self {
+            image_width,
+            image_height,
+            camera_matrix,
+            distortion_coeffs: d,
+            avg_reprojection_error,
+        })
+    }
+
+    /// Parse an OpenCV YAML file.
+    pub fn from_yaml(buf: &str) -> eyre::Result<Self> {
+        // The YAML 1.0 directive and the custom tag of OpenCV are not
+        // understood by serde_yaml.
+        let buf = buf
+            .lines()
+            .filter(|line| !line.starts_with("%YAML"))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .replace("!!opencv-matrix", "");
+        let storage: Storage<YamlMatrix> = serde_yaml::from_str(&buf)?;
+        Self::from_values(
+            storage.image_width,
+            storage.image_height,
+            storage.camera_matrix.into_values()?,
+            storage.distortion_coefficients.into_values()?,
+            storage.avg_reprojection_error,
+        )
+    }
+
+    /// Parse an OpenCV XML file.
+    pub fn from_xml(buf: &str) -> eyre::Result<Self> {
+        let storage: Storage<XmlMatrix> =
+            serde_xml_rs::from_str(buf).map_err(|e| eyre::eyre!("parsing XML: {e}"))?;
+        Self::from_values(
+            storage.image_width,
+            storage.image_height,
+            storage.camera_matrix.into_values()?,
+            storage.distortion_coefficients.into_values()?,
+            storage.avg_reprojection_error,
+        )
+    }
+
+    /// Format as an OpenCV YAML file.
+    pub fn to_yaml(&self) -> String {
+        let mut buf = format!(
+            "%YAML:1.0\n---\nimage_width: {}\nimage_height: {}\n",
+            self.image_width, self.image_height
+        );
+        let matrices = [
+            ("camera_matrix", 3, 3, &self.camera_matrix[..]),
+            ("distortion_coefficients", 5, 1, &self.distortion_coeffs[..]),
+        ];
+        for (name, rows, cols, data) in matrices {
+            buf.push_str(&format!(
+                "{name}: !!opencv-matrix\n   rows: {rows}\n   cols: {cols}\n   dt: d\n   data: [ {} ]\n",
+                join_values(data, ", ")
+            ));
+        }
+        if let Some(err) = self.avg_reprojection_error {
+            buf.push_str(&format!("avg_reprojection_error: {err:?}\n"));
+        }
+        buf
+    }
+
+    /// Format as an OpenCV XML file.
+    pub fn to_xml(&self) -> String {
+        let mut buf = format!(
+            "<?xml version=\"1.0\"?>\n<opencv_storage>\n<image_width>{}</image_width>\n<image_height>{}</image_height>\n",
+            self.image_width, self.image_height
+        );
+        let matrices = [
+            ("camera_matrix", 3, 3, &self.camera_matrix[..]),
+            ("distortion_coefficients", 5, 1, &self.distortion_coeffs[..]),
+        ];
+        for (name, rows, cols, data) in matrices {
+            buf.push_str(&format!(
+                "<{name} type_id=\"opencv-matrix\">\n  <rows>{rows}</rows>\n  <cols>{cols}</cols>\n  <dt>d</dt>\n  <data>\n    {}</data></{name}>\n",
+                join_values(data, " ")
+            ));
+        }
+        if let Some(err) = self.avg_reprojection_error {
+            buf.push_str(&format!(
+                "<avg_reprojection_error>{err:?}</avg_reprojection_error>\n"
+            ));
+        }
+        buf.push_str("</opencv_storage>\n");
+        buf
+    }
+}
+
+fn join_values(data: &[f64], sep: &str) -> String {
+    // The `Debug` format of `f64` is exact and always readable as a float.
+    data.iter()
+        .map(|x| format!("{x:?}"))
+        .collect::<Vec<_>>()
+        .join(sep)
+}
+
+#[test]
+fn test_opencv_storage_roundtrip() {
+    let cal = OpenCvCalibration {
+        image_width: 1920,
+        image_height: 1200,
+        camera_matrix: [1234.5, 0.0, 960.25, 0.0, 1230.0, 600.5, 0.0, 0.0, 1.0],
+        distortion_coeffs: [-0.1, 0.01, 1e-4, -2e-4, 0.0],
+        avg_reprojection_error: Some(0.25),
+    };
+    assert_eq!(OpenCvCalibration::from_yaml(&cal.to_yaml()).unwrap(), cal);
+    assert_eq!(OpenCvCalibration::from_xml(&cal.to_xml()).unwrap(), cal);
+
+    // As written by OpenCV.
+    let opencv_yaml = "%YAML:1.0
+---
+calibration_time: \"Tue 01 Oct 2024 10:00:00 CEST\"
+image_width: 640
+image_height: 480
+camera_matrix: !!opencv-matrix
+   rows: 3
+   cols: 3
+   dt: d
+   data: [ 6.5e+02, 0., 3.195e+02, 0., 6.5e+02, 2.395e+02, 0., 0.,
+       1. ]
+distortion_coefficients: !!opencv-matrix
+   rows: 5
+   cols: 1
+   dt: d
+   data: [ -0.2, 0.1, 0., 0., 0. ]
+";
+    let loaded = OpenCvCalibration::from_yaml(opencv_yaml).unwrap();
+    assert_eq!(loaded.camera_matrix[2], 319.5);
+    assert_eq!(loaded.distortion_coeffs[0], -0.2);
+    assert_eq!(loaded.avg_reprojection_error, None);
+}
@@ -110,6 +110,16 @@ impl<T> Sender<T> {
     pub fn is_full(&self) -> bool {
         self.0.is_full()
     }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
 impl<T> Clone for Sender<T> {
@@ -19,6 +19,9 @@
 //! generic implementation that can be used at the cost of spawning a new
 //! thread.
 //!
+//! Acquisition can be stopped and restarted while the stream is running with an
+//! [AcquisitionControl]. While stopped, the thread does not grab frames.
+//!
 //! It would be possible for an upstream camera backend module to directly
 //! implement the [AsyncCamera] trait. Such a camera-specific backend could
 //! implement [AsyncCamera] without serializing access to the camera but rather
@@ -34,7 +37,13 @@ use machine_vision_formats as formats;
 
 use ci2::Result;
 use parking_lot::Mutex;
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// How often the acquisition thread checks whether acquisition was restarted.
+const PAUSED_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
 
 pub enum FrameResult {
     Frame(DynamicFrame),
@@ -58,6 +67,65 @@ pub struct ThreadedAsyncCamera<C> {
     vendor: String,
     /// When acquiring, has value of Some, else None.
     control_and_join_handle: Option<(thread_control::Control, std::thread::JoinHandle<()>)>,
+    /// Set while acquisition is stopped by an [AcquisitionControl].
+    paused: Arc<AtomicBool>,
+}
+
+/// Stops and restarts acquisition of a [ThreadedAsyncCamera] while its stream
+/// of frames is running.
+///
+/// While acquisition is stopped, the stream yields no frames.
+pub struct AcquisitionControl<C> {
+    camera: Arc<Mutex<C>>,
+    paused: Arc<AtomicBool>,
+}
+
+impl<C> Clone for AcquisitionControl<C> {
+    fn clone(&self) -> Self {
+        Self {
+            camera: self.camera.clone(),
+            paused: self.paused.clone(),
+        }
+    }
+}
+
+impl<C> AcquisitionControl<C>
+where
+    C: ci2::Camera,
+{
+    /// Whether acquisition is currently stopped.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Stop acquisition.
+    ///
+    /// This waits until the frame currently being acquired, if any, was
+    /// returned by the camera.
+    pub fn pause(&self) -> Result<()> {
+        // Set the flag before taking the lock so that the acquisition thread
+        // does not grab another frame once the lock is released.
+        if self.paused.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let mut cam = self.camera.lock();
+        if let Err(e) = cam.acquisition_stop() {
+            self.paused.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Restart acquisition stopped with [Self::pause].
+    pub fn resume(&self) -> Result<()> {
+        let mut cam = self.camera.lock();
+        if !self.paused.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        cam.acquisition_start()?;
+        self.paused.store(false, Ordering::SeqCst);
+        Ok(())
+    }
 }
 
 fn _test_camera_is_send() {
@@ -86,6 +154,14 @@ where
     ) -> Option<(thread_control::Control, std::thread::JoinHandle<()>)> {
         self.control_and_join_handle
     }
+
+    /// Get a handle to stop and restart acquisition.
+    pub fn acquisition_control(&self) -> AcquisitionControl<C> {
+        AcquisitionControl {
+            camera: self.camera.clone(),
+            paused: self.paused.clone(),
+        }
+    }
 }
 
 impl<C> AsyncCamera for ThreadedAsyncCamera<C>
@@ -107,12 +183,21 @@ where
         let thread_builder =
             std::thread::Builder::new().name(format!("ThreadedAsyncCamera-{}", self.name));
         let cam_arc = self.camera.clone();
+        let paused = self.paused.clone();
         let join_handle: std::thread::JoinHandle<()> = thread_builder.spawn(move || {
             while flag.is_alive() {
                 // We need to release and re-acquire the lock every cycle to
                 // allow other threads the chance to grab the lock.
                 {
                     let mut cam = cam_arc.lock();
+                    if paused.load(Ordering::SeqCst) {
+                        // Acquisition is stopped. The flag is checked with
+                        // the lock held so that no frame is requested after
+                        // acquisition was stopped.
+                        drop(cam);
+                        std::thread::sleep(PAUSED_POLL_INTERVAL);
+                        continue;
+                    }
                     let msg = match cam.next_frame() {
                         Ok(frame) => FrameResult::Frame(frame),
                         Err(ci2::Error::SingleFrameError(s)) => FrameResult::SingleFrameError(s),
@@ -178,6 +263,7 @@ where
             vendor,
             serial,
             control_and_join_handle: None,
+            paused: Arc::new(AtomicBool::new(false)),
         })
     }
 }
@@ -356,6 +442,58 @@ where
         c.set_software_frame_rate_limit(fps_limit)
     }
 
+    fn set_frame_buffer_config(&mut self, value: ci2::FrameBufferConfig) -> ci2::Result<()> {
+        let mut c = self.camera.lock();
+        c.set_frame_buffer_config(value)
+    }
+
+    fn post_processing_support(&self) -> ci2::Result<ci2::PostProcessingSupport> {
+        let c = self.camera.lock();
+        c.post_processing_support()
+    }
+    fn post_processing(&self) -> ci2::Result<ci2::PostProcessingConfig> {
+        let c = self.camera.lock();
+        c.post_processing()
+    }
+    fn set_post_processing(&mut self, value: ci2::PostProcessingConfig) -> ci2::Result<()> {
+        let mut c = self.camera.lock();
+        c.set_post_processing(value)
+    }
+
+    fn user_sets(&self) -> ci2::Result<Vec<String>> {
+        let c = self.camera.lock();
+        c.user_sets()
+    }
+    fn user_set_load(&mut self, name: &str) -> ci2::Result<()> {
+        let mut c = self.camera.lock();
+        c.user_set_load(name)
+    }
+    fn user_set_save(&mut self, name: &str) -> ci2::Result<()> {
+        let mut c = self.camera.lock();
+        c.user_set_save(name)
+    }
+    fn user_set_default(&self) -> ci2::Result<String> {
+        let c = self.camera.lock();
+        c.user_set_default()
+    }
+    fn set_user_set_default(&mut self, name: &str) -> ci2::Result<()> {
+        let mut c = self.camera.lock();
+        c.set_user_set_default(name)
+    }
+
+    fn roi_offset(&self) -> ci2::Result<(u32, u32)> {
+        let c = self.camera.lock();
+        c.roi_offset()
+    }
+    fn set_roi_offset(&mut self, offset_x: u32, offset_y: u32) -> ci2::Result<()> {
+        let mut c = self.camera.lock();
+        c.set_roi_offset(offset_x, offset_y)
+    }
+    fn roi_max_size(&self) -> ci2::Result<(u32, u32)> {
+        let c = self.camera.lock();
+        c.roi_max_size()
+    }
+
     fn trigger_mode(&self) -> ci2::Result<ci2::TriggerMode> {
         let c = self.camera.lock();
         c.trigger_mode()
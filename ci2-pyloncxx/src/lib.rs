@@ -166,6 +166,7 @@ impl ci2::ExtractFrameInfo for PylonFrameInfo {
             frame_id: std::num::NonZeroU64::new(pylon_extra.block_id),
             host_framenumber: extra.host_framenumber(),
             host_timestamp: extra.host_timestamp(),
+            buffer_saturation: None,
         }
     }
 }
@@ -6,8 +6,15 @@ extern crate enum_iter;
 extern crate rust_cam_bui_types;
 
 use enum_iter::EnumIter;
+use provenance::Provenance;
 use rust_cam_bui_types::ClockModel;
 
+/// The maximum framerate at which frames are recorded.
+///
+/// Arbitrary framerates such as 12.5 fps are given with
+/// [RecordingFrameRate::Rational]. These can be created with
+/// [RecordingFrameRate::from_rational] or parsed from strings such as
+/// `"12.5"`, `"25/2 fps"` or `"unlimited"`.
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Default)]
 pub enum RecordingFrameRate {
     Fps1,
@@ -23,9 +30,58 @@ pub enum RecordingFrameRate {
     Fps100,
     #[default]
     Unlimited,
+    /// `num / den` frames per second. Both must be nonzero.
+    Rational {
+        num: u32,
+        den: u32,
+    },
+}
+
+/// The reason a framerate is invalid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidFrameRate(String);
+
+impl std::fmt::Display for InvalidFrameRate {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(fmt, "invalid framerate: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidFrameRate {}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
 }
 
 impl RecordingFrameRate {
+    /// Create a framerate of `num / den` frames per second.
+    ///
+    /// The fraction is reduced and, if it equals one of the fixed framerates,
+    /// that variant is returned so that equal framerates compare equal.
+    pub fn from_rational(num: u32, den: u32) -> Result<Self, InvalidFrameRate> {
+        if num == 0 || den == 0 {
+            return Err(InvalidFrameRate(format!(
+                "{num}/{den} is not a positive framerate"
+            )));
+        }
+        let divisor = gcd(num.into(), den.into()) as u32;
+        let (num, den) = (num / divisor, den / divisor);
+        let fixed = Self::variants()
+            .into_iter()
+            .find(|x| x.as_numerator_denominator() == Some((num, den)));
+        Ok(fixed.unwrap_or(Self::Rational { num, den }))
+    }
+
+    /// The framerate in frames per second, or `None` if unlimited.
+    pub fn as_f64(&self) -> Option<f64> {
+        self.as_numerator_denominator()
+            .map(|(num, den)| num as f64 / den as f64)
+    }
+
     pub fn interval(&self) -> std::time::Duration {
         use std::time::Duration;
         use RecordingFrameRate::*;
@@ -42,6 +98,9 @@ impl RecordingFrameRate {
             Fps60 => Duration::from_nanos(16666667),
             Fps100 => Duration::from_millis(10),
             Unlimited => Duration::from_millis(0),
+            Rational { num, den } => {
+                Duration::from_nanos(1_000_000_000 * u64::from(*den) / u64::from(*num).max(1))
+            }
         }
     }
 
@@ -62,6 +121,7 @@ impl RecordingFrameRate {
             Unlimited => {
                 return None;
             }
+            Rational { num, den } => (*num, *den),
         })
     }
 }
@@ -82,11 +142,74 @@ impl std::fmt::Display for RecordingFrameRate {
             Fps60 => "60 fps",
             Fps100 => "100 fps",
             Unlimited => "unlimited",
+            Rational { num, den } => {
+                // Show as a decimal if this is exact, otherwise as a fraction.
+                let mut rest = *den;
+                while rest % 2 == 0 {
+                    rest /= 2;
+                }
+                while rest % 5 == 0 {
+                    rest /= 5;
+                }
+                if rest == 1 {
+                    return write!(fmt, "{} fps", *num as f64 / *den as f64);
+                } else {
+                    return write!(fmt, "{num}/{den} fps");
+                }
+            }
         };
         write!(fmt, "{s}")
     }
 }
 
+impl std::str::FromStr for RecordingFrameRate {
+    type Err = InvalidFrameRate;
+
+    /// Parse a framerate such as `"30"`, `"12.5 fps"`, `"25/2"` or
+    /// `"unlimited"`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidFrameRate(format!("could not parse \"{s}\""));
+        let trimmed = s.trim();
+        let trimmed = trimmed.strip_suffix("fps").unwrap_or(trimmed).trim();
+        if trimmed.eq_ignore_ascii_case("unlimited") {
+            return Ok(Self::Unlimited);
+        }
+
+        let (num, den): (u64, u64) = if let Some((num, den)) = trimmed.split_once('/') {
+            (
+                num.trim().parse().map_err(|_| invalid())?,
+                den.trim().parse().map_err(|_| invalid())?,
+            )
+        } else {
+            let (int_part, frac_part) = trimmed.split_once('.').unwrap_or((trimmed, ""));
+            if frac_part.len() > 6 || !frac_part.chars().all(|c| c.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            let den = 10u64.pow(frac_part.len() as u32);
+            let int_part: u64 = if int_part.is_empty() {
+                0
+            } else {
+                int_part.parse().map_err(|_| invalid())?
+            };
+            let frac_part: u64 = if frac_part.is_empty() {
+                0
+            } else {
+                frac_part.parse().map_err(|_| invalid())?
+            };
+            let num = int_part
+                .checked_mul(den)
+                .and_then(|x| x.checked_add(frac_part))
+                .ok_or_else(invalid)?;
+            (num, den)
+        };
+
+        let divisor = gcd(num, den).max(1);
+        let num = u32::try_from(num / divisor).map_err(|_| invalid())?;
+        let den = u32::try_from(den / divisor).map_err(|_| invalid())?;
+        Self::from_rational(num, den)
+    }
+}
+
 impl EnumIter for RecordingFrameRate {
     fn variants() -> Vec<Self> {
         use RecordingFrameRate::*;
@@ -171,6 +294,21 @@ pub struct NvidiaH264Options {
     pub bitrate: u32,
     /// The device number of the CUDA device to use.
     pub cuda_device: i32,
+    #[serde(default)]
+    pub rate_control: NvencRateControl,
+    /// The number of frames from one keyframe to the next. If `None`, the
+    /// default of the preset is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gop_length: Option<u32>,
+    /// The number of B-frames between successive reference frames. If `None`,
+    /// the default of the preset is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_b_frames: Option<u32>,
+    #[serde(default)]
+    pub preset: NvencPreset,
+    /// The tuning of the presets `P1` to `P7`. Ignored with other presets.
+    #[serde(default)]
+    pub tuning: NvencTuning,
 }
 
 impl Default for NvidiaH264Options {
@@ -178,16 +316,195 @@ impl Default for NvidiaH264Options {
         Self {
             bitrate: 1000,
             cuda_device: 0,
+            rate_control: Default::default(),
+            gop_length: None,
+            num_b_frames: None,
+            preset: Default::default(),
+            tuning: Default::default(),
+        }
+    }
+}
+
+/// The rate control mode of NVENC.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum NvencRateControl {
+    /// Variable bitrate with `bitrate` as average and maximum.
+    #[default]
+    Vbr,
+    /// Constant bitrate of `bitrate`.
+    Cbr,
+    /// Constant quantization parameter from 0 (best quality) to 51, ignoring
+    /// `bitrate`.
+    ConstQp(u32),
+}
+
+/// The encoder preset of NVENC, trading off speed against quality.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum NvencPreset {
+    /// The legacy high performance preset.
+    #[default]
+    HighPerformance,
+    /// The fastest preset with the lowest quality.
+    P1,
+    P2,
+    P3,
+    P4,
+    P5,
+    P6,
+    /// The slowest preset with the highest quality.
+    P7,
+}
+
+/// The tuning of the NVENC presets `P1` to `P7`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum NvencTuning {
+    /// For latency tolerant encoding such as archival recordings.
+    #[default]
+    HighQuality,
+    /// For low latency streaming such as previews.
+    LowLatency,
+    UltraLowLatency,
+    Lossless,
+}
+
+/// An NVENC option which could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidNvencOption(String);
+
+impl std::fmt::Display for InvalidNvencOption {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(fmt, "invalid NVENC option: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidNvencOption {}
+
+impl std::str::FromStr for NvencRateControl {
+    type Err = InvalidNvencOption;
+
+    /// Parse `"vbr"`, `"cbr"` or a constant QP such as `"cqp:23"`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidNvencOption(format!("could not parse rate control \"{s}\""));
+        match s.trim().to_ascii_lowercase().as_str() {
+            "vbr" => Ok(Self::Vbr),
+            "cbr" => Ok(Self::Cbr),
+            other => {
+                let qp = other.strip_prefix("cqp:").ok_or_else(invalid)?;
+                Ok(Self::ConstQp(qp.trim().parse().map_err(|_| invalid())?))
+            }
         }
     }
 }
 
+impl std::str::FromStr for NvencPreset {
+    type Err = InvalidNvencOption;
+
+    /// Parse `"hp"` or one of `"p1"` to `"p7"`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use NvencPreset::*;
+        Ok(match s.trim().to_ascii_lowercase().as_str() {
+            "hp" => HighPerformance,
+            "p1" => P1,
+            "p2" => P2,
+            "p3" => P3,
+            "p4" => P4,
+            "p5" => P5,
+            "p6" => P6,
+            "p7" => P7,
+            _ => {
+                return Err(InvalidNvencOption(format!(
+                    "could not parse preset \"{s}\""
+                )));
+            }
+        })
+    }
+}
+
+impl std::str::FromStr for NvencTuning {
+    type Err = InvalidNvencOption;
+
+    /// Parse `"high-quality"`, `"low-latency"`, `"ultra-low-latency"` or
+    /// `"lossless"`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use NvencTuning::*;
+        Ok(match s.trim().to_ascii_lowercase().as_str() {
+            "high-quality" => HighQuality,
+            "low-latency" => LowLatency,
+            "ultra-low-latency" => UltraLowLatency,
+            "lossless" => Lossless,
+            _ => {
+                return Err(InvalidNvencOption(format!(
+                    "could not parse tuning \"{s}\""
+                )));
+            }
+        })
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Mp4RecordingConfig {
     pub codec: Mp4Codec,
     /// Limits the recording to a maximum frame rate.
     pub max_framerate: RecordingFrameRate,
     pub h264_metadata: Option<H264Metadata>,
+    /// Metadata saved in the MP4 container in addition to `h264_metadata`.
+    ///
+    /// If `None`, it is derived from `h264_metadata` with
+    /// [Mp4ContainerMetadata::from_h264_metadata].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_metadata: Option<Mp4ContainerMetadata>,
+    /// If set, write a fragmented MP4 file with fragments of about this
+    /// duration.
+    ///
+    /// Each fragment is complete once written, so if writing is interrupted,
+    /// e.g. by a crash, at most the last fragment is lost. Fragments start
+    /// with a keyframe and are thus longer if keyframes are further apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fragment_duration_secs: Option<f64>,
+}
+
+/// Metadata saved in the `udta` box of an MP4 file.
+///
+/// Unlike [H264Metadata], which is saved in the H264 stream, this is shown by
+/// tools which only read the container metadata, such as `ffprobe` or file
+/// managers.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Mp4ContainerMetadata {
+    /// The title, e.g. the camera name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The creation time. If `None`, the time of the first frame is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub creation_time: Option<chrono::DateTime<chrono::FixedOffset>>,
+    /// The program which wrote the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoder: Option<String>,
+    /// Additional keys and values, saved as freeform (`----`) items.
+    #[serde(default, skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    pub custom: std::collections::BTreeMap<String, String>,
+}
+
+impl Mp4ContainerMetadata {
+    /// The camera name as title and the writing app as encoder, with the
+    /// recording label and tags, if any, as custom keys.
+    pub fn from_h264_metadata(h264_metadata: &H264Metadata) -> Self {
+        let mut custom = std::collections::BTreeMap::new();
+        if let Some(label) = &h264_metadata.recording_label {
+            custom.insert("recording_label".to_string(), label.clone());
+        }
+        if !h264_metadata.recording_tags.is_empty() {
+            custom.insert(
+                "recording_tags".to_string(),
+                h264_metadata.recording_tags.join(","),
+            );
+        }
+        Self {
+            title: h264_metadata.camera_name.clone(),
+            creation_time: None,
+            encoder: Some(h264_metadata.writing_app.clone()),
+            custom,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -203,6 +520,97 @@ pub enum RecordingConfig {
     Ffmpeg(FfmpegRecordingConfig),
 }
 
+/// Split a recording into multiple MP4 files ("segments").
+///
+/// A new segment is started when either limit is reached. Each segment is a
+/// complete MP4 file containing its own copy of the [H264Metadata].
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Mp4SegmentConfig {
+    /// Start a new segment once this duration has been recorded.
+    pub max_duration_secs: Option<f64>,
+    /// Start a new segment once the file reaches this size. Not applied to
+    /// recordings with ffmpeg.
+    pub max_size_bytes: Option<u64>,
+}
+
+impl Mp4SegmentConfig {
+    /// Return true if recordings will be split into segments.
+    pub fn is_enabled(&self) -> bool {
+        self.max_duration_secs.is_some() || self.max_size_bytes.is_some()
+    }
+}
+
+/// A label and free-form tags describing the experimental conditions of a
+/// recording.
+///
+/// These are applied to recordings started after they were set.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct RecordingLabel {
+    pub label: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl RecordingLabel {
+    /// Return true if neither a label nor tags are set.
+    pub fn is_empty(&self) -> bool {
+        self.label.is_empty() && self.tags.is_empty()
+    }
+}
+
+/// The corner of the image in which a [ComplianceWatermarkConfig] is drawn.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum WatermarkPosition {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// A human-readable date and time, camera name and facility string drawn into
+/// recorded MP4 frames.
+///
+/// Unlike the timestamps saved in the MP4 metadata, the watermark is part of
+/// the image itself and remains visible in any player or copy of the footage.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ComplianceWatermarkConfig {
+    /// Free-form text identifying the facility, e.g. its name or license
+    /// number. Not drawn if empty.
+    #[serde(default)]
+    pub facility: String,
+    #[serde(default)]
+    pub position: WatermarkPosition,
+    /// Opacity of the text and its background, from 0 (invisible) to 1.
+    pub opacity: f32,
+    /// Height of the text, in pixels.
+    pub font_size: f32,
+    /// `strftime` format of the local time of each frame.
+    pub datetime_format: String,
+}
+
+impl Default for ComplianceWatermarkConfig {
+    fn default() -> Self {
+        Self {
+            facility: String::new(),
+            position: WatermarkPosition::default(),
+            opacity: 0.8,
+            font_size: 24.0,
+            datetime_format: "%Y-%m-%d %H:%M:%S%.3f %z".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for RecordingLabel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\"{}\"", self.label)?;
+        if !self.tags.is_empty() {
+            write!(f, " (tags: {})", self.tags.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
 impl RecordingConfig {
     pub fn max_framerate(&self) -> &RecordingFrameRate {
         use RecordingConfig::*;
@@ -240,6 +648,38 @@ pub struct H264Metadata {
 
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub gamma: Option<f32>,
+
+    /// Index of this file within a segmented recording, starting at 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment_index: Option<u32>,
+
+    /// Number of frames saved in the previous segments of the recording.
+    ///
+    /// Adding this to the frame index within this file gives the frame index
+    /// within the entire recording.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment_first_frame: Option<u64>,
+
+    /// Label of the recording, see [RecordingLabel].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recording_label: Option<String>,
+
+    /// Tags of the recording, see [RecordingLabel].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recording_tags: Vec<String>,
+
+    /// The program, build and host which made the recording.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+
+    /// Relation of the camera clock to the host clock when recording started.
+    ///
+    /// If present, the frame timestamps were computed from the camera
+    /// timestamps with a periodically updated model instead of being taken
+    /// when the host received the frame. Recordings of several cameras on the
+    /// same host can then be aligned by their timestamps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_clock_model: Option<DeviceClockModel>,
 }
 
 impl H264Metadata {
@@ -250,10 +690,67 @@ impl H264Metadata {
             creation_time,
             camera_name: None,
             gamma: None,
+            segment_index: None,
+            segment_first_frame: None,
+            recording_label: None,
+            recording_tags: Vec::new(),
+            provenance: None,
+            device_clock_model: None,
         }
     }
 }
 
+/// Linear model of the host clock as a function of the camera clock.
+///
+/// The host time of a camera timestamp `t` is `host_time0` plus
+/// `(t - device_time0) * gain + offset` nanoseconds.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct DeviceClockModel {
+    /// Host time of the first measurement.
+    pub host_time0: chrono::DateTime<chrono::Utc>,
+    /// Camera timestamp of the first measurement, in nanoseconds.
+    pub device_time0: u64,
+    pub gain: f64,
+    /// Offset in nanoseconds.
+    pub offset: f64,
+    pub residuals: f64,
+    pub n_measurements: u64,
+}
+
+impl DeviceClockModel {
+    /// Estimate the host time at the camera timestamp `device_timestamp`, in
+    /// nanoseconds.
+    pub fn host_time(&self, device_timestamp: u64) -> chrono::DateTime<chrono::Utc> {
+        let device_elapsed = device_timestamp as i128 - self.device_time0 as i128;
+        let host_elapsed = device_elapsed as f64 * self.gain + self.offset;
+        self.host_time0 + chrono::Duration::nanoseconds(host_elapsed.round() as i64)
+    }
+}
+
+/// Universal identifier for per-frame camera settings in H264 data.
+///
+/// Generated with `uuid -v3 ns:URL
+/// https://strawlab.org/h264-frame-camera-settings/`
+pub const FRAME_CAMERA_SETTINGS_UUID: [u8; 16] = [
+    // 30752900-05b3-3609-a55f-8521f36f2545
+    0x30, 0x75, 0x29, 0x00, 0x05, 0xB3, 0x36, 0x09, 0xA5, 0x5F, 0x85, 0x21, 0xF3, 0x6F, 0x25, 0x45,
+];
+
+/// Camera settings in effect when a frame was acquired.
+///
+/// These can be stored, serialized as JSON, in an SEI message with
+/// [FRAME_CAMERA_SETTINGS_UUID] preceding each frame in H264 data. Values
+/// which are not known (e.g. from chunk data of the camera) are `None`.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+pub struct FrameCameraSettings {
+    /// Exposure time in microseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exposure_time_usec: Option<f64>,
+    /// Gain in dB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gain_db: Option<f64>,
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum CsvSaveConfig {
     /// Do not save CSV
@@ -262,6 +759,17 @@ pub enum CsvSaveConfig {
     Saving(Option<f32>),
 }
 
+/// File format of camera intrinsics.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum CalibrationFileFormat {
+    /// ROS `camera_info` YAML
+    RosYaml,
+    /// OpenCV `FileStorage` YAML
+    OpenCvYaml,
+    /// OpenCV `FileStorage` XML
+    OpenCvXml,
+}
+
 // April tags
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Default)]
@@ -475,6 +983,15 @@ pub enum CamArg {
     ///
     /// If seconds are not given, ignore forever.
     SetIngoreFutureFrameProcessingErrors(Option<i64>),
+    /// Clear the error shown after recordings were stopped due to low disk space.
+    DismissDiskSpaceError,
+    /// Allow recording although the startup self-test failed.
+    DismissSelfTestFailure,
+    /// Apply the named configuration profile of Strand Camera.
+    ///
+    /// All settings of the profile are changed before any other command is
+    /// handled. Nothing is changed if the profile cannot be read.
+    ApplyProfile(String),
 
     SetExposureTime(f64),
     SetExposureAuto(ci2_types::AutoMode),
@@ -482,13 +999,62 @@ pub enum CamArg {
     SetFrameRateLimit(f64),
     SetGain(f64),
     SetGainAuto(ci2_types::AutoMode),
+    /// Set the trigger mode of the camera.
+    ///
+    /// Ignored within Braid, which configures the triggering of the cameras.
+    SetTriggerMode(ci2_types::TriggerMode),
+    /// Set the number of frames buffered by the camera backend.
+    ///
+    /// Acquisition is briefly restarted to apply the new settings.
+    SetFrameBufferConfig(ci2_types::FrameBufferConfig),
+    /// Set the in-camera image post-processing.
+    ///
+    /// An error is logged if the camera does not support an enabled feature.
+    SetPostProcessing(ci2_types::PostProcessingConfig),
+    /// Move the region of interest (ROI) on the sensor, in pixels.
+    SetRoiOffset {
+        offset_x: u32,
+        offset_y: u32,
+    },
     SetRecordingFps(RecordingFrameRate),
     SetMp4Bitrate(BitrateSelection),
     SetMp4Codec(CodecSelection),
     SetMp4CudaDevice(String),
     SetMp4MaxFramerate(RecordingFrameRate),
+    /// Also save a half-width, half-height proxy MP4 with each MP4 recording.
+    SetMp4ProxyEnabled(bool),
+    /// Set how subsequent MP4 recordings are split into multiple files.
+    SetMp4SegmentConfig(Mp4SegmentConfig),
+    /// Draw a watermark into subsequent MP4 recordings, or stop doing so if
+    /// `None`.
+    SetComplianceWatermark(Option<ComplianceWatermarkConfig>),
     SetIsRecordingMp4(bool),
     SetIsRecordingFmf(bool),
+    /// Start or stop MP4 and FMF recording together.
+    ///
+    /// Both files are started and stopped on the same frame and contain
+    /// identical frames and timestamps.
+    SetIsRecordingMp4AndFmf(bool),
+    /// Stop all MP4, FMF and UFMF recordings which are currently running.
+    StopAllRecordings,
+    /// Record for the given duration and then stop all recordings.
+    ///
+    /// MP4 recording is started if no recording is running. Any recording
+    /// running when the duration elapses is stopped and its file finalized.
+    StartRecordingFor {
+        duration_secs: f64,
+    },
+    /// Record until the given time and then stop all recordings.
+    ///
+    /// Behaves like [CamArg::StartRecordingFor] but with an absolute end time.
+    StartRecordingUntil {
+        end_time: chrono::DateTime<chrono::Utc>,
+    },
+    /// Set the label and tags of subsequently started recordings.
+    SetRecordingLabel(RecordingLabel),
+    /// Save a bug report bundle with the recent pipeline state and the current
+    /// configuration.
+    SaveBugReport,
     /// used only with image-tracker crate
     SetIsRecordingUfmf(bool),
     /// used only with image-tracker crate
@@ -497,10 +1063,28 @@ pub enum CamArg {
     SetIsSavingObjDetectionCsv(CsvSaveConfig),
     /// used only with image-tracker crate
     SetObjDetectionConfig(String),
+    /// Reset the background model to the current image, e.g. after the arena
+    /// was rearranged. Used only with image-tracker crate.
+    ResetBackgroundModel,
+    /// Suspend the updates of the background model, e.g. while a hand is in
+    /// the arena. Used only with image-tracker crate.
+    ///
+    /// If `duration_secs` is given, the updates resume automatically after
+    /// this duration.
+    FreezeBackgroundModel {
+        duration_secs: Option<f64>,
+    },
+    /// Resume the updates of the background model. Used only with
+    /// image-tracker crate.
+    UnfreezeBackgroundModel,
     CamArgSetKalmanTrackingConfig(String),
     CamArgSetLedProgramConfig(String),
     SetFrameOffset(u64),
     SetTriggerboxClockModel(Option<ClockModel>),
+    /// Measure the camera clock relative to the host clock.
+    ///
+    /// Sent periodically when clock skew correction is enabled.
+    MeasureDeviceClock,
     SetFormatStr(String),
     ToggleCheckerboardDetection(bool),
     ToggleCheckerboardDebug(bool),
@@ -508,6 +1092,21 @@ pub enum CamArg {
     SetCheckerboardHeight(u32),
     ClearCheckerboards,
     PerformCheckerboardCalibration,
+    /// Save the current camera calibration to `path` in the given format.
+    ///
+    /// A relative `path` is relative to the data directory.
+    ExportCameraCalibration {
+        path: String,
+        format: CalibrationFileFormat,
+    },
+    /// Load a camera calibration from `path` and make it the current camera
+    /// calibration.
+    ///
+    /// ROS camera_info YAML, OpenCV YAML and OpenCV XML files are supported.
+    /// A relative `path` is relative to the data directory.
+    ImportCameraCalibration {
+        path: String,
+    },
     DoQuit,
     PostTrigger,
     SetPostTriggerBufferSize(usize),
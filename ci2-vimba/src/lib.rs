@@ -4,7 +4,7 @@ use parking_lot::Mutex;
 use std::{
     convert::TryInto,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
 };
@@ -14,21 +14,50 @@ use lazy_static::lazy_static;
 
 use machine_vision_formats as formats;
 
-use ci2::{AcquisitionMode, AutoMode, TriggerMode};
+use ci2::{
+    AcquisitionMode, AutoMode, FrameBufferConfig, FrameBufferSaturation, PostProcessingConfig,
+    PostProcessingSupport, TriggerMode,
+};
 use formats::PixFmt;
 use timestamped_frame::HostTimeData;
 
 use basic_frame::DynamicFrame;
 use channellib::{Receiver, Sender};
 
-// Number of frames to allocate for the Vimba driver.
-const N_BUFFER_FRAMES: usize = 10;
-// Number of slots to allocate purely within rust.
-const N_CHANNEL_FRAMES: usize = 10;
+// Alvium cameras offer noise suppression and sharpening as modes of a
+// convolution filter, so only one can be used at a time.
+const CONVOLUTION_MODE: &str = "ConvolutionMode";
+const CONVOLUTION_MODE_OFF: &str = "Off";
+const CONVOLUTION_MODE_DENOISE: &str = "AdaptiveNoiseSuppression";
+const CONVOLUTION_MODE_SHARPEN: &str = "Sharpness";
+
+const USER_SET_SELECTOR: &str = "UserSetSelector";
+// Older Allied Vision cameras name the feature `UserSetDefaultSelector`
+// instead of the GenICam standard `UserSetDefault`.
+const USER_SET_DEFAULT: &str = "UserSetDefault";
+const USER_SET_DEFAULT_LEGACY: &str = "UserSetDefaultSelector";
+/// How long to wait for loading or saving a user set to complete.
+const USER_SET_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
 
 struct FrameSender {
     handle: CamHandle,
     tx: Sender<std::result::Result<DynamicFrame, ci2::Error>>,
+    capacity: usize,
+    stats: Arc<ChannelStats>,
+}
+
+/// Fill level statistics of the channel from the Vimba callback to rust.
+#[derive(Default)]
+struct ChannelStats {
+    max_queued: AtomicUsize,
+    n_full: AtomicU64,
+}
+
+impl ChannelStats {
+    fn reset(&self) {
+        self.max_queued.store(0, Ordering::Relaxed);
+        self.n_full.store(0, Ordering::Relaxed);
+    }
 }
 
 struct CamHandle {
@@ -59,6 +88,38 @@ fn callback_rust(
     let now = chrono::Utc::now(); // earliest possible timestamp
     let frame_status = unsafe { (*frame).receiveStatus };
     if !IS_DONE.load(Ordering::Relaxed) {
+        let (tx, capacity, stats) = {
+            // In this scope, we keep the lock on the SENDERS mutex.
+            let vec_senders = &mut *SENDERS.lock();
+            if let Some(idx) = vec_senders
+                .iter()
+                .position(|x| x.handle.inner == camera_handle)
+            {
+                let sender = &vec_senders[idx];
+                (sender.tx.clone(), sender.capacity, sender.stats.clone())
+            } else {
+                return Err(ci2::Error::from(format!(
+                    "CB: no sender found for camera: {:?}",
+                    camera_handle
+                )));
+            }
+        };
+
+        // Measure how full the channel is before this frame is added.
+        let n_queued = tx.len();
+        let max_queued = stats.max_queued.fetch_max(n_queued, Ordering::Relaxed);
+        let n_full = if n_queued >= capacity {
+            stats.n_full.fetch_add(1, Ordering::Relaxed) + 1
+        } else {
+            stats.n_full.load(Ordering::Relaxed)
+        };
+        let buffer_saturation = FrameBufferSaturation {
+            n_queued,
+            max_queued: max_queued.max(n_queued),
+            capacity,
+            n_full,
+        };
+
         // Copy all data from Vimba.
 
         let msg = if frame_status == vmbc_sys::VmbFrameStatusType::VmbFrameStatusComplete {
@@ -99,6 +160,7 @@ fn callback_rust(
                     device_timestamp,
                     host_timestamp: now,
                     pixel_format,
+                    buffer_saturation,
                 });
 
                 let width = unsafe { (*frame).width };
@@ -149,23 +211,6 @@ fn callback_rust(
             return Err(ve2ce(e));
         }
 
-        let tx = {
-            // In this scope, we keep the lock on the SENDERS mutex.
-            let vec_senders = &mut *SENDERS.lock();
-            if let Some(idx) = vec_senders
-                .iter()
-                .position(|x| x.handle.inner == camera_handle)
-            {
-                let sender = &vec_senders[idx];
-                sender.tx.clone()
-            } else {
-                return Err(ci2::Error::from(format!(
-                    "CB: no sender found for camera: {:?}",
-                    camera_handle
-                )));
-            }
-        };
-
         match tx.send(msg) {
             Ok(()) => {}
             Err(e) => {
@@ -301,26 +346,18 @@ impl<'a> ci2::CameraModule for &'a WrappedModule {
         }
         let info = my_info.unwrap();
 
-        let rx = {
-            // In this scope, we keep the lock on the SENDERS mutex.
-            let vec_senders = &mut *SENDERS.lock();
-            let (tx, rx) = channellib::bounded(N_CHANNEL_FRAMES);
-            let sender = FrameSender {
-                handle: CamHandle {
-                    inner: camera.handle(),
-                },
-                tx,
-            };
-            vec_senders.push(sender);
-            rx
-        };
+        let buffer_config = FrameBufferConfig::default();
+        let stats = Arc::new(ChannelStats::default());
+        let rx = register_sender(camera.handle(), buffer_config.n_channel_frames, &stats);
 
         Ok(WrappedCamera {
             camera: Arc::new(Mutex::new(camera)),
             acquisition_started: false,
             info,
-            frames: Vec::with_capacity(N_BUFFER_FRAMES),
+            frames: Vec::with_capacity(buffer_config.n_buffer_frames),
             rx,
+            buffer_config,
+            stats,
         })
     }
 
@@ -350,6 +387,7 @@ impl ci2::ExtractFrameInfo for VimbaFrameInfo {
             frame_id: std::num::NonZeroU64::new(vimba_extra.frame_id),
             host_framenumber: extra.host_framenumber(),
             host_timestamp: extra.host_timestamp(),
+            buffer_saturation: Some(vimba_extra.buffer_saturation),
         }
     }
 }
@@ -383,6 +421,48 @@ pub struct WrappedCamera<'lib> {
     acquisition_started: bool,
     frames: Vec<vimba::Frame>,
     rx: Receiver<std::result::Result<DynamicFrame, ci2::Error>>,
+    buffer_config: FrameBufferConfig,
+    stats: Arc<ChannelStats>,
+}
+
+/// Create the channel for frames from `handle`, replacing any existing one.
+fn register_sender(
+    handle: vmbc_sys::VmbHandle_t,
+    capacity: usize,
+    stats: &Arc<ChannelStats>,
+) -> Receiver<std::result::Result<DynamicFrame, ci2::Error>> {
+    // In this scope, we keep the lock on the SENDERS mutex.
+    let vec_senders = &mut *SENDERS.lock();
+    vec_senders.retain(|x| x.handle.inner != handle);
+    let (tx, rx) = channellib::bounded(capacity);
+    let sender = FrameSender {
+        handle: CamHandle { inner: handle },
+        tx,
+        capacity,
+        stats: stats.clone(),
+    };
+    vec_senders.push(sender);
+    rx
+}
+
+impl<'lib> WrappedCamera<'lib> {
+    /// Select the user set `name` and run `command`, waiting for completion.
+    fn user_set_command(&self, name: &str, command: &str) -> ci2::Result<()> {
+        let c = self.camera.lock();
+        c.feature_enum_set(USER_SET_SELECTOR, name)
+            .map_vimba_err()?;
+        c.command_run(command).map_vimba_err()?;
+        let start = std::time::Instant::now();
+        while !c.command_is_done(command).map_vimba_err()? {
+            if start.elapsed() > USER_SET_TIMEOUT {
+                return Err(ci2::Error::from(format!(
+                    "timeout running {command} for user set {name}"
+                )));
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        Ok(())
+    }
 }
 
 fn _test_camera_is_send() {
@@ -652,6 +732,124 @@ impl<'lib> ci2::Camera for WrappedCamera<'lib> {
         Ok(())
     }
 
+    fn set_frame_buffer_config(
+        &mut self,
+        value: FrameBufferConfig,
+    ) -> std::result::Result<(), ci2::Error> {
+        if value.n_buffer_frames == 0 || value.n_channel_frames == 0 {
+            return Err(ci2::Error::from(format!(
+                "frame buffer sizes must be nonzero: {:?}",
+                value
+            )));
+        }
+
+        let restart = if self.acquisition_started {
+            self.acquisition_stop()?;
+            true
+        } else {
+            false
+        };
+
+        if value.n_channel_frames != self.buffer_config.n_channel_frames {
+            let handle = self.camera.lock().handle();
+            self.rx = register_sender(handle, value.n_channel_frames, &self.stats);
+        }
+        self.buffer_config = value;
+
+        if restart {
+            self.acquisition_start()?;
+        }
+        Ok(())
+    }
+
+    fn post_processing_support(&self) -> std::result::Result<PostProcessingSupport, ci2::Error> {
+        let modes = match self
+            .camera
+            .lock()
+            .feature_enum_range_query(CONVOLUTION_MODE)
+        {
+            Ok(modes) => modes,
+            // The camera does not have the feature.
+            Err(_) => return Ok(PostProcessingSupport::default()),
+        };
+        Ok(PostProcessingSupport {
+            denoise: modes.iter().any(|m| m == CONVOLUTION_MODE_DENOISE),
+            sharpen: modes.iter().any(|m| m == CONVOLUTION_MODE_SHARPEN),
+            simultaneous: false,
+        })
+    }
+    fn post_processing(&self) -> std::result::Result<PostProcessingConfig, ci2::Error> {
+        let c = self.camera.lock();
+        let mut value = PostProcessingConfig::default();
+        match c.feature_enum(CONVOLUTION_MODE).map_vimba_err()? {
+            CONVOLUTION_MODE_DENOISE => {
+                value.denoise = Some(
+                    c.feature_float("AdaptiveNoiseSuppressionFactor")
+                        .map_vimba_err()?,
+                );
+            }
+            CONVOLUTION_MODE_SHARPEN => {
+                value.sharpen = Some(c.feature_int("Sharpness").map_vimba_err()? as f64);
+            }
+            _ => {}
+        }
+        Ok(value)
+    }
+    fn set_post_processing(
+        &mut self,
+        value: PostProcessingConfig,
+    ) -> std::result::Result<(), ci2::Error> {
+        let support = self.post_processing_support()?;
+        support.check(&value).map_err(ci2::Error::from)?;
+        let c = self.camera.lock();
+        if let Some(factor) = value.denoise {
+            c.feature_enum_set(CONVOLUTION_MODE, CONVOLUTION_MODE_DENOISE)
+                .map_vimba_err()?;
+            c.feature_float_set("AdaptiveNoiseSuppressionFactor", factor)
+                .map_vimba_err()
+        } else if let Some(sharpness) = value.sharpen {
+            c.feature_enum_set(CONVOLUTION_MODE, CONVOLUTION_MODE_SHARPEN)
+                .map_vimba_err()?;
+            c.feature_int_set("Sharpness", sharpness.round() as i64)
+                .map_vimba_err()
+        } else if support.denoise || support.sharpen {
+            c.feature_enum_set(CONVOLUTION_MODE, CONVOLUTION_MODE_OFF)
+                .map_vimba_err()
+        } else {
+            Ok(())
+        }
+    }
+
+    fn user_sets(&self) -> std::result::Result<Vec<String>, ci2::Error> {
+        self.camera
+            .lock()
+            .feature_enum_range_query(USER_SET_SELECTOR)
+            .map_vimba_err()
+    }
+    fn user_set_load(&mut self, name: &str) -> std::result::Result<(), ci2::Error> {
+        self.user_set_command(name, "UserSetLoad")
+    }
+    fn user_set_save(&mut self, name: &str) -> std::result::Result<(), ci2::Error> {
+        self.user_set_command(name, "UserSetSave")
+    }
+    fn user_set_default(&self) -> std::result::Result<String, ci2::Error> {
+        let c = self.camera.lock();
+        let val = match c.feature_enum(USER_SET_DEFAULT) {
+            Ok(val) => val,
+            Err(_) => c.feature_enum(USER_SET_DEFAULT_LEGACY).map_vimba_err()?,
+        };
+        Ok(val.to_string())
+    }
+    fn set_user_set_default(&mut self, name: &str) -> std::result::Result<(), ci2::Error> {
+        let c = self.camera.lock();
+        match c.feature_enum_set(USER_SET_DEFAULT, name) {
+            Ok(()) => Ok(()),
+            Err(_) => c
+                .feature_enum_set(USER_SET_DEFAULT_LEGACY, name)
+                .map_vimba_err(),
+        }
+    }
+
     fn trigger_mode(&self) -> std::result::Result<TriggerMode, ci2::Error> {
         let c = self.camera.lock();
         let val = c.feature_enum("TriggerMode").map_vimba_err()?;
@@ -784,10 +982,11 @@ impl<'lib> ci2::Camera for WrappedCamera<'lib> {
     }
     fn acquisition_start(&mut self) -> std::result::Result<(), ci2::Error> {
         IS_DONE.store(false, Ordering::Relaxed); // indicate we are done
+        self.stats.reset();
 
         let camera = self.camera.lock();
 
-        for _ in 0..N_BUFFER_FRAMES {
+        for _ in 0..self.buffer_config.n_buffer_frames {
             let buffer = camera.allocate_buffer().map_vimba_err()?;
             let mut frame = vimba::Frame::new(buffer);
             camera.frame_announce(&mut frame).map_vimba_err()?;
@@ -848,6 +1047,7 @@ pub struct VimbaExtra {
     host_timestamp: DateTime<Utc>,
     pub pixel_format: formats::PixFmt,
     pub device_timestamp: u64,
+    pub buffer_saturation: FrameBufferSaturation,
 }
 
 impl HostTimeData for VimbaExtra {
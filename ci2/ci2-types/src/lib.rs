@@ -46,3 +46,88 @@ pub enum AcquisitionMode {
     SingleFrame,
     MultiFrame,
 }
+
+/// Number of frames buffered between the camera driver and the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FrameBufferConfig {
+    /// Number of frame buffers allocated for the camera driver.
+    #[serde(default = "default_n_buffer_frames")]
+    pub n_buffer_frames: usize,
+    /// Number of frames which can wait in the backend before being consumed.
+    #[serde(default = "default_n_channel_frames")]
+    pub n_channel_frames: usize,
+}
+
+const fn default_n_buffer_frames() -> usize {
+    10
+}
+
+const fn default_n_channel_frames() -> usize {
+    10
+}
+
+impl Default for FrameBufferConfig {
+    fn default() -> Self {
+        Self {
+            n_buffer_frames: default_n_buffer_frames(),
+            n_channel_frames: default_n_channel_frames(),
+        }
+    }
+}
+
+/// In-camera image post-processing, for cameras supporting it.
+///
+/// `None` disables a feature. The meaning of the strengths depends on the
+/// camera backend.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PostProcessingConfig {
+    /// Strength of noise suppression.
+    #[serde(default)]
+    pub denoise: Option<f64>,
+    /// Strength of sharpening.
+    #[serde(default)]
+    pub sharpen: Option<f64>,
+}
+
+/// The in-camera post-processing features supported by a camera.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct PostProcessingSupport {
+    pub denoise: bool,
+    pub sharpen: bool,
+    /// Whether noise suppression and sharpening can be used together.
+    pub simultaneous: bool,
+}
+
+impl PostProcessingSupport {
+    /// Check that the features enabled in `cfg` are supported.
+    pub fn check(&self, cfg: &PostProcessingConfig) -> Result<(), String> {
+        if cfg.denoise.is_some() && !self.denoise {
+            return Err("noise suppression not supported by this camera".into());
+        }
+        if cfg.sharpen.is_some() && !self.sharpen {
+            return Err("sharpening not supported by this camera".into());
+        }
+        if cfg.denoise.is_some() && cfg.sharpen.is_some() && !self.simultaneous {
+            return Err(
+                "noise suppression and sharpening cannot be used together with this camera".into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// How full the frame buffers were when a frame arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct FrameBufferSaturation {
+    /// Number of frames already waiting to be consumed.
+    pub n_queued: usize,
+    /// Largest value of `n_queued` since acquisition started.
+    pub max_queued: usize,
+    /// Number of frames which can wait to be consumed.
+    pub capacity: usize,
+    /// Number of frames since acquisition started which arrived while no
+    /// space was available. Each of these stalled the driver.
+    pub n_full: u64,
+}
@@ -4,7 +4,10 @@
 use std::backtrace::Backtrace;
 
 use basic_frame::DynamicFrame;
-pub use ci2_types::{AcquisitionMode, AutoMode, TriggerMode, TriggerSelector};
+pub use ci2_types::{
+    AcquisitionMode, AutoMode, FrameBufferConfig, FrameBufferSaturation, PostProcessingConfig,
+    PostProcessingSupport, TriggerMode, TriggerSelector,
+};
 use machine_vision_formats as formats;
 
 // TODO add binning support
@@ -110,6 +113,8 @@ pub struct FrameInfo {
     pub frame_id: Option<std::num::NonZeroU64>,
     pub host_framenumber: usize,
     pub host_timestamp: chrono::DateTime<chrono::Utc>,
+    /// Buffer fill level when this frame arrived, if known by the backend.
+    pub buffer_saturation: Option<FrameBufferSaturation>,
 }
 
 pub trait ExtractFrameInfo: Sync + Send {
@@ -164,6 +169,36 @@ pub trait Camera: CameraInfo + Send {
     // TODO: add this
     // fn stride(&self) -> Result<u32>;
 
+    /// Return the offset of the region of interest (ROI) on the sensor, in
+    /// pixels.
+    fn roi_offset(&self) -> Result<(u32, u32)> {
+        // This is the generic default implementation using the GenICam
+        // standard feature names which may be overriden by implementors.
+        let x = self.feature_int("OffsetX")?;
+        let y = self.feature_int("OffsetY")?;
+        Ok((x.try_into()?, y.try_into()?))
+    }
+    /// Move the region of interest (ROI) on the sensor without changing its
+    /// size.
+    ///
+    /// Cameras supporting this typically allow it during acquisition. The
+    /// offsets must be multiples of the increment of the camera.
+    fn set_roi_offset(&mut self, offset_x: u32, offset_y: u32) -> Result<()> {
+        // This is the generic default implementation using the GenICam
+        // standard feature names which may be overriden by implementors.
+        self.feature_int_set("OffsetX", offset_x.into())?;
+        self.feature_int_set("OffsetY", offset_y.into())
+    }
+    /// Return the maximum width and height of the region of interest (ROI),
+    /// in pixels.
+    fn roi_max_size(&self) -> Result<(u32, u32)> {
+        // This is the generic default implementation using the GenICam
+        // standard feature names which may be overriden by implementors.
+        let w = self.feature_int("WidthMax")?;
+        let h = self.feature_int("HeightMax")?;
+        Ok((w.try_into()?, h.try_into()?))
+    }
+
     // Settings: PixFmt ----------------------------
     fn pixel_format(&self) -> Result<formats::PixFmt>;
     fn possible_pixel_formats(&self) -> Result<Vec<formats::PixFmt>>;
@@ -235,6 +270,94 @@ pub trait Camera: CameraInfo + Send {
         self.set_acquisition_frame_rate(fps_limit)
     }
 
+    /// Set the number of frames buffered by the driver and the backend.
+    ///
+    /// If acquisition is running, it is restarted to apply the new settings.
+    fn set_frame_buffer_config(&mut self, _: FrameBufferConfig) -> Result<()> {
+        // This is the generic default implementation which may be overriden by
+        // implementors.
+        Err(Error::from(
+            "frame buffer configuration not supported by this camera backend",
+        ))
+    }
+
+    // Post-processing ----------------------------
+    /// The in-camera post-processing features supported by this camera.
+    ///
+    /// This is detected at runtime because support varies between models of
+    /// the same backend.
+    fn post_processing_support(&self) -> Result<PostProcessingSupport> {
+        // This is the generic default implementation which may be overriden by
+        // implementors.
+        Ok(PostProcessingSupport::default())
+    }
+
+    /// The current in-camera post-processing.
+    fn post_processing(&self) -> Result<PostProcessingConfig> {
+        // This is the generic default implementation which may be overriden by
+        // implementors.
+        Err(Error::from(
+            "post-processing not supported by this camera backend",
+        ))
+    }
+
+    /// Set the in-camera post-processing.
+    ///
+    /// Returns an error if an enabled feature is not supported by the camera.
+    fn set_post_processing(&mut self, value: PostProcessingConfig) -> Result<()> {
+        // This is the generic default implementation which may be overriden by
+        // implementors.
+        PostProcessingSupport::default()
+            .check(&value)
+            .map_err(Error::from)
+    }
+
+    // User sets ----------------------------
+    /// The names of the user sets of the camera, e.g. `Default` and `UserSet1`.
+    ///
+    /// A user set stores camera settings on the camera itself.
+    fn user_sets(&self) -> Result<Vec<String>> {
+        // This is the generic default implementation which may be overriden by
+        // implementors.
+        Err(Error::from(
+            "listing user sets not supported by this camera backend",
+        ))
+    }
+
+    /// Load the settings stored in the user set `name` into the camera.
+    ///
+    /// Acquisition must be stopped.
+    fn user_set_load(&mut self, name: &str) -> Result<()> {
+        // This is the generic default implementation using the GenICam
+        // standard feature names which may be overriden by implementors.
+        self.feature_enum_set("UserSetSelector", name)?;
+        self.command_execute("UserSetLoad", true)
+    }
+
+    /// Store the current settings of the camera in the user set `name`.
+    ///
+    /// Acquisition must be stopped.
+    fn user_set_save(&mut self, name: &str) -> Result<()> {
+        // This is the generic default implementation using the GenICam
+        // standard feature names which may be overriden by implementors.
+        self.feature_enum_set("UserSetSelector", name)?;
+        self.command_execute("UserSetSave", true)
+    }
+
+    /// The user set loaded when the camera powers up.
+    fn user_set_default(&self) -> Result<String> {
+        // This is the generic default implementation using the GenICam
+        // standard feature names which may be overriden by implementors.
+        self.feature_enum("UserSetDefault")
+    }
+
+    /// Set the user set loaded when the camera powers up.
+    fn set_user_set_default(&mut self, name: &str) -> Result<()> {
+        // This is the generic default implementation using the GenICam
+        // standard feature names which may be overriden by implementors.
+        self.feature_enum_set("UserSetDefault", name)
+    }
+
     // Acquisition ----------------------------
     fn acquisition_start(&mut self) -> Result<()>;
     fn acquisition_stop(&mut self) -> Result<()>;
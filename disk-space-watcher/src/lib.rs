@@ -0,0 +1,82 @@
+//! Monitor the free space of the disk to which recordings are saved.
+//!
+//! Writing to a full disk fails somewhere in the middle of a file with an I/O
+//! error and can leave the file unreadable. By checking the free space
+//! periodically, recordings can be stopped cleanly before this happens.
+
+use std::path::{Path, PathBuf};
+
+pub use rust_cam_bui_types::{DiskSpaceConfig, DiskSpaceLevel, DiskSpaceStatus};
+
+/// The default interval between checks of the free space.
+pub const DEFAULT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Query the free space of the filesystem containing `path`.
+pub fn query(path: &Path, cfg: &DiskSpaceConfig) -> std::io::Result<DiskSpaceStatus> {
+    let available_bytes = fs2::available_space(path)?;
+    let total_bytes = fs2::total_space(path)?;
+    Ok(DiskSpaceStatus {
+        path: path.display().to_string(),
+        available_bytes,
+        total_bytes,
+        level: cfg.level(available_bytes),
+    })
+}
+
+/// Check the free space every `interval`, calling `on_status` with the result.
+///
+/// A message is logged whenever the level changes. If the free space cannot be
+/// determined, an error is logged and `on_status` is not called. This function
+/// never returns and is intended to be spawned as a task.
+pub async fn watch<F>(
+    path: PathBuf,
+    cfg: DiskSpaceConfig,
+    interval: std::time::Duration,
+    mut on_status: F,
+) where
+    F: FnMut(DiskSpaceStatus),
+{
+    let mut prev_level = DiskSpaceLevel::Ok;
+    let mut had_error = false;
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let status = match query(&path, &cfg) {
+            Ok(status) => status,
+            Err(e) => {
+                // Only log the first of a series of errors.
+                if !had_error {
+                    tracing::error!(
+                        "could not determine free space of \"{}\": {e}",
+                        path.display()
+                    );
+                }
+                had_error = true;
+                continue;
+            }
+        };
+        had_error = false;
+        if status.level != prev_level {
+            let available_gb = status.available_bytes as f64 / 1e9;
+            match status.level {
+                DiskSpaceLevel::Ok => {
+                    tracing::info!("{available_gb:.1} GB free space on \"{}\"", status.path);
+                }
+                DiskSpaceLevel::Low => {
+                    tracing::warn!(
+                        "low disk space: {available_gb:.1} GB free on \"{}\"",
+                        status.path
+                    );
+                }
+                DiskSpaceLevel::Critical => {
+                    tracing::error!(
+                        "disk almost full: {available_gb:.1} GB free on \"{}\"",
+                        status.path
+                    );
+                }
+            }
+            prev_level = status.level;
+        }
+        on_status(status);
+    }
+}
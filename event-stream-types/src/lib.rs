@@ -142,6 +142,14 @@ impl ConnectionSessionKey {
             connection_key,
         }
     }
+    /// Identifier for the session (one per browser).
+    pub fn session_key(&self) -> uuid::Uuid {
+        self.session_key
+    }
+    /// Identifier for the connection (one per tab).
+    pub fn connection_key(&self) -> std::net::SocketAddr {
+        self.connection_key
+    }
 }
 
 /// broadcasts events to many listeners.
@@ -176,6 +184,32 @@ where
 
         (tx, body)
     }
+    /// The number of connections which are not closed.
+    pub fn num_connections(&self) -> usize {
+        let txers = self.txers.read().unwrap();
+        txers.values().filter(|tx| !tx.is_closed()).count()
+    }
+    /// Drop connections which are closed or whose client is not reading.
+    ///
+    /// A connection is considered idle if its buffer of unsent events is full.
+    /// Once all other senders to a connection are also dropped, its response
+    /// ends and its socket is released. This returns the keys of the dropped
+    /// connections.
+    pub fn close_idle_connections(&self) -> Vec<KEY>
+    where
+        KEY: Clone,
+    {
+        let mut txers = self.txers.write().unwrap();
+        let idle: Vec<KEY> = txers
+            .iter()
+            .filter(|(_key, tx)| tx.is_closed() || tx.capacity() == 0)
+            .map(|(key, _tx)| key.clone())
+            .collect();
+        for key in idle.iter() {
+            txers.remove(key);
+        }
+        idle
+    }
     /// Transmit bytes as frame
     ///
     /// This will drop connections which have errored.
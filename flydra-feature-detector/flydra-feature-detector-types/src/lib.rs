@@ -7,7 +7,7 @@
 
 use serde::{Deserialize, Serialize};
 
-use http_video_streaming_types::Shape;
+use http_video_streaming_types::{PolygonParams, Shape};
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum ContrastPolarity {
@@ -16,6 +16,44 @@ pub enum ContrastPolarity {
     DetectAbsDiff,
 }
 
+/// How the location of a detected feature is estimated.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+pub enum CentroidMethod {
+    /// Intensity weighted center of mass.
+    ///
+    /// Uses the pixels within `feature_window_size` of the maximum whose
+    /// difference from the background exceeds the `clear_fraction` and
+    /// `despeckle_threshold` limits.
+    #[default]
+    CenterOfMass,
+    /// Peak of a Gaussian fit to the difference image around the maximum.
+    ///
+    /// The fit is done separately along the row and the column of the maximum
+    /// pixel. This is more precise than the center of mass for small, roughly
+    /// Gaussian features such as retroreflective markers.
+    GaussianFit(GaussianFitParams),
+    /// Center of the pixel with the largest difference from the background.
+    ///
+    /// No sub-pixel refinement is done.
+    MaxPixel,
+}
+
+/// Parameters for [CentroidMethod::GaussianFit].
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GaussianFitParams {
+    /// Number of pixels on each side of the maximum used for the fit.
+    ///
+    /// 1 gives the classic three point estimator.
+    pub half_width: u8,
+}
+
+impl Default for GaussianFitParams {
+    fn default() -> Self {
+        Self { half_width: 1 }
+    }
+}
+
 /// Configuration parameters for feature detection.
 ///
 /// These parameters are used in the 2D feature detection step. As such, they
@@ -72,6 +110,29 @@ pub struct ImPtDetectCfg {
     /// whichever is larger. Intensity difference value. Value range is 0-255.
     pub despeckle_threshold: u8,
     /// The shape of the reason over which detected points are checked.
+    ///
+    /// To restrict detection to an arena of arbitrary shape, use a `Polygon`
+    /// with the vertices of the arena outline in pixel coordinates.
     #[serde(with = "serde_yaml::with::singleton_map")]
     pub valid_region: Shape,
+    /// Polygons within which no points are detected.
+    ///
+    /// These are removed from `valid_region`, for example to ignore
+    /// reflections or equipment within the arena. Each polygon is given by the
+    /// list of its vertices in pixel coordinates.
+    #[serde(default)]
+    pub exclude_regions: Vec<PolygonParams>,
+    /// How the sub-pixel location of each feature is estimated.
+    ///
+    /// The area, slope and eccentricity are always computed from the moments
+    /// of the feature.
+    #[serde(default, with = "serde_yaml::with::singleton_map")]
+    pub centroid_method: CentroidMethod,
+    /// Compute the appearance of each detection.
+    ///
+    /// The appearance (mean intensity and intensity histogram of the image
+    /// patch around the maximum pixel) is sent with each detection. Braid can
+    /// use it to keep the identity of objects which come close to each other.
+    #[serde(default)]
+    pub compute_appearance: bool,
 }
@@ -24,6 +24,9 @@ fn my_default(polarity: ContrastPolarity, valid_region: Shape) -> ImPtDetectCfg
         clear_fraction: 0.3,
         despeckle_threshold: 5,
         valid_region,
+        exclude_regions: Vec::new(),
+        centroid_method: Default::default(),
+        compute_appearance: false,
     }
 }
 
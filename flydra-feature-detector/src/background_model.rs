@@ -33,6 +33,8 @@ pub(crate) struct BackgroundModel {
     pub(crate) complete_stamp: (chrono::DateTime<chrono::Utc>, usize),
     tx_to_worker: channellib::Sender<ToWorker>,
     rx_from_worker: channellib::Receiver<FromWorker>,
+    /// Set when the model was updated on the GPU but not yet polled.
+    updated_on_gpu: bool,
 }
 
 impl BackgroundModel {
@@ -152,6 +154,7 @@ impl BackgroundModel {
             tx_to_worker,
             rx_from_worker,
             complete_stamp,
+            updated_on_gpu: false,
         };
         Ok(result)
     }
@@ -173,7 +176,26 @@ impl BackgroundModel {
         Ok(())
     }
 
+    /// Replace the background model with one updated on the GPU.
+    #[cfg(feature = "gpu")]
+    pub(crate) fn set_from_gpu(
+        &mut self,
+        bg: &crate::gpu::GpuBackground,
+        complete_stamp: (chrono::DateTime<chrono::Utc>, usize),
+    ) -> Result<()> {
+        copy_rows(&mut self.mean_background, &bg.mean)?;
+        copy_rows(&mut self.mean_squared_im, &bg.mean_squared)?;
+        copy_rows(&mut self.cmp_im, &bg.cmp)?;
+        self.mean_im = FastImageData::copy_from_32f8u_c1(&self.mean_background, RoundMode::Near)?;
+        self.complete_stamp = complete_stamp;
+        self.updated_on_gpu = true;
+        Ok(())
+    }
+
     pub(crate) fn poll_complete_updates(&mut self) -> bool {
+        if std::mem::take(&mut self.updated_on_gpu) {
+            return true;
+        }
         match self.rx_from_worker.try_recv() {
             Ok(msg) => {
                 let (running_mean, mean_squared_im, mean_im, cmp_im, roi, ts, fno) = msg;
@@ -197,6 +219,17 @@ impl BackgroundModel {
     }
 }
 
+/// Copy `src`, which has no padding, into the valid region of `dest`.
+#[cfg(feature = "gpu")]
+fn copy_rows<I: fastim_mod::MutableFastImage>(dest: &mut I, src: &[I::D]) -> Result<()> {
+    let size = *dest.size();
+    let src_rows = src.chunks_exact(size.width() as usize);
+    for (dest_row, src_row) in dest.valid_row_iter_mut(&size)?.zip(src_rows) {
+        dest_row.copy_from_slice(src_row);
+    }
+    Ok(())
+}
+
 struct BackgroundModelWorker {
     mean_background: FastImageData<Chan1, f32>,
     mean_im: FastImageData<Chan1, u8>,
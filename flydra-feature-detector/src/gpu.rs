@@ -0,0 +1,606 @@
+//! Background subtraction and feature detection on the GPU.
+//!
+//! At high resolution and framerate, updating the background model and
+//! searching the difference image is too slow on the CPU. When compiled with
+//! the `gpu` feature and a GPU is found at runtime, these steps are done with
+//! compute shaders (see `gpu_detect.wgsl`) using wgpu, which supports Vulkan,
+//! Metal and DirectX 12. Otherwise, the CPU implementation is used.
+//!
+//! The GPU path finds features as connected components of the pixels which
+//! pass the threshold rather than by repeatedly searching for the maximum
+//! pixel. The moments of each feature are then computed, as on the CPU, from
+//! the pixels within `feature_window_size` of its maximum which are above the
+//! `clear_fraction` and `despeckle_threshold` limits. Because of this,
+//! results can differ slightly between the two paths, especially for
+//! features which are close together. With [CentroidMethod::GaussianFit],
+//! only pixels of the feature itself are used for the fit.
+
+use std::collections::{BTreeMap, HashMap};
+
+use tracing::{info, warn};
+use wgpu::util::DeviceExt;
+
+use flydra_feature_detector_types::{CentroidMethod, ContrastPolarity, ImPtDetectCfg};
+
+use crate::{errors::Error, Result};
+
+/// Maximum number of foreground pixels read back per frame.
+const PIXEL_CAPACITY: u32 = 1 << 18;
+
+/// Number of label propagation steps between checks for convergence.
+const PROPAGATE_STEPS_PER_CHECK: usize = 8;
+
+/// Maximum number of checks for convergence of the labelling.
+const MAX_PROPAGATE_CHECKS: usize = 32;
+
+const WORKGROUP_SIZE: u32 = 256;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    width: u32,
+    height: u32,
+    polarity: u32,
+    use_cmp: u32,
+    diff_threshold: u32,
+    capacity: u32,
+    bright_non_gaussian_cutoff: u32,
+    bright_non_gaussian_replacement: u32,
+    alpha: f32,
+    n_sigma: f32,
+    _pad0: u32,
+    _pad1: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Pixel {
+    label: u32,
+    index: u32,
+    value: u32,
+    _pad: u32,
+}
+
+/// A feature found on the GPU.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GpuPoint {
+    pub(crate) x0_abs: f64,
+    pub(crate) y0_abs: f64,
+    pub(crate) area: f64,
+    pub(crate) maybe_slope_eccentricty: Option<(f64, f64)>,
+    /// Column of the maximum pixel.
+    pub(crate) index_x: usize,
+    /// Row of the maximum pixel.
+    pub(crate) index_y: usize,
+    pub(crate) max_value: u8,
+}
+
+/// The background model after an update on the GPU.
+pub(crate) struct GpuBackground {
+    pub(crate) mean: Vec<f32>,
+    pub(crate) mean_squared: Vec<f32>,
+    pub(crate) cmp: Vec<u8>,
+}
+
+fn gpu_error(msg: String) -> Error {
+    Error::OtherError {
+        msg,
+        #[cfg(feature = "backtrace")]
+        backtrace: std::backtrace::Backtrace::capture(),
+    }
+}
+
+/// Pack rows of 8 bit pixels into a buffer whose length is a multiple of 4.
+fn pack_u8<'a>(rows: impl Iterator<Item = &'a [u8]>, n_pixels: usize) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(n_pixels.next_multiple_of(4));
+    for row in rows {
+        buf.extend_from_slice(row);
+    }
+    debug_assert_eq!(buf.len(), n_pixels);
+    buf.resize(n_pixels.next_multiple_of(4), 0);
+    buf
+}
+
+/// Feature detector state on the GPU for images of a fixed size.
+pub(crate) struct GpuDetector {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    width: u32,
+    height: u32,
+    params_buf: wgpu::Buffer,
+    raw_buf: wgpu::Buffer,
+    mask_buf: wgpu::Buffer,
+    background_buf: wgpu::Buffer,
+    cmp_buf: wgpu::Buffer,
+    // Only used within shaders but kept here to own them.
+    _absdiff_buf: wgpu::Buffer,
+    _labels_buf: wgpu::Buffer,
+    counters_buf: wgpu::Buffer,
+    pixels_buf: wgpu::Buffer,
+    staging_buf: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    bg_update_pipeline: wgpu::ComputePipeline,
+    diff_pipeline: wgpu::ComputePipeline,
+    propagate_pipeline: wgpu::ComputePipeline,
+    compact_pipeline: wgpu::ComputePipeline,
+}
+
+impl GpuDetector {
+    /// Initialize the GPU for images of size `width` by `height`.
+    ///
+    /// Returns `None` if no suitable GPU is available, in which case the CPU
+    /// implementation should be used.
+    pub(crate) fn new(width: u32, height: u32) -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        }));
+        let Some(adapter) = adapter else {
+            info!("no GPU found, using CPU for feature detection");
+            return None;
+        };
+        let adapter_info = adapter.get_info();
+        if adapter_info.device_type == wgpu::DeviceType::Cpu {
+            info!(
+                "only software GPU \"{}\" found, using CPU for feature detection",
+                adapter_info.name
+            );
+            return None;
+        }
+
+        let n_pixels = width as u64 * height as u64;
+        let limits = adapter.limits();
+        // The largest buffers are the background model and the read back
+        // foreground pixels.
+        let max_buffer_size = (n_pixels * 8).max(PIXEL_CAPACITY as u64 * 16);
+        if max_buffer_size > limits.max_storage_buffer_binding_size as u64
+            || n_pixels.div_ceil(WORKGROUP_SIZE as u64)
+                > limits.max_compute_workgroups_per_dimension as u64
+                    * limits.max_compute_workgroups_per_dimension as u64
+        {
+            warn!(
+                "image size {width}x{height} too large for GPU \"{}\", using CPU for feature \
+                detection",
+                adapter_info.name
+            );
+            return None;
+        }
+
+        let (device, queue) = match pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("flydra-feature-detector"),
+                required_features: wgpu::Features::empty(),
+                required_limits: limits,
+            },
+            None,
+        )) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!(
+                    "could not open GPU \"{}\" ({e}), using CPU for feature detection",
+                    adapter_info.name
+                );
+                return None;
+            }
+        };
+        info!(
+            "using GPU \"{}\" ({:?}) for feature detection",
+            adapter_info.name, adapter_info.backend
+        );
+
+        let storage_buf = |label: &str, size: u64| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        };
+
+        let packed_size = n_pixels.next_multiple_of(4);
+        let params_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("params"),
+            size: std::mem::size_of::<Params>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let raw_buf = storage_buf("raw", packed_size);
+        let mask_buf = storage_buf("mask", packed_size);
+        let background_buf = storage_buf("background", n_pixels * 8);
+        let cmp_buf = storage_buf("cmp", n_pixels * 4);
+        let absdiff_buf = storage_buf("absdiff", n_pixels * 4);
+        let labels_buf = storage_buf("labels", n_pixels * 4);
+        let counters_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("counters"),
+            contents: bytemuck::cast_slice(&[0u32; 2]),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+        let pixels_buf = storage_buf(
+            "pixels",
+            PIXEL_CAPACITY as u64 * std::mem::size_of::<Pixel>() as u64,
+        );
+        let staging_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging"),
+            size: max_buffer_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let layout_entry = |binding: u32, ty: wgpu::BufferBindingType| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let read_only = wgpu::BufferBindingType::Storage { read_only: true };
+        let read_write = wgpu::BufferBindingType::Storage { read_only: false };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("feature detection"),
+            entries: &[
+                layout_entry(0, wgpu::BufferBindingType::Uniform),
+                layout_entry(1, read_only),
+                layout_entry(2, read_only),
+                layout_entry(3, read_write),
+                layout_entry(4, read_write),
+                layout_entry(5, read_write),
+                layout_entry(6, read_write),
+                layout_entry(7, read_write),
+                layout_entry(8, read_write),
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("feature detection"),
+            layout: &bind_group_layout,
+            entries: &[
+                &params_buf,
+                &raw_buf,
+                &mask_buf,
+                &background_buf,
+                &cmp_buf,
+                &absdiff_buf,
+                &labels_buf,
+                &counters_buf,
+                &pixels_buf,
+            ]
+            .iter()
+            .enumerate()
+            .map(|(binding, buf)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buf.as_entire_binding(),
+            })
+            .collect::<Vec<_>>(),
+        });
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("gpu_detect.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu_detect.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("feature detection"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point,
+            })
+        };
+        let bg_update_pipeline = pipeline("bg_update");
+        let diff_pipeline = pipeline("diff");
+        let propagate_pipeline = pipeline("propagate");
+        let compact_pipeline = pipeline("compact");
+
+        Some(Self {
+            device,
+            queue,
+            width,
+            height,
+            params_buf,
+            raw_buf,
+            mask_buf,
+            background_buf,
+            cmp_buf,
+            _absdiff_buf: absdiff_buf,
+            _labels_buf: labels_buf,
+            counters_buf,
+            pixels_buf,
+            staging_buf,
+            bind_group,
+            bg_update_pipeline,
+            diff_pipeline,
+            propagate_pipeline,
+            compact_pipeline,
+        })
+    }
+
+    fn n_pixels(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// Set the mask image. Pixels with nonzero values are ignored.
+    pub(crate) fn set_mask<'a>(&self, rows: impl Iterator<Item = &'a [u8]>) {
+        let buf = pack_u8(rows, self.n_pixels());
+        self.queue.write_buffer(&self.mask_buf, 0, &buf);
+    }
+
+    /// Replace the background model.
+    ///
+    /// All slices have one element per pixel without padding.
+    pub(crate) fn set_background(&self, mean: &[f32], mean_squared: &[f32], cmp: &[u8]) {
+        let background: Vec<[f32; 2]> = mean
+            .iter()
+            .zip(mean_squared.iter())
+            .map(|(m, s)| [*m, *s])
+            .collect();
+        let cmp: Vec<u32> = cmp.iter().map(|x| *x as u32).collect();
+        self.queue
+            .write_buffer(&self.background_buf, 0, bytemuck::cast_slice(&background));
+        self.queue
+            .write_buffer(&self.cmp_buf, 0, bytemuck::cast_slice(&cmp));
+    }
+
+    fn write_params(&self, cfg: &ImPtDetectCfg) {
+        let polarity = match cfg.polarity {
+            ContrastPolarity::DetectLight => 0,
+            ContrastPolarity::DetectDark => 1,
+            ContrastPolarity::DetectAbsDiff => 2,
+        };
+        let params = Params {
+            width: self.width,
+            height: self.height,
+            polarity,
+            use_cmp: cfg.use_cmp.into(),
+            diff_threshold: cfg.diff_threshold.into(),
+            capacity: PIXEL_CAPACITY,
+            bright_non_gaussian_cutoff: cfg.bright_non_gaussian_cutoff.into(),
+            bright_non_gaussian_replacement: cfg.bright_non_gaussian_replacement.into(),
+            alpha: cfg.alpha,
+            n_sigma: cfg.n_sigma,
+            _pad0: 0,
+            _pad1: 0,
+        };
+        self.queue
+            .write_buffer(&self.params_buf, 0, bytemuck::bytes_of(&params));
+    }
+
+    fn dispatch(&self, encoder: &mut wgpu::CommandEncoder, pipeline: &wgpu::ComputePipeline) {
+        let n_groups = (self.n_pixels() as u32).div_ceil(WORKGROUP_SIZE);
+        let max_per_dim = self.device.limits().max_compute_workgroups_per_dimension;
+        let groups_x = n_groups.min(max_per_dim);
+        let groups_y = n_groups.div_ceil(groups_x);
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.dispatch_workgroups(groups_x, groups_y, 1);
+    }
+
+    /// Copy `size` bytes of `src` to the CPU.
+    fn read_buffer(&self, src: &wgpu::Buffer, size: u64) -> Result<Vec<u8>> {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(src, 0, &self.staging_buf, 0, size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = self.staging_buf.slice(..size);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        rx.recv()
+            .map_err(|e| gpu_error(format!("GPU buffer mapping cancelled: {e}")))?
+            .map_err(|e| gpu_error(format!("could not map GPU buffer: {e}")))?;
+        let data = slice.get_mapped_range().to_vec();
+        self.staging_buf.unmap();
+        Ok(data)
+    }
+
+    /// Update the background model with a new image.
+    pub(crate) fn update_background<'a>(
+        &self,
+        rows: impl Iterator<Item = &'a [u8]>,
+        cfg: &ImPtDetectCfg,
+    ) -> Result<GpuBackground> {
+        let n_pixels = self.n_pixels();
+        self.queue
+            .write_buffer(&self.raw_buf, 0, &pack_u8(rows, n_pixels));
+        self.write_params(cfg);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        self.dispatch(&mut encoder, &self.bg_update_pipeline);
+        self.queue.submit(Some(encoder.finish()));
+
+        let background = self.read_buffer(&self.background_buf, n_pixels as u64 * 8)?;
+        let background: &[[f32; 2]] = bytemuck::cast_slice(&background);
+        let cmp = self.read_buffer(&self.cmp_buf, n_pixels as u64 * 4)?;
+        let cmp: &[u32] = bytemuck::cast_slice(&cmp);
+        Ok(GpuBackground {
+            mean: background.iter().map(|x| x[0]).collect(),
+            mean_squared: background.iter().map(|x| x[1]).collect(),
+            cmp: cmp.iter().map(|x| *x as u8).collect(),
+        })
+    }
+
+    /// Find features in a new image.
+    ///
+    /// Returns at most `cfg.max_num_points` features, in order of decreasing
+    /// maximum difference from the background.
+    pub(crate) fn detect<'a>(
+        &self,
+        rows: impl Iterator<Item = &'a [u8]>,
+        cfg: &ImPtDetectCfg,
+    ) -> Result<Vec<GpuPoint>> {
+        self.queue
+            .write_buffer(&self.raw_buf, 0, &pack_u8(rows, self.n_pixels()));
+        self.write_params(cfg);
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        self.dispatch(&mut encoder, &self.diff_pipeline);
+        self.queue.submit(Some(encoder.finish()));
+
+        // Label connected components.
+        let mut converged = false;
+        for _ in 0..MAX_PROPAGATE_CHECKS {
+            self.queue
+                .write_buffer(&self.counters_buf, 0, bytemuck::cast_slice(&[0u32; 2]));
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            for _ in 0..PROPAGATE_STEPS_PER_CHECK {
+                self.dispatch(&mut encoder, &self.propagate_pipeline);
+            }
+            self.queue.submit(Some(encoder.finish()));
+            let counters = self.read_buffer(&self.counters_buf, 8)?;
+            let changed = bytemuck::cast_slice::<u8, u32>(&counters)[0];
+            if changed == 0 {
+                converged = true;
+                break;
+            }
+        }
+        if !converged {
+            warn!("connected component labelling did not converge, features may be split");
+        }
+
+        // Collect the foreground pixels.
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        self.dispatch(&mut encoder, &self.compact_pipeline);
+        self.queue.submit(Some(encoder.finish()));
+        let counters = self.read_buffer(&self.counters_buf, 8)?;
+        let n_foreground = bytemuck::cast_slice::<u8, u32>(&counters)[1];
+        if n_foreground > PIXEL_CAPACITY {
+            warn!(
+                "{n_foreground} pixels above threshold, only {PIXEL_CAPACITY} used for feature \
+                detection"
+            );
+        }
+        let n_read = n_foreground.min(PIXEL_CAPACITY) as u64;
+        if n_read == 0 {
+            return Ok(Vec::new());
+        }
+        let pixels = self.read_buffer(
+            &self.pixels_buf,
+            n_read * std::mem::size_of::<Pixel>() as u64,
+        )?;
+        let pixels: &[Pixel] = bytemuck::cast_slice(&pixels);
+
+        Ok(features_from_pixels(pixels, self.width, cfg))
+    }
+}
+
+/// Compute features from the labelled foreground pixels.
+fn features_from_pixels(pixels: &[Pixel], width: u32, cfg: &ImPtDetectCfg) -> Vec<GpuPoint> {
+    let mut components: BTreeMap<u32, Vec<&Pixel>> = BTreeMap::new();
+    for pixel in pixels.iter() {
+        components.entry(pixel.label).or_default().push(pixel);
+    }
+
+    // Find the maximum of each component.
+    let mut maxima: Vec<(&Pixel, &[&Pixel])> = components
+        .values()
+        .map(|pixels| {
+            // Components are never empty.
+            let max = *pixels
+                .iter()
+                .max_by_key(|p| (p.value, u32::MAX - p.index))
+                .unwrap();
+            (max, pixels.as_slice())
+        })
+        .collect();
+    maxima.sort_by_key(|(max, _)| (std::cmp::Reverse(max.value), max.index));
+    maxima.truncate(cfg.max_num_points.into());
+
+    let window = i64::from(cfg.feature_window_size);
+    maxima
+        .into_iter()
+        .filter_map(|(max, pixels)| {
+            let max_value = max.value as u8;
+            let max_x = i64::from(max.index % width);
+            let max_y = i64::from(max.index / width);
+            let thresh = std::cmp::max(
+                (cfg.clear_fraction * max_value as f32) as u8,
+                cfg.despeckle_threshold,
+            );
+
+            let (mut m00, mut m10, mut m01, mut m20, mut m02, mut m11) =
+                (0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+            for pixel in pixels.iter() {
+                let x = i64::from(pixel.index % width);
+                let y = i64::from(pixel.index / width);
+                if (x - max_x).abs() > window || (y - max_y).abs() > window {
+                    continue;
+                }
+                if pixel.value < thresh.into() {
+                    continue;
+                }
+                let v = pixel.value as f64;
+                let (x, y) = (x as f64, y as f64);
+                m00 += v;
+                m10 += v * x;
+                m01 += v * y;
+                m20 += v * x * x;
+                m02 += v * y * y;
+                m11 += v * x * y;
+            }
+            if m00 == 0.0 {
+                return None;
+            }
+            let x0 = m10 / m00;
+            let y0 = m01 / m00;
+            let uu20 = m20 - x0 * m10;
+            let uu02 = m02 - y0 * m01;
+            let uu11 = m11 - x0 * m01;
+
+            let (max_x_abs, max_y_abs) = (max_x as f64, max_y as f64);
+            let (x0_abs, y0_abs) = match &cfg.centroid_method {
+                CentroidMethod::CenterOfMass => (x0, y0),
+                CentroidMethod::GaussianFit(params) => {
+                    let values: HashMap<(i64, i64), f64> = pixels
+                        .iter()
+                        .map(|p| {
+                            let x = i64::from(p.index % width);
+                            let y = i64::from(p.index / width);
+                            ((x, y), p.value as f64)
+                        })
+                        .collect();
+                    let (dx, dy) = crate::gaussian_fit_offset(max_x, max_y, params, |col, row| {
+                        values.get(&(col, row)).copied()
+                    });
+                    (max_x_abs + dx, max_y_abs + dy)
+                }
+                CentroidMethod::MaxPixel => (max_x_abs, max_y_abs),
+            };
+            Some(GpuPoint {
+                x0_abs,
+                y0_abs,
+                area: m00,
+                maybe_slope_eccentricty: crate::slope_from_central_moments(uu20, uu11, uu02).ok(),
+                index_x: max_x as usize,
+                index_y: max_y as usize,
+                max_value,
+            })
+        })
+        .collect()
+}
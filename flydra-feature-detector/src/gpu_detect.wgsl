@@ -0,0 +1,175 @@
+// Compute shaders for background subtraction, thresholding and connected
+// component labelling. See `gpu.rs`.
+
+struct Params {
+    width: u32,
+    height: u32,
+    // 0: DetectLight, 1: DetectDark, 2: DetectAbsDiff
+    polarity: u32,
+    use_cmp: u32,
+    diff_threshold: u32,
+    capacity: u32,
+    bright_non_gaussian_cutoff: u32,
+    bright_non_gaussian_replacement: u32,
+    alpha: f32,
+    n_sigma: f32,
+    _pad0: u32,
+    _pad1: u32,
+}
+
+struct Pixel {
+    label: u32,
+    index: u32,
+    value: u32,
+    _pad: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+// Camera image, four 8 bit pixels per element.
+@group(0) @binding(1) var<storage, read> raw: array<u32>;
+// Mask image, four 8 bit pixels per element. Nonzero pixels are ignored.
+@group(0) @binding(2) var<storage, read> mask: array<u32>;
+// Mean and mean of squares of the background model.
+@group(0) @binding(3) var<storage, read_write> background: array<vec2<f32>>;
+// Minimum difference from the mean to detect a pixel when `use_cmp` is set.
+@group(0) @binding(4) var<storage, read_write> cmp: array<u32>;
+@group(0) @binding(5) var<storage, read_write> absdiff: array<u32>;
+// Zero for background pixels, otherwise one plus the smallest index of a
+// pixel known to be in the same connected component.
+@group(0) @binding(6) var<storage, read_write> labels: array<u32>;
+// [0]: set if a label changed, [1]: number of foreground pixels.
+@group(0) @binding(7) var<storage, read_write> counters: array<atomic<u32>, 2>;
+@group(0) @binding(8) var<storage, read_write> pixels: array<Pixel>;
+
+fn raw_at(i: u32) -> u32 {
+    return (raw[i / 4u] >> ((i % 4u) * 8u)) & 0xffu;
+}
+
+fn mask_at(i: u32) -> u32 {
+    return (mask[i / 4u] >> ((i % 4u) * 8u)) & 0xffu;
+}
+
+fn pixel_index(gid: vec3<u32>, nwg: vec3<u32>) -> u32 {
+    return gid.y * nwg.x * 256u + gid.x;
+}
+
+@compute @workgroup_size(256)
+fn bg_update(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let i = pixel_index(gid, nwg);
+    if (i >= params.width * params.height) {
+        return;
+    }
+    let v = f32(raw_at(i));
+    let a = params.alpha;
+    let prev = background[i];
+    let mean = prev.x * (1.0 - a) + v * a;
+    let mean_sq = prev.y * (1.0 - a) + v * v * a;
+    background[i] = vec2<f32>(mean, mean_sq);
+
+    var c = u32(clamp(round(params.n_sigma * sqrt(abs(mean_sq - mean * mean))), 0.0, 255.0));
+    if (u32(round(mean)) > params.bright_non_gaussian_cutoff) {
+        c = params.bright_non_gaussian_replacement;
+    }
+    cmp[i] = c;
+}
+
+@compute @workgroup_size(256)
+fn diff(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let i = pixel_index(gid, nwg);
+    if (i >= params.width * params.height) {
+        return;
+    }
+    let v = i32(raw_at(i));
+    let m = i32(round(background[i].x));
+    var d: i32;
+    switch params.polarity {
+        case 0u: {
+            d = v - m;
+        }
+        case 1u: {
+            d = m - v;
+        }
+        default: {
+            d = abs(v - m);
+        }
+    }
+    d = max(d, 0);
+    if (mask_at(i) != 0u) {
+        d = 0;
+    }
+    absdiff[i] = u32(d);
+
+    var is_foreground: bool;
+    if (params.use_cmp != 0u) {
+        is_foreground = u32(d) > max(cmp[i], params.diff_threshold);
+    } else {
+        is_foreground = u32(d) >= params.diff_threshold;
+    }
+    labels[i] = select(0u, i + 1u, is_foreground);
+}
+
+@compute @workgroup_size(256)
+fn propagate(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let i = pixel_index(gid, nwg);
+    if (i >= params.width * params.height) {
+        return;
+    }
+    let label = labels[i];
+    if (label == 0u) {
+        return;
+    }
+    let x = i % params.width;
+    let y = i / params.width;
+
+    var best = label;
+    if (x > 0u) {
+        let other = labels[i - 1u];
+        if (other != 0u && other < best) {
+            best = other;
+        }
+    }
+    if (x + 1u < params.width) {
+        let other = labels[i + 1u];
+        if (other != 0u && other < best) {
+            best = other;
+        }
+    }
+    if (y > 0u) {
+        let other = labels[i - params.width];
+        if (other != 0u && other < best) {
+            best = other;
+        }
+    }
+    if (y + 1u < params.height) {
+        let other = labels[i + params.width];
+        if (other != 0u && other < best) {
+            best = other;
+        }
+    }
+    // Pointer jumping: take the label of the pixel the label points to.
+    let jump = labels[best - 1u];
+    if (jump != 0u && jump < best) {
+        best = jump;
+    }
+
+    if (best < label) {
+        labels[i] = best;
+        atomicStore(&counters[0], 1u);
+    }
+}
+
+@compute @workgroup_size(256)
+fn compact(@builtin(global_invocation_id) gid: vec3<u32>, @builtin(num_workgroups) nwg: vec3<u32>) {
+    let i = pixel_index(gid, nwg);
+    if (i >= params.width * params.height) {
+        return;
+    }
+    let label = labels[i];
+    if (label == 0u) {
+        return;
+    }
+    let slot = atomicAdd(&counters[1], 1u);
+    if (slot < params.capacity) {
+        pixels[slot] = Pixel(label, i, absdiff[i], 0u);
+    }
+}
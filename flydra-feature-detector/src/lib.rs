@@ -37,12 +37,14 @@ use timestamped_frame::ExtraTimeData;
 
 use basic_frame::DynamicFrame;
 use flydra_types::{
-    FlydraFloatTimestampLocal, FlydraRawUdpPacket, FlydraRawUdpPoint, ImageProcessingSteps,
-    RawCamName,
+    Appearance, FlydraFloatTimestampLocal, FlydraRawUdpPacket, FlydraRawUdpPoint,
+    ImageProcessingSteps, RawCamName,
 };
 use ufmf::UFMFWriter;
 
-pub use flydra_feature_detector_types::{ContrastPolarity, ImPtDetectCfg};
+pub use flydra_feature_detector_types::{
+    CentroidMethod, ContrastPolarity, GaussianFitParams, ImPtDetectCfg,
+};
 use http_video_streaming_types::Shape;
 
 mod borrow_fastimage;
@@ -54,8 +56,33 @@ use crate::background_model::BackgroundModel;
 mod errors;
 pub use crate::errors::*;
 
+#[cfg(feature = "gpu")]
+mod gpu;
+
 const NUM_BG_START_IMAGES: usize = 20;
 
+/// Half the width (and half the height) of the image patch for the appearance
+/// of a detection. In pixels.
+const APPEARANCE_PATCH_HALF_WIDTH: i64 = 4;
+
+/// Appearance of the image patch around pixel (`col`, `row`).
+///
+/// The patch is clipped at the image borders.
+fn patch_appearance<S>(raw_im_full: &S, col: i64, row: i64) -> Option<Appearance>
+where
+    S: FastImage<D = u8, C = Chan1>,
+{
+    let w = i64::from(raw_im_full.size().width());
+    let h = i64::from(raw_im_full.size().height());
+    let half_width = APPEARANCE_PATCH_HALF_WIDTH;
+    let rows = (row - half_width).max(0)..=(row + half_width).min(h - 1);
+    let cols = (col - half_width).max(0)..=(col + half_width).min(w - 1);
+    Appearance::from_pixels(rows.flat_map(|r| {
+        cols.clone()
+            .map(move |c| raw_im_full.pixel_slice(r as usize, c as usize)[0])
+    }))
+}
+
 fn eigen_2x2_real(a: f64, b: f64, c: f64, d: f64) -> Result<(f64, f64, f64, f64)> {
     if c == 0.0 {
         return Err(Error::DivideByZero(
@@ -76,6 +103,11 @@ fn compute_slope(moments: &MomentState) -> Result<(f64, f64)> {
     let uu11 = moments.central(1, 1, 0)?;
     let uu20 = moments.central(2, 0, 0)?;
     let uu02 = moments.central(0, 2, 0)?;
+    slope_from_central_moments(uu20, uu11, uu02)
+}
+
+/// Compute slope and eccentricity from the second order central moments.
+fn slope_from_central_moments(uu20: f64, uu11: f64, uu02: f64) -> Result<(f64, f64)> {
     let (eval_a, evec_a1, eval_b, evec_b1) = eigen_2x2_real(uu20, uu11, uu11, uu02)?;
 
     let rise = 1.0;
@@ -88,6 +120,58 @@ fn compute_slope(moments: &MomentState) -> Result<(f64, f64)> {
     Ok((slope, eccentricity))
 }
 
+/// Sub-pixel offset of the peak of a Gaussian fit to `samples`.
+///
+/// Each sample is `(offset, value)` relative to the maximum pixel. A parabola
+/// is fit to the logarithm of the values by least squares. Returns 0.0 if
+/// there are too few samples or the fit has no maximum.
+fn gaussian_peak_offset(samples: &[(f64, f64)]) -> f64 {
+    if samples.len() < 3 {
+        return 0.0;
+    }
+    let mut ata = nalgebra::Matrix3::<f64>::zeros();
+    let mut atb = nalgebra::Vector3::<f64>::zeros();
+    for &(x, value) in samples.iter() {
+        let row = nalgebra::Vector3::new(x * x, x, 1.0);
+        ata += row * row.transpose();
+        atb += row * value.max(1.0).ln();
+    }
+    let Some(coeffs) = ata.lu().solve(&atb) else {
+        return 0.0;
+    };
+    let (a, b) = (coeffs[0], coeffs[1]);
+    if a >= 0.0 {
+        return 0.0;
+    }
+    let (lo, hi) = samples.iter().fold((0.0f64, 0.0f64), |(lo, hi), (x, _)| {
+        (lo.min(*x), hi.max(*x))
+    });
+    (-b / (2.0 * a)).clamp(lo, hi)
+}
+
+/// Offset of the Gaussian peak from the maximum pixel at (`col`, `row`).
+///
+/// `value_at(col, row)` returns the difference from the background or `None`
+/// if it is not available.
+fn gaussian_fit_offset(
+    col: i64,
+    row: i64,
+    params: &GaussianFitParams,
+    value_at: impl Fn(i64, i64) -> Option<f64>,
+) -> (f64, f64) {
+    let half_width = i64::from(params.half_width);
+    let x_samples: Vec<(f64, f64)> = (-half_width..=half_width)
+        .filter_map(|k| value_at(col + k, row).map(|v| (k as f64, v)))
+        .collect();
+    let y_samples: Vec<(f64, f64)> = (-half_width..=half_width)
+        .filter_map(|k| value_at(col, row + k).map(|v| (k as f64, v)))
+        .collect();
+    (
+        gaussian_peak_offset(&x_samples),
+        gaussian_peak_offset(&y_samples),
+    )
+}
+
 #[allow(dead_code)]
 #[derive(Serialize)]
 enum ImageTrackerState {
@@ -304,6 +388,31 @@ impl TrackingState {
             let roi2_sz = FastImageSize::new(right2 - left2, top2 - bottom2);
 
             let roi2 = FastImageRegion::new(fastim_mod::Point::new(left2, bottom2), roi2_sz);
+
+            // The Gaussian fit uses the difference image before thresholding.
+            let gaussian_offset = if let CentroidMethod::GaussianFit(params) = &cfg.centroid_method
+            {
+                let w = i64::from(self.background.current_roi.width());
+                let h = i64::from(self.background.current_roi.height());
+                gaussian_fit_offset(
+                    max_loc.x().into(),
+                    max_loc.y().into(),
+                    params,
+                    |col, row| {
+                        if (0..w).contains(&col) && (0..h).contains(&row) {
+                            Some(
+                                absdiff_im_roi_view.pixel_slice(row as usize, col as usize)[0]
+                                    as f64,
+                            )
+                        } else {
+                            None
+                        }
+                    },
+                )
+            } else {
+                (0.0, 0.0)
+            };
+
             {
                 let mut absdiff_im_roi2_view =
                     MutableFastImageView::view_region(&mut absdiff_im_roi_view, &roi2)?;
@@ -338,9 +447,18 @@ impl TrackingState {
                         let y0 = mu01 / mu00;
                         let maybe_slope_eccentricty = compute_slope(&self.moments).ok();
 
-                        // set x0 and y0 relative to whole frame
-                        let x0_abs = x0 + left2 as f64;
-                        let y0_abs = y0 + bottom2 as f64;
+                        let max_x_abs = (max_loc.x() + self.background.current_roi.left()) as f64;
+                        let max_y_abs = (max_loc.y() + self.background.current_roi.bottom()) as f64;
+                        let (x0_abs, y0_abs) = match &cfg.centroid_method {
+                            // set x0 and y0 relative to whole frame
+                            CentroidMethod::CenterOfMass => {
+                                (x0 + left2 as f64, y0 + bottom2 as f64)
+                            }
+                            CentroidMethod::GaussianFit(_) => {
+                                (max_x_abs + gaussian_offset.0, max_y_abs + gaussian_offset.1)
+                            }
+                            CentroidMethod::MaxPixel => (max_x_abs, max_y_abs),
+                        };
 
                         let index_x = max_loc.x();
                         let index_y = max_loc.y();
@@ -366,6 +484,7 @@ impl TrackingState {
                                 cur_val,
                                 mean_val,
                                 sumsqf_val,
+                                appearance: None,
                             },
                             index_x,
                             index_y,
@@ -387,6 +506,8 @@ enum BackgroundAcquisitionState {
     Initialization,
     StartupMode(StartupState),
     ClearToValue(f32),
+    /// Use the next image as background without averaging startup images.
+    ResetToCurrentImage,
     NormalUpdates(TrackingState),
     TemporaryHold,
 }
@@ -427,12 +548,17 @@ pub struct FlydraFeatureDetector {
     last_sent_raw_image_time: std::time::Instant,
     mask_image: Option<FastImageData<Chan1, u8>>,
     background_update_state: BackgroundAcquisitionState, // command from UI "take a new bg image"
+    /// If set, the background model is not updated with new images.
+    background_frozen: bool,
     frame_offset: Option<u64>,
     acquisition_histogram: AcquisitionHistogram,
     acquisition_duration_allowed_imprecision_msec: Option<f64>,
 
     transmit_feature_detect_settings_tx:
         Option<mpsc::Sender<flydra_feature_detector_types::ImPtDetectCfg>>,
+    /// `None` if no GPU is available, in which case the CPU is used.
+    #[cfg(feature = "gpu")]
+    gpu: Option<gpu::GpuDetector>,
 }
 
 #[derive(Debug)]
@@ -572,10 +698,13 @@ impl FlydraFeatureDetector {
             mask_image: None,
             last_sent_raw_image_time: std::time::Instant::now(),
             background_update_state: BackgroundAcquisitionState::Initialization,
+            background_frozen: false,
             frame_offset,
             acquisition_histogram,
             acquisition_duration_allowed_imprecision_msec,
             transmit_feature_detect_settings_tx,
+            #[cfg(feature = "gpu")]
+            gpu: gpu::GpuDetector::new(w, h),
         };
 
         result.reload_config()?;
@@ -599,10 +728,111 @@ impl FlydraFeatureDetector {
             sender.try_send(self.cfg.clone()).unwrap();
         }
 
-        self.mask_image = Some(compute_mask_image(&self.roi_sz, &self.cfg.valid_region)?);
+        let mut mask_image = compute_mask_image(&self.roi_sz, &self.cfg.valid_region)?;
+        apply_exclude_regions(&mut mask_image, &self.cfg.exclude_regions)?;
+        #[cfg(feature = "gpu")]
+        if let Some(gpu) = &self.gpu {
+            gpu.set_mask(mask_image.valid_row_iter(&self.roi_sz)?);
+        }
+        self.mask_image = Some(mask_image);
         Ok(())
     }
 
+    /// Copy a new background model to the GPU, if it is used.
+    #[cfg_attr(not(feature = "gpu"), allow(unused_variables))]
+    fn gpu_set_background(&self, background: &BackgroundModel) -> Result<()> {
+        #[cfg(feature = "gpu")]
+        if let Some(gpu) = &self.gpu {
+            let mean: Vec<f32> = background
+                .mean_background
+                .valid_row_iter(&self.roi_sz)?
+                .flatten()
+                .copied()
+                .collect();
+            let mean_squared: Vec<f32> = background
+                .mean_squared_im
+                .valid_row_iter(&self.roi_sz)?
+                .flatten()
+                .copied()
+                .collect();
+            let cmp: Vec<u8> = background
+                .cmp_im
+                .valid_row_iter(&self.roi_sz)?
+                .flatten()
+                .copied()
+                .collect();
+            gpu.set_background(&mean, &mean_squared, &cmp);
+        }
+        Ok(())
+    }
+
+    /// Update the background model on the GPU, if it is used.
+    ///
+    /// Returns false if the background model must be updated on the CPU.
+    #[cfg_attr(not(feature = "gpu"), allow(unused_variables))]
+    fn gpu_update_background<S>(
+        &self,
+        raw_im_full: &S,
+        frame: &DynamicFrame,
+        background: &mut BackgroundModel,
+    ) -> Result<bool>
+    where
+        S: FastImage<D = u8, C = Chan1>,
+    {
+        #[cfg(feature = "gpu")]
+        if let Some(gpu) = &self.gpu {
+            let bg = gpu.update_background(raw_im_full.valid_row_iter(&self.roi_sz)?, &self.cfg)?;
+            let complete_stamp = (
+                frame.extra().host_timestamp(),
+                frame.extra().host_framenumber(),
+            );
+            background.set_from_gpu(&bg, complete_stamp)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Detect features on the GPU, if it is used.
+    ///
+    /// Returns `None` if detection must be done on the CPU.
+    #[cfg_attr(not(feature = "gpu"), allow(unused_variables))]
+    fn gpu_detect<S>(
+        &self,
+        raw_im_full: &S,
+        background: &BackgroundModel,
+    ) -> Result<Option<Vec<PointInfo>>>
+    where
+        S: FastImage<D = u8, C = Chan1>,
+    {
+        #[cfg(feature = "gpu")]
+        if let Some(gpu) = &self.gpu {
+            let points = gpu.detect(raw_im_full.valid_row_iter(&self.roi_sz)?, &self.cfg)?;
+            let points = points
+                .into_iter()
+                .map(|pt| {
+                    let (row, col) = (pt.index_y, pt.index_x);
+                    PointInfo {
+                        inner: FlydraRawUdpPoint {
+                            x0_abs: pt.x0_abs,
+                            y0_abs: pt.y0_abs,
+                            area: pt.area,
+                            maybe_slope_eccentricty: pt.maybe_slope_eccentricty,
+                            cur_val: raw_im_full.pixel_slice(row, col)[0],
+                            mean_val: background.mean_background.pixel_slice(row, col)[0] as f64,
+                            sumsqf_val: background.mean_squared_im.pixel_slice(row, col)[0] as f64,
+                            appearance: None,
+                        },
+                        index_x: col as ipp_ctypes::c_int,
+                        index_y: row as ipp_ctypes::c_int,
+                        max_value: pt.max_value,
+                    }
+                })
+                .collect();
+            return Ok(Some(points));
+        }
+        Ok(None)
+    }
+
     pub fn set_frame_offset(&mut self, value: u64) {
         debug!("set_frame_offset");
         self.frame_offset = Some(value);
@@ -622,6 +852,30 @@ impl FlydraFeatureDetector {
         Ok(())
     }
 
+    /// Reset the background model to the next image.
+    ///
+    /// Unlike [Self::do_take_current_image_as_background], no startup images
+    /// are averaged, so detection continues with the next frame.
+    pub fn do_reset_background_to_current_image(&mut self) -> Result<()> {
+        debug!("resetting bg image to current image");
+        self.background_update_state = BackgroundAcquisitionState::ResetToCurrentImage;
+        Ok(())
+    }
+
+    /// Suspend or resume the updates of the background model.
+    ///
+    /// While frozen, the background model is still initialized, cleared and
+    /// reset when commanded.
+    pub fn set_background_frozen(&mut self, frozen: bool) {
+        debug!("set_background_frozen {}", frozen);
+        self.background_frozen = frozen;
+    }
+
+    /// Whether the updates of the background model are suspended.
+    pub fn is_background_frozen(&self) -> bool {
+        self.background_frozen
+    }
+
     /// Detect features of interest and update background model.
     ///
     /// The detected features are returned as a [FlydraRawUdpPacket] in the
@@ -720,11 +974,12 @@ impl FlydraFeatureDetector {
             n_frames_skipped: 0, // FIXME TODO XXX FIX THIS, should be n_frames_skipped
             done_camnode_processing: 0.0,
             preprocess_stamp,
+            packet_sent_stamp: 0.0,
             image_processing_steps: ImageProcessingSteps::empty(),
             points: vec![],
         };
 
-        let (results, next_background_update_state) = match current_update_state {
+        let (results, mut next_background_update_state) = match current_update_state {
             BackgroundAcquisitionState::TemporaryHold => {
                 panic!("unreachable");
             }
@@ -781,6 +1036,7 @@ impl FlydraFeatureDetector {
                         pixel_format,
                         complete_stamp,
                     )?;
+                    self.gpu_set_background(&state.background)?;
                     (packet, BackgroundAcquisitionState::NormalUpdates(state))
                 } else {
                     (
@@ -813,15 +1069,41 @@ impl FlydraFeatureDetector {
                     pixel_format,
                     complete_stamp,
                 )?;
+                self.gpu_set_background(&state.background)?;
                 debug!("cleared background model to value {}", value);
                 packet.image_processing_steps |= ImageProcessingSteps::BGCLEARED;
                 (packet, BackgroundAcquisitionState::NormalUpdates(state))
             }
+            BackgroundAcquisitionState::ResetToCurrentImage => {
+                let running_mean = FastImageData::<Chan1, f32>::copy_from_8u32f_c1(&raw_im_full)?;
+
+                let mut mean_squared_im =
+                    FastImageData::<Chan1, f32>::copy_from_8u32f_c1(&raw_im_full)?;
+                ripp::sqr_32f_c1ir(&mut mean_squared_im, &self.roi_sz)?;
+
+                let complete_stamp = (
+                    frame.extra().host_timestamp(),
+                    frame.extra().host_framenumber(),
+                );
+
+                let state = TrackingState::new(
+                    &raw_im_full,
+                    running_mean,
+                    mean_squared_im,
+                    &self.cfg,
+                    pixel_format,
+                    complete_stamp,
+                )?;
+                self.gpu_set_background(&state.background)?;
+                debug!("reset background model to current image");
+                packet.image_processing_steps |= ImageProcessingSteps::BGCLEARED;
+                (packet, BackgroundAcquisitionState::NormalUpdates(state))
+            }
             BackgroundAcquisitionState::NormalUpdates(mut state) => {
                 let got_new_bg_data = state.background.poll_complete_updates();
 
                 if state.frames_since_background_update >= self.cfg.bg_update_interval {
-                    if self.cfg.do_update_background_model {
+                    if self.cfg.do_update_background_model && !self.background_frozen {
                         packet.image_processing_steps |= ImageProcessingSteps::BGUPDATE;
                         // defer processing bg images until after this frame data sent
                         saved_bg_image = Some(frame);
@@ -831,21 +1113,31 @@ impl FlydraFeatureDetector {
                     state.frames_since_background_update += 1;
                 }
                 // The following can take 40+ msec? e.g. 2018-08-29T08:41:19.582785551Z
-                let points = if let Some(ref mask_image) = self.mask_image {
-                    state.do_work(
-                        //corrected_frame,
-                        &raw_im_full,
-                        &self.cfg,
-                        Some(mask_image),
-                    )?
-                } else {
-                    state.do_work::<_, FastImageData<Chan1, u8>>(
-                        // corrected_frame,
-                        &raw_im_full,
-                        &self.cfg,
-                        None,
-                    )?
-                };
+                let mut points =
+                    if let Some(points) = self.gpu_detect(&raw_im_full, &state.background)? {
+                        points
+                    } else if let Some(ref mask_image) = self.mask_image {
+                        state.do_work(
+                            //corrected_frame,
+                            &raw_im_full,
+                            &self.cfg,
+                            Some(mask_image),
+                        )?
+                    } else {
+                        state.do_work::<_, FastImageData<Chan1, u8>>(
+                            // corrected_frame,
+                            &raw_im_full,
+                            &self.cfg,
+                            None,
+                        )?
+                    };
+
+                if self.cfg.compute_appearance {
+                    for pt in points.iter_mut() {
+                        pt.inner.appearance =
+                            patch_appearance(&raw_im_full, pt.index_x.into(), pt.index_y.into());
+                    }
+                }
 
                 let radius = self.cfg.feature_window_size;
                 let point_data: Vec<_> = points
@@ -884,17 +1176,18 @@ impl FlydraFeatureDetector {
                 (packet, BackgroundAcquisitionState::NormalUpdates(state))
             }
         };
-        self.background_update_state = next_background_update_state;
-
         if let Some(frame) = saved_bg_image {
             if let BackgroundAcquisitionState::NormalUpdates(ref mut state) =
-                self.background_update_state
+                next_background_update_state
             {
-                state.background.start_bg_update(frame, &self.cfg)?;
+                if !self.gpu_update_background(&raw_im_full, frame, &mut state.background)? {
+                    state.background.start_bg_update(frame, &self.cfg)?;
+                }
             } else {
                 panic!("unreachable");
             }
         }
+        self.background_update_state = next_background_update_state;
 
         Ok((results, new_ufmf_state))
     }
@@ -968,6 +1261,35 @@ pub fn compute_mask_image(
     Ok(mask_image)
 }
 
+/// Mask all pixels within any of the `exclude_regions` polygons.
+pub fn apply_exclude_regions(
+    mask_image: &mut FastImageData<Chan1, u8>,
+    exclude_regions: &[http_video_streaming_types::PolygonParams],
+) -> Result<()> {
+    let mask_value = 255;
+    let size = *mask_image.size();
+    let m = nalgebra::geometry::Isometry::identity();
+    for polygon in exclude_regions.iter() {
+        if polygon.points.len() < 3 {
+            warn!("ignoring exclude region with fewer than 3 vertices");
+            continue;
+        }
+        let shape = parry_geom::mask_from_points(&polygon.points);
+        let mask_row_iter = mask_image.valid_row_iter_mut(&size)?;
+        for (row, mask_row) in mask_row_iter.enumerate() {
+            for (col, row_item) in mask_row.iter_mut().enumerate() {
+                let cur_pos = nalgebra::geometry::Point2::new(col as f64, row as f64);
+                use parry2d_f64::query::PointQuery;
+                if shape.distance_to_point(&m, &cur_pos, true) <= 0.0 {
+                    // inside polygon or on its edge
+                    *row_item = mask_value;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 #[test]
 fn test_mask_polygon() -> anyhow::Result<()> {
     let roi_sz = FastImageSize::new(12, 8);
@@ -1049,3 +1371,44 @@ fn test_mask_multiple_circles() -> anyhow::Result<()> {
     assert_eq!(mask, expected);
     Ok(())
 }
+
+#[test]
+fn test_mask_exclude_regions() -> anyhow::Result<()> {
+    let roi_sz = FastImageSize::new(12, 8);
+    let mut mask = compute_mask_image(&roi_sz, &Shape::Everything)?;
+    let exclude = vec![http_video_streaming_types::PolygonParams {
+        points: vec![(2.0, 2.0), (5.0, 2.0), (5.0, 4.0), (2.0, 4.0)],
+    }];
+    apply_exclude_regions(&mut mask, &exclude)?;
+    let expected = {
+        let mut full = FastImageData::<_, u8>::new(12, 8, 0)?;
+        for row in 2..5 {
+            for col in 2..6 {
+                full.pixel_slice_mut(row, col)[0] = 255;
+            }
+        }
+        full
+    };
+    assert_eq!(mask, expected);
+    Ok(())
+}
+
+#[test]
+fn test_gaussian_peak_offset() {
+    // Samples of a Gaussian with its peak at 0.3.
+    let samples: Vec<(f64, f64)> = (-2..=2)
+        .map(|k| {
+            let x = k as f64;
+            (x, 200.0 * (-(x - 0.3).powi(2) / 2.0).exp())
+        })
+        .collect();
+    let offset = gaussian_peak_offset(&samples);
+    assert!((offset - 0.3).abs() < 1e-6, "offset {offset}");
+
+    // Too few samples.
+    assert_eq!(gaussian_peak_offset(&samples[..2]), 0.0);
+
+    // No maximum.
+    let flat = [(-1.0, 10.0), (0.0, 10.0), (1.0, 10.0)];
+    assert_eq!(gaussian_peak_offset(&flat), 0.0);
+}
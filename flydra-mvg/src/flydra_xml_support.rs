@@ -13,8 +13,15 @@ pub struct FlydraReconstructor<R: RealField + serde::Serialize> {
     /// This is ignored when reading and not written.
     #[serde(default)]
     pub minimum_eccentricity: R,
+    /// Refractive index of water below the surface.
     #[serde(default)]
     pub water: Option<R>,
+    /// Height of the water surface, if not at z=0.
+    #[serde(default)]
+    pub water_surface_z: Option<R>,
+    /// Refractive index above the water surface, if not that of air.
+    #[serde(default)]
+    pub air_refractive_index: Option<R>,
     #[serde(default)]
     pub comment: Option<String>,
 }
@@ -86,6 +93,15 @@ where
     if let Some(ref w) = recon.water {
         v.push(format!("    <water>{}</water>", w));
     }
+    if let Some(ref z) = recon.water_surface_z {
+        v.push(format!("    <water_surface_z>{}</water_surface_z>", z));
+    }
+    if let Some(ref n) = recon.air_refractive_index {
+        v.push(format!(
+            "    <air_refractive_index>{}</air_refractive_index>",
+            n
+        ));
+    }
     if let Some(ref c) = recon.comment {
         v.push(format!("    <comment>{}</comment>", c));
     }
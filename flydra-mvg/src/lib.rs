@@ -6,7 +6,7 @@ use std::io::{Read, Write};
 
 extern crate log;
 
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use num_traits::{One, Zero};
 
@@ -32,7 +32,8 @@ pub mod flydra_xml_support;
 
 use crate::flydra_xml_support::{FlydraDistortionModel, SingleCameraCalibration};
 
-const AIR_REFRACTION: f64 = 1.0003;
+/// The default refractive index of air.
+pub const AIR_REFRACTION: f64 = 1.0003;
 
 pub use mvg::Result;
 
@@ -129,6 +130,43 @@ where
     }
 }
 
+// RefractiveBoundary -------------------------------------------------------
+
+/// A horizontal refractive boundary with air above and water below.
+///
+/// Anything below the boundary (with a z coordinate less than `z`) is under
+/// water.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(
+    deny_unknown_fields,
+    bound(deserialize = "R: RealField + Deserialize<'de>")
+)]
+pub struct RefractiveBoundary<R> {
+    /// Refractive index below the boundary, e.g. 1.333 for water.
+    pub n_water: R,
+    /// Refractive index above the boundary. Defaults to that of air.
+    #[serde(default = "default_n_air")]
+    pub n_air: R,
+    /// Height of the boundary. Defaults to 0.
+    #[serde(default = "num_traits::Zero::zero")]
+    pub z: R,
+}
+
+fn default_n_air<R: RealField>() -> R {
+    na::convert(AIR_REFRACTION)
+}
+
+impl<R: RealField> RefractiveBoundary<R> {
+    /// A boundary at z=0 with air above and refractive index `n_water` below.
+    pub fn water(n_water: R) -> Self {
+        Self {
+            n_water,
+            n_air: default_n_air(),
+            z: R::zero(),
+        }
+    }
+}
+
 // RayCamera -------------------------------------------------------
 
 /// defines operations with Ray type
@@ -189,7 +227,7 @@ impl<R: RealField + Copy + Default + serde::Serialize> RayCamera<R> for Camera<R
 /// ray based methods.
 #[derive(Clone, Debug)]
 pub struct MultiCamera<R: RealField + Copy + Default + serde::Serialize> {
-    water: Option<R>,
+    water: Option<RefractiveBoundary<R>>,
     name: String,
     cam: Camera<R>,
 }
@@ -232,18 +270,17 @@ impl<R: RealField + Copy + Default + serde::Serialize> MultiCamera<R> {
     pub fn project_3d_to_ray(&self, pt3d: &PointWorldFrame<R>) -> parry3d_f64::query::Ray {
         let camcenter = self.extrinsics().camcenter();
 
-        let dir: Vector3<R> = if self.water.is_some() && pt3d.coords[2] < na::convert(0.0) {
+        let dir: Vector3<R> = if let Some(water) = self.water.filter(|w| pt3d.coords[2] < w.z) {
             // this is tag "laksdfjasl".
-            let n1 = na::convert(AIR_REFRACTION);
-            let n2 = self.water.unwrap();
+            let n1 = water.n_air;
+            let n2 = water.n_water;
 
-            let camcenter_z0 =
-                Point3::from(Vector3::new(camcenter[0], camcenter[1], na::convert(0.0)));
+            let camcenter_z0 = Point3::from(Vector3::new(camcenter[0], camcenter[1], water.z));
             let shifted_pt = pt3d.coords - camcenter_z0; // origin under cam at surface. cam at (0,0,z).
             let theta = shifted_pt[1].atan2(shifted_pt[0]); // angles to points
             let r = (shifted_pt[0].powi(2) + shifted_pt[1].powi(2)).sqrt(); // horizontal dist
             let depth = -shifted_pt[2];
-            let height = camcenter[2];
+            let height = camcenter[2] - water.z;
 
             let water_roots_eps = na::convert(1e-5);
             let root_params =
@@ -381,11 +418,11 @@ impl<R: RealField + Copy + Default + serde::Serialize> MultiCamera<R> {
 #[derive(Clone, Debug)]
 pub struct FlydraMultiCameraSystem<R: RealField + Copy + serde::Serialize> {
     system: MultiCameraSystem<R>,
-    water: Option<R>,
+    water: Option<RefractiveBoundary<R>>,
 }
 
 impl<R: RealField + Copy + Default + serde::Serialize> FlydraMultiCameraSystem<R> {
-    pub fn from_system(system: MultiCameraSystem<R>, water: Option<R>) -> Self {
+    pub fn from_system(system: MultiCameraSystem<R>, water: Option<RefractiveBoundary<R>>) -> Self {
         FlydraMultiCameraSystem { system, water }
     }
 
@@ -393,6 +430,15 @@ impl<R: RealField + Copy + Default + serde::Serialize> FlydraMultiCameraSystem<R
         self.water.is_some()
     }
 
+    pub fn refractive_boundary(&self) -> Option<&RefractiveBoundary<R>> {
+        self.water.as_ref()
+    }
+
+    /// Replace the refractive boundary (or remove it with `None`).
+    pub fn set_refractive_boundary(&mut self, water: Option<RefractiveBoundary<R>>) {
+        self.water = water;
+    }
+
     pub fn to_system(self) -> MultiCameraSystem<R> {
         self.system
     }
@@ -401,7 +447,10 @@ impl<R: RealField + Copy + Default + serde::Serialize> FlydraMultiCameraSystem<R
         &self.system
     }
 
-    pub fn new(cams_by_name: BTreeMap<String, Camera<R>>, water: Option<R>) -> Self {
+    pub fn new(
+        cams_by_name: BTreeMap<String, Camera<R>>,
+        water: Option<RefractiveBoundary<R>>,
+    ) -> Self {
         let system = MultiCameraSystem::new(cams_by_name);
 
         FlydraMultiCameraSystem { system, water }
@@ -469,11 +518,11 @@ impl<R: RealField + Copy + Default + serde::Serialize> FlydraMultiCameraSystem<R
         use crate::PointWorldFrameMaybeWithSumReprojError::*;
 
         match self.water {
-            Some(n2) => {
+            Some(water) => {
                 // TODO: would it be possible to have a 3d reconstruction with
-                // lower reprojection error when it was z<0 but with the air
-                // based calculation? This would seem problematic...
-                let opt_water_3d_pt = match self.find3d_water(points, n2) {
+                // lower reprojection error when it was under water but with
+                // the air based calculation? This would seem problematic...
+                let opt_water_3d_pt = match self.find3d_water(points, &water) {
                     Ok(water_3d_pt) => Some(water_3d_pt),
                     Err(MvgError::CamGeomError { .. }) => None,
                     Err(e) => {
@@ -532,10 +581,12 @@ impl<R: RealField + Copy + Default + serde::Serialize> FlydraMultiCameraSystem<R
     fn find3d_water(
         &self,
         points: &[(String, UndistortedPixel<R>)],
-        n2: R,
+        water: &RefractiveBoundary<R>,
     ) -> Result<PointWorldFrame<R>> {
         use cam_geom::{Ray, WorldFrame};
+        // The half space is defined in a frame with the surface at z=0.
         let z0 = parry3d_f64::shape::HalfSpace::new(Vector3::z_axis());
+        let surface_offset: Vector3<f64> = (&Vector3::new(R::zero(), R::zero(), water.z)).to_f64();
 
         let mut rays: Vec<Ray<WorldFrame, _>> = Vec::with_capacity(points.len());
 
@@ -544,9 +595,11 @@ impl<R: RealField + Copy + Default + serde::Serialize> FlydraMultiCameraSystem<R
             let air_ray = cam.project_pixel_to_ray(xy);
             let solid = false; // will intersect either side of plane
 
+            let local_air_ray =
+                parry3d_f64::query::Ray::new(air_ray.origin - surface_offset, air_ray.dir);
             let opt_surface_pt_toi: Option<f64> = parry3d_f64::query::RayCast::cast_local_ray(
                 &z0,
-                &air_ray,
+                &local_air_ray,
                 f64::max_value().unwrap(),
                 solid,
             );
@@ -558,10 +611,10 @@ impl<R: RealField + Copy + Default + serde::Serialize> FlydraMultiCameraSystem<R
                 let toi: R = na::convert(toi);
                 let surface_pt: Point3<R> = air_ray_origin + air_ray_dir * toi;
 
-                // closest point to camera on water surface, assumes water at z==0
+                // closest point to camera on water surface
                 let camcenter = &air_ray_origin;
                 let camcenter_z0: Point3<R> =
-                    Point3::from(Vector3::new(camcenter[0], camcenter[1], na::convert(0.0)));
+                    Point3::from(Vector3::new(camcenter[0], camcenter[1], water.z));
 
                 let surface_pt_cam = surface_pt - camcenter_z0;
 
@@ -570,11 +623,10 @@ impl<R: RealField + Copy + Default + serde::Serialize> FlydraMultiCameraSystem<R
                 let x = surface_pt_cam[0];
                 let pt_angle = y.atan2(x);
                 let pt_horiz_dist = (x * x + y * y).sqrt(); // horizontal distance from camera to water surface
-                let theta_air = pt_horiz_dist.atan2(camcenter[2]);
+                let theta_air = pt_horiz_dist.atan2(camcenter[2] - water.z);
 
                 // sin(theta_water)/sin(theta_air) = sin(n_air)/sin(n_water)
-                let n_air = na::convert(AIR_REFRACTION);
-                let sin_theta_water = theta_air.sin() * n_air / n2;
+                let sin_theta_water = theta_air.sin() * water.n_air / water.n_water;
                 let theta_water = sin_theta_water.asin();
                 let horiz_dist_at_depth_1 = theta_water.tan();
                 let horiz_dist_cam_depth_1 = horiz_dist_at_depth_1 + pt_horiz_dist; // total horizontal distance
@@ -625,7 +677,16 @@ impl<R: RealField + Copy + Default + serde::Serialize> FlydraMultiCameraSystem<R
     pub fn from_flydra_reconstructor(
         recon: &flydra_xml_support::FlydraReconstructor<R>,
     ) -> Result<Self> {
-        let water = recon.water;
+        let water = recon.water.map(|n_water| {
+            let mut water = RefractiveBoundary::water(n_water);
+            if let Some(n_air) = recon.air_refractive_index {
+                water.n_air = n_air;
+            }
+            if let Some(z) = recon.water_surface_z {
+                water.z = z;
+            }
+            water
+        });
         let mut cams = BTreeMap::new();
         for flydra_cam in recon.cameras.iter() {
             let (name, cam) = Camera::from_flydra(flydra_cam)?;
@@ -647,12 +708,17 @@ impl<R: RealField + Copy + Default + serde::Serialize> FlydraMultiCameraSystem<R
             })
             .collect();
         let cameras = cameras?;
-        let water = self.water;
+        // Only save the refractive index of air and the height of the
+        // surface if they differ from the defaults so that the XML remains
+        // readable by programs which do not support them.
+        let default_n_air: R = default_n_air();
 
         Ok(flydra_xml_support::FlydraReconstructor {
             cameras,
             comment: self.system.comment().cloned(),
-            water,
+            water: self.water.map(|w| w.n_water),
+            water_surface_z: self.water.map(|w| w.z).filter(|z| !z.is_zero()),
+            air_refractive_index: self.water.map(|w| w.n_air).filter(|n| *n != default_n_air),
             minimum_eccentricity: na::convert(0.0),
         })
     }
@@ -1,7 +1,7 @@
 #[macro_use]
 extern crate approx;
 
-use flydra_mvg::FlydraMultiCameraSystem;
+use flydra_mvg::{FlydraMultiCameraSystem, RefractiveBoundary};
 
 use nalgebra::geometry::{Point2, Point3};
 
@@ -113,6 +113,77 @@ fn test_distortion_above_water_flydra_xml() {
     assert_relative_eq!(pt.coords, pt_actual.coords, max_relative = 1e-5);
 }
 
+#[test]
+fn test_shifted_water_surface() {
+    // Moving the cameras and the water surface up by the same amount must
+    // move the reconstructed points by this amount.
+    let buf = include_str!("flydra/sample_calibration_water.xml");
+    let cams =
+        FlydraMultiCameraSystem::<f64>::from_flydra_xml(buf.as_bytes()).expect("from_flydra_xml");
+    let orig_water = *cams.refractive_boundary().unwrap();
+    assert_eq!(orig_water.z, 0.0);
+
+    let dz = nalgebra::Vector3::new(0.0, 0.0, 0.2);
+    let shifted_cams = cams
+        .system()
+        .cams_by_name()
+        .iter()
+        .map(|(name, cam)| {
+            let extrinsics = cam_geom::ExtrinsicParameters::from_rotation_and_camcenter(
+                nalgebra::UnitQuaternion::from_rotation_matrix(cam.extrinsics().rotation()),
+                cam.extrinsics().camcenter() + dz,
+            );
+            let shifted = mvg::Camera::new(
+                cam.width(),
+                cam.height(),
+                extrinsics,
+                cam.intrinsics().clone(),
+            )
+            .unwrap();
+            (name.clone(), shifted)
+        })
+        .collect();
+    let water = RefractiveBoundary {
+        n_water: 1.4,
+        n_air: 1.0,
+        z: 0.2,
+    };
+    let shifted = FlydraMultiCameraSystem::new(shifted_cams, Some(water));
+    let unshifted = FlydraMultiCameraSystem::from_system(
+        cams.system().clone(),
+        Some(RefractiveBoundary { z: 0.0, ..water }),
+    );
+
+    for coords in [
+        Point3::new(0.01, 0.02, -0.03),
+        Point3::new(0.01, 0.02, 0.03),
+    ] {
+        let pt = PointWorldFrame { coords };
+        let shifted_pt = PointWorldFrame {
+            coords: coords + dz,
+        };
+        let mut points = Vec::new();
+        for cam in shifted.cameras() {
+            let expected = unshifted
+                .cam_by_name(cam.name())
+                .unwrap()
+                .project_3d_to_distorted_pixel(&pt);
+            let actual = cam.project_3d_to_distorted_pixel(&shifted_pt);
+            assert_relative_eq!(actual.coords, expected.coords, max_relative = 1e-6);
+            points.push((cam.name().to_string(), actual));
+        }
+        let pt_actual = shifted.find3d_distorted(&points).unwrap().point();
+        assert_relative_eq!(shifted_pt.coords, pt_actual.coords, max_relative = 1e-5);
+    }
+
+    // The surface height and the refractive index of air are saved in XML.
+    let mut xml: Vec<u8> = Vec::new();
+    shifted.to_flydra_xml(&mut xml).expect("to_flydra_xml");
+    let reloaded =
+        FlydraMultiCameraSystem::<f64>::from_flydra_xml(xml.as_slice()).expect("from_flydra_xml");
+    assert_eq!(reloaded.refractive_boundary(), Some(&water));
+}
+
 #[test]
 fn test_flydra_xml_writing() {
     for input_xml in [
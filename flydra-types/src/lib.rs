@@ -11,7 +11,9 @@ extern crate bitflags;
 extern crate static_assertions;
 
 use ordered_float::NotNan;
-use rust_cam_bui_types::{ClockModel, RecordingPath};
+use rust_cam_bui_types::{
+    ClockModel, DiskSpaceStatus, OutputLatency, RecordingPath, SelfTestReport,
+};
 use std::net::SocketAddr;
 
 use serde::{Deserialize, Deserializer, Serialize};
@@ -19,13 +21,15 @@ use serde::{Deserialize, Deserializer, Serialize};
 use bui_backend_session_types::AccessToken;
 use withkey::WithKey;
 
+pub use provenance::Provenance;
+
 pub const DEFAULT_MODEL_SERVER_ADDR: &str = "0.0.0.0:8397";
 
 // These are the filenames saved during recording. --------------------
 //
 // Any changes to these names, including additions and removes, should update
 // BraidMetadataSchemaTag.
-pub const BRAID_SCHEMA: u16 = 3; // BraidMetadataSchemaTag
+pub const BRAID_SCHEMA: u16 = 5; // BraidMetadataSchemaTag
 
 // CSV files. (These may also exist as .csv.gz)
 pub const KALMAN_ESTIMATES_CSV_FNAME: &str = "kalman_estimates.csv";
@@ -35,6 +39,8 @@ pub const CAM_INFO_CSV_FNAME: &str = "cam_info.csv";
 pub const TRIGGER_CLOCK_INFO_CSV_FNAME: &str = "trigger_clock_info.csv";
 pub const EXPERIMENT_INFO_CSV_FNAME: &str = "experiment_info.csv";
 pub const TEXTLOG_CSV_FNAME: &str = "textlog.csv";
+pub const LATENCY_BUDGET_CSV_FNAME: &str = "latency_budget.csv";
+pub const DATA2D_APPEARANCE_CSV_FNAME: &str = "data2d_appearance.csv";
 
 // Other files
 pub const CALIBRATION_XML_FNAME: &str = "calibration.xml";
@@ -145,6 +151,134 @@ pub struct FlydraRawUdpPoint {
     pub cur_val: u8,
     pub mean_val: f64,
     pub sumsqf_val: f64,
+    /// The appearance of the detection, if computed by the camera.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appearance: Option<Appearance>,
+}
+
+/// Number of bins in [Appearance::histogram].
+pub const APPEARANCE_HISTOGRAM_BINS: usize = 8;
+
+/// The appearance of a detection in the image patch around it.
+///
+/// This is used in data association to keep the identity of objects which
+/// come close to each other. See [TrackingParams::appearance_weight].
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Appearance {
+    /// The mean pixel intensity of the patch.
+    pub mean_intensity: f64,
+    /// The histogram of pixel intensities of the patch.
+    ///
+    /// The bins divide the range 0-255 evenly. The values are fractions of the
+    /// pixels of the patch and thus sum to one.
+    pub histogram: [f64; APPEARANCE_HISTOGRAM_BINS],
+}
+
+impl Appearance {
+    /// Compute the appearance from the pixel intensities of a patch.
+    ///
+    /// Returns `None` if there are no pixels.
+    pub fn from_pixels<I: IntoIterator<Item = u8>>(pixels: I) -> Option<Self> {
+        let mut histogram = [0.0; APPEARANCE_HISTOGRAM_BINS];
+        let mut sum = 0.0;
+        let mut n = 0usize;
+        for value in pixels {
+            histogram[value as usize * APPEARANCE_HISTOGRAM_BINS / 256] += 1.0;
+            sum += value as f64;
+            n += 1;
+        }
+        if n == 0 {
+            return None;
+        }
+        let n = n as f64;
+        for bin in histogram.iter_mut() {
+            *bin /= n;
+        }
+        Some(Self {
+            mean_intensity: sum / n,
+            histogram,
+        })
+    }
+
+    /// Dissimilarity between two appearances in the range 0 (identical) to 1.
+    ///
+    /// This is the mean of the total variation distance of the histograms and
+    /// the difference of mean intensity relative to the full range.
+    pub fn distance(&self, other: &Self) -> f64 {
+        let hist_dist = self
+            .histogram
+            .iter()
+            .zip(other.histogram.iter())
+            .map(|(a, b)| (a - b).abs())
+            .sum::<f64>()
+            / 2.0;
+        let intensity_dist = (self.mean_intensity - other.mean_intensity).abs() / 255.0;
+        ((hist_dist + intensity_dist) / 2.0).clamp(0.0, 1.0)
+    }
+}
+
+/// The appearance of a detection, as saved in `.braidz` files.
+///
+/// Rows are only saved for detections with an [Appearance]. They are linked to
+/// the rows of [Data2dDistortedRow] by `camn`, `frame` and `frame_pt_idx`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Data2dAppearanceRow {
+    /// The number of the camera.
+    pub camn: CamNum,
+    /// The synchronized frame number.
+    pub frame: i64,
+    /// The index of this particular detection within a given frame.
+    pub frame_pt_idx: u8,
+    /// The area of the detection, in camera pixels^2.
+    pub area: f32,
+    /// See [Appearance::mean_intensity].
+    pub mean_intensity: f32,
+    /// See [Appearance::histogram].
+    pub hist0: f32,
+    pub hist1: f32,
+    pub hist2: f32,
+    pub hist3: f32,
+    pub hist4: f32,
+    pub hist5: f32,
+    pub hist6: f32,
+    pub hist7: f32,
+}
+
+impl Data2dAppearanceRow {
+    pub fn new(camn: CamNum, frame: i64, frame_pt_idx: u8, area: f64, app: &Appearance) -> Self {
+        let h = app.histogram.map(|v| v as f32);
+        Self {
+            camn,
+            frame,
+            frame_pt_idx,
+            area: area as f32,
+            mean_intensity: app.mean_intensity as f32,
+            hist0: h[0],
+            hist1: h[1],
+            hist2: h[2],
+            hist3: h[3],
+            hist4: h[4],
+            hist5: h[5],
+            hist6: h[6],
+            hist7: h[7],
+        }
+    }
+}
+
+#[test]
+fn test_appearance() {
+    let dark = Appearance::from_pixels([0, 10, 20, 30]).unwrap();
+    assert_eq!(dark.mean_intensity, 15.0);
+    assert_eq!(dark.histogram[0], 1.0);
+    let bright = Appearance::from_pixels([255, 255, 250, 240]).unwrap();
+    assert_eq!(bright.histogram[7], 1.0);
+    let mixed = Appearance::from_pixels([0, 10, 255, 255]).unwrap();
+
+    assert_eq!(dark.distance(&dark), 0.0);
+    assert!(dark.distance(&bright) > 0.9);
+    assert!(dark.distance(&mixed) < dark.distance(&bright));
+    assert_eq!(dark.distance(&mixed), mixed.distance(&dark));
+    assert!(Appearance::from_pixels([]).is_none());
 }
 
 /// The original camera name from the driver.
@@ -170,6 +304,8 @@ pub mod braid_http {
     // URL paths on Braid HTTP server.
     pub const REMOTE_CAMERA_INFO_PATH: &str = "remote-camera-info";
     pub const CAM_PROXY_PATH: &str = "cam-proxy";
+    /// Prefix of the JSON API for experiment control.
+    pub const API_PATH: &str = "api/v1";
 
     /// Encode camera name, potentially with slashes or spaces, to be a single
     /// URL path component.
@@ -301,6 +437,12 @@ pub struct BraidCameraConfig {
     ///
     /// Can contain shell variables such as `~`, `$A`, or `${B}`.
     pub camera_settings_filename: Option<std::path::PathBuf>,
+    /// The user set stored on the camera, e.g. `UserSet1`, loaded when the
+    /// camera is opened.
+    ///
+    /// The camera settings file and the other settings are applied afterwards.
+    #[serde(default)]
+    pub user_set: Option<String>,
     /// The pixel format to use.
     pub pixel_format: Option<String>,
     /// Configuration for detecting points.
@@ -315,6 +457,43 @@ pub struct BraidCameraConfig {
     /// The interval at which the current image should be sent, in milliseconds.
     #[serde(default = "default_send_current_image_interval_msec")]
     pub send_current_image_interval_msec: u64,
+    /// Number of frames buffered by the camera backend.
+    ///
+    /// If not set, the camera backend defaults are used. Currently only
+    /// supported with `start_backend = "vimba"`.
+    #[serde(default)]
+    pub frame_buffer_config: Option<ci2_types::FrameBufferConfig>,
+    /// In-camera image post-processing, such as noise suppression and
+    /// sharpening.
+    ///
+    /// If not set, the camera settings are not changed. Currently only
+    /// supported with `start_backend = "vimba"` and cameras which have these
+    /// features.
+    #[serde(default)]
+    pub post_processing: Option<ci2_types::PostProcessingConfig>,
+    /// Directory with the configuration profiles of Strand Camera, saved as
+    /// `<name>.toml`.
+    ///
+    /// Can contain shell variables such as `~`, `$A`, or `${B}`.
+    #[serde(default)]
+    pub profile_dir: Option<std::path::PathBuf>,
+    /// The configuration profile applied when the camera starts.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Environment variables set for the launched Strand Camera process.
+    ///
+    /// Not used with `start_backend = "remote"`.
+    #[serde(default)]
+    pub env: std::collections::BTreeMap<String, String>,
+    /// Working directory of the launched Strand Camera process.
+    ///
+    /// Can contain shell variables such as `~`, `$A`, or `${B}`. Not used with
+    /// `start_backend = "remote"`.
+    #[serde(default)]
+    pub working_dir: Option<std::path::PathBuf>,
+    /// Additional command line arguments for Strand Camera.
+    #[serde(default)]
+    pub extra_args: Vec<String>,
 
     /// Deprecated, useless old config option (not removed for backwards compatibility)
     #[serde(
@@ -366,6 +545,7 @@ impl BraidCameraConfig {
         Self {
             name,
             camera_settings_filename: None,
+            user_set: None,
             pixel_format: None,
             point_detection_config: flydra_pt_detect_cfg::default_absdiff(),
             _raise_grab_thread_priority: Default::default(),
@@ -374,15 +554,59 @@ impl BraidCameraConfig {
                 DEFAULT_ACQUISITION_DURATION_ALLOWED_IMPRECISION_MSEC,
             http_server_addr: None,
             send_current_image_interval_msec: default_send_current_image_interval_msec(),
+            frame_buffer_config: None,
+            post_processing: None,
+            profile_dir: None,
+            profile: None,
+            env: Default::default(),
+            working_dir: None,
+            extra_args: Vec::new(),
+        }
+    }
+}
+
+/// Configuration of the pool of NVENC encoder sessions.
+///
+/// The cameras are balanced across the CUDA devices, so that each device
+/// encodes the MP4 files of a similar number of cameras. If encoding fails
+/// repeatedly on a device, the camera is reassigned to another device.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NvencPoolConfig {
+    /// The indices of the CUDA devices to use. If empty, all devices are used.
+    #[serde(default)]
+    pub devices: Vec<u32>,
+    /// The number of failed encoding sessions on a device after which the
+    /// camera is reassigned to another device.
+    #[serde(default = "default_nvenc_pool_max_failures")]
+    pub max_failures: u32,
+}
+
+impl Default for NvencPoolConfig {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+            max_failures: default_nvenc_pool_max_failures(),
         }
     }
 }
 
+const fn default_nvenc_pool_max_failures() -> u32 {
+    1
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct PerCamSaveData {
     pub current_image_png: PngImageData,
     pub cam_settings_data: Option<UpdateCamSettings>,
     pub feature_detect_settings: Option<UpdateFeatureDetectSettings>,
+    /// True if `feature_detect_settings` differ from the
+    /// `point_detection_config` of the camera in the Braid configuration, e.g.
+    /// because they were changed on the camera host.
+    #[serde(default)]
+    pub feature_detect_settings_overridden: bool,
+    /// The program which acquires images from the camera.
+    pub provenance: Option<Provenance>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -398,6 +622,12 @@ pub struct RegisterNewCamera {
     /// The period of the periodic signal generator in the camera.
     /// This is used for PTP-based synchronization.
     pub camera_periodic_signal_period_usec: Option<f64>,
+    /// The program which acquires images from the camera.
+    #[serde(default)]
+    pub provenance: Option<Provenance>,
+    /// The feature detection settings in use when the camera started.
+    #[serde(default)]
+    pub feature_detect_settings: Option<UpdateFeatureDetectSettings>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -475,6 +705,93 @@ pub struct BraidHttpApiSharedState {
     pub model_server_addr: Option<SocketAddr>,
     pub flydra_app_name: String,
     pub all_expected_cameras_are_synced: bool,
+    /// Free space on the disk to which data is saved.
+    pub disk_space: Option<DiskSpaceStatus>,
+    /// Set when saving was stopped because the disk was almost full.
+    pub disk_space_error: Option<String>,
+    /// The startup self-tests of the cameras which run one, by camera name.
+    pub self_tests: std::collections::BTreeMap<String, SelfTestReport>,
+    /// The most recent heartbeat of each camera, by camera name.
+    pub camera_health: std::collections::BTreeMap<String, CamHeartbeat>,
+    /// The most recent automatic resynchronization of the cameras.
+    pub last_resync: Option<ResyncEvent>,
+}
+
+/// An automatic resynchronization of the cameras.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ResyncEvent {
+    /// Time at which the resynchronization started.
+    pub started: chrono::DateTime<chrono::Utc>,
+    /// Why the cameras were resynchronized.
+    pub reason: String,
+}
+
+/// Health of a camera, sent periodically from Strand Camera to Braid.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct CamHeartbeat {
+    /// Time at which the heartbeat was sent.
+    pub host_timestamp: chrono::DateTime<chrono::Utc>,
+    /// Measured frame rate.
+    pub measured_fps: f32,
+    /// Frames dropped because processing could not keep up, since startup.
+    pub dropped_frames: u64,
+    /// Largest time from acquisition until processing finished, over the
+    /// frames since the previous heartbeat.
+    ///
+    /// `None` if no frames were processed.
+    pub max_latency_msec: Option<f64>,
+    /// Detections rejected by the detection filter of the camera, since
+    /// startup.
+    #[serde(default)]
+    pub filtered_detections: u64,
+    /// The time from exposure until frames were sent on each output of the
+    /// camera.
+    #[serde(default)]
+    pub output_latency: OutputLatency,
+    pub is_recording_mp4: bool,
+    pub is_recording_fmf: bool,
+    pub is_recording_ufmf: bool,
+    pub is_saving_csv: bool,
+}
+
+impl CamHeartbeat {
+    /// A one-line description for display.
+    pub fn summary(&self) -> String {
+        let latency = match self.max_latency_msec {
+            Some(msec) => format!("{msec:.1} ms"),
+            None => "-".to_string(),
+        };
+        let recording: Vec<&str> = [
+            (self.is_recording_mp4, "MP4"),
+            (self.is_recording_fmf, "FMF"),
+            (self.is_recording_ufmf, "UFMF"),
+            (self.is_saving_csv, "CSV"),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .map(|(_, name)| *name)
+        .collect();
+        let recording = if recording.is_empty() {
+            "not recording".to_string()
+        } else {
+            format!("recording {}", recording.join(", "))
+        };
+        let filtered = if self.filtered_detections > 0 {
+            format!(", {} filtered detections", self.filtered_detections)
+        } else {
+            String::new()
+        };
+        let output_latency = self.output_latency.summary();
+        let output_latency = if output_latency.is_empty() {
+            String::new()
+        } else {
+            format!(" (outputs: {output_latency})")
+        };
+        format!(
+            "{:.1} fps, {} dropped frames, max. latency {}{}{}, {}",
+            self.measured_fps, self.dropped_frames, latency, output_latency, filtered, recording
+        )
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, Default)]
@@ -693,6 +1010,26 @@ pub fn expand_unspecified_addr(addr: &SocketAddr) -> std::io::Result<Vec<Specifi
     }
 }
 
+/// Latency of one stage of the processing pipeline.
+///
+/// This is returned by the `GET /api/v1/latency` endpoint of the Braid JSON
+/// API and periodically saved in the latency budget table of the `.braidz`
+/// file.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct LatencyStageStats {
+    // changes to this struct should update BraidMetadataSchemaTag
+    /// Time at which the statistics were computed.
+    pub mainbrain_timestamp: f64,
+    /// The name of the stage, e.g. `detection`.
+    pub stage: String,
+    /// Number of samples.
+    pub n: usize,
+    pub p50_msec: f64,
+    pub p90_msec: f64,
+    pub p99_msec: f64,
+    pub max_msec: f64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TextlogRow {
     // changes to this struct should update BraidMetadataSchemaTag
@@ -774,6 +1111,16 @@ pub struct TrackingParams {
     /// This is MiniArenaConfig::NoMiniArena if no mini arena is in use.
     #[serde(skip_serializing_if = "MiniArenaConfig::is_none", default)]
     pub mini_arena_config: MiniArenaConfig,
+    /// How strongly the appearance of detections is used in data association.
+    ///
+    /// The likelihood of an observation is multiplied by
+    /// `exp(-appearance_weight * d)`, where `d` is the [Appearance::distance]
+    /// between the detection and the recent appearance of the object. This
+    /// only has an effect if the cameras compute the appearance
+    /// (`compute_appearance` in the feature detection settings). 0.0, the
+    /// default, disables the appearance term.
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub appearance_weight: f64,
 }
 
 pub struct MiniArenaLocator {
@@ -956,6 +1303,10 @@ impl Iterator for XYGridIter {
     }
 }
 
+fn is_zero(val: &f64) -> bool {
+    *val == 0.0
+}
+
 fn default_num_observations_to_visibility() -> u8 {
     // This number should suppress spurious trajectory births but not wait too
     // long before notifying listeners.
@@ -975,6 +1326,7 @@ pub fn default_tracking_params_full_3d() -> TrackingParams {
         hypothesis_test_params: Some(make_hypothesis_test_full3d_default()),
         num_observations_to_visibility: default_num_observations_to_visibility(),
         mini_arena_config: MiniArenaConfig::NoMiniArena,
+        appearance_weight: 0.0,
     }
 }
 
@@ -989,6 +1341,7 @@ pub fn default_tracking_params_flat_3d() -> TrackingParams {
         hypothesis_test_params: None,
         num_observations_to_visibility: 10,
         mini_arena_config: MiniArenaConfig::NoMiniArena,
+        appearance_weight: 0.0,
     }
 }
 
@@ -1016,6 +1369,75 @@ pub struct CamInfo {
     pub recent_stats: RecentStats,
 }
 
+/// Response of the `GET /api/v1/status` endpoint of the Braid JSON API.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct BraidApiStatus {
+    /// The `.braid` directory being saved (and later zipped to a `.braidz`
+    /// file), if saving.
+    pub saving_braidz: Option<String>,
+    /// Whether the cameras are saving MP4 files.
+    pub saving_mp4: bool,
+    /// Whether all cameras in the configuration are connected and
+    /// synchronized.
+    pub all_expected_cameras_are_synced: bool,
+    /// Set when saving was stopped because the disk was almost full.
+    pub disk_space_error: Option<String>,
+    pub cameras: Vec<BraidApiCamera>,
+}
+
+/// A connected camera, as reported by the Braid JSON API.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct BraidApiCamera {
+    pub name: String,
+    pub synchronized: bool,
+    /// Frames received since the previous statistics update.
+    pub frames_collected: usize,
+    /// Points detected since the previous statistics update.
+    pub points_detected: usize,
+    /// The most recent heartbeat of the camera, if any was received.
+    pub health: Option<CamHeartbeat>,
+}
+
+impl From<&CamInfo> for BraidApiCamera {
+    fn from(cam: &CamInfo) -> Self {
+        Self {
+            name: cam.name.as_str().to_string(),
+            synchronized: cam.state.is_synchronized(),
+            frames_collected: cam.recent_stats.frames_collected,
+            points_detected: cam.recent_stats.points_detected,
+            health: None,
+        }
+    }
+}
+
+/// Body of the `POST /api/v1/recording` endpoint of the Braid JSON API.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct BraidApiRecording {
+    /// Start (`true`) or stop (`false`) saving the `.braidz` file.
+    pub save_braidz: bool,
+}
+
+/// Body of the `POST /api/v1/experiment-info` endpoint of the Braid JSON API.
+///
+/// Both fields are optional. They are saved in the `.braidz` file currently
+/// being written and ignored if not saving.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct BraidApiExperimentInfo {
+    /// Set the uuid in the experiment_info table.
+    #[serde(default)]
+    pub uuid: Option<String>,
+    /// Add a line to the textlog table.
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+/// Body of the `POST /api/v1/profile` endpoint of the Braid JSON API.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct BraidApiProfile {
+    /// The name of the configuration profile applied by all cameras.
+    pub name: String,
+}
+
 /// Messages to Braid
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum BraidHttpApiCallback {
@@ -1043,6 +1465,13 @@ pub enum BraidHttpApiCallback {
     SetPostTriggerBufferSize(usize),
     /// Initiate MKV recording using post trigger
     PostTriggerMp4Recording,
+    /// Called from strand-cam to update the state of its startup self-test
+    UpdateSelfTest(PerCam<SelfTestReport>),
+    /// Called from strand-cam to add a line to the textlog table of the
+    /// `.braidz` file being saved
+    AddTextlog(PerCam<String>),
+    /// Called periodically from strand-cam to report the camera health
+    Heartbeat(PerCam<CamHeartbeat>),
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -1074,6 +1503,11 @@ pub struct FlydraRawUdpPacket {
     pub done_camnode_processing: f64,
     /// this will always be 0.0 for flydra1 custom serialized packets
     pub preprocess_stamp: f64,
+    /// time at which the packet was sent to the mainbrain
+    ///
+    /// This is 0.0 if not known, e.g. for packets from older versions.
+    #[serde(default)]
+    pub packet_sent_stamp: f64,
     /// this will always be 0 for flydra1 custom serialized packets
     pub image_processing_steps: ImageProcessingSteps,
     pub points: Vec<FlydraRawUdpPoint>,
@@ -1185,6 +1619,17 @@ pub struct FakeSyncConfig {
     pub framerate: f64,
 }
 
+/// Configuration of synchronization by a master camera.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CameraMasterConfig {
+    /// The name of the master camera. Its exposure output must trigger the
+    /// other cameras.
+    pub master_camera: String,
+    /// The frame rate of the master camera.
+    pub framerate: f64,
+}
+
 impl Default for FakeSyncConfig {
     fn default() -> Self {
         Self { framerate: 95.0 }
@@ -1203,6 +1648,9 @@ pub enum TriggerType {
     DeviceTimestamp,
     /// Cameras are not synchronized, but we pretend they are.
     FakeSync(FakeSyncConfig),
+    /// Cameras are synchronized by the exposure output of a master camera
+    /// triggering the other cameras.
+    CameraMaster(CameraMasterConfig),
 }
 
 impl Default for TriggerType {
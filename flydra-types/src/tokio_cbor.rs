@@ -140,6 +140,7 @@ fn make_test_packet(framenumber: i32) -> FlydraRawUdpPacket {
         n_frames_skipped,
         done_camnode_processing: 0.0,
         preprocess_stamp: 0.0,
+        packet_sent_stamp: 0.0,
         image_processing_steps: ImageProcessingSteps::empty(),
         points,
     }
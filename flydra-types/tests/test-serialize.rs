@@ -32,6 +32,7 @@ fn make_test_packet() -> FlydraRawUdpPacket {
         n_frames_skipped,
         done_camnode_processing: 0.0,
         preprocess_stamp: 0.0,
+        packet_sent_stamp: 0.0,
         image_processing_steps: ImageProcessingSteps::empty(),
         points,
     }
@@ -46,6 +47,10 @@ fn make_test_point() -> FlydraRawUdpPoint {
         cur_val: 13,
         mean_val: 12345.0,
         sumsqf_val: 55.5,
+        appearance: Some(flydra_types::Appearance {
+            mean_intensity: 42.0,
+            histogram: [0.5, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+        }),
     }
 }
 
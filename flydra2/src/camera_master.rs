@@ -0,0 +1,198 @@
+//! Synchronization of cameras triggered by a master camera.
+//!
+//! With [flydra_types::TriggerType::CameraMaster], the master camera runs
+//! freely and its exposure (strobe) output triggers the other cameras, the
+//! slaves. All cameras thus count the same frames, and only the frame numbers
+//! at which they started differ. The master camera is synchronized as with fake
+//! synchronization: its first frame after synchronization starts gets frame
+//! number [crate::TRIGGERBOX_FIRST_PULSE]. A slave camera is synchronized by
+//! matching its frames to the master frames received at the same time, within
+//! half a frame period. Once enough frames are matched with the same offset,
+//! that offset is used for all frames of the slave.
+//!
+//! A frame is only matched once a master frame received more than half a frame
+//! period later is known, so the order in which frames of the cameras arrive
+//! does not matter. If the frames of a slave do not consistently match the
+//! master frames, the slave is likely not wired to the exposure output of the
+//! master, or not configured for external triggering. This is also checked
+//! after the slave is synchronized.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use flydra_types::RawCamName;
+
+/// Number of frames of a slave matched to determine its offset.
+const N_MATCHES: usize = 20;
+/// Number of frames of a synchronized slave matched to check its offset.
+const N_CHECK: usize = 1000;
+/// Minimum fraction of matched frames with the same offset.
+const MIN_AGREEMENT: f64 = 0.8;
+/// Number of recent master frames kept for matching.
+const MAX_MASTER_FRAMES: usize = 1000;
+/// Maximum number of frames of a slave waiting to be matched.
+const MAX_PENDING: usize = 1000;
+
+/// The result of adding a frame of a slave camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SlaveStatus {
+    /// Not enough frames are matched yet.
+    Pending,
+    /// The frames of the slave match the master with this frame offset, which
+    /// is the slave frame number minus the synchronized frame number.
+    Matched(u64),
+    /// The frames of the slave do not consistently match the master. Only this
+    /// fraction of the matched frames had the same offset.
+    NotTriggered { agreement: f64 },
+}
+
+#[derive(Debug, Default)]
+struct SlaveFrames {
+    /// Frames not yet matched: camera frame number and time received.
+    pending: VecDeque<(u64, f64)>,
+    /// The offsets of matched frames, or `None` if no master frame was received
+    /// at the same time.
+    offsets: Vec<Option<u64>>,
+}
+
+/// Matches frames of slave cameras to frames of the master camera.
+#[derive(Debug)]
+pub(crate) struct MasterSync {
+    half_period: f64,
+    /// Recent frames of the master: synchronized frame number and time
+    /// received, in the order received.
+    master_frames: VecDeque<(u64, f64)>,
+    slaves: BTreeMap<RawCamName, SlaveFrames>,
+}
+
+impl MasterSync {
+    pub(crate) fn new(framerate: f64) -> Self {
+        Self {
+            half_period: 0.5 / framerate,
+            master_frames: VecDeque::new(),
+            slaves: BTreeMap::new(),
+        }
+    }
+
+    /// Forget all frames, e.g. when the cameras are synchronized again.
+    pub(crate) fn reset(&mut self) {
+        self.master_frames.clear();
+        self.slaves.clear();
+    }
+
+    /// Add a synchronized frame of the master camera, received at `received`
+    /// (in seconds).
+    pub(crate) fn push_master(&mut self, synced_frame: u64, received: f64) {
+        if self.master_frames.len() >= MAX_MASTER_FRAMES {
+            self.master_frames.pop_front();
+        }
+        self.master_frames.push_back((synced_frame, received));
+    }
+
+    /// Add a frame of a slave camera, received at `received` (in seconds).
+    ///
+    /// `frame0` is the frame offset if the slave is already synchronized, in
+    /// which case its frames are checked against this offset.
+    pub(crate) fn push_slave(
+        &mut self,
+        raw_cam_name: &RawCamName,
+        cam_frame: u64,
+        received: f64,
+        frame0: Option<u64>,
+    ) -> SlaveStatus {
+        let slave = self.slaves.entry(raw_cam_name.clone()).or_default();
+        if slave.pending.len() >= MAX_PENDING {
+            slave.pending.pop_front();
+        }
+        slave.pending.push_back((cam_frame, received));
+
+        let Some(&(_, first_received)) = self.master_frames.front() else {
+            return SlaveStatus::Pending;
+        };
+        let Some(&(_, last_received)) = self.master_frames.back() else {
+            return SlaveStatus::Pending;
+        };
+        while let Some(&(cam_frame, received)) = slave.pending.front() {
+            if received < first_received - self.half_period {
+                // Received before the master frames we know, cannot match.
+                slave.pending.pop_front();
+                continue;
+            }
+            if received + self.half_period >= last_received {
+                // A closer master frame may still arrive.
+                break;
+            }
+            slave.pending.pop_front();
+            let offset = nearest(&self.master_frames, received)
+                .filter(|(_, dt)| *dt < self.half_period)
+                .and_then(|(synced_frame, _)| cam_frame.checked_sub(synced_frame));
+            slave.offsets.push(offset);
+        }
+
+        let n_matches = if frame0.is_some() { N_CHECK } else { N_MATCHES };
+        if slave.offsets.len() < n_matches {
+            return SlaveStatus::Pending;
+        }
+        let offsets = std::mem::take(&mut slave.offsets);
+        let mut counts: BTreeMap<u64, usize> = BTreeMap::new();
+        for offset in offsets.iter().flatten() {
+            *counts.entry(*offset).or_default() += 1;
+        }
+        let (offset, count) = match frame0 {
+            Some(frame0) => (frame0, counts.get(&frame0).copied().unwrap_or(0)),
+            None => counts
+                .into_iter()
+                .max_by_key(|(_, count)| *count)
+                .unwrap_or((0, 0)),
+        };
+        let agreement = count as f64 / offsets.len() as f64;
+        if agreement >= MIN_AGREEMENT {
+            SlaveStatus::Matched(offset)
+        } else {
+            SlaveStatus::NotTriggered { agreement }
+        }
+    }
+}
+
+/// The master frame received closest to `received` and the time difference.
+fn nearest(master_frames: &VecDeque<(u64, f64)>, received: f64) -> Option<(u64, f64)> {
+    let idx = master_frames.partition_point(|(_, t)| *t < received);
+    [idx.checked_sub(1), Some(idx)]
+        .into_iter()
+        .flatten()
+        .filter_map(|i| master_frames.get(i))
+        .map(|(synced_frame, t)| (*synced_frame, (t - received).abs()))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+#[test]
+fn test_master_sync() {
+    let period = 0.01;
+    let slave = RawCamName::new("slave".to_string());
+
+    // The slave counts from frame 1000 at synchronized frame 0, and its frames
+    // arrive before or after the master frames.
+    let mut sync = MasterSync::new(1.0 / period);
+    let mut status = SlaveStatus::Pending;
+    for i in 0..40u64 {
+        let t = i as f64 * period;
+        let jitter = if i % 2 == 0 { 0.002 } else { -0.003 };
+        sync.push_master(i, t);
+        status = sync.push_slave(&slave, 1000 + i, t + jitter, None);
+        if status != SlaveStatus::Pending {
+            break;
+        }
+    }
+    assert_eq!(status, SlaveStatus::Matched(1000));
+
+    // A free running slave at a different frame rate does not match.
+    let mut sync = MasterSync::new(1.0 / period);
+    let mut status = SlaveStatus::Pending;
+    for i in 0..100u64 {
+        sync.push_master(i, i as f64 * period);
+        status = sync.push_slave(&slave, 1000 + i, i as f64 * period * 1.3, None);
+        if status != SlaveStatus::Pending {
+            break;
+        }
+    }
+    assert!(matches!(status, SlaveStatus::NotTriggered { .. }));
+}
@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use parking_lot::RwLock;
+use std::sync::Arc;
+
+/// The source of the current time for the live tracking pipeline.
+///
+/// In production, [Clock::Wall] returns the system time. For replaying
+/// recorded data faster than realtime, [Clock::Simulated] returns a time which
+/// is advanced explicitly by the replay driver so that runs are deterministic.
+///
+/// Note that timers (e.g. `tokio::time::interval`) are not affected. A replay
+/// driver can control these with `tokio::time::pause()` and
+/// `tokio::time::advance()`.
+#[derive(Debug, Clone, Default)]
+pub enum Clock {
+    /// Use the system time.
+    #[default]
+    Wall,
+    /// Use a time controlled by a [SimulatedClock].
+    Simulated(SimulatedClock),
+}
+
+impl Clock {
+    /// Return the current time.
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            Clock::Wall => Utc::now(),
+            Clock::Simulated(sim) => sim.now(),
+        }
+    }
+
+    /// Return the current time in the local timezone.
+    pub fn now_local(&self) -> DateTime<chrono::Local> {
+        self.now().with_timezone(&chrono::Local)
+    }
+
+    /// Return true if the time is simulated.
+    pub fn is_simulated(&self) -> bool {
+        matches!(self, Clock::Simulated(_))
+    }
+
+    /// Return the current instant, to measure the time elapsed since then
+    /// with [Clock::elapsed].
+    pub fn instant(&self) -> ClockInstant {
+        match self {
+            Clock::Wall => ClockInstant::Wall(std::time::Instant::now()),
+            Clock::Simulated(sim) => ClockInstant::Simulated(sim.now()),
+        }
+    }
+
+    /// Return the time elapsed since `since`, which was returned by
+    /// [Clock::instant].
+    ///
+    /// If the simulated time was set back to before `since`, this is zero.
+    pub fn elapsed(&self, since: ClockInstant) -> std::time::Duration {
+        match since {
+            ClockInstant::Wall(instant) => instant.elapsed(),
+            ClockInstant::Simulated(time) => (self.now() - time).to_std().unwrap_or_default(),
+        }
+    }
+}
+
+/// An instant of a [Clock], used to measure elapsed time.
+///
+/// For [Clock::Wall], this is a monotonic [std::time::Instant], so that steps
+/// of the system time, e.g. by NTP, do not affect the elapsed time.
+#[derive(Debug, Clone, Copy)]
+pub enum ClockInstant {
+    Wall(std::time::Instant),
+    Simulated(DateTime<Utc>),
+}
+
+/// A clock whose time only changes when set or advanced.
+///
+/// Clones share the same time, so the replay driver keeps one clone and the
+/// pipeline another.
+#[derive(Debug, Clone)]
+pub struct SimulatedClock {
+    now: Arc<RwLock<DateTime<Utc>>>,
+}
+
+impl SimulatedClock {
+    /// Create a new simulated clock starting at `start`.
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self {
+            now: Arc::new(RwLock::new(start)),
+        }
+    }
+
+    /// Return the current simulated time.
+    pub fn now(&self) -> DateTime<Utc> {
+        *self.now.read()
+    }
+
+    /// Set the current simulated time.
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.write() = now;
+    }
+
+    /// Advance the simulated time by `dur`.
+    pub fn advance(&self, dur: chrono::Duration) {
+        let mut now = self.now.write();
+        *now += dur;
+    }
+}
+
+#[test]
+fn test_simulated_clock() {
+    let start = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    let sim = SimulatedClock::new(start);
+    let clock = Clock::Simulated(sim.clone());
+    assert!(clock.is_simulated());
+    assert_eq!(clock.now(), start);
+
+    sim.advance(chrono::Duration::milliseconds(10));
+    assert_eq!(clock.now(), start + chrono::Duration::milliseconds(10));
+
+    sim.set(start);
+    assert_eq!(clock.now(), start);
+
+    let instant = clock.instant();
+    sim.advance(chrono::Duration::seconds(2));
+    assert_eq!(clock.elapsed(instant), std::time::Duration::from_secs(2));
+    sim.set(start - chrono::Duration::seconds(1));
+    assert_eq!(clock.elapsed(instant), std::time::Duration::ZERO);
+}
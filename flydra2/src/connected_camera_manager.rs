@@ -4,10 +4,11 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tracing::{debug, error, info};
 
-use crate::{safe_u8, CamInfoRow, MyFloat};
+use crate::camera_master::{MasterSync, SlaveStatus};
+use crate::{safe_u8, CamInfoRow, Clock, ClockInstant, MyFloat};
 use flydra_types::{
-    BuiServerInfo, CamInfo, CamNum, ConnectedCameraSyncState, PtpStamp, PtpSyncConfig, RawCamName,
-    RecentStats, SyncFno, TriggerType, TRIGGERBOX_SYNC_SECONDS,
+    BuiServerInfo, CamInfo, CamNum, CameraMasterConfig, ConnectedCameraSyncState, PtpStamp,
+    PtpSyncConfig, RawCamName, RecentStats, SyncFno, TriggerType, TRIGGERBOX_SYNC_SECONDS,
 };
 
 pub(crate) trait HasCameraList {
@@ -64,6 +65,8 @@ struct ConnectedCamerasManagerInner {
     all_expected_cameras_are_present: bool,
     all_expected_cameras_are_synced: bool,
     first_frame_arrived: BTreeSet<RawCamName>,
+    /// Created on the first frame when synchronized by a master camera.
+    master_sync: Option<MasterSync>,
 }
 
 pub trait ConnectedCamCallback: Send {
@@ -84,6 +87,7 @@ pub struct ConnectedCamerasManager {
     signal_all_cams_synced: Arc<AtomicBool>,
     launch_time_ptp: PtpStamp,
     periodic_signal_period_usec: Option<f64>,
+    clock: Clock,
 }
 
 impl HasCameraList for ConnectedCamerasManager {
@@ -106,6 +110,25 @@ impl ConnectedCamerasManager {
         signal_all_cams_present: Arc<AtomicBool>,
         signal_all_cams_synced: Arc<AtomicBool>,
         periodic_signal_period_usec: Option<f64>,
+    ) -> Self {
+        Self::new_with_clock(
+            recon,
+            all_expected_cameras,
+            signal_all_cams_present,
+            signal_all_cams_synced,
+            periodic_signal_period_usec,
+            Clock::Wall,
+        )
+    }
+
+    /// Create a new manager which takes the current time from `clock`.
+    pub fn new_with_clock(
+        recon: &Option<flydra_mvg::FlydraMultiCameraSystem<MyFloat>>,
+        all_expected_cameras: BTreeSet<RawCamName>,
+        signal_all_cams_present: Arc<AtomicBool>,
+        signal_all_cams_synced: Arc<AtomicBool>,
+        periodic_signal_period_usec: Option<f64>,
+        clock: Clock,
     ) -> Self {
         let mut not_yet_connected = BTreeMap::new();
 
@@ -121,7 +144,7 @@ impl ConnectedCamerasManager {
             0
         };
 
-        let launch_time = chrono::Utc::now();
+        let launch_time = clock.now();
         let mut launch_time_ptp = PtpStamp::try_from(launch_time).unwrap();
 
         if let Some(periodic_signal_period_usec) = periodic_signal_period_usec.as_ref() {
@@ -144,14 +167,21 @@ impl ConnectedCamerasManager {
                 all_expected_cameras_are_present: false,
                 all_expected_cameras_are_synced: false,
                 first_frame_arrived: BTreeSet::new(),
+                master_sync: None,
             })),
             on_cam_change_func: Arc::new(Mutex::new(None)),
             recon: recon.clone(),
             launch_time_ptp,
             periodic_signal_period_usec,
+            clock,
         }
     }
 
+    /// The source of the current time.
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
     /// The cameras are being (re)synchronized. Clear all inner data and reset camera numbers.
     pub fn reset_sync_data(&mut self) {
         info!("Camera manager dropping old cameras and expecting new cameras");
@@ -175,6 +205,9 @@ impl ConnectedCamerasManager {
             inner.next_cam_num = next_cam_num.into();
             let old_ccis = std::mem::take(&mut inner.ccis);
             inner.not_yet_connected = not_yet_connected;
+            if let Some(master_sync) = inner.master_sync.as_mut() {
+                master_sync.reset();
+            }
             old_ccis
         };
 
@@ -189,6 +222,29 @@ impl ConnectedCamerasManager {
         }
     }
 
+    /// The cameras are being resynchronized while running.
+    ///
+    /// Unlike [Self::reset_sync_data], the connected cameras and their camera
+    /// numbers are kept such that data saved before and after the
+    /// resynchronization remains consistent. Until a camera is synchronized
+    /// again, no synchronized frame number is returned for its frames.
+    pub fn reset_sync_state(&self) {
+        info!("Camera manager expecting cameras to synchronize again");
+        {
+            let mut inner = self.inner.write();
+            for cci in inner.ccis.values_mut() {
+                cci.sync_state = ConnectedCameraSyncState::Unsynchronized;
+                cci.frames_during_sync = 0;
+            }
+            if let Some(master_sync) = inner.master_sync.as_mut() {
+                master_sync.reset();
+            }
+            inner.all_expected_cameras_are_synced = false;
+        }
+        self.signal_all_cams_synced.store(false, Ordering::SeqCst);
+        self.notify_cam_changed_listeners();
+    }
+
     /// Set callback to be called when connected cameras or their state changes
     pub fn set_cam_changed_callback(
         &mut self,
@@ -373,7 +429,7 @@ impl ConnectedCamerasManager {
     pub fn got_new_frame_live<F>(
         &self,
         packet: &flydra_types::FlydraRawUdpPacket,
-        sync_pulse_pause_started_arc: &Arc<RwLock<Option<std::time::Instant>>>,
+        sync_pulse_pause_started_arc: &Arc<RwLock<Option<ClockInstant>>>,
         send_new_frame_offset: F,
         trigger_cfg: &TriggerType,
     ) -> Option<SyncFno>
@@ -390,6 +446,9 @@ impl ConnectedCamerasManager {
                 self.got_new_frame_live_triggerbox(packet, sync_pulse_pause_started_arc, 0)
             }
             TriggerType::PtpSync(ptpcfg) => self.got_new_frame_live_ptp(packet, ptpcfg)?,
+            TriggerType::CameraMaster(cfg) => {
+                self.got_new_frame_live_camera_master(packet, sync_pulse_pause_started_arc, cfg)?
+            }
             TriggerType::DeviceTimestamp => {
                 todo!();
             }
@@ -401,7 +460,7 @@ impl ConnectedCamerasManager {
     fn got_new_frame_live_triggerbox(
         &self,
         packet: &flydra_types::FlydraRawUdpPacket,
-        sync_pulse_pause_started_arc: &Arc<RwLock<Option<std::time::Instant>>>,
+        sync_pulse_pause_started_arc: &Arc<RwLock<Option<ClockInstant>>>,
         sync_time_min_sec: u64,
     ) -> SyncData {
         assert!(packet.framenumber >= 0);
@@ -426,7 +485,7 @@ impl ConnectedCamerasManager {
                         do_check_if_all_cameras_present = true;
                         let sync_pulse_pause_started = sync_pulse_pause_started_arc.read();
                         if let Some(pulse_time) = *sync_pulse_pause_started {
-                            let elapsed = pulse_time.elapsed();
+                            let elapsed = self.clock.elapsed(pulse_time);
                             if sync_time_min < elapsed && elapsed < sync_time_max {
                                 // Camera is not synchronized, but we are
                                 // expecting a sync pulse. Therefore,
@@ -509,6 +568,78 @@ impl ConnectedCamerasManager {
         }
     }
 
+    /// Register that a new frame was received if we are using a master camera.
+    ///
+    /// The master camera is synchronized as with fake synchronization and the
+    /// other cameras by matching their frames to those of the master, see
+    /// [crate::camera_master].
+    fn got_new_frame_live_camera_master(
+        &self,
+        packet: &flydra_types::FlydraRawUdpPacket,
+        sync_pulse_pause_started_arc: &Arc<RwLock<Option<ClockInstant>>>,
+        cfg: &CameraMasterConfig,
+    ) -> Option<SyncData> {
+        let received = packet.cam_received_time.as_f64();
+        if packet.cam_name == cfg.master_camera {
+            let sync_data =
+                self.got_new_frame_live_triggerbox(packet, sync_pulse_pause_started_arc, 0);
+            if let Some(synced_frame) = sync_data.synced_frame {
+                let mut inner = self.inner.write();
+                inner
+                    .master_sync
+                    .get_or_insert_with(|| MasterSync::new(cfg.framerate))
+                    .push_master(synced_frame, received);
+            }
+            return Some(sync_data);
+        }
+
+        assert!(packet.framenumber >= 0);
+        let raw_cam_name = RawCamName::new(packet.cam_name.clone());
+        let cam_frame = packet.framenumber as u64;
+
+        let (frame0, status) = {
+            // This scope is for the write lock on self.inner. Keep it minimal.
+            let mut inner = self.inner.write();
+            let inner: &mut ConnectedCamerasManagerInner = &mut inner;
+            // Ignore frames while the camera is starting up (or shutting down).
+            let cci = inner.ccis.get(&raw_cam_name)?;
+            let frame0 = match cci.sync_state {
+                ConnectedCameraSyncState::Unsynchronized => None,
+                ConnectedCameraSyncState::Synchronized(frame0) => Some(frame0),
+            };
+            let status = inner
+                .master_sync
+                .get_or_insert_with(|| MasterSync::new(cfg.framerate))
+                .push_slave(&raw_cam_name, cam_frame, received, frame0);
+            (frame0, status)
+        };
+
+        if let SlaveStatus::NotTriggered { agreement } = status {
+            error!(
+                "Frames of camera \"{}\" do not match the frames of master camera \"{}\" \
+                ({:.0}% agree). Is the camera triggered by the exposure output of the master \
+                camera?",
+                raw_cam_name.as_str(),
+                cfg.master_camera,
+                agreement * 100.0,
+            );
+        }
+
+        let new_frame0 = match (frame0, status) {
+            (None, SlaveStatus::Matched(frame0)) => Some(frame0),
+            _ => None,
+        };
+        let synced_frame = frame0
+            .or(new_frame0)
+            .and_then(|frame0| cam_frame.checked_sub(frame0));
+        Some(SyncData {
+            new_frame0,
+            raw_cam_name,
+            do_check_if_all_cameras_present: frame0.is_none(),
+            synced_frame,
+        })
+    }
+
     /// Register that a new frame was received if we are using PTP
     fn got_new_frame_live_ptp(
         &self,
@@ -530,12 +661,16 @@ impl ConnectedCamerasManager {
                     panic!("camera period not set to expected period");
                 }
             }
-            let device_timestamp = PtpStamp::new(
-                packet
-                    .device_timestamp
-                    .expect("could not get device_timestamp for frame")
-                    .get(),
-            );
+            let Some(device_timestamp) = packet.device_timestamp else {
+                // The camera does not support PTP or did not enable it.
+                tracing::error!(
+                    "Ignoring frame {} from camera \"{cam}\" without device timestamp. \
+                    PTP synchronization requires cameras supporting PTP.",
+                    packet.framenumber
+                );
+                return None;
+            };
+            let device_timestamp = PtpStamp::new(device_timestamp.get());
             let elapsed_since_launch = if let Some(dur) =
                 device_timestamp.duration_since(&self.launch_time_ptp)
             {
@@ -758,3 +893,62 @@ fn test_camera_list() {
     let c2 = CameraList::new(&[4, 3, 2, 5]);
     assert!(c1 != c2);
 }
+
+#[test]
+fn test_sync_with_simulated_clock() {
+    use flydra_types::{FlydraFloatTimestampLocal, ImageProcessingSteps};
+
+    let start = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    let sim = crate::SimulatedClock::new(start);
+    let mut cam_manager = ConnectedCamerasManager::new_with_clock(
+        &None,
+        BTreeSet::new(),
+        Arc::new(AtomicBool::new(false)),
+        Arc::new(AtomicBool::new(false)),
+        None,
+        Clock::Simulated(sim.clone()),
+    );
+    let raw_cam_name = RawCamName::new("cam1".to_string());
+    cam_manager
+        .register_new_camera(&raw_cam_name, &BuiServerInfo::NoServer, None)
+        .unwrap();
+
+    let packet = |framenumber| flydra_types::FlydraRawUdpPacket {
+        cam_name: raw_cam_name.as_str().to_string(),
+        timestamp: None,
+        cam_received_time: FlydraFloatTimestampLocal::from_dt(&sim.now()),
+        device_timestamp: None,
+        block_id: None,
+        framenumber,
+        n_frames_skipped: 0,
+        done_camnode_processing: 0.0,
+        preprocess_stamp: 0.0,
+        packet_sent_stamp: 0.0,
+        image_processing_steps: ImageProcessingSteps::empty(),
+        points: vec![],
+    };
+
+    let sync_pulse_pause_started_arc = Arc::new(RwLock::new(Some(cam_manager.clock().instant())));
+
+    // During the pause of the sync pulses, the camera is not synchronized.
+    sim.advance(chrono::Duration::seconds(1));
+    let sync_data = cam_manager.got_new_frame_live_triggerbox(
+        &packet(100),
+        &sync_pulse_pause_started_arc,
+        TRIGGERBOX_SYNC_SECONDS,
+    );
+    assert_eq!(sync_data.synced_frame, None);
+
+    // The first frame after the pause is the first pulse.
+    sim.advance(chrono::Duration::seconds(TRIGGERBOX_SYNC_SECONDS as i64));
+    let sync_data = cam_manager.got_new_frame_live_triggerbox(
+        &packet(200),
+        &sync_pulse_pause_started_arc,
+        TRIGGERBOX_SYNC_SECONDS,
+    );
+    assert_eq!(sync_data.synced_frame, Some(crate::TRIGGERBOX_FIRST_PULSE));
+    assert_eq!(
+        sync_data.new_frame0,
+        Some(200 - crate::TRIGGERBOX_FIRST_PULSE)
+    );
+}
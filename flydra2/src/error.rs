@@ -71,6 +71,13 @@ pub enum Error {
         backtrace: Backtrace,
     },
     #[error("{source}")]
+    BraidzWriter {
+        #[from]
+        source: braidz_writer::Error,
+        #[cfg(feature = "backtrace")]
+        backtrace: Backtrace,
+    },
+    #[error("{source}")]
     SendToDiskError {
         #[from]
         source: tokio::sync::mpsc::error::SendError<crate::SaveToDiskMsg>,
@@ -17,9 +17,6 @@ use hdrhistogram::{
     Counter, Histogram,
 };
 
-use libflate::finish::AutoFinishUnchecked;
-use libflate::gzip::Encoder;
-
 use nalgebra::{
     allocator::Allocator,
     dimension::{DimMin, U1, U2, U3, U6},
@@ -29,15 +26,20 @@ use nalgebra::{
 #[allow(unused_imports)]
 use mvg::{DistortedPixel, PointWorldFrame, PointWorldFrameWithSumReprojError};
 
-pub use braidz_types::BraidMetadata;
+pub use braidz_types::{BraidMetadata, TrackingDeterminism};
 
 use flydra_types::{
     CamInfoRow, CamNum, ConnectedCameraSyncState, DataAssocRow, FlydraFloatTimestampLocal,
-    HostClock, KalmanEstimatesRow, RawCamName, SyncFno, TextlogRow, TrackingParams,
-    TriggerClockInfoRow, Triggerbox, RECONSTRUCT_LATENCY_HLOG_FNAME, REPROJECTION_DIST_HLOG_FNAME,
+    HostClock, KalmanEstimatesRow, LatencyStageStats, RawCamName, SyncFno, TextlogRow,
+    TrackingParams, TriggerClockInfoRow, Triggerbox, RECONSTRUCT_LATENCY_HLOG_FNAME,
+    REPROJECTION_DIST_HLOG_FNAME,
 };
-pub use flydra_types::{Data2dDistortedRow, Data2dDistortedRowF32};
+pub use flydra_types::{Data2dAppearanceRow, Data2dDistortedRow, Data2dDistortedRowF32};
+
+mod clock;
+pub use clock::{Clock, ClockInstant, SimulatedClock};
 
+mod camera_master;
 mod connected_camera_manager;
 pub use connected_camera_manager::{ConnectedCamCallback, ConnectedCamerasManager};
 
@@ -57,7 +59,17 @@ mod tracking_core;
 mod mini_arenas;
 
 mod model_server;
-pub use crate::model_server::{new_model_server, SendKalmanEstimatesRow, SendType};
+pub use crate::model_server::{
+    new_model_server, new_model_server_with_clock, SendKalmanEstimatesRow, SendType,
+};
+
+mod latency;
+pub use crate::latency::{LatencyBudget, LatencyStage, LATENCY_WINDOW};
+
+mod pose_udp;
+pub use crate::pose_udp::{
+    LiveEstimate, LivePose, LivePoseMessage, PoseUdpSender, LIVE_POSE_SCHEMA_VERSION,
+};
 
 use crate::contiguous_stream::make_contiguous;
 use crate::frame_bundler::bundle_frames;
@@ -178,12 +190,6 @@ pub struct NumberedRawUdpPoint {
     pub pt: flydra_types::FlydraRawUdpPoint,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct TrackingParamsSaver {
-    tracking_params: flydra_types::TrackingParams,
-    git_revision: String,
-}
-
 #[derive(Clone, Debug, Serialize)]
 struct SyncedFrameCount {
     frame: SyncFno,
@@ -476,6 +482,7 @@ pub enum SaveToDiskMsg {
     Textlog(TextlogRow),
     TriggerClockInfo(TriggerClockInfoRow),
     SetExperimentUuid(String),
+    LatencyStats(Vec<LatencyStageStats>),
 }
 
 /// Acts like a `csv::Writer` but buffers and orders by frame.
@@ -589,19 +596,36 @@ fn save_hlog(
     let mut log_path = output_dirname.to_path_buf();
     log_path.push(fname);
     log_path.set_extension("hlog");
-    let mut fd = std::fs::File::create(&log_path).expect("creating latency log file");
+    // The output directory may be unwritable, e.g. on a dropped network
+    // drive, so errors are logged rather than panicking.
+    let mut fd = match std::fs::File::create(&log_path) {
+        Ok(fd) => fd,
+        Err(e) => {
+            error!("creating {}: {e}", log_path.display());
+            return;
+        }
+    };
 
     let mut serializer = V2DeflateSerializer::new();
     // create a writer via a builder
-    let mut latency_log_wtr = interval_log::IntervalLogWriterBuilder::new()
+    let mut latency_log_wtr = match interval_log::IntervalLogWriterBuilder::new()
         .with_start_time(file_start_time)
         .begin_log_with(&mut fd, &mut serializer)
-        .unwrap();
+    {
+        Ok(wtr) => wtr,
+        Err(e) => {
+            error!("writing {}: {e:?}", log_path.display());
+            return;
+        }
+    };
 
     for h in histograms.iter() {
-        latency_log_wtr
-            .write_histogram(&h.histogram, h.start_timestamp, h.duration, None)
-            .unwrap();
+        if let Err(e) =
+            latency_log_wtr.write_histogram(&h.histogram, h.start_timestamp, h.duration, None)
+        {
+            error!("writing {}: {e:?}", log_path.display());
+            return;
+        }
     }
 }
 
@@ -675,6 +699,11 @@ pub struct CoordProcessorConfig {
     pub ignore_latency: bool,
     pub mini_arena_debug_image_dir: Option<std::path::PathBuf>,
     pub write_buffer_size_num_messages: usize,
+    /// If set, each `.braidz` file is encrypted with this key when finished.
+    pub braidz_encryption_key: Option<braidz_crypt::BraidzKey>,
+    /// Local directory for data which cannot be written to the `.braid`
+    /// directory. If `None`, a directory in the temporary directory is used.
+    pub braidz_spill_dir: Option<std::path::PathBuf>,
 }
 
 /// A [tokio::sync::mpsc::Sender] which cannot be cloned.
@@ -728,6 +757,7 @@ pub struct CoordProcessor {
         Vec<crate::tracking_core::ModelCollection<crate::tracking_core::CollectionFrameDone>>,
     >,
     next_obj_id: Arc<Mutex<u32>>,
+    latency_budget: Option<Arc<LatencyBudget>>,
 }
 
 impl CoordProcessor {
@@ -744,6 +774,8 @@ impl CoordProcessor {
             ignore_latency,
             mini_arena_debug_image_dir,
             write_buffer_size_num_messages,
+            braidz_encryption_key,
+            braidz_spill_dir,
         } = cfg;
 
         trace!("CoordProcessor using {:?}", recon);
@@ -774,6 +806,8 @@ impl CoordProcessor {
                 save_empty_data2d,
                 metadata_builder,
                 ignore_latency,
+                braidz_encryption_key,
+                braidz_spill_dir,
             )
         });
 
@@ -787,6 +821,7 @@ impl CoordProcessor {
             model_collections: None,
             mini_arena_images,
             next_obj_id: Arc::new(Mutex::new(0)),
+            latency_budget: None,
         })
     }
 
@@ -819,6 +854,11 @@ impl CoordProcessor {
         self.model_servers.push(model_server);
     }
 
+    /// Record when the 3D update of each frame is done in `latency_budget`.
+    pub fn set_latency_budget(&mut self, latency_budget: Arc<LatencyBudget>) {
+        self.latency_budget = Some(latency_budget);
+    }
+
     /// Consume the CoordProcessor and the input stream.
     ///
     /// Returns a future that completes when done. This is basically the "main
@@ -1003,6 +1043,10 @@ impl CoordProcessor {
                 }
 
                 self.model_collections = Some(model_collections);
+
+                if let Some(latency_budget) = &self.latency_budget {
+                    latency_budget.tracking_done(tdpt.synced_frame());
+                }
             }
         }
         debug!("consume_stream future done");
@@ -1038,7 +1082,7 @@ fn test_csv_nan() {
         camn: CamNum(1),
         frame: 2,
         timestamp: None,
-        cam_received_timestamp: FlydraFloatTimestampLocal::from_dt(&chrono::Local::now()),
+        cam_received_timestamp: FlydraFloatTimestampLocal::from_f64(1_700_000_000.0),
         device_timestamp: None,
         block_id: None,
         x: f32::NAN,
@@ -0,0 +1,279 @@
+//! Latency budget of the processing pipeline.
+//!
+//! For each frame, the time is taken at these points:
+//!
+//! 1. camera callback: the frame was received by Strand Camera,
+//! 2. 2D detection complete,
+//! 3. packet sent to the mainbrain,
+//! 4. packet received by the mainbrain,
+//! 5. 3D update done: tracking finished with all cameras' data of the frame.
+//!
+//! The durations between these points are the stages of the [LatencyBudget].
+//! Percentiles are computed over the samples of the last [LATENCY_WINDOW].
+//!
+//! The first three times are taken on the camera computers. If these are not
+//! the computer running the mainbrain, the `network` stage also includes the
+//! offset between the clocks of the computers.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use tracing::trace;
+
+use flydra_types::{FlydraRawUdpPacket, LatencyStageStats, SyncFno};
+
+/// Duration over which the latency percentiles are computed.
+pub const LATENCY_WINDOW: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Frames awaiting tracking which are remembered at most.
+const MAX_PENDING_FRAMES: usize = 1000;
+
+/// A stage of the processing pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyStage {
+    /// From the camera callback until 2D detection is complete.
+    Detection,
+    /// From 2D detection complete until the packet is sent.
+    Send,
+    /// From sending the packet until it is received by the mainbrain.
+    Network,
+    /// From receiving the last packet of a frame until the 3D update is done.
+    Tracking,
+    /// From the first camera callback of a frame until the 3D update is done.
+    Total,
+}
+
+impl LatencyStage {
+    const ALL: [LatencyStage; 5] = [
+        LatencyStage::Detection,
+        LatencyStage::Send,
+        LatencyStage::Network,
+        LatencyStage::Tracking,
+        LatencyStage::Total,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LatencyStage::Detection => "detection",
+            LatencyStage::Send => "send",
+            LatencyStage::Network => "network",
+            LatencyStage::Tracking => "tracking",
+            LatencyStage::Total => "total",
+        }
+    }
+}
+
+/// Times of a frame not yet tracked, in seconds since the Unix epoch.
+struct PendingFrame {
+    /// The earliest camera callback.
+    first_cam_received: f64,
+    /// The latest reception by the mainbrain.
+    last_mainbrain_received: f64,
+}
+
+#[derive(Default)]
+struct LatencyBudgetInner {
+    /// For each stage, the time of recording and the latency, in seconds.
+    samples: [VecDeque<(f64, f64)>; LatencyStage::ALL.len()],
+    pending: BTreeMap<SyncFno, PendingFrame>,
+}
+
+impl LatencyBudgetInner {
+    fn record(&mut self, stage: LatencyStage, latency: f64, now: f64) {
+        let samples = &mut self.samples[stage as usize];
+        samples.push_back((now, latency));
+        let oldest = now - LATENCY_WINDOW.as_secs_f64();
+        while samples.front().is_some_and(|(t, _)| *t < oldest) {
+            samples.pop_front();
+        }
+    }
+}
+
+/// Collects the latencies of the stages of the processing pipeline.
+#[derive(Default)]
+pub struct LatencyBudget {
+    inner: parking_lot::Mutex<LatencyBudgetInner>,
+    clock: crate::Clock,
+}
+
+impl std::fmt::Debug for LatencyBudget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LatencyBudget").finish_non_exhaustive()
+    }
+}
+
+impl LatencyBudget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a latency budget which takes the times of the mainbrain from
+    /// `clock`.
+    pub fn with_clock(clock: crate::Clock) -> Self {
+        Self {
+            clock,
+            ..Self::default()
+        }
+    }
+
+    fn now(&self) -> f64 {
+        datetime_conversion::datetime_to_f64(&self.clock.now())
+    }
+
+    /// The mainbrain received `packet`, which is for `synced_frame`.
+    pub fn packet_received(&self, packet: &FlydraRawUdpPacket, synced_frame: SyncFno) {
+        self.packet_received_at(packet, synced_frame, self.now());
+    }
+
+    fn packet_received_at(&self, packet: &FlydraRawUdpPacket, synced_frame: SyncFno, now: f64) {
+        let cam_received = packet.cam_received_time.as_f64();
+        // Zero if not known.
+        let detection_done = packet.done_camnode_processing;
+        let packet_sent = packet.packet_sent_stamp;
+        trace!(
+            cam_name = %packet.cam_name,
+            frame = synced_frame.0,
+            cam_received,
+            detection_done,
+            packet_sent,
+            mainbrain_received = now,
+            "packet received"
+        );
+
+        let mut inner = self.inner.lock();
+        if detection_done > 0.0 {
+            inner.record(LatencyStage::Detection, detection_done - cam_received, now);
+            if packet_sent > 0.0 {
+                inner.record(LatencyStage::Send, packet_sent - detection_done, now);
+            }
+        }
+        if packet_sent > 0.0 {
+            inner.record(LatencyStage::Network, now - packet_sent, now);
+        }
+
+        let pending = inner.pending.entry(synced_frame).or_insert(PendingFrame {
+            first_cam_received: cam_received,
+            last_mainbrain_received: now,
+        });
+        pending.first_cam_received = pending.first_cam_received.min(cam_received);
+        pending.last_mainbrain_received = pending.last_mainbrain_received.max(now);
+        // Without tracking, frames are never done.
+        while inner.pending.len() > MAX_PENDING_FRAMES {
+            inner.pending.pop_first();
+        }
+    }
+
+    /// The 3D update of `synced_frame` is done.
+    pub(crate) fn tracking_done(&self, synced_frame: SyncFno) {
+        self.tracking_done_at(synced_frame, self.now());
+    }
+
+    fn tracking_done_at(&self, synced_frame: SyncFno, now: f64) {
+        let mut inner = self.inner.lock();
+        let later = inner.pending.split_off(&SyncFno(synced_frame.0 + 1));
+        let done = std::mem::replace(&mut inner.pending, later);
+        if let Some(pending) = done.get(&synced_frame) {
+            trace!(
+                frame = synced_frame.0,
+                tracking_done = now,
+                "3D update done"
+            );
+            inner.record(
+                LatencyStage::Tracking,
+                now - pending.last_mainbrain_received,
+                now,
+            );
+            inner.record(LatencyStage::Total, now - pending.first_cam_received, now);
+        }
+    }
+
+    /// Percentiles of the latency of each stage with any samples.
+    pub fn stats(&self) -> Vec<LatencyStageStats> {
+        self.stats_at(self.now())
+    }
+
+    fn stats_at(&self, now: f64) -> Vec<LatencyStageStats> {
+        let inner = self.inner.lock();
+        let oldest = now - LATENCY_WINDOW.as_secs_f64();
+        LatencyStage::ALL
+            .iter()
+            .filter_map(|stage| {
+                let mut msecs: Vec<f64> = inner.samples[*stage as usize]
+                    .iter()
+                    .filter(|(t, _)| *t >= oldest)
+                    .map(|(_, latency)| latency * 1000.0)
+                    .collect();
+                if msecs.is_empty() {
+                    return None;
+                }
+                msecs.sort_by(f64::total_cmp);
+                let percentile = |p: f64| {
+                    let idx = (p * (msecs.len() - 1) as f64).round() as usize;
+                    msecs[idx]
+                };
+                Some(LatencyStageStats {
+                    mainbrain_timestamp: now,
+                    stage: stage.as_str().to_string(),
+                    n: msecs.len(),
+                    p50_msec: percentile(0.5),
+                    p90_msec: percentile(0.9),
+                    p99_msec: percentile(0.99),
+                    max_msec: msecs[msecs.len() - 1],
+                })
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_latency_budget() {
+    use flydra_types::{FlydraFloatTimestampLocal, ImageProcessingSteps};
+
+    let budget = LatencyBudget::new();
+    let t0 = 1_700_000_000.0;
+    for i in 0..100u64 {
+        let t = t0 + i as f64 * 0.01;
+        for (cam_name, delay) in [("cam1", 0.0), ("cam2", 0.002)] {
+            let packet = FlydraRawUdpPacket {
+                cam_name: cam_name.to_string(),
+                timestamp: None,
+                cam_received_time: FlydraFloatTimestampLocal::from_f64(t + delay),
+                device_timestamp: None,
+                block_id: None,
+                framenumber: i as i32,
+                n_frames_skipped: 0,
+                done_camnode_processing: t + delay + 0.003,
+                preprocess_stamp: t + delay,
+                packet_sent_stamp: t + delay + 0.004,
+                image_processing_steps: ImageProcessingSteps::empty(),
+                points: vec![],
+            };
+            budget.packet_received_at(&packet, SyncFno(i), t + delay + 0.005);
+        }
+        budget.tracking_done_at(SyncFno(i), t + 0.010);
+    }
+
+    let stats = budget.stats_at(t0 + 1.0);
+    let stages: Vec<&str> = stats.iter().map(|s| s.stage.as_str()).collect();
+    assert_eq!(
+        stages,
+        ["detection", "send", "network", "tracking", "total"]
+    );
+    let msec = |stage: &str| {
+        let s = stats.iter().find(|s| s.stage == stage).unwrap();
+        (s.n, s.p50_msec)
+    };
+    let close = |(n, p50): (usize, f64), (n_expected, p50_expected): (usize, f64)| {
+        assert_eq!(n, n_expected);
+        assert!((p50 - p50_expected).abs() < 1e-3, "{p50} != {p50_expected}");
+    };
+    close(msec("detection"), (200, 3.0));
+    close(msec("send"), (200, 1.0));
+    close(msec("network"), (200, 1.0));
+    close(msec("tracking"), (100, 3.0));
+    close(msec("total"), (100, 10.0));
+
+    // Old samples are not used.
+    assert!(budget
+        .stats_at(t0 + 1.0 + LATENCY_WINDOW.as_secs_f64())
+        .is_empty());
+}
@@ -7,7 +7,7 @@ use serde::{Deserialize, Serialize};
 
 use event_stream_types::{AcceptsEventStream, EventBroadcaster};
 
-use crate::{Result, TimeDataPassthrough};
+use crate::{Clock, Result, TimeDataPassthrough};
 
 use flydra_types::{FlydraFloatTimestampLocal, SyncFno, Triggerbox};
 
@@ -46,7 +46,7 @@ async fn events_handler(
 
     // If we extracted a calibration above, send it already now.
     if let Some(cal_data) = cal_data {
-        let cal_body = get_body(&cal_data);
+        let cal_body = get_body(&cal_data, &app_state.clock);
         tx.send(Ok(Frame::data(cal_body.into()))).await.unwrap();
     }
 
@@ -58,6 +58,7 @@ struct ModelServerAppState {
     current_calibration: Arc<RwLock<Option<(String, TimeDataPassthrough)>>>,
     event_broadcaster: EventBroadcaster<usize>,
     next_connection_id: Arc<RwLock<usize>>,
+    clock: Clock,
 }
 
 impl Default for ModelServerAppState {
@@ -66,6 +67,7 @@ impl Default for ModelServerAppState {
             current_calibration: Arc::new(RwLock::new(None)),
             event_broadcaster: Default::default(),
             next_connection_id: Arc::new(RwLock::new(0)),
+            clock: Clock::Wall,
         }
     }
 }
@@ -143,10 +145,23 @@ pub struct ToListener {
 }
 
 pub async fn new_model_server(
+    data_rx: tokio::sync::mpsc::Receiver<(SendType, TimeDataPassthrough)>,
+    addr: std::net::SocketAddr,
+) -> Result<()> {
+    new_model_server_with_clock(data_rx, addr, Clock::Wall).await
+}
+
+/// Like [new_model_server], but the latency of the sent data is computed with
+/// the current time of `clock`.
+pub async fn new_model_server_with_clock(
     mut data_rx: tokio::sync::mpsc::Receiver<(SendType, TimeDataPassthrough)>,
     addr: std::net::SocketAddr,
+    clock: Clock,
 ) -> Result<()> {
-    let app_state = ModelServerAppState::default();
+    let app_state = ModelServerAppState {
+        clock,
+        ..Default::default()
+    };
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
 
@@ -249,10 +264,10 @@ pub async fn new_model_server(
     Ok(())
 }
 
-fn get_body(data: &(SendType, TimeDataPassthrough)) -> String {
+fn get_body(data: &(SendType, TimeDataPassthrough), clock: &Clock) -> String {
     let (msg, tdpt) = data;
     let latency: f64 = if let Some(ref tt) = tdpt.trigger_timestamp() {
-        let now_f64 = datetime_conversion::datetime_to_f64(&chrono::Local::now());
+        let now_f64 = datetime_conversion::datetime_to_f64(&clock.now());
         now_f64 - tt.as_f64()
     } else {
         f64::NAN
@@ -279,7 +294,7 @@ async fn send_msg(
     data: &(SendType, TimeDataPassthrough),
     app_state: &ModelServerAppState,
 ) -> Result<()> {
-    let buf = get_body(data);
+    let buf = get_body(data, &app_state.clock);
     app_state.event_broadcaster.broadcast_frame(buf).await;
     Ok(())
 }
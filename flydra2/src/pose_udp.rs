@@ -0,0 +1,187 @@
+//! Stream of live 3D pose updates as UDP datagrams.
+//!
+//! This is an alternative to the event stream of the model server for
+//! closed-loop experiments: datagrams have lower latency and are simple to
+//! receive from any language. Each datagram contains one JSON object of type
+//! [LivePoseMessage]. Every update of a Kalman estimate is sent as an
+//! `"estimate"` message:
+//!
+//! ```json
+//! {
+//!   "v": 1,
+//!   "type": "estimate",
+//!   "obj_id": 12,
+//!   "frame": 123456,
+//!   "timestamp": 1700000000.123,
+//!   "latency": 0.012,
+//!   "x": 0.1, "y": 0.2, "z": 0.3,
+//!   "xvel": 0.0, "yvel": 0.1, "zvel": -0.2,
+//!   "covariance_trace": 0.0001
+//! }
+//! ```
+//!
+//! `timestamp` is the trigger time of the frame in seconds since the UNIX
+//! epoch, or `null` if it is not known. `latency` is the time from the trigger
+//! until the message was sent, in seconds, or `null`. `covariance_trace` is
+//! the trace of the covariance of the position, in square meters. When an
+//! object is no longer tracked, a `"death"` message is sent:
+//!
+//! ```json
+//! {"v": 1, "type": "death", "obj_id": 12, "frame": 123500}
+//! ```
+//!
+//! UDP does not guarantee delivery. Messages may be lost, but are never
+//! delayed by slow receivers.
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info};
+
+use crate::{Result, SendType, TimeDataPassthrough};
+
+/// The version of the JSON schema of [LivePoseMessage], sent as `v`.
+pub const LIVE_POSE_SCHEMA_VERSION: u16 = 1;
+
+/// A message of the live pose stream.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LivePoseMessage {
+    /// The schema version, [LIVE_POSE_SCHEMA_VERSION].
+    pub v: u16,
+    #[serde(flatten)]
+    pub msg: LivePose,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LivePose {
+    /// A new or updated estimate of the state of an object.
+    Estimate(LiveEstimate),
+    /// The object is no longer tracked.
+    Death { obj_id: u32, frame: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LiveEstimate {
+    pub obj_id: u32,
+    pub frame: u64,
+    /// Trigger time of the frame, in seconds since the UNIX epoch.
+    pub timestamp: Option<f64>,
+    /// Time from the trigger until sending, in seconds.
+    pub latency: Option<f64>,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub xvel: f64,
+    pub yvel: f64,
+    pub zvel: f64,
+    /// Trace of the covariance of the position.
+    pub covariance_trace: f64,
+}
+
+impl LivePoseMessage {
+    /// The message for `data`, if it is sent in the live pose stream.
+    fn new(data: &(SendType, TimeDataPassthrough), now: f64) -> Option<Self> {
+        let (msg, tdpt) = data;
+        let msg = match msg {
+            SendType::Birth(row) | SendType::Update(row) => {
+                let timestamp = tdpt.trigger_timestamp().map(|t| t.as_f64());
+                LivePose::Estimate(LiveEstimate {
+                    obj_id: row.obj_id,
+                    frame: row.frame.0,
+                    timestamp,
+                    latency: timestamp.map(|t| now - t),
+                    x: row.x,
+                    y: row.y,
+                    z: row.z,
+                    xvel: row.xvel,
+                    yvel: row.yvel,
+                    zvel: row.zvel,
+                    covariance_trace: row.P00 + row.P11 + row.P22,
+                })
+            }
+            SendType::Death(obj_id) => LivePose::Death {
+                obj_id: *obj_id,
+                frame: tdpt.synced_frame().0,
+            },
+            SendType::EndOfFrame(_) | SendType::CalibrationFlydraXml(_) => return None,
+        };
+        Some(Self {
+            v: LIVE_POSE_SCHEMA_VERSION,
+            msg,
+        })
+    }
+}
+
+/// Sends the live pose stream.
+pub struct PoseUdpSender {
+    socket: tokio::net::UdpSocket,
+    destination: std::net::SocketAddr,
+    clock: crate::Clock,
+}
+
+impl PoseUdpSender {
+    /// Create a sender to `destination`, which may be a unicast or multicast
+    /// address. Multicast datagrams are sent with a time-to-live of
+    /// `multicast_ttl`.
+    pub async fn new(destination: std::net::SocketAddr, multicast_ttl: u32) -> Result<Self> {
+        let bind_addr: std::net::SocketAddr = if destination.is_ipv4() {
+            (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = tokio::net::UdpSocket::bind(bind_addr).await?;
+        if destination.ip().is_multicast() && destination.is_ipv4() {
+            socket.set_multicast_ttl_v4(multicast_ttl)?;
+        }
+        Ok(Self {
+            socket,
+            destination,
+            clock: crate::Clock::Wall,
+        })
+    }
+
+    /// Compute the latency of the sent poses with the current time of `clock`.
+    pub fn with_clock(self, clock: crate::Clock) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// Send the messages from `data_rx` until all its senders are dropped.
+    pub async fn run(
+        self,
+        mut data_rx: tokio::sync::mpsc::Receiver<(SendType, TimeDataPassthrough)>,
+    ) {
+        let destination = self.destination;
+        info!("Sending live 3D poses to udp://{destination}");
+        let mut n_errors: u64 = 0;
+        while let Some(data) = data_rx.recv().await {
+            let now = datetime_conversion::datetime_to_f64(&self.clock.now());
+            let Some(msg) = LivePoseMessage::new(&data, now) else {
+                continue;
+            };
+            let buf = serde_json::to_vec(&msg).unwrap();
+            if let Err(e) = self.socket.send_to(&buf, destination).await {
+                // Do not flood the log if the destination is unreachable.
+                if n_errors == 0 {
+                    error!("Sending live 3D pose to {destination} failed: {e}");
+                } else {
+                    debug!("Sending live 3D pose to {destination} failed: {e}");
+                }
+                n_errors += 1;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_live_pose_schema() {
+    let msg = LivePoseMessage {
+        v: LIVE_POSE_SCHEMA_VERSION,
+        msg: LivePose::Death {
+            obj_id: 12,
+            frame: 123500,
+        },
+    };
+    let buf = serde_json::to_string(&msg).unwrap();
+    assert_eq!(buf, r#"{"v":1,"type":"death","obj_id":12,"frame":123500}"#);
+    let msg2: LivePoseMessage = serde_json::from_str(&buf).unwrap();
+    assert_eq!(msg, msg2);
+}
@@ -17,8 +17,8 @@ use adskalman::ObservationModel as ObservationModelTrait;
 use adskalman::{StateAndCovariance, TransitionModelLinearNoControl};
 
 use flydra_types::{
-    CamNum, DataAssocRow, FlydraFloatTimestampLocal, FlydraRawUdpPoint, KalmanEstimatesRow,
-    RawCamName, SyncFno, TrackingParams, Triggerbox,
+    Appearance, CamNum, DataAssocRow, FlydraFloatTimestampLocal, FlydraRawUdpPoint,
+    KalmanEstimatesRow, RawCamName, SyncFno, TrackingParams, Triggerbox,
 };
 
 use crate::bundled_data::{MiniArenaPointPerCam, PerMiniArenaAllCamsOneFrameUndistorted};
@@ -151,6 +151,52 @@ struct LMInner {
     obj_id: u32,
     /// Initial start frame number
     _start_frame: SyncFno,
+    /// Recent appearance of the object in each camera.
+    appearance: BTreeMap<RawCamName, Appearance>,
+}
+
+/// Fraction by which a new observation changes the appearance of an object.
+const APPEARANCE_UPDATE_RATE: f64 = 0.1;
+
+impl LMInner {
+    /// Factor by which the likelihood of observing `pt` in camera `cam_name`
+    /// is scaled given the appearance of this object.
+    ///
+    /// This is 1.0 if the appearance is not known.
+    fn appearance_factor(
+        &self,
+        cam_name: &RawCamName,
+        pt: &FlydraRawUdpPoint,
+        appearance_weight: f64,
+    ) -> f64 {
+        if appearance_weight == 0.0 {
+            return 1.0;
+        }
+        match (self.appearance.get(cam_name), &pt.appearance) {
+            (Some(expected), Some(observed)) => {
+                (-appearance_weight * expected.distance(observed)).exp()
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// Update the appearance in camera `cam_name` with an observation.
+    fn update_appearance(&mut self, cam_name: &RawCamName, observed: Option<&Appearance>) {
+        let Some(observed) = observed else {
+            return;
+        };
+        let a = APPEARANCE_UPDATE_RATE;
+        self.appearance
+            .entry(cam_name.clone())
+            .and_modify(|current| {
+                current.mean_intensity =
+                    (1.0 - a) * current.mean_intensity + a * observed.mean_intensity;
+                for (bin, obs) in current.histogram.iter_mut().zip(observed.histogram.iter()) {
+                    *bin = (1.0 - a) * *bin + a * obs;
+                }
+            })
+            .or_insert_with(|| observed.clone());
+    }
 }
 
 impl LivingModel<ModelFrameStarted> {
@@ -210,6 +256,7 @@ impl LivingModel<ModelFrameStarted> {
         arena_bundle: &PerMiniArenaAllCamsOneFrameUndistorted,
         recon: &flydra_mvg::FlydraMultiCameraSystem<MyFloat>,
         ekf_observation_covariance_pixels: f64,
+        appearance_weight: f64,
     ) -> LivingModel<ModelFrameWithObservationLikes> {
         // for each camera with data:
         //  - compute likelihood of each real observation given expected observation
@@ -254,7 +301,15 @@ impl LivingModel<ModelFrameStarted> {
                                 // Compute the likelihood of this observation given our model.
                                 let likelihood = expected_observation.pdf(&obs.transpose())[0];
 
-                                nalgebra::convert(likelihood)
+                                // Penalize observations which do not look like
+                                // this object.
+                                let likelihood: f64 = nalgebra::convert(likelihood);
+                                likelihood
+                                    * self.lmi.appearance_factor(
+                                        cam_name,
+                                        &mappc.numbered_raw_udp_point.pt,
+                                        appearance_weight,
+                                    )
                             })
                             .collect()
                     } else {
@@ -624,6 +679,7 @@ impl ModelCollection<CollectionFrameStarted> {
                     arena_bundle,
                     &mcinner.recon,
                     mcinner.params.ekf_observation_covariance_pixels,
+                    mcinner.params.appearance_weight,
                 )
             })
             .collect();
@@ -844,6 +900,10 @@ impl ModelCollection<CollectionFrameWithObservationLikes> {
                             // );
 
                             next_model.state.data_assoc_this_timestamp.push(assoc);
+                            next_model.lmi.update_appearance(
+                                &cam_name,
+                                this_pt.numbered_raw_udp_point.pt.appearance.as_ref(),
+                            );
                         }
                     }
                 }
@@ -1039,6 +1099,7 @@ impl ModelCollection<CollectionFramePosteriors> {
                     lmi: LMInner {
                         obj_id,
                         _start_frame: tdpt.frame,
+                        appearance: BTreeMap::new(),
                     },
                 };
 
@@ -1125,3 +1186,44 @@ fn convert_pt(input: &flydra_types::FlydraRawUdpPoint) -> mvg::DistortedPixel<My
         coords: nalgebra::geometry::Point2::new(input.x0_abs, input.y0_abs),
     }
 }
+
+#[test]
+fn test_appearance_factor() {
+    let cam_name = RawCamName::new("cam1".to_string());
+    let other_cam_name = RawCamName::new("cam2".to_string());
+    let dark = Appearance::from_pixels([10, 20]).unwrap();
+    let bright = Appearance::from_pixels([240, 250]).unwrap();
+    let pt = |appearance: &Appearance| FlydraRawUdpPoint {
+        x0_abs: 0.0,
+        y0_abs: 0.0,
+        area: 1.0,
+        maybe_slope_eccentricty: None,
+        cur_val: 0,
+        mean_val: 0.0,
+        sumsqf_val: 0.0,
+        appearance: Some(appearance.clone()),
+    };
+
+    let mut lmi = LMInner {
+        obj_id: 0,
+        _start_frame: SyncFno(0),
+        appearance: BTreeMap::new(),
+    };
+    // Unknown appearance does not change the likelihood.
+    assert_eq!(lmi.appearance_factor(&cam_name, &pt(&bright), 10.0), 1.0);
+
+    lmi.update_appearance(&cam_name, Some(&dark));
+    assert_eq!(lmi.appearance_factor(&cam_name, &pt(&dark), 10.0), 1.0);
+    assert!(lmi.appearance_factor(&cam_name, &pt(&bright), 10.0) < 1e-3);
+    assert_eq!(lmi.appearance_factor(&cam_name, &pt(&bright), 0.0), 1.0);
+    assert_eq!(
+        lmi.appearance_factor(&other_cam_name, &pt(&bright), 10.0),
+        1.0
+    );
+
+    // The appearance changes slowly.
+    lmi.update_appearance(&cam_name, Some(&bright));
+    assert!(
+        lmi.appearance[&cam_name].distance(&dark) < lmi.appearance[&cam_name].distance(&bright)
+    );
+}
@@ -1,38 +1,37 @@
 use crate::*;
 use tracing::info;
 
-use std::io::Write;
+use std::{io::Write, path::Path};
 
 use flydra_types::{
-    BRAID_SCHEMA, CAM_SETTINGS_DIRNAME, FEATURE_DETECT_SETTINGS_DIRNAME, IMAGES_DIRNAME,
+    Provenance, BRAID_SCHEMA, CAM_SETTINGS_DIRNAME, FEATURE_DETECT_SETTINGS_DIRNAME,
 };
 
 struct WritingState {
-    output_dirname: std::path::PathBuf,
-    /// The readme file in the output directory.
-    ///
-    /// We keep this file open to establish locking on the open directory.
-    ///
-    /// In theory, we might prefer an open reference to the directory itself,
-    /// but this does not seem possible. So we have a potential slight race
-    /// condition when we have our directory but not yet the file handle on
-    /// readme.
-    #[allow(dead_code)]
-    readme_fd: Option<std::fs::File>,
+    /// The output directory. This is `None` once it was converted to the
+    /// `.braidz` file.
+    braidz_writer: Option<braidz_writer::BraidzWriter>,
     save_empty_data2d: bool,
     // kalman_estimates_wtr: Option<csv::Writer<Box<dyn std::io::Write>>>,
     kalman_estimates_wtr: Option<OrderingWriter>,
     data_assoc_wtr: Option<csv::Writer<Box<dyn std::io::Write + Send>>>,
     data_2d_wtr: csv::Writer<Box<dyn std::io::Write + Send>>,
+    /// Only created once a detection with appearance is saved.
+    data_2d_appearance_wtr: Option<csv::Writer<Box<dyn std::io::Write + Send>>>,
     textlog_wtr: csv::Writer<Box<dyn std::io::Write + Send>>,
     trigger_clock_info_wtr: csv::Writer<Box<dyn std::io::Write + Send>>,
     experiment_info_wtr: csv::Writer<Box<dyn std::io::Write + Send>>,
+    latency_budget_wtr: csv::Writer<Box<dyn std::io::Write + Send>>,
     writer_stats: Option<(usize, usize)>,
     file_start_time: std::time::SystemTime,
 
     reconstruction_latency_usec: Option<HistogramWritingState>,
     reproj_dist_pixels: Option<HistogramWritingState>,
     last_flush: std::time::Instant,
+    /// Source of the current time for the histogram timestamps.
+    clock: Clock,
+    /// Key to encrypt the `.braidz` file with once it is written.
+    encryption_key: Option<braidz_crypt::BraidzKey>,
 }
 
 fn _test_writing_state_is_send() {
@@ -52,13 +51,51 @@ impl BraidMetadataBuilder {
     pub fn saving_program_name<S: Into<String>>(saving_program_name: S) -> BraidMetadataBuilder {
         BraidMetadataBuilder::GenerateNew(MetadataParts {
             saving_program_name: saving_program_name.into(),
+            provenance: Vec::new(),
+            tracking_determinism: None,
         })
     }
+
+    /// Record that the saving program, or another program producing the
+    /// data, is `provenance`.
+    ///
+    /// The provenance of each camera is added when saving starts.
+    pub fn with_provenance(self, provenance: Provenance) -> BraidMetadataBuilder {
+        match self {
+            BraidMetadataBuilder::GenerateNew(mut parts) => {
+                parts.provenance.push(provenance);
+                BraidMetadataBuilder::GenerateNew(parts)
+            }
+            BraidMetadataBuilder::Existing(mut metadata) => {
+                metadata.provenance.push(provenance);
+                BraidMetadataBuilder::Existing(metadata)
+            }
+        }
+    }
+
+    /// Record that tracking runs in determinism mode with these settings.
+    pub fn with_tracking_determinism(
+        self,
+        tracking_determinism: TrackingDeterminism,
+    ) -> BraidMetadataBuilder {
+        match self {
+            BraidMetadataBuilder::GenerateNew(mut parts) => {
+                parts.tracking_determinism = Some(tracking_determinism);
+                BraidMetadataBuilder::GenerateNew(parts)
+            }
+            BraidMetadataBuilder::Existing(mut metadata) => {
+                metadata.tracking_determinism = Some(tracking_determinism);
+                BraidMetadataBuilder::Existing(metadata)
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct MetadataParts {
     saving_program_name: String,
+    provenance: Vec<Provenance>,
+    tracking_determinism: Option<TrackingDeterminism>,
 }
 
 impl WritingState {
@@ -69,6 +106,9 @@ impl WritingState {
         tracking_params: Arc<TrackingParams>,
         save_empty_data2d: bool,
         metadata_builder: BraidMetadataBuilder,
+        encryption_key: Option<braidz_crypt::BraidzKey>,
+        spill_dir: Option<&std::path::Path>,
+        clock: Clock,
     ) -> Result<Self> {
         let output_dirname = cfg.out_dir;
         let local = cfg.local;
@@ -78,240 +118,119 @@ impl WritingState {
 
         // Any changes to what is saved should update BraidMetadataSchemaTag.
 
-        // create output dir
-        std::fs::create_dir_all(&output_dirname)?;
-
-        // Until we obtain the readme file handle, we have a small race
-        // condition where another process could also open this directory.
-
-        let readme_fd = {
-            let readme_path = output_dirname.join(flydra_types::README_MD_FNAME);
-
-            let mut fd = std::fs::File::create(readme_path)?;
-
-            // Start and end it with some newlines so the text is more
-            // readable.
-            fd.write_all(
-                "\n\nThis is data saved by the braid program. \
-                See https://strawlab.org/braid for more information.\n\n"
-                    .as_bytes(),
-            )
-            .unwrap();
-            Some(fd)
-        };
-
-        {
-            let braid_metadata_path = output_dirname.join(flydra_types::BRAID_METADATA_YML_FNAME);
-
-            let metadata = match metadata_builder {
-                BraidMetadataBuilder::GenerateNew(parts) => {
-                    BraidMetadata {
-                        schema: BRAID_SCHEMA, // BraidMetadataSchemaTag
-                        git_revision: git_revision.clone(),
-                        original_recording_time: local,
-                        save_empty_data2d,
-                        saving_program_name: parts.saving_program_name,
-                    }
+        let metadata = match metadata_builder {
+            BraidMetadataBuilder::GenerateNew(parts) => {
+                let mut provenance = parts.provenance;
+                provenance.extend(
+                    per_cam_data
+                        .values()
+                        .filter_map(|cam| cam.provenance.clone()),
+                );
+                BraidMetadata {
+                    schema: BRAID_SCHEMA, // BraidMetadataSchemaTag
+                    git_revision: git_revision.clone(),
+                    original_recording_time: local,
+                    save_empty_data2d,
+                    saving_program_name: parts.saving_program_name,
+                    provenance,
+                    tracking_determinism: parts.tracking_determinism,
                 }
-                BraidMetadataBuilder::Existing(metadata) => metadata,
-            };
-            let metadata_buf = serde_yaml::to_string(&metadata).unwrap();
+            }
+            BraidMetadataBuilder::Existing(metadata) => metadata,
+        };
 
-            let mut fd = std::fs::File::create(braid_metadata_path)?;
-            fd.write_all(metadata_buf.as_bytes()).unwrap();
-        }
+        // create output dir with the README and metadata
+        let braidz_writer = match spill_dir {
+            Some(spill_dir) => braidz_writer::BraidzWriter::new_with_spill_dir(
+                &output_dirname,
+                &metadata,
+                spill_dir.to_path_buf(),
+            )?,
+            None => braidz_writer::BraidzWriter::new(&output_dirname, &metadata)?,
+        };
 
         // write images
-        {
-            let mut image_path = output_dirname.clone();
-            image_path.push(IMAGES_DIRNAME);
-            std::fs::create_dir_all(&image_path)?;
-
-            for (raw_cam_name, data) in per_cam_data.iter() {
-                let buf = data.current_image_png.as_slice();
-                let fname = format!("{}.png", raw_cam_name.as_str());
-                let fullpath = image_path.clone().join(fname);
-                let mut fd = std::fs::File::create(&fullpath)?;
-                fd.write_all(buf)?;
-            }
+        for (raw_cam_name, data) in per_cam_data.iter() {
+            braidz_writer.add_image(raw_cam_name.as_str(), data.current_image_png.as_slice())?;
         }
 
         // write camera settings
-        {
-            let mut cam_settings_path = output_dirname.clone();
-            cam_settings_path.push(CAM_SETTINGS_DIRNAME);
-            if per_cam_data
-                .iter()
-                .any(|(_, x)| x.cam_settings_data.is_some())
-            {
-                std::fs::create_dir_all(&cam_settings_path)?;
-            }
-
-            for (raw_cam_name, cam) in per_cam_data.iter() {
-                if let Some(data) = &cam.cam_settings_data {
-                    let fname = format!(
-                        "{}.{}",
-                        raw_cam_name.as_str(),
-                        data.current_cam_settings_extension
-                    );
-                    let fullpath = cam_settings_path.clone().join(fname);
-                    let mut fd = std::fs::File::create(&fullpath)?;
-                    fd.write_all(data.current_cam_settings_buf.as_bytes())?;
-                }
+        for (raw_cam_name, cam) in per_cam_data.iter() {
+            if let Some(data) = &cam.cam_settings_data {
+                let fname = format!(
+                    "{}.{}",
+                    raw_cam_name.as_str(),
+                    data.current_cam_settings_extension
+                );
+                let mut fd =
+                    braidz_writer.create_file(Path::new(CAM_SETTINGS_DIRNAME).join(fname))?;
+                fd.write_all(data.current_cam_settings_buf.as_bytes())?;
             }
         }
 
         // write feature detection settings
-        {
-            let mut feature_detect_settings_path = output_dirname.clone();
-            feature_detect_settings_path.push(FEATURE_DETECT_SETTINGS_DIRNAME);
-            if per_cam_data
-                .iter()
-                .any(|(_, x)| x.feature_detect_settings.is_some())
-            {
-                std::fs::create_dir_all(&feature_detect_settings_path)?;
-            }
-
-            for (raw_cam_name, cam) in per_cam_data.iter() {
-                if let Some(data) = &cam.feature_detect_settings {
-                    let buf = toml::to_vec(&data.current_feature_detect_settings)?;
-                    let fname = format!("{}.toml", raw_cam_name.as_str());
-                    let fullpath = feature_detect_settings_path.clone().join(fname);
-                    let mut fd = std::fs::File::create(&fullpath)?;
-                    fd.write_all(&buf)?;
+        for (raw_cam_name, cam) in per_cam_data.iter() {
+            if let Some(data) = &cam.feature_detect_settings {
+                let buf = toml::to_vec(&data.current_feature_detect_settings)?;
+                let fname = format!("{}.toml", raw_cam_name.as_str());
+                let mut fd = braidz_writer
+                    .create_file(Path::new(FEATURE_DETECT_SETTINGS_DIRNAME).join(fname))?;
+                if cam.feature_detect_settings_overridden {
+                    fd.write_all(
+                        b"# Local override: differs from the point_detection_config in the \
+                        Braid configuration.\n",
+                    )?;
                 }
+                fd.write_all(&buf)?;
             }
         }
 
         // write cam info (pairs of CamNum and cam name)
-        {
-            let mut csv_path = output_dirname.clone();
-            csv_path.push(format!("{}.gz", flydra_types::CAM_INFO_CSV_FNAME));
-            let fd = std::fs::File::create(&csv_path)?;
-            let fd: Box<dyn std::io::Write + Send> =
-                Box::new(AutoFinishUnchecked::new(Encoder::new(fd)?));
-            let mut cam_info_wtr = csv::Writer::from_writer(fd);
-            for row in cam_info_rows.iter() {
-                cam_info_wtr.serialize(row)?;
-            }
-        }
+        braidz_writer.write_table(flydra_types::CAM_INFO_CSV_FNAME, cam_info_rows.iter())?;
 
         // write calibration
         if let Some(ref recon) = recon {
-            let mut cal_path = output_dirname.clone();
-            cal_path.push(flydra_types::CALIBRATION_XML_FNAME);
-            let fd = std::fs::File::create(&cal_path)?;
+            let fd = braidz_writer.create_file(flydra_types::CALIBRATION_XML_FNAME)?;
             recon.to_flydra_xml(fd)?;
         }
 
         // open textlog and write initial message
-        let textlog_wtr = {
-            let timestamp = datetime_conversion::datetime_to_f64(&chrono::Local::now());
-
-            let fps = match fps {
-                Some(fps) => format!("{}", fps),
-                None => "unknown".to_string(),
-            };
-            let version = "2.0.0";
-            let tzname = iana_time_zone::get_timezone()?;
-            let message = format!(
-                "MainBrain running at {} fps, (\
-                flydra_version {}, git_revision {}, time_tzname0 {})",
-                fps, version, git_revision, tzname
-            );
-
-            let tps = TrackingParamsSaver {
-                tracking_params: (*tracking_params).clone(),
-                git_revision,
-            };
-            let message2 = serde_json::to_string(&tps)?;
-
-            let textlog: Vec<TextlogRow> = vec![
-                TextlogRow {
-                    mainbrain_timestamp: timestamp,
-                    cam_id: "mainbrain".to_string(),
-                    host_timestamp: timestamp,
-                    message,
-                },
-                TextlogRow {
-                    mainbrain_timestamp: timestamp,
-                    cam_id: "mainbrain".to_string(),
-                    host_timestamp: timestamp,
-                    message: message2,
-                },
-            ];
-
-            // We do not stream this to .gz because we want to maximize chances
-            // that it is completely flushed to disk even in event of a panic.
-            let mut csv_path = output_dirname.clone();
-            csv_path.push(flydra_types::TEXTLOG_CSV_FNAME);
-            let fd = std::fs::File::create(&csv_path)?;
-            let mut textlog_wtr =
-                csv::Writer::from_writer(Box::new(fd) as Box<dyn std::io::Write + Send>);
-            for row in textlog.iter() {
-                textlog_wtr.serialize(row)?;
-            }
-            // Flush to disk. In case braid crashes, at least we want to recover this info.
-            textlog_wtr.flush()?;
-            textlog_wtr
-        };
+        let textlog_wtr = braidz_writer.textlog_writer(fps, &git_revision, &tracking_params)?;
 
         // kalman estimates
         let kalman_estimates_wtr = if let Some(ref _recon) = recon {
-            let mut csv_path = output_dirname.clone();
-            csv_path.push(format!("{}.gz", flydra_types::KALMAN_ESTIMATES_CSV_FNAME));
-            let fd = std::fs::File::create(&csv_path)?;
-            let fd: Box<dyn std::io::Write + Send> =
-                Box::new(AutoFinishUnchecked::new(Encoder::new(fd)?));
-            Some(OrderingWriter::new(csv::Writer::from_writer(fd)))
+            Some(OrderingWriter::new(
+                braidz_writer.csv_gz_writer(flydra_types::KALMAN_ESTIMATES_CSV_FNAME)?,
+            ))
         } else {
             None
         };
 
-        let trigger_clock_info_wtr = {
-            let mut csv_path = output_dirname.clone();
-            csv_path.push(format!("{}.gz", flydra_types::TRIGGER_CLOCK_INFO_CSV_FNAME));
-            let fd = std::fs::File::create(&csv_path)?;
-            let fd: Box<dyn std::io::Write + Send> =
-                Box::new(AutoFinishUnchecked::new(Encoder::new(fd)?));
-            csv::Writer::from_writer(fd)
-        };
+        let trigger_clock_info_wtr =
+            braidz_writer.csv_gz_writer(flydra_types::TRIGGER_CLOCK_INFO_CSV_FNAME)?;
 
-        let experiment_info_wtr = {
-            // We do not stream this to .gz because we want to maximize chances
-            // that it is completely flushed to disk even in event of a panic.
-            let mut csv_path = output_dirname.clone();
-            csv_path.push(flydra_types::EXPERIMENT_INFO_CSV_FNAME);
-            let fd = std::fs::File::create(&csv_path)?;
-            csv::Writer::from_writer(Box::new(fd) as Box<dyn std::io::Write + Send>)
-        };
+        // We do not stream this to .gz because we want to maximize chances
+        // that it is completely flushed to disk even in event of a panic.
+        let experiment_info_wtr =
+            braidz_writer.csv_writer(flydra_types::EXPERIMENT_INFO_CSV_FNAME)?;
+
+        let latency_budget_wtr =
+            braidz_writer.csv_writer(flydra_types::LATENCY_BUDGET_CSV_FNAME)?;
 
         let data_assoc_wtr = if let Some(ref _recon) = recon {
-            let mut csv_path = output_dirname.clone();
-            csv_path.push(format!("{}.gz", flydra_types::DATA_ASSOCIATE_CSV_FNAME));
-            let fd = std::fs::File::create(&csv_path)?;
-            let fd: Box<dyn std::io::Write + Send> =
-                Box::new(AutoFinishUnchecked::new(Encoder::new(fd)?));
-            Some(csv::Writer::from_writer(fd))
+            Some(braidz_writer.csv_gz_writer(flydra_types::DATA_ASSOCIATE_CSV_FNAME)?)
         } else {
             None
         };
 
-        let data_2d_wtr = {
-            let mut csv_path = output_dirname.clone();
-            csv_path.push(format!("{}.gz", flydra_types::DATA2D_DISTORTED_CSV_FNAME));
-            let fd = std::fs::File::create(&csv_path)?;
-            let fd: Box<dyn std::io::Write + Send> =
-                Box::new(AutoFinishUnchecked::new(Encoder::new(fd)?));
-            csv::Writer::from_writer(fd)
-        };
+        let data_2d_wtr = braidz_writer.csv_gz_writer(flydra_types::DATA2D_DISTORTED_CSV_FNAME)?;
 
         let writer_stats = if cfg.print_stats { Some((0, 0)) } else { None };
 
         let file_start_time = if let Some(local) = local {
             local.into()
         } else {
-            std::time::SystemTime::now()
+            clock.now().into()
         };
 
         let (reconstruction_latency_usec, reproj_dist_pixels) = if cfg.save_performance_histograms {
@@ -324,24 +243,28 @@ impl WritingState {
         };
 
         Ok(Self {
-            output_dirname,
-            readme_fd,
+            braidz_writer: Some(braidz_writer),
             save_empty_data2d,
             kalman_estimates_wtr,
             data_assoc_wtr,
             data_2d_wtr,
+            data_2d_appearance_wtr: None,
             textlog_wtr,
             trigger_clock_info_wtr,
             experiment_info_wtr,
+            latency_budget_wtr,
             writer_stats,
             file_start_time,
             reconstruction_latency_usec,
             reproj_dist_pixels,
             last_flush: std::time::Instant::now(),
+            clock,
+            encryption_key,
         })
     }
 
     fn save_data_2d_distorted(&mut self, fdp: FrameDataAndPoints) -> Result<usize> {
+        self.save_data_2d_appearance(&fdp)?;
         let data2d_distorted = fdp.into_save(self.save_empty_data2d);
         for row in data2d_distorted.iter() {
             self.data_2d_wtr.serialize(row)?;
@@ -349,6 +272,32 @@ impl WritingState {
         Ok(data2d_distorted.len())
     }
 
+    fn save_data_2d_appearance(&mut self, fdp: &FrameDataAndPoints) -> Result<()> {
+        let frame_data = &fdp.frame_data;
+        for pt in fdp.points.iter() {
+            let Some(appearance) = &pt.pt.appearance else {
+                continue;
+            };
+            if self.data_2d_appearance_wtr.is_none() {
+                let Some(braidz_writer) = &self.braidz_writer else {
+                    return Ok(());
+                };
+                self.data_2d_appearance_wtr =
+                    Some(braidz_writer.csv_gz_writer(flydra_types::DATA2D_APPEARANCE_CSV_FNAME)?);
+            }
+            if let Some(wtr) = self.data_2d_appearance_wtr.as_mut() {
+                wtr.serialize(Data2dAppearanceRow::new(
+                    frame_data.cam_num,
+                    frame_data.synced_frame.0 as i64,
+                    pt.idx,
+                    pt.pt.area,
+                    appearance,
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
     fn flush_all(&mut self) -> Result<()> {
         if let Some(ref mut kew) = self.kalman_estimates_wtr {
             kew.flush()?;
@@ -357,9 +306,13 @@ impl WritingState {
             daw.flush()?;
         }
         self.data_2d_wtr.flush()?;
+        if let Some(ref mut daw) = self.data_2d_appearance_wtr {
+            daw.flush()?;
+        }
         self.textlog_wtr.flush()?;
         self.trigger_clock_info_wtr.flush()?;
         self.experiment_info_wtr.flush()?;
+        self.latency_budget_wtr.flush()?;
         self.last_flush = std::time::Instant::now();
         Ok(())
     }
@@ -383,18 +336,23 @@ impl Drop for WritingState {
         {
             self.kalman_estimates_wtr.take();
             self.data_assoc_wtr.take();
+            self.data_2d_appearance_wtr.take();
             // Could equivalently call `.flush()` on the writers?
             self.data_2d_wtr = dummy_csv();
             self.textlog_wtr = dummy_csv();
             self.trigger_clock_info_wtr = dummy_csv();
             self.experiment_info_wtr = dummy_csv();
+            self.latency_budget_wtr = dummy_csv();
         }
 
-        // Move out original output name so that a subsequent call to `drop()`
-        // doesn't accidentally overwrite our real data.
-        let output_dirname = std::mem::take(&mut self.output_dirname);
+        // Move out the writer so that a subsequent call to `drop()` doesn't
+        // accidentally overwrite our real data.
+        let Some(braidz_writer) = self.braidz_writer.take() else {
+            return;
+        };
+        let output_dirname = braidz_writer.dirname();
 
-        let now_system = std::time::SystemTime::now();
+        let now_system: std::time::SystemTime = self.clock.now().into();
         {
             if let Some(reconstruction_latency_usec) = &mut self.reconstruction_latency_usec {
                 finish_histogram(
@@ -406,7 +364,7 @@ impl Drop for WritingState {
                 .unwrap();
 
                 save_hlog(
-                    &output_dirname,
+                    output_dirname,
                     RECONSTRUCT_LATENCY_HLOG_FNAME,
                     &reconstruction_latency_usec.histograms,
                     self.file_start_time,
@@ -423,7 +381,7 @@ impl Drop for WritingState {
                 .unwrap();
 
                 save_hlog(
-                    &output_dirname,
+                    output_dirname,
                     REPROJECTION_DIST_HLOG_FNAME,
                     &reproj_dist_pixels.histograms,
                     self.file_start_time,
@@ -440,36 +398,46 @@ impl Drop for WritingState {
             // trajectory, those smoothing costs are amortized throughout the
             // experiment.
 
-            let replace_extension = match output_dirname.extension() {
-                Some(ext) => ext == "braid",
-                None => false,
-            };
-
-            // compute the name of the zip file.
-            let output_zipfile: std::path::PathBuf = if replace_extension {
-                output_dirname.with_extension("braidz")
-            } else {
-                let mut tmp = output_dirname.clone().into_os_string();
-                tmp.push(".braidz");
-                tmp.into()
-            };
-
-            info!("creating zip file {}", output_zipfile.display());
-            braidz_writer::dir_to_braidz(&output_dirname, output_zipfile).unwrap();
-
-            // Release the file so we no longer have exclusive access to the
-            // directory. (Until we remove the directory, we have a small race
-            // condition where another process could open the directory without
-            // obtaining the readme file handle.)
-            self.readme_fd = None;
-
-            // Once the original directory is written successfully to a zip
-            // file, we remove it.
             info!(
-                "done creating zip file, removing {}",
-                output_dirname.display()
+                "creating zip file {}",
+                braidz_writer.braidz_path().display()
             );
-            std::fs::remove_dir_all(&output_dirname).unwrap();
+            // Once the original directory is written successfully to a zip
+            // file, it is removed. If the directory could not be written
+            // completely, it is kept and a partial zip file is created
+            // locally.
+            let output_dirname = output_dirname.to_path_buf();
+            let output_zipfile = match braidz_writer.finish() {
+                Ok(output_zipfile) => output_zipfile,
+                Err(braidz_writer::Error::SavedLocally {
+                    dirname,
+                    braidz_path,
+                }) => {
+                    tracing::error!(
+                        "could not write all data to {}, a partial copy is saved to {}",
+                        dirname.display(),
+                        braidz_path.display()
+                    );
+                    braidz_path
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "could not create zip file of {}: {e}",
+                        output_dirname.display()
+                    );
+                    return;
+                }
+            };
+            info!("done creating zip file {}", output_zipfile.display());
+
+            if let Some(key) = &self.encryption_key {
+                info!(
+                    "encrypting {} with key {}",
+                    output_zipfile.display(),
+                    key.id()
+                );
+                braidz_crypt::encrypt_file_in_place(&output_zipfile, key).unwrap();
+            }
         }
     }
 }
@@ -488,6 +456,8 @@ pub(crate) fn writer_task_main(
     save_empty_data2d: bool,
     metadata_builder: BraidMetadataBuilder,
     ignore_latency: bool,
+    encryption_key: Option<braidz_crypt::BraidzKey>,
+    spill_dir: Option<std::path::PathBuf>,
 ) -> Result<()> {
     use crate::SaveToDiskMsg::*;
     use std::time::Duration;
@@ -535,7 +505,7 @@ pub(crate) fn writer_task_main(
                             // triggerbox clock model is first initializing.
                             use chrono::{DateTime, Utc};
                             let then: DateTime<Utc> = trigger_timestamp.into();
-                            let now = Utc::now();
+                            let now = ws.clock.now();
                             let elapsed = now.signed_duration_since(then);
                             let now_system: std::time::SystemTime = now.into();
 
@@ -570,7 +540,7 @@ pub(crate) fn writer_task_main(
 
                     {
                         if let Some(mean_reproj_dist_100x) = mean_reproj_dist_100x {
-                            let now_system = std::time::SystemTime::now();
+                            let now_system: std::time::SystemTime = ws.clock.now().into();
 
                             if let Some(reproj_dist_pixels) = &mut ws.reproj_dist_pixels {
                                 match histogram_record(
@@ -612,6 +582,9 @@ pub(crate) fn writer_task_main(
                     tracking_params.clone(),
                     save_empty_data2d,
                     metadata_builder.clone(),
+                    encryption_key.clone(),
+                    spill_dir.as_deref(),
+                    cam_manager.clock().clone(),
                 )?);
             }
             StopSavingCsv => {
@@ -637,6 +610,14 @@ pub(crate) fn writer_task_main(
                 }
                 // simply drop data if no file opened
             }
+            LatencyStats(rows) => {
+                if let Some(ref mut ws) = writing_state {
+                    for row in rows.iter() {
+                        ws.latency_budget_wtr.serialize(row)?;
+                    }
+                }
+                // simply drop data if no file opened
+            }
         }
 
         if let Some(ref mut ws) = writing_state {
@@ -690,6 +671,9 @@ mod test {
                 tracking_params,
                 save_empty_data2d,
                 BraidMetadataBuilder::saving_program_name(format!("{}:{}", file!(), line!())),
+                None,
+                None,
+                cam_manager.clock().clone(),
             )
             .unwrap();
 
@@ -775,6 +759,9 @@ mod test {
                 tracking_params,
                 save_empty_data2d,
                 BraidMetadataBuilder::saving_program_name(format!("{}:{}", file!(), line!())),
+                None,
+                None,
+                cam_manager.clock().clone(),
             )?;
 
             // Check that original directory exists.
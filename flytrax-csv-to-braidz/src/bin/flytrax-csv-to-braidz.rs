@@ -6,7 +6,10 @@ extern crate lazy_static;
 use anyhow::Context;
 
 use flydra_types::{MiniArenaConfig, XYGridConfig};
-use flytrax_csv_to_braidz::{parse_configs_and_run, PseudoCalParams, RowFilter};
+use flytrax_csv_to_braidz::{
+    parse_configs_and_run, parse_multi_camera_configs_and_run, row_filters_from_toml, CameraCsv,
+    PseudoCalParams, RowFilter,
+};
 
 use clap::Parser;
 
@@ -29,6 +32,9 @@ lazy_static! {
         format!(
             "This program will read a flytrax CSV file saved by strand-cam and, using \
             Kalman filtering and data association, track objects there.\n\n\
+            If several CSV files are given, one per camera, the detections of all \
+            cameras are aligned by their timestamps and tracked in 3D using a \
+            Braid XML calibration of all cameras.\n\n\
             \
             FURTHER INFORMATION:\n\n\
             # Information regarding Braid calibrations\n\n\
@@ -52,8 +58,11 @@ lazy_static! {
 #[command(author, version, about, after_help = VAL_HELP.as_str())]
 struct Cli {
     /// Input CSV file with 2D detections
-    #[arg(long = "csv", short = 'c')]
-    flytrax_csv: std::path::PathBuf,
+    ///
+    /// Give this option once per camera to track in 3D with several cameras.
+    /// This requires a Braid XML calibration including all cameras.
+    #[arg(long = "csv", short = 'c', required = true)]
+    flytrax_csv: Vec<std::path::PathBuf>,
     /// Output file, must end with '.braidz'
     #[arg(long = "output", short = 'o')]
     output_braidz: Option<std::path::PathBuf>,
@@ -100,9 +109,73 @@ struct Cli {
     #[arg(long = "include-all", short = 'a')]
     track_all_points_outside_calibration_region: bool,
 
+    /// TOML file with filters to exclude detections before tracking
+    ///
+    /// The filters are read from the `row_filters` array. Available filter
+    /// types are `time_range`, `in_pseudo_cal_region`, `min_area`,
+    /// `in_region`, `outside_region`, `any` and `not`. See the README for an
+    /// example.
+    #[arg(long)]
+    row_filters: Option<std::path::PathBuf>,
+
+    /// Name of the camera in the calibration, once per CSV file
+    ///
+    /// By default, the camera model saved in each CSV file is used. Only used
+    /// with several CSV files.
+    #[arg(long = "camera-name")]
+    camera_names: Vec<String>,
+
+    /// Time offset, in seconds, added to the timestamps of each CSV file
+    ///
+    /// Give this option once per CSV file to align the clocks of the cameras.
+    /// Only used with several CSV files.
+    #[arg(long = "time-offset", allow_negative_numbers = true)]
+    time_offsets: Vec<f64>,
+
+    /// Frame rate used to align the frames of several CSV files
+    ///
+    /// By default, the mean frame rate of the CSV files is used.
+    #[arg(long)]
+    fps: Option<f64>,
+
+    /// Refractive index of the water, to track through an air-water boundary
+    ///
+    /// This replaces the boundary in the calibration, if any. Objects below
+    /// the water surface are then located taking refraction into account.
+    #[arg(long)]
+    water_refractive_index: Option<f64>,
+
+    /// Height (z coordinate) of the water surface, in meters
+    #[arg(
+        long,
+        requires = "water_refractive_index",
+        allow_negative_numbers = true
+    )]
+    water_surface_z: Option<f64>,
+
+    /// Refractive index of the air above the water surface
+    #[arg(long, requires = "water_refractive_index", default_value_t = flydra_mvg::AIR_REFRACTION)]
+    air_refractive_index: f64,
+
     /// Hide the progress bar
     #[arg(long)]
     no_progress: bool,
+
+    /// Stitch trajectories broken into several obj_ids after tracking
+    ///
+    /// The `kalman_estimates` table then has an `original_obj_id` column with
+    /// the obj_id before stitching.
+    #[arg(long)]
+    stitch: bool,
+
+    /// Maximum number of frames between two stitched trajectories
+    #[arg(long, requires = "stitch", default_value_t = braidz_parser::stitch::StitchParams::default().max_gap_frames)]
+    stitch_max_gap_frames: u64,
+
+    /// Maximum distance, in meters, between the start of a stitched trajectory
+    /// and the position predicted from the end of the previous
+    #[arg(long, requires = "stitch", default_value_t = braidz_parser::stitch::StitchParams::default().max_distance)]
+    stitch_max_distance: f64,
 }
 
 #[tokio::main]
@@ -138,28 +211,151 @@ async fn open_files_and_run() -> anyhow::Result<()> {
         .tempdir()?;
 
     info!("strand-cam csv conversion to temporary flydra format:");
-    info!(
-        "  {} -> {}",
-        cli.flytrax_csv.display(),
-        flydra_csv_temp_dir.as_ref().display()
-    );
+    for flytrax_csv in cli.flytrax_csv.iter() {
+        info!(
+            "  {} -> {}",
+            flytrax_csv.display(),
+            flydra_csv_temp_dir.as_ref().display()
+        );
+    }
 
     let output_braidz = match cli.output_braidz {
         Some(op) => op,
-        None => cli.flytrax_csv.with_extension("braidz"), // replace '.csv' -> '.braidz'
+        None => cli.flytrax_csv[0].with_extension("braidz"), // replace '.csv' -> '.braidz'
+    };
+
+    let mut filters = Vec::new();
+
+    if !cli.track_all_points_outside_calibration_region {
+        filters.push(RowFilter::InPseudoCalRegion);
+    }
+
+    if let Some(ref fname) = cli.row_filters {
+        let buf = std::fs::read_to_string(fname)
+            .with_context(|| format!("reading row filters {}", fname.display()))?;
+        filters.extend(
+            row_filters_from_toml(&buf)
+                .with_context(|| format!("parsing row filters {}", fname.display()))?,
+        );
+    }
+
+    let refractive_boundary =
+        cli.water_refractive_index
+            .map(|n_water| flydra_mvg::RefractiveBoundary {
+                n_water,
+                n_air: cli.air_refractive_index,
+                z: cli.water_surface_z.unwrap_or(0.0),
+            });
+
+    let opt2 = braid_offline::KalmanizeOptions {
+        start_frame: cli.start_frame,
+        stop_frame: cli.stop_frame,
+        ..Default::default()
     };
 
-    let data_file = std::fs::File::open(&cli.flytrax_csv)
+    if cli.flytrax_csv.len() > 1 {
+        for (name, values) in [
+            ("--camera-name", cli.camera_names.len()),
+            ("--time-offset", cli.time_offsets.len()),
+        ] {
+            if values != 0 && values != cli.flytrax_csv.len() {
+                anyhow::bail!("{name} must be given once per CSV file or not at all");
+            }
+        }
+        let mut cameras = Vec::new();
+        for (i, flytrax_csv) in cli.flytrax_csv.iter().enumerate() {
+            let (image, _) = load_flytrax_image(flytrax_csv)?;
+            cameras.push(CameraCsv {
+                reader: open_csv(flytrax_csv)?,
+                camera_name: cli.camera_names.get(i).cloned(),
+                image,
+                time_offset_secs: cli.time_offsets.get(i).copied().unwrap_or(0.0),
+            });
+        }
+
+        parse_multi_camera_configs_and_run(
+            cameras,
+            Some(&flydra_csv_temp_dir),
+            &output_braidz,
+            cal_file_name,
+            tracking_params_buf.as_ref().map(AsRef::as_ref),
+            &filters,
+            refractive_boundary,
+            cli.fps,
+            cli.no_progress,
+            opt2,
+        )
+        .await?;
+    } else {
+        let flytrax_csv = cli.flytrax_csv[0].clone();
+        let point_detection_csv_reader = open_csv(&flytrax_csv)?;
+        let (flytrax_image, flytrax_jpeg_fname) = load_flytrax_image(&flytrax_csv)?;
+
+        let eargs = cli
+            .apriltags_3d_fiducial_coords
+            .map(
+                |apriltags_3d_fiducial_coords| flytrax_csv_to_braidz::ExtrinsicsArgs {
+                    apriltags_3d_fiducial_coords,
+                    flytrax_csv,
+                    image_filename: flytrax_jpeg_fname,
+                },
+            );
+
+        parse_configs_and_run(
+            point_detection_csv_reader,
+            Some(&flydra_csv_temp_dir),
+            flytrax_image,
+            &output_braidz,
+            cal_file_name,
+            tracking_params_buf.as_ref().map(AsRef::as_ref),
+            &filters,
+            refractive_boundary,
+            cli.no_progress,
+            eargs,
+            opt2,
+        )
+        .await?;
+    }
+
+    if cli.stitch {
+        let params = braidz_parser::stitch::StitchParams {
+            max_gap_frames: cli.stitch_max_gap_frames,
+            max_distance: cli.stitch_max_distance,
+        };
+        let stitched = output_braidz.with_extension("stitched.braidz");
+        let result = braidz_parser::stitch::stitch_braidz(&output_braidz, &stitched, &params)
+            .with_context(|| format!("stitching trajectories in {}", output_braidz.display()))?;
+        std::fs::rename(&stitched, &output_braidz)?;
+        info!(
+            "stitched {} trajectories into {}",
+            result.num_trajectories_before, result.num_trajectories_after
+        );
+    }
+
+    flydra_csv_temp_dir.close()?;
+
+    Ok(())
+}
+
+fn open_csv(flytrax_csv: &std::path::Path) -> anyhow::Result<std::io::BufReader<std::fs::File>> {
+    let data_file = std::fs::File::open(flytrax_csv)
         .map_err(anyhow::Error::from)
         .context(format!(
             "Could not open point detection csv file: {}",
-            cli.flytrax_csv.display()
+            flytrax_csv.display()
         ))?;
 
-    let point_detection_csv_reader = std::io::BufReader::new(data_file);
+    Ok(std::io::BufReader::new(data_file))
+}
 
+/// Load the image saved next to the CSV file, if it exists.
+///
+/// Also returns the filename of the image.
+fn load_flytrax_image(
+    flytrax_csv: &std::path::Path,
+) -> anyhow::Result<(Option<image::DynamicImage>, std::path::PathBuf)> {
     let mut flytrax_image = None;
-    let mut flytrax_jpeg_fname = cli.flytrax_csv.clone();
+    let mut flytrax_jpeg_fname = flytrax_csv.to_path_buf();
     flytrax_jpeg_fname.set_extension("jpg");
     if flytrax_jpeg_fname.exists() {
         let jpeg_buf = std::fs::read(&flytrax_jpeg_fname)
@@ -174,44 +370,5 @@ async fn open_files_and_run() -> anyhow::Result<()> {
             flytrax_jpeg_fname.display()
         );
     }
-
-    let mut filters = Vec::new();
-
-    if !cli.track_all_points_outside_calibration_region {
-        filters.push(RowFilter::InPseudoCalRegion);
-    }
-
-    let eargs = cli
-        .apriltags_3d_fiducial_coords
-        .map(
-            |apriltags_3d_fiducial_coords| flytrax_csv_to_braidz::ExtrinsicsArgs {
-                apriltags_3d_fiducial_coords,
-                flytrax_csv: cli.flytrax_csv,
-                image_filename: flytrax_jpeg_fname,
-            },
-        );
-
-    let opt2 = braid_offline::KalmanizeOptions {
-        start_frame: cli.start_frame,
-        stop_frame: cli.stop_frame,
-        ..Default::default()
-    };
-
-    parse_configs_and_run(
-        point_detection_csv_reader,
-        Some(&flydra_csv_temp_dir),
-        flytrax_image,
-        &output_braidz,
-        cal_file_name,
-        tracking_params_buf.as_ref().map(AsRef::as_ref),
-        &filters,
-        cli.no_progress,
-        eargs,
-        opt2,
-    )
-    .await?;
-
-    flydra_csv_temp_dir.close()?;
-
-    Ok(())
+    Ok((flytrax_image, flytrax_jpeg_fname))
 }
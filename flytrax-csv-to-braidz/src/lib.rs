@@ -9,7 +9,7 @@ use std::{
 };
 
 use braid_offline::KalmanizeOptions;
-use flydra_mvg::FlydraMultiCameraSystem;
+use flydra_mvg::{FlydraMultiCameraSystem, RefractiveBoundary};
 use serde::{Deserialize, Serialize};
 
 use flydra2::Data2dDistortedRow;
@@ -19,6 +19,9 @@ use strand_cam_pseudo_cal::PseudoCameraCalibrationData;
 
 use anyhow::{Context, Result};
 
+mod row_filter;
+pub use row_filter::{row_filters_from_toml, RowFilter, RowFilterConfig};
+
 enum CalibrationType {
     SimpleCal(PseudoCalParams),
     FullCal(Box<FlydraMultiCameraSystem<f64>>),
@@ -88,6 +91,7 @@ async fn kalmanize_2d<R>(
     tracking_params: TrackingParams,
     cal_file_name: &str,
     row_filters: &[RowFilter],
+    refractive_boundary: Option<RefractiveBoundary<f64>>,
     no_progress: bool,
     eargs: Option<ExtrinsicsArgs>,
     opt2: KalmanizeOptions,
@@ -116,7 +120,10 @@ where
     } else {
         anyhow::bail!("unrecognized file extension for calibration: \"{cal_file_name}\"");
     };
-    let recon = to_recon_func(&cfg, &cal_type)?;
+    let mut recon = to_recon_func(&cfg, &cal_type)?;
+    if refractive_boundary.is_some() {
+        recon.set_refractive_boundary(refractive_boundary);
+    }
 
     let images = {
         let mut images = BTreeMap::new();
@@ -153,11 +160,34 @@ where
 
     info!("    {} detected points converted.", num_points_converted);
 
-    let data_src =
-        braidz_parser::incremental_parser::IncrementalParser::open_dir(braid_csv_temp_dir.path())?;
+    track_braid_dir(
+        braid_csv_temp_dir.path(),
+        output_braidz,
+        tracking_params,
+        no_progress,
+        opt2,
+    )
+    .await?;
+
+    if let Some(t) = owned_temp_dir {
+        t.close()?;
+    }
+
+    Ok(())
+}
+
+/// Run the tracker on the 2D detections saved in `braid_dir`.
+async fn track_braid_dir(
+    braid_dir: &Path,
+    output_braidz: &Path,
+    tracking_params: TrackingParams,
+    no_progress: bool,
+    opt2: KalmanizeOptions,
+) -> Result<()> {
+    let data_src = braidz_parser::incremental_parser::IncrementalParser::open_dir(braid_dir)?;
     let data_src = data_src.parse_basics().context(format!(
         "Failed parsing initial braidz information from {}",
-        braid_csv_temp_dir.path().display()
+        braid_dir.display()
     ))?;
 
     let save_performance_histograms = false;
@@ -174,26 +204,9 @@ where
         None,
     )
     .await?;
-
-    if let Some(t) = owned_temp_dir {
-        t.close()?;
-    }
-
     Ok(())
 }
 
-/// These filters can be used to exclude data from being converted.
-#[derive(Clone)]
-pub enum RowFilter {
-    /// Row is in time interval between start and stop
-    InTimeInterval(
-        flydra_types::FlydraFloatTimestampLocal<flydra_types::HostClock>,
-        flydra_types::FlydraFloatTimestampLocal<flydra_types::HostClock>,
-    ),
-    /// Row is in region of calibration
-    InPseudoCalRegion,
-}
-
 fn convert_flytrax_csv_to_braid_csv_dir<R>(
     cfg: FullCfgFview2_0_26,
     recon: FlydraMultiCameraSystem<f64>,
@@ -207,74 +220,21 @@ where
     R: BufRead,
 {
     let ts0 = to_ts0(&cfg)?;
-    let git_revision = env!("GIT_HASH").to_string(); // or take from csv cfg?
 
     assert_eq!(recon.len(), 1);
 
-    // -------------------------------------------------
-    let mut cal_path: std::path::PathBuf = braid_csv_temp_dir.as_ref().to_path_buf();
-    cal_path.push(flydra_types::CALIBRATION_XML_FNAME);
-
-    // let cam_name: String = recon.cams().keys().next().unwrap().clone();
-
-    let fd = std::fs::File::create(&cal_path)?;
-    // save calibration.xml file
-    recon.to_flydra_xml(fd)?;
-
-    // -------------------------------------------------
-    // save cam_info.csv
-
-    let mut csv_path = braid_csv_temp_dir.as_ref().to_path_buf();
-    csv_path.push(flydra_types::CAM_INFO_CSV_FNAME);
-    let fd = std::fs::File::create(&csv_path)?;
-    let mut cam_info_wtr = csv::Writer::from_writer(fd);
-
-    let cam_name = recon.cam_names().next().unwrap();
-
-    let cam_info_rows: Vec<CamInfoRow> = vec![CamInfoRow {
-        cam_id: cam_name.to_string(),
-        camn: flydra_types::CamNum(0),
-    }];
-    for row in cam_info_rows.iter() {
-        cam_info_wtr.serialize(row)?;
-    }
-
-    // -------------------------------------------------
-    // save images/<cam>.png
-
-    {
-        let mut image_path = braid_csv_temp_dir.as_ref().to_path_buf();
-        image_path.push(flydra_types::IMAGES_DIRNAME);
-        std::fs::create_dir_all(&image_path)?;
-
-        for (cam_name, data) in images.iter() {
-            let fname = format!("{cam_name}.png");
-            let fullpath = image_path.clone().join(fname);
-            data.save(&fullpath)?;
-        }
-    }
-
-    // -------------------------------------------------
-    // save braid_metadata.yml
-
-    {
-        let braid_metadata_path = braid_csv_temp_dir
-            .as_ref()
-            .to_path_buf()
-            .join(flydra_types::BRAID_METADATA_YML_FNAME);
-
-        let metadata = braidz_types::BraidMetadata {
-            schema: flydra_types::BRAID_SCHEMA, // BraidMetadataSchemaTag
-            git_revision,
-            original_recording_time: Some(cfg.created_at),
-            save_empty_data2d: false, // We do filtering below, but is this correct?
-            saving_program_name: env!("CARGO_PKG_NAME").to_string(),
-        };
-        let metadata_buf = serde_yaml::to_string(&metadata)?;
-
-        let mut fd = std::fs::File::create(&braid_metadata_path)?;
-        fd.write_all(metadata_buf.as_bytes())?;
-    }
+    let cam_name = recon.cam_names().next().unwrap().to_string();
+    save_cameras(
+        braid_csv_temp_dir.path(),
+        &recon,
+        std::slice::from_ref(&cam_name),
+        &images,
+    )?;
+    save_braid_metadata(
+        braid_csv_temp_dir.path(),
+        cfg.created_at,
+        cfg.provenance.clone().into_iter().collect(),
+    )?;
 
     // -------------------------------------------------
     // save data2d_distorted.csv
@@ -296,69 +256,125 @@ where
         let record: Fview2CsvRecord = result?;
         let this_time = get_timestamp(&record, &ts0);
 
-        let mut keep_row = true;
-        for filter_row in row_filters.iter() {
-            match filter_row {
-                RowFilter::InTimeInterval(start, stop) => {
-                    if !(start.as_f64() <= this_time.as_f64()
-                        && this_time.as_f64() <= stop.as_f64())
-                    {
-                        keep_row = false;
-                        break;
-                    }
-                }
-                RowFilter::InPseudoCalRegion => {
-                    if let Some(pseudo_cal_params) = pseudo_cal_params.as_ref() {
-                        // reject points outside calibration region
-                        if !is_inside_calibration_region(&record, pseudo_cal_params) {
-                            keep_row = false;
-                            break;
-                        }
-                    }
-                }
-            }
-        }
-
-        if keep_row {
+        if keep_row(
+            &record,
+            &this_time,
+            &cam_name,
+            row_filters,
+            pseudo_cal_params,
+        ) {
             if ts0_f0.1 == -1 {
                 ts0_f0 = (this_time.as_f64(), record.frame);
             }
             ts1_f1 = (this_time.as_f64(), record.frame);
-            let save = convert_row(record, &ts0, &mut row_state);
+            let frame = record.frame;
+            let frame_pt_idx = row_state.update(frame);
+            let save = convert_row(&record, &ts0, flydra_types::CamNum(0), frame, frame_pt_idx);
             writer.serialize(save)?;
             count += 1;
         }
     }
 
+    let n_frames = ts1_f1.1 - ts0_f0.1 + 1;
+    let dur = ts1_f1.0 - ts0_f0.0;
+    let fps = n_frames as f64 / dur;
+    save_textlog(braid_csv_temp_dir.path(), &cfg.created_at, fps)?;
+
+    Ok(count)
+}
+
+/// Return true if the row of camera `cam_name` passes all `row_filters`.
+fn keep_row(
+    record: &Fview2CsvRecord,
+    this_time: &flydra_types::FlydraFloatTimestampLocal<flydra_types::HostClock>,
+    cam_name: &str,
+    row_filters: &[RowFilter],
+    pseudo_cal_params: Option<&PseudoCalParams>,
+) -> bool {
+    row_filters
+        .iter()
+        .all(|f| f.keep(record, this_time, cam_name, pseudo_cal_params))
+}
+
+/// Save the calibration, `cam_info.csv` and the camera images.
+///
+/// The camera number of each camera is its index in `cam_names`.
+fn save_cameras(
+    braid_dir: &Path,
+    recon: &FlydraMultiCameraSystem<f64>,
+    cam_names: &[String],
+    images: &BTreeMap<String, image::DynamicImage>,
+) -> Result<()> {
     // -------------------------------------------------
-    // save textlog.csv
-
-    {
-        let n_frames = ts1_f1.1 - ts0_f0.1 + 1;
-        let dur = ts1_f1.0 - ts0_f0.0;
-        let fps = n_frames as f64 / dur;
-        let timestamp = datetime_conversion::datetime_to_f64(&cfg.created_at);
-        let textlog_path = braid_csv_temp_dir
-            .as_ref()
-            .to_path_buf()
-            .join(flydra_types::TEXTLOG_CSV_FNAME);
-
-        let message = format!("MainBrain running at {} fps, ()", fps);
-
-        let record = TextlogRow {
-            mainbrain_timestamp: timestamp,
-            cam_id: "mainbrain".to_string(),
-            host_timestamp: timestamp,
-            message,
-        };
+    // save calibration.xml file
+    let fd = std::fs::File::create(braid_dir.join(flydra_types::CALIBRATION_XML_FNAME))?;
+    recon.to_flydra_xml(fd)?;
 
-        let fd: std::fs::File = std::fs::File::create(&textlog_path)?;
-        let mut textlog_wtr =
-            csv::Writer::from_writer(Box::new(fd) as Box<dyn std::io::Write + Send>);
-        textlog_wtr.serialize(record)?;
+    // -------------------------------------------------
+    // save cam_info.csv
+    let fd = std::fs::File::create(braid_dir.join(flydra_types::CAM_INFO_CSV_FNAME))?;
+    let mut cam_info_wtr = csv::Writer::from_writer(fd);
+    for (camn, cam_name) in cam_names.iter().enumerate() {
+        cam_info_wtr.serialize(CamInfoRow {
+            cam_id: cam_name.clone(),
+            camn: flydra_types::CamNum(camn.try_into()?),
+        })?;
     }
 
-    Ok(count)
+    // -------------------------------------------------
+    // save images/<cam>.png
+    let image_path = braid_dir.join(flydra_types::IMAGES_DIRNAME);
+    std::fs::create_dir_all(&image_path)?;
+    for (cam_name, data) in images.iter() {
+        data.save(image_path.join(format!("{cam_name}.png")))?;
+    }
+    Ok(())
+}
+
+/// Save `braid_metadata.yml`.
+///
+/// `provenance` are the programs which saved the CSV files.
+fn save_braid_metadata(
+    braid_dir: &Path,
+    original_recording_time: chrono::DateTime<chrono::Local>,
+    provenance: Vec<flydra_types::Provenance>,
+) -> Result<()> {
+    let metadata = braidz_types::BraidMetadata {
+        schema: flydra_types::BRAID_SCHEMA, // BraidMetadataSchemaTag
+        git_revision: env!("GIT_HASH").to_string(), // or take from csv cfg?
+        original_recording_time: Some(original_recording_time),
+        save_empty_data2d: false, // We do filtering below, but is this correct?
+        saving_program_name: env!("CARGO_PKG_NAME").to_string(),
+        provenance,
+        tracking_determinism: None,
+    };
+    let metadata_buf = serde_yaml::to_string(&metadata)?;
+
+    let mut fd = std::fs::File::create(braid_dir.join(flydra_types::BRAID_METADATA_YML_FNAME))?;
+    fd.write_all(metadata_buf.as_bytes())?;
+    Ok(())
+}
+
+/// Save `textlog.csv`, which stores the frame rate.
+fn save_textlog(
+    braid_dir: &Path,
+    created_at: &chrono::DateTime<chrono::Local>,
+    fps: f64,
+) -> Result<()> {
+    let timestamp = datetime_conversion::datetime_to_f64(created_at);
+    let message = format!("MainBrain running at {} fps, ()", fps);
+
+    let record = TextlogRow {
+        mainbrain_timestamp: timestamp,
+        cam_id: "mainbrain".to_string(),
+        host_timestamp: timestamp,
+        message,
+    };
+
+    let fd: std::fs::File = std::fs::File::create(braid_dir.join(flydra_types::TEXTLOG_CSV_FNAME))?;
+    let mut textlog_wtr = csv::Writer::from_writer(Box::new(fd) as Box<dyn std::io::Write + Send>);
+    textlog_wtr.serialize(record)?;
+    Ok(())
 }
 
 #[inline]
@@ -446,23 +462,24 @@ fn get_timestamp(
 
 // maybe use Data2dDistortedRowF32 ?
 fn convert_row(
-    strand_cam_row: Fview2CsvRecord,
+    strand_cam_row: &Fview2CsvRecord,
     ts0: &chrono::DateTime<chrono::Utc>,
-    row_state: &mut RowState,
+    camn: flydra_types::CamNum,
+    frame: i64,
+    frame_pt_idx: u8,
 ) -> Data2dDistortedRow {
     let (eccentricity, slope) = match strand_cam_row.orientation_radians_mod_pi {
         Some(angle) => (1.1, angle.tan()),
         None => (f64::NAN, f64::NAN),
     };
-    let frame_pt_idx = row_state.update(strand_cam_row.frame);
     Data2dDistortedRow {
         area: strand_cam_row.central_moment.unwrap_or(f64::NAN),
-        cam_received_timestamp: get_timestamp(&strand_cam_row, ts0),
+        cam_received_timestamp: get_timestamp(strand_cam_row, ts0),
         device_timestamp: None,
         block_id: None,
-        camn: flydra_types::CamNum(0),
+        camn,
         cur_val: 255,
-        frame: strand_cam_row.frame,
+        frame,
         eccentricity,
         frame_pt_idx,
         mean_val: f64::NAN,
@@ -503,6 +520,8 @@ pub struct ExtrinsicsArgs {
 /// - `output_braidz` is used to initially create a "braid dir" (typically
 ///   ending with `.braid` in the name). Upon closing, this directory will be
 ///   converted to a file that ends with `.braidz`.
+/// - If `refractive_boundary` is `Some(_)`, it replaces the air-water boundary
+///   of the calibration.
 #[allow(clippy::too_many_arguments)]
 pub async fn parse_configs_and_run<R>(
     point_detection_csv_reader: R,
@@ -512,6 +531,7 @@ pub async fn parse_configs_and_run<R>(
     cal_file_name: &str,
     tracking_params_buf: Option<&str>,
     row_filters: &[RowFilter],
+    refractive_boundary: Option<RefractiveBoundary<f64>>,
     no_progress: bool,
     eargs: Option<ExtrinsicsArgs>,
     opt2: KalmanizeOptions,
@@ -536,9 +556,246 @@ where
         tracking_params,
         cal_file_name,
         row_filters,
+        refractive_boundary,
         no_progress,
         eargs,
         opt2,
     )
     .await
 }
+
+/// The flytrax CSV file of one camera, see [parse_multi_camera_configs_and_run].
+pub struct CameraCsv<R> {
+    /// Reader of the CSV file with 2D detections.
+    pub reader: R,
+    /// The name of the camera in the calibration. Defaults to the camera model
+    /// saved in the header of the CSV file.
+    pub camera_name: Option<String>,
+    /// Image of the camera to save in the `.braidz` file.
+    pub image: Option<image::DynamicImage>,
+    /// Offset, in seconds, added to the timestamps of this camera to align them
+    /// with the other cameras.
+    pub time_offset_secs: f64,
+}
+
+/// The detections read from the CSV file of one camera.
+struct CameraRecords {
+    cam_name: String,
+    created_at: chrono::DateTime<chrono::Local>,
+    /// The time of `time_microseconds == 0`, including the time offset.
+    ts0: chrono::DateTime<chrono::Utc>,
+    records: Vec<Fview2CsvRecord>,
+    /// The program which saved the CSV file, if known.
+    provenance: Option<flydra_types::Provenance>,
+}
+
+impl CameraRecords {
+    /// Estimate the frame rate from the first and last detections.
+    fn estimate_fps(&self) -> Option<f64> {
+        let (first, last) = (self.records.first()?, self.records.last()?);
+        let n_frames = (last.frame - first.frame) as f64;
+        let dur = (last.time_microseconds - first.time_microseconds) as f64 * 1e-6;
+        (n_frames > 0.0 && dur > 0.0).then_some(n_frames / dur)
+    }
+}
+
+/// Parse the CSV files of several cameras and run the 3D tracker
+///
+/// `cal_file_name` must be a Braid XML calibration including all cameras.
+///
+/// The frames of the cameras are aligned using their timestamps, which assumes
+/// that the cameras acquired their frames synchronously (e.g. with a hardware
+/// trigger) and that their clocks agree, up to the time offset of each camera.
+/// A detection at time `t` gets the frame number `round((t - t0) * fps)`, where
+/// `t0` is the time of the earliest detection of all cameras. If `fps` is
+/// `None`, the mean frame rate of the CSV files is used.
+///
+/// If `tracking_params_buf` is `None`, the default parameters for 3D tracking
+/// are used. If `refractive_boundary` is `Some(_)`, it replaces the air-water
+/// boundary of the calibration.
+#[allow(clippy::too_many_arguments)]
+pub async fn parse_multi_camera_configs_and_run<R>(
+    cameras: Vec<CameraCsv<R>>,
+    flydra_csv_temp_dir: Option<&tempfile::TempDir>,
+    output_braidz: &std::path::Path,
+    cal_file_name: &str,
+    tracking_params_buf: Option<&str>,
+    row_filters: &[RowFilter],
+    refractive_boundary: Option<RefractiveBoundary<f64>>,
+    fps: Option<f64>,
+    no_progress: bool,
+    opt2: KalmanizeOptions,
+) -> Result<()>
+where
+    R: BufRead,
+{
+    let tracking_params = match tracking_params_buf {
+        Some(buf) => {
+            let tracking_params: flydra_types::TrackingParams =
+                toml::from_str(buf).map_err(anyhow::Error::from)?;
+            tracking_params
+        }
+        None => flydra_types::default_tracking_params_full_3d(),
+    };
+
+    if !cal_file_name.ends_with(".xml") {
+        anyhow::bail!("multiple cameras require a Braid XML calibration, not \"{cal_file_name}\"");
+    }
+    log::info!("reading calibration from file {}", cal_file_name);
+    let calibration_buf = std::fs::read_to_string(cal_file_name)
+        .with_context(|| format!("reading calibration file \"{}\"", cal_file_name))?;
+    let mut recon = FlydraMultiCameraSystem::from_flydra_xml(calibration_buf.as_bytes())?;
+    log::info!("loaded XML calibration with {} cameras", recon.len());
+    if refractive_boundary.is_some() {
+        recon.set_refractive_boundary(refractive_boundary);
+    }
+
+    // -------------------------------------------------
+    // read the detections of all cameras
+
+    let mut cams: Vec<CameraRecords> = Vec::new();
+    let mut images = BTreeMap::new();
+    for camera in cameras.into_iter() {
+        let mut reader = camera.reader;
+        let cfg = flytrax_io::read_csv_commented_header(&mut reader)?;
+        let cam_name = camera
+            .camera_name
+            .unwrap_or_else(|| get_cam_name(&cfg.camera).to_string());
+        if recon.cam_by_name(&cam_name).is_none() {
+            anyhow::bail!(
+                "camera \"{cam_name}\" not in calibration, which has cameras {:?}",
+                recon.cam_names().collect::<Vec<_>>()
+            );
+        }
+        if cams.iter().any(|c| c.cam_name == cam_name) {
+            anyhow::bail!("camera \"{cam_name}\" given more than once");
+        }
+        if let Some(image) = camera.image {
+            images.insert(cam_name.clone(), image);
+        }
+
+        let offset = chrono::Duration::microseconds((camera.time_offset_secs * 1e6).round() as i64);
+        let mut rdr = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .from_reader(reader);
+        let mut records = Vec::new();
+        for result in rdr.deserialize() {
+            let record: Fview2CsvRecord = result?;
+            records.push(record);
+        }
+        cams.push(CameraRecords {
+            cam_name,
+            created_at: cfg.created_at,
+            ts0: to_ts0(&cfg)? + offset,
+            records,
+            provenance: cfg.provenance,
+        });
+    }
+
+    // -------------------------------------------------
+    // align the frames of all cameras
+
+    let fps = match fps {
+        Some(fps) => fps,
+        None => {
+            let estimates: Vec<f64> = cams
+                .iter()
+                .filter_map(CameraRecords::estimate_fps)
+                .collect();
+            if estimates.is_empty() {
+                anyhow::bail!("cannot estimate the frame rate, please specify it");
+            }
+            let fps = estimates.iter().sum::<f64>() / estimates.len() as f64;
+            log::info!("estimated frame rate: {fps:.3} fps");
+            fps
+        }
+    };
+    if !(fps.is_finite() && fps > 0.0) {
+        anyhow::bail!("invalid frame rate: {fps}");
+    }
+
+    let mut rows = Vec::new();
+    for (camn, cam) in cams.iter().enumerate() {
+        for record in cam.records.iter() {
+            let this_time = get_timestamp(record, &cam.ts0);
+            if keep_row(record, &this_time, &cam.cam_name, row_filters, None) {
+                rows.push((this_time.as_f64(), camn, record));
+            }
+        }
+    }
+    let t0 = rows
+        .iter()
+        .map(|(t, _, _)| *t)
+        .fold(f64::INFINITY, f64::min);
+    let mut rows: Vec<(i64, usize, &Fview2CsvRecord)> = rows
+        .into_iter()
+        .map(|(t, camn, record)| (((t - t0) * fps).round() as i64, camn, record))
+        .collect();
+    // A stable sort keeps the order of the detections of each camera.
+    rows.sort_by_key(|(frame, camn, _)| (*frame, *camn));
+
+    // -------------------------------------------------
+    // save the braid directory
+
+    let mut owned_temp_dir = None;
+
+    let braid_csv_temp_dir = match flydra_csv_temp_dir {
+        Some(x) => x,
+        None => {
+            owned_temp_dir = Some(
+                tempfile::Builder::new()
+                    .prefix("tmp-strand-convert")
+                    .tempdir()?,
+            );
+            owned_temp_dir.as_ref().unwrap()
+        }
+    };
+    let braid_dir = braid_csv_temp_dir.path();
+
+    let cam_names: Vec<String> = cams.iter().map(|c| c.cam_name.clone()).collect();
+    save_cameras(braid_dir, &recon, &cam_names, &images)?;
+    let created_at = cams
+        .iter()
+        .map(|c| c.created_at)
+        .min()
+        .ok_or_else(|| anyhow::anyhow!("no camera CSV files given"))?;
+    let provenance = cams.iter().filter_map(|c| c.provenance.clone()).collect();
+    save_braid_metadata(braid_dir, created_at, provenance)?;
+    save_textlog(braid_dir, &created_at, fps)?;
+
+    let fd = std::fs::File::create(braid_dir.join(flydra_types::DATA2D_DISTORTED_CSV_FNAME))?;
+    let mut writer = csv::Writer::from_writer(fd);
+    let mut prev = None;
+    let mut frame_pt_idx = 0;
+    for (frame, camn, record) in rows.iter() {
+        if prev == Some((*frame, *camn)) {
+            frame_pt_idx += 1;
+        } else {
+            frame_pt_idx = 0;
+            prev = Some((*frame, *camn));
+        }
+        let camn = flydra_types::CamNum((*camn).try_into()?);
+        let save = convert_row(
+            record,
+            &cams[camn.0 as usize].ts0,
+            camn,
+            *frame,
+            frame_pt_idx,
+        );
+        writer.serialize(save)?;
+    }
+    writer.flush()?;
+    info!(
+        "    {} detected points from {} cameras converted.",
+        rows.len(),
+        cams.len()
+    );
+
+    track_braid_dir(braid_dir, output_braidz, tracking_params, no_progress, opt2).await?;
+
+    if let Some(t) = owned_temp_dir {
+        t.close()?;
+    }
+
+    Ok(())
+}
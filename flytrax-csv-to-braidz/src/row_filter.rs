@@ -0,0 +1,232 @@
+//! Filters to exclude detections before tracking.
+//!
+//! A detection is converted if it passes all filters given. Filters can be
+//! combined with [RowFilter::Any] and [RowFilter::Not], and can be read from
+//! the `row_filters` array of a TOML file with [row_filters_from_toml]:
+//!
+//! ```toml
+//! [[row_filters]]
+//! type = "min_area"
+//! min_area = 50.0
+//!
+//! [[row_filters]]
+//! type = "outside_region"
+//! camera = "Basler-22448739"
+//! points = [[0.0, 0.0], [100.0, 0.0], [100.0, 100.0], [0.0, 100.0]]
+//!
+//! [[row_filters]]
+//! type = "time_range"
+//! start = "2019-11-22T10:35:10+01:00"
+//! stop = "2019-11-22T10:36:00+01:00"
+//! ```
+
+use anyhow::Result;
+use http_video_streaming_types::PolygonParams;
+use serde::{Deserialize, Serialize};
+
+use crate::{is_inside_calibration_region, Fview2CsvRecord, PseudoCalParams};
+
+/// These filters can be used to exclude data from being converted.
+#[derive(Debug, Clone)]
+pub enum RowFilter {
+    /// Row is in time interval between start and stop
+    InTimeInterval(
+        flydra_types::FlydraFloatTimestampLocal<flydra_types::HostClock>,
+        flydra_types::FlydraFloatTimestampLocal<flydra_types::HostClock>,
+    ),
+    /// Row is in region of calibration
+    ///
+    /// Without a simple calibration, all rows pass.
+    InPseudoCalRegion,
+    /// Row has at least this area, i.e. central moment of the detected blob
+    ///
+    /// Rows without area fail.
+    MinArea(f64),
+    /// Row is inside the polygon, in pixels, if it is from `camera`
+    ///
+    /// Rows of other cameras pass. If `camera` is `None`, this applies to all
+    /// cameras.
+    InRegion {
+        camera: Option<String>,
+        region: PolygonParams,
+    },
+    /// Row is outside the polygon, in pixels, if it is from `camera`
+    ///
+    /// Rows of other cameras pass. If `camera` is `None`, this applies to all
+    /// cameras.
+    OutsideRegion {
+        camera: Option<String>,
+        region: PolygonParams,
+    },
+    /// Row passes at least one of the filters
+    Any(Vec<RowFilter>),
+    /// Row does not pass the filter
+    Not(Box<RowFilter>),
+}
+
+impl RowFilter {
+    /// Return true if the row of camera `cam_name` passes this filter.
+    pub(crate) fn keep(
+        &self,
+        record: &Fview2CsvRecord,
+        this_time: &flydra_types::FlydraFloatTimestampLocal<flydra_types::HostClock>,
+        cam_name: &str,
+        pseudo_cal_params: Option<&PseudoCalParams>,
+    ) -> bool {
+        let applies_to =
+            |camera: &Option<String>| camera.is_none() || camera.as_deref() == Some(cam_name);
+        match self {
+            RowFilter::InTimeInterval(start, stop) => {
+                start.as_f64() <= this_time.as_f64() && this_time.as_f64() <= stop.as_f64()
+            }
+            RowFilter::InPseudoCalRegion => match pseudo_cal_params {
+                // reject points outside calibration region
+                Some(pseudo_cal_params) => is_inside_calibration_region(record, pseudo_cal_params),
+                None => true,
+            },
+            RowFilter::MinArea(min_area) => {
+                record.central_moment.is_some_and(|area| area >= *min_area)
+            }
+            RowFilter::InRegion { camera, region } => {
+                !applies_to(camera) || is_inside_polygon(region, record.x_px, record.y_px)
+            }
+            RowFilter::OutsideRegion { camera, region } => {
+                !applies_to(camera) || !is_inside_polygon(region, record.x_px, record.y_px)
+            }
+            RowFilter::Any(filters) => filters
+                .iter()
+                .any(|f| f.keep(record, this_time, cam_name, pseudo_cal_params)),
+            RowFilter::Not(filter) => !filter.keep(record, this_time, cam_name, pseudo_cal_params),
+        }
+    }
+}
+
+/// A row filter in a TOML file, see [row_filters_from_toml].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, tag = "type", rename_all = "snake_case")]
+pub enum RowFilterConfig {
+    /// See [RowFilter::InTimeInterval].
+    TimeRange {
+        start: chrono::DateTime<chrono::FixedOffset>,
+        stop: chrono::DateTime<chrono::FixedOffset>,
+    },
+    /// See [RowFilter::InPseudoCalRegion].
+    InPseudoCalRegion,
+    /// See [RowFilter::MinArea].
+    MinArea { min_area: f64 },
+    /// See [RowFilter::InRegion].
+    InRegion {
+        #[serde(default)]
+        camera: Option<String>,
+        points: Vec<(f64, f64)>,
+    },
+    /// See [RowFilter::OutsideRegion].
+    OutsideRegion {
+        #[serde(default)]
+        camera: Option<String>,
+        points: Vec<(f64, f64)>,
+    },
+    /// See [RowFilter::Any].
+    Any { filters: Vec<RowFilterConfig> },
+    /// See [RowFilter::Not].
+    Not { filter: Box<RowFilterConfig> },
+}
+
+impl From<RowFilterConfig> for RowFilter {
+    fn from(cfg: RowFilterConfig) -> Self {
+        match cfg {
+            RowFilterConfig::TimeRange { start, stop } => {
+                RowFilter::InTimeInterval((&start).into(), (&stop).into())
+            }
+            RowFilterConfig::InPseudoCalRegion => RowFilter::InPseudoCalRegion,
+            RowFilterConfig::MinArea { min_area } => RowFilter::MinArea(min_area),
+            RowFilterConfig::InRegion { camera, points } => RowFilter::InRegion {
+                camera,
+                region: PolygonParams { points },
+            },
+            RowFilterConfig::OutsideRegion { camera, points } => RowFilter::OutsideRegion {
+                camera,
+                region: PolygonParams { points },
+            },
+            RowFilterConfig::Any { filters } => {
+                RowFilter::Any(filters.into_iter().map(Into::into).collect())
+            }
+            RowFilterConfig::Not { filter } => RowFilter::Not(Box::new((*filter).into())),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RowFiltersSection {
+    #[serde(default)]
+    row_filters: Vec<RowFilterConfig>,
+}
+
+/// Read the filters in the `row_filters` array of a TOML file.
+///
+/// Other keys of the file are ignored, so the filters can share a file with
+/// other configuration.
+pub fn row_filters_from_toml(buf: &str) -> Result<Vec<RowFilter>> {
+    let section: RowFiltersSection = toml::from_str(buf)?;
+    Ok(section.row_filters.into_iter().map(Into::into).collect())
+}
+
+/// Return true if the point is inside the polygon, using the even-odd rule.
+fn is_inside_polygon(polygon: &PolygonParams, x: f64, y: f64) -> bool {
+    let points = &polygon.points;
+    let mut inside = false;
+    for (i, (x1, y1)) in points.iter().enumerate() {
+        let (x0, y0) = points[(i + points.len() - 1) % points.len()];
+        if (*y1 > y) != (y0 > y) && x < x0 + (y - y0) * (x1 - x0) / (y1 - y0) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[test]
+fn test_row_filters() {
+    let filters = row_filters_from_toml(
+        r#"
+        [[row_filters]]
+        type = "min_area"
+        min_area = 50.0
+
+        [[row_filters]]
+        type = "outside_region"
+        camera = "cam1"
+        points = [[0.0, 0.0], [100.0, 0.0], [100.0, 100.0], [0.0, 100.0]]
+
+        [[row_filters]]
+        type = "not"
+        filter = { type = "in_region", points = [[0.0, 0.0], [10.0, 0.0], [0.0, 10.0]] }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(filters.len(), 3);
+
+    let this_time = flydra_types::FlydraFloatTimestampLocal::from_f64(0.0);
+    let keep = |x_px, y_px, central_moment, cam_name| {
+        let record = Fview2CsvRecord {
+            time_microseconds: 0,
+            frame: 0,
+            central_moment,
+            orientation_radians_mod_pi: None,
+            x_px,
+            y_px,
+        };
+        filters
+            .iter()
+            .all(|f| f.keep(&record, &this_time, cam_name, None))
+    };
+    assert!(keep(200.0, 200.0, Some(60.0), "cam1"));
+    // too small
+    assert!(!keep(200.0, 200.0, Some(40.0), "cam1"));
+    assert!(!keep(200.0, 200.0, None, "cam1"));
+    // in the excluded region of cam1 only
+    assert!(!keep(50.0, 50.0, Some(60.0), "cam1"));
+    assert!(keep(50.0, 50.0, Some(60.0), "cam2"));
+    // in the excluded triangle of all cameras
+    assert!(!keep(2.0, 2.0, Some(60.0), "cam2"));
+    assert!(keep(8.0, 8.0, Some(60.0), "cam2"));
+}
@@ -1,6 +1,8 @@
 use test_log::test;
 
-use flytrax_csv_to_braidz::{parse_configs_and_run, RowFilter};
+use flytrax_csv_to_braidz::{
+    parse_configs_and_run, parse_multi_camera_configs_and_run, CameraCsv, RowFilter,
+};
 
 #[test(tokio::test)]
 async fn test_run_end_to_end() {
@@ -27,6 +29,7 @@ async fn test_run_end_to_end() {
         CALIBRATION_PARAMS_FILENAME,
         tracking_params_buf,
         &row_filters,
+        None,
         true,
         None,
         braid_offline::KalmanizeOptions::default(),
@@ -70,6 +73,7 @@ async fn test_z_values_zero() {
         CALIBRATION_PARAMS_FILENAME,
         None,
         &row_filters,
+        None,
         true,
         None,
         braid_offline::KalmanizeOptions::default(),
@@ -99,6 +103,98 @@ async fn test_z_values_zero() {
     output_dir.close().unwrap();
 }
 
+#[test(tokio::test)]
+async fn test_multi_camera() {
+    const INPUT_CSV: &str = include_str!("data/flytrax20191122_103500.csv");
+    const CALIBRATION_FILENAME: &str = "tests/data/cal2cams.xml";
+
+    // Use the same detections for both cameras, delayed by one frame for the
+    // second camera.
+    let cameras = |names: [&str; 2]| {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| CameraCsv {
+                reader: INPUT_CSV.as_bytes(),
+                camera_name: Some(name.to_string()),
+                image: None,
+                time_offset_secs: i as f64 * 0.01,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Create unique dir for this test so we do not conflict with other
+    // concurrent tests.
+    let output_dir = tempfile::Builder::new().tempdir().unwrap();
+    // The output .braidz filename:
+    let output_braidz = output_dir.as_ref().join("out.braidz");
+
+    // A camera missing in the calibration is an error.
+    let result = parse_multi_camera_configs_and_run(
+        cameras(["cam1", "cam3"]),
+        None,
+        &output_braidz,
+        CALIBRATION_FILENAME,
+        None,
+        &[],
+        None,
+        Some(100.0),
+        true,
+        braid_offline::KalmanizeOptions::default(),
+    )
+    .await;
+    assert!(result.is_err());
+
+    parse_multi_camera_configs_and_run(
+        cameras(["cam1", "cam2"]),
+        None,
+        &output_braidz,
+        CALIBRATION_FILENAME,
+        None,
+        &[],
+        None,
+        Some(100.0),
+        true,
+        braid_offline::KalmanizeOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    let reader = zip_or_dir::ZipDirArchive::auto_from_path(output_braidz).unwrap();
+    let parsed = braidz_parser::braidz_parse(reader).unwrap();
+
+    assert_eq!(parsed.cam_info.camid2camn.len(), 2);
+    assert_eq!(parsed.data2d_distorted.as_ref().unwrap().qz.len(), 2);
+
+    // The air-water boundary given is saved with the calibration.
+    let water = flydra_mvg::RefractiveBoundary {
+        n_water: 1.333,
+        n_air: 1.0,
+        z: -0.01,
+    };
+    let output_braidz = output_dir.as_ref().join("water.braidz");
+    parse_multi_camera_configs_and_run(
+        cameras(["cam1", "cam2"]),
+        None,
+        &output_braidz,
+        CALIBRATION_FILENAME,
+        None,
+        &[],
+        Some(water),
+        Some(100.0),
+        true,
+        braid_offline::KalmanizeOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    let reader = zip_or_dir::ZipDirArchive::auto_from_path(output_braidz).unwrap();
+    let parsed = braidz_parser::braidz_parse(reader).unwrap();
+    assert_eq!(parsed.calibration_info.unwrap().water, Some(water));
+
+    output_dir.close().unwrap();
+}
+
 #[test(tokio::test)]
 async fn mini_arenas_with_apriltags() -> anyhow::Result<()> {
     const URL_BASE: &str = "https://strawlab-cdn.com/assets";
@@ -190,6 +286,7 @@ async fn mini_arenas_with_apriltags() -> anyhow::Result<()> {
         CHECKERBOARD_CAL_FNAME,
         tracking_params_buf.as_deref(),
         &row_filters,
+        None,
         true,
         eargs,
         opt2,
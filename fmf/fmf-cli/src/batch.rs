@@ -0,0 +1,122 @@
+//! Processing of several input files in one invocation.
+//!
+//! Export commands accept a glob pattern (e.g. `"*.fmf"`, quoted to prevent
+//! expansion by the shell) instead of a single input file. The matching files
+//! are processed by a pool of `--jobs` threads.
+
+use anyhow::Result;
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Command line options for processing several input files.
+#[derive(Debug, Clone, clap::Args)]
+pub(crate) struct BatchArgs {
+    /// Number of input files processed in parallel when the input is a glob
+    /// pattern
+    #[arg(short, long, default_value_t = 1, value_parser = parse_jobs)]
+    pub(crate) jobs: usize,
+}
+
+impl Default for BatchArgs {
+    fn default() -> Self {
+        Self { jobs: 1 }
+    }
+}
+
+fn parse_jobs(s: &str) -> std::result::Result<usize, String> {
+    let jobs: usize = s.parse().map_err(|e| format!("invalid number: {e}"))?;
+    if jobs > 0 {
+        Ok(jobs)
+    } else {
+        Err("number of jobs must be larger than zero".to_string())
+    }
+}
+
+/// Expand `pattern` into the matching input files.
+///
+/// A path without glob special characters is returned unchanged, so that a
+/// missing file is reported by the command itself.
+fn expand_inputs(pattern: &Path) -> Result<Vec<PathBuf>> {
+    let pattern_str = match pattern.to_str() {
+        Some(s) if s.contains(['*', '?', '[']) => s,
+        _ => return Ok(vec![pattern.to_path_buf()]),
+    };
+    let inputs = glob::glob(pattern_str)?.collect::<std::result::Result<Vec<_>, _>>()?;
+    if inputs.is_empty() {
+        anyhow::bail!("no files match \"{pattern_str}\"");
+    }
+    Ok(inputs)
+}
+
+/// Call `job` for each input file matching `pattern`.
+///
+/// A single input file is processed directly. Several input files are
+/// processed in parallel with a progress bar, and the output filenames are the
+/// defaults derived from the input filenames. An error with one file is
+/// printed and the remaining files are still processed.
+pub(crate) fn run<F>(pattern: &Path, output: Option<&Path>, args: &BatchArgs, job: F) -> Result<()>
+where
+    F: Fn(PathBuf) -> Result<()> + Sync,
+{
+    let mut inputs = expand_inputs(pattern)?;
+    if inputs.len() == 1 {
+        return job(inputs.pop().unwrap());
+    }
+    if let Some(output) = output {
+        anyhow::bail!(
+            "output {} given, but {} files match \"{}\"",
+            output.display(),
+            inputs.len(),
+            pattern.display()
+        );
+    }
+
+    let n_inputs = inputs.len();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()?;
+    // Custom progress bar with space at right end to prevent obscuring last
+    // digit with cursor.
+    let style = ProgressStyle::with_template("{wide_bar} {pos}/{len} ETA: {eta} ")?;
+    let pb = ProgressBar::new(n_inputs.try_into().unwrap()).with_style(style);
+    let n_failed = pool.install(|| {
+        inputs
+            .into_par_iter()
+            .filter(|input| {
+                let result = job(input.clone());
+                if let Err(e) = &result {
+                    pb.println(format!("{}: {e:#}", input.display()));
+                }
+                pb.inc(1);
+                result.is_err()
+            })
+            .count()
+    });
+    pb.finish_and_clear();
+
+    if n_failed > 0 {
+        anyhow::bail!("{n_failed} of {n_inputs} files failed");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_expand_inputs() -> Result<()> {
+    let tmpdir = tempfile::tempdir()?;
+    for name in ["a.fmf", "b.fmf", "c.txt"] {
+        std::fs::write(tmpdir.path().join(name), b"")?;
+    }
+
+    let pattern = tmpdir.path().join("*.fmf");
+    let inputs = expand_inputs(&pattern)?;
+    assert_eq!(
+        inputs,
+        vec![tmpdir.path().join("a.fmf"), tmpdir.path().join("b.fmf")]
+    );
+
+    let missing = tmpdir.path().join("missing.fmf");
+    assert_eq!(expand_inputs(&missing)?, vec![missing]);
+    assert!(expand_inputs(&tmpdir.path().join("*.mp4")).is_err());
+    Ok(())
+}
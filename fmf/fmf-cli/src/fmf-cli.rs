@@ -4,14 +4,24 @@ extern crate log;
 use anyhow::Result;
 
 use basic_frame::{match_all_dynamic_fmts, DynamicFrame};
-use ci2_remote_control::{Mp4RecordingConfig, NvidiaH264Options, OpenH264Options};
-use clap::Parser;
+use ci2_remote_control::{
+    H264Metadata, Mp4RecordingConfig, NvencPreset, NvencRateControl, NvencTuning,
+    NvidiaH264Options, OpenH264Options,
+};
+use clap::{Parser, ValueEnum};
 use convert_image::EncoderOptions;
 use machine_vision_formats::{pixel_format, pixel_format::PixFmt, Stride};
 use std::path::{Path, PathBuf};
 use timestamped_frame::ExtraTimeData;
 use y4m::Colorspace;
 
+mod batch;
+use batch::BatchArgs;
+mod transform;
+use transform::TransformArgs;
+mod ufmf_convert;
+use ufmf_convert::{ExportUfmf, ImportUfmf};
+
 /*
 
 Examples of exporting from FMF to MKV with `ffv1` codec. Note these all loose
@@ -36,7 +46,7 @@ video in ffmpeg with the option `-metadata creation_time="2012-02-07 12:15:27"`.
 However, as of the time of writing, ffmpeg only parses the command line date to
 the second (whereas the MKV spec allows better precision).
 
-Example export to mp4:
+Example export to mp4 (keeps timestamps):
 
     fmf export-mp4 test_rgb8.fmf -o /tmp/test.mp4
 
@@ -87,12 +97,18 @@ enum Opt {
         #[arg(long)]
         forced_input_pixel_format: Option<PixFmt>,
 
-        /// Filename of input fmf
+        /// Filename of input fmf, or a glob pattern for several files
         input: PathBuf,
 
         /// Filename of output .fmf, "-" for stdout
         #[arg(short, long)]
         output: Option<PathBuf>,
+
+        #[command(flatten)]
+        transform: TransformArgs,
+
+        #[command(flatten)]
+        batch: BatchArgs,
     },
 
     /// print information about an fmf file
@@ -103,18 +119,24 @@ enum Opt {
 
     /// export a sequence of jpeg images
     ExportJpeg {
-        /// Filename of input fmf
+        /// Filename of input fmf, or a glob pattern for several files
         input: PathBuf,
 
         /// Quality (1-100 where 1 is the worst and 100 is the best)
         #[arg(short, long, default_value = "99")]
         quality: u8,
+
+        #[command(flatten)]
+        batch: BatchArgs,
     },
 
     /// export a sequence of png images
     ExportPng {
-        /// Filename of input fmf
+        /// Filename of input fmf, or a glob pattern for several files
         input: PathBuf,
+
+        #[command(flatten)]
+        batch: BatchArgs,
     },
 
     /// export to y4m (YUV4MPEG2) format
@@ -132,13 +154,103 @@ enum Opt {
         #[arg(short, long)]
         output: PathBuf,
     },
+
+    /// concatenate fmf files with identical image size and pixel format
+    Concat {
+        /// Filenames of input fmf files, in order
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Filename of output fmf
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+
+    /// save a range of frames to a new fmf file
+    Cut(Cut),
+
+    /// import a video (.mp4, .mkv or .h264), converting it to an FMF file
+    ImportVideo(ImportVideo),
+
+    /// export to ufmf, saving only the regions which differ from the
+    /// background
+    ExportUfmf(ExportUfmf),
+
+    /// import a ufmf file, converting it to an FMF file with full frames
+    ImportUfmf(ImportUfmf),
 }
 
 #[derive(Parser, Debug)]
-struct ExportY4m {
+struct ImportVideo {
+    /// Filename of input video
+    input: PathBuf,
+
+    /// Filename of output .fmf
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Source of frame timestamps
+    #[arg(long, value_enum, default_value_t)]
+    timestamp_source: TimestampSource,
+
+    /// Time of the first frame (e.g. "2024-01-01T12:00:00+01:00")
+    ///
+    /// By default, this is taken from the video.
+    #[arg(long)]
+    start_time: Option<chrono::DateTime<chrono::FixedOffset>>,
+}
+
+#[derive(Default, Debug, Clone, Copy, ValueEnum, PartialEq)]
+enum TimestampSource {
+    #[default]
+    BestGuess,
+    FrameInfoRecvTime,
+    Mp4Pts,
+    MispMicrosectime,
+}
+
+impl From<TimestampSource> for frame_source::TimestampSource {
+    fn from(orig: TimestampSource) -> Self {
+        match orig {
+            TimestampSource::BestGuess => frame_source::TimestampSource::BestGuess,
+            TimestampSource::FrameInfoRecvTime => frame_source::TimestampSource::FrameInfoRecvTime,
+            TimestampSource::Mp4Pts => frame_source::TimestampSource::Mp4Pts,
+            TimestampSource::MispMicrosectime => frame_source::TimestampSource::MispMicrosectime,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+struct Cut {
     /// Filename of input fmf
     input: PathBuf,
 
+    /// Filename of output .fmf
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// First frame to save (the first frame of the file is 0)
+    #[arg(long, conflicts_with_all = ["start_secs", "stop_secs"])]
+    start_frame: Option<usize>,
+
+    /// Stop before this frame
+    #[arg(long, conflicts_with_all = ["start_secs", "stop_secs"])]
+    stop_frame: Option<usize>,
+
+    /// Save frames from this many seconds after the first frame
+    #[arg(long)]
+    start_secs: Option<f64>,
+
+    /// Stop at this many seconds after the first frame
+    #[arg(long)]
+    stop_secs: Option<f64>,
+}
+
+#[derive(Parser, Debug, Clone)]
+struct ExportY4m {
+    /// Filename of input fmf, or a glob pattern for several files
+    input: PathBuf,
+
     /// Filename of output .y4m, "-" for stdout
     #[arg(short, long)]
     output: Option<PathBuf>,
@@ -162,6 +274,12 @@ struct ExportY4m {
     /// aspect ratio denominator
     #[arg(long, default_value = "1")]
     aspect_denominator: u32,
+
+    #[command(flatten)]
+    transform: TransformArgs,
+
+    #[command(flatten)]
+    batch: BatchArgs,
 }
 
 fn str_to_colorspace(s: &str) -> anyhow::Result<Colorspace> {
@@ -172,9 +290,9 @@ fn str_to_colorspace(s: &str) -> anyhow::Result<Colorspace> {
     }
 }
 
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 struct ExportMp4 {
-    /// Filename of input fmf
+    /// Filename of input fmf, or a glob pattern for several files
     input: PathBuf,
 
     /// Filename of output .mp4, "-" for stdout
@@ -188,6 +306,47 @@ struct ExportMp4 {
     /// video codec
     #[arg(long, default_value = "vp9", help=VALID_CODECS)]
     codec: Codec,
+
+    /// Camera name to save in the MP4 metadata
+    ///
+    /// By default, the camera name is taken from the filename if it was saved
+    /// by Strand Camera with the default filename template.
+    #[arg(long)]
+    camera_name: Option<String>,
+
+    #[command(flatten)]
+    nvenc: NvencArgs,
+
+    #[command(flatten)]
+    transform: TransformArgs,
+
+    #[command(flatten)]
+    batch: BatchArgs,
+}
+
+/// Command line options for the nvenc-h264 codec.
+#[derive(Debug, Clone, Default, clap::Args)]
+struct NvencArgs {
+    /// NVENC preset: hp, or p1 (fastest) to p7 (highest quality)
+    #[arg(long)]
+    nvenc_preset: Option<NvencPreset>,
+
+    /// NVENC tuning of the presets p1 to p7: high-quality, low-latency,
+    /// ultra-low-latency or lossless
+    #[arg(long)]
+    nvenc_tuning: Option<NvencTuning>,
+
+    /// NVENC rate control: vbr, cbr or cqp:<QP> for a constant QP
+    #[arg(long)]
+    nvenc_rate_control: Option<NvencRateControl>,
+
+    /// NVENC number of frames from one keyframe to the next
+    #[arg(long)]
+    nvenc_gop_length: Option<u32>,
+
+    /// NVENC number of B-frames between reference frames
+    #[arg(long)]
+    nvenc_b_frames: Option<u32>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -229,6 +388,26 @@ impl std::str::FromStr for Autocrop {
     }
 }
 
+/// Guess the camera name from a filename saved by Strand Camera with the
+/// default template `movie%Y%m%d_%H%M%S.%f_{CAMNAME}.fmf`.
+fn camera_name_from_filename(path: &Path) -> Option<String> {
+    let fname = path.file_name()?.to_str()?;
+    let stem = fname
+        .strip_suffix(".fmf.gz")
+        .or_else(|| fname.strip_suffix(".fmf"))?;
+    let (date, rest) = stem.strip_prefix("movie")?.split_once('_')?;
+    let (time, camera_name) = rest.split_once('_')?;
+    let (hms, frac) = time.split_once('.')?;
+    let is_digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    if date.len() != 8 || hms.len() != 6 || ![date, hms, frac].into_iter().all(is_digits) {
+        return None;
+    }
+    if camera_name.is_empty() {
+        return None;
+    }
+    Some(camera_name.to_string())
+}
+
 /// convert None into default name, convert "-" into None (for stdout)
 fn default_filename(path: &Path, output: Option<PathBuf>, ext: &str) -> Option<PathBuf> {
     match output {
@@ -284,12 +463,14 @@ fn info(path: PathBuf) -> Result<()> {
 ///
 /// If the `forced_input_pixel_format` argument is not None, it forces the
 /// interpretation of the original data into this format regardless of the pixel
-/// format specied in the header of the input file.
+/// format specied in the header of the input file. The `transform` is applied
+/// before converting to `new_pixel_format`.
 fn export_fmf(
     path: PathBuf,
     new_pixel_format: Option<PixFmt>,
     output: Option<PathBuf>,
     forced_input_pixel_format: Option<PixFmt>,
+    transform: &TransformArgs,
 ) -> Result<()> {
     let output_fname = default_filename(&path, output, "fmf");
 
@@ -312,6 +493,7 @@ fn export_fmf(
             Some(forced_input_pixel_format) => frame.force_pixel_format(forced_input_pixel_format),
             None => frame,
         };
+        let frame = transform.apply(frame)?;
 
         let fmt = match new_pixel_format {
             Some(new_pixel_format) => new_pixel_format,
@@ -337,6 +519,192 @@ fn import_images(pattern: &str, output_fname: PathBuf) -> Result<()> {
     Ok(())
 }
 
+/// Write the frames of all `inputs` to a new FMF file.
+///
+/// All frames must have the same size and pixel format and the timestamps must
+/// not decrease. Otherwise, an error is returned and the incomplete output file
+/// is removed.
+fn concat(inputs: &[PathBuf], output: &Path) -> Result<()> {
+    info!(
+        "concatenating {} files to {}",
+        inputs.len(),
+        output.display()
+    );
+    let result = concat_inner(inputs, output);
+    if result.is_err() {
+        std::fs::remove_file(output).ok();
+    }
+    result
+}
+
+fn concat_inner(inputs: &[PathBuf], output: &Path) -> Result<()> {
+    let f = std::fs::File::create(output)?;
+    let mut writer = fmf::FMFWriter::new(f)?;
+
+    let mut first: Option<(u32, u32, PixFmt)> = None;
+    let mut prev_stamp = None;
+    for input in inputs.iter() {
+        let reader = fmf::FMFReader::new(input)?;
+        for (fno, frame) in reader.enumerate() {
+            let frame = frame?;
+            let shape = (frame.width(), frame.height(), frame.pixel_format());
+            let (w, h, pixfmt) = *first.get_or_insert(shape);
+            if shape != (w, h, pixfmt) {
+                anyhow::bail!(
+                    "{} frame {fno}: {}x{} {} differs from {w}x{h} {pixfmt} of the first frame",
+                    input.display(),
+                    shape.0,
+                    shape.1,
+                    shape.2,
+                );
+            }
+            let stamp = frame.extra().host_timestamp();
+            if let Some(prev) = prev_stamp {
+                if stamp < prev {
+                    anyhow::bail!(
+                        "{} frame {fno}: timestamp {stamp} is before the previous frame ({prev})",
+                        input.display()
+                    );
+                }
+            }
+            prev_stamp = Some(stamp);
+            match_all_dynamic_fmts!(frame, f, writer.write(&f, stamp)?);
+        }
+    }
+    writer.close()?;
+    Ok(())
+}
+
+/// Write the frames of `x.input` within the requested range to a new FMF file.
+///
+/// Time ranges are relative to the timestamp of the first frame. The start is
+/// inclusive and the stop is exclusive. Uncompressed files are indexed to seek
+/// directly to the start of the range.
+fn cut(x: Cut) -> Result<()> {
+    let output_fname = match default_filename(&x.input, x.output, "fmf") {
+        Some(output_fname) => output_fname,
+        None => anyhow::bail!("cannot write fmf file to stdout"),
+    };
+
+    info!(
+        "saving frames of {} to {}",
+        x.input.display(),
+        output_fname.display()
+    );
+    type Frames = Box<dyn Iterator<Item = fmf::FMFResult<DynamicFrame>>>;
+    let (frames, first_fno, mut frame0_stamp) = match fmf::IndexedFMFReader::new(&x.input) {
+        Ok(mut reader) => {
+            let frame0_stamp = reader.index().timestamp(0);
+            let mut first_fno = x.start_frame.unwrap_or(0).min(reader.len());
+            if let (Some(start_secs), Some(frame0_stamp)) = (x.start_secs, frame0_stamp) {
+                let start =
+                    frame0_stamp + chrono::Duration::nanoseconds((start_secs * 1e9).round() as i64);
+                // Start one frame early to not depend on the rounding of
+                // the timestamps. Frames before the start are skipped
+                // below.
+                first_fno = reader.index().frame_at_time(&start).saturating_sub(1);
+            }
+            reader.seek_to_frame(first_fno)?;
+            (Box::new(reader) as Frames, first_fno, frame0_stamp)
+        }
+        Err(fmf::FMFError::NotSeekable(_)) => {
+            (Box::new(fmf::FMFReader::new(&x.input)?) as Frames, 0, None)
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let f = std::fs::File::create(&output_fname)?;
+    let mut writer = fmf::FMFWriter::new(f)?;
+
+    let mut n_saved = 0;
+    for (fno, frame) in (first_fno..).zip(frames) {
+        let frame = frame?;
+        let stamp = frame.extra().host_timestamp();
+        let frame0_stamp = *frame0_stamp.get_or_insert(stamp);
+        let secs = (stamp - frame0_stamp).num_nanoseconds().unwrap() as f64 * 1e-9;
+
+        if x.stop_frame.is_some_and(|stop| fno >= stop)
+            || x.stop_secs.is_some_and(|stop| secs >= stop)
+        {
+            break;
+        }
+        if x.start_frame.is_some_and(|start| fno < start)
+            || x.start_secs.is_some_and(|start| secs < start)
+        {
+            continue;
+        }
+        match_all_dynamic_fmts!(frame, f, writer.write(&f, stamp)?);
+        n_saved += 1;
+    }
+    writer.close()?;
+
+    if n_saved == 0 {
+        std::fs::remove_file(&output_fname)?;
+        anyhow::bail!("no frames in the requested range");
+    }
+    info!("saved {n_saved} frames");
+    Ok(())
+}
+
+/// Decode the frames of the video `x.input` and save them to a new FMF file.
+///
+/// Each frame is saved with its timestamp from the video, e.g. the precision
+/// timestamps saved by Strand Camera.
+fn import_video(x: ImportVideo) -> Result<()> {
+    use frame_source::FrameDataSource;
+
+    let output_fname = match default_filename(&x.input, x.output, "fmf") {
+        Some(output_fname) => output_fname,
+        None => anyhow::bail!("cannot write fmf file to stdout"),
+    };
+
+    info!(
+        "importing {} to {}",
+        x.input.display(),
+        output_fname.display()
+    );
+
+    // frame-source uses `eyre` for errors.
+    let mut src = frame_source::from_path_with_timestamp_source(
+        &x.input,
+        frame_source::ImageDataMode::Decoded,
+        x.timestamp_source.into(),
+    )
+    .map_err(|e| anyhow::anyhow!("{e:#}"))?;
+    info!("using timestamps from {}", src.timestamp_source());
+    let frame0_time = match x.start_time.or_else(|| src.frame0_time()) {
+        Some(frame0_time) => frame0_time,
+        None => anyhow::bail!(
+            "start time could not be determined from the video, \
+            nor was it specified on the command line"
+        ),
+    };
+
+    let f = std::fs::File::create(&output_fname)?;
+    let mut writer = fmf::FMFWriter::new(f)?;
+    let mut n_saved = 0;
+    for frame in src.iter() {
+        let frame = frame.map_err(|e| anyhow::anyhow!("{e:#}"))?;
+        let idx = frame.idx();
+        let pts = match frame.timestamp() {
+            frame_source::Timestamp::Duration(pts) => pts,
+            frame_source::Timestamp::Fraction(_) => {
+                anyhow::bail!("{} has no frame timestamps", x.input.display());
+            }
+        };
+        let stamp = (frame0_time + chrono::Duration::from_std(pts)?).with_timezone(&chrono::Utc);
+        let image = match frame.take_decoded() {
+            Some(image) => image,
+            None => anyhow::bail!("frame {idx} of {} was not decoded", x.input.display()),
+        };
+        match_all_dynamic_fmts!(image, f, writer.write(&f, stamp)?);
+        n_saved += 1;
+    }
+    writer.close()?;
+    info!("saved {n_saved} frames");
+    Ok(())
+}
+
 fn export_images(path: PathBuf, opts: EncoderOptions) -> Result<()> {
     use std::io::Write;
 
@@ -481,7 +849,14 @@ fn export_mp4(x: ExportMp4) -> Result<()> {
 
     let (codec, nv_enc) = match x.codec {
         Codec::NvencH264 => {
-            let mut opts = NvidiaH264Options::default();
+            let mut opts = NvidiaH264Options {
+                rate_control: x.nvenc.nvenc_rate_control.unwrap_or_default(),
+                gop_length: x.nvenc.nvenc_gop_length,
+                num_b_frames: x.nvenc.nvenc_b_frames,
+                preset: x.nvenc.nvenc_preset.unwrap_or_default(),
+                tuning: x.nvenc.nvenc_tuning.unwrap_or_default(),
+                ..Default::default()
+            };
             if let Some(bitrate) = x.bitrate {
                 opts.bitrate = bitrate;
             }
@@ -529,17 +904,31 @@ fn export_mp4(x: ExportMp4) -> Result<()> {
         .collect();
     dbg!(&dt_first);
 
+    // The MP4 writer saves the timestamp of each frame as `MISPmicrosectime`
+    // and updates the creation time to that of the first frame.
+    let frame0_time = match ts_first.first() {
+        Some(ts) => ts.with_timezone(&chrono::Local).into(),
+        None => anyhow::bail!("no frames in {}", x.input.display()),
+    };
+    let writing_app = format!("{}-{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    let mut h264_metadata = H264Metadata::new(&writing_app, frame0_time);
+    h264_metadata.camera_name = x
+        .camera_name
+        .or_else(|| camera_name_from_filename(&x.input));
+
     let cfg = Mp4RecordingConfig {
         codec,
         max_framerate: ci2_remote_control::RecordingFrameRate::Unlimited,
-        h264_metadata: None,
+        h264_metadata: Some(h264_metadata),
+        container_metadata: None,
+        fragment_duration_secs: None,
     };
 
     debug!("opening file {}", output_fname.unwrap().display());
     let mut my_mp4_writer = mp4_writer::Mp4Writer::new(out_fd, cfg, nv_enc)?;
 
     for (fno, fmf_frame) in buffered_first.into_iter().chain(reader).enumerate() {
-        let fmf_frame = fmf_frame?;
+        let fmf_frame = x.transform.apply(fmf_frame?)?;
         debug!("saving frame {}", fno);
         let ts = fmf_frame.extra().host_timestamp();
         match_all_dynamic_fmts!(fmf_frame, frame, {
@@ -579,7 +968,7 @@ fn export_y4m(x: ExportY4m) -> Result<()> {
     let reader = fmf::FMFReader::new(&x.input)?;
 
     for frame in reader {
-        let frame = frame?;
+        let frame = x.transform.apply(frame?)?;
         basic_frame::match_all_dynamic_fmts!(frame, f, {
             y4m_writer.write_frame(&f)?;
         });
@@ -601,30 +990,69 @@ fn main() -> Result<()> {
             new_pixel_format,
             output,
             forced_input_pixel_format,
+            transform,
+            batch,
         } => {
-            export_fmf(input, new_pixel_format, output, forced_input_pixel_format)?;
+            batch::run(&input, output.as_deref(), &batch, |input| {
+                export_fmf(
+                    input,
+                    new_pixel_format,
+                    output.clone(),
+                    forced_input_pixel_format,
+                    &transform,
+                )
+            })?;
         }
         Opt::Info { input } => {
             info(input)?;
         }
-        Opt::ExportJpeg { input, quality } => {
-            export_images(input, EncoderOptions::Jpeg(quality))?;
+        Opt::ExportJpeg {
+            input,
+            quality,
+            batch,
+        } => {
+            batch::run(&input, None, &batch, |input| {
+                export_images(input, EncoderOptions::Jpeg(quality))
+            })?;
         }
-        Opt::ExportPng { input } => {
-            export_images(input, EncoderOptions::Png)?;
+        Opt::ExportPng { input, batch } => {
+            batch::run(&input, None, &batch, |input| {
+                export_images(input, EncoderOptions::Png)
+            })?;
         }
         Opt::ExportY4m(x) => {
-            export_y4m(x)?;
+            batch::run(&x.input, x.output.as_deref(), &x.batch, |input| {
+                export_y4m(ExportY4m { input, ..x.clone() })
+            })?;
         }
         // Opt::ExportBgr24(x) => {
         //     export_bgr24(x)?;
         // },
         Opt::ExportMp4(x) => {
-            export_mp4(x)?;
+            batch::run(&x.input, x.output.as_deref(), &x.batch, |input| {
+                export_mp4(ExportMp4 { input, ..x.clone() })
+            })?;
         }
         Opt::ImportImages { input, output } => {
             import_images(&input, output)?;
         }
+        Opt::Concat { inputs, output } => {
+            concat(&inputs, &output)?;
+        }
+        Opt::Cut(x) => {
+            cut(x)?;
+        }
+        Opt::ImportVideo(x) => {
+            import_video(x)?;
+        }
+        Opt::ExportUfmf(x) => {
+            batch::run(&x.input, x.output.as_deref(), &x.batch, |input| {
+                ufmf_convert::export_ufmf(ExportUfmf { input, ..x.clone() })
+            })?;
+        }
+        Opt::ImportUfmf(x) => {
+            ufmf_convert::import_ufmf(x)?;
+        }
     }
     Ok(())
 }
@@ -699,6 +1127,8 @@ fn test_y4m() -> anyhow::Result<()> {
                 fps_denominator: 1,
                 aspect_numerator: 1,
                 aspect_denominator: 1,
+                transform: Default::default(),
+                batch: Default::default(),
             };
 
             export_y4m(x)?;
@@ -716,6 +1146,260 @@ fn test_y4m() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_mp4_timestamps() -> anyhow::Result<()> {
+    use frame_source::FrameDataSource;
+    use machine_vision_formats::pixel_format::Mono8;
+
+    let start = chrono::DateTime::from_timestamp(61, 0).unwrap();
+    // Irregular intervals, as when frames are dropped.
+    let offsets_msec = [0, 10, 20, 40, 50, 65];
+
+    let tmpdir = tempfile::tempdir()?;
+    let fmf_fname = tmpdir
+        .path()
+        .join("movie20240101_120000.123456_cam-1_a.fmf");
+    let mp4_fname = tmpdir.path().join("out.mp4");
+    {
+        let fd = std::fs::File::create(&fmf_fname)?;
+        let mut writer = fmf::FMFWriter::new(fd)?;
+        for (i, offset) in offsets_msec.iter().enumerate() {
+            let frame = basic_frame::BasicFrame::<Mono8> {
+                width: 32,
+                height: 16,
+                stride: 32,
+                pixel_format: std::marker::PhantomData,
+                image_data: vec![(i * 40) as u8; 32 * 16],
+                extra: Box::new(basic_frame::BasicExtra {
+                    host_timestamp: start,
+                    host_framenumber: i,
+                }),
+            };
+            writer.write(&frame, start + chrono::Duration::milliseconds(*offset))?;
+        }
+    }
+
+    export_mp4(ExportMp4 {
+        input: fmf_fname,
+        output: Some(mp4_fname.clone()),
+        bitrate: None,
+        codec: Codec::OpenH264,
+        camera_name: None,
+        nvenc: Default::default(),
+        transform: Default::default(),
+        batch: Default::default(),
+    })?;
+
+    let mut src =
+        frame_source::from_path(&mp4_fname, frame_source::ImageDataMode::Encoded).unwrap();
+    assert_eq!(src.timestamp_source(), "MISPmicrosectime");
+    assert_eq!(src.frame0_time().unwrap(), start);
+    assert_eq!(src.camera_name(), Some("cam-1_a"));
+    let timestamps: Vec<_> = src
+        .iter()
+        .map(|frame| frame.unwrap().timestamp().unwrap_duration())
+        .collect();
+    assert_eq!(timestamps.len(), offsets_msec.len());
+    for (actual, offset) in timestamps.iter().zip(offsets_msec.iter()) {
+        // FMF stores timestamps as floating point seconds.
+        let expected = std::time::Duration::from_millis(*offset as u64);
+        let diff = actual.as_secs_f64() - expected.as_secs_f64();
+        assert!(
+            diff.abs() < 2e-6,
+            "timestamp {actual:?}, expected {expected:?}"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn write_test_fmf(
+    path: &Path,
+    (width, height): (u32, u32),
+    start: chrono::DateTime<chrono::Utc>,
+    offsets_msec: &[i64],
+) -> anyhow::Result<()> {
+    let fd = std::fs::File::create(path)?;
+    let mut writer = fmf::FMFWriter::new(fd)?;
+    for (i, offset) in offsets_msec.iter().enumerate() {
+        let frame = basic_frame::BasicFrame::<pixel_format::Mono8> {
+            width,
+            height,
+            stride: width,
+            pixel_format: std::marker::PhantomData,
+            image_data: vec![i as u8; (width * height) as usize],
+            extra: Box::new(basic_frame::BasicExtra {
+                host_timestamp: start,
+                host_framenumber: i,
+            }),
+        };
+        writer.write(&frame, start + chrono::Duration::milliseconds(*offset))?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+fn read_test_fmf(path: &Path) -> anyhow::Result<Vec<(u8, i64)>> {
+    let start = chrono::DateTime::from_timestamp(61, 0).unwrap();
+    let mut result = Vec::new();
+    for frame in fmf::FMFReader::new(path)? {
+        let frame = frame?;
+        let stamp = frame.extra().host_timestamp();
+        let offset_msec = (stamp - start).num_microseconds().unwrap() as f64 / 1000.0;
+        result.push((
+            frame.image_data_without_format()[0],
+            offset_msec.round() as i64,
+        ));
+    }
+    Ok(result)
+}
+
+#[test]
+fn test_concat() -> anyhow::Result<()> {
+    let start = chrono::DateTime::from_timestamp(61, 0).unwrap();
+    let tmpdir = tempfile::tempdir()?;
+    let a = tmpdir.path().join("a.fmf");
+    let b = tmpdir.path().join("b.fmf");
+    let narrow = tmpdir.path().join("narrow.fmf");
+    let out = tmpdir.path().join("out.fmf");
+    write_test_fmf(&a, (8, 4), start, &[0, 10, 20])?;
+    write_test_fmf(&b, (8, 4), start, &[30, 40])?;
+    write_test_fmf(&narrow, (4, 4), start, &[50])?;
+
+    concat(&[a.clone(), b.clone()], &out)?;
+    assert_eq!(
+        read_test_fmf(&out)?,
+        vec![(0, 0), (1, 10), (2, 20), (0, 30), (1, 40)]
+    );
+
+    // Timestamps go backwards.
+    assert!(concat(&[b.clone(), a.clone()], &out).is_err());
+    assert!(!out.exists());
+
+    // Image size differs.
+    assert!(concat(&[a, narrow], &out).is_err());
+    assert!(!out.exists());
+    Ok(())
+}
+
+#[test]
+fn test_cut() -> anyhow::Result<()> {
+    let start = chrono::DateTime::from_timestamp(61, 0).unwrap();
+    let tmpdir = tempfile::tempdir()?;
+    let input = tmpdir.path().join("input.fmf");
+    let out = tmpdir.path().join("out.fmf");
+    write_test_fmf(&input, (8, 4), start, &[0, 10, 20, 30, 40])?;
+
+    let args = |start_frame, stop_frame, start_secs, stop_secs| Cut {
+        input: input.clone(),
+        output: Some(out.clone()),
+        start_frame,
+        stop_frame,
+        start_secs,
+        stop_secs,
+    };
+
+    cut(args(Some(1), Some(3), None, None))?;
+    assert_eq!(read_test_fmf(&out)?, vec![(1, 10), (2, 20)]);
+
+    cut(args(None, None, Some(0.015), None))?;
+    assert_eq!(read_test_fmf(&out)?, vec![(2, 20), (3, 30), (4, 40)]);
+
+    cut(args(None, None, None, Some(0.025)))?;
+    assert_eq!(read_test_fmf(&out)?, vec![(0, 0), (1, 10), (2, 20)]);
+
+    assert!(cut(args(Some(10), None, None, None)).is_err());
+    assert!(!out.exists());
+    Ok(())
+}
+
+#[test]
+fn test_import_video() -> anyhow::Result<()> {
+    let start = chrono::DateTime::from_timestamp(61, 0).unwrap();
+    let offsets_msec = [0, 10, 20, 40, 50, 65];
+
+    let tmpdir = tempfile::tempdir()?;
+    let fmf_fname = tmpdir.path().join("input.fmf");
+    let mp4_fname = tmpdir.path().join("video.mp4");
+    let imported_fname = tmpdir.path().join("imported.fmf");
+    write_test_fmf(&fmf_fname, (32, 16), start, &offsets_msec)?;
+
+    export_mp4(ExportMp4 {
+        input: fmf_fname,
+        output: Some(mp4_fname.clone()),
+        bitrate: None,
+        codec: Codec::OpenH264,
+        camera_name: None,
+        nvenc: Default::default(),
+        transform: Default::default(),
+        batch: Default::default(),
+    })?;
+
+    import_video(ImportVideo {
+        input: mp4_fname,
+        output: Some(imported_fname.clone()),
+        timestamp_source: TimestampSource::MispMicrosectime,
+        start_time: None,
+    })?;
+
+    let reader = fmf::FMFReader::new(&imported_fname)?;
+    assert_eq!((reader.width(), reader.height()), (32, 16));
+    let imported_offsets: Vec<i64> = read_test_fmf(&imported_fname)?
+        .into_iter()
+        .map(|(_, offset)| offset)
+        .collect();
+    assert_eq!(imported_offsets, offsets_msec);
+    Ok(())
+}
+
+#[test]
+fn test_batch() -> anyhow::Result<()> {
+    let start = chrono::DateTime::from_timestamp(61, 0).unwrap();
+    let tmpdir = tempfile::tempdir()?;
+    write_test_fmf(&tmpdir.path().join("a.fmf"), (8, 4), start, &[0, 10])?;
+    write_test_fmf(&tmpdir.path().join("b.fmf"), (8, 4), start, &[20])?;
+    std::fs::write(tmpdir.path().join("broken.fmf"), b"not an fmf file")?;
+
+    let pattern = tmpdir.path().join("*.fmf");
+    let args = BatchArgs { jobs: 2 };
+    let export = |output: Option<PathBuf>| {
+        batch::run(&pattern, output.as_deref(), &args, |input| {
+            export_fmf(input, None, output.clone(), None, &Default::default())
+        })
+    };
+
+    // An output filename cannot be used for several inputs.
+    assert!(export(Some(tmpdir.path().join("out.fmf"))).is_err());
+
+    // The broken file fails without stopping the others.
+    assert!(export(None).is_err());
+    assert_eq!(
+        read_test_fmf(&tmpdir.path().join("a.exported.fmf"))?,
+        vec![(0, 0), (1, 10)]
+    );
+    assert_eq!(
+        read_test_fmf(&tmpdir.path().join("b.exported.fmf"))?,
+        vec![(0, 20)]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_camera_name_from_filename() {
+    let name = |s: &str| camera_name_from_filename(Path::new(s));
+    assert_eq!(
+        name("/data/movie20240101_120000.123456_Basler-123.fmf"),
+        Some("Basler-123".to_string())
+    );
+    assert_eq!(
+        name("movie20240101_120000.123456_cam_1.fmf.gz"),
+        Some("cam_1".to_string())
+    );
+    assert_eq!(name("test_rgb8.fmf"), None);
+    assert_eq!(name("movie20240101_120000.123456_.fmf"), None);
+}
+
 #[cfg(test)]
 fn are_images_equal<FMT>(
     frame1: &dyn machine_vision_formats::ImageStride<FMT>,
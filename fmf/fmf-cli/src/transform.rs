@@ -0,0 +1,314 @@
+//! Geometric transforms applied to frames during export.
+//!
+//! The transforms are applied in the order crop, rotate, flip, scale. Mono8
+//! and RGB8 images keep their pixel format. Other formats are converted to
+//! RGB8 first because, e.g., rotating a Bayer image would change its pattern.
+
+use anyhow::Result;
+
+use basic_frame::{BasicFrame, DynamicFrame};
+use machine_vision_formats::{pixel_format, pixel_format::PixFmt, Stride};
+use timestamped_frame::HostTimeData;
+
+/// Clockwise rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Rotation {
+    #[value(name = "90")]
+    R90,
+    #[value(name = "180")]
+    R180,
+    #[value(name = "270")]
+    R270,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Flip {
+    /// Mirror left and right
+    #[value(alias = "horizontal")]
+    H,
+    /// Mirror top and bottom
+    #[value(alias = "vertical")]
+    V,
+}
+
+/// A region of the input image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Crop {
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+}
+
+impl std::str::FromStr for Crop {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let vals = s
+            .split(',')
+            .map(|v| v.trim().parse::<usize>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid crop \"{s}\": {e}"))?;
+        match vals.as_slice() {
+            &[x, y, w, h] if w > 0 && h > 0 => Ok(Crop { x, y, w, h }),
+            _ => Err(format!(
+                "invalid crop \"{s}\": expected x,y,w,h with non-zero width and height"
+            )),
+        }
+    }
+}
+
+fn parse_scale(s: &str) -> std::result::Result<f64, String> {
+    let scale: f64 = s.parse().map_err(|e| format!("invalid scale: {e}"))?;
+    if scale.is_finite() && scale > 0.0 {
+        Ok(scale)
+    } else {
+        Err("scale must be larger than zero".to_string())
+    }
+}
+
+/// Command line options for transforming the exported frames.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub(crate) struct TransformArgs {
+    /// Crop to the region `x,y,w,h` of the input image, in pixels
+    #[arg(long)]
+    pub(crate) crop: Option<Crop>,
+
+    /// Rotate clockwise by 90, 180 or 270 degrees
+    #[arg(long, value_enum)]
+    pub(crate) rotate: Option<Rotation>,
+
+    /// Flip horizontally (h) or vertically (v)
+    #[arg(long, value_enum)]
+    pub(crate) flip: Option<Flip>,
+
+    /// Scale the image by this factor (bilinear interpolation)
+    #[arg(long, value_parser = parse_scale)]
+    pub(crate) scale: Option<f64>,
+}
+
+impl TransformArgs {
+    fn is_identity(&self) -> bool {
+        self.crop.is_none()
+            && self.rotate.is_none()
+            && self.flip.is_none()
+            && self.scale.map_or(true, |s| s == 1.0)
+    }
+
+    /// Transform a frame. The timestamp and frame number are kept.
+    pub(crate) fn apply(&self, frame: DynamicFrame) -> Result<DynamicFrame> {
+        if self.is_identity() {
+            return Ok(frame);
+        }
+        let is_mono = frame.pixel_format() == PixFmt::Mono8;
+        let (mut packed, extra) = if is_mono {
+            let frame = frame.as_basic::<pixel_format::Mono8>().unwrap();
+            (Packed::from_basic(&frame, 1), frame.extra)
+        } else {
+            let frame = frame.into_pixel_format::<pixel_format::RGB8>()?;
+            (Packed::from_basic(&frame, 3), frame.extra)
+        };
+
+        if let Some(crop) = &self.crop {
+            packed = packed.crop(crop)?;
+        }
+        if let Some(rotation) = self.rotate {
+            packed = packed.rotate(rotation);
+        }
+        if let Some(flip) = self.flip {
+            packed = packed.flip(flip);
+        }
+        if let Some(scale) = self.scale {
+            packed = packed.scale(scale);
+        }
+
+        Ok(if is_mono {
+            DynamicFrame::Mono8(packed.into_basic(extra))
+        } else {
+            DynamicFrame::RGB8(packed.into_basic(extra))
+        })
+    }
+}
+
+/// Image data without padding at the end of the rows.
+#[derive(Debug, PartialEq)]
+struct Packed {
+    width: usize,
+    height: usize,
+    /// Bytes per pixel
+    bpp: usize,
+    data: Vec<u8>,
+}
+
+impl Packed {
+    /// Copy the valid bytes of each row, dropping the padding.
+    fn from_basic<F>(frame: &BasicFrame<F>, bpp: usize) -> Self {
+        let width = frame.width as usize;
+        let height = frame.height as usize;
+        let row_len = width * bpp;
+        let mut data = Vec::with_capacity(row_len * height);
+        for row in frame.image_data.chunks(frame.stride()).take(height) {
+            data.extend_from_slice(&row[..row_len]);
+        }
+        Self {
+            width,
+            height,
+            bpp,
+            data,
+        }
+    }
+
+    fn into_basic<F>(self, extra: Box<dyn HostTimeData>) -> BasicFrame<F> {
+        BasicFrame {
+            width: self.width.try_into().unwrap(),
+            height: self.height.try_into().unwrap(),
+            stride: (self.width * self.bpp).try_into().unwrap(),
+            image_data: self.data,
+            pixel_format: std::marker::PhantomData,
+            extra,
+        }
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> &[u8] {
+        let start = (y * self.width + x) * self.bpp;
+        &self.data[start..start + self.bpp]
+    }
+
+    /// Create an image where pixel (x, y) is `src(x, y)` of this image.
+    fn remap(
+        &self,
+        width: usize,
+        height: usize,
+        src: impl Fn(usize, usize) -> (usize, usize),
+    ) -> Self {
+        let mut data = Vec::with_capacity(width * height * self.bpp);
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = src(x, y);
+                data.extend_from_slice(self.pixel(sx, sy));
+            }
+        }
+        Self {
+            width,
+            height,
+            bpp: self.bpp,
+            data,
+        }
+    }
+
+    fn crop(&self, crop: &Crop) -> Result<Self> {
+        if crop.x + crop.w > self.width || crop.y + crop.h > self.height {
+            anyhow::bail!(
+                "crop region {},{},{},{} exceeds image size {}x{}",
+                crop.x,
+                crop.y,
+                crop.w,
+                crop.h,
+                self.width,
+                self.height
+            );
+        }
+        Ok(self.remap(crop.w, crop.h, |x, y| (crop.x + x, crop.y + y)))
+    }
+
+    fn rotate(&self, rotation: Rotation) -> Self {
+        let (w, h) = (self.width, self.height);
+        match rotation {
+            Rotation::R90 => self.remap(h, w, |x, y| (y, h - 1 - x)),
+            Rotation::R180 => self.remap(w, h, |x, y| (w - 1 - x, h - 1 - y)),
+            Rotation::R270 => self.remap(h, w, |x, y| (w - 1 - y, x)),
+        }
+    }
+
+    fn flip(&self, flip: Flip) -> Self {
+        let (w, h) = (self.width, self.height);
+        match flip {
+            Flip::H => self.remap(w, h, |x, y| (w - 1 - x, y)),
+            Flip::V => self.remap(w, h, |x, y| (x, h - 1 - y)),
+        }
+    }
+
+    fn scale(&self, scale: f64) -> Self {
+        let new_size = |n: usize| ((n as f64 * scale).round() as usize).max(1);
+        let width = new_size(self.width);
+        let height = new_size(self.height);
+
+        // Source coordinate of the center of a destination pixel, and the
+        // neighboring source pixels with the weight of the second.
+        let sample = |i: usize, n_src: usize, n_dest: usize| {
+            let pos = ((i as f64 + 0.5) * n_src as f64 / n_dest as f64 - 0.5)
+                .clamp(0.0, (n_src - 1) as f64);
+            let i0 = pos.floor() as usize;
+            let i1 = (i0 + 1).min(n_src - 1);
+            (i0, i1, pos - i0 as f64)
+        };
+
+        let mut data = Vec::with_capacity(width * height * self.bpp);
+        for y in 0..height {
+            let (y0, y1, fy) = sample(y, self.height, height);
+            for x in 0..width {
+                let (x0, x1, fx) = sample(x, self.width, width);
+                for c in 0..self.bpp {
+                    let top = self.pixel(x0, y0)[c] as f64 * (1.0 - fx)
+                        + self.pixel(x1, y0)[c] as f64 * fx;
+                    let bottom = self.pixel(x0, y1)[c] as f64 * (1.0 - fx)
+                        + self.pixel(x1, y1)[c] as f64 * fx;
+                    let value = top * (1.0 - fy) + bottom * fy;
+                    data.push(value.round().clamp(0.0, 255.0) as u8);
+                }
+            }
+        }
+        Self {
+            width,
+            height,
+            bpp: self.bpp,
+            data,
+        }
+    }
+}
+
+#[test]
+fn test_transforms() -> Result<()> {
+    // A 3x2 image with padding at the end of each row:
+    //   1 2 3
+    //   4 5 6
+    let frame = BasicFrame::<pixel_format::Mono8> {
+        width: 3,
+        height: 2,
+        stride: 4,
+        image_data: vec![1, 2, 3, 0, 4, 5, 6, 0],
+        pixel_format: std::marker::PhantomData,
+        extra: Box::new(basic_frame::BasicExtra {
+            host_timestamp: chrono::DateTime::from_timestamp(61, 0).unwrap(),
+            host_framenumber: 7,
+        }),
+    };
+    let packed = Packed::from_basic(&frame, 1);
+    assert_eq!(packed.data, vec![1, 2, 3, 4, 5, 6]);
+
+    assert_eq!(packed.rotate(Rotation::R90).data, vec![4, 1, 5, 2, 6, 3]);
+    assert_eq!(packed.rotate(Rotation::R180).data, vec![6, 5, 4, 3, 2, 1]);
+    assert_eq!(packed.rotate(Rotation::R270).data, vec![3, 6, 2, 5, 1, 4]);
+    assert_eq!(packed.flip(Flip::H).data, vec![3, 2, 1, 6, 5, 4]);
+    assert_eq!(packed.flip(Flip::V).data, vec![4, 5, 6, 1, 2, 3]);
+    let cropped = packed.crop(&"1,0,2,2".parse().unwrap())?;
+    assert_eq!((cropped.width, cropped.height), (2, 2));
+    assert_eq!(cropped.data, vec![2, 3, 5, 6]);
+    assert!(packed.crop(&"2,0,2,2".parse().unwrap()).is_err());
+    let scaled = packed.scale(2.0);
+    assert_eq!((scaled.width, scaled.height), (6, 4));
+    assert_eq!(scaled.pixel(0, 0), &[1]);
+    assert_eq!(scaled.pixel(5, 3), &[6]);
+
+    let transform = TransformArgs {
+        rotate: Some(Rotation::R90),
+        ..Default::default()
+    };
+    let rotated = transform.apply(DynamicFrame::Mono8(frame))?;
+    assert_eq!(rotated.pixel_format(), PixFmt::Mono8);
+    assert_eq!((rotated.width(), rotated.height()), (2, 3));
+    assert_eq!(rotated.stride(), 2);
+    assert_eq!(rotated.image_data_without_format(), &[4, 1, 5, 2, 6, 3]);
+    use timestamped_frame::ExtraTimeData;
+    assert_eq!(rotated.extra().host_framenumber(), 7);
+    Ok(())
+}
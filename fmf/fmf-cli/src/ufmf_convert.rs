@@ -0,0 +1,282 @@
+//! Conversion between FMF and UFMF files.
+//!
+//! UFMF files save a background image and, for each frame, only the regions
+//! which differ from it. Exporting to UFMF keeps a running mean of the frames
+//! as background and saves the square patches in which any pixel differs from
+//! the background by more than a threshold. Importing reconstructs full frames
+//! by drawing the saved patches onto the background, e.g. to retrack old UFMF
+//! recordings made by Strand Camera.
+
+use anyhow::Result;
+use std::path::PathBuf;
+
+use basic_frame::{match_all_dynamic_fmts, BasicExtra, BasicFrame, DynamicFrame};
+use machine_vision_formats::{pixel_format::PixFmt, Stride};
+use timestamped_frame::ExtraTimeData;
+
+use crate::{batch::BatchArgs, default_filename};
+
+#[derive(clap::Parser, Debug, Clone)]
+pub(crate) struct ExportUfmf {
+    /// Filename of input fmf, or a glob pattern for several files
+    pub(crate) input: PathBuf,
+
+    /// Filename of output .ufmf
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+
+    /// Pixels differing from the background by more than this are saved
+    #[arg(long, default_value_t = 10)]
+    pub(crate) threshold: u8,
+
+    /// Width and height of the saved patches, in pixels
+    #[arg(long, default_value_t = 32, value_parser = parse_patch_size)]
+    pub(crate) patch_size: u16,
+
+    /// Weight of each new frame in the running mean background
+    #[arg(long, default_value_t = 0.01)]
+    pub(crate) bg_alpha: f32,
+
+    /// Save the background every this many frames
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) bg_interval: u64,
+
+    #[command(flatten)]
+    pub(crate) batch: BatchArgs,
+}
+
+fn parse_patch_size(s: &str) -> std::result::Result<u16, String> {
+    let size: u16 = s.parse().map_err(|e| format!("invalid number: {e}"))?;
+    // Patches start at even pixels to keep the 2x2 Bayer pattern.
+    if size > 0 && size % 2 == 0 {
+        Ok(size)
+    } else {
+        Err("patch size must be a positive even number".to_string())
+    }
+}
+
+#[derive(clap::Parser, Debug)]
+pub(crate) struct ImportUfmf {
+    /// Filename of input ufmf
+    pub(crate) input: PathBuf,
+
+    /// Filename of output .fmf
+    #[arg(short, long)]
+    pub(crate) output: Option<PathBuf>,
+}
+
+/// The running mean background of an [ExportUfmf].
+struct Background {
+    width: usize,
+    height: usize,
+    mean: Vec<f32>,
+}
+
+impl Background {
+    fn new(frame: &DynamicFrame) -> Self {
+        let width = frame.width() as usize;
+        let height = frame.height() as usize;
+        let stride = frame.stride();
+        let data = frame.image_data_without_format();
+        let mean = (0..height)
+            .flat_map(|y| data[y * stride..y * stride + width].iter())
+            .map(|v| f32::from(*v))
+            .collect();
+        Self {
+            width,
+            height,
+            mean,
+        }
+    }
+
+    /// Return the patches of `frame` which differ from the background.
+    fn foreground(&self, frame: &DynamicFrame, threshold: u8, patch_size: u16) -> Vec<(u16, u16)> {
+        let stride = frame.stride();
+        let data = frame.image_data_without_format();
+        let threshold = f32::from(threshold);
+        let size = usize::from(patch_size);
+        let mut patches = Vec::new();
+        for y0 in (0..self.height).step_by(size) {
+            for x0 in (0..self.width).step_by(size) {
+                let differs = (y0..(y0 + size).min(self.height)).any(|y| {
+                    (x0..(x0 + size).min(self.width)).any(|x| {
+                        (f32::from(data[y * stride + x]) - self.mean[y * self.width + x]).abs()
+                            > threshold
+                    })
+                });
+                if differs {
+                    // Both fit in u16 because the image size does.
+                    patches.push((x0 as u16, y0 as u16));
+                }
+            }
+        }
+        patches
+    }
+
+    fn update(&mut self, frame: &DynamicFrame, alpha: f32) {
+        let stride = frame.stride();
+        let data = frame.image_data_without_format();
+        for y in 0..self.height {
+            let row = &data[y * stride..y * stride + self.width];
+            let mean_row = &mut self.mean[y * self.width..(y + 1) * self.width];
+            for (m, v) in mean_row.iter_mut().zip(row) {
+                *m += alpha * (f32::from(*v) - *m);
+            }
+        }
+    }
+
+    fn to_frame(
+        &self,
+        stamp: chrono::DateTime<chrono::Utc>,
+        fno: usize,
+    ) -> BasicFrame<machine_vision_formats::pixel_format::Mono32f> {
+        let image_data = self.mean.iter().flat_map(|v| v.to_le_bytes()).collect();
+        BasicFrame {
+            width: self.width as u32,
+            height: self.height as u32,
+            stride: self.width as u32 * 4,
+            image_data,
+            extra: Box::new(BasicExtra {
+                host_timestamp: stamp,
+                host_framenumber: fno,
+            }),
+            pixel_format: std::marker::PhantomData,
+        }
+    }
+}
+
+/// Save the frames of the FMF file `x.input` to a UFMF file.
+///
+/// Only 8 bit monochrome and Bayer images are supported.
+pub(crate) fn export_ufmf(x: ExportUfmf) -> Result<()> {
+    let output_fname = match default_filename(&x.input, x.output, "ufmf") {
+        Some(output_fname) => output_fname,
+        None => anyhow::bail!("cannot write ufmf file to stdout"),
+    };
+
+    info!(
+        "exporting {} to {}",
+        x.input.display(),
+        output_fname.display()
+    );
+    let mut reader = fmf::FMFReader::new(&x.input)?;
+    let frame0 = match reader.next() {
+        Some(frame0) => frame0?,
+        None => anyhow::bail!("no frames in {}", x.input.display()),
+    };
+    use PixFmt::*;
+    if !matches!(
+        frame0.pixel_format(),
+        Mono8 | BayerRG8 | BayerGB8 | BayerGR8 | BayerBG8
+    ) {
+        anyhow::bail!(
+            "unsupported pixel format {} for ufmf export",
+            frame0.pixel_format()
+        );
+    }
+
+    let f = std::fs::File::create(&output_fname)?;
+    let mut writer = ufmf::UFMFWriter::new(
+        f,
+        frame0.width().try_into()?,
+        frame0.height().try_into()?,
+        frame0.pixel_format(),
+        Some(&frame0),
+    )?;
+    let mut background = Background::new(&frame0);
+    let patch_size = x.patch_size;
+    let (mut n_saved, mut n_patches) = (0, 0);
+    for (fno, frame) in std::iter::once(Ok(frame0)).chain(reader).enumerate() {
+        let frame = frame?;
+        let patches = background.foreground(&frame, x.threshold, patch_size);
+        let regions: Vec<_> = patches
+            .iter()
+            .map(|(x0, y0)| {
+                ufmf::RectFromCenter::from_xy_wh(
+                    x0 + patch_size / 2,
+                    y0 + patch_size / 2,
+                    patch_size,
+                    patch_size,
+                )
+            })
+            .collect();
+        writer.add_frame(&frame, &regions)?;
+        n_saved += 1;
+        n_patches += regions.len();
+
+        background.update(&frame, x.bg_alpha);
+        if fno as u64 % x.bg_interval == 0 {
+            let mean = background.to_frame(frame.extra().host_timestamp(), fno);
+            writer.add_keyframe(b"mean", &mean)?;
+        }
+    }
+    writer.close()?;
+    info!("saved {n_saved} frames with {n_patches} patches");
+    Ok(())
+}
+
+/// Save the frames of the UFMF file `x.input`, reconstructed from the
+/// background and saved patches, to an FMF file.
+pub(crate) fn import_ufmf(x: ImportUfmf) -> Result<()> {
+    let output_fname = match default_filename(&x.input, x.output, "fmf") {
+        Some(output_fname) => output_fname,
+        None => anyhow::bail!("cannot write fmf file to stdout"),
+    };
+
+    info!(
+        "importing {} to {}",
+        x.input.display(),
+        output_fname.display()
+    );
+    let reader = ufmf::UFMFReader::from_path(&x.input)?;
+    let f = std::fs::File::create(&output_fname)?;
+    let mut writer = fmf::FMFWriter::new(f)?;
+    let mut n_saved = 0;
+    for frame in reader {
+        let frame = frame?;
+        let stamp = frame.extra().host_timestamp();
+        match_all_dynamic_fmts!(frame, f, writer.write(&f, stamp)?);
+        n_saved += 1;
+    }
+    writer.close()?;
+    info!("saved {n_saved} frames");
+    Ok(())
+}
+
+#[test]
+fn test_ufmf_roundtrip() -> Result<()> {
+    let start = chrono::DateTime::from_timestamp(61, 0).unwrap();
+    let tmpdir = tempfile::tempdir()?;
+    let input = tmpdir.path().join("input.fmf");
+    let ufmf_fname = tmpdir.path().join("input.ufmf");
+    let out = tmpdir.path().join("out.fmf");
+    crate::write_test_fmf(&input, (40, 4), start, &[0, 10, 20, 30])?;
+
+    let export = |threshold| ExportUfmf {
+        input: input.clone(),
+        output: Some(ufmf_fname.clone()),
+        threshold,
+        patch_size: 32,
+        bg_alpha: 0.01,
+        bg_interval: 100,
+        batch: Default::default(),
+    };
+    let import = || ImportUfmf {
+        input: ufmf_fname.clone(),
+        output: Some(out.clone()),
+    };
+
+    // With a zero threshold, every changed patch is saved.
+    export_ufmf(export(0))?;
+    import_ufmf(import())?;
+    assert_eq!(crate::read_test_fmf(&out)?, crate::read_test_fmf(&input)?);
+
+    // Otherwise, small changes show the background (the first frame).
+    export_ufmf(export(10))?;
+    import_ufmf(import())?;
+    assert_eq!(
+        crate::read_test_fmf(&out)?,
+        vec![(0, 0), (0, 10), (0, 20), (0, 30)]
+    );
+    Ok(())
+}
@@ -0,0 +1,307 @@
+//! Random access to the frames of FMF files.
+//!
+//! All frames of an FMF file have the same size, so the position of each frame
+//! follows from the header. An [FMFIndex] additionally holds the timestamps of
+//! all frames, which allows finding frames by time. Building the index reads
+//! the timestamp of every frame. For large files, the index can be saved to a
+//! sidecar file next to the FMF file (see [FMFIndex::load_or_build]).
+//!
+//! Compressed (`.fmf.gz`) files cannot be indexed because the decoder does not
+//! support seeking. Use [crate::FMFReader] to read them.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use basic_frame::DynamicFrame;
+use datetime_conversion::{datetime_to_f64, f64_to_datetime};
+use formats::PixFmt;
+
+use crate::{
+    reader::{to_frame, TIMESTAMP_SIZE},
+    FMFError, FMFReader, FMFResult,
+};
+
+/// Magic bytes at the start of an index sidecar file.
+const INDEX_MAGIC: &[u8; 8] = b"FMFIDX1\0";
+
+fn io_path_error(path: &Path, source: std::io::Error) -> FMFError {
+    FMFError::IoPath {
+        source,
+        path: path.display().to_string(),
+        #[cfg(feature = "backtrace")]
+        backtrace: std::backtrace::Backtrace::capture(),
+    }
+}
+
+/// The positions and timestamps of the frames in an FMF file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FMFIndex {
+    pixel_format: PixFmt,
+    width: u32,
+    height: u32,
+    header_size: u64,
+    chunk_size: u64,
+    file_len: u64,
+    /// The number of frames in the header, which may differ from the number of
+    /// frames in the file.
+    header_n_frames: u64,
+    timestamps: Vec<f64>,
+}
+
+impl FMFIndex {
+    /// Build the index of the FMF file at `path`.
+    ///
+    /// The number of frames is taken from the header but limited to the
+    /// complete frames in the file.
+    pub fn build<P: AsRef<Path>>(path: P) -> FMFResult<Self> {
+        let path = path.as_ref();
+        let mut index = Self::read_header(path)?;
+        let n_complete = index.file_len.saturating_sub(index.header_size) / index.chunk_size;
+        let n_frames = n_complete.min(index.header_n_frames);
+
+        // Only the timestamps are read, so the reads are not buffered.
+        let mut f = File::open(path).map_err(|e| io_path_error(path, e))?;
+        index.timestamps.reserve(n_frames as usize);
+        for i in 0..n_frames {
+            f.seek(SeekFrom::Start(index.header_size + i * index.chunk_size))?;
+            index.timestamps.push(f.read_f64::<LittleEndian>()?);
+        }
+        Ok(index)
+    }
+
+    /// Load the index of the FMF file at `path` from its sidecar file, or
+    /// build it and save the sidecar file.
+    ///
+    /// The sidecar file is rebuilt if the size of the FMF file or the number
+    /// of frames in its header changed since it was saved. Failure to save the sidecar file (e.g. in a read-only
+    /// directory) is not an error.
+    pub fn load_or_build<P: AsRef<Path>>(path: P) -> FMFResult<Self> {
+        let path = path.as_ref();
+        let sidecar = Self::sidecar_path(path);
+        // The header is small, so it is always read from the FMF file itself.
+        let mut index = Self::read_header(path)?;
+        if let Ok(timestamps) = index.read_sidecar(&sidecar) {
+            index.timestamps = timestamps;
+            return Ok(index);
+        }
+        let index = Self::build(path)?;
+        let _ = index.save_sidecar(&sidecar);
+        Ok(index)
+    }
+
+    /// The filename of the index sidecar file of the FMF file at `path`.
+    ///
+    /// This is the FMF filename with `.idx` appended.
+    pub fn sidecar_path<P: AsRef<Path>>(path: P) -> PathBuf {
+        let mut sidecar = path.as_ref().as_os_str().to_owned();
+        sidecar.push(".idx");
+        PathBuf::from(sidecar)
+    }
+
+    /// Read the header of the FMF file at `path`, returning an index without
+    /// timestamps.
+    fn read_header(path: &Path) -> FMFResult<Self> {
+        if path.extension().and_then(|x| x.to_str()) == Some("gz") {
+            return Err(FMFError::NotSeekable(path.display().to_string()));
+        }
+        let rdr = FMFReader::new(path)?;
+        let file_len = std::fs::metadata(path)
+            .map_err(|e| io_path_error(path, e))?
+            .len();
+        Ok(Self {
+            pixel_format: rdr.format(),
+            width: rdr.width(),
+            height: rdr.height(),
+            header_size: rdr.file_pos() as u64,
+            chunk_size: rdr.chunk_size() as u64,
+            file_len,
+            header_n_frames: rdr.n_frames() as u64,
+            timestamps: Vec::new(),
+        })
+    }
+
+    /// Read the timestamps from the sidecar file if it matches the FMF file.
+    fn read_sidecar(&self, sidecar: &Path) -> FMFResult<Vec<f64>> {
+        let mut f = BufReader::new(File::open(sidecar).map_err(|e| io_path_error(sidecar, e))?);
+        let mut magic = [0u8; 8];
+        f.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(FMFError::UnknownFormat(sidecar.display().to_string()));
+        }
+        if f.read_u64::<LittleEndian>()? != self.file_len
+            || f.read_u64::<LittleEndian>()? != self.header_n_frames
+        {
+            return Err(FMFError::InconsistentState);
+        }
+        let n_frames = f.read_u64::<LittleEndian>()?;
+        (0..n_frames)
+            .map(|_| Ok(f.read_f64::<LittleEndian>()?))
+            .collect()
+    }
+
+    fn save_sidecar(&self, sidecar: &Path) -> FMFResult<()> {
+        let mut f = BufWriter::new(File::create(sidecar).map_err(|e| io_path_error(sidecar, e))?);
+        f.write_all(INDEX_MAGIC)?;
+        f.write_u64::<LittleEndian>(self.file_len)?;
+        f.write_u64::<LittleEndian>(self.header_n_frames)?;
+        f.write_u64::<LittleEndian>(self.timestamps.len() as u64)?;
+        for timestamp in self.timestamps.iter() {
+            f.write_f64::<LittleEndian>(*timestamp)?;
+        }
+        f.flush()?;
+        Ok(())
+    }
+
+    /// Return the number of frames.
+    pub fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+
+    /// Return `true` if the file has no frames.
+    pub fn is_empty(&self) -> bool {
+        self.timestamps.is_empty()
+    }
+
+    /// Return the position of frame `n` in the file.
+    pub fn frame_offset(&self, n: usize) -> Option<u64> {
+        (n < self.len()).then(|| self.header_size + n as u64 * self.chunk_size)
+    }
+
+    /// Return the timestamp of frame `n`.
+    pub fn timestamp(&self, n: usize) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.timestamps.get(n).map(|t| f64_to_datetime(*t))
+    }
+
+    /// Return the index of the first frame with a timestamp at or after
+    /// `timestamp`, or [Self::len] if there is no such frame.
+    ///
+    /// The timestamps are assumed to increase monotonically.
+    pub fn frame_at_time<TZ>(&self, timestamp: &chrono::DateTime<TZ>) -> usize
+    where
+        TZ: chrono::TimeZone,
+    {
+        let t = datetime_to_f64(timestamp);
+        self.timestamps.partition_point(|x| *x < t)
+    }
+}
+
+/// Reads frames of an uncompressed FMF file in any order.
+///
+/// Iterating returns the frames from the current position onwards.
+pub struct IndexedFMFReader {
+    // Frames are large, so the reads are not buffered.
+    f: File,
+    index: FMFIndex,
+    pos: usize,
+}
+
+impl IndexedFMFReader {
+    /// Open the FMF file at `path` and build its index.
+    pub fn new<P: AsRef<Path>>(path: P) -> FMFResult<Self> {
+        let index = FMFIndex::build(path.as_ref())?;
+        Self::with_index(path, index)
+    }
+
+    /// Open the FMF file at `path` with a previously built index.
+    pub fn with_index<P: AsRef<Path>>(path: P, index: FMFIndex) -> FMFResult<Self> {
+        let path = path.as_ref();
+        let f = File::open(path).map_err(|e| io_path_error(path, e))?;
+        Ok(Self { f, index, pos: 0 })
+    }
+
+    #[inline]
+    pub fn index(&self) -> &FMFIndex {
+        &self.index
+    }
+
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.index.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.index.height
+    }
+
+    #[inline]
+    pub fn format(&self) -> PixFmt {
+        self.index.pixel_format
+    }
+
+    /// Return the number of frames.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Return `true` if the file has no frames.
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Return the index of the frame read next.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Set the position such that frame `n` is read next.
+    ///
+    /// Seeking to [Self::len] is allowed and ends the iteration.
+    pub fn seek_to_frame(&mut self, n: usize) -> FMFResult<()> {
+        if n > self.len() {
+            return Err(FMFError::ReadingPastEnd);
+        }
+        self.pos = n;
+        Ok(())
+    }
+
+    /// Set the position to the first frame with a timestamp at or after
+    /// `timestamp` and return the index of this frame.
+    pub fn seek_to_time<TZ>(&mut self, timestamp: &chrono::DateTime<TZ>) -> FMFResult<usize>
+    where
+        TZ: chrono::TimeZone,
+    {
+        let n = self.index.frame_at_time(timestamp);
+        self.seek_to_frame(n)?;
+        Ok(n)
+    }
+
+    /// Read frame `n` without changing the position.
+    pub fn read_frame(&mut self, n: usize) -> FMFResult<DynamicFrame> {
+        let offset = self.index.frame_offset(n).ok_or(FMFError::ReadingPastEnd)?;
+        self.f.seek(SeekFrom::Start(offset))?;
+        let timestamp_f64 = self.f.read_f64::<LittleEndian>()?;
+        let mut image_data = vec![0; self.index.chunk_size as usize - TIMESTAMP_SIZE];
+        self.f.read_exact(&mut image_data)?;
+        to_frame(
+            self.index.pixel_format,
+            self.index.width,
+            self.index.height,
+            timestamp_f64,
+            image_data,
+            n,
+        )
+    }
+}
+
+impl Iterator for IndexedFMFReader {
+    type Item = FMFResult<DynamicFrame>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.len() {
+            return None;
+        }
+        let frame = self.read_frame(self.pos);
+        // After an error, do not read more.
+        self.pos = if frame.is_ok() {
+            self.pos + 1
+        } else {
+            self.len()
+        };
+        Some(frame)
+    }
+}
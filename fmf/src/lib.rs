@@ -9,8 +9,9 @@ extern crate datetime_conversion;
 
 use std::f64;
 use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
 
-use byteorder::{LittleEndian, WriteBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use formats::{ImageStride, PixFmt, PixelFormat};
 
 pub type FMFResult<M> = std::result::Result<M, FMFError>;
@@ -39,6 +40,8 @@ pub enum FMFError {
 
     #[error("reading past the end of the file")]
     ReadingPastEnd,
+    #[error("cannot seek in compressed file {0}")]
+    NotSeekable(String),
 
     #[error("{source}")]
     Io {
@@ -70,6 +73,9 @@ impl From<std::cell::BorrowMutError> for FMFError {
 pub mod reader;
 pub use crate::reader::FMFReader;
 
+pub mod index;
+pub use crate::index::{FMFIndex, IndexedFMFReader};
+
 /// Writes FMF (fly movie format) movie files.
 ///
 /// The FMF format is very simple and writes a fixed sized chunk of bytes to
@@ -276,6 +282,46 @@ impl<F: Write + Seek> FMFWriterInner<F> {
     }
 }
 
+/// Repair an FMF file which was not closed, e.g. because the program writing
+/// it crashed.
+///
+/// An incomplete last frame is removed and the number of frames in the header
+/// is set to the number of complete frames, which is returned.
+pub fn repair<P: AsRef<Path>>(path: P) -> FMFResult<u64> {
+    let mut f = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path.as_ref())
+        .map_err(|e| FMFError::IoPath {
+            source: e,
+            path: path.as_ref().display().to_string(),
+            #[cfg(feature = "backtrace")]
+            backtrace: std::backtrace::Backtrace::capture(),
+        })?;
+
+    let version = f.read_u32::<LittleEndian>()?;
+    if version != 3 {
+        return Err(FMFError::UnimplementedVersion(version));
+    }
+    let format_len = f.read_u32::<LittleEndian>()?;
+    // Skip the format, bits per pixel, height and width.
+    f.seek(SeekFrom::Current(i64::from(format_len) + 12))?;
+    let chunksize = f.read_u64::<LittleEndian>()?;
+    let n_frames_pos_bytes = f.stream_position()?;
+    let header_len = n_frames_pos_bytes + 8;
+
+    let file_len = f.metadata()?.len();
+    if chunksize == 0 || file_len < header_len {
+        return Err(FMFError::PrematureFileEnd);
+    }
+    let n_frames = (file_len - header_len) / chunksize;
+    f.set_len(header_len + n_frames * chunksize)?;
+    f.seek(SeekFrom::Start(n_frames_pos_bytes))?;
+    f.write_u64::<LittleEndian>(n_frames)?;
+    f.flush()?;
+    Ok(n_frames)
+}
+
 /// This will silently ignore any error.
 impl<F: Write + Seek> Drop for FMFWriterInner<F> {
     fn drop(&mut self) {
@@ -343,4 +389,86 @@ mod tests {
         let expected = [3, 0, 0, 0, 5, 0, 0, 0, 77, 79]; // TODO improve test
         assert_eq!(&buf[0..10], expected);
     }
+
+    #[test]
+    fn test_repair() {
+        let w = 32;
+        let h = 24;
+        let mut buf = {
+            let mut writer = FMFWriter::new(std::io::Cursor::new(Vec::new())).unwrap();
+            for _ in 0..3 {
+                let frame = zeros(w, h);
+                writer
+                    .write(&frame, frame.extra().host_timestamp())
+                    .unwrap();
+            }
+            writer.close().unwrap().into_inner()
+        };
+        let full_len = buf.len();
+        let chunksize = (w * h) as usize + 8;
+        let n_frames_pos = full_len - 3 * chunksize - 8;
+
+        // Simulate a crash while writing the third frame: the header still
+        // says zero frames and the last frame is incomplete.
+        buf.truncate(full_len - 100);
+        buf[n_frames_pos..n_frames_pos + 8].copy_from_slice(&0u64.to_le_bytes());
+
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("crashed.fmf");
+        std::fs::write(&path, &buf).unwrap();
+
+        assert_eq!(super::repair(&path).unwrap(), 2);
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len() as usize,
+            full_len - chunksize
+        );
+        let reader = super::FMFReader::new(&path).unwrap();
+        assert_eq!(reader.count(), 2);
+    }
+
+    #[test]
+    fn test_indexed_reader() {
+        let start = chrono::DateTime::from_timestamp(61, 0).unwrap();
+        let stamp = |i: i64| start + chrono::Duration::milliseconds(10 * i);
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("movie.fmf");
+        {
+            let f = std::fs::File::create(&path).unwrap();
+            let mut writer = FMFWriter::new(f).unwrap();
+            for i in 0..5 {
+                let mut frame = zeros(8, 4);
+                frame.image_data[0] = i as u8;
+                writer.write(&frame, stamp(i)).unwrap();
+            }
+            writer.close().unwrap();
+        }
+        let first_pixel = |frame: basic_frame::DynamicFrame| frame.image_data_without_format()[0];
+
+        let mut reader = super::IndexedFMFReader::new(&path).unwrap();
+        assert_eq!(reader.len(), 5);
+        let error = reader.index().timestamp(4).unwrap() - stamp(4);
+        assert_eq!(error.num_microseconds(), Some(0));
+
+        reader.seek_to_frame(3).unwrap();
+        let rest: Vec<_> = reader.by_ref().map(|f| first_pixel(f.unwrap())).collect();
+        assert_eq!(rest, vec![3, 4]);
+        assert!(reader.seek_to_frame(6).is_err());
+
+        // Between frames 1 and 2, so frame 2 is next.
+        let t = stamp(1) + chrono::Duration::milliseconds(5);
+        assert_eq!(reader.seek_to_time(&t).unwrap(), 2);
+        assert_eq!(first_pixel(reader.next().unwrap().unwrap()), 2);
+        assert_eq!(reader.seek_to_time(&stamp(10)).unwrap(), 5);
+        assert!(reader.next().is_none());
+        assert_eq!(first_pixel(reader.read_frame(0).unwrap()), 0);
+
+        // The sidecar index is saved and then loaded.
+        let index = super::FMFIndex::load_or_build(&path).unwrap();
+        assert!(super::FMFIndex::sidecar_path(&path).exists());
+        assert_eq!(super::FMFIndex::load_or_build(&path).unwrap(), index);
+        assert_eq!(&index, reader.index());
+
+        let gz = tmpdir.path().join("movie.fmf.gz");
+        assert!(super::FMFIndex::build(gz).is_err());
+    }
 }
@@ -21,7 +21,7 @@ macro_rules! bf {
     }};
 }
 
-const TIMESTAMP_SIZE: usize = 8;
+pub(crate) const TIMESTAMP_SIZE: usize = 8;
 
 /// Return an DynamicFrame variant according to $pixfmt.
 #[macro_export]
@@ -157,6 +157,11 @@ impl FMFReader {
         self.n_frames
     }
 
+    /// Return the number of bytes of each frame, including its timestamp.
+    pub fn chunk_size(&self) -> usize {
+        self.image_data_size + TIMESTAMP_SIZE
+    }
+
     fn next_frame(&mut self) -> FMFResult<DynamicFrame> {
         // Private function to actually read next frame.
         if self.count >= self.n_frames {
@@ -172,25 +177,42 @@ impl FMFReader {
         self.file_pos += self.image_data_size;
 
         let timestamp_f64 = timestamp_data.as_slice().read_f64::<LittleEndian>()?;
-        let host_timestamp = f64_to_datetime(timestamp_f64);
 
-        let width = self.width;
-        let height = self.height;
-        let pixel_format = self.pixel_format;
-        let bpp = self.pixel_format.bits_per_pixel() as u32;
-        let stride = (width * bpp) / 8;
         let host_framenumber = self.count;
         self.count += 1;
 
-        let extra = Box::new(BasicExtra {
-            host_timestamp,
+        to_frame(
+            self.pixel_format,
+            self.width,
+            self.height,
+            timestamp_f64,
+            image_data,
             host_framenumber,
-        });
-
-        to_dynamic!(pixel_format, width, height, stride, image_data, extra)
+        )
     }
 }
 
+/// Create a frame from the image data and timestamp read from an FMF file.
+pub(crate) fn to_frame(
+    pixel_format: PixFmt,
+    width: u32,
+    height: u32,
+    timestamp_f64: f64,
+    image_data: Vec<u8>,
+    host_framenumber: usize,
+) -> FMFResult<DynamicFrame> {
+    let host_timestamp = f64_to_datetime(timestamp_f64);
+    let bpp = pixel_format.bits_per_pixel() as u32;
+    let stride = (width * bpp) / 8;
+
+    let extra = Box::new(BasicExtra {
+        host_timestamp,
+        host_framenumber,
+    });
+
+    to_dynamic!(pixel_format, width, height, stride, image_data, extra)
+}
+
 impl Iterator for FMFReader {
     type Item = FMFResult<DynamicFrame>;
     fn next(&mut self) -> Option<Self::Item> {
@@ -12,6 +12,9 @@ use event_stream_types::{ConnectionEvent, ConnectionEventType, EventChunkSender}
 
 pub use http_video_streaming_types::{CircleParams, DrawableShape, Point, Shape, ToClient};
 
+mod mjpeg;
+pub use mjpeg::{mjpeg_preview, MjpegConfig, MjpegEncoder, MjpegPreview, MJPEG_BOUNDARY};
+
 type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug, thiserror::Error)]
@@ -148,9 +151,10 @@ impl PerSender {
                 match self.out.send(Ok(hc)).await {
                     Ok(()) => {}
                     Err(_) => {
+                        // Failed to send data to event stream key. The
+                        // sender is dropped in the next call to
+                        // `TaskState::service`.
                         tracing::info!("failed to send data to connection. dropping.");
-                        // Failed to send data to event stream key.
-                        // TODO: drop this sender.
                     }
                 }
                 self.ready_to_send = false;
@@ -178,6 +182,8 @@ fn _test_task_state_is_send() {
 
 impl TaskState {
     async fn service(&mut self) -> Result<()> {
+        // Forget connections which were closed by the client.
+        self.per_sender_map.retain(|_key, ps| !ps.out.is_closed());
         // TODO: make sending concurrent on all listeners and set a timeout.
         for ps in self.per_sender_map.values_mut() {
             ps.service().await?;
@@ -223,6 +229,7 @@ pub async fn firehose_task(
     connection_callback_rx: tokio::sync::mpsc::Receiver<ConnectionEvent>,
     mut firehose_rx: tokio::sync::mpsc::Receiver<AnnotatedFrame>,
     firehose_callback_rx: tokio::sync::mpsc::Receiver<ConnectionKey>,
+    mut mjpeg_encoder: Option<MjpegEncoder>,
 ) -> Result<()> {
     // Wait for the first frame so we don't need to deal with an Option<>.
     let first_frame = firehose_rx.recv().await.unwrap();
@@ -255,6 +262,11 @@ pub async fn firehose_task(
             opt_new_frame = firehose_rx.recv() => {
                 match opt_new_frame {
                     Some(new_frame) => {
+                        if let Some(mjpeg_encoder) = mjpeg_encoder.as_mut() {
+                            if let Err(e) = mjpeg_encoder.handle_frame(&new_frame.frame) {
+                                tracing::error!("could not encode MJPEG preview frame: {e}");
+                            }
+                        }
                         task_state.handle_frame(new_frame)?;
                     }
                     None => {
@@ -0,0 +1,172 @@
+//! Low-latency preview as a Motion JPEG (MJPEG) stream.
+//!
+//! Unlike the firehose, which sends each frame to a single browser tab as
+//! part of the event stream and waits for it to be displayed, the MJPEG
+//! preview is a plain `multipart/x-mixed-replace` HTTP response which can be
+//! viewed by many clients, e.g. with an `<img>` tag or directly in a browser.
+//! Frames are encoded at a reduced rate and resolution, and only while at
+//! least one client is connected.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use machine_vision_formats::{pixel_format::RGB8, ImageData, Stride};
+
+use basic_frame::{match_all_dynamic_fmts, BasicExtra, BasicFrame, DynamicFrame};
+
+use crate::Result;
+
+/// The boundary between the parts of the multipart response.
+pub const MJPEG_BOUNDARY: &str = "strand-cam-mjpeg-frame";
+
+/// Configuration of the MJPEG preview stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MjpegConfig {
+    /// Maximum number of frames per second sent.
+    pub max_fps: f64,
+    /// Frames wider than this are downsampled by an integer factor.
+    pub max_width: u32,
+    /// JPEG quality, from 1 to 100.
+    pub jpeg_quality: u8,
+}
+
+impl Default for MjpegConfig {
+    fn default() -> Self {
+        Self {
+            max_fps: 5.0,
+            max_width: 640,
+            jpeg_quality: 70,
+        }
+    }
+}
+
+/// The most recent JPEG image. `None` until the first one is encoded.
+type Latest = tokio::sync::watch::Sender<Option<Bytes>>;
+
+/// Handle for HTTP handlers to subscribe to the preview stream.
+#[derive(Clone)]
+pub struct MjpegPreview {
+    tx: Arc<Latest>,
+}
+
+/// Encodes frames for the preview stream. Used by [crate::firehose_task].
+pub struct MjpegEncoder {
+    cfg: MjpegConfig,
+    tx: Arc<Latest>,
+    last_encoded: Option<Instant>,
+}
+
+/// Create a preview stream.
+pub fn mjpeg_preview(cfg: MjpegConfig) -> (MjpegEncoder, MjpegPreview) {
+    let tx = Arc::new(tokio::sync::watch::channel(None).0);
+    let encoder = MjpegEncoder {
+        cfg,
+        tx: tx.clone(),
+        last_encoded: None,
+    };
+    (encoder, MjpegPreview { tx })
+}
+
+impl MjpegEncoder {
+    /// Encode the frame if any client is connected and the rate limit allows.
+    pub(crate) fn handle_frame(&mut self, frame: &DynamicFrame) -> Result<()> {
+        if self.tx.receiver_count() == 0 {
+            return Ok(());
+        }
+        let min_interval = Duration::from_secs_f64(1.0 / self.cfg.max_fps.max(1e-3));
+        if let Some(last) = self.last_encoded {
+            if last.elapsed() < min_interval {
+                return Ok(());
+            }
+        }
+        self.last_encoded = Some(Instant::now());
+
+        let jpeg = encode_downsampled(frame, self.cfg.max_width, self.cfg.jpeg_quality)?;
+        self.tx.send_replace(Some(Bytes::from(jpeg)));
+        Ok(())
+    }
+}
+
+impl MjpegPreview {
+    /// The value of the `Content-Type` header of the stream.
+    pub fn content_type() -> String {
+        format!("multipart/x-mixed-replace; boundary={MJPEG_BOUNDARY}")
+    }
+
+    /// Subscribe to the stream.
+    ///
+    /// Each item is one part of the multipart response. Slow clients skip
+    /// frames rather than delaying other clients or acquisition.
+    pub fn subscribe(
+        &self,
+    ) -> impl futures::Stream<Item = std::result::Result<Bytes, std::convert::Infallible>> {
+        let rx = self.tx.subscribe();
+        // The current image, if any, is sent immediately.
+        futures::stream::unfold((rx, true), |(mut rx, mut is_first)| async move {
+            loop {
+                if !is_first {
+                    rx.changed().await.ok()?;
+                }
+                is_first = false;
+                let jpeg = rx.borrow_and_update().clone();
+                if let Some(jpeg) = jpeg {
+                    return Some((Ok(multipart_chunk(&jpeg)), (rx, false)));
+                }
+            }
+        })
+    }
+
+    /// The number of clients currently subscribed to the stream.
+    pub fn num_subscribers(&self) -> usize {
+        self.tx.receiver_count()
+    }
+}
+
+fn multipart_chunk(jpeg: &[u8]) -> Bytes {
+    let mut buf = format!(
+        "--{MJPEG_BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+        jpeg.len()
+    )
+    .into_bytes();
+    buf.extend_from_slice(jpeg);
+    buf.extend_from_slice(b"\r\n");
+    Bytes::from(buf)
+}
+
+/// Convert to RGB, keep every n-th pixel such that the width is at most
+/// `max_width` and encode as JPEG.
+fn encode_downsampled(frame: &DynamicFrame, max_width: u32, quality: u8) -> Result<Vec<u8>> {
+    let rgb = match_all_dynamic_fmts!(frame, x, convert_image::convert_ref::<_, RGB8>(x))?;
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    let step = width.div_ceil(max_width.max(1) as usize).max(1);
+    let (dest_width, dest_height) = (width.div_ceil(step), height.div_ceil(step));
+
+    let src = rgb.image_data();
+    let src_stride = rgb.stride();
+    let mut image_data = Vec::with_capacity(dest_width * dest_height * 3);
+    for row in (0..height).step_by(step) {
+        let src_row = &src[row * src_stride..];
+        for col in (0..width).step_by(step) {
+            image_data.extend_from_slice(&src_row[col * 3..col * 3 + 3]);
+        }
+    }
+
+    let small = BasicFrame::<RGB8> {
+        width: dest_width.try_into().unwrap(),
+        height: dest_height.try_into().unwrap(),
+        stride: (dest_width * 3).try_into().unwrap(),
+        image_data,
+        pixel_format: std::marker::PhantomData,
+        extra: Box::new(BasicExtra {
+            host_timestamp: chrono::Utc::now(),
+            host_framenumber: 0,
+        }),
+    };
+    Ok(convert_image::frame_to_encoded_buffer(
+        &small,
+        convert_image::EncoderOptions::Jpeg(quality),
+    )?)
+}
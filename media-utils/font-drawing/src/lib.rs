@@ -1,91 +1,165 @@
-use rusttype::{point, Scale};
-use machine_vision_formats::{pixel_format, ImageMutStride};
 use eyre::Result;
+use machine_vision_formats::{pixel_format, ImageMutStride, PixelFormat};
+use rusttype::{point, Scale};
 
 struct Rgba(pub [u8; 4]);
 
-fn put_pixel(image: &mut dyn ImageMutStride<pixel_format::RGB8>, x: u32, y: u32, incoming: Rgba) {
-    let row_start = image.stride() as usize * y as usize;
-    let pix_start = row_start + x as usize * 3;
+/// A pixel format into which text can be drawn.
+pub trait StampPixel: PixelFormat {
+    /// Number of bytes per pixel.
+    const BYTES_PER_PIXEL: usize;
+    /// Blend `colour` with opacity `alpha` (from 0 to 1) into the pixel `dest`.
+    fn blend(dest: &mut [u8], colour: [u8; 3], alpha: f64);
+}
+
+impl StampPixel for pixel_format::RGB8 {
+    const BYTES_PER_PIXEL: usize = 3;
+    fn blend(dest: &mut [u8], colour: [u8; 3], alpha: f64) {
+        for (old, new) in dest.iter_mut().zip(colour) {
+            *old = (*old as f64 * (1.0 - alpha) + new as f64 * alpha).round() as u8;
+        }
+    }
+}
+
+impl StampPixel for pixel_format::Mono8 {
+    const BYTES_PER_PIXEL: usize = 1;
+    fn blend(dest: &mut [u8], colour: [u8; 3], alpha: f64) {
+        let luma = 0.299 * colour[0] as f64 + 0.587 * colour[1] as f64 + 0.114 * colour[2] as f64;
+        dest[0] = (dest[0] as f64 * (1.0 - alpha) + luma * alpha).round() as u8;
+    }
+}
+
+fn put_pixel<F: StampPixel>(image: &mut dyn ImageMutStride<F>, x: u32, y: u32, incoming: Rgba) {
+    // Glyphs may extend beyond the image.
+    if x >= image.width() || y >= image.height() {
+        return;
+    }
+    let row_start = image.stride() * y as usize;
+    let pix_start = row_start + x as usize * F::BYTES_PER_PIXEL;
 
     let alpha = incoming.0[3] as f64 / 255.0;
-    let p = 1.0 - alpha;
-    let q = alpha;
-
-    let old: [u8; 3] = image.image_data()[pix_start..pix_start + 3]
-        .try_into()
-        .unwrap();
-    let new: [u8; 3] = [
-        (old[0] as f64 * p + incoming.0[0] as f64 * q).round() as u8,
-        (old[1] as f64 * p + incoming.0[1] as f64 * q).round() as u8,
-        (old[2] as f64 * p + incoming.0[2] as f64 * q).round() as u8,
-    ];
-
-    image.buffer_mut_ref().data[pix_start] = new[0];
-    image.buffer_mut_ref().data[pix_start + 1] = new[1];
-    image.buffer_mut_ref().data[pix_start + 2] = new[2];
+    let colour = [incoming.0[0], incoming.0[1], incoming.0[2]];
+    let mut buf = image.buffer_mut_ref();
+    F::blend(
+        &mut buf.data[pix_start..pix_start + F::BYTES_PER_PIXEL],
+        colour,
+        alpha,
+    );
 }
 
-pub fn stamp_frame<'a>(image: &mut dyn ImageMutStride<pixel_format::RGB8>, font: &rusttype::Font<'a>, text: &str) -> Result<()> {
-    // from https://gitlab.redox-os.org/redox-os/rusttype/blob/master/dev/examples/image.rs
+/// The corner of the image in which text is drawn.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
 
-    // The font size to use
-    let scale = Scale::uniform(32.0);
+/// How text is drawn by [stamp_frame_styled].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StampStyle {
+    /// The font size, in pixels.
+    pub font_size: f32,
+    pub corner: Corner,
+    /// Distance between the text and the edges of the image, in pixels.
+    pub margin: f32,
+    /// Opacity of the text and its background, from 0 (invisible) to 1.
+    pub opacity: f32,
+    pub text_colour: [u8; 3],
+    /// If set, the text is drawn on a box of this colour.
+    pub background_colour: Option<[u8; 3]>,
+}
+
+impl Default for StampStyle {
+    fn default() -> Self {
+        Self {
+            font_size: 32.0,
+            corner: Corner::TopLeft,
+            margin: 20.0,
+            opacity: 1.0,
+            // Use a dark red colour on white.
+            text_colour: [150, 0, 0],
+            background_colour: Some([255, 255, 255]),
+        }
+    }
+}
+
+/// Draw `text` in the top left corner of `image` with the default
+/// [StampStyle].
+pub fn stamp_frame<'a>(
+    image: &mut dyn ImageMutStride<pixel_format::RGB8>,
+    font: &rusttype::Font<'a>,
+    text: &str,
+) -> Result<()> {
+    stamp_frame_styled(image, font, text, &StampStyle::default())
+}
 
-    // Use a dark red colour
-    let colour = (150, 0, 0);
+/// Draw a single line of `text` into `image`.
+pub fn stamp_frame_styled<'a, F: StampPixel>(
+    image: &mut dyn ImageMutStride<F>,
+    font: &rusttype::Font<'a>,
+    text: &str,
+    style: &StampStyle,
+) -> Result<()> {
+    // from https://gitlab.redox-os.org/redox-os/rusttype/blob/master/dev/examples/image.rs
+
+    let scale = Scale::uniform(style.font_size);
+    let opacity = style.opacity.clamp(0.0, 1.0);
 
     let v_metrics = font.v_metrics(scale);
 
-    let x0 = 20.0;
-    let y0 = 20.0;
+    // Find the most visually pleasing width to display
+    let width = font
+        .layout(text, scale, point(0.0, 0.0))
+        .last()
+        .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
+        .unwrap_or(0.0)
+        .ceil();
+    let height = (v_metrics.ascent - v_metrics.descent).ceil();
+
+    let right = image.width() as f32 - style.margin - width;
+    let bottom = image.height() as f32 - style.margin - height;
+    let (x0, y0) = match style.corner {
+        Corner::TopLeft => (style.margin, style.margin),
+        Corner::TopRight => (right, style.margin),
+        Corner::BottomLeft => (style.margin, bottom),
+        Corner::BottomRight => (right, bottom),
+    };
+    let (x0, y0) = (x0.max(0.0).floor(), y0.max(0.0).floor());
 
-    // layout the glyphs in a line with 20 pixels padding
     let glyphs: Vec<_> = font
         .layout(text, scale, point(x0, y0 + v_metrics.ascent))
         .collect();
 
-    // Find the most visually pleasing width to display
-    let width = glyphs
-        .iter()
-        .rev()
-        .map(|g| g.position().x as f32 + g.unpositioned().h_metrics().advance_width)
-        .next()
-        .unwrap_or(0.0)
-        .ceil() as usize;
-
-    let x_start = x0.floor() as usize;
-    let x_end = x_start + width;
-
-    let y_start = y0.floor() as usize;
-    let y_end = y_start + v_metrics.ascent.ceil() as usize;
-
-    for x in x_start..x_end {
-        for y in y_start..y_end {
-            put_pixel(
-                image,
-                // Offset the position by the glyph bounding box
-                x as u32,
-                y as u32,
-                // Turn the coverage into an alpha value
-                Rgba([255, 255, 255, 255]),
-            )
+    if let Some(bg) = style.background_colour {
+        let alpha = (opacity * 255.0).round() as u8;
+        let x_start = x0 as u32;
+        let y_start = y0 as u32;
+        for x in x_start..x_start + width as u32 {
+            for y in y_start..y_start + height as u32 {
+                put_pixel(image, x, y, Rgba([bg[0], bg[1], bg[2], alpha]));
+            }
         }
     }
 
-    // TODO: clear background
-
+    let colour = style.text_colour;
     for glyph in glyphs {
         if let Some(bounding_box) = glyph.pixel_bounding_box() {
             // Draw the glyph into the image per-pixel by using the draw closure
             glyph.draw(|x, y, v| {
+                let x = x as i32 + bounding_box.min.x;
+                let y = y as i32 + bounding_box.min.y;
+                if x < 0 || y < 0 {
+                    return;
+                }
                 put_pixel(
                     image,
-                    // Offset the position by the glyph bounding box
-                    x + bounding_box.min.x as u32,
-                    y + bounding_box.min.y as u32,
+                    x as u32,
+                    y as u32,
                     // Turn the coverage into an alpha value
-                    Rgba([colour.0, colour.1, colour.2, (v * 255.0) as u8]),
+                    Rgba([colour[0], colour[1], colour[2], (v * opacity * 255.0) as u8]),
                 )
             });
         }
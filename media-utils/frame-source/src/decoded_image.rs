@@ -0,0 +1,86 @@
+use openh264::formats::YUVSource;
+
+use basic_frame::DynamicFrame;
+use machine_vision_formats::PixFmt;
+use timestamped_frame::HostTimeData;
+
+/// An image decoded by OpenH264, not yet associated with its frame timing.
+pub(crate) struct DecodedImage {
+    width: u32,
+    height: u32,
+    stride: u32,
+    data: Vec<u8>,
+    pixel_format: PixFmt,
+}
+
+impl DecodedImage {
+    /// Copy the image data from the decoder.
+    ///
+    /// If `nv12` is true, the YUV 4:2:0 data of the decoder is kept as NV12.
+    /// Otherwise, it is converted to RGB8.
+    pub(crate) fn from_yuv(decoded_yuv: &openh264::decoder::DecodedYUV<'_>, nv12: bool) -> Self {
+        let (width, height) = decoded_yuv.dimensions();
+        let (stride, data, pixel_format) = if nv12 {
+            let (stride, data) = yuv420_to_nv12(decoded_yuv);
+            (stride, data, PixFmt::NV12)
+        } else {
+            let stride = width * 3;
+            let mut data = vec![0u8; stride * height];
+            decoded_yuv.write_rgb8(&mut data);
+            (stride, data, PixFmt::RGB8)
+        };
+        Self {
+            width: width.try_into().unwrap(),
+            height: height.try_into().unwrap(),
+            stride: stride.try_into().unwrap(),
+            data,
+            pixel_format,
+        }
+    }
+
+    pub(crate) fn into_dynamic_frame(self, extra: Box<dyn HostTimeData>) -> DynamicFrame {
+        DynamicFrame::new(
+            self.width,
+            self.height,
+            self.stride,
+            extra,
+            self.data,
+            self.pixel_format,
+        )
+    }
+}
+
+/// Interleave the U and V planes of `yuv` into NV12 layout.
+///
+/// Returns the stride and the image data: the Y plane followed by the UV plane
+/// with half the number of rows, both with the same stride.
+fn yuv420_to_nv12<Y: YUVSource>(yuv: &Y) -> (usize, Vec<u8>) {
+    let (width, height) = yuv.dimensions();
+    let (y_stride, u_stride, v_stride) = yuv.strides();
+    let chroma_width = width.div_ceil(2);
+    let chroma_height = height.div_ceil(2);
+    // Each UV row holds `chroma_width` pairs of bytes.
+    let stride = chroma_width * 2;
+    let mut data = vec![0u8; stride * (height + chroma_height)];
+    let (y_dest, uv_dest) = data.split_at_mut(stride * height);
+
+    for (dest_row, src_row) in y_dest
+        .chunks_exact_mut(stride)
+        .zip(yuv.y().chunks(y_stride))
+    {
+        dest_row[..width].copy_from_slice(&src_row[..width]);
+    }
+    for (dest_row, (u_row, v_row)) in uv_dest
+        .chunks_exact_mut(stride)
+        .zip(yuv.u().chunks(u_stride).zip(yuv.v().chunks(v_stride)))
+    {
+        for (uv, (u, v)) in dest_row
+            .chunks_exact_mut(2)
+            .zip(u_row[..chroma_width].iter().zip(&v_row[..chroma_width]))
+        {
+            uv[0] = *u;
+            uv[1] = *v;
+        }
+    }
+    (stride, data)
+}
@@ -1,29 +1,43 @@
-use crate::{FrameData, FrameDataSource, ImageData, Timestamp};
+use crate::{Estimate, FrameData, FrameDataSource, ImageData, Timestamp};
+use basic_frame::DynamicFrame;
 use eyre::{self as anyhow, Result, WrapErr};
-use fmf::reader::FMFReader;
+use fmf::{reader::FMFReader, FMFIndex, IndexedFMFReader};
 use std::path::Path;
 use timestamped_frame::ExtraTimeData;
 
 struct FmfSourceIter {
-    rdr: FMFReader,
+    rdr: Box<dyn Iterator<Item = fmf::FMFResult<DynamicFrame>>>,
     frame0_time_utc: chrono::DateTime<chrono::Utc>,
+    chunk_size: usize,
     idx: usize,
 }
 impl FmfSourceIter {
     fn new(parent: &FmfSource) -> Result<Self> {
-        let mut rdr = FMFReader::new(&parent.filename).with_context(|| {
+        let open_err = || {
             anyhow::anyhow!(
                 "Error from FMFReader opening '{}'",
                 parent.filename.display()
             )
-        })?;
-        let frame0_time_utc = parent.frame0_time_utc;
-        for _ in 0..parent.skip_frames {
-            rdr.next();
-        }
+        };
+        let rdr: Box<dyn Iterator<Item = _>> = match &parent.index {
+            Some(index) => {
+                let mut rdr = IndexedFMFReader::with_index(&parent.filename, index.clone())
+                    .with_context(open_err)?;
+                rdr.seek_to_frame(parent.skip_frames)?;
+                Box::new(rdr)
+            }
+            None => {
+                let mut rdr = FMFReader::new(&parent.filename).with_context(open_err)?;
+                for _ in 0..parent.skip_frames {
+                    rdr.next();
+                }
+                Box::new(rdr)
+            }
+        };
         Ok(Self {
             rdr,
-            frame0_time_utc,
+            frame0_time_utc: parent.frame0_time_utc,
+            chunk_size: parent.chunk_size,
             idx: 0,
         })
     }
@@ -31,11 +45,9 @@ impl FmfSourceIter {
 impl Iterator for FmfSourceIter {
     type Item = Result<FrameData>;
     fn next(&mut self) -> Option<Self::Item> {
-        let pos_start = self.rdr.file_pos();
         self.rdr.next().map(|fmf_result| match fmf_result {
             Ok(frame) => {
-                let pos_end = self.rdr.file_pos();
-                let buf_len = pos_end - pos_start;
+                let buf_len = self.chunk_size;
                 let frame_time_utc = frame.extra().host_timestamp();
                 let timestamp = frame_time_utc - self.frame0_time_utc;
                 let timestamp = Timestamp::Duration(timestamp.to_std()?);
@@ -46,6 +58,8 @@ impl Iterator for FmfSourceIter {
                     timestamp,
                     buf_len,
                     idx,
+                    camera_settings: None,
+                    is_keyframe: true,
                 })
             }
             Err(e) => Err(anyhow::Error::from(e)),
@@ -54,11 +68,9 @@ impl Iterator for FmfSourceIter {
 }
 
 // Because of the need to create an iterator over the frames an arbitrary number
-// of times but the inability of `FMFReader` to seek (due to underlying
-// potential use of a .gz file reader which does not support seeking), we store
-// the filename and repeatedly reopen the file as necessary. An an optimization,
-// the opened reader and its last read frame could be kept in a cache. This
-// would reduce the number of re-openings.
+// of times, we store the filename and repeatedly reopen the file as necessary.
+// Uncompressed files are indexed once, so that skipped frames are not read.
+// Compressed (.gz) files cannot seek and are read from the start each time.
 pub struct FmfSource {
     filename: std::path::PathBuf,
     width: u32,
@@ -66,6 +78,11 @@ pub struct FmfSource {
     frame0_time_utc: chrono::DateTime<chrono::Utc>,
     frame0_time: chrono::DateTime<chrono::FixedOffset>,
     skip_frames: usize,
+    /// Number of frames in the file, from the header.
+    n_frames: usize,
+    chunk_size: usize,
+    /// The index of an uncompressed file.
+    index: Option<FMFIndex>,
 }
 
 impl FrameDataSource for FmfSource {
@@ -82,25 +99,31 @@ impl FrameDataSource for FmfSource {
         if n_frames == 0 {
             return Ok(());
         }
-        let mut rdr = FMFReader::new(&self.filename).with_context(|| {
-            anyhow::anyhow!("Error from FMFReader opening '{}'", self.filename.display())
-        })?;
+        let missing_frames = || {
+            anyhow::anyhow!(
+                "fmf file without {n_frames} of data '{}'",
+                self.filename.display()
+            )
+        };
+        let frame_time_utc = if let Some(index) = &self.index {
+            // The first frame after the skipped frames.
+            index.timestamp(n_frames).ok_or_else(missing_frames)?
+        } else {
+            let mut rdr = FMFReader::new(&self.filename).with_context(|| {
+                anyhow::anyhow!("Error from FMFReader opening '{}'", self.filename.display())
+            })?;
 
-        let mut frame = None;
-        for _ in 0..n_frames {
-            frame = rdr.next()
-        }
+            let mut frame = None;
+            for _ in 0..n_frames {
+                frame = rdr.next()
+            }
 
-        let frame = frame
-            .map(|f| f.map_err(anyhow::Error::from))
-            .unwrap_or_else(|| {
-                anyhow::bail!(
-                    "fmf file without {n_frames} of data '{}'",
-                    self.filename.display()
-                )
-            })?;
+            let frame = frame
+                .map(|f| f.map_err(anyhow::Error::from))
+                .unwrap_or_else(|| Err(missing_frames()))?;
+            frame.extra().host_timestamp()
+        };
 
-        let frame_time_utc = frame.extra().host_timestamp();
         let duration = frame_time_utc - self.frame0_time_utc;
         let frame_time = self.frame0_time + duration;
 
@@ -122,6 +145,23 @@ impl FrameDataSource for FmfSource {
     fn has_timestamps(&self) -> bool {
         true
     }
+    fn frame_count(&self) -> Option<Estimate<usize>> {
+        Some(Estimate::exact(
+            self.n_frames.saturating_sub(self.skip_frames),
+        ))
+    }
+    fn keyframe_indices(&self) -> Option<Vec<usize>> {
+        // Every frame is stored uncompressed.
+        Some((0..self.frame_count()?.value).collect())
+    }
+    fn duration(&self) -> Option<Estimate<std::time::Duration>> {
+        let last = self.last_frame_time().ok()?;
+        let duration = (last.value - self.frame0_time_utc).to_std().ok()?;
+        Some(Estimate {
+            value: duration,
+            is_exact: last.is_exact,
+        })
+    }
 }
 
 impl FmfSource {
@@ -139,6 +179,12 @@ impl FmfSource {
 
         let frame0_time_utc = frame0.extra().host_timestamp();
         let frame0_time = mkv_strand_reader::infer_timezone(&frame0_time_utc, filename.to_str())?;
+        let index = match FMFIndex::build(&filename) {
+            Ok(index) => Some(index),
+            Err(fmf::FMFError::NotSeekable(_)) => None,
+            Err(e) => return Err(e.into()),
+        };
+        let n_frames = index.as_ref().map(FMFIndex::len).unwrap_or(rdr.n_frames());
 
         Ok(Self {
             filename,
@@ -147,8 +193,44 @@ impl FmfSource {
             frame0_time_utc,
             frame0_time,
             skip_frames: 0,
+            n_frames,
+            chunk_size: rdr.chunk_size(),
+            index,
         })
     }
+
+    /// Get the time of the last frame.
+    ///
+    /// In uncompressed files, the timestamp of the last frame is taken from
+    /// the index. Compressed (`.gz`) files cannot seek, so the time is
+    /// extrapolated from the first two frames.
+    fn last_frame_time(&self) -> Result<Estimate<chrono::DateTime<chrono::Utc>>> {
+        let last_idx = self
+            .n_frames
+            .checked_sub(1)
+            .ok_or_else(|| anyhow::anyhow!("FMF file has no frames"))?;
+        if let Some(index) = &self.index {
+            let last = index
+                .timestamp(last_idx)
+                .ok_or_else(|| anyhow::anyhow!("FMF file has no frames"))?;
+            Ok(Estimate::exact(last))
+        } else {
+            let mut rdr = FMFReader::new(&self.filename)?;
+            let mut next_time = || -> Result<_> {
+                let frame = rdr
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("FMF file ended"))??;
+                Ok(frame.extra().host_timestamp())
+            };
+            let t0 = next_time()?;
+            if last_idx == 0 {
+                return Ok(Estimate::exact(t0));
+            }
+            let t1 = next_time()?;
+            let n_intervals = i32::try_from(last_idx)?;
+            Ok(Estimate::estimated(t0 + (t1 - t0) * n_intervals))
+        }
+    }
 }
 
 pub fn from_path<P: AsRef<Path>>(path: P) -> Result<FmfSource> {
@@ -1,5 +1,6 @@
 // Copyright 2022-2024 Andrew D. Straw.
 use std::{
+    collections::{BTreeMap, VecDeque},
     io::{BufReader, Read, Seek},
     path::Path,
 };
@@ -14,16 +15,19 @@ use h264_reader::{
     rbsp::BitReaderError,
     Context as H264ParsingContext,
 };
-use openh264::formats::YUVSource;
 use serde::{Deserialize, Serialize};
 
-use ci2_remote_control::{H264Metadata, H264_METADATA_UUID, H264_METADATA_VERSION};
+use ci2_remote_control::{
+    FrameCameraSettings, H264Metadata, FRAME_CAMERA_SETTINGS_UUID, H264_METADATA_UUID,
+    H264_METADATA_VERSION,
+};
 
 use crate::{
+    decoded_image::DecodedImage,
     ntp_timestamp::NtpTimestamp,
     srt_reader::{self, Stanza},
-    EncodedH264, FrameData, FrameDataSource, H264EncodingVariant, ImageData, MyAsStr, Result,
-    Timestamp, TimestampSource,
+    EncodedH264, Estimate, FrameData, FrameDataSource, H264EncodingVariant, ImageData,
+    ImageDataMode, MyAsStr, Result, Timestamp, TimestampSource,
 };
 
 struct SrtData {
@@ -93,19 +97,38 @@ impl SrtData {
 /// information (SEI) which is ignored by decoders but can provide additional
 /// information such as metadata at the start of H264 data and per-frame
 /// timestamps as specified in MISB ST 0604.3.
+///
+/// Per-frame camera settings such as exposure time and gain are read from SEI
+/// messages with [FRAME_CAMERA_SETTINGS_UUID] and returned with
+/// [FrameData::camera_settings].
+///
+/// ## Frame reordering
+///
+/// With B-frames, frames are stored in decode order, which differs from the
+/// order in which they are presented. In MP4 files, the composition time
+/// offsets give the presentation timestamp (PTS) of each sample. If these are
+/// not monotonic, the decoded images, which the decoder returns in
+/// presentation order, are matched with the timing information (PTS,
+/// `MISPmicrosectime` and so on) of the frame presented at that position.
+/// This is indicated by [FrameDataSource::pts_reordered]. Raw H264 files carry
+/// no information about reordering and are assumed to contain no B-frames.
 pub struct H264Source<H: SeekableH264Source> {
     seekable_h264_source: H,
     /// For every NAL unit, the coordinates in the source to read it.
     nal_locations: Vec<H::NalLocation>,
     /// timestamps from MP4 files, one per MP4 sample (which we assume to be one per frame)
     mp4_pts: Option<Vec<std::time::Duration>>,
+    /// If frames are not stored in presentation order, the decode order
+    /// indices of the frames in presentation order.
+    presentation_order: Option<Vec<usize>>,
     frame_time_info: Vec<FrameTimeInfo>,
     pub h264_metadata: Option<H264Metadata>,
     frame0_precision_time: Option<chrono::DateTime<chrono::FixedOffset>>,
     frame0_frameinfo_recv_ntp: Option<NtpTimestamp>,
     width: u32,
     height: u32,
-    do_decode_h264: bool,
+    image_data_mode: ImageDataMode,
+    decode_to_nv12: bool,
     timestamp_source: Option<crate::TimestampSource>,
     has_timestamps: bool,
     srt_data: Option<SrtData>,
@@ -121,6 +144,9 @@ pub struct FrameTimeInfo {
     nal_location_index: usize,
     precise_timestamp: Option<DateTime<Utc>>,
     frameinfo_recv_ntp: Option<NtpTimestamp>,
+    camera_settings: Option<FrameCameraSettings>,
+    /// Whether the frame is an IDR frame.
+    is_idr: bool,
 }
 
 impl<H: SeekableH264Source> FrameDataSource for H264Source<H> {
@@ -163,8 +189,11 @@ impl<H: SeekableH264Source> FrameDataSource for H264Source<H> {
     fn estimate_luminance_range(&mut self) -> Result<(u16, u16)> {
         anyhow::bail!("h264 luminance scanning not implemented");
     }
+    fn set_decode_to_nv12(&mut self, value: bool) {
+        self.decode_to_nv12 = value;
+    }
     fn iter<'a>(&'a mut self) -> Box<dyn Iterator<Item = Result<FrameData>> + 'a> {
-        let openh264_decoder_state = if self.do_decode_h264 {
+        let openh264_decoder_state = if self.image_data_mode.decodes() {
             Some(openh264::decoder::Decoder::new().unwrap())
         } else {
             None
@@ -173,7 +202,12 @@ impl<H: SeekableH264Source> FrameDataSource for H264Source<H> {
             parent: self,
             frame_idx: 0,
             next_nal_idx: 0,
+            n_returned: 0,
             openh264_decoder_state,
+            buf_lens: Vec::new(),
+            encoded: BTreeMap::new(),
+            flushed: VecDeque::new(),
+            did_flush: false,
         })
     }
     fn timestamp_source(&self) -> &str {
@@ -182,6 +216,68 @@ impl<H: SeekableH264Source> FrameDataSource for H264Source<H> {
     fn has_timestamps(&self) -> bool {
         self.has_timestamps
     }
+    fn pts_reordered(&self) -> bool {
+        self.presentation_order.is_some()
+    }
+    fn frame_count(&self) -> Option<Estimate<usize>> {
+        // All NAL units were read when opening the source.
+        Some(Estimate::exact(self.frame_time_info.len()))
+    }
+    fn keyframe_indices(&self) -> Option<Vec<usize>> {
+        let is_idr = |idx: usize| self.frame_time_info[idx].is_idr;
+        let indices = match &self.presentation_order {
+            // Decoded frames are returned in presentation order.
+            Some(order) if self.image_data_mode.decodes() => order
+                .iter()
+                .enumerate()
+                .filter(|(_, &src_idx)| is_idr(src_idx))
+                .map(|(idx, _)| idx)
+                .collect(),
+            _ => (0..self.frame_time_info.len())
+                .filter(|&idx| is_idr(idx))
+                .collect(),
+        };
+        Some(indices)
+    }
+    fn duration(&self) -> Option<Estimate<std::time::Duration>> {
+        // With B-frames, the last frame in decode order is not necessarily
+        // the last one presented, so take the maximum.
+        let duration = match &self.timestamp_source {
+            Some(TimestampSource::BestGuess) => unreachable!(),
+            Some(TimestampSource::MispMicrosectime) => {
+                let f0 = self.frame0_precision_time.as_ref()?;
+                let last = self
+                    .frame_time_info
+                    .iter()
+                    .filter_map(|x| x.precise_timestamp)
+                    .max()?;
+                last.signed_duration_since(*f0).to_std().ok()?
+            }
+            Some(TimestampSource::FrameInfoRecvTime) => {
+                let t0: DateTime<Utc> = self.frame0_frameinfo_recv_ntp?.into();
+                let last = self
+                    .frame_time_info
+                    .iter()
+                    .filter_map(|x| x.frameinfo_recv_ntp)
+                    .map(DateTime::<Utc>::from)
+                    .max()?;
+                last.signed_duration_since(t0).to_std().ok()?
+            }
+            Some(TimestampSource::Mp4Pts) => self.mp4_pts.as_ref()?.iter().max().copied()?,
+            Some(TimestampSource::SrtFile) => {
+                let srt_data = self.srt_data.as_ref()?;
+                let last = srt_data
+                    .stanzas
+                    .get(self.frame_time_info.len().checked_sub(1)?)?;
+                SrtData::parse_time(last)
+                    .signed_duration_since(srt_data.frame0_time())
+                    .to_std()
+                    .ok()?
+            }
+            None => return None,
+        };
+        Some(Estimate::exact(duration))
+    }
 }
 
 pub(crate) struct FromMp4Track {
@@ -258,7 +354,7 @@ where
 {
     pub(crate) fn from_seekable_h264_source_with_timestamp_source(
         mut seekable_h264_source: H,
-        do_decode_h264: bool,
+        image_data_mode: ImageDataMode,
         mp4_pts: Option<Vec<std::time::Duration>>,
         data_from_mp4_track: Option<FromMp4Track>,
         timestamp_source: crate::TimestampSource,
@@ -300,6 +396,9 @@ where
         // Cached value of NTP received time data for the frame whose data is
         // being accumulated.
         let mut frameinfo_recv_ntp = None;
+        // Cached value of camera settings for the frame whose data is being
+        // accumulated.
+        let mut camera_settings = None;
         // Cached value of frame number as we accumluate data.
         let mut next_frame_num = 0;
 
@@ -406,6 +505,11 @@ where
                                                             frameinfo_recv_ntp;
                                                     }
                                                 }
+                                                &FRAME_CAMERA_SETTINGS_UUID => {
+                                                    let cs: FrameCameraSettings =
+                                                        serde_json::from_slice(udu.payload)?;
+                                                    camera_settings = Some(cs);
+                                                }
                                                 _uuid => {
                                                     // anyhow::bail!("unexpected SEI UDU UUID: {uuid:?}");
                                                 }
@@ -470,6 +574,8 @@ where
                             nal_location_index,
                             precise_timestamp,
                             frameinfo_recv_ntp,
+                            camera_settings: camera_settings.take(),
+                            is_idr: nal_unit_type == UnitType::SliceLayerWithoutPartitioningIdr,
                         });
                         // Reset temporary values.
                         precise_timestamp = None;
@@ -546,17 +652,24 @@ where
             }
         }
 
+        let presentation_order = mp4_pts.as_deref().and_then(presentation_order);
+        if presentation_order.is_some() {
+            tracing::info!("Frames are not stored in presentation order, reordering by PTS.");
+        }
+
         Ok(Self {
             seekable_h264_source,
             nal_locations,
             mp4_pts,
+            presentation_order,
             frame_time_info,
             h264_metadata,
             frame0_precision_time,
             frame0_frameinfo_recv_ntp,
             width,
             height,
-            do_decode_h264,
+            image_data_mode,
+            decode_to_nv12: false,
             timestamp_source,
             has_timestamps,
             srt_data,
@@ -566,149 +679,245 @@ where
 
 struct RawH264Iter<'parent, H: SeekableH264Source> {
     parent: &'parent mut H264Source<H>,
-    /// frame index (not NAL unit index)
+    /// frame index (not NAL unit index) of the next frame to read, in decode
+    /// order
     frame_idx: usize,
     next_nal_idx: usize,
+    /// number of frames returned
+    n_returned: usize,
     openh264_decoder_state: Option<openh264::decoder::Decoder>,
+    /// number of bytes of each frame read, in decode order
+    buf_lens: Vec<usize>,
+    /// With [ImageDataMode::Both], the NAL units of the frames passed to the
+    /// decoder but not yet returned, by index in decode order
+    encoded: BTreeMap<usize, Vec<Vec<u8>>>,
+    /// decoded images remaining in the decoder at the end of the stream
+    flushed: VecDeque<DecodedImage>,
+    did_flush: bool,
 }
 
-impl<'parent, H: SeekableH264Source> Iterator for RawH264Iter<'parent, H> {
-    type Item = Result<FrameData>;
-    fn next(&mut self) -> Option<Self::Item> {
-        let frame_number = self.frame_idx;
-        let res = self.parent.frame_time_info.get(self.frame_idx);
+impl<'parent, H: SeekableH264Source> RawH264Iter<'parent, H> {
+    /// The index of the first NAL location of frame `idx` (in decode order).
+    ///
+    /// For MP4 files, this is also the index of the MP4 sample.
+    fn first_nal_idx(&self, idx: usize) -> usize {
+        if idx == 0 {
+            0
+        } else {
+            self.parent.frame_time_info[idx - 1].nal_location_index + 1
+        }
+    }
+
+    /// Read the NAL units of the next frame in decode order.
+    fn read_next(&mut self) -> Option<Result<Vec<Vec<u8>>>> {
+        let nti = self.parent.frame_time_info.get(self.frame_idx)?;
         self.frame_idx += 1;
 
-        res.map(|nti| {
-            // create slice of all NAL units up and including NALU for the frame
-            let nal_locations =
-                &self.parent.nal_locations[self.next_nal_idx..=(nti.nal_location_index)];
-            let mp4_pts = self.parent.mp4_pts.as_ref().map(|x| x[self.next_nal_idx]); // one per mp4 sample
-            let fraction_done = self.next_nal_idx as f32 / self.parent.nal_locations.len() as f32;
-
-            self.next_nal_idx = nti.nal_location_index + 1;
-
-            let frame_timestamp = match self.parent.timestamp_source {
-                Some(TimestampSource::BestGuess) => unreachable!(),
-                Some(TimestampSource::MispMicrosectime) => {
-                    let f0 = self.parent.frame0_precision_time.as_ref().unwrap();
-                    Timestamp::Duration(
-                        nti.precise_timestamp
-                            .unwrap()
-                            .signed_duration_since(*f0)
-                            .to_std()
-                            .unwrap(),
-                    )
-                }
-                Some(TimestampSource::FrameInfoRecvTime) => {
-                    let t0 = self.parent.frame0_frameinfo_recv_ntp.as_ref().unwrap();
-                    let t0: chrono::DateTime<chrono::Utc> = (*t0).into();
-                    let this_frame: chrono::DateTime<chrono::Utc> =
-                        nti.frameinfo_recv_ntp.unwrap().into();
-                    Timestamp::Duration(this_frame.signed_duration_since(t0).to_std().unwrap())
-                }
-                Some(TimestampSource::Mp4Pts) => Timestamp::Duration(mp4_pts.unwrap()),
-                Some(TimestampSource::SrtFile) => {
-                    let srt_data = self.parent.srt_data.as_mut().unwrap();
-                    let pts = srt_data.next_pts().unwrap();
-                    Timestamp::Duration(pts)
+        // create slice of all NAL units up and including NALU for the frame
+        let nal_locations =
+            &self.parent.nal_locations[self.next_nal_idx..=(nti.nal_location_index)];
+        self.next_nal_idx = nti.nal_location_index + 1;
+
+        let result = self
+            .parent
+            .seekable_h264_source
+            .read_nal_units_at_locations(nal_locations);
+        if let Ok(nal_units) = &result {
+            self.buf_lens.push(nal_units.iter().map(|x| x.len()).sum());
+        }
+        Some(result)
+    }
+
+    /// Compute the timestamp of frame `idx` (in decode order).
+    ///
+    /// Must be called in the order in which frames are returned.
+    fn frame_timestamp(&mut self, idx: usize) -> Timestamp {
+        let nal_idx = self.first_nal_idx(idx);
+        let nti = &self.parent.frame_time_info[idx];
+        let mp4_pts = self.parent.mp4_pts.as_ref().map(|x| x[nal_idx]); // one per mp4 sample
+        match self.parent.timestamp_source {
+            Some(TimestampSource::BestGuess) => unreachable!(),
+            Some(TimestampSource::MispMicrosectime) => {
+                let f0 = self.parent.frame0_precision_time.as_ref().unwrap();
+                Timestamp::Duration(
+                    nti.precise_timestamp
+                        .unwrap()
+                        .signed_duration_since(*f0)
+                        .to_std()
+                        .unwrap(),
+                )
+            }
+            Some(TimestampSource::FrameInfoRecvTime) => {
+                let t0 = self.parent.frame0_frameinfo_recv_ntp.as_ref().unwrap();
+                let t0: chrono::DateTime<chrono::Utc> = (*t0).into();
+                let this_frame: chrono::DateTime<chrono::Utc> =
+                    nti.frameinfo_recv_ntp.unwrap().into();
+                Timestamp::Duration(this_frame.signed_duration_since(t0).to_std().unwrap())
+            }
+            Some(TimestampSource::Mp4Pts) => Timestamp::Duration(mp4_pts.unwrap()),
+            Some(TimestampSource::SrtFile) => {
+                let srt_data = self.parent.srt_data.as_mut().unwrap();
+                let pts = srt_data.next_pts().unwrap();
+                Timestamp::Duration(pts)
+            }
+            None => {
+                let fraction_done = nal_idx as f32 / self.parent.nal_locations.len() as f32;
+                Timestamp::Fraction(fraction_done)
+            }
+        }
+    }
+
+    /// Compute the host timestamp of frame `idx` (in decode order).
+    fn host_timestamp(&self, idx: usize) -> DateTime<Utc> {
+        let nal_idx = self.first_nal_idx(idx);
+        let mp4_pts = self.parent.mp4_pts.as_ref().map(|x| x[nal_idx]);
+        match self.parent.frame_time_info[idx].precise_timestamp {
+            Some(ts) => ts,
+            None => {
+                if let (Some(mp4_pts), Some(md)) = (mp4_pts, &self.parent.h264_metadata) {
+                    md.creation_time.with_timezone(&chrono::Utc)
+                        + chrono::Duration::from_std(mp4_pts).unwrap()
+                } else {
+                    // No possible source of timestamp, use dummy value.
+                    chrono::TimeZone::timestamp_opt(&chrono::Utc, 0, 0).unwrap()
                 }
-                None => Timestamp::Fraction(fraction_done),
-            };
-
-            let nal_units = self
-                .parent
-                .seekable_h264_source
-                .read_nal_units_at_locations(nal_locations)?;
-
-            if let Some(decoder) = &mut self.openh264_decoder_state {
-                // copy into Annex B format for OpenH264
-                let annex_b = copy_nalus_to_annex_b(nal_units.as_slice());
-
-                let decode_result = decoder.decode(&annex_b[..]);
-                match decode_result {
-                    Ok(Some(decoded_yuv)) => {
-                        let dim = decoded_yuv.dimensions();
-
-                        let stride = dim.0 * 3;
-                        let mut image_data = vec![0u8; stride * dim.1];
-                        decoded_yuv.write_rgb8(&mut image_data);
-
-                        let host_timestamp = match nti.precise_timestamp {
-                            Some(ts) => ts,
-                            None => {
-                                if let (Some(mp4_pts), Some(md)) =
-                                    (mp4_pts, &self.parent.h264_metadata)
-                                {
-                                    md.creation_time.with_timezone(&chrono::Utc)
-                                        + chrono::Duration::from_std(mp4_pts).unwrap()
-                                } else {
-                                    // No possible source of timestamp, use dummy value.
-                                    chrono::TimeZone::timestamp_opt(&chrono::Utc, 0, 0).unwrap()
-                                }
-                            }
-                        };
+            }
+        }
+    }
 
-                        let extra = Box::new(basic_frame::BasicExtra {
-                            host_timestamp,
-                            host_framenumber: frame_number,
-                        });
-                        let dynamic_frame =
-                            basic_frame::DynamicFrame::RGB8(basic_frame::BasicFrame::<
-                                machine_vision_formats::pixel_format::RGB8,
-                            > {
-                                width: dim.0.try_into().unwrap(),
-                                height: dim.1.try_into().unwrap(),
-                                stride: u32::try_from(stride).unwrap(),
-                                image_data,
-                                pixel_format: std::marker::PhantomData,
-                                extra,
-                            });
-
-                        let buf_len = nal_units.iter().map(|x| x.len()).sum();
-                        // let buf_len = avcc_data.len();
-                        let idx = frame_number;
-                        let image = ImageData::Decoded(dynamic_frame);
-                        Ok(FrameData {
-                            timestamp: frame_timestamp,
-                            image,
-                            buf_len,
-                            idx,
-                        })
+    /// Return the next decoded image with the timing of its source frame.
+    fn decoded_frame(&mut self, decoded: DecodedImage) -> FrameData {
+        let frame_number = self.n_returned;
+        self.n_returned += 1;
+        // The decoder returns images in presentation order.
+        let src_idx = match &self.parent.presentation_order {
+            Some(order) => order[frame_number],
+            None => frame_number,
+        };
+        let frame_timestamp = self.frame_timestamp(src_idx);
+        let host_timestamp = self.host_timestamp(src_idx);
+
+        let extra = Box::new(basic_frame::BasicExtra {
+            host_timestamp,
+            host_framenumber: frame_number,
+        });
+        let dynamic_frame = decoded.into_dynamic_frame(extra);
+
+        let image = match self.encoded.remove(&src_idx) {
+            Some(nal_units) => ImageData::Both(
+                dynamic_frame,
+                EncodedH264 {
+                    data: H264EncodingVariant::RawEbsp(nal_units),
+                    has_precision_timestamp: self.parent.frame0_precision_time.is_some(),
+                },
+            ),
+            None => ImageData::Decoded(dynamic_frame),
+        };
+
+        FrameData {
+            timestamp: frame_timestamp,
+            image,
+            buf_len: self.buf_lens[src_idx],
+            idx: frame_number,
+            camera_settings: self.parent.frame_time_info[src_idx].camera_settings.clone(),
+            is_keyframe: self.parent.frame_time_info[src_idx].is_idr,
+        }
+    }
+
+    fn next_decoded(&mut self) -> Result<Option<FrameData>> {
+        loop {
+            if let Some(decoded) = self.flushed.pop_front() {
+                return Ok(Some(self.decoded_frame(decoded)));
+            }
+            match self.read_next() {
+                Some(nal_units) => {
+                    let nal_units = nal_units?;
+                    // copy into Annex B format for OpenH264
+                    let annex_b = copy_nalus_to_annex_b(nal_units.as_slice());
+                    if self.parent.image_data_mode == ImageDataMode::Both {
+                        self.encoded.insert(self.frame_idx - 1, nal_units);
+                    }
+                    let nv12 = self.parent.decode_to_nv12;
+                    let decoder = self.openh264_decoder_state.as_mut().unwrap();
+                    match decoder.decode(&annex_b[..])? {
+                        Some(decoded_yuv) => {
+                            let decoded = DecodedImage::from_yuv(&decoded_yuv, nv12);
+                            return Ok(Some(self.decoded_frame(decoded)));
+                        }
+                        None => {
+                            if self.parent.presentation_order.is_none() {
+                                anyhow::bail!("decoder unexpectedly did not return image data");
+                            }
+                            // The decoder holds back frames until the frames
+                            // referenced by them are decoded.
+                        }
+                    }
+                }
+                None => {
+                    // Without reordering, the decoder returns every frame
+                    // immediately and does not need to be flushed.
+                    if self.did_flush || self.parent.presentation_order.is_none() {
+                        return Ok(None);
+                    }
+                    self.did_flush = true;
+                    let nv12 = self.parent.decode_to_nv12;
+                    let decoder = self.openh264_decoder_state.as_mut().unwrap();
+                    for decoded_yuv in decoder.flush_remaining()?.iter() {
+                        self.flushed
+                            .push_back(DecodedImage::from_yuv(decoded_yuv, nv12));
+                    }
+                    if self.flushed.is_empty() {
+                        return Ok(None);
                     }
-                    Ok(None) => Err(anyhow::anyhow!(
-                        "decoder unexpectedly did not return image data"
-                    )),
-                    Err(decode_err) => Err(decode_err.into()),
                 }
-            } else {
-                let buf_len = nal_units.iter().map(|x| x.len()).sum();
-                // let buf_len = avcc_data.len();
-                let idx = frame_number;
-                let buf = EncodedH264 {
-                    data: H264EncodingVariant::RawEbsp(nal_units.to_vec()),
-                    has_precision_timestamp: self.parent.frame0_precision_time.is_some(),
-                };
-                let image = ImageData::EncodedH264(buf);
-                Ok(FrameData {
-                    timestamp: frame_timestamp,
-                    image,
-                    buf_len,
-                    idx,
-                })
             }
-        })
+        }
+    }
+
+    fn next_encoded(&mut self) -> Option<Result<FrameData>> {
+        let frame_number = self.frame_idx;
+        let nal_units = match self.read_next()? {
+            Ok(nal_units) => nal_units,
+            Err(e) => return Some(Err(e)),
+        };
+        self.n_returned += 1;
+        let frame_timestamp = self.frame_timestamp(frame_number);
+        let buf = EncodedH264 {
+            data: H264EncodingVariant::RawEbsp(nal_units),
+            has_precision_timestamp: self.parent.frame0_precision_time.is_some(),
+        };
+        Some(Ok(FrameData {
+            timestamp: frame_timestamp,
+            image: ImageData::EncodedH264(buf),
+            buf_len: self.buf_lens[frame_number],
+            idx: frame_number,
+            camera_settings: self.parent.frame_time_info[frame_number]
+                .camera_settings
+                .clone(),
+            is_keyframe: self.parent.frame_time_info[frame_number].is_idr,
+        }))
+    }
+}
+
+impl<'parent, H: SeekableH264Source> Iterator for RawH264Iter<'parent, H> {
+    type Item = Result<FrameData>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.openh264_decoder_state.is_some() {
+            self.next_decoded().transpose()
+        } else {
+            self.next_encoded()
+        }
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        let remaining = self.parent.frame_time_info.len() - self.frame_idx;
+        let remaining = self.parent.frame_time_info.len() - self.n_returned;
         (remaining, Some(remaining))
     }
 }
 
 pub(crate) fn from_annexb_path_with_timestamp_source<P: AsRef<Path>>(
     path: P,
-    do_decode_h264: bool,
+    image_data_mode: ImageDataMode,
     timestamp_source: crate::TimestampSource,
     srt_file_path: Option<std::path::PathBuf>,
 ) -> Result<H264Source<H264AnnexBSource>> {
@@ -717,7 +926,7 @@ pub(crate) fn from_annexb_path_with_timestamp_source<P: AsRef<Path>>(
     let seekable_h264_source = H264AnnexBSource::from_file(rdr)?;
     from_annexb_reader_with_timestamp_source(
         seekable_h264_source,
-        do_decode_h264,
+        image_data_mode,
         timestamp_source,
         srt_file_path,
     )
@@ -726,13 +935,13 @@ pub(crate) fn from_annexb_path_with_timestamp_source<P: AsRef<Path>>(
 
 fn from_annexb_reader_with_timestamp_source(
     annex_b_source: H264AnnexBSource,
-    do_decode_h264: bool,
+    image_data_mode: ImageDataMode,
     timestamp_source: crate::TimestampSource,
     srt_file_path: Option<std::path::PathBuf>,
 ) -> Result<H264Source<H264AnnexBSource>> {
     H264Source::from_seekable_h264_source_with_timestamp_source(
         annex_b_source,
-        do_decode_h264,
+        image_data_mode,
         None,
         None,
         timestamp_source,
@@ -803,6 +1012,20 @@ pub(crate) fn parse_precision_time(payload: &[u8]) -> Result<chrono::DateTime<ch
     Ok(epoch_start + dur)
 }
 
+/// Sort frames by presentation timestamp.
+///
+/// Returns the indices of the frames in presentation order, or `None` if the
+/// frames are already in presentation order.
+fn presentation_order(pts: &[std::time::Duration]) -> Option<Vec<usize>> {
+    if pts.windows(2).all(|w| w[0] <= w[1]) {
+        return None;
+    }
+    let mut order: Vec<usize> = (0..pts.len()).collect();
+    // stable sort keeps the decode order of frames with equal PTS
+    order.sort_by_key(|idx| pts[*idx]);
+    Some(order)
+}
+
 /// Copy raw headerless EBSP NAL units to Annex B
 fn copy_nalus_to_annex_b(nalus: &[Vec<u8>]) -> Vec<u8> {
     let sz = nalus.iter().fold(0, |acc, x| acc + x.len() + 4);
@@ -841,10 +1064,9 @@ mod test {
             let cursor = std::io::Cursor::new(file_buf);
             let seekable_h264_source = H264AnnexBSource::from_readseek(Box::new(cursor))?;
 
-            let do_decode_h264 = true;
             let mut h264_src = from_annexb_reader_with_timestamp_source(
                 seekable_h264_source,
-                do_decode_h264,
+                ImageDataMode::Decoded,
                 TimestampSource::BestGuess,
                 None,
             )?;
@@ -858,18 +1080,60 @@ mod test {
             let file_buf = include_bytes!("test-data/test_less-avc_rgb8_16x16.h264");
             let cursor = std::io::Cursor::new(file_buf);
             let seekable_h264_source = H264AnnexBSource::from_readseek(Box::new(cursor))?;
-            let do_decode_h264 = true;
             let mut h264_src = from_annexb_reader_with_timestamp_source(
                 seekable_h264_source,
-                do_decode_h264,
+                ImageDataMode::Both,
                 TimestampSource::BestGuess,
                 None,
             )?;
             assert_eq!(h264_src.width(), 16);
             assert_eq!(h264_src.height(), 16);
-            let frames: Vec<_> = h264_src.iter().collect();
+            let frames: Vec<_> = h264_src.iter().collect::<Result<_>>()?;
+            assert_eq!(frames.len(), 1);
+            assert!(frames[0].is_keyframe());
+            assert_eq!(h264_src.keyframe_indices(), Some(vec![0]));
+            // The decoded image comes with the data it was decoded from.
+            assert_eq!(frames[0].decoded().unwrap().width(), 16);
+            let encoded = frames[0].encoded_h264().unwrap();
+            match &encoded.data {
+                H264EncodingVariant::RawEbsp(nal_units) => {
+                    let n_bytes: usize = nal_units.iter().map(|x| x.len()).sum();
+                    assert_eq!(n_bytes, frames[0].num_bytes());
+                }
+                other => panic!("unexpected encoding {other:?}"),
+            }
+        }
+
+        {
+            let file_buf = include_bytes!("test-data/test_less-avc_rgb8_16x16.h264");
+            let cursor = std::io::Cursor::new(file_buf);
+            let seekable_h264_source = H264AnnexBSource::from_readseek(Box::new(cursor))?;
+            let mut h264_src = from_annexb_reader_with_timestamp_source(
+                seekable_h264_source,
+                ImageDataMode::Decoded,
+                TimestampSource::BestGuess,
+                None,
+            )?;
+            h264_src.set_decode_to_nv12(true);
+            let frames: Vec<_> = h264_src.iter().collect::<Result<_>>()?;
             assert_eq!(frames.len(), 1);
+            let decoded = frames[0].decoded().unwrap();
+            assert_eq!(decoded.pixel_format(), machine_vision_formats::PixFmt::NV12);
+            assert_eq!(decoded.width(), 16);
+            // Full resolution Y plane and half resolution interleaved UV plane.
+            assert_eq!(decoded.image_data_without_format().len(), 16 * 16 * 3 / 2);
         }
         Ok(())
     }
+
+    #[test]
+    fn test_presentation_order() {
+        let ms = std::time::Duration::from_millis;
+        assert_eq!(presentation_order(&[ms(0), ms(10), ms(20)]), None);
+        // I P B B in decode order
+        assert_eq!(
+            presentation_order(&[ms(0), ms(30), ms(10), ms(20)]),
+            Some(vec![0, 2, 3, 1])
+        );
+    }
 }
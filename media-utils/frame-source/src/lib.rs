@@ -4,15 +4,18 @@ use std::path::PathBuf;
 use eyre::{self as anyhow, Result};
 
 use basic_frame::DynamicFrame;
+pub use ci2_remote_control::FrameCameraSettings;
 
 pub mod pv_tiff_stack;
 use pv_tiff_stack::TiffImage;
+mod decoded_image;
 pub mod fmf_source;
 mod h264_annexb_splitter;
 pub mod h264_source;
 pub mod mp4_source;
 mod srt_reader;
 pub mod strand_cam_mkv_source;
+pub mod ufmf_source;
 
 mod ntp_timestamp;
 #[cfg(test)]
@@ -22,9 +25,9 @@ mod test_timestamps;
 ///
 /// The `frame0_time` method return value is an `Option` because we want to be
 /// able to parse sources without an absolute time for the first frame, such as
-/// normal MP4 video files. Similarly, we do not have a `len` method indicating
-/// number of frames because some sources (e.g. an .h264 file) do not store how
-/// many frames they have but rather must be parsed from beginning to end.
+/// normal MP4 video files. Similarly, [FrameDataSource::frame_count] and
+/// [FrameDataSource::duration] return an `Option` of an [Estimate] because not
+/// all sources can determine these without iterating over all frames.
 pub trait FrameDataSource {
     /// Get the width of the source images, in pixels.
     fn width(&self) -> u32;
@@ -57,10 +60,77 @@ pub trait FrameDataSource {
     fn has_timestamps(&self) -> bool;
     /// A string describing the source of the timestamp data
     fn timestamp_source(&self) -> &str;
+    /// Whether frames are stored in a different order than they are presented.
+    ///
+    /// This is the case for H264 streams with B-frames, in which the decode
+    /// timestamp (DTS) differs from the presentation timestamp (PTS). When
+    /// decoding, such frames are returned in presentation order with the
+    /// timing information of the respective source frame. When not decoding,
+    /// frames are returned in decode order and their timestamps are not
+    /// monotonic.
+    fn pts_reordered(&self) -> bool {
+        false
+    }
+    /// Get the number of frames returned by [FrameDataSource::iter], if it can
+    /// be determined without iterating over the frames.
+    ///
+    /// This takes into account frames skipped with
+    /// [FrameDataSource::skip_n_frames].
+    fn frame_count(&self) -> Option<Estimate<usize>> {
+        None
+    }
+    /// Get the timestamp of the last frame, if it can be determined without
+    /// iterating over the frames.
+    ///
+    /// As with [FrameData::timestamp], this is the duration since the first
+    /// frame. `None` if the source has no timestamps.
+    fn duration(&self) -> Option<Estimate<std::time::Duration>> {
+        None
+    }
+    /// Get the indices, as returned by [FrameData::idx], of the keyframes
+    /// (see [FrameData::is_keyframe]), if they can be determined without
+    /// iterating over the frames.
+    ///
+    /// Each keyframe starts a group of pictures (GOP) which can be decoded
+    /// independently of earlier frames.
+    fn keyframe_indices(&self) -> Option<Vec<usize>> {
+        None
+    }
+    /// Set whether decoded H264 frames are returned as [DynamicFrame::NV12]
+    /// rather than converted to RGB8 (the default).
+    ///
+    /// Keeping the YUV data of the decoder avoids the conversion to RGB and
+    /// back when the frames are encoded again, e.g. to H264. Sources which do
+    /// not decode H264 ignore this. Takes effect for the next
+    /// [FrameDataSource::iter].
+    fn set_decode_to_nv12(&mut self, _value: bool) {}
     /// Get an iterator over all frames.
     fn iter<'a>(&'a mut self) -> Box<dyn Iterator<Item = Result<FrameData>> + 'a>;
 }
 
+/// A value which was either determined exactly or estimated.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Estimate<T> {
+    pub value: T,
+    /// True if `value` is exact, false if it is an estimate.
+    pub is_exact: bool,
+}
+
+impl<T> Estimate<T> {
+    pub fn exact(value: T) -> Self {
+        Self {
+            value,
+            is_exact: true,
+        }
+    }
+    pub fn estimated(value: T) -> Self {
+        Self {
+            value,
+            is_exact: false,
+        }
+    }
+}
+
 /// A single frame of data, including `image` and `timestamp` fields.
 #[derive(PartialEq, Debug)]
 pub struct FrameData {
@@ -72,6 +142,11 @@ pub struct FrameData {
     ///
     /// Starts with 0
     idx: usize,
+    /// Camera settings such as exposure time and gain, if stored with the
+    /// frame.
+    camera_settings: Option<FrameCameraSettings>,
+    /// Whether the frame can be decoded without earlier frames.
+    is_keyframe: bool,
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -121,16 +196,39 @@ impl FrameData {
         self.idx
     }
 
+    /// Get the camera settings (e.g. exposure time and gain) in effect when
+    /// the frame was acquired, if stored with the frame.
+    pub fn camera_settings(&self) -> Option<&FrameCameraSettings> {
+        self.camera_settings.as_ref()
+    }
+
+    /// Whether the frame can be decoded without earlier frames.
+    ///
+    /// For H264, this is an IDR frame, at which decoding (and thus seeking or
+    /// copying the encoded data) can start. Frames of formats without
+    /// compression between frames, e.g. FMF, are always keyframes.
+    pub fn is_keyframe(&self) -> bool {
+        self.is_keyframe
+    }
+
     pub fn decoded(&self) -> Option<&DynamicFrame> {
         match &self.image {
-            ImageData::Decoded(frame) => Some(frame),
+            ImageData::Decoded(frame) | ImageData::Both(frame, _) => Some(frame),
             _ => None,
         }
     }
 
     pub fn take_decoded(self) -> Option<DynamicFrame> {
         match self.image {
-            ImageData::Decoded(frame) => Some(frame),
+            ImageData::Decoded(frame) | ImageData::Both(frame, _) => Some(frame),
+            _ => None,
+        }
+    }
+
+    /// Get the encoded H264 data, if available.
+    pub fn encoded_h264(&self) -> Option<&EncodedH264> {
+        match &self.image {
+            ImageData::EncodedH264(encoded) | ImageData::Both(_, encoded) => Some(encoded),
             _ => None,
         }
     }
@@ -142,6 +240,8 @@ pub enum ImageData {
     Decoded(DynamicFrame),
     Tiff(TiffImage),
     EncodedH264(EncodedH264),
+    /// The decoded image together with the H264 data it was decoded from.
+    Both(DynamicFrame, EncodedH264),
 }
 
 impl std::fmt::Debug for ImageData {
@@ -156,6 +256,9 @@ impl std::fmt::Debug for ImageData {
             ImageData::EncodedH264(_) => {
                 write!(f, "ImageData::EncodedH264")
             }
+            ImageData::Both(_, _) => {
+                write!(f, "ImageData::Both")
+            }
         }
     }
 }
@@ -188,6 +291,29 @@ pub struct EncodedH264 {
     pub has_precision_timestamp: bool,
 }
 
+/// Which image data is returned for the frames of an H264 source.
+///
+/// Sources which are not H264 encoded always return their own image data
+/// (e.g. [ImageData::Decoded] or [ImageData::Tiff]) regardless of this.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ImageDataMode {
+    /// Return the encoded data without decoding ([ImageData::EncodedH264]).
+    #[default]
+    Encoded,
+    /// Decode the frames ([ImageData::Decoded]).
+    Decoded,
+    /// Decode the frames and also return the encoded data they were decoded
+    /// from ([ImageData::Both]).
+    Both,
+}
+
+impl ImageDataMode {
+    /// Whether frames need to be decoded.
+    pub fn decodes(&self) -> bool {
+        matches!(self, Self::Decoded | Self::Both)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum TimestampSource {
     BestGuess,
@@ -217,38 +343,88 @@ impl MyAsStr for Option<TimestampSource> {
 
 /// Create a [FrameDataSource] from a path.
 ///
-/// The `do_decode_h264` argument specifies that an H264 source will be decoded
-/// (e.g. to extract individual images).
+/// The `image_data_mode` argument specifies whether an H264 source will be
+/// decoded (e.g. to extract individual images), see [ImageDataMode].
 pub fn from_path<P: AsRef<std::path::Path>>(
     input: P,
-    do_decode_h264: bool,
+    image_data_mode: ImageDataMode,
 ) -> Result<Box<dyn FrameDataSource>> {
-    from_path_with_srt_timestamp_source(input, do_decode_h264, TimestampSource::BestGuess, None)
+    from_path_with_srt_timestamp_source(input, image_data_mode, TimestampSource::BestGuess, None)
 }
 
 /// Create a [FrameDataSource] from a path with defined timestamp source
 ///
-/// The `do_decode_h264` argument specifies that an H264 source will be decoded
-/// (e.g. to extract individual images).
+/// The `image_data_mode` argument specifies whether an H264 source will be
+/// decoded (e.g. to extract individual images), see [ImageDataMode].
 pub fn from_path_with_timestamp_source<P: AsRef<std::path::Path>>(
     input: P,
-    do_decode_h264: bool,
+    image_data_mode: ImageDataMode,
     timestamp_source: TimestampSource,
 ) -> Result<Box<dyn FrameDataSource>> {
-    from_path_with_srt_timestamp_source(input, do_decode_h264, timestamp_source, None)
+    from_path_with_srt_timestamp_source(input, image_data_mode, timestamp_source, None)
 }
 
 /// Create a [FrameDataSource] from a path with defined timestamp source
 ///
-/// The `do_decode_h264` argument specifies that an H264 source will be decoded
-/// (e.g. to extract individual images).
+/// The `image_data_mode` argument specifies whether an H264 source will be
+/// decoded (e.g. to extract individual images), see [ImageDataMode].
 pub fn from_path_with_srt_timestamp_source<P: AsRef<std::path::Path>>(
     input: P,
-    do_decode_h264: bool,
+    image_data_mode: ImageDataMode,
     timestamp_source: TimestampSource,
     srt_file_path: Option<PathBuf>,
 ) -> Result<Box<dyn FrameDataSource>> {
+    from_path_with_options(
+        input,
+        FromPathOptions {
+            image_data_mode,
+            timestamp_source,
+            srt_file_path,
+            ..Default::default()
+        },
+    )
+}
+
+/// Options of [from_path_with_options].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FromPathOptions {
+    /// Whether an H264 source will be decoded, see [ImageDataMode].
+    pub image_data_mode: ImageDataMode,
+    pub timestamp_source: TimestampSource,
+    /// SRT file with the timestamps of the frames.
+    pub srt_file_path: Option<PathBuf>,
+    /// The video track of an MP4 file. Only the default may be given for other
+    /// inputs.
+    pub mp4_track: mp4_source::Mp4TrackSelection,
+}
+
+impl Default for FromPathOptions {
+    fn default() -> Self {
+        Self {
+            image_data_mode: ImageDataMode::default(),
+            timestamp_source: TimestampSource::BestGuess,
+            srt_file_path: None,
+            mp4_track: Default::default(),
+        }
+    }
+}
+
+/// Create a [FrameDataSource] from a path with the given options.
+pub fn from_path_with_options<P: AsRef<std::path::Path>>(
+    input: P,
+    options: FromPathOptions,
+) -> Result<Box<dyn FrameDataSource>> {
+    let FromPathOptions {
+        image_data_mode,
+        timestamp_source,
+        srt_file_path,
+        mp4_track,
+    } = options;
     let input_path = PathBuf::from(input.as_ref());
+    let is_mp4 = input_path.extension().and_then(|x| x.to_str()) == Some("mp4");
+    if !is_mp4 && mp4_track != mp4_source::Mp4TrackSelection::Single {
+        eyre::bail!("MP4 track selection given, but input is not an MP4 file");
+    }
     let is_file = std::fs::metadata(input.as_ref())?.is_file();
     if is_file {
         if let Some(extension) = input_path.extension() {
@@ -259,17 +435,18 @@ pub fn from_path_with_srt_timestamp_source<P: AsRef<std::path::Path>>(
                     }
                     let mkv_video = strand_cam_mkv_source::from_path_with_timestamp_source(
                         &input,
-                        do_decode_h264,
+                        image_data_mode,
                         timestamp_source,
                     )?;
                     return Ok(Box::new(mkv_video));
                 }
                 Some("mp4") => {
-                    let mp4_video = mp4_source::from_path_with_timestamp_source(
+                    let mp4_video = mp4_source::from_path_with_track(
                         &input,
-                        do_decode_h264,
+                        image_data_mode,
                         timestamp_source,
                         srt_file_path,
+                        &mp4_track,
                     )?;
                     return Ok(Box::new(mp4_video));
                 }
@@ -279,7 +456,7 @@ pub fn from_path_with_srt_timestamp_source<P: AsRef<std::path::Path>>(
                     }
                     let h264_video = h264_source::from_annexb_path_with_timestamp_source(
                         &input,
-                        do_decode_h264,
+                        image_data_mode,
                         timestamp_source,
                         None,
                     )?;
@@ -293,6 +470,13 @@ pub fn from_path_with_srt_timestamp_source<P: AsRef<std::path::Path>>(
             let fmf_video = fmf_source::from_path(&input)?;
             return Ok(Box::new(fmf_video));
         }
+        if fname_lower.ends_with(".ufmf") {
+            if srt_file_path.is_some() {
+                eyre::bail!("srt file given, but not supported for ufmf files");
+            }
+            let ufmf_video = ufmf_source::from_path(&input)?;
+            return Ok(Box::new(ufmf_video));
+        }
         anyhow::bail!(
             "input {} is a file, but the extension was not recognized.",
             input.as_ref().display()
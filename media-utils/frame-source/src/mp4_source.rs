@@ -3,10 +3,64 @@
 use std::path::Path;
 
 use eyre::{self as anyhow, Result, WrapErr};
+use h264_reader::nal::{
+    sei::{HeaderType, SeiReader},
+    Nal, RefNal, UnitType,
+};
+use serde::{Deserialize, Serialize};
 
-use crate::h264_source::{H264Source, SeekRead, SeekableH264Source};
+use ci2_remote_control::{H264Metadata, H264_METADATA_UUID};
+
+use crate::{
+    h264_source::{H264Source, SeekRead, SeekableH264Source, UserDataUnregistered},
+    ImageDataMode,
+};
 use mp4::MediaType;
 
+/// Which H264 video track of an MP4 file is read.
+///
+/// Some MP4 files contain more than one video track, e.g. the main video and
+/// a thumbnail.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mp4TrackSelection {
+    /// The only H264 track. It is an error if there are several.
+    #[default]
+    Single,
+    /// The H264 track at this index, counting from zero in the order of the
+    /// track ids.
+    Index(usize),
+    /// The track with this track id.
+    TrackId(u32),
+    /// The H264 track whose embedded H264 metadata has this camera name.
+    CameraName(String),
+}
+
+/// Information about an H264 video track of an MP4 file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mp4TrackInfo {
+    pub track_id: u32,
+    pub width: u16,
+    pub height: u16,
+    pub sample_count: u32,
+    /// The camera name in the H264 metadata of the first sample, if present.
+    pub camera_name: Option<String>,
+}
+
+impl std::fmt::Display for Mp4TrackInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "track id {}: {}x{}, {} samples",
+            self.track_id, self.width, self.height, self.sample_count
+        )?;
+        if let Some(camera_name) = &self.camera_name {
+            write!(f, ", camera \"{camera_name}\"")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Mp4NalLocation {
     track_id: u32,
@@ -40,31 +94,110 @@ impl SeekableH264Source for Mp4Source {
     }
 }
 
-pub(crate) fn from_reader_with_timestamp_source(
-    mut mp4_reader: mp4::Mp4Reader<Box<dyn SeekRead + Send>>,
-    do_decode_h264: bool,
-    timestamp_source: crate::TimestampSource,
-    srt_file_path: Option<std::path::PathBuf>,
-) -> Result<H264Source<Mp4Source>> {
-    let timescale = mp4_reader.timescale();
-    let mut video_track = None;
-    for (track_id, track) in mp4_reader.tracks().iter() {
+/// List the H264 video tracks, in the order of their track ids.
+fn h264_tracks<R: SeekRead>(mp4_reader: &mut mp4::Mp4Reader<R>) -> Result<Vec<Mp4TrackInfo>> {
+    let mut result = Vec::new();
+    for track in mp4_reader.tracks().values() {
         // ignore all tracks except H264
         if track.media_type()? == MediaType::H264 {
-            if video_track.is_some() {
-                anyhow::bail!("only MP4 files with a single H264 video track are supported");
+            result.push(Mp4TrackInfo {
+                track_id: track.track_id(),
+                width: track.width(),
+                height: track.height(),
+                sample_count: track.sample_count(),
+                camera_name: None,
+            });
+        }
+    }
+    result.sort_by_key(|t| t.track_id);
+    for info in result.iter_mut() {
+        info.camera_name = track_camera_name(mp4_reader, info.track_id)?;
+    }
+    Ok(result)
+}
+
+/// Get the camera name from the H264 metadata in the first sample of a track.
+fn track_camera_name<R: SeekRead>(
+    mp4_reader: &mut mp4::Mp4Reader<R>,
+    track_id: u32,
+) -> Result<Option<String>> {
+    let Some(sample) = mp4_reader.read_sample(track_id, 1)? else {
+        return Ok(None);
+    };
+    let mut scratch = Vec::new();
+    for nal_unit in avcc_to_nalu_ebsp(sample.bytes.as_ref())? {
+        let nal = RefNal::new(nal_unit, &[], true);
+        if nal.header().ok().map(|h| h.nal_unit_type()) != Some(UnitType::SEI) {
+            continue;
+        }
+        let mut sei_reader = SeiReader::from_rbsp_bytes(nal.rbsp_bytes(), &mut scratch);
+        while let Ok(Some(sei_message)) = sei_reader.next() {
+            if sei_message.payload_type != HeaderType::UserDataUnregistered {
+                continue;
+            }
+            let udu = UserDataUnregistered::read(&sei_message)?;
+            if udu.uuid == &H264_METADATA_UUID {
+                let md: H264Metadata = serde_json::from_slice(udu.payload)?;
+                return Ok(md.camera_name);
             }
-            video_track = Some((track_id, track));
         }
     }
+    Ok(None)
+}
+
+/// List the H264 video tracks of the MP4 file at `path`.
+pub fn list_tracks<P: AsRef<Path>>(path: P) -> Result<Vec<Mp4TrackInfo>> {
+    let mut mp4_reader = open_path(path.as_ref())?;
+    h264_tracks(&mut mp4_reader)
+        .with_context(|| format!("Reading MP4 file {}", path.as_ref().display()))
+}
 
-    let (track_id, track) = if let Some(vt) = video_track {
-        vt
-    } else {
-        anyhow::bail!("No H264 video track found in MP4 file.");
+/// Find the id of the selected H264 track.
+fn select_track(tracks: &[Mp4TrackInfo], selection: &Mp4TrackSelection) -> Result<u32> {
+    let found = match selection {
+        Mp4TrackSelection::Single => {
+            if tracks.len() > 1 {
+                let list: Vec<String> = tracks.iter().map(|t| t.to_string()).collect();
+                anyhow::bail!(
+                    "MP4 file has {} H264 video tracks ({}). Select one by index, track id \
+                    or camera name.",
+                    tracks.len(),
+                    list.join("; ")
+                );
+            }
+            tracks.first()
+        }
+        Mp4TrackSelection::Index(index) => tracks.get(*index),
+        Mp4TrackSelection::TrackId(track_id) => tracks.iter().find(|t| t.track_id == *track_id),
+        Mp4TrackSelection::CameraName(name) => {
+            let mut matching = tracks
+                .iter()
+                .filter(|t| t.camera_name.as_deref() == Some(name.as_str()));
+            let first = matching.next();
+            if matching.next().is_some() {
+                anyhow::bail!("More than one H264 video track with camera name \"{name}\".");
+            }
+            first
+        }
     };
+    match found {
+        Some(t) => Ok(t.track_id),
+        None if tracks.is_empty() => anyhow::bail!("No H264 video track found in MP4 file."),
+        None => anyhow::bail!("No H264 video track matching {selection:?} found in MP4 file."),
+    }
+}
 
-    let track_id = *track_id;
+pub(crate) fn from_reader_with_timestamp_source(
+    mut mp4_reader: mp4::Mp4Reader<Box<dyn SeekRead + Send>>,
+    image_data_mode: ImageDataMode,
+    timestamp_source: crate::TimestampSource,
+    srt_file_path: Option<std::path::PathBuf>,
+    track_selection: &Mp4TrackSelection,
+) -> Result<H264Source<Mp4Source>> {
+    let timescale = mp4_reader.timescale();
+    let tracks = h264_tracks(&mut mp4_reader)?;
+    let track_id = select_track(&tracks, track_selection)?;
+    let track = &mp4_reader.tracks()[&track_id];
 
     // Iterate over every sample in the track. Typically (always?) one such MP4
     // sample corresponds to one frame of video (and often multiple NAL units).
@@ -76,11 +209,31 @@ pub(crate) fn from_reader_with_timestamp_source(
         sequence_parameter_set: track.sequence_parameter_set()?.to_vec(),
         picture_parameter_set: track.picture_parameter_set()?.to_vec(),
     };
+    // The sample start times are decode timestamps (DTS). With B-frames, the
+    // composition time offsets must be added to get the presentation
+    // timestamps (PTS).
+    let ctts_entries: Vec<(u32, i32)> = track
+        .trak
+        .mdia
+        .minf
+        .stbl
+        .ctts
+        .as_ref()
+        .map(|ctts| {
+            ctts.entries
+                .iter()
+                .map(|e| (e.sample_count, e.sample_offset))
+                .collect()
+        })
+        .unwrap_or_default();
     let num_samples = mp4_reader.sample_count(track_id)?;
+    let offsets = composition_offsets(&ctts_entries, num_samples as usize);
 
     // mp4 uses 1 based indexing
-    for sample_id in 1..=num_samples {
+    for (sample_id, offset) in (1..=num_samples).zip(offsets.into_iter()) {
         let (start_time, _duration) = mp4_reader.sample_time_duration(track_id, sample_id)?;
+        let start_time = u64::try_from(start_time as i64 + offset)
+            .map_err(|_| anyhow::anyhow!("negative presentation time of MP4 sample {sample_id}"))?;
         let this_pts = raw2dur(start_time, timescale);
         mp4_pts.push(this_pts);
         nal_locations.push(Mp4NalLocation {
@@ -97,7 +250,7 @@ pub(crate) fn from_reader_with_timestamp_source(
 
     let h264_source = H264Source::from_seekable_h264_source_with_timestamp_source(
         seekable_h264_source,
-        do_decode_h264,
+        image_data_mode,
         Some(mp4_pts),
         Some(data_from_mp4_track),
         timestamp_source,
@@ -106,23 +259,45 @@ pub(crate) fn from_reader_with_timestamp_source(
     Ok(h264_source)
 }
 
+fn open_path(path: &Path) -> Result<mp4::Mp4Reader<Box<dyn SeekRead + Send>>> {
+    let rdr = std::fs::File::open(path).with_context(|| format!("Opening {}", path.display()))?;
+    let size = rdr.metadata()?.len();
+    let buf_reader: Box<(dyn SeekRead + Send + 'static)> = Box::new(std::io::BufReader::new(rdr));
+    Ok(mp4::Mp4Reader::read_header(buf_reader, size)?)
+}
+
 pub fn from_path_with_timestamp_source<P: AsRef<Path>>(
     path: P,
-    do_decode_h264: bool,
+    image_data_mode: ImageDataMode,
     timestamp_source: crate::TimestampSource,
     srt_file_path: Option<std::path::PathBuf>,
 ) -> Result<H264Source<Mp4Source>> {
-    let rdr = std::fs::File::open(path.as_ref())
-        .with_context(|| format!("Opening {}", path.as_ref().display()))?;
-    let size = rdr.metadata()?.len();
-    let buf_reader: Box<(dyn SeekRead + Send + 'static)> = Box::new(std::io::BufReader::new(rdr));
-    let mp4_reader = mp4::Mp4Reader::read_header(buf_reader, size)?;
+    from_path_with_track(
+        path,
+        image_data_mode,
+        timestamp_source,
+        srt_file_path,
+        &Mp4TrackSelection::Single,
+    )
+}
+
+/// Open the MP4 file at `path`, reading the H264 track given by
+/// `track_selection`.
+pub fn from_path_with_track<P: AsRef<Path>>(
+    path: P,
+    image_data_mode: ImageDataMode,
+    timestamp_source: crate::TimestampSource,
+    srt_file_path: Option<std::path::PathBuf>,
+    track_selection: &Mp4TrackSelection,
+) -> Result<H264Source<Mp4Source>> {
+    let mp4_reader = open_path(path.as_ref())?;
 
     let result = from_reader_with_timestamp_source(
         mp4_reader,
-        do_decode_h264,
+        image_data_mode,
         timestamp_source,
         srt_file_path,
+        track_selection,
     )
     .with_context(|| format!("Reading MP4 file {}", path.as_ref().display()))?;
     Ok(result)
@@ -163,6 +338,28 @@ fn avcc_to_nalu_ebsp(mp4_sample_buffer: &[u8]) -> Result<Vec<&[u8]>> {
     Ok(result)
 }
 
+/// Expand the run-length encoded composition time offsets of an MP4 track
+/// (the `ctts` box) to one offset per sample.
+///
+/// The offsets are shifted so that the smallest is zero. Encoders typically
+/// delay all frames by the maximum reordering depth, which is compensated
+/// with an edit list. This shift has the same effect, such that the first
+/// presented frame has the same time as the first decoded frame.
+fn composition_offsets(entries: &[(u32, i32)], num_samples: usize) -> Vec<i64> {
+    let mut offsets: Vec<i64> = entries
+        .iter()
+        .flat_map(|(count, offset)| std::iter::repeat(*offset as i64).take(*count as usize))
+        .chain(std::iter::repeat(0))
+        .take(num_samples)
+        .collect();
+    if let Some(min) = offsets.iter().min().copied() {
+        for offset in offsets.iter_mut() {
+            *offset -= min;
+        }
+    }
+    offsets
+}
+
 fn raw2dur(raw: u64, timescale: u32) -> std::time::Duration {
     std::time::Duration::from_secs_f64(raw as f64 / timescale as f64)
 }
@@ -184,3 +381,53 @@ fn test_raw_duration() {
     roundtrip(1_000_000_000);
     roundtrip(1_000_000_000_000);
 }
+
+#[test]
+fn test_composition_offsets() {
+    // No `ctts` box.
+    assert_eq!(composition_offsets(&[], 3), vec![0, 0, 0]);
+    // I P B B in decode order with a duration of 10 per frame.
+    assert_eq!(
+        composition_offsets(&[(1, 10), (1, 30), (2, 0)], 4),
+        vec![10, 30, 0, 0]
+    );
+    // The same, delayed by one frame.
+    assert_eq!(
+        composition_offsets(&[(1, 20), (1, 40), (2, 10)], 4),
+        vec![10, 30, 0, 0]
+    );
+}
+
+#[test]
+fn test_select_track() {
+    let track = |track_id, camera_name: Option<&str>| Mp4TrackInfo {
+        track_id,
+        width: 640,
+        height: 480,
+        sample_count: 10,
+        camera_name: camera_name.map(Into::into),
+    };
+    let tracks = vec![track(1, Some("cam1")), track(3, None)];
+
+    assert!(select_track(&tracks, &Mp4TrackSelection::Single).is_err());
+    assert_eq!(
+        select_track(&tracks[1..], &Mp4TrackSelection::Single).unwrap(),
+        3
+    );
+    assert_eq!(
+        select_track(&tracks, &Mp4TrackSelection::Index(1)).unwrap(),
+        3
+    );
+    assert!(select_track(&tracks, &Mp4TrackSelection::Index(2)).is_err());
+    assert_eq!(
+        select_track(&tracks, &Mp4TrackSelection::TrackId(1)).unwrap(),
+        1
+    );
+    assert!(select_track(&tracks, &Mp4TrackSelection::TrackId(2)).is_err());
+    assert_eq!(
+        select_track(&tracks, &Mp4TrackSelection::CameraName("cam1".into())).unwrap(),
+        1
+    );
+    assert!(select_track(&tracks, &Mp4TrackSelection::CameraName("cam2".into())).is_err());
+    assert!(select_track(&[], &Mp4TrackSelection::Single).is_err());
+}
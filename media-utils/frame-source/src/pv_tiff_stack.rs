@@ -106,6 +106,21 @@ impl FrameDataSource for PvTiffStack {
     fn has_timestamps(&self) -> bool {
         true
     }
+    fn frame_count(&self) -> Option<Estimate<usize>> {
+        Some(Estimate::exact(self.paths.len()))
+    }
+    fn keyframe_indices(&self) -> Option<Vec<usize>> {
+        Some((0..self.paths.len()).collect())
+    }
+    fn duration(&self) -> Option<Estimate<std::time::Duration>> {
+        // Only the metadata of the last image needs to be read.
+        let buf = read_file(self.paths.last()?).ok()?;
+        let metadata = extract_tiff_metadata(&buf).ok()?;
+        let duration = metadata
+            .timestamp
+            .checked_sub(self.frame0_timestamp_offset)?;
+        Some(Estimate::exact(duration))
+    }
 }
 
 impl PvTiffStack {
@@ -315,11 +330,24 @@ fn path_to_tiff(
     let mut metadata = extract_tiff_metadata(&buf)?;
     metadata.timestamp -= frame0_timestamp_offset;
     let timestamp = Timestamp::Duration(metadata.timestamp);
+    let camera_settings = camera_settings(&metadata);
     Ok(FrameData {
         image: ImageData::Tiff(read_tiff_image(&buf, metadata)?),
         timestamp,
         buf_len,
         idx: assign_idx,
+        camera_settings,
+        is_keyframe: true,
+    })
+}
+
+/// Get the exposure time from the PVCAM frame metadata, if present.
+fn camera_settings(metadata: &TiffMetadata) -> Option<FrameCameraSettings> {
+    let exposure_str = metadata.json["UserData"]["PVCAM-FMD-ExposureTimePs"]["scalar"].as_str()?;
+    let exposure = parse_picosecs(exposure_str).ok()?;
+    Some(FrameCameraSettings {
+        exposure_time_usec: Some(exposure.as_secs_f64() * 1e6),
+        gain_db: None,
     })
 }
 
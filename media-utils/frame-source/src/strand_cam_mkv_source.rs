@@ -5,11 +5,11 @@ use std::{
 };
 
 use eyre::{self as anyhow, WrapErr};
-use openh264::formats::YUVSource;
 
 use mkv_strand_reader::ParsedStrandCamMkv;
 
 use super::*;
+use crate::decoded_image::DecodedImage;
 
 // NAL unit start for b"MISPmicrosectime":
 const PRECISION_TIME_NALU_START: &[u8] = &[
@@ -27,6 +27,8 @@ pub struct StrandCamMkvSource<R: Read + Seek> {
     src_format: Format,
     is_uncompressed: bool,
     h264_decoder_state: Option<openh264::decoder::Decoder>,
+    image_data_mode: ImageDataMode,
+    decode_to_nv12: bool,
     keyframes_cache: Option<Vec<usize>>,
 }
 
@@ -112,6 +114,9 @@ impl<R: Read + Seek> FrameDataSource for StrandCamMkvSource<R> {
     fn estimate_luminance_range(&mut self) -> Result<(u16, u16)> {
         anyhow::bail!("mkv luminance scanning not implemented");
     }
+    fn set_decode_to_nv12(&mut self, value: bool) {
+        self.decode_to_nv12 = value;
+    }
     fn iter<'a>(&'a mut self) -> Box<dyn Iterator<Item = Result<FrameData>> + 'a> {
         Box::new(StrandCamMkvSourceIter {
             parent: self,
@@ -124,6 +129,19 @@ impl<R: Read + Seek> FrameDataSource for StrandCamMkvSource<R> {
     fn has_timestamps(&self) -> bool {
         true
     }
+    fn frame_count(&self) -> Option<Estimate<usize>> {
+        Some(Estimate::exact(self.parsed.block_data.len()))
+    }
+    fn keyframe_indices(&self) -> Option<Vec<usize>> {
+        let indices = (0..self.parsed.block_data.len())
+            .filter(|&idx| self.is_keyframe(idx))
+            .collect();
+        Some(indices)
+    }
+    fn duration(&self) -> Option<Estimate<std::time::Duration>> {
+        let last = self.parsed.block_data.iter().map(|bd| bd.pts).max()?;
+        Some(Estimate::exact(last))
+    }
 }
 
 struct StrandCamMkvSourceIter<'a, R: Read + Seek> {
@@ -141,7 +159,7 @@ impl<R: Read + Seek> StrandCamMkvSource<R> {
     fn new<P>(
         rdr: R,
         path: Option<P>,
-        do_decode_h264: bool,
+        image_data_mode: ImageDataMode,
         timestamp_source: crate::TimestampSource,
     ) -> Result<Self>
     where
@@ -165,7 +183,7 @@ impl<R: Read + Seek> StrandCamMkvSource<R> {
             anyhow::bail!("unsupported codec {}", parsed.codec);
         };
 
-        let h264_decoder_state = if do_decode_h264 {
+        let h264_decoder_state = if image_data_mode.decodes() {
             Some(openh264::decoder::Decoder::new()?)
         } else {
             None
@@ -184,6 +202,8 @@ impl<R: Read + Seek> StrandCamMkvSource<R> {
             src_format,
             is_uncompressed,
             h264_decoder_state,
+            image_data_mode,
+            decode_to_nv12: false,
             keyframes_cache: None,
         })
     }
@@ -192,6 +212,14 @@ impl<R: Read + Seek> StrandCamMkvSource<R> {
         self.is_uncompressed
     }
 
+    /// Whether frame `idx` is a keyframe, as flagged in the MKV file for H264.
+    fn is_keyframe(&self, idx: usize) -> bool {
+        match self.src_format {
+            Format::UncompressedMono => true,
+            Format::H264 => self.parsed.block_data[idx].is_keyframe,
+        }
+    }
+
     /// Return all indices of keyframes (I frames)
     fn read_keyframes(&mut self) {
         let mut keyframes_cache = vec![];
@@ -256,25 +284,22 @@ impl<R: Read + Seek> StrandCamMkvSource<R> {
                 let has_precision_timestamp = image_data.starts_with(PRECISION_TIME_NALU_START);
                 if let Some(decoder) = self.h264_decoder_state.as_mut() {
                     let dynamic_frame = if let Some(decoded_yuv) = decoder.decode(&image_data)? {
-                        let dim = decoded_yuv.dimensions();
-
-                        let stride = dim.0 * 3;
-                        let mut image_data = vec![0u8; stride * dim.1];
-                        decoded_yuv.write_rgb8(&mut image_data);
-                        basic_frame::DynamicFrame::RGB8(basic_frame::BasicFrame::<
-                            machine_vision_formats::pixel_format::RGB8,
-                        > {
-                            width,
-                            height,
-                            stride: u32::try_from(stride).unwrap(),
-                            image_data,
-                            pixel_format: std::marker::PhantomData,
-                            extra,
-                        })
+                        DecodedImage::from_yuv(&decoded_yuv, self.decode_to_nv12)
+                            .into_dynamic_frame(extra)
                     } else {
                         anyhow::bail!("could not decode single frame with openh264");
                     };
-                    super::ImageData::Decoded(dynamic_frame)
+                    if self.image_data_mode == ImageDataMode::Both {
+                        super::ImageData::Both(
+                            dynamic_frame,
+                            super::EncodedH264 {
+                                data: H264EncodingVariant::AnnexB(image_data),
+                                has_precision_timestamp,
+                            },
+                        )
+                    } else {
+                        super::ImageData::Decoded(dynamic_frame)
+                    }
                 } else {
                     super::ImageData::EncodedH264(super::EncodedH264 {
                         data: H264EncodingVariant::AnnexB(image_data),
@@ -289,6 +314,8 @@ impl<R: Read + Seek> StrandCamMkvSource<R> {
             image,
             buf_len: bd.size,
             idx,
+            camera_settings: None,
+            is_keyframe: self.is_keyframe(idx),
         })
     }
 }
@@ -308,7 +335,7 @@ impl<'a, R: Read + Seek> Iterator for StrandCamMkvSourceIter<'a, R> {
 
 pub fn from_path_with_timestamp_source<P: AsRef<Path>>(
     path: P,
-    do_decode_h264: bool,
+    image_data_mode: ImageDataMode,
     timestamp_source: crate::TimestampSource,
 ) -> Result<StrandCamMkvSource<BufReader<std::fs::File>>> {
     let rdr = std::fs::File::open(path.as_ref())
@@ -317,7 +344,7 @@ pub fn from_path_with_timestamp_source<P: AsRef<Path>>(
     StrandCamMkvSource::new(
         buf_reader,
         Some(path.as_ref().to_path_buf()),
-        do_decode_h264,
+        image_data_mode,
         timestamp_source,
     )
     .with_context(|| format!("Reading MKV file {}", path.as_ref().display()))
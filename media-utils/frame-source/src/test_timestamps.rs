@@ -1,7 +1,7 @@
 use chrono::{DateTime, Duration, Utc};
 use machine_vision_formats::pixel_format::RGB8;
 
-use crate::{h264_source::SeekRead, FrameDataSource};
+use crate::{h264_source::SeekRead, Estimate, FrameDataSource, ImageDataMode};
 use ci2_remote_control::Mp4RecordingConfig;
 
 #[test]
@@ -14,6 +14,8 @@ fn test_h264_precision_timestamps() -> eyre::Result<()> {
         codec: ci2_remote_control::Mp4Codec::H264LessAvc,
         max_framerate: Default::default(),
         h264_metadata: None,
+        container_metadata: None,
+        fragment_duration_secs: None,
     };
 
     const W: u32 = 32;
@@ -51,17 +53,20 @@ fn test_h264_precision_timestamps() -> eyre::Result<()> {
     let buf_reader: Box<(dyn SeekRead + Send)> = Box::new(std::io::BufReader::new(rdr));
     let mp4_reader = mp4::Mp4Reader::read_header(buf_reader, size)?;
 
-    let do_decode_h264 = false; // no need to decode h264 to get timestamps.
+    // no need to decode h264 to get timestamps.
     let mut src = crate::mp4_source::from_reader_with_timestamp_source(
         mp4_reader,
-        do_decode_h264,
+        ImageDataMode::Encoded,
         crate::TimestampSource::BestGuess,
         None,
+        &crate::mp4_source::Mp4TrackSelection::Single,
     )?;
 
     assert_eq!(src.width(), W);
     assert_eq!(src.height(), H);
     assert_eq!(src.frame0_time().unwrap(), start);
+    assert_eq!(src.frame_count(), Some(Estimate::exact(ptss.len())));
+    assert_eq!(src.duration(), Some(Estimate::exact(*ptss.last().unwrap())));
 
     for (frame, expected_pts) in src.iter().zip(ptss.iter()) {
         let frame = frame?;
@@ -0,0 +1,153 @@
+use crate::{FrameData, FrameDataSource, ImageData, Timestamp};
+use eyre::{self as anyhow, Result, WrapErr};
+use std::{fs::File, io::BufReader, path::Path};
+use timestamped_frame::ExtraTimeData;
+use ufmf::UFMFReader;
+
+struct UfmfSourceIter {
+    rdr: UFMFReader<BufReader<File>>,
+    frame0_time_utc: chrono::DateTime<chrono::Utc>,
+    idx: usize,
+}
+impl UfmfSourceIter {
+    fn new(parent: &UfmfSource) -> Result<Self> {
+        let mut rdr = parent.open()?;
+        for _ in 0..parent.skip_frames {
+            rdr.next();
+        }
+        Ok(Self {
+            rdr,
+            frame0_time_utc: parent.frame0_time_utc,
+            idx: 0,
+        })
+    }
+}
+impl Iterator for UfmfSourceIter {
+    type Item = Result<FrameData>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rdr.next().map(|ufmf_result| match ufmf_result {
+            Ok(frame) => {
+                let buf_len = frame.image_data_without_format().len();
+                let frame_time_utc = frame.extra().host_timestamp();
+                let timestamp = frame_time_utc - self.frame0_time_utc;
+                let timestamp = Timestamp::Duration(timestamp.to_std()?);
+                let idx = self.idx;
+                self.idx += 1;
+                Ok(FrameData {
+                    image: ImageData::Decoded(frame),
+                    timestamp,
+                    buf_len,
+                    idx,
+                    camera_settings: None,
+                    // Frames are reconstructed from the earlier background.
+                    is_keyframe: false,
+                })
+            }
+            Err(e) => Err(anyhow::Error::from(e)),
+        })
+    }
+}
+
+/// A UFMF file, returning full frames reconstructed from the saved background
+/// and foreground regions (see [UFMFReader]).
+///
+/// As with [crate::fmf_source::FmfSource], the file is reopened for each
+/// iteration.
+pub struct UfmfSource {
+    filename: std::path::PathBuf,
+    width: u32,
+    height: u32,
+    frame0_time_utc: chrono::DateTime<chrono::Utc>,
+    frame0_time: chrono::DateTime<chrono::FixedOffset>,
+    skip_frames: usize,
+}
+
+impl FrameDataSource for UfmfSource {
+    fn width(&self) -> u32 {
+        self.width
+    }
+    fn height(&self) -> u32 {
+        self.height
+    }
+    fn frame0_time(&self) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+        Some(self.frame0_time)
+    }
+    fn skip_n_frames(&mut self, n_frames: usize) -> Result<()> {
+        if n_frames == 0 {
+            return Ok(());
+        }
+        // The first frame after the skipped frames.
+        let frame = self
+            .open()?
+            .nth(n_frames)
+            .map(|f| f.map_err(anyhow::Error::from))
+            .unwrap_or_else(|| {
+                anyhow::bail!(
+                    "ufmf file without {n_frames} of data '{}'",
+                    self.filename.display()
+                )
+            })?;
+
+        let frame_time_utc = frame.extra().host_timestamp();
+        let duration = frame_time_utc - self.frame0_time_utc;
+        let frame_time = self.frame0_time + duration;
+
+        self.skip_frames = n_frames;
+        self.frame0_time = frame_time;
+        self.frame0_time_utc = frame_time_utc;
+        Ok(())
+    }
+    fn estimate_luminance_range(&mut self) -> Result<(u16, u16)> {
+        anyhow::bail!("estimating luminance range not supported for UFMF source.");
+    }
+    fn iter(&mut self) -> Box<dyn Iterator<Item = Result<FrameData>>> {
+        Box::new(UfmfSourceIter::new(self).unwrap())
+    }
+    fn timestamp_source(&self) -> &str {
+        "UFMF frame metadata"
+    }
+    fn has_timestamps(&self) -> bool {
+        true
+    }
+}
+
+impl UfmfSource {
+    fn new<P: AsRef<std::path::Path>>(filename: P) -> Result<Self> {
+        let filename = filename.as_ref().to_path_buf();
+        let mut rdr = UFMFReader::from_path(&filename).with_context(|| {
+            anyhow::anyhow!("Error from UFMFReader opening '{}'", filename.display())
+        })?;
+        let width = rdr.width();
+        let height = rdr.height();
+        let frame0 = rdr
+            .next()
+            .map(|f| f.map_err(anyhow::Error::from))
+            .unwrap_or_else(|| anyhow::bail!("ufmf file with no data '{}'", filename.display()))?;
+
+        let frame0_time_utc = frame0.extra().host_timestamp();
+        let frame0_time = mkv_strand_reader::infer_timezone(&frame0_time_utc, filename.to_str())?;
+
+        Ok(Self {
+            filename,
+            width,
+            height,
+            frame0_time_utc,
+            frame0_time,
+            skip_frames: 0,
+        })
+    }
+
+    fn open(&self) -> Result<UFMFReader<BufReader<File>>> {
+        UFMFReader::from_path(&self.filename).with_context(|| {
+            anyhow::anyhow!(
+                "Error from UFMFReader opening '{}'",
+                self.filename.display()
+            )
+        })
+    }
+}
+
+pub fn from_path<P: AsRef<Path>>(path: P) -> Result<UfmfSource> {
+    let filename = path.as_ref();
+    UfmfSource::new(filename).with_context(|| format!("Reading UFMF file {}", filename.display()))
+}
@@ -92,6 +92,8 @@ fn main() -> eyre::Result<()> {
                     codec,
                     max_framerate: Default::default(),
                     h264_metadata: None,
+                    container_metadata: None,
+                    fragment_duration_secs: None,
                 };
 
                 let mut my_mp4_writer = mp4_writer::Mp4Writer::new(out_fd, cfg, libs_and_nv_enc)?;
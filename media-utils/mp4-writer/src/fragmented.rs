@@ -0,0 +1,241 @@
+//! Fragmented MP4 output.
+//!
+//! In a fragmented MP4 file, the `moov` box at the start of the file describes
+//! the track but contains no samples. The samples follow in fragments, each a
+//! `moof` box with the timing and sizes of its samples followed by an `mdat`
+//! box with their data. A fragment is complete once written, so a file whose
+//! writing was interrupted, e.g. by a crash, can be played up to its last
+//! complete fragment.
+//!
+//! The `moov` box is created with the mp4 crate by writing a file without
+//! samples, to which the `mvex` box announcing the fragments is appended.
+
+use std::io::{Cursor, Seek, SeekFrom, Write};
+
+use ci2_remote_control::Mp4ContainerMetadata;
+
+use crate::{
+    udta::{self, mp4_box},
+    Error, Result, TRACK_ID,
+};
+
+/// `tfhd` flag: data offsets are relative to the start of the `moof` box.
+const TFHD_DEFAULT_BASE_IS_MOOF: u32 = 0x02_0000;
+
+/// `trun` flags: the data offset and the duration, size, flags and
+/// composition time offset of each sample are present.
+const TRUN_FLAGS: u32 = 0x00_0001 | 0x00_0100 | 0x00_0200 | 0x00_0400 | 0x00_0800;
+
+/// Sample flags of a sync sample: does not depend on other samples.
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+
+/// Sample flags of other samples: depends on other samples, is not a sync
+/// sample.
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+pub(crate) struct FragmentedWriter<T> {
+    fd: T,
+    /// Minimum duration of a fragment, in units of `movie_timescale`.
+    fragment_duration: u64,
+    /// The samples of the current fragment.
+    samples: Vec<mp4::Mp4Sample>,
+    /// Decode time of the first sample of the current fragment.
+    fragment_start: u64,
+    /// Decode time of the next sample.
+    decode_time: u64,
+    /// Sequence number of the next fragment, starting at 1.
+    sequence_number: u32,
+    /// Position of the duration in the `mehd` box, updated when finishing.
+    mehd_duration_pos: u64,
+}
+
+impl<T> FragmentedWriter<T>
+where
+    T: Write + Seek,
+{
+    /// Write the `ftyp` and `moov` boxes for a file with the track
+    /// `track_conf`.
+    pub(crate) fn new(
+        mut fd: T,
+        mp4_config: &mp4::Mp4Config,
+        track_conf: &mp4::TrackConfig,
+        fragment_duration: u64,
+        container_metadata: Option<&Mp4ContainerMetadata>,
+    ) -> Result<Self> {
+        let mut empty = mp4::Mp4Writer::write_start(Cursor::new(Vec::new()), mp4_config)?;
+        empty.add_track(track_conf)?;
+        empty.write_end()?;
+        let empty = empty.into_writer().into_inner();
+
+        let ftyp = find_box(&empty, b"ftyp")?;
+        let moov = find_box(&empty, b"moov")?;
+        fd.write_all(ftyp)?;
+        let moov_start = fd.stream_position()?;
+        fd.write_all(moov)?;
+
+        // The fragment duration in the `mehd` box is the duration of the
+        // whole file. It is zero until finished.
+        let mehd_duration_pos = fd.stream_position()? + 8 + 8 + 4;
+        let mut mvex = versioned_box(b"mehd", 1, 0, &0u64.to_be_bytes());
+        let mut trex = TRACK_ID.to_be_bytes().to_vec();
+        // default sample description index, duration, size and flags
+        trex.extend(1u32.to_be_bytes());
+        trex.extend([0u8; 12]);
+        mvex.extend(versioned_box(b"trex", 0, 0, &trex));
+        udta::append_box_to_moov(&mut fd, moov_start, &mp4_box(b"mvex", &mvex))?;
+        if let Some(metadata) = container_metadata {
+            udta::append_to_moov(&mut fd, moov_start, metadata)?;
+        }
+
+        Ok(Self {
+            fd,
+            fragment_duration,
+            samples: Vec::new(),
+            fragment_start: 0,
+            decode_time: 0,
+            sequence_number: 1,
+            mehd_duration_pos,
+        })
+    }
+
+    /// Add `sample`, given in decode order, to the current fragment.
+    ///
+    /// The current fragment is written first if it is long enough and
+    /// `sample` is a sync sample.
+    pub(crate) fn write_sample(&mut self, sample: &mp4::Mp4Sample) -> Result<()> {
+        if sample.is_sync
+            && !self.samples.is_empty()
+            && self.decode_time - self.fragment_start >= self.fragment_duration
+        {
+            self.write_fragment()?;
+        }
+        self.samples.push(sample.clone());
+        self.decode_time += u64::from(sample.duration);
+        Ok(())
+    }
+
+    /// Write the last fragment and the total duration.
+    pub(crate) fn finish(mut self) -> Result<()> {
+        self.write_fragment()?;
+        let end = self.fd.stream_position()?;
+        self.fd.seek(SeekFrom::Start(self.mehd_duration_pos))?;
+        self.fd.write_all(&self.decode_time.to_be_bytes())?;
+        self.fd.seek(SeekFrom::Start(end))?;
+        self.fd.flush()?;
+        Ok(())
+    }
+
+    /// Write the samples of the current fragment as `moof` and `mdat` boxes.
+    fn write_fragment(&mut self) -> Result<()> {
+        if self.samples.is_empty() {
+            return Ok(());
+        }
+        let data_size: u64 = self.samples.iter().map(|s| s.bytes.len() as u64).sum();
+        // A 64-bit size is needed if the data exceeds 4 GiB.
+        let mdat_header_size = if data_size + 8 > u64::from(u32::MAX) {
+            16
+        } else {
+            8
+        };
+
+        let mut trun = u32::try_from(self.samples.len())
+            .unwrap()
+            .to_be_bytes()
+            .to_vec();
+        // The data offset, set below once the size of the `moof` box is known.
+        let data_offset_pos = trun.len();
+        trun.extend([0u8; 4]);
+        for sample in self.samples.iter() {
+            let flags = if sample.is_sync {
+                SYNC_SAMPLE_FLAGS
+            } else {
+                NON_SYNC_SAMPLE_FLAGS
+            };
+            trun.extend(sample.duration.to_be_bytes());
+            trun.extend(u32::try_from(sample.bytes.len()).unwrap().to_be_bytes());
+            trun.extend(flags.to_be_bytes());
+            trun.extend(sample.rendering_offset.to_be_bytes());
+        }
+
+        let mut traf = versioned_box(
+            b"tfhd",
+            0,
+            TFHD_DEFAULT_BASE_IS_MOOF,
+            &TRACK_ID.to_be_bytes(),
+        );
+        traf.extend(versioned_box(
+            b"tfdt",
+            1,
+            0,
+            &self.fragment_start.to_be_bytes(),
+        ));
+        // The `trun` box is last, so the data offset is found from the end.
+        let trun = versioned_box(b"trun", 1, TRUN_FLAGS, &trun);
+        let data_offset_from_end = trun.len() - 12 - data_offset_pos;
+        traf.extend(trun);
+        let mut moof = versioned_box(b"mfhd", 0, 0, &self.sequence_number.to_be_bytes());
+        moof.extend(mp4_box(b"traf", &traf));
+        let mut moof = mp4_box(b"moof", &moof);
+
+        let data_offset =
+            i32::try_from(moof.len() + mdat_header_size).map_err(|_| Error::InconsistentState {
+                #[cfg(feature = "backtrace")]
+                backtrace: std::backtrace::Backtrace::capture(),
+            })?;
+        let pos = moof.len() - data_offset_from_end;
+        moof[pos..pos + 4].copy_from_slice(&data_offset.to_be_bytes());
+        self.fd.write_all(&moof)?;
+
+        if mdat_header_size == 16 {
+            self.fd.write_all(&1u32.to_be_bytes())?;
+            self.fd.write_all(b"mdat")?;
+            self.fd.write_all(&(data_size + 16).to_be_bytes())?;
+        } else {
+            self.fd
+                .write_all(&u32::try_from(data_size + 8).unwrap().to_be_bytes())?;
+            self.fd.write_all(b"mdat")?;
+        }
+        for sample in self.samples.drain(..) {
+            self.fd.write_all(&sample.bytes)?;
+        }
+        self.fd.flush()?;
+
+        self.fragment_start = self.decode_time;
+        self.sequence_number += 1;
+        Ok(())
+    }
+}
+
+/// A box with the given version and flags.
+fn versioned_box(box_type: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut contents = (u32::from(version) << 24 | flags).to_be_bytes().to_vec();
+    contents.extend(payload);
+    mp4_box(box_type, &contents)
+}
+
+/// Return the top-level box of type `box_type` in `buf`, including its header.
+fn find_box<'a>(buf: &'a [u8], box_type: &[u8; 4]) -> Result<&'a [u8]> {
+    let mut rest = buf;
+    while rest.len() >= 8 {
+        let size = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let size = match size {
+            // The box extends to the end of the data.
+            0 => rest.len(),
+            1 if rest.len() >= 16 => {
+                usize::try_from(u64::from_be_bytes(rest[8..16].try_into().unwrap())).unwrap()
+            }
+            size => size,
+        };
+        if size < 8 || size > rest.len() {
+            break;
+        }
+        if &rest[4..8] == box_type {
+            return Ok(&rest[..size]);
+        }
+        rest = &rest[size..];
+    }
+    Err(Error::InconsistentState {
+        #[cfg(feature = "backtrace")]
+        backtrace: std::backtrace::Backtrace::capture(),
+    })
+}
@@ -15,12 +15,19 @@
 
 #![cfg_attr(feature = "backtrace", feature(error_generic_member_access))]
 
-use std::rc::Rc;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+    rc::Rc,
+};
 
 #[macro_use]
 extern crate log;
 
-use ci2_remote_control::{H264Metadata, Mp4RecordingConfig, H264_METADATA_UUID};
+use ci2_remote_control::{
+    H264Metadata, Mp4ContainerMetadata, Mp4RecordingConfig, NvencPreset, NvencRateControl,
+    NvidiaH264Options, H264_METADATA_UUID,
+};
 use convert_image::convert_into;
 
 use basic_frame::{match_all_dynamic_fmts, DynamicFrame};
@@ -36,11 +43,40 @@ use thiserror::Error;
 mod h264_annexb_split;
 use h264_annexb_split::h264_annexb_split;
 
+mod fragmented;
+use fragmented::FragmentedWriter;
+
+mod udta;
+
 // The number of time units that pass in one second.
 // const MOVIE_TIMESCALE: u32 = 1_000_000;
 const MOVIE_TIMESCALE: u32 = 90_000;
 const TRACK_ID: u32 = 1;
 
+/// The maximum number of samples in an MP4 file.
+///
+/// The sample count in the `stsz` box is a 32-bit number.
+const MAX_NUM_SAMPLES: u64 = u32::MAX as u64;
+
+/// The offset of the first sample from the start of the file.
+///
+/// This is the size of the `ftyp` box written by [start_mp4_writer] and of the
+/// `mdat` header, for which the mp4 crate reserves 16 bytes to allow a 64-bit
+/// size. The `moov` box follows the sample data.
+const MDAT_DATA_OFFSET: u64 = 20 + 16;
+
+/// The smallest interval between frames which gives distinct sample times,
+/// slightly more than one unit of `movie_timescale`.
+const MIN_FRAME_INTERVAL_NANOS: i64 = 1_000_000_000 / MOVIE_TIMESCALE as i64 + 1;
+
+/// The number of samples held back before writing.
+///
+/// With B-frames, the decode order of samples differs from their presentation
+/// order. The decode time and duration of a sample are only known once the
+/// samples following it in decode order are available. 16 is the maximum
+/// number of reordered frames allowed by H.264.
+const MAX_REORDER_DEPTH: usize = 16;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("{source}")]
@@ -85,6 +121,11 @@ pub enum Error {
         #[cfg(feature = "backtrace")]
         backtrace: std::backtrace::Backtrace,
     },
+    #[error("too many frames for a single MP4 file (maximum {MAX_NUM_SAMPLES})")]
+    TooManySamples {
+        #[cfg(feature = "backtrace")]
+        backtrace: std::backtrace::Backtrace,
+    },
     #[error("convert image error")]
     ConvertImageError(
         #[from]
@@ -106,6 +147,8 @@ pub enum Error {
     ),
     #[error("nvenc libraries not loaded")]
     NvencLibsNotLoaded,
+    #[error("nvenc options not supported: {0}")]
+    UnsupportedNvencOptions(String),
     #[error("less-avc error {}", inner)]
     LessAvcWrapperError {
         #[from]
@@ -262,7 +305,7 @@ where
 
         let mut state = match inner {
             Some(WriteState::Configured(mut mybox)) => {
-                let (fd, _cfg, ref mut h264_parser) = *mybox;
+                let (fd, cfg, ref mut h264_parser) = *mybox;
                 if insert_precision_timestamp {
                     h264_parser.push_nals(sample, Some(timestamp));
                 } else {
@@ -270,6 +313,7 @@ where
                 }
                 let mp4_writer = start_mp4_writer(
                     fd,
+                    output_config(&cfg, timestamp),
                     h264_parser
                         .sps
                         .as_ref()
@@ -302,11 +346,8 @@ where
                     &mut MyEncoder::CopyRawH264 {
                         ref mut h264_parser,
                     } => {
-                        if insert_precision_timestamp {
-                            h264_parser.push_nals(sample, Some(timestamp));
-                        } else {
-                            h264_parser.push_nals(sample, None);
-                        }
+                        let precision_timestamp = insert_precision_timestamp.then_some(timestamp);
+                        h264_parser.push_nals(sample, precision_timestamp);
                     }
                     _ => {
                         panic!();
@@ -314,6 +355,10 @@ where
                 }
                 state
             }
+            Some(WriteState::Failed(state)) => {
+                self.inner = Some(WriteState::Failed(state));
+                return inconsistent_state_err();
+            }
             None | Some(WriteState::Finished) => {
                 return inconsistent_state_err();
             }
@@ -323,26 +368,23 @@ where
             return inconsistent_state_err();
         }
 
-        let sample = match &mut state.my_encoder {
+        let h264_parser = match &mut state.my_encoder {
             &mut MyEncoder::CopyRawH264 {
                 ref mut h264_parser,
-            } => h264_parser.avcc_sample().unwrap(),
+            } => h264_parser,
             _ => {
                 panic!();
             }
         };
 
-        match &mut state.mp4_segment {
-            MaybeMp4Writer::Mp4Writer(mp4_writer) => {
-                mp4_writer.write_sample(TRACK_ID, &sample)?;
-            }
+        let result = match &mut state.mp4_segment {
+            MaybeMp4Writer::Mp4Writer(mp4_writer) => h264_parser.write_avcc_sample(mp4_writer),
             _ => {
                 return inconsistent_state_err();
             }
-        }
-        self.inner = Some(WriteState::Recording(state));
-
-        Ok(())
+        };
+        self.inner = Some(keep_state(state, &result));
+        result
     }
 
     pub fn write_dynamic<TS>(&mut self, frame: &DynamicFrame, timestamp: TS) -> Result<()>
@@ -367,6 +409,7 @@ where
         match inner {
             Some(WriteState::Configured(mybox)) => {
                 let (fd, cfg, h264_parser) = *mybox;
+                let output = output_config(&cfg, timestamp);
                 let frame = trim_image(frame, frame.width(), frame.height());
 
                 let width = frame.width();
@@ -411,14 +454,39 @@ where
 
                                 let encode = nvenc::NV_ENC_CODEC_H264_GUID;
                                 // let encode = nvenc::NV_ENC_CODEC_HEVC_GUID;
-                                let preset = nvenc::NV_ENC_PRESET_HP_GUID;
-                                // let preset = nvenc::NV_ENC_PRESET_DEFAULT_GUID;
+                                let preset = match opts.preset {
+                                    NvencPreset::HighPerformance => nvenc::NV_ENC_PRESET_HP_GUID,
+                                    NvencPreset::P1 => nvenc::NV_ENC_PRESET_P1_GUID,
+                                    NvencPreset::P2 => nvenc::NV_ENC_PRESET_P2_GUID,
+                                    NvencPreset::P3 => nvenc::NV_ENC_PRESET_P3_GUID,
+                                    NvencPreset::P4 => nvenc::NV_ENC_PRESET_P4_GUID,
+                                    NvencPreset::P5 => nvenc::NV_ENC_PRESET_P5_GUID,
+                                    NvencPreset::P6 => nvenc::NV_ENC_PRESET_P6_GUID,
+                                    NvencPreset::P7 => nvenc::NV_ENC_PRESET_P7_GUID,
+                                };
+                                let tuning = convert_nvenc_tuning(opts);
                                 let format = nvenc::BufferFormat::NV12;
 
+                                let rate_control_mode = match opts.rate_control {
+                                    NvencRateControl::Vbr => RateControlMode::Vbr,
+                                    NvencRateControl::Cbr => RateControlMode::Cbr,
+                                    NvencRateControl::ConstQp(_) => RateControlMode::Constqp,
+                                };
+                                check_nvenc_options(
+                                    opts,
+                                    encoder.max_b_frames(encode)?,
+                                    encoder
+                                        .supports_rate_control_mode(encode, rate_control_mode)?,
+                                )?;
+
                                 let param_builder =
                                     nvenc::InitParamsBuilder::new(encode, width, height)
                                         // .ptd(true)
                                         .preset_guid(preset);
+                                let param_builder = match tuning {
+                                    Some(tuning) => param_builder.tuning_info(tuning),
+                                    None => param_builder,
+                                };
 
                                 let param_builder =
                                     match cfg.max_framerate.as_numerator_denominator() {
@@ -426,11 +494,30 @@ where
                                         None => param_builder,
                                     };
 
-                                let mut encoder_config =
-                                    encoder.get_encode_preset_config(encode, preset)?;
-                                encoder_config.set_rate_control_mode(RateControlMode::Vbr);
-                                encoder_config.set_average_bit_rate(opts.bitrate * 1000);
-                                encoder_config.set_max_bit_rate(opts.bitrate * 1000);
+                                let mut encoder_config = match tuning {
+                                    Some(tuning) => encoder
+                                        .get_encode_preset_config_ex(encode, preset, tuning)?,
+                                    None => encoder.get_encode_preset_config(encode, preset)?,
+                                };
+                                encoder_config.set_rate_control_mode(rate_control_mode);
+                                match opts.rate_control {
+                                    NvencRateControl::Vbr | NvencRateControl::Cbr => {
+                                        encoder_config.set_average_bit_rate(opts.bitrate * 1000);
+                                        encoder_config.set_max_bit_rate(opts.bitrate * 1000);
+                                    }
+                                    NvencRateControl::ConstQp(qp) => {
+                                        encoder_config.set_const_qp(qp);
+                                    }
+                                }
+                                if let Some(gop_length) = opts.gop_length {
+                                    encoder_config.set_gop_length(gop_length);
+                                    encoder_config.set_h264_idr_period(gop_length);
+                                }
+                                if let Some(num_b_frames) = opts.num_b_frames {
+                                    encoder_config.set_frame_interval_p(
+                                        (num_b_frames + 1).try_into().unwrap(),
+                                    );
+                                }
 
                                 let params =
                                     param_builder.set_encode_config(encoder_config).build()?;
@@ -474,6 +561,7 @@ where
                                     h264_parser: h264_parser.clone(),
                                     // annex_b_reader,
                                     vram_queue,
+                                    num_ready: 0,
                                     first_timestamp: timestamp,
                                 });
                             }
@@ -539,21 +627,26 @@ where
                 };
 
                 let mut state = RecordingState {
-                    mp4_segment: MaybeMp4Writer::Starting(fd),
+                    mp4_segment: MaybeMp4Writer::Starting(fd, output),
                     my_encoder,
                     inner: Some(inner),
                 };
 
-                write_frame(&mut state, &frame, timestamp)?;
-
-                self.inner = Some(WriteState::Recording(Box::new(state)));
-
-                Ok(())
+                let result = write_frame(&mut state, &frame, timestamp);
+                self.inner = Some(keep_state(Box::new(state), &result));
+                result
             }
             Some(WriteState::Recording(mut state)) => {
                 let frame = if let Some(state_inner) = &mut state.inner {
                     let interval = timestamp.signed_duration_since(state_inner.previous_timestamp);
-                    if interval >= state_inner.interval_for_limiting_fps {
+                    if interval < chrono::Duration::nanoseconds(MIN_FRAME_INTERVAL_NANOS) {
+                        // The MP4 sample times must increase.
+                        warn!(
+                            "Not saving frame at {}: not after previous frame at {}",
+                            timestamp, state_inner.previous_timestamp
+                        );
+                        None
+                    } else if interval >= state_inner.interval_for_limiting_fps {
                         let frame =
                             trim_image(frame, state_inner.trim_width, state_inner.trim_height);
                         debug!("Saving frame at {}: interval {}", timestamp, interval);
@@ -570,12 +663,16 @@ where
                 } else {
                     return inconsistent_state_err();
                 };
-                if let Some(frame) = frame {
-                    write_frame(&mut state, &frame, timestamp)?;
-                }
-                self.inner = Some(WriteState::Recording(state));
-
-                Ok(())
+                let result = match frame {
+                    Some(frame) => write_frame(&mut state, &frame, timestamp),
+                    None => Ok(()),
+                };
+                self.inner = Some(keep_state(state, &result));
+                result
+            }
+            Some(WriteState::Failed(state)) => {
+                self.inner = Some(WriteState::Failed(state));
+                inconsistent_state_err()
             }
             Some(WriteState::Finished) => {
                 self.inner = Some(WriteState::Finished);
@@ -597,6 +694,10 @@ where
     /// Calling this allows any errors to be caught explicitly. Otherwise,
     /// the MP4 file will be finished when the writer is dropped. In that case,
     /// any errors will result in a panic.
+    ///
+    /// After writing a frame failed, this saves the samples written before the
+    /// failure, without flushing the encoder. Such a writer is not finished
+    /// when dropped.
     pub fn finish(&mut self) -> Result<()> {
         let inner = self.inner.take();
         match inner {
@@ -640,14 +741,18 @@ where
                     }
                 }
 
-                if let MaybeMp4Writer::Mp4Writer(mut mp4_writer) = state.mp4_segment {
-                    mp4_writer.write_end()?;
-                }
+                finish_samples(state)?;
 
                 trace!("Finalized video.");
                 self.inner = Some(WriteState::Finished);
                 Ok(())
             }
+            Some(WriteState::Failed(state)) => {
+                self.inner = Some(WriteState::Finished);
+                finish_samples(state)?;
+                trace!("Finalized video after failure.");
+                Ok(())
+            }
             Some(WriteState::Finished) => {
                 self.inner = Some(WriteState::Finished);
                 Err(Error::FileAlreadyClosed {
@@ -663,6 +768,40 @@ where
     }
 }
 
+/// The state after writing a sample with `result`.
+fn keep_state<'lib, T>(
+    state: Box<RecordingState<'lib, T>>,
+    result: &Result<()>,
+) -> WriteState<'lib, T>
+where
+    T: std::io::Write + std::io::Seek,
+{
+    if result.is_ok() {
+        WriteState::Recording(state)
+    } else {
+        WriteState::Failed(state)
+    }
+}
+
+/// Write the pending samples and the index of the MP4 file, if started.
+fn finish_samples<T>(mut state: Box<RecordingState<'_, T>>) -> Result<()>
+where
+    T: std::io::Write + std::io::Seek,
+{
+    if let MaybeMp4Writer::Mp4Writer(mut sample_writer) = state.mp4_segment {
+        let h264_parser = match &mut state.my_encoder {
+            MyEncoder::CopyRawH264 { h264_parser } => h264_parser,
+            MyEncoder::LessH264(encoder) => &mut encoder.h264_parser,
+            #[cfg(feature = "openh264")]
+            MyEncoder::OpenH264(encoder) => &mut encoder.h264_parser,
+            MyEncoder::Nvidia(nv_encoder) => &mut nv_encoder.h264_parser,
+        };
+        h264_parser.flush_avcc_samples(&mut sample_writer)?;
+        sample_writer.finish(h264_parser.num_sample_bytes)?;
+    }
+    Ok(())
+}
+
 fn nv_outbuf_to_sample(outbuf: dynlink_nvidia_encode::api::LockedOutputBuffer) -> EbspNals {
     let nals = h264_annexb_split(outbuf.mem()).collect();
 
@@ -683,7 +822,7 @@ where
             // Happy path when .finish() already called.
             Some(WriteState::Finished) => {}
             // Error happened in self.write().
-            None => {}
+            None | Some(WriteState::Failed(_)) => {}
             // When .finished() not already called.
             Some(_) => {
                 if !std::thread::panicking() {
@@ -739,7 +878,7 @@ where
         }
         #[cfg(feature = "openh264")]
         (MyEncoder::OpenH264(encoder), Some(state_inner)) => {
-            // todo: bitrate, keyframes, timestamp check and duration finding.
+            // todo: bitrate, keyframes.
 
             let y4m = y4m_writer::encode_y4m_frame(raw_frame, y4m::Colorspace::C420paldv, None)?;
 
@@ -775,6 +914,13 @@ where
             let vram_buf: &mut IOBuffer<_, _> = match nv_encoder.vram_queue.get_available() {
                 Some(iobuf) => iobuf,
                 None => {
+                    // All buffers are in use. Save the oldest output, which
+                    // is ready unless the encoder buffers more frames than
+                    // there are buffers.
+                    if nv_encoder.num_ready == 0 {
+                        return inconsistent_state_err();
+                    }
+                    nv_encoder.num_ready -= 1;
                     let sample = {
                         let iobuf = nv_encoder.vram_queue.get_pending().expect("get pending");
                         // scope for locked output buffer
@@ -817,9 +963,19 @@ where
             let elapsed = timestamp.signed_duration_since(state_inner.first_timestamp);
             let pts = elapsed.to_std().unwrap();
 
-            nv_encoder
-                .encoder
-                .encode_picture(&vram_buf.in_buf, &vram_buf.out_buf, pitch, pts)?;
+            let status = nv_encoder.encoder.encode_picture(
+                &vram_buf.in_buf,
+                &vram_buf.out_buf,
+                pitch,
+                pts,
+            )?;
+            match status {
+                nvenc::EncodeStatus::Ready => {
+                    nv_encoder.num_ready = nv_encoder.vram_queue.pending_count();
+                }
+                // With B-frames, the frame is buffered for reordering.
+                nvenc::EncodeStatus::NeedMoreInput => {}
+            }
         }
         (_encoder, None) => {
             return inconsistent_state_err();
@@ -834,6 +990,9 @@ where
 {
     Configured(Box<(T, Mp4RecordingConfig, H264Parser)>),
     Recording(Box<RecordingState<'lib, T>>),
+    /// Writing a sample failed. The samples written before can still be saved
+    /// with [Mp4Writer::finish].
+    Failed(Box<RecordingState<'lib, T>>),
     Finished,
 }
 
@@ -846,6 +1005,43 @@ where
     inner: Option<RecordingStateInner>,
 }
 
+/// How the MP4 file is written, fixed before its first sample.
+#[derive(Default)]
+struct OutputConfig {
+    /// If set, write a fragmented MP4 file with fragments of at least this
+    /// duration, in units of `movie_timescale`.
+    fragment_duration: Option<u64>,
+    /// saved in the `moov` box
+    container_metadata: Option<Mp4ContainerMetadata>,
+}
+
+fn output_config(
+    cfg: &Mp4RecordingConfig,
+    first_timestamp: chrono::DateTime<chrono::Local>,
+) -> OutputConfig {
+    OutputConfig {
+        fragment_duration: cfg
+            .fragment_duration_secs
+            .map(|secs| (secs.max(0.0) * f64::from(MOVIE_TIMESCALE)).round() as u64),
+        container_metadata: container_metadata(cfg, first_timestamp),
+    }
+}
+
+/// The container metadata to save for `cfg`, created at `first_timestamp` if
+/// no creation time is given.
+fn container_metadata(
+    cfg: &Mp4RecordingConfig,
+    first_timestamp: chrono::DateTime<chrono::Local>,
+) -> Option<Mp4ContainerMetadata> {
+    let mut metadata = match (&cfg.container_metadata, &cfg.h264_metadata) {
+        (Some(metadata), _) => metadata.clone(),
+        (None, Some(h264_metadata)) => Mp4ContainerMetadata::from_h264_metadata(h264_metadata),
+        (None, None) => return None,
+    };
+    metadata.creation_time.get_or_insert(first_timestamp.into());
+    Some(metadata)
+}
+
 struct RecordingStateInner {
     first_timestamp: chrono::DateTime<chrono::Local>,
     previous_timestamp: chrono::DateTime<chrono::Local>,
@@ -882,16 +1078,15 @@ impl LessEncoderWrapper {
 
         let mut mp4_writer = match std::mem::replace(mp4_segment, MaybeMp4Writer::Nothing) {
             MaybeMp4Writer::Mp4Writer(mp4_writer) => mp4_writer,
-            MaybeMp4Writer::Starting(fd) => {
-                start_mp4_writer(fd, sps, pps, trim_width, trim_height)?
+            MaybeMp4Writer::Starting(fd, output) => {
+                start_mp4_writer(fd, output, sps, pps, trim_width, trim_height)?
             }
             MaybeMp4Writer::Nothing => {
                 panic!("inconsistent state");
             }
         };
 
-        let avcc_sample = self.h264_parser.avcc_sample().unwrap();
-        mp4_writer.write_sample(TRACK_ID, &avcc_sample)?;
+        self.h264_parser.write_avcc_sample(&mut mp4_writer)?;
 
         *mp4_segment = MaybeMp4Writer::Mp4Writer(mp4_writer);
 
@@ -903,6 +1098,11 @@ struct NvEncoder<'lib> {
     encoder: Rc<nvenc::Encoder<'lib>>,
     h264_parser: H264Parser,
     vram_queue: nvenc::Queue<IOBuffer<InputBuffer<'lib>, OutputBuffer<'lib>>>,
+    /// The number of the oldest pending buffers whose output can be locked.
+    ///
+    /// The encoder outputs the frames in decode order, so with B-frames, a
+    /// frame is only output once a later frame has been encoded.
+    num_ready: usize,
     first_timestamp: chrono::DateTime<chrono::Local>,
 }
 
@@ -924,18 +1124,17 @@ impl<'lib> NvEncoder<'lib> {
         self.h264_parser.push_nals(sample, Some(local_timestamp));
         let mut mp4_writer = match std::mem::replace(mp4_segment, MaybeMp4Writer::Nothing) {
             MaybeMp4Writer::Mp4Writer(mp4_writer) => mp4_writer,
-            MaybeMp4Writer::Starting(fd) => {
+            MaybeMp4Writer::Starting(fd, output) => {
                 let sps = self.h264_parser.sps().unwrap();
                 let pps = self.h264_parser.pps().unwrap();
-                start_mp4_writer(fd, sps, pps, trim_width, trim_height)?
+                start_mp4_writer(fd, output, sps, pps, trim_width, trim_height)?
             }
             MaybeMp4Writer::Nothing => {
                 panic!("inconsistent state");
             }
         };
 
-        let avcc_sample = self.h264_parser.avcc_sample().unwrap();
-        mp4_writer.write_sample(TRACK_ID, &avcc_sample)?;
+        self.h264_parser.write_avcc_sample(&mut mp4_writer)?;
 
         *mp4_segment = MaybeMp4Writer::Mp4Writer(mp4_writer);
 
@@ -945,25 +1144,29 @@ impl<'lib> NvEncoder<'lib> {
 
 fn start_mp4_writer<T>(
     fd: T,
+    output: OutputConfig,
     sps: &[u8],
     pps: &[u8],
     trim_width: u32,
     trim_height: u32,
-) -> Result<mp4::Mp4Writer<T>>
+) -> Result<SampleWriter<T>>
 where
     T: std::io::Write + std::io::Seek,
 {
+    let mut compatible_brands = vec![str::parse("isom").unwrap()];
+    if output.fragment_duration.is_some() {
+        // Signals support of movie fragments with `tfdt` boxes.
+        compatible_brands.push(str::parse("iso6").unwrap());
+    }
     let mp4_config = mp4::Mp4Config {
         major_brand: str::parse("isom").unwrap(),
         minor_version: 512,
-        compatible_brands: vec![str::parse("isom").unwrap()],
+        compatible_brands,
         // This is `movie_timescale`, the number of
         // time units that pass in one second.
         timescale: MOVIE_TIMESCALE,
     };
 
-    let mut mp4_writer = mp4::Mp4Writer::write_start(fd, &mp4_config)?;
-
     let media_conf = mp4::MediaConfig::AvcConfig(mp4::AvcConfig {
         width: trim_width.try_into().unwrap(),
         height: trim_height.try_into().unwrap(),
@@ -978,8 +1181,22 @@ where
         media_conf,
     };
 
+    if let Some(fragment_duration) = output.fragment_duration {
+        return Ok(SampleWriter::Fragmented(FragmentedWriter::new(
+            fd,
+            &mp4_config,
+            &track_conf,
+            fragment_duration,
+            output.container_metadata.as_ref(),
+        )?));
+    }
+
+    let mut mp4_writer = mp4::Mp4Writer::write_start(fd, &mp4_config)?;
     mp4_writer.add_track(&track_conf)?;
-    Ok(mp4_writer)
+    Ok(SampleWriter::Regular {
+        mp4_writer,
+        container_metadata: output.container_metadata,
+    })
 }
 
 #[cfg(feature = "openh264")]
@@ -1011,16 +1228,15 @@ impl OpenH264Encoder {
 
         let mut mp4_writer = match std::mem::replace(mp4_segment, MaybeMp4Writer::Nothing) {
             MaybeMp4Writer::Mp4Writer(mp4_writer) => mp4_writer,
-            MaybeMp4Writer::Starting(fd) => {
-                start_mp4_writer(fd, sps, pps, trim_width, trim_height)?
+            MaybeMp4Writer::Starting(fd, output) => {
+                start_mp4_writer(fd, output, sps, pps, trim_width, trim_height)?
             }
             MaybeMp4Writer::Nothing => {
                 panic!("inconsistent state");
             }
         };
 
-        let avcc_sample = self.h264_parser.avcc_sample().unwrap();
-        mp4_writer.write_sample(TRACK_ID, &avcc_sample)?;
+        self.h264_parser.write_avcc_sample(&mut mp4_writer)?;
 
         *mp4_segment = MaybeMp4Writer::Mp4Writer(mp4_writer);
 
@@ -1038,19 +1254,75 @@ where
     T: std::io::Write + std::io::Seek,
 {
     Nothing,
-    Starting(T),
-    Mp4Writer(mp4::Mp4Writer<T>),
+    Starting(T, OutputConfig),
+    Mp4Writer(SampleWriter<T>),
+}
+
+/// Writes the samples to a regular or a fragmented MP4 file.
+enum SampleWriter<T>
+where
+    T: std::io::Write + std::io::Seek,
+{
+    /// All samples are described in the `moov` box at the end of the file.
+    Regular {
+        mp4_writer: mp4::Mp4Writer<T>,
+        container_metadata: Option<Mp4ContainerMetadata>,
+    },
+    Fragmented(FragmentedWriter<T>),
+}
+
+impl<T> SampleWriter<T>
+where
+    T: std::io::Write + std::io::Seek,
+{
+    fn write_sample(&mut self, sample: &mp4::Mp4Sample) -> Result<()> {
+        match self {
+            Self::Regular { mp4_writer, .. } => mp4_writer.write_sample(TRACK_ID, sample)?,
+            Self::Fragmented(writer) => writer.write_sample(sample)?,
+        }
+        Ok(())
+    }
+
+    /// Finish the file after `num_sample_bytes` bytes of samples were written.
+    fn finish(self, num_sample_bytes: u64) -> Result<()> {
+        match self {
+            Self::Regular {
+                mut mp4_writer,
+                container_metadata,
+            } => {
+                mp4_writer.write_end()?;
+                if let Some(metadata) = &container_metadata {
+                    let moov_start = MDAT_DATA_OFFSET + num_sample_bytes;
+                    udta::append_to_moov(&mut mp4_writer.into_writer(), moov_start, metadata)?;
+                }
+                Ok(())
+            }
+            Self::Fragmented(writer) => writer.finish(),
+        }
+    }
 }
 
 #[derive(Clone)]
 struct H264Parser {
     sps: Option<Vec<u8>>,
     pps: Option<Vec<u8>>,
-    previous_stamp: Option<u64>,
-    /// stores MP4 sample until written
-    last_sample: Option<ParsedH264Frame>,
+    /// samples not yet written, in decode order
+    pending: VecDeque<ParsedH264Frame>,
+    /// presentation times of the pending samples, smallest first
+    pending_pts: BinaryHeap<Reverse<u64>>,
+    /// decode time of the last sample written
+    last_dts: Option<u64>,
+    /// duration of the last sample written
+    last_duration: u32,
+    /// Delay added to the presentation times so that the rendering offsets
+    /// are not negative. Set when the first sample is written.
+    composition_delay: Option<u64>,
     first_frame_done: bool,
     h264_metadata: Option<H264Metadata>,
+    /// number of samples written
+    num_samples: u64,
+    /// number of bytes of the samples written
+    num_sample_bytes: u64,
 }
 
 impl H264Parser {
@@ -1059,10 +1331,15 @@ impl H264Parser {
         Self {
             sps: None,
             pps: None,
-            previous_stamp: None,
-            last_sample: None,
+            pending: VecDeque::new(),
+            pending_pts: BinaryHeap::new(),
+            last_dts: None,
+            last_duration: 0,
+            composition_delay: None,
             first_frame_done: false,
             h264_metadata,
+            num_samples: 0,
+            num_sample_bytes: 0,
         }
     }
     fn sps(&self) -> Option<&[u8]> {
@@ -1072,11 +1349,27 @@ impl H264Parser {
         self.pps.as_deref()
     }
 
+    /// Add a sample, given in decode order, to the samples to be written.
+    ///
+    /// Like frames given for encoding, the sample is dropped with a warning if
+    /// its presentation time is not after the decode time of the samples
+    /// already written or equals that of a pending sample.
     fn push_nals(
         &mut self,
         nals: EbspNals,
         mut precision_timestamp: Option<chrono::DateTime<chrono::Local>>,
     ) {
+        let pts = nals.mp4_sample_start_time;
+        if self.last_dts.is_some_and(|dts| pts <= dts)
+            || self.pending.iter().any(|s| s.mp4_sample_start_time == pts)
+        {
+            warn!(
+                "Not saving frame {} seconds after the first frame: not after previous frames",
+                nals.pts.num_microseconds().unwrap_or(i64::MAX) as f64 * 1e-6
+            );
+            return;
+        }
+
         // We assume that sample contains one or more compete NAL units and
         // starts with a NAL unit. Furthermore, we assume the start bytes can
         // only be [0x00, 0x00, 0x00, 0x01]. This is not a real Annex B parser
@@ -1166,35 +1459,142 @@ impl H264Parser {
             }
         }
 
-        if self
-            .last_sample
-            .replace(ParsedH264Frame {
-                mp4_sample_start_time: nals.mp4_sample_start_time,
-                is_keyframe: nals.is_keyframe,
-                avcc_buf: all_avcc_nal_units,
-            })
-            .is_some()
-        {
-            eprintln!("unused NAL unit");
-        };
+        self.pending.push_back(ParsedH264Frame {
+            mp4_sample_start_time: pts,
+            is_keyframe: nals.is_keyframe,
+            avcc_buf: all_avcc_nal_units,
+        });
+        self.pending_pts.push(Reverse(pts));
     }
 
+    /// Take the next sample in decode order.
+    ///
+    /// The decode times are the presentation times in increasing order. The
+    /// duration of a sample is the interval until the decode time of the next
+    /// sample. For the last sample, this is not known and the duration of the
+    /// previous sample is used. The rendering offset is the difference between
+    /// presentation and decode time.
+    ///
+    /// With B-frames, a frame is presented before the decode time of the
+    /// frames preceding it in decode order. The offsets are stored unsigned in
+    /// the `ctts` box, so the presentation times are delayed by the largest
+    /// such difference among the samples pending when the first sample is
+    /// written. Without B-frames, the delay is zero, so the first frame is
+    /// presented at time zero without an edit list.
     fn avcc_sample(&mut self) -> Option<mp4::Mp4Sample> {
-        let mut sample = self.last_sample.take().map(parsed_to_mp4_sample);
-        if let Some(ref mut s) = sample {
-            if let Some(prev) = self.previous_stamp {
-                // FIXME: This will be off by one frame because it calculates duration
-                // of this frame as delta between previous frame and this frame. (It
-                // should be delta between this frame and next frame.)
-                let dur = s.start_time - prev;
-                s.duration = dur.try_into().unwrap();
-            }
-            self.previous_stamp = Some(s.start_time);
+        if self.pending.is_empty() {
+            return None;
         }
+        let delay = *self
+            .composition_delay
+            .get_or_insert_with(|| composition_delay(&self.pending, &self.pending_pts));
+        let orig = self.pending.pop_front()?;
+        let Reverse(dts) = self.pending_pts.pop().unwrap();
+        let mut sample = parsed_to_mp4_sample(orig);
+        sample.duration = match self.pending_pts.peek() {
+            Some(Reverse(next_dts)) => sample_duration(dts, *next_dts),
+            None => self.last_duration,
+        };
+        let offset =
+            i64::try_from(sample.start_time + delay).unwrap() - i64::try_from(dts).unwrap();
+        if offset < 0 {
+            warn!("MP4 sample presented {} before its decode time", -offset);
+        }
+        sample.rendering_offset = offset.clamp(0, i32::MAX.into()) as i32;
+        self.last_dts = Some(dts);
+        self.last_duration = sample.duration;
         // Note: as far as I can tell, as of version 0.13.0, the mp4 crate does not
-        // use `start_time` for writing the sample. (So we have gone to the trouble
-        // of ensuring it has a good PTS value but it is ignored.)
-        sample
+        // use `start_time` for writing the sample. The timing in the file is
+        // given by the durations and rendering offsets.
+        Some(sample)
+    }
+
+    /// Write the samples whose timing is known to `mp4_writer`.
+    fn write_avcc_sample<T>(&mut self, mp4_writer: &mut SampleWriter<T>) -> Result<()>
+    where
+        T: std::io::Write + std::io::Seek,
+    {
+        while self.pending.len() > MAX_REORDER_DEPTH {
+            self.write_next_sample(mp4_writer)?;
+        }
+        Ok(())
+    }
+
+    /// Write all remaining samples to `mp4_writer`.
+    ///
+    /// Call this after the last sample was pushed.
+    fn flush_avcc_samples<T>(&mut self, mp4_writer: &mut SampleWriter<T>) -> Result<()>
+    where
+        T: std::io::Write + std::io::Seek,
+    {
+        while !self.pending.is_empty() {
+            self.write_next_sample(mp4_writer)?;
+        }
+        Ok(())
+    }
+
+    fn write_next_sample<T>(&mut self, mp4_writer: &mut SampleWriter<T>) -> Result<()>
+    where
+        T: std::io::Write + std::io::Seek,
+    {
+        if self.num_samples >= MAX_NUM_SAMPLES {
+            return Err(Error::TooManySamples {
+                #[cfg(feature = "backtrace")]
+                backtrace: std::backtrace::Backtrace::capture(),
+            });
+        }
+        let sample = self.avcc_sample().ok_or_else(|| Error::InconsistentState {
+            #[cfg(feature = "backtrace")]
+            backtrace: std::backtrace::Backtrace::capture(),
+        })?;
+        mp4_writer.write_sample(&sample)?;
+        self.num_samples += 1;
+        self.num_sample_bytes += sample.bytes.len() as u64;
+        Ok(())
+    }
+}
+
+/// The delay, in units of `movie_timescale`, by which the presentation of the
+/// `pending` samples must be delayed to not precede their decode times.
+///
+/// `pending_pts` are the presentation times of the `pending` samples, which are
+/// also their decode times.
+fn composition_delay(
+    pending: &VecDeque<ParsedH264Frame>,
+    pending_pts: &BinaryHeap<Reverse<u64>>,
+) -> u64 {
+    let mut decode_times: Vec<u64> = pending_pts.iter().map(|Reverse(pts)| *pts).collect();
+    decode_times.sort_unstable();
+    pending
+        .iter()
+        .zip(decode_times)
+        .map(|(sample, dts)| dts.saturating_sub(sample.mp4_sample_start_time))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Compute the interval, in units of `movie_timescale`, from `prev` to
+/// `start_time`, e.g. the duration of a sample decoded at `prev` followed by a
+/// sample decoded at `start_time`.
+///
+/// The duration of a sample in the `stts` box is a 32-bit number, which at
+/// 90 kHz limits it to about 13 hours. Longer durations (for example an
+/// interruption of a timelapse) are clamped. The precision timestamp of each
+/// frame is not affected.
+fn sample_duration(prev: u64, start_time: u64) -> u32 {
+    let Some(dur) = start_time.checked_sub(prev) else {
+        warn!("MP4 sample time {start_time} is before previous sample time {prev}");
+        return 0;
+    };
+    match u32::try_from(dur) {
+        Ok(dur) => dur,
+        Err(_) => {
+            warn!(
+                "MP4 sample duration {dur} exceeds maximum, clamping to {}",
+                u32::MAX
+            );
+            u32::MAX
+        }
     }
 }
 
@@ -1346,7 +1746,10 @@ impl openh264::formats::YUVSource for YUVData {
 }
 
 fn dur2raw(dur: &std::time::Duration) -> u64 {
-    (dur.as_secs_f64() * MOVIE_TIMESCALE as f64).round() as u64
+    // Integer arithmetic keeps full precision also for very long recordings.
+    let timescale = u64::from(MOVIE_TIMESCALE);
+    let subsec = (u64::from(dur.subsec_nanos()) * timescale + 500_000_000) / 1_000_000_000;
+    dur.as_secs() * timescale + subsec
 }
 
 fn timestamp_to_sei_payload(timestamp: chrono::DateTime<chrono::Utc>, payload: &mut [u8]) {
@@ -1386,6 +1789,63 @@ fn convert_openh264_rc_mode(
     }
 }
 
+/// The tuning of the NVENC preset, or `None` for the legacy presets.
+fn convert_nvenc_tuning(opts: &NvidiaH264Options) -> Option<nvenc::TuningInfo> {
+    use ci2_remote_control::NvencTuning as tuning;
+    use nvenc::TuningInfo::*;
+    if opts.preset == NvencPreset::HighPerformance {
+        return None;
+    }
+    Some(match opts.tuning {
+        tuning::HighQuality => HighQuality,
+        tuning::LowLatency => LowLatency,
+        tuning::UltraLowLatency => UltraLowLatency,
+        tuning::Lossless => Lossless,
+    })
+}
+
+/// Check the NVENC options against the capabilities of the device.
+fn check_nvenc_options(
+    opts: &NvidiaH264Options,
+    max_b_frames: u32,
+    supports_rate_control_mode: bool,
+) -> Result<()> {
+    if !supports_rate_control_mode {
+        return Err(Error::UnsupportedNvencOptions(format!(
+            "rate control {:?} not supported by the device",
+            opts.rate_control
+        )));
+    }
+    if let NvencRateControl::ConstQp(qp) = opts.rate_control {
+        if qp > 51 {
+            return Err(Error::UnsupportedNvencOptions(format!(
+                "constant QP {qp} is above the maximum of 51"
+            )));
+        }
+    }
+    if let Some(num_b_frames) = opts.num_b_frames {
+        if num_b_frames > max_b_frames {
+            return Err(Error::UnsupportedNvencOptions(format!(
+                "{num_b_frames} B-frames requested but the device supports at most \
+                {max_b_frames}"
+            )));
+        }
+        if let Some(gop_length) = opts.gop_length {
+            if num_b_frames >= gop_length {
+                return Err(Error::UnsupportedNvencOptions(format!(
+                    "{num_b_frames} B-frames do not fit in a GOP of length {gop_length}"
+                )));
+            }
+        }
+    }
+    if opts.gop_length == Some(0) {
+        return Err(Error::UnsupportedNvencOptions(
+            "GOP length must be at least one".to_string(),
+        ));
+    }
+    Ok(())
+}
+
 struct NalAvccBufIter<'a> {
     cur_buf: &'a [u8],
 }
@@ -1421,7 +1881,7 @@ fn iter_avcc_bufs(buf: &[u8]) -> NalAvccBufIter {
 }
 
 /// parse h264 NAL unit and return if it is an IDR frame
-fn parse_h264_is_idr_frame(data: &frame_source::H264EncodingVariant) -> Result<bool> {
+pub fn parse_h264_is_idr_frame(data: &frame_source::H264EncodingVariant) -> Result<bool> {
     use h264_reader::nal::{Nal, RefNal, UnitType};
     use h264_reader::push::NalInterest;
     let mut calls = Vec::new();
@@ -1494,3 +1954,119 @@ fn inconsistent_state_err<T>() -> Result<T> {
         backtrace: std::backtrace::Backtrace::capture(),
     })
 }
+
+#[test]
+fn test_long_durations() {
+    // 31 days, in units of `movie_timescale`.
+    let month = 31 * 24 * 3600 * u64::from(MOVIE_TIMESCALE);
+    assert!(month > u64::from(u32::MAX));
+    let dur = std::time::Duration::from_secs(31 * 24 * 3600) + std::time::Duration::from_micros(1);
+    assert_eq!(dur2raw(&dur), month);
+
+    assert_eq!(sample_duration(month, month + 3000), 3000);
+    assert_eq!(sample_duration(0, month), u32::MAX);
+    assert_eq!(sample_duration(month, 0), 0);
+}
+
+#[cfg(test)]
+fn test_sample(mp4_sample_start_time: u64) -> EbspNals {
+    EbspNals {
+        pts: chrono::Duration::microseconds(
+            (mp4_sample_start_time * 1_000_000 / u64::from(MOVIE_TIMESCALE)) as i64,
+        ),
+        mp4_sample_start_time,
+        is_keyframe: mp4_sample_start_time == 0,
+        nals: vec![vec![0x65, 0x88]],
+    }
+}
+
+#[test]
+fn test_max_num_samples() {
+    let mut parser = H264Parser::new(None);
+    parser.push_nals(test_sample(0), None);
+    let cursor = std::io::Cursor::new(Vec::new());
+    let mut mp4_writer = start_mp4_writer(
+        cursor,
+        OutputConfig::default(),
+        &[0x67, 0, 0, 0],
+        &[0x68, 0],
+        16,
+        16,
+    )
+    .unwrap();
+
+    // Pretend the file already contains the maximum number of samples.
+    parser.num_samples = MAX_NUM_SAMPLES;
+    assert!(matches!(
+        parser.flush_avcc_samples(&mut mp4_writer),
+        Err(Error::TooManySamples { .. })
+    ));
+
+    parser.num_samples = MAX_NUM_SAMPLES - 1;
+    parser.flush_avcc_samples(&mut mp4_writer).unwrap();
+    assert_eq!(parser.num_samples, MAX_NUM_SAMPLES);
+}
+
+#[test]
+fn test_sample_timing() {
+    // Variable frame intervals with B-frames: samples in decode order.
+    let pts = [0, 6000, 3000, 4000, 12000, 7000, 9000];
+    let mut parser = H264Parser::new(None);
+    for pts in pts {
+        parser.push_nals(test_sample(pts), None);
+    }
+    let samples: Vec<_> = std::iter::from_fn(|| parser.avcc_sample()).collect();
+    let start: Vec<_> = samples.iter().map(|s| s.start_time).collect();
+    assert_eq!(start, pts);
+    // Decode times are the sorted presentation times.
+    let durations: Vec<_> = samples.iter().map(|s| s.duration).collect();
+    assert_eq!(durations, [3000, 1000, 2000, 1000, 2000, 3000, 3000]);
+    // The presentation is delayed by 3000 so that no offset is negative.
+    let offsets: Vec<_> = samples.iter().map(|s| s.rendering_offset).collect();
+    assert_eq!(offsets, [3000, 6000, 2000, 1000, 8000, 1000, 0]);
+
+    // A frame presented before frames already decoded is dropped, as is a
+    // frame with the same time as a pending frame.
+    parser.push_nals(test_sample(9000), None);
+    assert!(parser.pending.is_empty());
+    parser.push_nals(test_sample(15000), None);
+    parser.push_nals(test_sample(15000), None);
+    assert_eq!(parser.pending.len(), 1);
+
+    // Without B-frames, the presentation is not delayed.
+    let mut parser = H264Parser::new(None);
+    for pts in [0, 3000, 6000] {
+        parser.push_nals(test_sample(pts), None);
+    }
+    let samples: Vec<_> = std::iter::from_fn(|| parser.avcc_sample()).collect();
+    let offsets: Vec<_> = samples.iter().map(|s| s.rendering_offset).collect();
+    assert_eq!(offsets, [0, 0, 0]);
+}
+
+#[test]
+fn test_check_nvenc_options() {
+    let opts = NvidiaH264Options {
+        rate_control: NvencRateControl::ConstQp(23),
+        gop_length: Some(30),
+        num_b_frames: Some(2),
+        ..Default::default()
+    };
+    check_nvenc_options(&opts, 4, true).unwrap();
+    assert!(check_nvenc_options(&opts, 1, true).is_err());
+    assert!(check_nvenc_options(&opts, 4, false).is_err());
+
+    let opts = NvidiaH264Options {
+        rate_control: NvencRateControl::ConstQp(52),
+        ..Default::default()
+    };
+    assert!(check_nvenc_options(&opts, 4, true).is_err());
+
+    let opts = NvidiaH264Options {
+        gop_length: Some(2),
+        num_b_frames: Some(2),
+        ..Default::default()
+    };
+    assert!(check_nvenc_options(&opts, 4, true).is_err());
+
+    assert!(convert_nvenc_tuning(&NvidiaH264Options::default()).is_none());
+}
@@ -0,0 +1,117 @@
+//! Container metadata in the `udta` box of the `moov` box.
+//!
+//! The mp4 crate does not write user data, so the `udta` box is appended to
+//! the `moov` box after the file is otherwise complete. The items are written
+//! in the iTunes style understood by most tools: a `meta` box with an `mdir`
+//! handler and an `ilst` box with one item per key.
+
+use std::io::{Seek, SeekFrom, Write};
+
+use ci2_remote_control::Mp4ContainerMetadata;
+
+use crate::Result;
+
+/// Type of a `data` box holding UTF-8 text.
+const DATA_TYPE_UTF8: u32 = 1;
+
+/// Namespace of the freeform items holding custom keys.
+const FREEFORM_MEAN: &[u8] = b"com.apple.iTunes";
+
+/// Append the `udta` box with `metadata` to the `moov` box at `moov_start`.
+///
+/// The `moov` box must be the last box in `fd` and `fd` must be positioned at
+/// its end. The position is left at the new end of the file.
+pub(crate) fn append_to_moov<T>(
+    fd: &mut T,
+    moov_start: u64,
+    metadata: &Mp4ContainerMetadata,
+) -> Result<()>
+where
+    T: Write + Seek,
+{
+    append_box_to_moov(fd, moov_start, &udta_box(metadata))
+}
+
+/// Append the box `child` to the `moov` box at `moov_start`.
+///
+/// The same requirements as for [append_to_moov] apply.
+pub(crate) fn append_box_to_moov<T>(fd: &mut T, moov_start: u64, child: &[u8]) -> Result<()>
+where
+    T: Write + Seek,
+{
+    let moov_end = fd.stream_position()?;
+    let moov_size = moov_end
+        .checked_sub(moov_start)
+        .map(|size| size + child.len() as u64)
+        .and_then(|size| u32::try_from(size).ok())
+        .ok_or_else(|| crate::Error::InconsistentState {
+            #[cfg(feature = "backtrace")]
+            backtrace: std::backtrace::Backtrace::capture(),
+        })?;
+    fd.write_all(child)?;
+    let file_end = fd.stream_position()?;
+    fd.seek(SeekFrom::Start(moov_start))?;
+    fd.write_all(&moov_size.to_be_bytes())?;
+    fd.seek(SeekFrom::Start(file_end))?;
+    Ok(())
+}
+
+/// Build the `udta` box with `metadata`.
+fn udta_box(metadata: &Mp4ContainerMetadata) -> Vec<u8> {
+    let mut items = Vec::new();
+    if let Some(title) = &metadata.title {
+        items.extend(text_item(b"\xa9nam", title));
+    }
+    if let Some(creation_time) = &metadata.creation_time {
+        items.extend(text_item(b"\xa9day", &creation_time.to_rfc3339()));
+    }
+    if let Some(encoder) = &metadata.encoder {
+        items.extend(text_item(b"\xa9too", encoder));
+    }
+    for (key, value) in metadata.custom.iter() {
+        let mut item = full_box(b"mean", FREEFORM_MEAN);
+        item.extend(full_box(b"name", key.as_bytes()));
+        item.extend(data_box(value));
+        items.extend(mp4_box(b"----", &item));
+    }
+
+    // The handler of the `meta` box: version and flags, pre-defined, handler
+    // type, reserved and an empty name.
+    let mut hdlr = vec![0u8; 8];
+    hdlr.extend(b"mdir");
+    hdlr.extend(b"appl");
+    hdlr.extend([0u8; 9]);
+
+    let mut meta = vec![0u8; 4];
+    meta.extend(mp4_box(b"hdlr", &hdlr));
+    meta.extend(mp4_box(b"ilst", &items));
+    mp4_box(b"udta", &mp4_box(b"meta", &meta))
+}
+
+fn text_item(key: &[u8; 4], value: &str) -> Vec<u8> {
+    mp4_box(key, &data_box(value))
+}
+
+fn data_box(value: &str) -> Vec<u8> {
+    let mut data = DATA_TYPE_UTF8.to_be_bytes().to_vec();
+    // locale
+    data.extend([0u8; 4]);
+    data.extend(value.as_bytes());
+    mp4_box(b"data", &data)
+}
+
+/// A box with version 0 and no flags.
+pub(crate) fn full_box(box_type: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut contents = vec![0u8; 4];
+    contents.extend(payload);
+    mp4_box(box_type, &contents)
+}
+
+pub(crate) fn mp4_box(box_type: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+    let size: u32 = (contents.len() + 8).try_into().unwrap();
+    let mut result = Vec::with_capacity(contents.len() + 8);
+    result.extend(size.to_be_bytes());
+    result.extend(box_type);
+    result.extend(contents);
+    result
+}
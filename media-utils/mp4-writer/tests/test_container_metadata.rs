@@ -0,0 +1,66 @@
+use eyre::Result;
+
+use ci2_remote_control::{H264Metadata, Mp4RecordingConfig};
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Save a short video with H264 metadata and check the container metadata
+/// derived from it.
+#[test]
+fn test_container_metadata() -> Result<()> {
+    let start = chrono::DateTime::from_timestamp(61, 0).unwrap();
+    let mut h264_metadata = H264Metadata::new("mp4-writer-test", start.into());
+    h264_metadata.camera_name = Some("cam1".into());
+    h264_metadata.recording_label = Some("trial 3".into());
+
+    let tmpdir = tempfile::tempdir()?;
+    let path = tmpdir.path().join("metadata.mp4");
+    let n_frames = 3;
+    {
+        let cfg = Mp4RecordingConfig {
+            codec: ci2_remote_control::Mp4Codec::H264LessAvc,
+            max_framerate: Default::default(),
+            h264_metadata: Some(h264_metadata),
+            container_metadata: None,
+            fragment_duration_secs: None,
+        };
+        let fd = std::fs::File::create(&path)?;
+        let mut writer = mp4_writer::Mp4Writer::new(fd, cfg, None)?;
+        for i in 0..n_frames {
+            let timestamp = start + chrono::Duration::milliseconds(i64::from(i) * 10);
+            let frame = basic_frame::DynamicFrame::new(
+                16,
+                16,
+                16,
+                Box::new(basic_frame::BasicExtra {
+                    host_framenumber: i as usize,
+                    host_timestamp: timestamp,
+                }),
+                vec![(i * 40) as u8; 16 * 16],
+                machine_vision_formats::PixFmt::Mono8,
+            );
+            writer.write_dynamic(&frame, timestamp)?;
+        }
+        writer.finish()?;
+    }
+
+    // The `moov` box including the appended `udta` box is still valid.
+    let fd = std::fs::File::open(&path)?;
+    let size = fd.metadata()?.len();
+    let reader = mp4::Mp4Reader::read_header(std::io::BufReader::new(fd), size)?;
+    let track_id = *reader.tracks().keys().next().unwrap();
+    assert_eq!(reader.tracks()[&track_id].sample_count(), n_frames);
+
+    let buf = std::fs::read(&path)?;
+    assert!(contains(&buf, b"udta"));
+    assert!(contains(&buf, b"\xa9nam"));
+    assert!(contains(&buf, b"cam1"));
+    assert!(contains(&buf, b"\xa9too"));
+    assert!(contains(&buf, b"mp4-writer-test"));
+    assert!(contains(&buf, b"\xa9day"));
+    assert!(contains(&buf, b"recording_label"));
+    assert!(contains(&buf, b"trial 3"));
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use eyre::Result;
+
+use ci2_remote_control::Mp4RecordingConfig;
+
+/// Return the types of the top-level boxes in `buf`, which must consist of
+/// complete boxes.
+fn top_level_boxes(buf: &[u8]) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut rest = buf;
+    while !rest.is_empty() {
+        let size = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        assert!(size >= 8 && size <= rest.len(), "incomplete box");
+        result.push(String::from_utf8_lossy(&rest[4..8]).to_string());
+        rest = &rest[size..];
+    }
+    result
+}
+
+/// Save a fragmented MP4 file and check that it is playable while being
+/// written and after finishing.
+#[test]
+fn test_fragmented() -> Result<()> {
+    let start = chrono::DateTime::from_timestamp(61, 0).unwrap();
+    let tmpdir = tempfile::tempdir()?;
+    let path = tmpdir.path().join("fragmented.mp4");
+    let n_frames = 40;
+    {
+        let cfg = Mp4RecordingConfig {
+            codec: ci2_remote_control::Mp4Codec::H264LessAvc,
+            max_framerate: Default::default(),
+            h264_metadata: None,
+            container_metadata: None,
+            // Five frames per fragment.
+            fragment_duration_secs: Some(0.05),
+        };
+        let fd = std::fs::File::create(&path)?;
+        let mut writer = mp4_writer::Mp4Writer::new(fd, cfg, None)?;
+        for i in 0..n_frames {
+            let timestamp = start + chrono::Duration::milliseconds(i64::from(i) * 10);
+            let frame = basic_frame::DynamicFrame::new(
+                16,
+                16,
+                16,
+                Box::new(basic_frame::BasicExtra {
+                    host_framenumber: i as usize,
+                    host_timestamp: timestamp,
+                }),
+                vec![(i * 10) as u8; 16 * 16],
+                machine_vision_formats::PixFmt::Mono8,
+            );
+            writer.write_dynamic(&frame, timestamp)?;
+        }
+
+        // Before finishing, as after a crash, the file consists of complete
+        // fragments. The last frames are still held back by the writer.
+        let boxes = top_level_boxes(&std::fs::read(&path)?);
+        assert_eq!(boxes[..2], ["ftyp", "moov"]);
+        let fragments = &boxes[2..];
+        assert!(fragments.len() >= 2);
+        assert!(fragments.chunks(2).all(|f| f == ["moof", "mdat"]));
+
+        writer.finish()?;
+    }
+
+    let boxes = top_level_boxes(&std::fs::read(&path)?);
+    assert_eq!(boxes.len(), 2 + 2 * 8);
+
+    let fd = std::fs::File::open(&path)?;
+    let size = fd.metadata()?.len();
+    let reader = mp4::Mp4Reader::read_header(std::io::BufReader::new(fd), size)?;
+    let track_id = *reader.tracks().keys().next().unwrap();
+    assert_eq!(reader.tracks()[&track_id].sample_count(), n_frames);
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use eyre::Result;
+
+use ci2_remote_control::Mp4RecordingConfig;
+
+/// Save a synthetic timelapse spanning several days and check the durations
+/// stored in the MP4 file.
+#[test]
+fn test_long_timelapse() -> Result<()> {
+    const TIMESCALE: u64 = 90_000;
+    let start = chrono::DateTime::from_timestamp(61, 0).unwrap();
+    let hour = chrono::Duration::hours(1);
+    // The last interval is too long for the duration of a single sample.
+    let offsets = [0, 12, 24, 36, 48, 70];
+
+    let tmpdir = tempfile::tempdir()?;
+    let path = tmpdir.path().join("timelapse.mp4");
+    {
+        let cfg = Mp4RecordingConfig {
+            codec: ci2_remote_control::Mp4Codec::H264LessAvc,
+            max_framerate: Default::default(),
+            h264_metadata: None,
+            container_metadata: None,
+            fragment_duration_secs: None,
+        };
+        let fd = std::fs::File::create(&path)?;
+        let mut writer = mp4_writer::Mp4Writer::new(fd, cfg, None)?;
+        for (i, offset) in offsets.iter().enumerate() {
+            let timestamp = start + hour * *offset;
+            let frame = basic_frame::DynamicFrame::new(
+                16,
+                16,
+                16,
+                Box::new(basic_frame::BasicExtra {
+                    host_framenumber: i,
+                    host_timestamp: timestamp,
+                }),
+                vec![(i * 40) as u8; 16 * 16],
+                machine_vision_formats::PixFmt::Mono8,
+            );
+            writer.write_dynamic(&frame, timestamp)?;
+        }
+        writer.finish()?;
+    }
+
+    let fd = std::fs::File::open(&path)?;
+    let size = fd.metadata()?.len();
+    let mut reader = mp4::Mp4Reader::read_header(std::io::BufReader::new(fd), size)?;
+    let track_id = *reader.tracks().keys().next().unwrap();
+    assert_eq!(
+        reader.tracks()[&track_id].sample_count(),
+        u32::try_from(offsets.len())?
+    );
+
+    // The duration of each sample is the interval until the next sample. The
+    // last sample has the duration of the previous one.
+    let twelve_hours = 12 * 3600 * TIMESCALE;
+    let mut expected = vec![twelve_hours; 4];
+    expected.extend([u64::from(u32::MAX); 2]);
+    for (sample_id, expected) in (1..).zip(expected.iter()) {
+        let sample = reader.read_sample(track_id, sample_id)?.unwrap();
+        assert_eq!(u64::from(sample.duration), *expected);
+    }
+
+    // The total duration does not fit in 32 bits.
+    let total: u64 = expected.iter().sum();
+    assert!(total > u64::from(u32::MAX));
+    assert_eq!(reader.moov.mvhd.timescale, 90_000);
+    assert_eq!(reader.moov.mvhd.duration, total);
+    Ok(())
+}
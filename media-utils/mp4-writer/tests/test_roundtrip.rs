@@ -96,6 +96,8 @@ fn test_save_then_read_with_ffmpeg() -> Result<()> {
             codec,
             max_framerate: Default::default(),
             h264_metadata: None,
+            container_metadata: None,
+            fragment_duration_secs: None,
         };
 
         let frame = generate_image(pixfmt_str, *width, *height, start)?;
@@ -128,10 +130,10 @@ fn test_save_then_read_with_ffmpeg() -> Result<()> {
         }
 
         // check timestamp
-        let do_decode_h264 = false; // no need to decode h264 to get timestamps.
+        // no need to decode h264 to get timestamps.
         let src = frame_source::from_path_with_timestamp_source(
             &output_name,
-            do_decode_h264,
+            frame_source::ImageDataMode::Encoded,
             frame_source::TimestampSource::MispMicrosectime,
         )?;
         let loaded_timestamp = src.frame0_time().unwrap();
@@ -129,10 +129,10 @@ fn main() -> Result<()> {
             eprintln!("Performing initial open of \"{}\".", input_path.display());
         }
 
-        let do_decode_h264 = false; // no need to decode h264 to get timestamps.
+        // no need to decode h264 to get timestamps.
         let mut src = frame_source::from_path_with_srt_timestamp_source(
             &input_path,
-            do_decode_h264,
+            frame_source::ImageDataMode::Encoded,
             cli.timestamp_source.into(),
             srt_file_path,
         )?;
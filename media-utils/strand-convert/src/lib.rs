@@ -19,7 +19,7 @@ use ci2_remote_control::H264Metadata;
 use basic_frame::{match_all_dynamic_fmts, DynamicFrame};
 use frame_source::{
     fmf_source, mp4_source, pv_tiff_stack, strand_cam_mkv_source, FrameData, FrameDataSource,
-    ImageData,
+    ImageData, ImageDataMode,
 };
 use tiff_decoder::HdrConfig;
 
@@ -451,12 +451,16 @@ pub fn run_cli(cli: Cli) -> Result<()> {
             }
         }
 
-        let do_decode_h264 = cli.export_pngs || cli.skip.is_some();
+        let image_data_mode = if cli.export_pngs || cli.skip.is_some() {
+            ImageDataMode::Decoded
+        } else {
+            ImageDataMode::Encoded
+        };
         match ext {
             Some("mkv") => {
                 let mkv_video = strand_cam_mkv_source::from_path_with_timestamp_source(
                     &input_path,
-                    do_decode_h264,
+                    image_data_mode,
                     frame_source::TimestampSource::BestGuess,
                 )?;
                 let metadata = &mkv_video.parsed.metadata;
@@ -475,7 +479,7 @@ pub fn run_cli(cli: Cli) -> Result<()> {
                 let srt_file_path = None;
                 let mp4_video = mp4_source::from_path_with_timestamp_source(
                     &input_path,
-                    do_decode_h264,
+                    image_data_mode,
                     frame_source::TimestampSource::BestGuess,
                     srt_file_path,
                 )?;
@@ -657,16 +661,22 @@ pub fn run_cli(cli: Cli) -> Result<()> {
         hdr_lum_range = Some((min, max));
     }
 
-    let mut stack_iter = src.iter();
-
-    h264_metadata.creation_time = frame0_time;
-
     let encoder = if cli.export_pngs {
         Encoder::LessAvc // this is a dummy value.
     } else {
         cli.encoder.clone().unwrap_or(default_encoder)
     };
 
+    if !cli.export_pngs && matches!(encoder, Encoder::LessAvc | Encoder::OpenH264) {
+        // These encoders take YUV 4:2:0 input, so keep decoded frames in YUV
+        // rather than converting to RGB and back.
+        src.set_decode_to_nv12(true);
+    }
+
+    let mut stack_iter = src.iter();
+
+    h264_metadata.creation_time = frame0_time;
+
     let (codec, libs_and_nv_enc) = match encoder {
         Encoder::NoneCopyExistingH264 => (ci2_remote_control::Mp4Codec::H264RawStream, None),
         Encoder::LessAvc => (ci2_remote_control::Mp4Codec::H264LessAvc, None),
@@ -782,6 +792,8 @@ pub fn run_cli(cli: Cli) -> Result<()> {
             codec,
             max_framerate: Default::default(),
             h264_metadata,
+            container_metadata: None,
+            fragment_duration_secs: None,
         };
 
         let out_fd = std::fs::File::create(&output_fname)
@@ -912,7 +924,7 @@ pub fn run_cli(cli: Cli) -> Result<()> {
                 )?;
                 output_writer.write_dynamic(&frame, frame_timestamp_utc)?;
             }
-            ImageData::Decoded(frame) => {
+            ImageData::Decoded(frame) | ImageData::Both(frame, _) => {
                 output_writer.write_dynamic(frame, frame_timestamp_utc)?;
             }
             ImageData::EncodedH264(encoded_h264) => {
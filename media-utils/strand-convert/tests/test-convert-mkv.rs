@@ -62,7 +62,7 @@ fn get_metadata<P: AsRef<Path>>(fname: P) -> Result<H264Metadata> {
         Some("mkv") => {
             let mkv_video = frame_source::strand_cam_mkv_source::from_path_with_timestamp_source(
                 &fname,
-                false,
+                frame_source::ImageDataMode::Encoded,
                 frame_source::TimestampSource::BestGuess,
             )?;
             let metadata = &mkv_video.parsed.metadata;
@@ -75,13 +75,19 @@ fn get_metadata<P: AsRef<Path>>(fname: P) -> Result<H264Metadata> {
                 camera_name,
                 gamma,
                 creation_time,
+                segment_index: None,
+                segment_first_frame: None,
+                recording_label: None,
+                recording_tags: Vec::new(),
+                provenance: None,
+                device_clock_model: None,
             })
         }
         Some("mp4") => {
             let srt_file_path = None;
             let mp4_video = frame_source::mp4_source::from_path_with_timestamp_source(
                 &fname,
-                false,
+                frame_source::ImageDataMode::Encoded,
                 frame_source::TimestampSource::BestGuess,
                 srt_file_path,
             )?;
@@ -4,7 +4,7 @@ use indicatif::{ProgressBar, ProgressStyle};
 use std::path::PathBuf;
 
 use basic_frame::DynamicFrame;
-use frame_source::{ImageData, Timestamp};
+use frame_source::{ImageData, ImageDataMode, Timestamp};
 
 #[derive(Debug, Parser)]
 #[command(version, about)]
@@ -42,7 +42,7 @@ struct Opt {
 
 fn to_rr_image(im: ImageData) -> eyre::Result<rerun::EncodedImage> {
     let decoded = match im {
-        ImageData::Decoded(decoded) => decoded,
+        ImageData::Decoded(decoded) | ImageData::Both(decoded, _) => decoded,
         _ => eyre::bail!("image not decoded"),
     };
 
@@ -70,8 +70,7 @@ fn main() -> eyre::Result<()> {
         output.into()
     });
 
-    let do_decode_h264 = true;
-    let mut src = frame_source::from_path(&opt.input, do_decode_h264)?;
+    let mut src = frame_source::from_path(&opt.input, ImageDataMode::Decoded)?;
 
     let entity_path = if let Some(p) = opt.entity_path.as_ref() {
         p.clone()
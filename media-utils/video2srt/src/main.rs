@@ -3,7 +3,7 @@ use color_eyre::eyre::{self, WrapErr};
 use srt_writer::BufferingSrtFrameWriter;
 use std::path::PathBuf;
 
-use frame_source::Timestamp;
+use frame_source::{ImageDataMode, Timestamp};
 
 #[derive(Debug, Parser)]
 #[command(version, about)]
@@ -32,8 +32,7 @@ fn main() -> eyre::Result<()> {
         output
     });
 
-    let do_decode_h264 = false;
-    let mut src = frame_source::from_path(&opt.input, do_decode_h264)
+    let mut src = frame_source::from_path(&opt.input, ImageDataMode::Encoded)
         .with_context(|| format!("while opening path {}", opt.input.display()))?;
 
     let start_time = src
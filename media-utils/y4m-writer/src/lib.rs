@@ -115,6 +115,7 @@ impl Y4MWriter {
             formats::pixel_format::PixFmt::Mono8 => y4m::Colorspace::Cmono,
             formats::pixel_format::PixFmt::RGB8 => y4m::Colorspace::C420paldv,
             formats::pixel_format::PixFmt::YUV422 => y4m::Colorspace::C420paldv,
+            formats::pixel_format::PixFmt::NV12 => y4m::Colorspace::C420paldv,
             _ => {
                 return Err(Error::UnsupportedPixelFormat(this_fmt));
             }
@@ -715,6 +716,11 @@ where
                     // Special case for mono8.
                     Ok(mono8_into_yuv420_planar(frame, forced_block_size))
                 }
+                PixFmt::NV12 => {
+                    // Already YUV 4:2:0, only the chroma planes need to be
+                    // separated.
+                    Ok(nv12_into_yuv420_planar(frame, forced_block_size))
+                }
                 _ => {
                     if let Some(block_size) = forced_block_size {
                         generic_to_c420paldv_macroblocks(frame, block_size)
@@ -728,20 +734,19 @@ where
     }
 }
 
-fn mono8_into_yuv420_planar<FMT>(
+/// Compute the luma and chroma strides and the number of allocated luma and
+/// chroma rows of planar YUV 4:2:0 data for `frame`.
+fn yuv420_planar_layout<FMT>(
     frame: &dyn HasRowChunksExact<FMT>,
     forced_block_size: Option<u32>,
-) -> Y4MFrame
+) -> ((usize, usize), (usize, usize))
 where
     FMT: PixelFormat,
 {
-    // Copy intensity data, other planes
-    // at 128.
     let width: usize = frame.width().try_into().unwrap();
     let height: usize = frame.height().try_into().unwrap();
-    let src_stride = frame.stride();
 
-    let (luma_stride, chroma_stride): (usize, usize) = if let Some(block_size) = forced_block_size {
+    let strides = if let Some(block_size) = forced_block_size {
         let w_mbs = div_ceil(frame.width(), block_size);
         let dest_stride = (w_mbs * block_size).try_into().unwrap();
 
@@ -752,18 +757,95 @@ where
         (width, width / 2)
     };
 
-    let (num_luma_alloc_rows, num_chroma_alloc_rows): (usize, usize) =
-        if let Some(block_size) = forced_block_size {
-            let h_mbs = div_ceil(frame.height(), block_size);
-            let num_dest_alloc_rows = (h_mbs * block_size).try_into().unwrap();
+    let alloc_rows = if let Some(block_size) = forced_block_size {
+        let h_mbs = div_ceil(frame.height(), block_size);
+        let num_dest_alloc_rows = (h_mbs * block_size).try_into().unwrap();
 
-            let chroma_h_mbs = div_ceil(frame.height() / 2, block_size);
-            let num_chroma_alloc_rows = (chroma_h_mbs * block_size).try_into().unwrap();
+        let chroma_h_mbs = div_ceil(frame.height() / 2, block_size);
+        let num_chroma_alloc_rows = (chroma_h_mbs * block_size).try_into().unwrap();
 
-            (num_dest_alloc_rows, num_chroma_alloc_rows)
-        } else {
-            (height, height / 2)
-        };
+        (num_dest_alloc_rows, num_chroma_alloc_rows)
+    } else {
+        (height, height / 2)
+    };
+    (strides, alloc_rows)
+}
+
+fn nv12_into_yuv420_planar<FMT>(
+    frame: &dyn HasRowChunksExact<FMT>,
+    forced_block_size: Option<u32>,
+) -> Y4MFrame
+where
+    FMT: PixelFormat,
+{
+    let width: usize = frame.width().try_into().unwrap();
+    let height: usize = frame.height().try_into().unwrap();
+    let src_stride = frame.stride();
+
+    let ((luma_stride, chroma_stride), (num_luma_alloc_rows, num_chroma_alloc_rows)) =
+        yuv420_planar_layout(frame, forced_block_size);
+
+    let luma_size = luma_stride * num_luma_alloc_rows;
+    let chroma_size = chroma_stride * num_chroma_alloc_rows;
+    let mut data = vec![EMPTY_BYTE; luma_size + chroma_size * 2];
+    let (y_plane_dest, uv_data) = data.split_at_mut(luma_size);
+    let (u_plane_dest, v_plane_dest) = uv_data.split_at_mut(chroma_size);
+
+    // In NV12, the interleaved UV plane follows the Y plane, with the same
+    // stride.
+    let (y_plane_src, uv_plane_src) = frame.image_data().split_at(src_stride * height);
+
+    for (dest_row, src_row) in y_plane_dest
+        .chunks_exact_mut(luma_stride)
+        .zip(y_plane_src.chunks_exact(src_stride))
+    {
+        dest_row[..width].copy_from_slice(&src_row[..width]);
+    }
+
+    let chroma_width = width / 2;
+    for (src_row, (u_row, v_row)) in uv_plane_src.chunks(src_stride).take(height / 2).zip(
+        u_plane_dest
+            .chunks_exact_mut(chroma_stride)
+            .zip(v_plane_dest.chunks_exact_mut(chroma_stride)),
+    ) {
+        for (uv, (u, v)) in src_row[..chroma_width * 2]
+            .chunks_exact(2)
+            .zip(u_row.iter_mut().zip(v_row.iter_mut()))
+        {
+            *u = uv[0];
+            *v = uv[1];
+        }
+    }
+
+    Y4MFrame::new(
+        data,
+        frame.width(),
+        frame.height(),
+        luma_stride.try_into().unwrap(),
+        chroma_stride,
+        num_luma_alloc_rows.try_into().unwrap(),
+        num_chroma_alloc_rows.try_into().unwrap(),
+        false,
+        forced_block_size,
+        y4m::Colorspace::C420paldv,
+    )
+}
+
+fn mono8_into_yuv420_planar<FMT>(
+    frame: &dyn HasRowChunksExact<FMT>,
+    forced_block_size: Option<u32>,
+) -> Y4MFrame
+where
+    FMT: PixelFormat,
+{
+    // Copy intensity data, other planes
+    // at 128.
+    let width: usize = frame.width().try_into().unwrap();
+    let height: usize = frame.height().try_into().unwrap();
+    let src_stride = frame.stride();
+
+    let ((luma_stride, chroma_stride), (num_luma_alloc_rows, num_chroma_alloc_rows)) =
+        yuv420_planar_layout(frame, forced_block_size);
 
     // allocate space for Y U and V planes
     let expected_size =
@@ -175,6 +175,68 @@ impl<'lib> Encoder<'lib> {
         })
     }
 
+    /// Get the configuration of one of the presets P1 to P7 with the given
+    /// tuning.
+    pub fn get_encode_preset_config_ex(
+        &self,
+        encode: GUID,
+        preset: GUID,
+        tuning: TuningInfo,
+    ) -> Result<EncodeConfig, NvencError> {
+        let func = load_func!(self.parent.inner, nvEncGetEncodePresetConfigEx)?;
+
+        let config = MaybeUninit::zeroed();
+        let mut config: NV_ENC_PRESET_CONFIG = unsafe { config.assume_init() };
+
+        config.presetCfg.version = NV_ENC_CONFIG_VER;
+        config.version = NV_ENC_PRESET_CONFIG_VER;
+
+        api_call!(unsafe { func(self.inner.0, encode, preset, tuning.to_c(), &mut config) });
+        Ok(EncodeConfig {
+            config: config.presetCfg,
+        })
+    }
+
+    fn get_encode_caps(&self, encode: GUID, caps_to_query: NV_ENC_CAPS) -> Result<i32, NvencError> {
+        let func = load_func!(self.parent.inner, nvEncGetEncodeCaps)?;
+
+        let params = MaybeUninit::zeroed();
+        let mut params: NV_ENC_CAPS_PARAM = unsafe { params.assume_init() };
+        params.version = NV_ENC_CAPS_PARAM_VER;
+        params.capsToQuery = caps_to_query;
+
+        let mut value = 0;
+        api_call!(unsafe { func(self.inner.0, encode, &mut params, &mut value) });
+        Ok(value)
+    }
+
+    /// The maximum number of B-frames supported by the device for the codec
+    /// `encode`.
+    pub fn max_b_frames(&self, encode: GUID) -> Result<u32, NvencError> {
+        let value = self.get_encode_caps(encode, _NV_ENC_CAPS::NV_ENC_CAPS_NUM_MAX_BFRAMES)?;
+        Ok(value.try_into().unwrap_or(0))
+    }
+
+    /// Whether the device supports the rate control mode `mode` for the codec
+    /// `encode`.
+    pub fn supports_rate_control_mode(
+        &self,
+        encode: GUID,
+        mode: RateControlMode,
+    ) -> Result<bool, NvencError> {
+        let value = self.get_encode_caps(
+            encode,
+            _NV_ENC_CAPS::NV_ENC_CAPS_SUPPORTED_RATECONTROL_MODES,
+        )?;
+        // The value is a bitmask of the modes. Constant QP, which is zero, is
+        // always supported.
+        let mode = mode.to_c();
+        Ok(
+            mode == _NV_ENC_PARAMS_RC_MODE::NV_ENC_PARAMS_RC_CONSTQP
+                || (value as NvInt) & mode != 0,
+        )
+    }
+
     // TODO: return an InitializedEncoder type (forces the encoder to be initialized).
     pub fn initialize(&self, init_params: &InitParams) -> Result<(), NvencError> {
         // There seem to be under-documented minimum width requirements.
@@ -237,15 +299,15 @@ impl<'lib> Encoder<'lib> {
 
     /// Main entry to encode a video frame with a given presentation time stamp.
     ///
-    /// Note that since enablePTD is true, this may return
-    /// NV_ENC_ERR_NEED_MORE_INPUT which should not be treated as a fatal error.
+    /// Note that since enablePTD is true, the frame may be buffered for
+    /// reordering with B-frames, see [EncodeStatus].
     pub fn encode_picture(
         &self,
         input: &InputBuffer,
         output: &OutputBuffer,
         pitch: usize,
         pts: std::time::Duration,
-    ) -> Result<(), NvencError> {
+    ) -> Result<EncodeStatus, NvencError> {
         let func = load_func!(self.parent.inner, nvEncEncodePicture)?;
 
         let params = MaybeUninit::zeroed();
@@ -261,8 +323,12 @@ impl<'lib> Encoder<'lib> {
         params.pictureStruct = _NV_ENC_PIC_STRUCT::NV_ENC_PIC_STRUCT_FRAME;
         params.outputBitstream = output.ptr;
 
-        api_call!(unsafe { func(self.inner.0, &mut params) });
-        Ok(())
+        let status = unsafe { func(self.inner.0, &mut params) };
+        if let Some(encode_status) = EncodeStatus::from_nvenc_status(status) {
+            return Ok(encode_status);
+        }
+        api_call!(status);
+        unreachable!("unsuccessful status {status}");
     }
 
     /// End the encoder stream
@@ -314,6 +380,45 @@ fn from_secs_f64(secs: f64) -> std::time::Duration {
     std::time::Duration::new(whole_secs, subsec_nanos)
 }
 
+/// The result of passing a frame to [Encoder::encode_picture].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeStatus {
+    /// The output bitstreams of all frames passed so far can be locked.
+    Ready,
+    /// The frame was buffered, e.g. for reordering with B-frames. No output
+    /// bitstream may be locked until a later frame returns [Self::Ready] or
+    /// the stream is ended.
+    NeedMoreInput,
+}
+
+impl EncodeStatus {
+    /// Convert the status returned by `nvEncEncodePicture`. Returns `None` if
+    /// encoding failed.
+    fn from_nvenc_status(status: NVENCSTATUS) -> Option<Self> {
+        match status {
+            _NVENCSTATUS::NV_ENC_SUCCESS => Some(Self::Ready),
+            _NVENCSTATUS::NV_ENC_ERR_NEED_MORE_INPUT => Some(Self::NeedMoreInput),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_encode_status() {
+    assert_eq!(
+        EncodeStatus::from_nvenc_status(_NVENCSTATUS::NV_ENC_SUCCESS),
+        Some(EncodeStatus::Ready)
+    );
+    assert_eq!(
+        EncodeStatus::from_nvenc_status(_NVENCSTATUS::NV_ENC_ERR_NEED_MORE_INPUT),
+        Some(EncodeStatus::NeedMoreInput)
+    );
+    assert_eq!(
+        EncodeStatus::from_nvenc_status(_NVENCSTATUS::NV_ENC_ERR_ENCODER_BUSY),
+        None
+    );
+}
+
 fn dur2raw(dur: &std::time::Duration) -> u64 {
     (dur.as_secs_f64() * H264_RATE as f64).round() as u64
 }
@@ -718,6 +823,18 @@ impl InitParamsBuilder {
         self
     }
 
+    /// Set the tuning of the presets P1 to P7.
+    ///
+    /// This must match the tuning used to get the encode configuration with
+    /// [Encoder::get_encode_preset_config_ex].
+    pub fn tuning_info(mut self, tuning: TuningInfo) -> Self {
+        let ptr = self.init_params.as_mut_ptr();
+        unsafe {
+            addr_of_mut!((*ptr).tuningInfo).write(tuning.to_c());
+        }
+        self
+    }
+
     /// Set the frame rate (numerator and denominator)
     ///
     /// Note: "The frame rate has no meaning in NVENC other than deciding rate
@@ -764,10 +881,12 @@ pub struct EncodeConfig {
 
 impl Debug for EncodeConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        write!(f, "{{rcParams.rateControlMode: {}, rcParams.averageBitRate: {}, rcParams.maxBitRate: {} }}",
+        write!(f, "{{rcParams.rateControlMode: {}, rcParams.averageBitRate: {}, rcParams.maxBitRate: {}, gopLength: {}, frameIntervalP: {} }}",
         self.config.rcParams.rateControlMode,
         self.config.rcParams.averageBitRate,
         self.config.rcParams.maxBitRate,
+        self.config.gopLength,
+        self.config.frameIntervalP,
     )
     }
 }
@@ -782,6 +901,30 @@ impl EncodeConfig {
     pub fn set_max_bit_rate(&mut self, value: u32) {
         self.config.rcParams.maxBitRate = value;
     }
+    /// Set the same quantization parameter for all frame types, used with
+    /// [RateControlMode::Constqp].
+    pub fn set_const_qp(&mut self, value: u32) {
+        self.config.rcParams.constQP = NV_ENC_QP {
+            qpInterP: value,
+            qpInterB: value,
+            qpIntra: value,
+        };
+    }
+    /// Set the number of pictures from one I-frame to the next.
+    pub fn set_gop_length(&mut self, value: u32) {
+        self.config.gopLength = value;
+    }
+    /// Set the number of pictures from one reference picture to the next,
+    /// i.e. one more than the number of B-frames.
+    pub fn set_frame_interval_p(&mut self, value: i32) {
+        self.config.frameIntervalP = value;
+    }
+    /// Set the number of pictures from one IDR frame to the next for H264.
+    pub fn set_h264_idr_period(&mut self, value: u32) {
+        let mut h264_config = unsafe { self.config.encodeCodecConfig.h264Config };
+        h264_config.idrPeriod = value;
+        self.config.encodeCodecConfig.h264Config = h264_config;
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -813,3 +956,28 @@ impl RateControlMode {
         }
     }
 }
+
+/// Tuning of the presets P1 to P7.
+#[derive(Clone, Copy, Debug)]
+pub enum TuningInfo {
+    /// Tune for latency tolerant encoding
+    HighQuality,
+    /// Tune for low latency streaming
+    LowLatency,
+    /// Tune for ultra low latency streaming
+    UltraLowLatency,
+    /// Tune for lossless encoding
+    Lossless,
+}
+
+impl TuningInfo {
+    fn to_c(self) -> NvInt {
+        use TuningInfo::*;
+        match self {
+            HighQuality => NV_ENC_TUNING_INFO::NV_ENC_TUNING_INFO_HIGH_QUALITY,
+            LowLatency => NV_ENC_TUNING_INFO::NV_ENC_TUNING_INFO_LOW_LATENCY,
+            UltraLowLatency => NV_ENC_TUNING_INFO::NV_ENC_TUNING_INFO_ULTRA_LOW_LATENCY,
+            Lossless => NV_ENC_TUNING_INFO::NV_ENC_TUNING_INFO_LOSSLESS,
+        }
+    }
+}
@@ -36,6 +36,12 @@ impl<T> Queue<T> {
         }
     }
 
+    /// The number of items returned by [Self::get_available] but not yet by
+    /// [Self::get_pending].
+    pub fn pending_count(&self) -> usize {
+        self.m_uPendingCount
+    }
+
     pub fn get_pending(&mut self) -> Option<&mut T> {
         let sz = self.m_pBuffer.len();
         if self.m_uPendingCount == 0 {
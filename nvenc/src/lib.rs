@@ -9,8 +9,8 @@ pub use error::NvEncError;
 pub use dynlink_cuda::api::CudaDevice;
 pub use dynlink_nvidia_encode::{
     api::{
-        ApiVersion, BufferFormat, Encoder, InitParamsBuilder, InputBuffer, LibNvEncode,
-        OutputBuffer, RateControlMode,
+        ApiVersion, BufferFormat, EncodeStatus, Encoder, InitParamsBuilder, InputBuffer,
+        LibNvEncode, OutputBuffer, RateControlMode, TuningInfo,
     },
     guids::*,
     Queue, NV_ENC_CODEC_H264_GUID, NV_ENC_PRESET_HP_GUID,
@@ -0,0 +1,69 @@
+//! Information about the program which produced a file.
+//!
+//! This is saved in files such as `.braidz` archives and MP4 recordings to
+//! know which version of which program, built how and run where, produced
+//! them.
+
+use serde::{Deserialize, Serialize};
+
+/// The program, build and host which produced a file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// The name of the program, e.g. `braid-run`.
+    pub program: String,
+    /// The version of the program.
+    pub version: String,
+    /// The git revision from which the program was built.
+    pub git_revision: String,
+    /// The build profile, `debug` or `release`.
+    pub build_profile: String,
+    /// The operating system of the host, e.g. `linux`.
+    pub host_os: String,
+    /// The CPU architecture of the host, e.g. `x86_64`.
+    pub host_arch: String,
+    /// The name of the host, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+}
+
+#[cfg(feature = "host-info")]
+impl Provenance {
+    /// The provenance of the running program.
+    ///
+    /// Typically called as `Provenance::current("my-program",
+    /// env!("CARGO_PKG_VERSION"), env!("GIT_HASH"))`.
+    pub fn current(program: &str, version: &str, git_revision: &str) -> Self {
+        let build_profile = if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        };
+        let hostname = gethostname::gethostname().into_string().ok();
+        Self {
+            program: program.to_string(),
+            version: version.to_string(),
+            git_revision: git_revision.trim().to_string(),
+            build_profile: build_profile.to_string(),
+            host_os: std::env::consts::OS.to_string(),
+            host_arch: std::env::consts::ARCH.to_string(),
+            hostname: hostname.filter(|name| !name.is_empty()),
+        }
+    }
+}
+
+#[test]
+fn test_roundtrip() {
+    let provenance = Provenance {
+        program: "braid-run".into(),
+        version: "0.12.0".into(),
+        git_revision: "0123456789abcdef".into(),
+        build_profile: "release".into(),
+        host_os: "linux".into(),
+        host_arch: "x86_64".into(),
+        hostname: None,
+    };
+    let buf = serde_json::to_string(&provenance).unwrap();
+    assert!(!buf.contains("hostname"));
+    let loaded: Provenance = serde_json::from_str(&buf).unwrap();
+    assert_eq!(loaded, provenance);
+}
@@ -0,0 +1,189 @@
+//! Monitor the open files, sockets and memory used by the program.
+//!
+//! When the limit on open files is reached, opening a file or accepting a
+//! connection fails, which can abort a long experiment. By checking the usage
+//! periodically, it can be reduced before this happens.
+
+pub use rust_cam_bui_types::{ResourceLevel, ResourceLimitsConfig, ResourceUsage};
+
+/// The default interval between checks of the resource usage.
+pub const DEFAULT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Query the resources used by this process.
+///
+/// This is currently only implemented on Linux.
+#[cfg(target_os = "linux")]
+pub fn query(cfg: &ResourceLimitsConfig) -> std::io::Result<ResourceUsage> {
+    let mut open_files = 0;
+    let mut open_sockets = 0;
+    for entry in std::fs::read_dir("/proc/self/fd")? {
+        let entry = entry?;
+        open_files += 1;
+        // The descriptor may have been closed since the directory was read.
+        if let Ok(target) = std::fs::read_link(entry.path()) {
+            if target.to_string_lossy().starts_with("socket:") {
+                open_sockets += 1;
+            }
+        }
+    }
+    let max_open_files = std::fs::read_to_string("/proc/self/limits")
+        .ok()
+        .and_then(|limits| parse_max_open_files(&limits));
+    let memory_bytes = std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| parse_vm_rss(&status));
+    Ok(ResourceUsage {
+        open_files,
+        open_sockets,
+        max_open_files,
+        memory_bytes,
+        level: cfg.level(open_files, max_open_files, memory_bytes),
+    })
+}
+
+/// Query the resources used by this process.
+///
+/// This is currently only implemented on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn query(_cfg: &ResourceLimitsConfig) -> std::io::Result<ResourceUsage> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "resource usage can only be determined on Linux",
+    ))
+}
+
+/// Raise the soft limit on open files to the hard limit.
+///
+/// Returns the new limit. The default soft limit of many systems is low
+/// (1024) compared to what a long recording session with many clients can
+/// use.
+#[cfg(target_os = "linux")]
+pub fn raise_open_files_limit() -> std::io::Result<u64> {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `rlim` is a valid pointer to an `rlimit` struct.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if rlim.rlim_cur < rlim.rlim_max {
+        rlim.rlim_cur = rlim.rlim_max;
+        // SAFETY: `rlim` is a valid pointer to an `rlimit` struct.
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &rlim) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    // `rlim_t` is not 64 bits on all platforms.
+    #[allow(clippy::unnecessary_cast)]
+    Ok(rlim.rlim_cur as u64)
+}
+
+/// Raise the soft limit on open files to the hard limit.
+///
+/// This is currently only implemented on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn raise_open_files_limit() -> std::io::Result<u64> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "the limit on open files can only be changed on Linux",
+    ))
+}
+
+/// Parse the soft limit on open files from the contents of `/proc/self/limits`.
+fn parse_max_open_files(limits: &str) -> Option<u64> {
+    let line = limits.lines().find(|l| l.starts_with("Max open files"))?;
+    let soft_limit = line["Max open files".len()..].split_whitespace().next()?;
+    // "unlimited" is not parsed and thus treated as unknown.
+    soft_limit.parse().ok()
+}
+
+/// Parse the resident memory, in bytes, from the contents of
+/// `/proc/self/status`.
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let mut fields = line["VmRSS:".len()..].split_whitespace();
+    let value: u64 = fields.next()?.parse().ok()?;
+    match fields.next()? {
+        "kB" => Some(value * 1024),
+        _ => None,
+    }
+}
+
+/// Check the resource usage every `interval`, calling `on_usage` with the
+/// result.
+///
+/// A message is logged whenever the level changes. If the usage cannot be
+/// determined, an error is logged and `on_usage` is not called. This function
+/// never returns and is intended to be spawned as a task.
+pub async fn watch<F>(cfg: ResourceLimitsConfig, interval: std::time::Duration, mut on_usage: F)
+where
+    F: FnMut(ResourceUsage),
+{
+    let mut prev_level = ResourceLevel::Ok;
+    let mut had_error = false;
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let usage = match query(&cfg) {
+            Ok(usage) => usage,
+            Err(e) => {
+                // Only log the first of a series of errors.
+                if !had_error {
+                    tracing::error!("could not determine resource usage: {e}");
+                }
+                had_error = true;
+                continue;
+            }
+        };
+        had_error = false;
+        if usage.level != prev_level {
+            match usage.level {
+                ResourceLevel::Ok => {
+                    tracing::info!("resource usage normal: {}", usage.summary());
+                }
+                ResourceLevel::High => {
+                    tracing::warn!("high resource usage: {}", usage.summary());
+                }
+                ResourceLevel::Critical => {
+                    tracing::error!(
+                        "resource usage near limit, reducing usage: {}",
+                        usage.summary()
+                    );
+                }
+            }
+            prev_level = usage.level;
+        }
+        on_usage(usage);
+    }
+}
+
+#[test]
+fn test_parse_proc() {
+    let limits = "Limit                     Soft Limit           Hard Limit           Units     \n\
+        Max cpu time              unlimited            unlimited            seconds   \n\
+        Max open files            1024                 524288               files     \n";
+    assert_eq!(parse_max_open_files(limits), Some(1024));
+    let unlimited = "Max open files            unlimited            unlimited            files\n";
+    assert_eq!(parse_max_open_files(unlimited), None);
+
+    let status = "Name:\tstrand-cam\nVmPeak:\t  200000 kB\nVmRSS:\t   12345 kB\n";
+    assert_eq!(parse_vm_rss(status), Some(12345 * 1024));
+    assert_eq!(parse_vm_rss("Name:\tstrand-cam\n"), None);
+}
+
+#[test]
+fn test_level() {
+    let cfg = ResourceLimitsConfig {
+        critical_memory_bytes: Some(1000),
+        ..Default::default()
+    };
+    assert_eq!(cfg.level(100, Some(1024), None), ResourceLevel::Ok);
+    assert_eq!(cfg.level(800, Some(1024), None), ResourceLevel::High);
+    assert_eq!(cfg.level(1000, Some(1024), None), ResourceLevel::Critical);
+    assert_eq!(cfg.level(1000, None, Some(10)), ResourceLevel::Ok);
+    assert_eq!(
+        cfg.level(10, Some(1024), Some(2000)),
+        ResourceLevel::Critical
+    );
+}
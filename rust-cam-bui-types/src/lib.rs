@@ -41,3 +41,284 @@ pub struct ClockModel {
     pub residuals: f64,
     pub n_measurements: u64,
 }
+
+/// Thresholds of free disk space at which recording is affected.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiskSpaceConfig {
+    /// Warn when the free space falls below this many bytes.
+    #[serde(default = "default_warn_below_bytes")]
+    pub warn_below_bytes: u64,
+    /// Stop all recordings when the free space falls below this many bytes.
+    #[serde(default = "default_stop_below_bytes")]
+    pub stop_below_bytes: u64,
+}
+
+fn default_warn_below_bytes() -> u64 {
+    10_000_000_000
+}
+
+fn default_stop_below_bytes() -> u64 {
+    1_000_000_000
+}
+
+impl Default for DiskSpaceConfig {
+    fn default() -> Self {
+        Self {
+            warn_below_bytes: default_warn_below_bytes(),
+            stop_below_bytes: default_stop_below_bytes(),
+        }
+    }
+}
+
+impl DiskSpaceConfig {
+    /// Classify an amount of free space according to the thresholds.
+    pub fn level(&self, available_bytes: u64) -> DiskSpaceLevel {
+        if available_bytes < self.stop_below_bytes {
+            DiskSpaceLevel::Critical
+        } else if available_bytes < self.warn_below_bytes {
+            DiskSpaceLevel::Low
+        } else {
+            DiskSpaceLevel::Ok
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum DiskSpaceLevel {
+    /// Free space is above the warning threshold.
+    Ok,
+    /// Free space is below the warning threshold.
+    Low,
+    /// Free space is below the threshold at which recordings are stopped.
+    Critical,
+}
+
+/// The free space on the disk to which recordings are saved.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct DiskSpaceStatus {
+    /// The directory in which recordings are saved.
+    pub path: String,
+    pub available_bytes: u64,
+    pub total_bytes: u64,
+    pub level: DiskSpaceLevel,
+}
+
+impl DiskSpaceStatus {
+    /// A human readable description of the free space.
+    pub fn summary(&self) -> String {
+        format!(
+            "{:.1} GB free of {:.1} GB on \"{}\"",
+            self.available_bytes as f64 / 1e9,
+            self.total_bytes as f64 / 1e9,
+            self.path
+        )
+    }
+}
+
+/// Soft limits on the operating system resources used by the program.
+///
+/// Above the warning level, a warning is shown. Above the critical level,
+/// resource usage is reduced where possible, e.g. by closing idle connections
+/// and postponing the start of new files.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceLimitsConfig {
+    /// Warn when this fraction of the limit on open files is in use.
+    #[serde(default = "default_warn_open_files_fraction")]
+    pub warn_open_files_fraction: f64,
+    /// Reduce resource usage when this fraction of the limit on open files is
+    /// in use.
+    #[serde(default = "default_critical_open_files_fraction")]
+    pub critical_open_files_fraction: f64,
+    /// Warn when the resident memory exceeds this many bytes.
+    #[serde(default)]
+    pub warn_memory_bytes: Option<u64>,
+    /// Reduce resource usage when the resident memory exceeds this many bytes.
+    #[serde(default)]
+    pub critical_memory_bytes: Option<u64>,
+}
+
+fn default_warn_open_files_fraction() -> f64 {
+    0.7
+}
+
+fn default_critical_open_files_fraction() -> f64 {
+    0.9
+}
+
+impl Default for ResourceLimitsConfig {
+    fn default() -> Self {
+        Self {
+            warn_open_files_fraction: default_warn_open_files_fraction(),
+            critical_open_files_fraction: default_critical_open_files_fraction(),
+            warn_memory_bytes: None,
+            critical_memory_bytes: None,
+        }
+    }
+}
+
+impl ResourceLimitsConfig {
+    /// Classify resource usage according to the thresholds.
+    ///
+    /// `max_open_files` and `memory_bytes` are `None` if unknown, in which
+    /// case the corresponding thresholds are not checked.
+    pub fn level(
+        &self,
+        open_files: u64,
+        max_open_files: Option<u64>,
+        memory_bytes: Option<u64>,
+    ) -> ResourceLevel {
+        let open_files_fraction = max_open_files
+            .filter(|max| *max > 0)
+            .map(|max| open_files as f64 / max as f64);
+        let exceeds = |fraction: f64, bytes: Option<u64>| {
+            open_files_fraction.is_some_and(|x| x >= fraction)
+                || matches!((memory_bytes, bytes), (Some(m), Some(b)) if m >= b)
+        };
+        if exceeds(
+            self.critical_open_files_fraction,
+            self.critical_memory_bytes,
+        ) {
+            ResourceLevel::Critical
+        } else if exceeds(self.warn_open_files_fraction, self.warn_memory_bytes) {
+            ResourceLevel::High
+        } else {
+            ResourceLevel::Ok
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum ResourceLevel {
+    /// Resource usage is below the warning thresholds.
+    Ok,
+    /// Resource usage is above a warning threshold.
+    High,
+    /// Resource usage is above a threshold at which it is reduced.
+    Critical,
+}
+
+/// The operating system resources used by the program.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// Number of open file descriptors, including sockets.
+    pub open_files: u64,
+    /// Number of open sockets.
+    pub open_sockets: u64,
+    /// The limit on open file descriptors, if known.
+    pub max_open_files: Option<u64>,
+    /// Resident memory, if known.
+    pub memory_bytes: Option<u64>,
+    pub level: ResourceLevel,
+}
+
+impl ResourceUsage {
+    /// A human readable description of the resource usage.
+    pub fn summary(&self) -> String {
+        let limit = match self.max_open_files {
+            Some(max) => format!(" of {max}"),
+            None => String::new(),
+        };
+        let mut summary = format!(
+            "{}{limit} open files ({} sockets)",
+            self.open_files, self.open_sockets
+        );
+        if let Some(memory_bytes) = self.memory_bytes {
+            summary.push_str(&format!(", {:.1} GB memory", memory_bytes as f64 / 1e9));
+        }
+        summary
+    }
+}
+
+/// The time from the exposure of a frame until it was sent on each output of
+/// Strand Camera, in milliseconds.
+///
+/// Each value is the largest latency over the most recent measurement
+/// interval. `None` if no frame was sent on that output.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct OutputLatency {
+    /// Frames sent for display in the browser UI.
+    pub preview_msec: Option<f64>,
+    /// Image moments and ROI statistics sent by UDP ("ImOps").
+    pub im_ops_msec: Option<f64>,
+    /// Frames encoded and written to the MP4 file. Frames saved from the
+    /// post-trigger buffer are older by up to the duration of the buffer.
+    pub mp4_msec: Option<f64>,
+    /// Frames written to the v4l2 output device.
+    pub v4l_out_msec: Option<f64>,
+}
+
+impl OutputLatency {
+    /// A human readable description of the latencies, e.g. `"preview 12.1 ms,
+    /// MP4 45.0 ms"`. Empty if no frame was sent on any output.
+    pub fn summary(&self) -> String {
+        [
+            ("preview", self.preview_msec),
+            ("ImOps", self.im_ops_msec),
+            ("MP4", self.mp4_msec),
+            ("v4l2", self.v4l_out_msec),
+        ]
+        .iter()
+        .filter_map(|(name, msec)| msec.map(|msec| format!("{name} {msec:.1} ms")))
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+/// The result of one check of the startup self-test.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct SelfTestCheck {
+    pub name: String,
+    pub passed: bool,
+    /// A human readable description of what was measured.
+    pub message: String,
+}
+
+/// The state of the startup self-test of a camera.
+///
+/// Recordings are refused until the self-test has passed or a failure was
+/// dismissed.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct SelfTestReport {
+    /// The results of the checks. `None` while the self-test is running.
+    pub checks: Option<Vec<SelfTestCheck>>,
+    /// Set when a failure was dismissed to allow recording anyway.
+    pub failure_dismissed: bool,
+}
+
+impl SelfTestReport {
+    /// The report of a self-test which has not finished.
+    pub fn running() -> Self {
+        Self {
+            checks: None,
+            failure_dismissed: false,
+        }
+    }
+
+    /// Whether all checks passed. `None` while the self-test is running.
+    pub fn passed(&self) -> Option<bool> {
+        self.checks
+            .as_ref()
+            .map(|checks| checks.iter().all(|check| check.passed))
+    }
+
+    /// The reason recording is not allowed, if it is not.
+    pub fn blocks_recording(&self) -> Option<String> {
+        match self.passed() {
+            None => Some("the startup self-test is still running".into()),
+            Some(false) if !self.failure_dismissed => Some("the startup self-test failed".into()),
+            Some(_) => None,
+        }
+    }
+
+    /// A human readable description of the result.
+    pub fn summary(&self) -> String {
+        match (self.passed(), self.failure_dismissed) {
+            (None, _) => "Self-test running.".into(),
+            (Some(true), _) => "Self-test passed.".into(),
+            (Some(false), false) => "Self-test failed.".into(),
+            (Some(false), true) => "Self-test failed (dismissed).".into(),
+        }
+    }
+}
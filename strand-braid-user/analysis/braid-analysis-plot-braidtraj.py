@@ -0,0 +1,37 @@
+#!/usr/bin/env python
+"""Load and plot a single trajectory exported with `braidz-cli --export-traj`.
+
+The checksum of the trajectory is not verified here. Use
+`braidz-cli <filename>.braidtraj` to verify a file.
+"""
+import sys
+import json
+import pandas as pd
+import matplotlib.pyplot as plt
+
+def load_braidtraj(filename):
+    with open(filename, mode='r') as fd:
+        traj = json.load(fd)
+    if traj['format'] != 'braidtraj':
+        raise ValueError('not a braidtraj file')
+    df = pd.DataFrame(traj['rows'])
+    # Missing timestamps are saved as null.
+    df['timestamp'] = df['timestamp'].astype(float)
+    return traj, df
+
+filename = sys.argv[1]
+traj, df = load_braidtraj(filename)
+source = traj['source']
+print('obj_id %d from %s (sha256: %s)' % (traj['obj_id'], source['filename'], source['sha256']))
+
+dimnames = ['x','y','z']
+fig, axes = plt.subplots(nrows=len(dimnames),sharex=True)
+
+for ax,dimname in zip(axes,dimnames):
+    ax.plot(df['frame'], df[dimname], '-')
+    ax.set_ylabel(dimname)
+
+axes[0].set_title('obj_id %d' % traj['obj_id'])
+axes[-1].set_xlabel('frame')
+
+plt.show()
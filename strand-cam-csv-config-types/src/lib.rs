@@ -34,4 +34,7 @@ pub struct FullCfgFview2_0_26 {
     pub created_at: chrono::DateTime<chrono::Local>,
     pub csv_rate_limit: Option<f32>,
     pub object_detection_cfg: flydra_feature_detector_types::ImPtDetectCfg,
+    /// The program, build and host which saved the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<provenance::Provenance>,
 }
@@ -0,0 +1,46 @@
+//! List the Strand Camera instances on the local network.
+
+use clap::Parser;
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Cli {
+    /// Seconds to wait for answers.
+    #[arg(short, long, default_value_t = 3.0)]
+    timeout: f64,
+
+    /// Print JSON instead of a table.
+    #[arg(long)]
+    json: bool,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    let nodes = strand_cam_discovery::discover(std::time::Duration::from_secs_f64(cli.timeout))?;
+
+    if cli.json {
+        println!("{}", serde_json::to_string_pretty(&nodes)?);
+        return Ok(());
+    }
+    if nodes.is_empty() {
+        eprintln!("No Strand Camera found.");
+        return Ok(());
+    }
+    println!(
+        "{:<24} {:<12} {:<20} {:<12} {:<20} URL",
+        "CAMERA", "VENDOR", "MODEL", "SERIAL", "HOST"
+    );
+    for node in nodes.iter() {
+        let info = &node.info;
+        println!(
+            "{:<24} {:<12} {:<20} {:<12} {:<20} {}",
+            info.camera_name,
+            info.vendor,
+            info.model,
+            info.serial,
+            node.hostname,
+            node.urls.join(" ")
+        );
+    }
+    Ok(())
+}
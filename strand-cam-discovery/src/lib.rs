@@ -0,0 +1,319 @@
+//! Advertisement and discovery of Strand Camera instances with mDNS.
+//!
+//! Each Strand Camera advertises its browser UI on the local network as a
+//! DNS-SD service of type [SERVICE_TYPE]. The TXT record of the service
+//! contains the camera name, vendor, model and serial number and the version
+//! of Strand Camera (see [CameraNodeInfo]). The access token of the browser UI
+//! is not advertised.
+//!
+//! [Browser] finds the advertised instances, for example for Braid or the
+//! `strand-cam-ls` utility.
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::{Deserialize, Serialize};
+
+/// The DNS-SD service type of the Strand Camera browser UI.
+pub const SERVICE_TYPE: &str = "_strandcam._tcp.local.";
+
+/// Maximum length of a DNS label, in bytes.
+const MAX_LABEL_LEN: usize = 63;
+
+const TXT_NAME: &str = "name";
+const TXT_VENDOR: &str = "vendor";
+const TXT_MODEL: &str = "model";
+const TXT_SERIAL: &str = "serial";
+const TXT_VERSION: &str = "version";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("mDNS error: {0}")]
+    Mdns(#[from] mdns_sd::Error),
+    #[error("not advertising loopback address {0}")]
+    LoopbackAddress(SocketAddr),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The camera of a Strand Camera instance.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CameraNodeInfo {
+    pub camera_name: String,
+    pub vendor: String,
+    pub model: String,
+    pub serial: String,
+    /// The version of Strand Camera.
+    pub version: String,
+}
+
+/// A Strand Camera instance found on the network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CameraNode {
+    #[serde(flatten)]
+    pub info: CameraNodeInfo,
+    /// The host running Strand Camera.
+    pub hostname: String,
+    /// The URLs of the browser UI, without access token.
+    pub urls: Vec<String>,
+}
+
+/// Name of the service instance for a camera.
+///
+/// Dots separate labels in DNS names, so they are replaced. The name is
+/// truncated to the maximum length of a DNS label. The unmodified camera name
+/// is in the TXT record.
+fn instance_name(camera_name: &str) -> String {
+    let mut name = String::new();
+    for c in camera_name.chars().map(|c| if c == '.' { '-' } else { c }) {
+        if name.len() + c.len_utf8() > MAX_LABEL_LEN {
+            break;
+        }
+        name.push(c);
+    }
+    name
+}
+
+/// The camera node advertised in `service`, if it is a Strand Camera.
+fn node_from_service(service: &ServiceInfo) -> Option<CameraNode> {
+    let txt = |key| {
+        service
+            .get_property_val_str(key)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let camera_name = service.get_property_val_str(TXT_NAME)?.to_string();
+    let port = service.get_port();
+    // IPv6 link-local addresses need a scope, so they cannot be used in URLs.
+    let mut addrs: Vec<&IpAddr> = service
+        .get_addresses()
+        .iter()
+        .filter(|ip| !matches!(ip, IpAddr::V6(v6) if v6.segments()[0] & 0xffc0 == 0xfe80))
+        .collect();
+    addrs.sort();
+    let urls = addrs
+        .into_iter()
+        .map(|ip| format!("http://{}/", SocketAddr::new(*ip, port)))
+        .collect();
+    Some(CameraNode {
+        info: CameraNodeInfo {
+            camera_name,
+            vendor: txt(TXT_VENDOR),
+            model: txt(TXT_MODEL),
+            serial: txt(TXT_SERIAL),
+            version: txt(TXT_VERSION),
+        },
+        hostname: service.get_hostname().trim_end_matches('.').to_string(),
+        urls,
+    })
+}
+
+/// Advertisement of the browser UI of a Strand Camera instance.
+///
+/// The advertisement is withdrawn when this is dropped.
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Advertisement {
+    /// Advertise the browser UI listening at `addr`.
+    ///
+    /// If the IP address of `addr` is unspecified (e.g. `0.0.0.0`), all
+    /// addresses of the host are advertised. Loopback addresses are not
+    /// reachable from other hosts and are refused.
+    pub fn new(info: &CameraNodeInfo, addr: SocketAddr) -> Result<Self> {
+        if addr.ip().is_loopback() {
+            return Err(Error::LoopbackAddress(addr));
+        }
+        let hostname = format!("{}.local.", gethostname::gethostname().to_string_lossy());
+        let properties = [
+            (TXT_NAME, info.camera_name.as_str()),
+            (TXT_VENDOR, info.vendor.as_str()),
+            (TXT_MODEL, info.model.as_str()),
+            (TXT_SERIAL, info.serial.as_str()),
+            (TXT_VERSION, info.version.as_str()),
+        ];
+        let ips: Vec<IpAddr> = if addr.ip().is_unspecified() {
+            vec![]
+        } else {
+            vec![addr.ip()]
+        };
+        let mut service = ServiceInfo::new(
+            SERVICE_TYPE,
+            &instance_name(&info.camera_name),
+            &hostname,
+            ips.as_slice(),
+            addr.port(),
+            &properties[..],
+        )?;
+        if addr.ip().is_unspecified() {
+            service = service.enable_addr_auto();
+        }
+        let fullname = service.get_fullname().to_string();
+        let daemon = ServiceDaemon::new()?;
+        daemon.register(service)?;
+        Ok(Self { daemon, fullname })
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        // Announce that the service is gone before stopping the daemon.
+        if let Ok(status) = self.daemon.unregister(&self.fullname) {
+            let _ = status.recv_timeout(Duration::from_secs(1));
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Event of a [Browser].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiscoveryEvent {
+    /// A Strand Camera instance was found or its advertisement changed.
+    Found(CameraNode),
+    /// The Strand Camera instance with this camera name is gone.
+    Removed(String),
+}
+
+/// Finds Strand Camera instances on the network.
+pub struct Browser {
+    daemon: ServiceDaemon,
+    receiver: mdns_sd::Receiver<ServiceEvent>,
+    /// Camera names by service instance name.
+    camera_names: BTreeMap<String, String>,
+}
+
+impl Browser {
+    pub fn new() -> Result<Self> {
+        let daemon = ServiceDaemon::new()?;
+        let receiver = daemon.browse(SERVICE_TYPE)?;
+        Ok(Self {
+            daemon,
+            receiver,
+            camera_names: BTreeMap::new(),
+        })
+    }
+
+    fn handle(&mut self, event: ServiceEvent) -> Option<DiscoveryEvent> {
+        match event {
+            ServiceEvent::ServiceResolved(service) => {
+                let node = node_from_service(&service)?;
+                self.camera_names.insert(
+                    service.get_fullname().to_string(),
+                    node.info.camera_name.clone(),
+                );
+                Some(DiscoveryEvent::Found(node))
+            }
+            ServiceEvent::ServiceRemoved(_, fullname) => self
+                .camera_names
+                .remove(&fullname)
+                .map(DiscoveryEvent::Removed),
+            _ => None,
+        }
+    }
+
+    /// Wait for the next event. Returns `None` if browsing stopped.
+    pub async fn next(&mut self) -> Option<DiscoveryEvent> {
+        loop {
+            let event = self.receiver.recv_async().await.ok()?;
+            if let Some(event) = self.handle(event) {
+                return Some(event);
+            }
+        }
+    }
+
+    /// Block until the next event or until `deadline`.
+    pub fn next_before(&mut self, deadline: Instant) -> Option<DiscoveryEvent> {
+        loop {
+            let event = self.receiver.recv_deadline(deadline).ok()?;
+            if let Some(event) = self.handle(event) {
+                return Some(event);
+            }
+        }
+    }
+}
+
+impl Drop for Browser {
+    fn drop(&mut self) {
+        let _ = self.daemon.stop_browse(SERVICE_TYPE);
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Find the Strand Camera instances advertised during `duration`.
+///
+/// This blocks for `duration`. The result is sorted by camera name.
+pub fn discover(duration: Duration) -> Result<Vec<CameraNode>> {
+    let deadline = Instant::now() + duration;
+    let mut browser = Browser::new()?;
+    let mut nodes = BTreeMap::new();
+    while let Some(event) = browser.next_before(deadline) {
+        match event {
+            DiscoveryEvent::Found(node) => {
+                nodes.insert(node.info.camera_name.clone(), node);
+            }
+            DiscoveryEvent::Removed(camera_name) => {
+                nodes.remove(&camera_name);
+            }
+        }
+    }
+    Ok(nodes.into_values().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instance_name() {
+        assert_eq!(instance_name("Basler-22005677"), "Basler-22005677");
+        assert_eq!(instance_name("cam.1"), "cam-1");
+        let long = "ä".repeat(40);
+        let name = instance_name(&long);
+        assert_eq!(name.len(), 62);
+        assert!(long.starts_with(&name));
+    }
+
+    #[test]
+    fn test_node_from_service() {
+        let properties = [
+            (TXT_NAME, "cam.1"),
+            (TXT_VENDOR, "Basler"),
+            (TXT_MODEL, "a2A1920-160umBAS"),
+            (TXT_SERIAL, "40022057"),
+            (TXT_VERSION, "0.12.0"),
+        ];
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            &instance_name("cam.1"),
+            "rig1.local.",
+            "192.168.1.20",
+            3440,
+            &properties[..],
+        )
+        .unwrap();
+        let node = node_from_service(&service).unwrap();
+        assert_eq!(node.info.camera_name, "cam.1");
+        assert_eq!(node.info.serial, "40022057");
+        assert_eq!(node.hostname, "rig1.local");
+        assert_eq!(node.urls, vec!["http://192.168.1.20:3440/".to_string()]);
+
+        // Services without camera name are not from Strand Camera.
+        let properties = [(TXT_VENDOR, "other")];
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            "other",
+            "rig1.local.",
+            "",
+            80,
+            &properties[..],
+        )
+        .unwrap();
+        assert!(node_from_service(&service).is_none());
+    }
+}
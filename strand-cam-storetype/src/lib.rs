@@ -1,11 +1,16 @@
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
 
-use rust_cam_bui_types::RecordingPath;
+use rust_cam_bui_types::{
+    DiskSpaceStatus, OutputLatency, RecordingPath, ResourceUsage, SelfTestReport,
+};
 use serde::{Deserialize, Serialize};
 
 use http_video_streaming_types::{CircleParams, Shape};
 
-use ci2_remote_control::{BitrateSelection, CodecSelection, RecordingFrameRate, TagFamily};
+use ci2_remote_control::{
+    BitrateSelection, CodecSelection, ComplianceWatermarkConfig, Mp4SegmentConfig,
+    RecordingFrameRate, RecordingLabel, TagFamily,
+};
 use flydra_feature_detector_types::ImPtDetectCfg;
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -47,6 +52,10 @@ pub struct StoreType {
     pub is_recording_fmf: Option<RecordingPath>,
     /// is saving UFMF file
     pub is_recording_ufmf: Option<RecordingPath>,
+    /// When set, all recordings will be stopped automatically at this time.
+    pub recording_stop_time: Option<chrono::DateTime<chrono::Utc>>,
+    /// Label and tags of recordings started from now on.
+    pub recording_label: RecordingLabel,
     pub format_str_mp4: String,
     pub format_str: String,
     pub format_str_ufmf: String,
@@ -59,6 +68,12 @@ pub struct StoreType {
     pub mp4_codec: CodecSelection,
     /// CUDA device number (only used if using nvidia encoder)
     pub mp4_cuda_device: String,
+    /// Whether a half-width, half-height proxy MP4 is saved with each MP4.
+    pub mp4_proxy_enabled: bool,
+    /// Limits at which MP4 recordings are split into a new file.
+    pub mp4_segment_config: Mp4SegmentConfig,
+    /// If set, a watermark drawn into the frames of MP4 recordings.
+    pub compliance_watermark: Option<ComplianceWatermarkConfig>,
     pub gain_auto: Option<ci2_types::AutoMode>,
     pub gain: RangedValue,
     pub exposure_auto: Option<ci2_types::AutoMode>,
@@ -79,7 +94,24 @@ pub struct StoreType {
     // used only with image-tracker crate
     /// Whether object detection is currently used.
     pub is_doing_object_detection: bool,
+    /// Whether the updates of the background model are suspended.
+    pub is_background_frozen: bool,
+    /// When set, the updates of the background model resume automatically at
+    /// this time.
+    pub background_unfreeze_time: Option<chrono::DateTime<chrono::Utc>>,
     pub measured_fps: f32,
+    /// Frame buffer sizes in use by the camera backend.
+    ///
+    /// None if the camera backend does not support setting these.
+    pub frame_buffer_config: Option<ci2_types::FrameBufferConfig>,
+    /// In-camera image post-processing in use.
+    ///
+    /// None if the camera does not support post-processing.
+    pub post_processing: Option<ci2_types::PostProcessingConfig>,
+    /// How full the camera backend frame buffers were at the last measurement.
+    pub frame_buffer_saturation: Option<ci2_types::FrameBufferSaturation>,
+    /// The time from exposure until frames were sent on each output.
+    pub output_latency: OutputLatency,
     /// is saving object detection CSV file
     pub is_saving_im_pt_detect_csv: Option<RecordingPath>,
     // used only with image-tracker crate
@@ -103,6 +135,27 @@ pub struct StoreType {
     pub im_ops_state: ImOpsState,
     pub format_str_apriltag_csv: String,
     pub had_frame_processing_error: bool,
+    /// Free space on the disk to which recordings are saved.
+    pub disk_space: Option<DiskSpaceStatus>,
+    /// Set when recordings were stopped because the disk was almost full.
+    pub disk_space_error: Option<String>,
+    /// Open files, sockets and memory used by Strand Camera.
+    pub resource_usage: Option<ResourceUsage>,
+    /// Whether acquisition is stopped because the camera is not used.
+    pub acquisition_idle: bool,
+    /// The camera clock relative to the host clock, if clock skew correction
+    /// is enabled and enough measurements were made.
+    pub device_clock_model: Option<ci2_remote_control::DeviceClockModel>,
+    /// The startup self-test, if enabled.
+    pub self_test: Option<SelfTestReport>,
+    /// Filename of the most recently saved bug report bundle.
+    pub bug_report_filename: Option<String>,
+    /// The names of the configuration profiles which can be applied.
+    pub profiles: Vec<String>,
+    /// The most recently applied configuration profile.
+    pub active_profile: Option<String>,
+    /// Why applying the most recent configuration profile failed, if it did.
+    pub profile_error: Option<String>,
     /// The camera calibration (does not contain potential information about water)
     pub camera_calibration: Option<mvg::Camera<f64>>,
 }
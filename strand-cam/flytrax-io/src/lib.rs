@@ -140,6 +140,7 @@ fn config25_upgrade(
         created_at: orig.created_at,
         csv_rate_limit: orig.csv_rate_limit,
         object_detection_cfg: orig.object_detection_cfg,
+        provenance: None,
     }
 }
 
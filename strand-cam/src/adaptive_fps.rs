@@ -0,0 +1,157 @@
+//! Adaptive frame rate: slow down acquisition while nothing is detected.
+//!
+//! To save disk space and power when the arena is empty, an
+//! [AdaptiveFrameRate] lowers the frame rate limit of the camera to
+//! [AdaptiveFpsConfig::idle_fps] once no point was detected for
+//! [AdaptiveFpsConfig::idle_after]. On the first detection, the frame rate
+//! limit in effect before is restored. Because slowing down requires a long
+//! time without detections but speeding up happens immediately, the frame
+//! rate does not switch back and forth with intermittent detections.
+//!
+//! While idle, an object entering the arena is noticed up to one idle frame
+//! period later. The frame rate limit is set with
+//! [CamArg::SetFrameRateLimit] and [CamArg::SetFrameRateLimitEnabled] and is
+//! also shown in the browser UI. Adaptive frame rate is not used when the
+//! camera is synchronized by Braid.
+
+use std::time::{Duration, Instant};
+
+use eyre::Result;
+use tracing::{debug, info};
+
+use ci2_remote_control::CamArg;
+use strand_cam_storetype::StoreType;
+
+/// Configuration of the adaptive frame rate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptiveFpsConfig {
+    /// The frame rate limit while nothing is detected, in frames per second.
+    pub idle_fps: f64,
+    /// The frame rate is lowered after no point was detected for this long.
+    pub idle_after: Duration,
+}
+
+impl Default for AdaptiveFpsConfig {
+    fn default() -> Self {
+        Self {
+            idle_fps: 5.0,
+            idle_after: Duration::from_secs(30),
+        }
+    }
+}
+
+/// The frame rate limit of the camera before going idle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FrameRateLimit {
+    enabled: bool,
+    fps: f64,
+}
+
+/// Lowers the frame rate while nothing is detected.
+pub(crate) struct AdaptiveFrameRate {
+    cfg: AdaptiveFpsConfig,
+    cam_args_tx: tokio::sync::mpsc::Sender<CamArg>,
+    last_detection: Instant,
+    /// If idle, the frame rate limit to restore.
+    idle: Option<FrameRateLimit>,
+}
+
+impl AdaptiveFrameRate {
+    pub(crate) fn new(
+        cfg: AdaptiveFpsConfig,
+        cam_args_tx: tokio::sync::mpsc::Sender<CamArg>,
+    ) -> Result<Self> {
+        if cfg.idle_fps.is_nan() || cfg.idle_fps <= 0.0 {
+            eyre::bail!("idle frame rate must be larger than zero");
+        }
+        info!(
+            "Adaptive frame rate: {} fps after {} seconds without detections.",
+            cfg.idle_fps,
+            cfg.idle_after.as_secs_f64()
+        );
+        Ok(Self {
+            cfg,
+            cam_args_tx,
+            last_detection: Instant::now(),
+            idle: None,
+        })
+    }
+
+    /// Update the frame rate limit given the number of points detected in a
+    /// frame. `store` holds the current frame rate limit.
+    pub(crate) fn process(&mut self, n_points: usize, store: Option<&StoreType>) {
+        let now = Instant::now();
+        if n_points > 0 {
+            self.last_detection = now;
+            if let Some(restore) = self.idle {
+                if self.send(restore) {
+                    info!(
+                        "Object detected, restoring frame rate limit ({}).",
+                        describe(restore)
+                    );
+                    self.idle = None;
+                }
+            }
+            return;
+        }
+
+        if self.idle.is_some() || now.duration_since(self.last_detection) < self.cfg.idle_after {
+            return;
+        }
+        let Some(current) = store.and_then(|store| {
+            store
+                .frame_rate_limit
+                .as_ref()
+                .map(|ranged| FrameRateLimit {
+                    enabled: store.frame_rate_limit_enabled,
+                    fps: ranged.current,
+                })
+        }) else {
+            debug!("frame rate limit not known, not lowering frame rate");
+            return;
+        };
+        if current.enabled && current.fps <= self.cfg.idle_fps {
+            // Already slow enough.
+            return;
+        }
+        let idle = FrameRateLimit {
+            enabled: true,
+            fps: self.cfg.idle_fps,
+        };
+        if self.send(idle) {
+            info!(
+                "Nothing detected for {} seconds, lowering frame rate limit to {} fps \
+                (was {}).",
+                self.cfg.idle_after.as_secs_f64(),
+                self.cfg.idle_fps,
+                describe(current)
+            );
+            self.idle = Some(current);
+        }
+    }
+
+    /// Request the frame rate limit. Returns `false` if the request could not
+    /// be queued, in which case it is tried again with the next frame.
+    fn send(&self, limit: FrameRateLimit) -> bool {
+        // Set the rate before enabling the limit so that the camera never
+        // runs limited at the previous rate.
+        if self.cam_args_tx.capacity() < 2 {
+            return false;
+        }
+        self.cam_args_tx
+            .try_send(CamArg::SetFrameRateLimit(limit.fps))
+            .is_ok()
+            && self
+                .cam_args_tx
+                .try_send(CamArg::SetFrameRateLimitEnabled(limit.enabled))
+                .is_ok()
+    }
+}
+
+fn describe(limit: FrameRateLimit) -> String {
+    if limit.enabled {
+        format!("{} fps", limit.fps)
+    } else {
+        "disabled".to_string()
+    }
+}
@@ -0,0 +1,167 @@
+//! Recent per-frame pipeline state, saved on request as a bug report bundle.
+//!
+//! A small record of the state of the image processing pipeline is kept for
+//! each of the most recent frames. When the user saves a bug report, these
+//! records are written together with the current configuration to a zip file
+//! which can be attached to an issue.
+
+use std::{
+    collections::VecDeque,
+    hash::{Hash, Hasher},
+    io::Write,
+    path::Path,
+};
+
+use eyre::Result;
+use serde::Serialize;
+
+use flydra_feature_detector_types::ImPtDetectCfg;
+use flydra_types::ImageProcessingSteps;
+use strand_cam_storetype::StoreType;
+
+/// The number of frames for which the pipeline state is kept.
+pub(crate) const BUG_REPORT_HISTORY_LEN: usize = 1000;
+
+/// The filename template of bug report bundles.
+pub(crate) const BUG_REPORT_FILENAME_TEMPLATE: &str =
+    "strand-cam-bug-report%Y%m%d_%H%M%S_{CAMNAME}.zip";
+
+/// The state of the pipeline when processing a single frame.
+#[derive(Debug, Clone, Serialize)]
+struct PipelineSnapshot {
+    framenumber: u64,
+    /// Host timestamp of the frame, in seconds since the UNIX epoch.
+    host_timestamp: f64,
+    /// Hash of the feature detection settings, to spot changes.
+    settings_hash: String,
+    diff_threshold: u8,
+    use_cmp: bool,
+    is_doing_object_detection: bool,
+    /// Number of frames since the background model last changed.
+    ///
+    /// `None` if the background model has not been initialized yet.
+    frames_since_background_change: Option<u64>,
+    /// Number of detected features, if detection was run on this frame.
+    n_points: Option<usize>,
+    /// Time from acquisition of the frame until processing finished.
+    latency_msec: f64,
+}
+
+/// The outcome of feature detection on a single frame.
+#[cfg_attr(not(feature = "flydra_feat_detect"), allow(dead_code))]
+pub(crate) struct DetectionSummary {
+    pub(crate) n_points: usize,
+    pub(crate) image_processing_steps: ImageProcessingSteps,
+}
+
+/// A circular buffer of recent [PipelineSnapshot]s.
+pub(crate) struct PipelineHistory {
+    entries: VecDeque<PipelineSnapshot>,
+    frames_since_background_change: Option<u64>,
+    /// Cached hash of the most recently seen settings.
+    settings: Option<(ImPtDetectCfg, String)>,
+}
+
+impl PipelineHistory {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: VecDeque::with_capacity(BUG_REPORT_HISTORY_LEN),
+            frames_since_background_change: None,
+            settings: None,
+        }
+    }
+
+    /// Record the state of the pipeline after processing a frame.
+    pub(crate) fn push(
+        &mut self,
+        framenumber: u64,
+        host_timestamp: chrono::DateTime<chrono::Utc>,
+        cfg: &ImPtDetectCfg,
+        is_doing_object_detection: bool,
+        detection: Option<DetectionSummary>,
+    ) {
+        let now = chrono::Utc::now();
+
+        if let Some(detection) = &detection {
+            let background_changed = detection.image_processing_steps.intersects(
+                ImageProcessingSteps::BGINIT
+                    | ImageProcessingSteps::BGSTARTUP
+                    | ImageProcessingSteps::BGCLEARED
+                    | ImageProcessingSteps::BGUPDATE,
+            );
+            if background_changed {
+                self.frames_since_background_change = Some(0);
+            } else if let Some(n) = self.frames_since_background_change.as_mut() {
+                *n += 1;
+            }
+        }
+
+        let settings_hash = match &self.settings {
+            Some((prev_cfg, hash)) if prev_cfg == cfg => hash.clone(),
+            _ => {
+                let hash = settings_hash(cfg);
+                self.settings = Some((cfg.clone(), hash.clone()));
+                hash
+            }
+        };
+
+        if self.entries.len() == BUG_REPORT_HISTORY_LEN {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(PipelineSnapshot {
+            framenumber,
+            host_timestamp: datetime_conversion::datetime_to_f64(&host_timestamp),
+            settings_hash,
+            diff_threshold: cfg.diff_threshold,
+            use_cmp: cfg.use_cmp,
+            is_doing_object_detection,
+            frames_since_background_change: self.frames_since_background_change,
+            n_points: detection.map(|d| d.n_points),
+            latency_msec: (now - host_timestamp)
+                .num_microseconds()
+                .unwrap_or(i64::MAX) as f64
+                / 1000.0,
+        });
+    }
+
+    /// Write a bug report bundle to `path`.
+    ///
+    /// The zip file contains `pipeline_state.jsonl` with one line per recent
+    /// frame, `config.yaml` with the current state and settings of Strand
+    /// Camera and `version.txt`.
+    pub(crate) fn write_bug_report(&self, path: &Path, store: &StoreType) -> Result<()> {
+        let fd = std::fs::File::create(path)?;
+        let mut zip = zip::ZipWriter::new(fd);
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+        zip.start_file("pipeline_state.jsonl", options)?;
+        for entry in self.entries.iter() {
+            serde_json::to_writer(&mut zip, entry)?;
+            zip.write_all(b"\n")?;
+        }
+
+        zip.start_file("config.yaml", options)?;
+        serde_yaml::to_writer(&mut zip, store)?;
+
+        zip.start_file("version.txt", options)?;
+        writeln!(
+            zip,
+            "{} {}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        )?;
+
+        zip.finish()?;
+        Ok(())
+    }
+}
+
+fn settings_hash(cfg: &ImPtDetectCfg) -> String {
+    // `ImPtDetectCfg` contains floats and so does not implement `Hash`. Hash
+    // its serialized form instead.
+    let buf = serde_json::to_string(cfg).unwrap();
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    buf.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
@@ -2,7 +2,7 @@ use std::path::PathBuf;
 
 use clap::{arg, FromArgMatches};
 
-use clap::{Arg, ArgAction, Args};
+use clap::{Arg, ArgAction, Args, ValueEnum};
 
 use crate::{run_strand_cam_app, BraidArgs, StandaloneArgs, StandaloneOrBraid, StrandCamArgs};
 
@@ -29,11 +29,28 @@ where
         );
     }
 
-    let args = parse_args(app_name).with_context(|| "parsing args".to_string())?;
+    let args = match parse_args(app_name).with_context(|| "parsing args".to_string())? {
+        CliCommand::Run(args) => args,
+        CliCommand::ListCameras => {
+            use ci2::{CameraInfo, CameraModule};
+            for cam_info in mymod.camera_infos()?.iter() {
+                println!("{}", cam_info.name());
+            }
+            return Ok(mymod);
+        }
+    };
 
     run_strand_cam_app(mymod, args, app_name)
 }
 
+/// What to do after parsing the command line.
+enum CliCommand {
+    /// Run the camera app.
+    Run(StrandCamArgs),
+    /// Print the names of the detected cameras, one per line, and exit.
+    ListCameras,
+}
+
 fn parse_led_box_device(matches: &clap::ArgMatches) -> Option<String> {
     matches.get_one::<String>("led_box_device").map(Into::into)
 }
@@ -50,6 +67,10 @@ fn get_tracker_cfg(_matches: &clap::ArgMatches) -> Result<crate::ImPtDetectCfgSo
 // the existing builder API.
 #[derive(Args, Debug)]
 struct DerivedArgs {
+    /// List the names of the detected cameras, one per line, and exit.
+    #[arg(long)]
+    list_cameras: bool,
+
     #[cfg(target_os = "linux")]
     /// If set, output a copy of the video stream on this v4l2 device (e.g. `/dev/video0`)
     #[arg(long)]
@@ -58,9 +79,282 @@ struct DerivedArgs {
     /// If set, .mp4 videos and log files are saved to this directory.
     #[arg(long)]
     data_dir: Option<PathBuf>,
+
+    /// If set, save a video of the most recent frames and the logs to the
+    /// `diagnostics` subdirectory of the data directory when frames are
+    /// dropped or no frames are received.
+    #[arg(long)]
+    diagnostics: bool,
+
+    /// Duration without frames, in milliseconds, after which diagnostics are
+    /// saved. Default: 2000.
+    #[arg(long, requires = "diagnostics")]
+    diagnostics_stall_msec: Option<u64>,
+
+    /// Number of frames before the problem to save. Default: 100.
+    #[arg(long, requires = "diagnostics")]
+    diagnostics_pre_event_frames: Option<usize>,
+
+    /// Minimum duration, in seconds, between saved diagnostics. Default: 600.
+    #[arg(long, requires = "diagnostics")]
+    diagnostics_min_interval_secs: Option<u64>,
+
+    /// Maximum frame rate of the MJPEG preview at `/preview.mjpeg`.
+    #[arg(long, default_value_t = 5.0)]
+    preview_max_fps: f64,
+
+    /// Maximum width, in pixels, of the MJPEG preview. Larger frames are
+    /// downsampled.
+    #[arg(long, default_value_t = 640)]
+    preview_max_width: u32,
+
+    /// JPEG quality (1-100) of the MJPEG preview.
+    #[arg(long, default_value_t = 70, value_parser = clap::value_parser!(u8).range(1..=100))]
+    preview_jpeg_quality: u8,
+
+    /// If set, move the region of interest (ROI) on the sensor to follow the
+    /// detected object. The ROI size is given by the camera settings. The ROI
+    /// offset of each frame is saved to the data directory.
+    #[arg(long)]
+    roi_follow: bool,
+
+    /// ROI offsets are multiples of this many pixels. Default: 4.
+    #[arg(long, requires = "roi_follow")]
+    roi_follow_offset_step: Option<u32>,
+
+    /// Minimum distance, in pixels, by which the ROI is moved. Default: 16.
+    #[arg(long, requires = "roi_follow")]
+    roi_follow_min_move: Option<u32>,
+
+    /// TOML file with named regions of interest whose statistics (mean, max
+    /// and centroid) are computed for each frame while ImOps detection is
+    /// enabled and sent to the ImOps destination.
+    #[arg(long)]
+    roi_stats_config: Option<PathBuf>,
+
+    /// If set, assign the CUDA device for NVENC encoding from a pool of
+    /// devices shared by several cameras. Cameras with consecutive slot numbers
+    /// are assigned different devices. Set by Braid if configured.
+    #[arg(long)]
+    nvenc_pool_slot: Option<usize>,
+
+    /// Comma-separated indices of the CUDA devices in the NVENC pool. Default:
+    /// all devices.
+    #[arg(long, requires = "nvenc_pool_slot", value_delimiter = ',')]
+    nvenc_pool_devices: Vec<u32>,
+
+    /// Number of failed encoding sessions on a CUDA device after which the
+    /// camera is reassigned to another device of the NVENC pool. Default: 1.
+    #[arg(long, requires = "nvenc_pool_slot")]
+    nvenc_pool_max_failures: Option<u32>,
+
+    /// If set, lower the frame rate limit while nothing is detected and
+    /// restore it on the first detection. Not used with Braid.
+    #[arg(long)]
+    adaptive_fps: bool,
+
+    /// Frame rate limit while nothing is detected. Default: 5.
+    #[arg(long, requires = "adaptive_fps")]
+    adaptive_fps_idle_fps: Option<f64>,
+
+    /// Seconds without detections after which the frame rate is lowered.
+    /// Default: 30.
+    #[arg(long, requires = "adaptive_fps")]
+    adaptive_fps_idle_after_secs: Option<u64>,
+
+    /// If set, stop acquisition while the camera is not used and restart it
+    /// on demand. The camera is used while a client is connected or anything
+    /// is recorded. Not used with Braid.
+    #[arg(long)]
+    idle_mode: bool,
+
+    /// Minutes without use after which acquisition is stopped. Default: 10.
+    #[arg(long, requires = "idle_mode")]
+    idle_mode_after_minutes: Option<u64>,
+
+    /// If set, periodically measure the camera clock against the host clock
+    /// and timestamp frames with the camera clock converted to host time.
+    /// This aligns recordings of several untriggered cameras on one host. Not
+    /// used with Braid.
+    #[arg(long)]
+    clock_skew_correction: bool,
+
+    /// Seconds between measurements of the camera clock. Default: 10.
+    #[arg(long, requires = "clock_skew_correction")]
+    clock_skew_interval_secs: Option<u64>,
+
+    /// If set, run a self-test at startup (frame rate, detection and disk
+    /// speed). Recording is refused until it passed.
+    #[arg(long)]
+    self_test: bool,
+
+    /// Do not advertise the browser UI on the local network with mDNS.
+    #[arg(long)]
+    no_mdns: bool,
+
+    /// When running with Braid, use the point detection configuration in this
+    /// TOML file instead of the `point_detection_config` of the camera in the
+    /// Braid configuration. Braid flags the camera as locally overridden.
+    #[arg(long)]
+    point_detection_config: Option<PathBuf>,
+
+    /// If set, draw the date and time, the camera name and this facility
+    /// string into every frame of MP4 recordings. May be empty.
+    #[arg(long)]
+    watermark: Option<String>,
+
+    /// Corner of the image in which the watermark is drawn.
+    #[arg(long, value_enum, default_value_t = WatermarkCorner::TopLeft, requires = "watermark")]
+    watermark_position: WatermarkCorner,
+
+    /// Opacity of the watermark, from 0 to 1. Default: 0.8.
+    #[arg(long, requires = "watermark")]
+    watermark_opacity: Option<f32>,
+
+    /// Height of the watermark text, in pixels. Default: 24.
+    #[arg(long, requires = "watermark")]
+    watermark_font_size: Option<f32>,
+
+    /// `strftime` format of the date and time in the watermark. Default:
+    /// `%Y-%m-%d %H:%M:%S%.3f %z`.
+    #[arg(long, requires = "watermark")]
+    watermark_datetime_format: Option<String>,
+
+    /// Number of frames over which the frame rate is measured. Default: 100.
+    #[arg(long, requires = "self_test")]
+    self_test_frames: Option<usize>,
+
+    /// Expected frame rate. Default: from the trigger configuration or the
+    /// frame rate limit.
+    #[arg(long, requires = "self_test")]
+    self_test_fps: Option<f64>,
+
+    /// Maximum relative error of the frame rate. Default: 0.05.
+    #[arg(long, requires = "self_test")]
+    self_test_max_fps_error: Option<f64>,
+
+    /// FMF file whose first frame must contain between
+    /// `--self-test-min-points` and `--self-test-max-points` detections.
+    #[arg(long, requires = "self_test")]
+    self_test_pattern: Option<PathBuf>,
+
+    /// Minimum number of points detected in the test pattern. Default: 1.
+    #[arg(long, requires = "self_test_pattern")]
+    self_test_min_points: Option<usize>,
+
+    /// Maximum number of points detected in the test pattern. Default: 10.
+    #[arg(long, requires = "self_test_pattern")]
+    self_test_max_points: Option<usize>,
+
+    /// Minimum disk write speed, in MB per second. Default: 50.
+    #[arg(long, requires = "self_test")]
+    self_test_min_disk_mbps: Option<f64>,
+
+    /// If set, upload finished MP4 recordings to this S3-compatible server,
+    /// e.g. `https://s3.eu-central-1.amazonaws.com`.
+    #[arg(
+        long,
+        requires = "upload_s3_bucket",
+        conflicts_with = "upload_webdav_url"
+    )]
+    upload_s3_endpoint: Option<String>,
+
+    /// Bucket into which recordings are uploaded.
+    #[arg(long, requires = "upload_s3_endpoint")]
+    upload_s3_bucket: Option<String>,
+
+    /// Region of the S3 bucket.
+    #[arg(long, default_value = "us-east-1")]
+    upload_s3_region: String,
+
+    /// Access key ID for S3 uploads.
+    #[arg(long, env = "AWS_ACCESS_KEY_ID", requires = "upload_s3_endpoint")]
+    upload_s3_access_key_id: Option<String>,
+
+    /// Secret access key for S3 uploads.
+    #[arg(long, env = "AWS_SECRET_ACCESS_KEY", hide_env_values = true)]
+    upload_s3_secret_access_key: Option<String>,
+
+    /// If set, upload finished MP4 recordings into this WebDAV collection.
+    #[arg(long)]
+    upload_webdav_url: Option<String>,
+
+    /// User name for WebDAV uploads.
+    #[arg(
+        long,
+        env = "STRAND_CAM_WEBDAV_USERNAME",
+        requires = "upload_webdav_url"
+    )]
+    upload_webdav_username: Option<String>,
+
+    /// Password for WebDAV uploads.
+    #[arg(long, env = "STRAND_CAM_WEBDAV_PASSWORD", hide_env_values = true)]
+    upload_webdav_password: Option<String>,
+
+    /// Prefix of the object keys of S3 uploads, e.g. `rig1/`.
+    #[arg(long, default_value = "", requires = "upload_s3_endpoint")]
+    upload_prefix: String,
+
+    /// Size of the parts of S3 multipart uploads, in bytes. At least 5 MiB.
+    /// Default: 8 MiB.
+    #[arg(long)]
+    upload_chunk_bytes: Option<u64>,
+
+    /// If set, limit the upload rate to this many bytes per second.
+    #[arg(long)]
+    upload_max_bytes_per_sec: Option<u64>,
+
+    /// If set, delete local recordings this many hours after their upload.
+    #[arg(long)]
+    upload_delete_after_hours: Option<f64>,
+
+    #[cfg(feature = "flydra_feat_detect")]
+    /// TOML file with the regions, areas and aspect ratios of accepted
+    /// detections. Other detections are not sent to Braid or saved.
+    #[arg(long)]
+    detection_filter_config: Option<PathBuf>,
+
+    #[cfg(feature = "dnn-detector")]
+    /// Detect points with this ONNX model instead of background subtraction.
+    #[arg(long)]
+    dnn_model: Option<PathBuf>,
+
+    #[cfg(feature = "dnn-detector")]
+    /// Text file with the labels of the classes of the ONNX model, one per line.
+    #[arg(long, requires = "dnn_model")]
+    dnn_labels: Option<PathBuf>,
+
+    #[cfg(feature = "dnn-detector")]
+    /// Points found by the ONNX model with lower confidence are ignored.
+    #[arg(long, default_value_t = 0.5)]
+    dnn_min_confidence: f32,
+
+    #[cfg(feature = "dnn-detector")]
+    /// Only use points found by the ONNX model with this label. May be repeated.
+    #[arg(long = "dnn-keep-label")]
+    dnn_keep_labels: Vec<String>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum WatermarkCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
 }
 
-fn parse_args(app_name: &str) -> Result<StrandCamArgs> {
+impl From<WatermarkCorner> for ci2_remote_control::WatermarkPosition {
+    fn from(orig: WatermarkCorner) -> Self {
+        match orig {
+            WatermarkCorner::TopLeft => Self::TopLeft,
+            WatermarkCorner::TopRight => Self::TopRight,
+            WatermarkCorner::BottomLeft => Self::BottomLeft,
+            WatermarkCorner::BottomRight => Self::BottomRight,
+        }
+    }
+}
+
+fn parse_args(app_name: &str) -> Result<CliCommand> {
     let cli_args: Vec<String> = std::env::args().collect();
 
     let arg_default_box: Box<StrandCamArgs> = Default::default();
@@ -93,6 +387,59 @@ fn parse_args(app_name: &str) -> Result<StrandCamArgs> {
                     .default_value(&*arg_default.mp4_filename_template)
                     .help("Set the initial filename template of the destination to be saved to."),
             )
+            .arg(
+                Arg::new("mp4_segment_duration_secs")
+                    .long("mp4-segment-duration-secs")
+                    .value_parser(clap::value_parser!(f64))
+                    .help("Start a new MP4 file after this many seconds of recording."),
+            )
+            .arg(
+                Arg::new("mp4_segment_size_bytes")
+                    .long("mp4-segment-size-bytes")
+                    .value_parser(clap::value_parser!(u64))
+                    .help("Start a new MP4 file once the current file reaches this size."),
+            )
+            .arg(
+                Arg::new("disk_space_warn_bytes")
+                    .long("disk-space-warn-bytes")
+                    .value_parser(clap::value_parser!(u64))
+                    .help("Warn when free disk space falls below this many bytes."),
+            )
+            .arg(
+                Arg::new("disk_space_stop_bytes")
+                    .long("disk-space-stop-bytes")
+                    .value_parser(clap::value_parser!(u64))
+                    .help("Stop all recordings when free disk space falls below this many bytes."),
+            )
+            .arg(
+                Arg::new("warn_memory_bytes")
+                    .long("warn-memory-bytes")
+                    .value_parser(clap::value_parser!(u64))
+                    .help("Warn when Strand Camera uses more than this many bytes of memory."),
+            )
+            .arg(
+                Arg::new("critical_memory_bytes")
+                    .long("critical-memory-bytes")
+                    .value_parser(clap::value_parser!(u64))
+                    .help("Close idle connections and postpone new MP4 segments when Strand Camera uses more than this many bytes of memory."),
+            )
+            .arg(
+                Arg::new("mqtt_broker")
+                    .long("mqtt-broker")
+                    .help("Publish live detections and recording state to this MQTT broker (host:port)."),
+            )
+            .arg(
+                Arg::new("mqtt_topic_prefix")
+                    .long("mqtt-topic-prefix")
+                    .help("Prefix of the MQTT topics to which events are published.")
+                    .default_value(crate::event_publisher::DEFAULT_MQTT_TOPIC_PREFIX),
+            )
+            .arg(
+                Arg::new("osc_destination")
+                    .long("osc-destination")
+                    .value_parser(clap::value_parser!(std::net::SocketAddr))
+                    .help("Send live detections and recording state as OSC messages to this UDP address."),
+            )
             .arg(
                 Arg::new("fmf_filename_template")
                     .long("fmf_filename_template")
@@ -115,6 +462,11 @@ fn parse_args(app_name: &str) -> Result<StrandCamArgs> {
                     .long("camera-settings-filename")
                     .help("Path to file with camera settings which will be loaded."),
             )
+            .arg(
+                Arg::new("user_set")
+                    .long("user-set")
+                    .help("Name of the user set stored on the camera, e.g. UserSet1, which will be loaded before the other settings. (incompatible with braid)."),
+            )
             .arg(
                 Arg::new("http_server_addr")
                     .long("http-server-addr")
@@ -141,6 +493,41 @@ fn parse_args(app_name: &str) -> Result<StrandCamArgs> {
                         .env("STRAND_CAM_COOKIE_SECRET")
                         .action(ArgAction::Set),
                 )
+                .arg(
+                    Arg::new("n_buffer_frames")
+                        .long("n-buffer-frames")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Number of frame buffers allocated for the camera driver. (incompatible with braid)."),
+                )
+                .arg(
+                    Arg::new("n_channel_frames")
+                        .long("n-channel-frames")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Number of frames which can wait in the camera backend. (incompatible with braid)."),
+                )
+                .arg(
+                    Arg::new("denoise")
+                        .long("denoise")
+                        .value_parser(clap::value_parser!(f64))
+                        .help("Strength of in-camera noise suppression, if supported by the camera. (incompatible with braid)."),
+                )
+                .arg(
+                    Arg::new("sharpen")
+                        .long("sharpen")
+                        .value_parser(clap::value_parser!(f64))
+                        .help("Strength of in-camera sharpening, if supported by the camera. (incompatible with braid)."),
+                )
+                .arg(
+                    Arg::new("profile_dir")
+                        .long("profile-dir")
+                        .help("Directory with configuration profiles saved as <name>.toml. (incompatible with braid)."),
+                )
+                .arg(
+                    Arg::new("profile")
+                        .long("profile")
+                        .requires("profile_dir")
+                        .help("Name of the configuration profile applied at startup. (incompatible with braid)."),
+                )
                 .arg(
                     Arg::new("force_camera_sync_mode")
                         .long("force_camera_sync_mode")
@@ -192,6 +579,10 @@ fn parse_args(app_name: &str) -> Result<StrandCamArgs> {
         parser.get_matches_from(cli_args)
     };
 
+    if matches.get_flag("list_cameras") {
+        return Ok(CliCommand::ListCameras);
+    }
+
     let secret = matches
         .get_one::<String>("strand_cam_cookie_secret")
         .cloned()
@@ -202,6 +593,42 @@ fn parse_args(app_name: &str) -> Result<StrandCamArgs> {
         .ok_or_else(|| eyre!("expected mp4_filename_template"))?
         .to_string();
 
+    let mp4_segment_config = ci2_remote_control::Mp4SegmentConfig {
+        max_duration_secs: matches.get_one::<f64>("mp4_segment_duration_secs").copied(),
+        max_size_bytes: matches.get_one::<u64>("mp4_segment_size_bytes").copied(),
+    };
+
+    let disk_space_config = {
+        let defaults = disk_space_watcher::DiskSpaceConfig::default();
+        disk_space_watcher::DiskSpaceConfig {
+            warn_below_bytes: matches
+                .get_one::<u64>("disk_space_warn_bytes")
+                .copied()
+                .unwrap_or(defaults.warn_below_bytes),
+            stop_below_bytes: matches
+                .get_one::<u64>("disk_space_stop_bytes")
+                .copied()
+                .unwrap_or(defaults.stop_below_bytes),
+        }
+    };
+
+    let resource_limits_config = resource_watcher::ResourceLimitsConfig {
+        warn_memory_bytes: matches.get_one::<u64>("warn_memory_bytes").copied(),
+        critical_memory_bytes: matches.get_one::<u64>("critical_memory_bytes").copied(),
+        ..Default::default()
+    };
+
+    let event_publisher_config = crate::EventPublisherConfig {
+        mqtt_broker: matches.get_one::<String>("mqtt_broker").cloned(),
+        mqtt_topic_prefix: matches
+            .get_one::<String>("mqtt_topic_prefix")
+            .ok_or_else(|| eyre!("expected mqtt_topic_prefix"))?
+            .to_string(),
+        osc_destination: matches
+            .get_one::<std::net::SocketAddr>("osc_destination")
+            .copied(),
+    };
+
     let fmf_filename_template = matches
         .get_one::<String>("fmf_filename_template")
         .ok_or_else(|| eyre!("expected fmf_filename_template"))?
@@ -216,6 +643,7 @@ fn parse_args(app_name: &str) -> Result<StrandCamArgs> {
     let camera_settings_filename = matches
         .get_one::<String>("camera_settings_filename")
         .map(PathBuf::from);
+    let user_set = matches.get_one::<String>("user_set").cloned();
 
     #[cfg(feature = "flydratrax")]
     let camera_xml_calibration = matches
@@ -275,7 +703,14 @@ fn parse_args(app_name: &str) -> Result<StrandCamArgs> {
             "pixel_format",
             "JWT_SECRET",
             "camera_settings_filename",
+            "user_set",
             "http_server_addr",
+            "n_buffer_frames",
+            "n_channel_frames",
+            "denoise",
+            "sharpen",
+            "profile_dir",
+            "profile",
         ] {
             // These values are not relevant or are set via
             // [flydra_types::RemoteCameraInfoResponse].
@@ -298,16 +733,61 @@ fn parse_args(app_name: &str) -> Result<StrandCamArgs> {
             eyre!("camera name must be set using command-line argument when running with braid")
         })?;
 
+        let point_detection_config = match matches.get_one::<PathBuf>("point_detection_config") {
+            Some(path) => {
+                let buf = std::fs::read_to_string(path)
+                    .with_context(|| format!("reading {}", path.display()))?;
+                let cfg =
+                    toml::from_str(&buf).with_context(|| format!("parsing {}", path.display()))?;
+                Some(cfg)
+            }
+            None => None,
+        };
+
         StandaloneOrBraid::Braid(BraidArgs {
             braid_url,
             camera_name,
+            point_detection_config,
         })
     } else {
         // not braid
+        if matches.contains_id("point_detection_config") {
+            eyre::bail!("'point_detection_config' can only be set when running with braid.");
+        }
         let pixel_format = matches.get_one::<String>("pixel_format").map(Into::into);
         let force_camera_sync_mode = !matches!(matches.get_count("force_camera_sync_mode"), 0);
         let software_limit_framerate = flydra_types::StartSoftwareFrameRateLimit::NoChange;
 
+        let n_buffer_frames = matches.get_one::<usize>("n_buffer_frames").copied();
+        let n_channel_frames = matches.get_one::<usize>("n_channel_frames").copied();
+        let frame_buffer_config = if n_buffer_frames.is_some() || n_channel_frames.is_some() {
+            let defaults = ci2::FrameBufferConfig::default();
+            Some(ci2::FrameBufferConfig {
+                n_buffer_frames: n_buffer_frames.unwrap_or(defaults.n_buffer_frames),
+                n_channel_frames: n_channel_frames.unwrap_or(defaults.n_channel_frames),
+            })
+        } else {
+            None
+        };
+
+        let denoise = matches.get_one::<f64>("denoise").copied();
+        let sharpen = matches.get_one::<f64>("sharpen").copied();
+        let post_processing = if denoise.is_some() || sharpen.is_some() {
+            Some(ci2::PostProcessingConfig { denoise, sharpen })
+        } else {
+            None
+        };
+
+        let profile_dir = match matches.get_one::<String>("profile_dir") {
+            Some(profile_dir) => Some(PathBuf::from(
+                shellexpand::full(profile_dir)
+                    .map_err(|e| eyre!("{}", e))?
+                    .to_string(),
+            )),
+            None => None,
+        };
+        let profile = matches.get_one::<String>("profile").cloned();
+
         let acquisition_duration_allowed_imprecision_msec =
             flydra_types::DEFAULT_ACQUISITION_DURATION_ALLOWED_IMPRECISION_MSEC;
 
@@ -323,6 +803,11 @@ fn parse_args(app_name: &str) -> Result<StrandCamArgs> {
             software_limit_framerate,
             acquisition_duration_allowed_imprecision_msec,
             camera_settings_filename,
+            user_set,
+            frame_buffer_config,
+            post_processing,
+            profile_dir,
+            profile,
             #[cfg(feature = "flydra_feat_detect")]
             tracker_cfg_src,
             http_server_addr,
@@ -352,14 +837,256 @@ fn parse_args(app_name: &str) -> Result<StrandCamArgs> {
         .map_err(|err| err.exit())
         .unwrap();
 
+    let diagnostics_config = if derived_matches.diagnostics {
+        let defaults = crate::DiagnosticsConfig::default();
+        Some(crate::DiagnosticsConfig {
+            stall_timeout: derived_matches
+                .diagnostics_stall_msec
+                .map(std::time::Duration::from_millis)
+                .unwrap_or(defaults.stall_timeout),
+            pre_event_frames: derived_matches
+                .diagnostics_pre_event_frames
+                .unwrap_or(defaults.pre_event_frames),
+            min_interval: derived_matches
+                .diagnostics_min_interval_secs
+                .map(std::time::Duration::from_secs)
+                .unwrap_or(defaults.min_interval),
+        })
+    } else {
+        None
+    };
+
+    let mjpeg_config = crate::MjpegConfig {
+        max_fps: derived_matches.preview_max_fps,
+        max_width: derived_matches.preview_max_width,
+        jpeg_quality: derived_matches.preview_jpeg_quality,
+    };
+
+    let roi_follow_config = if derived_matches.roi_follow {
+        let defaults = crate::RoiFollowConfig::default();
+        Some(crate::RoiFollowConfig {
+            offset_step: derived_matches
+                .roi_follow_offset_step
+                .unwrap_or(defaults.offset_step),
+            min_move: derived_matches
+                .roi_follow_min_move
+                .unwrap_or(defaults.min_move),
+        })
+    } else {
+        None
+    };
+
+    let roi_stats_config = match &derived_matches.roi_stats_config {
+        Some(path) => {
+            let buf = std::fs::read_to_string(path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            let cfg =
+                toml::from_str(&buf).with_context(|| format!("parsing {}", path.display()))?;
+            Some(cfg)
+        }
+        None => None,
+    };
+
+    let nvenc_pool = derived_matches.nvenc_pool_slot.map(|slot| {
+        let defaults = crate::NvencPoolConfig::default();
+        crate::NvencPoolArgs {
+            config: crate::NvencPoolConfig {
+                devices: derived_matches.nvenc_pool_devices.clone(),
+                max_failures: derived_matches
+                    .nvenc_pool_max_failures
+                    .unwrap_or(defaults.max_failures),
+            },
+            slot,
+        }
+    });
+
+    let adaptive_fps_config = if derived_matches.adaptive_fps {
+        let defaults = crate::AdaptiveFpsConfig::default();
+        Some(crate::AdaptiveFpsConfig {
+            idle_fps: derived_matches
+                .adaptive_fps_idle_fps
+                .unwrap_or(defaults.idle_fps),
+            idle_after: derived_matches
+                .adaptive_fps_idle_after_secs
+                .map(std::time::Duration::from_secs)
+                .unwrap_or(defaults.idle_after),
+        })
+    } else {
+        None
+    };
+
+    let idle_mode_config = if derived_matches.idle_mode {
+        let defaults = crate::IdleModeConfig::default();
+        Some(crate::IdleModeConfig {
+            idle_after: derived_matches
+                .idle_mode_after_minutes
+                .map(|minutes| std::time::Duration::from_secs(minutes * 60))
+                .unwrap_or(defaults.idle_after),
+        })
+    } else {
+        None
+    };
+
+    let clock_skew_config = if derived_matches.clock_skew_correction {
+        let defaults = crate::ClockSkewConfig::default();
+        Some(crate::ClockSkewConfig {
+            interval: derived_matches
+                .clock_skew_interval_secs
+                .map(std::time::Duration::from_secs)
+                .unwrap_or(defaults.interval),
+            ..defaults
+        })
+    } else {
+        None
+    };
+
+    let upload_target = match (
+        &derived_matches.upload_s3_endpoint,
+        &derived_matches.upload_webdav_url,
+    ) {
+        (Some(endpoint), _) => Some(crate::UploadTarget::S3(crate::S3Target {
+            endpoint: endpoint.clone(),
+            region: derived_matches.upload_s3_region.clone(),
+            bucket: derived_matches.upload_s3_bucket.clone().unwrap(),
+            prefix: derived_matches.upload_prefix.clone(),
+            access_key_id: derived_matches
+                .upload_s3_access_key_id
+                .clone()
+                .ok_or_else(|| eyre!("no access key ID for S3 uploads"))?,
+            secret_access_key: derived_matches
+                .upload_s3_secret_access_key
+                .clone()
+                .ok_or_else(|| eyre!("no secret access key for S3 uploads"))?,
+        })),
+        (None, Some(url)) => Some(crate::UploadTarget::WebDav(crate::WebDavTarget {
+            url: url.clone(),
+            username: derived_matches.upload_webdav_username.clone(),
+            password: derived_matches.upload_webdav_password.clone(),
+        })),
+        (None, None) => None,
+    };
+    let upload_config = upload_target.map(|target| crate::UploadConfig {
+        target,
+        chunk_size: derived_matches
+            .upload_chunk_bytes
+            .unwrap_or(crate::uploader::DEFAULT_CHUNK_SIZE),
+        max_bytes_per_sec: derived_matches.upload_max_bytes_per_sec,
+        delete_local_after: derived_matches
+            .upload_delete_after_hours
+            .map(|hours| std::time::Duration::from_secs_f64(hours * 3600.0)),
+    });
+
+    let self_test_config = if derived_matches.self_test {
+        let defaults = crate::SelfTestConfig::default();
+        Some(crate::SelfTestConfig {
+            n_frames: derived_matches
+                .self_test_frames
+                .unwrap_or(defaults.n_frames),
+            expected_fps: derived_matches.self_test_fps,
+            max_fps_error: derived_matches
+                .self_test_max_fps_error
+                .unwrap_or(defaults.max_fps_error),
+            test_pattern: derived_matches.self_test_pattern.clone(),
+            min_test_pattern_points: derived_matches
+                .self_test_min_points
+                .unwrap_or(defaults.min_test_pattern_points),
+            max_test_pattern_points: derived_matches
+                .self_test_max_points
+                .unwrap_or(defaults.max_test_pattern_points),
+            min_disk_bytes_per_sec: derived_matches
+                .self_test_min_disk_mbps
+                .map(|mbps| mbps * 1e6)
+                .unwrap_or(defaults.min_disk_bytes_per_sec),
+            ..defaults
+        })
+    } else {
+        None
+    };
+
+    let compliance_watermark = match &derived_matches.watermark {
+        Some(facility) => {
+            let defaults = ci2_remote_control::ComplianceWatermarkConfig::default();
+            let opacity = derived_matches
+                .watermark_opacity
+                .unwrap_or(defaults.opacity);
+            if !(0.0..=1.0).contains(&opacity) {
+                eyre::bail!("watermark opacity must be between 0 and 1");
+            }
+            Some(ci2_remote_control::ComplianceWatermarkConfig {
+                facility: facility.clone(),
+                position: derived_matches.watermark_position.into(),
+                opacity,
+                font_size: derived_matches
+                    .watermark_font_size
+                    .unwrap_or(defaults.font_size),
+                datetime_format: derived_matches
+                    .watermark_datetime_format
+                    .clone()
+                    .unwrap_or(defaults.datetime_format),
+            })
+        }
+        None => None,
+    };
+
+    #[cfg(feature = "flydra_feat_detect")]
+    let detection_filter_config = match &derived_matches.detection_filter_config {
+        Some(path) => {
+            let buf = std::fs::read_to_string(path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            let cfg =
+                toml::from_str(&buf).with_context(|| format!("parsing {}", path.display()))?;
+            Some(cfg)
+        }
+        None => None,
+    };
+
+    #[cfg(feature = "dnn-detector")]
+    let dnn_detector_config = match &derived_matches.dnn_model {
+        Some(model_path) => {
+            let labels = match &derived_matches.dnn_labels {
+                Some(labels_path) => std::fs::read_to_string(labels_path)
+                    .with_context(|| format!("reading {}", labels_path.display()))?
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(String::from)
+                    .collect(),
+                None => vec![],
+            };
+            Some(crate::DnnDetectorConfig {
+                model_path: model_path.clone(),
+                labels,
+                min_confidence: derived_matches.dnn_min_confidence,
+                keep_labels: derived_matches.dnn_keep_labels.clone(),
+            })
+        }
+        None => None,
+    };
+
     // There are some fields set by `Default::default()` but only when various
     // cargo features are used. So turn off this clippy warning.
     #[allow(clippy::needless_update)]
-    Ok(StrandCamArgs {
+    Ok(CliCommand::Run(StrandCamArgs {
         standalone_or_braid,
         secret,
         no_browser,
         mp4_filename_template,
+        mp4_segment_config,
+        compliance_watermark,
+        disk_space_config,
+        resource_limits_config,
+        mdns_advertise: !derived_matches.no_mdns,
+        event_publisher_config,
+        diagnostics_config,
+        mjpeg_config,
+        roi_follow_config,
+        roi_stats_config,
+        nvenc_pool,
+        adaptive_fps_config,
+        idle_mode_config,
+        clock_skew_config,
+        self_test_config,
+        upload_config,
         fmf_filename_template,
         ufmf_filename_template,
 
@@ -373,9 +1100,13 @@ fn parse_args(app_name: &str) -> Result<StrandCamArgs> {
         model_server_addr,
         #[cfg(feature = "fiducial")]
         apriltag_csv_filename_template,
+        #[cfg(feature = "dnn-detector")]
+        dnn_detector_config,
+        #[cfg(feature = "flydra_feat_detect")]
+        detection_filter_config,
         #[cfg(target_os = "linux")]
         v4l2loopback: derived_matches.v4l2loopback,
         data_dir: derived_matches.data_dir,
         ..Default::default()
-    })
+    }))
 }
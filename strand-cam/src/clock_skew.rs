@@ -0,0 +1,133 @@
+//! Compensation of the clock skew of untriggered cameras.
+//!
+//! Without Braid, several cameras on one host run on their own clocks and the
+//! host timestamps of their frames jitter with the latency of the camera
+//! interface. With clock skew correction enabled, the camera clock is
+//! measured against the host clock every [ClockSkewConfig::interval] and a
+//! linear model is fit to the most recent measurements. Frames are then
+//! timestamped by converting their camera timestamp to host time with this
+//! model, and the model is saved in the metadata of MP4 recordings (see
+//! [ci2_remote_control::H264Metadata::device_clock_model]). Recordings of
+//! several cameras on the same host can thus be aligned to within a frame.
+//!
+//! The camera must support the `TimestampLatch` command and report its
+//! timestamps in nanoseconds.
+
+use std::{collections::VecDeque, time::Duration};
+
+use ci2_remote_control::{CamArg, DeviceClockModel};
+use tracing::debug;
+
+use crate::clock_model::{fit_time_model, ClockModelFitError};
+
+/// Configuration of the clock skew correction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClockSkewConfig {
+    /// How often the camera clock is measured.
+    pub interval: Duration,
+    /// Number of most recent measurements to which the model is fit.
+    pub window: usize,
+}
+
+impl Default for ClockSkewConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            window: 30,
+        }
+    }
+}
+
+/// Fits a [DeviceClockModel] to the most recent clock measurements.
+pub(crate) struct DeviceClockEstimator {
+    window: usize,
+    /// Pairs of host time and camera timestamp.
+    measurements: VecDeque<(chrono::DateTime<chrono::Utc>, u64)>,
+}
+
+impl DeviceClockEstimator {
+    pub(crate) fn new(window: usize) -> Self {
+        Self {
+            window: window.max(2),
+            measurements: VecDeque::new(),
+        }
+    }
+
+    /// Add a measurement, dropping the oldest one if the window is full.
+    pub(crate) fn push(&mut self, host_time: chrono::DateTime<chrono::Utc>, device_time: u64) {
+        if self.measurements.len() == self.window {
+            self.measurements.pop_front();
+        }
+        self.measurements.push_back((host_time, device_time));
+    }
+
+    /// Fit the model, or return `None` with fewer than two measurements.
+    pub(crate) fn model(&self) -> Option<Result<DeviceClockModel, ClockModelFitError>> {
+        if self.measurements.len() < 2 {
+            return None;
+        }
+        let (host_time0, device_time0) = self.measurements[0];
+        let data: Vec<(f64, f64)> = self
+            .measurements
+            .iter()
+            .map(|(host_time, device_time)| {
+                let device_elapsed = *device_time as i128 - device_time0 as i128;
+                let host_elapsed = (*host_time - host_time0).num_nanoseconds().unwrap();
+                (device_elapsed as f64, host_elapsed as f64)
+            })
+            .collect();
+        Some(
+            fit_time_model(&data).map(|(gain, offset, residuals)| DeviceClockModel {
+                host_time0,
+                device_time0,
+                gain,
+                offset,
+                residuals,
+                n_measurements: data.len().try_into().unwrap(),
+            }),
+        )
+    }
+}
+
+/// Request a measurement of the camera clock every `cfg.interval`.
+///
+/// Returns when the camera task stopped.
+pub(crate) async fn run(cfg: ClockSkewConfig, cam_args_tx: tokio::sync::mpsc::Sender<CamArg>) {
+    let mut interval = tokio::time::interval(cfg.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if cam_args_tx.send(CamArg::MeasureDeviceClock).await.is_err() {
+            debug!("camera task stopped, ending clock skew measurements");
+            return;
+        }
+    }
+}
+
+#[test]
+fn test_device_clock_estimator() {
+    let host_time0 = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    let device_time0 = 5_000_000_000;
+    // The camera clock runs 100 ppm slow.
+    let gain = 1.0001;
+    let host_time = |device_time: u64| {
+        let elapsed = ((device_time - device_time0) as f64 * gain).round() as i64;
+        host_time0 + chrono::Duration::nanoseconds(elapsed)
+    };
+
+    let mut estimator = DeviceClockEstimator::new(3);
+    assert!(estimator.model().is_none());
+    // A measurement which will be dropped from the window.
+    estimator.push(host_time0 + chrono::Duration::milliseconds(5), 0);
+    for i in 0..3 {
+        let device_time = device_time0 + i * 10_000_000_000;
+        estimator.push(host_time(device_time), device_time);
+    }
+
+    let model = estimator.model().unwrap().unwrap();
+    assert_eq!(model.n_measurements, 3);
+    assert!((model.gain - gain).abs() < 1e-9);
+    let device_time = device_time0 + 35_000_000_000;
+    let error = model.host_time(device_time) - host_time(device_time);
+    assert!(error.num_microseconds().unwrap().abs() < 1);
+}
@@ -0,0 +1,143 @@
+//! Human-readable watermark drawn into the frames of MP4 recordings.
+//!
+//! Some facility protocols require the date and time to be visible in the
+//! footage itself. The watermark shows the timestamp of each frame (the same
+//! timestamp saved in the MP4 metadata), the camera name and a facility
+//! string. FMF and UFMF files are not modified.
+
+use std::fmt::Write;
+
+use basic_frame::DynamicFrame;
+use ci2_remote_control::{ComplianceWatermarkConfig, WatermarkPosition};
+use font_drawing::{Corner, StampStyle};
+use machine_vision_formats::{pixel_format::RGB8, PixFmt};
+use tracing::warn;
+
+/// Draws the watermark configured when a recording was started.
+pub(crate) struct Watermarker {
+    cfg: ComplianceWatermarkConfig,
+    camera_name: String,
+    font: rusttype::Font<'static>,
+    style: StampStyle,
+    unsupported: bool,
+}
+
+impl Watermarker {
+    pub(crate) fn new(cfg: ComplianceWatermarkConfig, camera_name: &str) -> Self {
+        let font = rusttype::Font::try_from_bytes(ttf_firacode::REGULAR).unwrap();
+        let corner = match cfg.position {
+            WatermarkPosition::TopLeft => Corner::TopLeft,
+            WatermarkPosition::TopRight => Corner::TopRight,
+            WatermarkPosition::BottomLeft => Corner::BottomLeft,
+            WatermarkPosition::BottomRight => Corner::BottomRight,
+        };
+        let style = StampStyle {
+            font_size: cfg.font_size,
+            corner,
+            margin: cfg.font_size / 2.0,
+            opacity: cfg.opacity,
+            text_colour: [255, 255, 255],
+            background_colour: Some([0, 0, 0]),
+        };
+        Self {
+            cfg,
+            camera_name: camera_name.to_string(),
+            font,
+            style,
+            unsupported: false,
+        }
+    }
+
+    /// The text of the watermark of a frame with timestamp `stamp`.
+    fn text(&self, stamp: chrono::DateTime<chrono::Local>) -> String {
+        let mut text = String::new();
+        // An invalid format string is only detected when formatting.
+        if write!(text, "{}", stamp.format(&self.cfg.datetime_format)).is_err() {
+            text = stamp.to_rfc3339();
+        }
+        text.push_str(" | ");
+        text.push_str(&self.camera_name);
+        if !self.cfg.facility.is_empty() {
+            text.push_str(" | ");
+            text.push_str(&self.cfg.facility);
+        }
+        text
+    }
+
+    /// Draw the watermark into `frame`.
+    ///
+    /// Mono8 and RGB8 frames are modified in place. Frames of other pixel
+    /// formats are converted to RGB8.
+    pub(crate) fn apply<TS>(&mut self, frame: &mut DynamicFrame, timestamp: TS)
+    where
+        TS: Into<chrono::DateTime<chrono::Local>>,
+    {
+        if self.unsupported {
+            return;
+        }
+        let text = self.text(timestamp.into());
+        if !matches!(frame.pixel_format(), PixFmt::Mono8 | PixFmt::RGB8) {
+            match frame.clone().into_pixel_format::<RGB8>() {
+                Ok(rgb) => *frame = DynamicFrame::RGB8(rgb),
+                Err(e) => {
+                    warn!(
+                        "Not drawing watermark: cannot convert pixel format {}: {e}",
+                        frame.pixel_format()
+                    );
+                    self.unsupported = true;
+                    return;
+                }
+            }
+        }
+        let result = match frame {
+            DynamicFrame::Mono8(x) => {
+                font_drawing::stamp_frame_styled(x, &self.font, &text, &self.style)
+            }
+            DynamicFrame::RGB8(x) => {
+                font_drawing::stamp_frame_styled(x, &self.font, &text, &self.style)
+            }
+            _ => unreachable!(),
+        };
+        if let Err(e) = result {
+            warn!("Not drawing watermark: {e}");
+            self.unsupported = true;
+        }
+    }
+}
+
+#[test]
+fn test_watermark() {
+    use basic_frame::BasicExtra;
+    use chrono::TimeZone;
+
+    let cfg = ComplianceWatermarkConfig {
+        facility: "Facility 12".into(),
+        position: WatermarkPosition::BottomRight,
+        opacity: 1.0,
+        datetime_format: "%Y-%m-%d %H:%M:%S".into(),
+        ..Default::default()
+    };
+    let mut watermarker = Watermarker::new(cfg, "cam1");
+    let stamp = chrono::Local
+        .with_ymd_and_hms(2024, 3, 1, 12, 30, 0)
+        .unwrap();
+    assert_eq!(
+        watermarker.text(stamp),
+        "2024-03-01 12:30:00 | cam1 | Facility 12"
+    );
+
+    let (w, h) = (640, 480);
+    let extra = Box::new(BasicExtra {
+        host_timestamp: stamp.into(),
+        host_framenumber: 0,
+    });
+    let mut frame = DynamicFrame::new(w, h, w, extra, vec![128; (w * h) as usize], PixFmt::Mono8);
+    watermarker.apply(&mut frame, stamp);
+    assert_eq!(frame.pixel_format(), PixFmt::Mono8);
+    let data = frame.image_data_without_format();
+    let (top, bottom) = data.split_at(data.len() / 2);
+    // Only the bottom half contains the watermark.
+    assert!(top.iter().all(|&v| v == 128));
+    assert!(bottom.iter().any(|&v| v == 0));
+    assert!(bottom.iter().any(|&v| v == 255));
+}
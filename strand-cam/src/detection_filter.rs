@@ -0,0 +1,183 @@
+//! Camera-side filtering of detected features.
+//!
+//! Detections can be rejected by their position, area and aspect ratio before
+//! they are sent to Braid and saved to the CSV file, for example to ignore
+//! reflections at the wall of the arena. Rejected detections are counted in
+//! the heartbeats sent to Braid.
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use flydra_types::FlydraRawUdpPoint;
+
+/// Configuration of the detection filter, e.g. read from a TOML file:
+///
+/// ```toml
+/// min_area = 4.0
+/// max_aspect_ratio = 5.0
+///
+/// [[include]]
+/// points = [[20.0, 20.0], [620.0, 20.0], [620.0, 460.0], [20.0, 460.0]]
+///
+/// [[exclude]]
+/// points = [[300.0, 200.0], [340.0, 200.0], [340.0, 240.0]]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DetectionFilterConfig {
+    /// If not empty, detections must be inside one of these polygons.
+    #[serde(default)]
+    pub include: Vec<FilterPolygon>,
+    /// Detections inside any of these polygons are rejected.
+    #[serde(default)]
+    pub exclude: Vec<FilterPolygon>,
+    #[serde(default)]
+    pub min_area: Option<f64>,
+    #[serde(default)]
+    pub max_area: Option<f64>,
+    /// The minimum ratio of the major to the minor axis of a detection.
+    ///
+    /// Detections without slope and eccentricity, e.g. from a detector plugin,
+    /// are not rejected by their aspect ratio.
+    #[serde(default)]
+    pub min_aspect_ratio: Option<f64>,
+    /// The maximum ratio of the major to the minor axis of a detection.
+    #[serde(default)]
+    pub max_aspect_ratio: Option<f64>,
+}
+
+/// A polygon in image coordinates, in pixels.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterPolygon {
+    pub points: Vec<(f64, f64)>,
+}
+
+/// Rejects detections according to a [DetectionFilterConfig].
+pub(crate) struct DetectionFilter {
+    cfg: DetectionFilterConfig,
+}
+
+impl DetectionFilter {
+    pub(crate) fn new(cfg: DetectionFilterConfig) -> Result<Self> {
+        for polygon in cfg.include.iter().chain(cfg.exclude.iter()) {
+            if polygon.points.len() < 3 {
+                eyre::bail!("detection filter polygon has fewer than 3 points");
+            }
+        }
+        if let (Some(min), Some(max)) = (cfg.min_area, cfg.max_area) {
+            if min > max {
+                eyre::bail!("detection filter minimum area {min} exceeds maximum {max}");
+            }
+        }
+        if let (Some(min), Some(max)) = (cfg.min_aspect_ratio, cfg.max_aspect_ratio) {
+            if min > max {
+                eyre::bail!("detection filter minimum aspect ratio {min} exceeds maximum {max}");
+            }
+        }
+        Ok(Self { cfg })
+    }
+
+    /// Remove the rejected detections from `points`.
+    ///
+    /// Returns the number of removed detections.
+    pub(crate) fn apply(&self, points: &mut Vec<FlydraRawUdpPoint>) -> usize {
+        let n_before = points.len();
+        points.retain(|pt| self.accepts(pt));
+        n_before - points.len()
+    }
+
+    fn accepts(&self, pt: &FlydraRawUdpPoint) -> bool {
+        let cfg = &self.cfg;
+        if cfg.min_area.is_some_and(|min| pt.area < min)
+            || cfg.max_area.is_some_and(|max| pt.area > max)
+        {
+            return false;
+        }
+        if let Some((_slope, eccentricity)) = pt.maybe_slope_eccentricty {
+            // The eccentricity is the ratio of the eigenvalues of the second
+            // order moments, i.e. of the squared axis lengths.
+            let aspect_ratio = eccentricity.sqrt();
+            if cfg.min_aspect_ratio.is_some_and(|min| aspect_ratio < min)
+                || cfg.max_aspect_ratio.is_some_and(|max| aspect_ratio > max)
+            {
+                return false;
+            }
+        }
+        let (x, y) = (pt.x0_abs, pt.y0_abs);
+        if !cfg.include.is_empty() && !cfg.include.iter().any(|p| polygon_contains(p, x, y)) {
+            return false;
+        }
+        !cfg.exclude.iter().any(|p| polygon_contains(p, x, y))
+    }
+}
+
+/// Whether the point `(x, y)` is inside `polygon`, by the even-odd rule.
+fn polygon_contains(polygon: &FilterPolygon, x: f64, y: f64) -> bool {
+    let points = &polygon.points;
+    let mut inside = false;
+    let mut j = points.len() - 1;
+    for (i, &(xi, yi)) in points.iter().enumerate() {
+        let (xj, yj) = points[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+#[test]
+fn test_detection_filter() {
+    let pt = |x0_abs, y0_abs, area, eccentricity: Option<f64>| FlydraRawUdpPoint {
+        x0_abs,
+        y0_abs,
+        area,
+        maybe_slope_eccentricty: eccentricity.map(|e| (1.0, e)),
+        cur_val: 255,
+        mean_val: 0.0,
+        sumsqf_val: 0.0,
+        appearance: None,
+    };
+    let cfg: DetectionFilterConfig = toml::from_str(
+        r#"
+        min_area = 4.0
+        max_area = 100.0
+        max_aspect_ratio = 3.0
+
+        [[include]]
+        points = [[0.0, 0.0], [100.0, 0.0], [100.0, 100.0], [0.0, 100.0]]
+
+        [[exclude]]
+        points = [[40.0, 40.0], [60.0, 40.0], [50.0, 60.0]]
+        "#,
+    )
+    .unwrap();
+    let filter = DetectionFilter::new(cfg).unwrap();
+
+    let mut points = vec![
+        pt(10.0, 10.0, 10.0, Some(4.0)),
+        // Outside of the include polygon.
+        pt(110.0, 10.0, 10.0, None),
+        // Inside the exclude polygon.
+        pt(50.0, 45.0, 10.0, None),
+        // Too small and too large.
+        pt(10.0, 10.0, 2.0, None),
+        pt(10.0, 10.0, 200.0, None),
+        // Too elongated.
+        pt(10.0, 10.0, 10.0, Some(16.0)),
+        // Without eccentricity.
+        pt(90.0, 90.0, 10.0, None),
+    ];
+    assert_eq!(filter.apply(&mut points), 5);
+    assert_eq!(points.len(), 2);
+    assert_eq!((points[1].x0_abs, points[1].y0_abs), (90.0, 90.0));
+
+    let invalid = DetectionFilterConfig {
+        exclude: vec![FilterPolygon {
+            points: vec![(0.0, 0.0), (1.0, 1.0)],
+        }],
+        ..Default::default()
+    };
+    assert!(DetectionFilter::new(invalid).is_err());
+}
@@ -0,0 +1,350 @@
+//! Detector plugins to find 2D points without background subtraction.
+//!
+//! A [Detector] replaces the background subtraction of
+//! `flydra-feature-detector`, for example with a learned model for arenas in
+//! which the background is difficult to model. Detection runs on a separate
+//! thread so that a slow detector does not stall image acquisition. While the
+//! detector is busy, new frames are dropped and no points are sent for them.
+//! Points are sent to Braid with the timestamps of the frame in which they
+//! were detected.
+//!
+//! [OnnxDetector] runs an ONNX model.
+
+use std::{
+    num::NonZeroU64,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use eyre::{eyre, Result, WrapErr};
+use tracing::{debug, error, info};
+
+use basic_frame::{match_all_dynamic_fmts, DynamicFrame};
+use flydra_types::{
+    FlydraFloatTimestampLocal, FlydraRawUdpPacket, FlydraRawUdpPoint, ImageProcessingSteps,
+    Triggerbox,
+};
+use machine_vision_formats::{pixel_format, ImageData, Stride};
+use timestamped_frame::ExtraTimeData;
+
+/// A point found by a [Detector], in pixel coordinates of the frame.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LabeledPoint {
+    pub(crate) x: f64,
+    pub(crate) y: f64,
+    pub(crate) label: String,
+    /// The confidence of the detection, between 0 and 1.
+    pub(crate) confidence: f32,
+}
+
+/// Finds points in a frame.
+pub(crate) trait Detector: Send {
+    fn detect(&mut self, frame: &DynamicFrame) -> Result<Vec<LabeledPoint>>;
+}
+
+/// Configuration of the detector plugin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnnDetectorConfig {
+    /// Path to the ONNX model.
+    pub model_path: PathBuf,
+    /// Labels of the classes of the model, in order.
+    pub labels: Vec<String>,
+    /// Points with lower confidence are ignored.
+    pub min_confidence: f32,
+    /// If not empty, only points with these labels are used.
+    pub keep_labels: Vec<String>,
+}
+
+/// The timing information of a frame needed to send its points to Braid.
+struct FrameStamps {
+    host_framenumber: usize,
+    host_timestamp: chrono::DateTime<chrono::Utc>,
+    device_timestamp: Option<NonZeroU64>,
+    block_id: Option<NonZeroU64>,
+    braid_ts: Option<FlydraFloatTimestampLocal<Triggerbox>>,
+    submitted: chrono::DateTime<chrono::Utc>,
+}
+
+type DetectorResult = (FrameStamps, Result<Vec<LabeledPoint>>);
+
+/// Runs a [Detector] on a background thread, dropping frames while it is busy.
+pub(crate) struct AsyncDetector {
+    cfg: DnnDetectorConfig,
+    frame_tx: std::sync::mpsc::SyncSender<(DynamicFrame, FrameStamps)>,
+    result_rx: std::sync::mpsc::Receiver<DetectorResult>,
+    /// Set while the detector thread is working on a frame.
+    busy: Arc<AtomicBool>,
+    n_dropped: u64,
+    n_failed: u64,
+}
+
+impl AsyncDetector {
+    pub(crate) fn new<D: Detector + 'static>(mut detector: D, cfg: DnnDetectorConfig) -> Self {
+        // With a capacity of zero, a frame can only be sent while the
+        // detector thread is waiting for one.
+        let (frame_tx, frame_rx) = std::sync::mpsc::sync_channel::<(DynamicFrame, FrameStamps)>(0);
+        let (result_tx, result_rx) = std::sync::mpsc::channel();
+        let busy = Arc::new(AtomicBool::new(false));
+        let busy2 = busy.clone();
+        std::thread::Builder::new()
+            .name("detector-plugin".to_string())
+            .spawn(move || {
+                while let Ok((frame, stamps)) = frame_rx.recv() {
+                    let result = detector.detect(&frame);
+                    if result_tx.send((stamps, result)).is_err() {
+                        break;
+                    }
+                    busy2.store(false, Ordering::Release);
+                }
+            })
+            .unwrap();
+        Self {
+            cfg,
+            frame_tx,
+            result_rx,
+            busy,
+            n_dropped: 0,
+            n_failed: 0,
+        }
+    }
+
+    /// Submit a frame for detection and return any completed detections.
+    ///
+    /// The frame is dropped if the detector is busy. The returned packet is
+    /// from an earlier frame. If detection failed for that frame, no packet is
+    /// returned.
+    pub(crate) fn process(
+        &mut self,
+        frame: &DynamicFrame,
+        cam_name: &str,
+        device_timestamp: Option<NonZeroU64>,
+        block_id: Option<NonZeroU64>,
+        braid_ts: Option<FlydraFloatTimestampLocal<Triggerbox>>,
+        max_num_points: usize,
+    ) -> Result<Option<(FlydraRawUdpPacket, chrono::DateTime<chrono::Utc>)>> {
+        // Check first to avoid copying frames which would be dropped. The
+        // flag is set before sending so that the detector thread clears it
+        // only after the frame was sent.
+        let sent = if self.busy.swap(true, Ordering::AcqRel) {
+            false
+        } else {
+            let stamps = FrameStamps {
+                host_framenumber: frame.extra().host_framenumber(),
+                host_timestamp: frame.extra().host_timestamp(),
+                device_timestamp,
+                block_id,
+                braid_ts,
+                submitted: chrono::Utc::now(),
+            };
+            match self.frame_tx.try_send((frame.clone(), stamps)) {
+                Ok(()) => true,
+                Err(std::sync::mpsc::TrySendError::Full(_)) => {
+                    self.busy.store(false, Ordering::Release);
+                    false
+                }
+                Err(std::sync::mpsc::TrySendError::Disconnected(_)) => {
+                    eyre::bail!("The detector thread stopped.");
+                }
+            }
+        };
+        if !sent {
+            self.n_dropped += 1;
+            if self.n_dropped.is_power_of_two() {
+                debug!("detector busy, {} frames dropped", self.n_dropped);
+            }
+        }
+
+        let (stamps, result) = match self.result_rx.try_recv() {
+            Ok(x) => x,
+            Err(_) => return Ok(None),
+        };
+        let mut points = match result {
+            Ok(points) => points,
+            Err(e) => {
+                self.n_failed += 1;
+                if self.n_failed.is_power_of_two() {
+                    error!(
+                        "detection failed for frame {} ({} failures): {e:?}",
+                        stamps.host_framenumber, self.n_failed
+                    );
+                }
+                return Ok(None);
+            }
+        };
+        points.retain(|pt| {
+            pt.confidence >= self.cfg.min_confidence
+                && (self.cfg.keep_labels.is_empty() || self.cfg.keep_labels.contains(&pt.label))
+        });
+        points.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        points.truncate(max_num_points);
+        let done = chrono::Utc::now();
+
+        let packet = FlydraRawUdpPacket {
+            cam_name: cam_name.to_string(),
+            timestamp: stamps.braid_ts,
+            cam_received_time: FlydraFloatTimestampLocal::from_dt(&stamps.host_timestamp),
+            device_timestamp: stamps.device_timestamp,
+            block_id: stamps.block_id,
+            framenumber: stamps.host_framenumber as i32,
+            n_frames_skipped: 0,
+            done_camnode_processing: datetime_conversion::datetime_to_f64(&done),
+            preprocess_stamp: datetime_conversion::datetime_to_f64(&stamps.submitted),
+            packet_sent_stamp: 0.0,
+            image_processing_steps: ImageProcessingSteps::empty(),
+            points: points
+                .iter()
+                .map(|pt| FlydraRawUdpPoint {
+                    x0_abs: pt.x,
+                    y0_abs: pt.y,
+                    // The size of the object is not known.
+                    area: 1.0,
+                    maybe_slope_eccentricty: None,
+                    cur_val: (pt.confidence.clamp(0.0, 1.0) * 255.0).round() as u8,
+                    mean_val: 0.0,
+                    sumsqf_val: 0.0,
+                    appearance: None,
+                })
+                .collect(),
+        };
+        Ok(Some((packet, stamps.host_timestamp)))
+    }
+}
+
+/// Runs an ONNX model with `tract`.
+///
+/// The model must have a single input of shape `[1, C, H, W]` with `C` being
+/// 1 (mono) or 3 (RGB) and pixel values scaled to 0..1. Frames are resized
+/// to `H` and `W` if these are fixed by the model. The first output must have
+/// shape `[N, 4]` or `[1, N, 4]` where each of the `N` rows is
+/// `(x, y, confidence, class)` with `x` and `y` in input pixel coordinates.
+pub(crate) struct OnnxDetector {
+    model: tract_onnx::prelude::TypedRunnableModel<tract_onnx::prelude::TypedModel>,
+    labels: Vec<String>,
+    channels: usize,
+    input_width: usize,
+    input_height: usize,
+}
+
+impl OnnxDetector {
+    pub(crate) fn new(
+        cfg: &DnnDetectorConfig,
+        frame_width: usize,
+        frame_height: usize,
+    ) -> Result<Self> {
+        use tract_onnx::prelude::*;
+        use tract_onnx::tract_hir::infer::Factoid;
+
+        let model = tract_onnx::onnx()
+            .model_for_path(&cfg.model_path)
+            .map_err(|e| eyre!("{e}"))
+            .with_context(|| format!("loading ONNX model {}", cfg.model_path.display()))?;
+        let shape = model
+            .input_fact(0)
+            .map_err(|e| eyre!("{e}"))?
+            .shape
+            .dims()
+            .map(|d| d.concretize().and_then(|d| d.as_i64()).map(|d| d as usize))
+            .collect::<Vec<_>>();
+        if shape.len() != 4 {
+            eyre::bail!("expected model input of rank 4, not {}", shape.len());
+        }
+        let channels = shape[1].unwrap_or(1);
+        if channels != 1 && channels != 3 {
+            eyre::bail!("expected model input with 1 or 3 channels, not {channels}");
+        }
+        let input_height = shape[2].unwrap_or(frame_height);
+        let input_width = shape[3].unwrap_or(frame_width);
+
+        let model = model
+            .with_input_fact(
+                0,
+                f32::fact([1, channels, input_height, input_width]).into(),
+            )
+            .and_then(|m| m.into_optimized())
+            .and_then(|m| m.into_runnable())
+            .map_err(|e| eyre!("{e}"))
+            .with_context(|| format!("preparing ONNX model {}", cfg.model_path.display()))?;
+        info!(
+            "loaded ONNX model {} with input {channels}x{input_height}x{input_width}",
+            cfg.model_path.display()
+        );
+
+        Ok(Self {
+            model,
+            labels: cfg.labels.clone(),
+            channels,
+            input_width,
+            input_height,
+        })
+    }
+
+    /// Resize (nearest neighbor) and scale the frame to the model input.
+    fn to_input(&self, frame: &DynamicFrame) -> Result<tract_onnx::prelude::Tensor> {
+        let (width, height) = (frame.width() as usize, frame.height() as usize);
+        let (iw, ih, c) = (self.input_width, self.input_height, self.channels);
+        let (data, stride) = match_all_dynamic_fmts!(frame, x, {
+            if c == 1 {
+                let im = convert_image::convert_ref::<_, pixel_format::Mono8>(x)?;
+                (im.image_data().to_vec(), im.stride())
+            } else {
+                let im = convert_image::convert_ref::<_, pixel_format::RGB8>(x)?;
+                (im.image_data().to_vec(), im.stride())
+            }
+        });
+        let input = tract_onnx::prelude::tract_ndarray::Array4::from_shape_fn(
+            (1, c, ih, iw),
+            |(_, ch, row, col)| {
+                let src_row = row * height / ih;
+                let src_col = col * width / iw;
+                data[src_row * stride + src_col * c + ch] as f32 / 255.0
+            },
+        );
+        Ok(input.into())
+    }
+}
+
+impl Detector for OnnxDetector {
+    fn detect(&mut self, frame: &DynamicFrame) -> Result<Vec<LabeledPoint>> {
+        let input = self.to_input(frame)?;
+        let outputs = self
+            .model
+            .run(tract_onnx::prelude::tvec!(input.into()))
+            .map_err(|e| eyre!("{e}"))?;
+        let output = outputs[0]
+            .to_array_view::<f32>()
+            .map_err(|e| eyre!("{e}"))?;
+        let n_cols = *output.shape().last().unwrap_or(&0);
+        if n_cols < 3 {
+            eyre::bail!("unexpected model output shape {:?}", output.shape());
+        }
+
+        let x_scale = frame.width() as f64 / self.input_width as f64;
+        let y_scale = frame.height() as f64 / self.input_height as f64;
+        let values: Vec<f32> = output.iter().copied().collect();
+        let points = values
+            .chunks_exact(n_cols)
+            .map(|row| {
+                let label = match row.get(3) {
+                    Some(class) => {
+                        let class = class.round() as usize;
+                        self.labels
+                            .get(class)
+                            .cloned()
+                            .unwrap_or_else(|| class.to_string())
+                    }
+                    None => String::new(),
+                };
+                LabeledPoint {
+                    x: row[0] as f64 * x_scale,
+                    y: row[1] as f64 * y_scale,
+                    label,
+                    confidence: row[2],
+                }
+            })
+            .collect();
+        Ok(points)
+    }
+}
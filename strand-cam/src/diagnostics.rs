@@ -0,0 +1,317 @@
+//! Automatic capture of diagnostic data when frame acquisition misbehaves.
+//!
+//! A [FrameWatchdog] checks each incoming frame for gaps in the frame numbers
+//! (dropped frames) and raises an incident if no frame arrives for longer than
+//! the stall timeout. For each incident, a directory
+//! `<data_dir>/diagnostics/<incident_id>/` is created containing:
+//!
+//! - `incident.yaml`: what was detected and when,
+//! - `pre_event.mp4`: the most recent frames before the incident,
+//! - `bug_report.zip`: the recent pipeline state and the configuration, as
+//!   saved by the "bug report" button,
+//! - `strand-cam.log`: the end of the log file.
+//!
+//! This leaves evidence of intermittent problems, for example with the camera
+//! hardware, which happen while nobody is watching. To limit disk usage, at
+//! most one incident is saved per [DiagnosticsConfig::min_interval].
+
+use std::{
+    collections::VecDeque,
+    io::{Seek, SeekFrom},
+    num::NonZeroU64,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use eyre::{Result, WrapErr};
+use serde::Serialize;
+use tracing::{debug, error, info, warn};
+
+use basic_frame::{match_all_dynamic_fmts, DynamicFrame};
+use flydra_types::RawCamName;
+use strand_cam_storetype::StoreType;
+use timestamped_frame::ExtraTimeData;
+
+use crate::{
+    bug_report::PipelineHistory, post_trigger_buffer::PostTriggerBuffer, FinalMp4RecordingConfig,
+};
+
+/// The name of the directory, within the data directory, of saved incidents.
+pub(crate) const DIAGNOSTICS_DIRNAME: &str = "diagnostics";
+
+/// The number of bytes at the end of the log file saved with an incident.
+const LOG_TAIL_BYTES: u64 = 1024 * 1024;
+
+/// Configuration of the frame watchdog.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiagnosticsConfig {
+    /// An incident is raised if no frame is received for this long.
+    pub stall_timeout: Duration,
+    /// The number of frames before an incident which are saved.
+    pub pre_event_frames: usize,
+    /// Incidents occurring within this duration of the last saved incident
+    /// are logged but not saved.
+    pub min_interval: Duration,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        Self {
+            stall_timeout: Duration::from_secs(2),
+            pre_event_frames: 100,
+            min_interval: Duration::from_secs(600),
+        }
+    }
+}
+
+/// An anomaly detected by the [FrameWatchdog].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum Anomaly {
+    /// No frame was received for `waited_msec` after `last_framenumber`.
+    Stall {
+        last_framenumber: usize,
+        waited_msec: f64,
+    },
+    /// `n_missing` frames between `last_framenumber` and `framenumber` were
+    /// not received.
+    DroppedFrames {
+        last_framenumber: usize,
+        framenumber: usize,
+        n_missing: u64,
+    },
+}
+
+impl std::fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Anomaly::Stall {
+                last_framenumber,
+                waited_msec,
+            } => write!(
+                f,
+                "no frame received for {waited_msec:.0} msec after frame {last_framenumber}"
+            ),
+            Anomaly::DroppedFrames {
+                last_framenumber,
+                framenumber,
+                n_missing,
+            } => write!(
+                f,
+                "{n_missing} frame(s) dropped between frames {last_framenumber} and {framenumber}"
+            ),
+        }
+    }
+}
+
+/// The contents of `incident.yaml`.
+#[derive(Serialize)]
+struct IncidentReport<'a> {
+    incident_id: &'a str,
+    cam_name: &'a str,
+    detected_at: chrono::DateTime<chrono::Local>,
+    #[serde(flatten)]
+    anomaly: &'a Anomaly,
+    n_pre_event_frames: usize,
+    version: &'static str,
+}
+
+struct LastFrame {
+    framenumber: usize,
+    block_id: Option<NonZeroU64>,
+    received: Instant,
+}
+
+/// Watches the incoming frames and saves diagnostic data on anomalies.
+pub(crate) struct FrameWatchdog {
+    cfg: DiagnosticsConfig,
+    buffer: PostTriggerBuffer,
+    log_file: Option<PathBuf>,
+    /// `None` until the first frame and while disarmed.
+    last_frame: Option<LastFrame>,
+    /// Set once a stall was reported, until the next frame arrives.
+    stalled: bool,
+    last_incident: Option<Instant>,
+}
+
+impl FrameWatchdog {
+    pub(crate) fn new(cfg: DiagnosticsConfig, log_file: Option<PathBuf>) -> Self {
+        let mut buffer = PostTriggerBuffer::new();
+        buffer.set_size(cfg.pre_event_frames);
+        Self {
+            cfg,
+            buffer,
+            log_file,
+            last_frame: None,
+            stalled: false,
+            last_incident: None,
+        }
+    }
+
+    /// How long to wait for the next frame before calling [Self::timed_out].
+    ///
+    /// `None` if no stall should be detected, e.g. before the first frame.
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        if self.stalled {
+            return None;
+        }
+        self.last_frame.as_ref().map(|last| {
+            self.cfg
+                .stall_timeout
+                .saturating_sub(last.received.elapsed())
+        })
+    }
+
+    /// Stop detecting anomalies until the next frame, e.g. while frames are
+    /// expected to pause.
+    pub(crate) fn disarm(&mut self) {
+        self.last_frame = None;
+        self.stalled = false;
+    }
+
+    /// Check a newly received frame.
+    pub(crate) fn check_frame(
+        &mut self,
+        frame: &DynamicFrame,
+        block_id: Option<NonZeroU64>,
+    ) -> Option<Anomaly> {
+        let framenumber = frame.extra().host_framenumber();
+        self.buffer.push(frame);
+
+        let anomaly = self.last_frame.as_ref().and_then(|last| {
+            let host_gap = framenumber.saturating_sub(last.framenumber + 1) as u64;
+            let device_gap = match (last.block_id, block_id) {
+                (Some(prev), Some(cur)) => cur.get().saturating_sub(prev.get() + 1),
+                _ => 0,
+            };
+            let n_missing = host_gap.max(device_gap);
+            (n_missing > 0).then_some(Anomaly::DroppedFrames {
+                last_framenumber: last.framenumber,
+                framenumber,
+                n_missing,
+            })
+        });
+        self.last_frame = Some(LastFrame {
+            framenumber,
+            block_id,
+            received: Instant::now(),
+        });
+        self.stalled = false;
+        anomaly
+    }
+
+    /// Called when no frame was received within [Self::timeout].
+    pub(crate) fn timed_out(&mut self) -> Option<Anomaly> {
+        let last = self.last_frame.as_ref()?;
+        self.stalled = true;
+        Some(Anomaly::Stall {
+            last_framenumber: last.framenumber,
+            waited_msec: last.received.elapsed().as_secs_f64() * 1000.0,
+        })
+    }
+
+    /// Log an anomaly and save the diagnostic data unless an incident was
+    /// saved recently.
+    pub(crate) fn handle_anomaly(
+        &mut self,
+        anomaly: &Anomaly,
+        cam_name: &RawCamName,
+        data_dir: &Path,
+        store: Option<&StoreType>,
+        pipeline_history: &PipelineHistory,
+    ) {
+        warn!("frame watchdog: {anomaly}");
+        if let Some(prev) = self.last_incident {
+            if prev.elapsed() < self.cfg.min_interval {
+                debug!("not saving diagnostics, last incident was saved recently");
+                return;
+            }
+        }
+        self.last_incident = Some(Instant::now());
+        match self.save_incident(anomaly, cam_name, data_dir, store, pipeline_history) {
+            Ok(dir) => info!("saved diagnostics to \"{}\"", dir.display()),
+            Err(e) => error!("could not save diagnostics: {e:?}"),
+        }
+    }
+
+    fn save_incident(
+        &mut self,
+        anomaly: &Anomaly,
+        cam_name: &RawCamName,
+        data_dir: &Path,
+        store: Option<&StoreType>,
+        pipeline_history: &PipelineHistory,
+    ) -> Result<PathBuf> {
+        let now = chrono::Local::now();
+        let incident_id = format!("{}_{}", now.format("%Y%m%d_%H%M%S"), cam_name.as_str());
+        let dir = data_dir.join(DIAGNOSTICS_DIRNAME).join(&incident_id);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("creating directory \"{}\"", dir.display()))?;
+
+        let frames = self.buffer.get_and_clear();
+        let report = IncidentReport {
+            incident_id: &incident_id,
+            cam_name: cam_name.as_str(),
+            detected_at: now,
+            anomaly,
+            n_pre_event_frames: frames.len(),
+            version: env!("CARGO_PKG_VERSION"),
+        };
+        let fd = std::fs::File::create(dir.join("incident.yaml"))?;
+        serde_yaml::to_writer(fd, &report)?;
+
+        // Each part is saved even if saving another one failed.
+        if let Some(store) = store {
+            if let Err(e) = pipeline_history.write_bug_report(&dir.join("bug_report.zip"), store) {
+                warn!("could not save bug report for incident {incident_id}: {e}");
+            }
+            if let Err(e) = write_mp4(frames, store, &dir) {
+                warn!("could not save video for incident {incident_id}: {e}");
+            }
+        }
+        if let Some(log_file) = &self.log_file {
+            if let Err(e) = copy_tail(log_file, &dir.join("strand-cam.log"), LOG_TAIL_BYTES) {
+                warn!("could not save log for incident {incident_id}: {e}");
+            }
+        }
+        Ok(dir)
+    }
+}
+
+/// Write `frames` to `pre_event.mp4` in `dir`.
+///
+/// Encoding happens on a background thread, so the file may not be complete
+/// when this returns.
+fn write_mp4(frames: VecDeque<DynamicFrame>, store: &StoreType, dir: &Path) -> Result<()> {
+    let Some(frame0) = frames.front() else {
+        return Ok(());
+    };
+    let creation_time = frame0.extra().host_timestamp().into();
+    let mp4_recording_config = FinalMp4RecordingConfig::new(store, creation_time);
+    let mut writer = bg_movie_writer::BgMovieWriter::new_segmented(
+        "pre_event.mp4".to_string(),
+        mp4_recording_config.final_cfg,
+        Default::default(),
+        frames.len() + 1,
+        Some(dir.to_path_buf()),
+    );
+    for mut frame in frames.into_iter() {
+        // Force frame width to be a multiple of 2.
+        let clipped_width = (frame.width() / 2) * 2;
+        match_all_dynamic_fmts!(&mut frame, x, { x.width = clipped_width });
+        let ts = frame.extra().host_timestamp();
+        writer.write(frame, ts)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Copy at most the last `max_bytes` of `src` to `dest`.
+fn copy_tail(src: &Path, dest: &Path, max_bytes: u64) -> Result<()> {
+    let mut rdr = std::fs::File::open(src)?;
+    let len = rdr.metadata()?.len();
+    rdr.seek(SeekFrom::Start(len.saturating_sub(max_bytes)))?;
+    let mut wtr = std::fs::File::create(dest)?;
+    std::io::copy(&mut rdr, &mut wtr)?;
+    Ok(())
+}
@@ -0,0 +1,337 @@
+//! Publish live detections and recording state changes over MQTT and OSC.
+//!
+//! This allows closed-loop integrations with stimulus software without
+//! parsing the CSV files or the braid UDP packets. Every event is a JSON
+//! object with a `"type"` field, which is either `"detections"` or
+//! `"recording_state"`, and a `"schema_version"` field, currently
+//! [EVENT_SCHEMA_VERSION].
+//!
+//! A `"detections"` event is sent for each frame on which feature detection
+//! is run, even if nothing was detected:
+//!
+//! ```json
+//! {
+//!   "type": "detections",
+//!   "schema_version": 1,
+//!   "cam_name": "Basler-22005677",
+//!   "framenumber": 1234,
+//!   "trigger_timestamp": 1700000000.123,
+//!   "cam_received_timestamp": 1700000000.125,
+//!   "points": [
+//!     {"x": 320.5, "y": 240.1, "area": 42.0, "slope": 0.3, "eccentricity": 2.1}
+//!   ]
+//! }
+//! ```
+//!
+//! `trigger_timestamp` is `null` if it cannot be determined and `slope` and
+//! `eccentricity` are `null` if they could not be computed for a point.
+//! Timestamps are seconds since the UNIX epoch. `x` and `y` are pixel
+//! coordinates in the (distorted) camera image.
+//!
+//! A `"recording_state"` event is sent at startup and whenever a recording is
+//! started or stopped:
+//!
+//! ```json
+//! {
+//!   "type": "recording_state",
+//!   "schema_version": 1,
+//!   "cam_name": "Basler-22005677",
+//!   "timestamp": 1700000000.5,
+//!   "mp4": true,
+//!   "fmf": false,
+//!   "ufmf": false,
+//!   "csv": false
+//! }
+//! ```
+//!
+//! With MQTT, events are published to the topics
+//! `<prefix>/<cam_name>/detections` and `<prefix>/<cam_name>/recording_state`.
+//! Recording state messages are retained so that late subscribers receive the
+//! current state. With OSC, each event is sent as a message with the address
+//! `/strand-cam/detections` or `/strand-cam/recording_state` with the JSON
+//! string as its single argument.
+//!
+//! Events are sent from a background task. If this task cannot keep up, events
+//! are dropped rather than slowing down image processing.
+
+use std::net::{SocketAddr, UdpSocket};
+
+use eyre::{Result, WrapErr};
+use serde::Serialize;
+use tracing::{debug, error, warn};
+
+use flydra_types::FlydraRawUdpPacket;
+
+/// The version of the JSON schema of published events.
+pub const EVENT_SCHEMA_VERSION: u16 = 1;
+
+/// The default prefix of MQTT topics.
+pub const DEFAULT_MQTT_TOPIC_PREFIX: &str = "strand-cam";
+
+const OSC_ADDRESS_PREFIX: &str = "/strand-cam";
+
+/// Number of events buffered before new events are dropped.
+const QUEUE_SIZE: usize = 100;
+
+/// Where to publish events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventPublisherConfig {
+    /// The MQTT broker as `host:port`, if any.
+    pub mqtt_broker: Option<String>,
+    /// The prefix of MQTT topics.
+    pub mqtt_topic_prefix: String,
+    /// The destination of OSC messages, if any.
+    pub osc_destination: Option<SocketAddr>,
+}
+
+impl Default for EventPublisherConfig {
+    fn default() -> Self {
+        Self {
+            mqtt_broker: None,
+            mqtt_topic_prefix: DEFAULT_MQTT_TOPIC_PREFIX.to_string(),
+            osc_destination: None,
+        }
+    }
+}
+
+impl EventPublisherConfig {
+    /// Return true if events are published to at least one destination.
+    pub fn is_enabled(&self) -> bool {
+        self.mqtt_broker.is_some() || self.osc_destination.is_some()
+    }
+}
+
+/// An event as published. See the module documentation for the schema.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[cfg_attr(not(feature = "flydra_feat_detect"), allow(dead_code))]
+pub(crate) enum Event {
+    Detections(DetectionsEvent),
+    RecordingState(RecordingStateEvent),
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Detections(_) => "detections",
+            Event::RecordingState(_) => "recording_state",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub(crate) struct DetectionsEvent {
+    schema_version: u16,
+    cam_name: String,
+    framenumber: i32,
+    trigger_timestamp: Option<f64>,
+    cam_received_timestamp: f64,
+    points: Vec<DetectionPoint>,
+}
+
+impl From<&FlydraRawUdpPacket> for DetectionsEvent {
+    fn from(packet: &FlydraRawUdpPacket) -> Self {
+        let points = packet
+            .points
+            .iter()
+            .map(|pt| DetectionPoint {
+                x: pt.x0_abs,
+                y: pt.y0_abs,
+                area: pt.area,
+                slope: pt.maybe_slope_eccentricty.map(|x| x.0),
+                eccentricity: pt.maybe_slope_eccentricty.map(|x| x.1),
+            })
+            .collect();
+        Self {
+            schema_version: EVENT_SCHEMA_VERSION,
+            cam_name: packet.cam_name.clone(),
+            framenumber: packet.framenumber,
+            trigger_timestamp: packet.timestamp.as_ref().map(|x| x.as_f64()),
+            cam_received_timestamp: packet.cam_received_time.as_f64(),
+            points,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+struct DetectionPoint {
+    x: f64,
+    y: f64,
+    area: f64,
+    slope: Option<f64>,
+    eccentricity: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub(crate) struct RecordingStateEvent {
+    schema_version: u16,
+    cam_name: String,
+    timestamp: f64,
+    #[serde(flatten)]
+    state: RecordingState,
+}
+
+impl RecordingStateEvent {
+    pub(crate) fn new(cam_name: &str, state: RecordingState) -> Self {
+        Self {
+            schema_version: EVENT_SCHEMA_VERSION,
+            cam_name: cam_name.to_string(),
+            timestamp: datetime_conversion::datetime_to_f64(&chrono::Utc::now()),
+            state,
+        }
+    }
+}
+
+/// Which kinds of recording are in progress.
+#[derive(Debug, Default, Clone, Copy, Serialize, PartialEq, Eq)]
+pub(crate) struct RecordingState {
+    pub(crate) mp4: bool,
+    pub(crate) fmf: bool,
+    pub(crate) ufmf: bool,
+    pub(crate) csv: bool,
+}
+
+/// A handle to send events to the background publishing task.
+#[derive(Clone)]
+pub(crate) struct EventPublisher {
+    tx: tokio::sync::mpsc::Sender<Event>,
+}
+
+impl EventPublisher {
+    /// Spawn the background publishing task.
+    ///
+    /// Returns `None` if no destination is configured. Must be called from
+    /// within a tokio runtime.
+    pub(crate) fn new(cfg: &EventPublisherConfig, client_id: &str) -> Result<Option<Self>> {
+        if !cfg.is_enabled() {
+            return Ok(None);
+        }
+
+        let mqtt = if let Some(broker) = &cfg.mqtt_broker {
+            let (host, port) = parse_host_port(broker)?;
+            let mut opts = rumqttc::MqttOptions::new(client_id, host, port);
+            opts.set_keep_alive(std::time::Duration::from_secs(5));
+            let (client, eventloop) = rumqttc::AsyncClient::new(opts, QUEUE_SIZE);
+            tokio::spawn(drive_mqtt_eventloop(broker.clone(), eventloop));
+            debug!("publishing events to MQTT broker {broker}");
+            Some((client, cfg.mqtt_topic_prefix.clone()))
+        } else {
+            None
+        };
+
+        let osc = if let Some(dest) = cfg.osc_destination {
+            let bind_addr: SocketAddr = if dest.is_ipv4() {
+                "0.0.0.0:0".parse().unwrap()
+            } else {
+                "[::]:0".parse().unwrap()
+            };
+            let sock = UdpSocket::bind(bind_addr)?;
+            sock.set_nonblocking(true)?;
+            debug!("publishing events via OSC to {dest}");
+            Some((sock, dest))
+        } else {
+            None
+        };
+
+        let (tx, rx) = tokio::sync::mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(publish_events(rx, mqtt, osc));
+        Ok(Some(Self { tx }))
+    }
+
+    /// Queue an event to be published. Never blocks.
+    pub(crate) fn publish(&self, event: Event) {
+        match self.tx.try_send(event) {
+            Ok(()) => {}
+            Err(tokio::sync::mpsc::error::TrySendError::Full(event)) => {
+                debug!("event queue full, dropping {} event", event.name());
+            }
+            Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+fn parse_host_port(broker: &str) -> Result<(String, u16)> {
+    let (host, port) = broker
+        .rsplit_once(':')
+        .ok_or_else(|| eyre::eyre!("MQTT broker \"{broker}\" is not of the form host:port"))?;
+    let port = port
+        .parse()
+        .with_context(|| format!("invalid port in MQTT broker \"{broker}\""))?;
+    Ok((host.to_string(), port))
+}
+
+async fn drive_mqtt_eventloop(broker: String, mut eventloop: rumqttc::EventLoop) {
+    let mut had_error = false;
+    loop {
+        match eventloop.poll().await {
+            Ok(_) => {
+                had_error = false;
+            }
+            Err(e) => {
+                // Only log the first of a series of errors. The event loop
+                // reconnects on the next poll.
+                if !had_error {
+                    warn!("MQTT connection to {broker} failed: {e}");
+                }
+                had_error = true;
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+async fn publish_events(
+    mut rx: tokio::sync::mpsc::Receiver<Event>,
+    mqtt: Option<(rumqttc::AsyncClient, String)>,
+    osc: Option<(UdpSocket, SocketAddr)>,
+) {
+    while let Some(event) = rx.recv().await {
+        let buf = match serde_json::to_string(&event) {
+            Ok(buf) => buf,
+            Err(e) => {
+                error!("could not serialize event: {e}");
+                continue;
+            }
+        };
+        if let Some((client, prefix)) = &mqtt {
+            let (cam_name, qos, retain) = match &event {
+                Event::Detections(ev) => (&ev.cam_name, rumqttc::QoS::AtMostOnce, false),
+                Event::RecordingState(ev) => (&ev.cam_name, rumqttc::QoS::AtLeastOnce, true),
+            };
+            let topic = format!("{prefix}/{cam_name}/{}", event.name());
+            if let Err(e) = client.try_publish(topic, qos, retain, buf.as_bytes()) {
+                debug!("could not publish MQTT message: {e}");
+            }
+        }
+        if let Some((sock, dest)) = &osc {
+            let address = format!("{OSC_ADDRESS_PREFIX}/{}", event.name());
+            let msg = encode_osc_string_message(&address, &buf);
+            if let Err(e) = sock.send_to(&msg, dest) {
+                debug!("could not send OSC message: {e}");
+            }
+        }
+    }
+}
+
+/// Append `s` as an OSC string: null terminated and padded to 4 bytes.
+fn push_osc_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(s.as_bytes());
+    let n_pad = 4 - (s.len() % 4);
+    buf.resize(buf.len() + n_pad, 0);
+}
+
+/// Encode an OSC 1.0 message with a single string argument.
+fn encode_osc_string_message(address: &str, arg: &str) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(address.len() + arg.len() + 12);
+    push_osc_string(&mut buf, address);
+    push_osc_string(&mut buf, ",s");
+    push_osc_string(&mut buf, arg);
+    buf
+}
+
+#[test]
+fn test_encode_osc() {
+    let buf = encode_osc_string_message("/a", "xyzw");
+    assert_eq!(buf, b"/a\0\0,s\0\0xyzw\0\0\0\0");
+    assert_eq!(buf.len() % 4, 0);
+}
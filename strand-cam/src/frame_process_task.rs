@@ -21,7 +21,6 @@ use async_change_tracker::ChangeTracker;
 use basic_frame::{match_all_dynamic_fmts, DynamicFrame};
 use flydra_feature_detector_types::ImPtDetectCfg;
 use flydra_types::{FlydraFloatTimestampLocal, PtpStamp, RawCamName, TriggerType};
-use fmf::FMFWriter;
 use http_video_streaming::AnnotatedFrame;
 use rust_cam_bui_types::RecordingPath;
 
@@ -31,10 +30,29 @@ use timestamped_frame::ExtraTimeData;
 #[cfg(feature = "fiducial")]
 use ads_apriltag as apriltag;
 
+#[cfg(feature = "flydra_feat_detect")]
+use crate::event_publisher::DetectionsEvent;
+
 use crate::{
-    convert_stream, open_braid_destination_addr, post_trigger_buffer, video_streaming,
-    CentroidToDevice, FinalMp4RecordingConfig, FmfWriteInfo, FpsCalc, MomentCentroid, Msg,
-    TimestampSource, LED_BOX_HEARTBEAT_INTERVAL_MSEC, MOMENT_CENTROID_SCHEMA_VERSION,
+    adaptive_fps::AdaptiveFrameRate,
+    bug_report::{DetectionSummary, PipelineHistory},
+    compliance_watermark::Watermarker,
+    convert_stream,
+    diagnostics::FrameWatchdog,
+    event_publisher::{Event, EventPublisher, RecordingState, RecordingStateEvent},
+    heartbeat::HeartbeatCounters,
+    mp4_proxy::Mp4ProxyWriter,
+    nvenc_pool::NvencPool,
+    open_braid_destination_addr,
+    output_latency::{Output, OutputLatencyCounters},
+    post_trigger_buffer,
+    roi_follow::RoiFollower,
+    roi_stats::{RoiStatsFrame, RoiStatsProcessor, ROI_STATS_SCHEMA_VERSION},
+    shutdown::{finish_blocking, ShutdownEvent},
+    uploader::Uploader,
+    video_streaming, CentroidToDevice, FinalMp4RecordingConfig, FmfWriteInfo, FpsCalc,
+    MomentCentroid, Msg, TimestampSource, LED_BOX_HEARTBEAT_INTERVAL_MSEC,
+    MOMENT_CENTROID_SCHEMA_VERSION,
 };
 
 /// Perform image analysis
@@ -77,6 +95,21 @@ pub(crate) async fn frame_process_task<'a>(
     trigger_type: Option<TriggerType>,
     #[cfg(target_os = "linux")] mut v4l_out_stream: Option<v4l::io::mmap::stream::Stream<'a>>,
     data_dir: PathBuf,
+    event_publisher: Option<EventPublisher>,
+    uploader: Option<Uploader>,
+    mut frame_watchdog: Option<FrameWatchdog>,
+    mut roi_follower: Option<RoiFollower>,
+    mut roi_stats: Option<RoiStatsProcessor>,
+    mut nvenc_pool: Option<NvencPool>,
+    mut adaptive_frame_rate: Option<AdaptiveFrameRate>,
+    heartbeat_counters: Arc<HeartbeatCounters>,
+    output_latency: Arc<OutputLatencyCounters>,
+    #[cfg(feature = "flydra_feat_detect")] detection_filter: Option<
+        crate::detection_filter::DetectionFilter,
+    >,
+    #[cfg(feature = "dnn-detector")] mut dnn_detector: Option<
+        crate::detector_plugin::AsyncDetector,
+    >,
 ) -> Result<()> {
     // As currently implemented, this function has a problem: it does
     // potentially computationally expensive image processing and thus should
@@ -113,7 +146,11 @@ pub(crate) async fn frame_process_task<'a>(
     #[cfg(feature = "fiducial")]
     let mut apriltag_writer: Option<_> = None;
     let mut my_mp4_writer: Option<bg_movie_writer::BgMovieWriter> = None;
+    let mut my_mp4_proxy_writer: Option<Mp4ProxyWriter> = None;
+    let mut my_mp4_watermarker: Option<Watermarker> = None;
     let mut fmf_writer: Option<FmfWriteInfo<_>> = None;
+    // When true, the MP4 writer saves exactly the frames saved to FMF.
+    let mut mp4_fmf_synced = false;
     #[cfg(feature = "flydra_feat_detect")]
     let mut ufmf_state = Some(flydra_feature_detector::UfmfState::Stopped);
     #[cfg(feature = "flydra_feat_detect")]
@@ -191,6 +228,10 @@ pub(crate) async fn frame_process_task<'a>(
     #[cfg(feature = "flydratrax")]
     let red_style = http_video_streaming_types::StrokeStyle::from_rgb(255, 100, 100);
 
+    // Used to draw the regions excluded from object detection.
+    #[cfg(feature = "flydra_feat_detect")]
+    let exclude_style = http_video_streaming_types::StrokeStyle::from_rgb(255, 200, 0);
+
     let expected_framerate_arc = Arc::new(parking_lot::RwLock::new(None));
 
     let mut post_trig_buffer = post_trigger_buffer::PostTriggerBuffer::new();
@@ -227,6 +268,12 @@ pub(crate) async fn frame_process_task<'a>(
 
     let mut triggerbox_clock_model = None;
     let mut opt_frame_offset = None;
+    let mut clock_skew_model: Option<ci2_remote_control::DeviceClockModel> = None;
+
+    // The recording state last published. `None` until the first publication.
+    let mut published_recording_state: Option<RecordingState> = None;
+
+    let mut pipeline_history = PipelineHistory::new();
 
     loop {
         #[cfg(feature = "flydra_feat_detect")]
@@ -356,12 +403,15 @@ pub(crate) async fn frame_process_task<'a>(
                                         mini_arena_debug_image_dir: None,
                                         write_buffer_size_num_messages: args
                                             .write_buffer_size_num_messages,
+                                        braidz_encryption_key: None,
+                                        braidz_spill_dir: None,
                                     },
                                     cam_manager,
                                     Some(recon),
                                     flydra2::BraidMetadataBuilder::saving_program_name(
                                         "strand-cam",
-                                    ),
+                                    )
+                                    .with_provenance(crate::provenance()),
                                 )
                                 .expect("create CoordProcessor");
 
@@ -435,12 +485,41 @@ pub(crate) async fn frame_process_task<'a>(
             }
         }
 
-        let msg = match incoming_frame_rx.recv().await {
-            Some(msg) => msg,
-            None => {
+        let msg = match frame_watchdog.as_ref().and_then(|w| w.timeout()) {
+            Some(timeout) => tokio::time::timeout(timeout, incoming_frame_rx.recv()).await,
+            None => Ok(incoming_frame_rx.recv().await),
+        };
+        let msg = match msg {
+            Ok(Some(msg)) => msg,
+            Ok(None) => {
                 info!("incoming frame channel closed for '{}'", cam_name.as_str());
                 break;
             }
+            Err(_elapsed) => {
+                // No frame was received within the stall timeout.
+                if let Some(watchdog) = frame_watchdog.as_mut() {
+                    let is_idle = shared_store_arc
+                        .as_ref()
+                        .map(|ssa| ssa.read().as_ref().acquisition_idle)
+                        .unwrap_or(false);
+                    if is_idle {
+                        // Acquisition was stopped by the idle mode.
+                        watchdog.disarm();
+                    } else if let Some(anomaly) = watchdog.timed_out() {
+                        let store = shared_store_arc
+                            .as_ref()
+                            .map(|ssa| ssa.read().as_ref().clone());
+                        watchdog.handle_anomaly(
+                            &anomaly,
+                            &cam_name,
+                            &data_dir,
+                            store.as_ref(),
+                            &pipeline_history,
+                        );
+                    }
+                }
+                continue;
+            }
         };
         let store_cache = if let Some(ref ssa) = shared_store_arc {
             let tracker = ssa.read();
@@ -488,9 +567,9 @@ pub(crate) async fn frame_process_task<'a>(
                 shared_store_arc = Some(stor);
             }
             Msg::StartFMF((dest, recording_framerate)) => {
-                let path = Path::new(&dest);
-                let f = std::fs::File::create(path)?;
-                fmf_writer = Some(FmfWriteInfo::new(FMFWriter::new(f)?, recording_framerate));
+                stop_fmf_writer(&mut fmf_writer)?;
+                fmf_writer = Some(FmfWriteInfo::create(dest.into(), recording_framerate)?);
+                mp4_fmf_synced = false;
             }
             #[cfg(feature = "flydra_feat_detect")]
             Msg::StartUFMF(dest) => {
@@ -503,52 +582,36 @@ pub(crate) async fn frame_process_task<'a>(
                     Msg::StartMp4 => std::collections::VecDeque::with_capacity(0),
                     _ => unreachable!(),
                 };
-
-                let local = chrono::Local::now();
-
-                // Get start time, either from buffered frames if present or current time.
-                let creation_time = if let Some(frame0) = frames.front() {
-                    frame0.extra().host_timestamp().into()
-                } else {
-                    local
-                };
-
-                let (format_str_mp4, mp4_recording_config) = {
-                    // scope for reading cache
-                    let tracker = shared_store_arc.as_ref().unwrap().read();
-                    let shared: &StoreType = tracker.as_ref();
-
-                    let mp4_recording_config = FinalMp4RecordingConfig::new(shared, creation_time);
-
-                    (shared.format_str_mp4.clone(), mp4_recording_config)
-                };
-
-                let filename = creation_time.format(format_str_mp4.as_str()).to_string();
-                let is_recording_mp4 = Some(RecordingPath::new(filename.clone()));
-
-                let mut raw = bg_movie_writer::BgMovieWriter::new(
-                    format_str_mp4,
-                    mp4_recording_config.final_cfg,
-                    frames.len() + 100,
-                    Some(data_dir.clone()),
-                );
-                for mut frame in frames.into_iter() {
-                    // Force frame width to be power of 2.
-                    let val = 2;
-                    let clipped_width = (frame.width() / val as u32) * val as u32;
-                    match_all_dynamic_fmts!(&mut frame, x, { x.width = clipped_width });
-                    // frame.width = clipped_width;
-                    let ts = frame.extra().host_timestamp();
-                    raw.write(frame, ts)?;
-                }
-                my_mp4_writer = Some(raw);
-
-                if let Some(ref mut store) = shared_store_arc {
-                    let mut tracker = store.write();
-                    tracker.modify(|tracker| {
-                        tracker.is_recording_mp4 = is_recording_mp4;
-                    });
-                }
+                let (mp4_writer, mp4_proxy_writer, watermarker) = start_mp4_writer(
+                    frames,
+                    shared_store_arc.as_ref().unwrap(),
+                    &data_dir,
+                    uploader.as_ref(),
+                    nvenc_pool.as_ref(),
+                    &output_latency,
+                )?;
+                my_mp4_writer = Some(mp4_writer);
+                my_mp4_proxy_writer = mp4_proxy_writer;
+                my_mp4_watermarker = watermarker;
+                mp4_fmf_synced = false;
+            }
+            Msg::StartMp4AndFMF((dest, recording_framerate)) => {
+                // Both writers are created while handling a single message, so
+                // the first frame seen by each of them is the same.
+                stop_fmf_writer(&mut fmf_writer)?;
+                fmf_writer = Some(FmfWriteInfo::create(dest.into(), recording_framerate)?);
+                let (mp4_writer, mp4_proxy_writer, watermarker) = start_mp4_writer(
+                    std::collections::VecDeque::with_capacity(0),
+                    shared_store_arc.as_ref().unwrap(),
+                    &data_dir,
+                    uploader.as_ref(),
+                    nvenc_pool.as_ref(),
+                    &output_latency,
+                )?;
+                my_mp4_writer = Some(mp4_writer);
+                my_mp4_proxy_writer = mp4_proxy_writer;
+                my_mp4_watermarker = watermarker;
+                mp4_fmf_synced = true;
             }
             Msg::StartAprilTagRec(format_str_apriltags_csv) => {
                 #[cfg(feature = "fiducial")]
@@ -582,19 +645,21 @@ pub(crate) async fn frame_process_task<'a>(
             }
             Msg::Mframe(frame) => {
                 let extracted_frame_info = frame_info_extractor.extract_frame_info(&frame);
+                #[cfg_attr(not(feature = "flydra_feat_detect"), allow(unused_mut))]
+                let mut detection_summary = None;
                 let device_timestamp = extracted_frame_info.device_timestamp;
                 tracing::trace!("device_timestamp: {device_timestamp:?}");
                 let block_id = extracted_frame_info.frame_id;
 
                 // Compute, as cleverly as possible, a timestamp.
                 let braid_ts = match &trigger_type {
-                    Some(TriggerType::TriggerboxV1(_)) | Some(TriggerType::FakeSync(_)) => {
-                        flydra_types::triggerbox_time(
-                            triggerbox_clock_model.as_ref(),
-                            opt_frame_offset,
-                            extracted_frame_info.host_framenumber,
-                        )
-                    }
+                    Some(TriggerType::TriggerboxV1(_))
+                    | Some(TriggerType::FakeSync(_))
+                    | Some(TriggerType::CameraMaster(_)) => flydra_types::triggerbox_time(
+                        triggerbox_clock_model.as_ref(),
+                        opt_frame_offset,
+                        extracted_frame_info.host_framenumber,
+                    ),
                     Some(TriggerType::PtpSync(ptpcfg)) => {
                         let ptp_stamp = PtpStamp::new(device_timestamp.unwrap().get());
                         if tracing::Level::TRACE <= tracing::level_filters::STATIC_MAX_LEVEL {
@@ -637,6 +702,13 @@ pub(crate) async fn frame_process_task<'a>(
                 };
                 let (timestamp_source, save_mp4_fmf_stamp) = if let Some(stamp) = &braid_ts {
                     (TimestampSource::BraidTrigger, stamp.into())
+                } else if let (Some(cm), Some(device_timestamp)) =
+                    (&clock_skew_model, extracted_frame_info.device_timestamp)
+                {
+                    (
+                        TimestampSource::DeviceClockModel,
+                        cm.host_time(device_timestamp.get()),
+                    )
                 } else {
                     (
                         TimestampSource::HostAcquiredTimestamp,
@@ -649,6 +721,8 @@ pub(crate) async fn frame_process_task<'a>(
                         let mut tracker = store.write();
                         tracker.modify(|tracker| {
                             tracker.measured_fps = new_fps as f32;
+                            tracker.frame_buffer_saturation =
+                                extracted_frame_info.buffer_saturation;
                         });
                     }
 
@@ -660,6 +734,28 @@ pub(crate) async fn frame_process_task<'a>(
 
                 post_trig_buffer.push(&frame); // If buffer size larger than 0, copies data.
 
+                if let Some(watchdog) = frame_watchdog.as_mut() {
+                    // Frames pause while Braid synchronizes the cameras.
+                    let is_synchronizing = matches!(
+                        trigger_type,
+                        Some(TriggerType::TriggerboxV1(_))
+                            | Some(TriggerType::FakeSync(_))
+                            | Some(TriggerType::CameraMaster(_))
+                    ) && (opt_frame_offset.is_none()
+                        || triggerbox_clock_model.is_none());
+                    if is_synchronizing {
+                        watchdog.disarm();
+                    } else if let Some(anomaly) = watchdog.check_frame(&frame, block_id) {
+                        watchdog.handle_anomaly(
+                            &anomaly,
+                            &cam_name,
+                            &data_dir,
+                            store_cache.as_ref(),
+                            &pipeline_history,
+                        );
+                    }
+                }
+
                 #[cfg(target_os = "linux")]
                 if let Some(v4l_out_stream) = v4l_out_stream.as_mut() {
                     let (buf_out, buf_out_meta) =
@@ -671,6 +767,7 @@ pub(crate) async fn frame_process_task<'a>(
                     buf_out.copy_from_slice(buf_in);
                     buf_out_meta.field = 0;
                     buf_out_meta.bytesused = bytesused;
+                    output_latency.frame_sent(Output::V4lOut, save_mp4_fmf_stamp);
                 }
 
                 #[cfg(feature = "checkercal")]
@@ -825,6 +922,21 @@ pub(crate) async fn frame_process_task<'a>(
                     {
                         if let Some(ref store_cache_ref) = store_cache {
                             if store_cache_ref.im_ops_state.do_detection {
+                                let mut roi_stats_msg = match roi_stats.as_mut() {
+                                    Some(roi_stats) => roi_stats.process(&frame)?.map(|rois| {
+                                        CentroidToDevice::RoiStats(RoiStatsFrame {
+                                            schema_version: ROI_STATS_SCHEMA_VERSION,
+                                            framenumber: block_id.unwrap().get(),
+                                            timestamp_source: timestamp_source.clone(),
+                                            timestamp: save_mp4_fmf_stamp,
+                                            cam_name: cam_name.as_str().to_string(),
+                                            rois,
+                                            latency_usec: 0,
+                                        })
+                                    }),
+                                    None => None,
+                                };
+
                                 let thresholded = if let DynamicFrame::Mono8(mono8) = &frame {
                                     imops::threshold(
                                         mono8.clone(),
@@ -839,7 +951,7 @@ pub(crate) async fn frame_process_task<'a>(
                                 let mu00 = imops::spatial_moment_00(&thresholded);
                                 let mu01 = imops::spatial_moment_01(&thresholded);
                                 let mu10 = imops::spatial_moment_10(&thresholded);
-                                let mc = if mu00 != 0.0 {
+                                let mut mc = if mu00 != 0.0 {
                                     let x = mu10 / mu00;
                                     let y = mu01 / mu00;
 
@@ -856,6 +968,7 @@ pub(crate) async fn frame_process_task<'a>(
                                         center_x: store_cache_ref.im_ops_state.center_x,
                                         center_y: store_cache_ref.im_ops_state.center_y,
                                         cam_name: cam_name.as_str().to_string(),
+                                        latency_usec: 0,
                                     });
                                     all_points.push(video_streaming::Point {
                                         x,
@@ -892,8 +1005,11 @@ pub(crate) async fn frame_process_task<'a>(
                                 }
 
                                 if let Some(socket) = &mut im_ops_socket {
-                                    if let Some(mc) = mc {
-                                        let buf = serde_cbor::to_vec(&mc).unwrap();
+                                    let latency_usec = output_latency
+                                        .frame_sent(Output::ImOps, save_mp4_fmf_stamp);
+                                    for msg in mc.iter_mut().chain(roi_stats_msg.iter_mut()) {
+                                        msg.set_latency_usec(latency_usec);
+                                        let buf = serde_cbor::to_vec(msg).unwrap();
                                         match socket
                                             .send_to(&buf, store_cache_ref.im_ops_state.destination)
                                         {
@@ -955,7 +1071,7 @@ pub(crate) async fn frame_process_task<'a>(
                         let preprocess_stamp =
                             datetime_conversion::datetime_to_f64(&process_new_frame_start);
 
-                        let tracker_annotation = flydra_types::FlydraRawUdpPacket {
+                        let mut tracker_annotation = flydra_types::FlydraRawUdpPacket {
                             cam_name: raw_cam_name.as_str().to_string(),
                             timestamp: braid_ts,
                             cam_received_time: acquire_stamp,
@@ -965,11 +1081,14 @@ pub(crate) async fn frame_process_task<'a>(
                             n_frames_skipped: 0, // FIXME TODO XXX FIX THIS, should be n_frames_skipped
                             done_camnode_processing: 0.0,
                             preprocess_stamp,
+                            packet_sent_stamp: 0.0,
                             image_processing_steps: ImageProcessingSteps::empty(),
                             points: vec![],
                         };
                         if let Some(ref coord_socket) = coord_socket {
                             // Send the data to the mainbrain
+                            tracker_annotation.packet_sent_stamp =
+                                datetime_conversion::datetime_to_f64(&chrono::Utc::now());
                             let mut vec = Vec::new();
                             {
                                 let mut serializer = serde_cbor::ser::Serializer::new(&mut vec);
@@ -986,300 +1105,396 @@ pub(crate) async fn frame_process_task<'a>(
                         if is_doing_object_detection {
                             let inner_ufmf_state = ufmf_state.take().unwrap();
                             // Detect features in the image and send them to the
-                            // mainbrain for 3D processing.
-                            let (tracker_annotation, new_ufmf_state) = im_tracker
-                                .process_new_frame(
+                            // mainbrain for 3D processing. With a detector
+                            // plugin, the detections are from an earlier frame
+                            // or there are none yet.
+                            #[cfg(feature = "dnn-detector")]
+                            let plugin_result = match dnn_detector.as_mut() {
+                                Some(dnn_detector) => Some(dnn_detector.process(
                                     &frame,
-                                    inner_ufmf_state,
+                                    raw_cam_name.as_str(),
                                     device_timestamp,
                                     block_id,
                                     braid_ts,
-                                )?;
-                            if let Some(ref coord_socket) = coord_socket {
-                                // Send the data to the mainbrain
-                                let mut vec = Vec::new();
-                                {
-                                    let mut serializer = serde_cbor::ser::Serializer::new(&mut vec);
-                                    serializer.self_describe().unwrap();
-                                    tracker_annotation.serialize(&mut serializer).unwrap();
+                                    im_tracker.config().max_num_points.into(),
+                                )?),
+                                None => None,
+                            };
+                            #[cfg(not(feature = "dnn-detector"))]
+                            let plugin_result = None;
+                            let detection = match plugin_result {
+                                Some(plugin_result) => {
+                                    ufmf_state = Some(inner_ufmf_state);
+                                    plugin_result
                                 }
-                                use crate::datagram_socket::SendComplete;
-                                coord_socket.send_complete(&vec)?;
-                            }
-                            ufmf_state.get_or_insert(new_ufmf_state);
-
-                            #[cfg(feature = "flydratrax")]
-                            {
-                                if let Some(ref mut flydra2_stream) = maybe_flydra2_stream {
-                                    let points = tracker_annotation
-                                        .points
-                                        .iter()
-                                        .filter(|pt| {
-                                            pt.area
-                                                >= kalman_tracking_config.min_central_moment as f64
-                                        })
-                                        .enumerate()
-                                        .map(|(i, pt)| {
-                                            assert!(i <= u8::MAX as usize);
-                                            let idx = i as u8;
-                                            flydra2::NumberedRawUdpPoint {
-                                                idx,
-                                                pt: pt.clone(),
-                                            }
-                                        })
-                                        .collect();
-
-                                    let cam_received_timestamp =
-                                        datetime_conversion::datetime_to_f64(
-                                            &frame.extra().host_timestamp(),
-                                        );
+                                None => {
+                                    let (tracker_annotation, new_ufmf_state) = im_tracker
+                                        .process_new_frame(
+                                            &frame,
+                                            inner_ufmf_state,
+                                            device_timestamp,
+                                            block_id,
+                                            braid_ts,
+                                        )?;
+                                    ufmf_state = Some(new_ufmf_state);
+                                    Some((tracker_annotation, frame.extra().host_timestamp()))
+                                }
+                            };
+                            if let Some((mut tracker_annotation, det_host_timestamp)) = detection {
+                                let det_framenumber = tracker_annotation.framenumber as usize;
+                                if let Some(detection_filter) = &detection_filter {
+                                    let n_filtered =
+                                        detection_filter.apply(&mut tracker_annotation.points);
+                                    heartbeat_counters.detections_filtered(n_filtered as u64);
+                                }
+                                if let Some(ref coord_socket) = coord_socket {
+                                    // Send the data to the mainbrain
+                                    tracker_annotation.packet_sent_stamp =
+                                        datetime_conversion::datetime_to_f64(&chrono::Utc::now());
+                                    let mut vec = Vec::new();
+                                    {
+                                        let mut serializer =
+                                            serde_cbor::ser::Serializer::new(&mut vec);
+                                        serializer.self_describe().unwrap();
+                                        tracker_annotation.serialize(&mut serializer).unwrap();
+                                    }
+                                    use crate::datagram_socket::SendComplete;
+                                    coord_socket.send_complete(&vec)?;
+                                }
+                                detection_summary = Some(DetectionSummary {
+                                    n_points: tracker_annotation.points.len(),
+                                    image_processing_steps: tracker_annotation
+                                        .image_processing_steps,
+                                });
 
-                                    // TODO FIXME XXX It is a lie that this
-                                    // timesource is Triggerbox. This is just for
-                                    // single-camera flydratrax, though.
-                                    let trigger_timestamp = Some(FlydraFloatTimestampLocal::<
-                                        flydra_types::Triggerbox,
-                                    >::from_f64(
-                                        cam_received_timestamp
+                                if let Some(event_publisher) = &event_publisher {
+                                    event_publisher.publish(Event::Detections(
+                                        DetectionsEvent::from(&tracker_annotation),
                                     ));
+                                }
 
-                                    // This is not a lie.
-                                    let cam_received_timestamp = FlydraFloatTimestampLocal::<
-                                        flydra_types::HostClock,
-                                    >::from_f64(
-                                        cam_received_timestamp
-                                    );
+                                #[cfg(feature = "flydratrax")]
+                                {
+                                    if let Some(ref mut flydra2_stream) = maybe_flydra2_stream {
+                                        let points = tracker_annotation
+                                            .points
+                                            .iter()
+                                            .filter(|pt| {
+                                                pt.area
+                                                    >= kalman_tracking_config.min_central_moment
+                                                        as f64
+                                            })
+                                            .enumerate()
+                                            .map(|(i, pt)| {
+                                                assert!(i <= u8::MAX as usize);
+                                                let idx = i as u8;
+                                                flydra2::NumberedRawUdpPoint {
+                                                    idx,
+                                                    pt: pt.clone(),
+                                                }
+                                            })
+                                            .collect();
+
+                                        let cam_received_timestamp =
+                                            datetime_conversion::datetime_to_f64(
+                                                &det_host_timestamp,
+                                            );
+
+                                        // TODO FIXME XXX It is a lie that this
+                                        // timesource is Triggerbox. This is just for
+                                        // single-camera flydratrax, though.
+                                        let trigger_timestamp = Some(FlydraFloatTimestampLocal::<
+                                            flydra_types::Triggerbox,
+                                        >::from_f64(
+                                            cam_received_timestamp
+                                        ));
+
+                                        // This is not a lie.
+                                        let cam_received_timestamp = FlydraFloatTimestampLocal::<
+                                            flydra_types::HostClock,
+                                        >::from_f64(
+                                            cam_received_timestamp
+                                        );
 
-                                    let cam_num = 0.into(); // Only one camera, so this must be correct.
-                                    let frame_data = flydra2::FrameData::new(
-                                        raw_cam_name.clone(),
-                                        cam_num,
-                                        flydra_types::SyncFno(
-                                            frame.extra().host_framenumber().try_into().unwrap(),
-                                        ),
-                                        trigger_timestamp,
-                                        cam_received_timestamp,
-                                        device_timestamp,
-                                        block_id,
-                                    );
-                                    let fdp = flydra2::FrameDataAndPoints { frame_data, points };
-                                    let si = flydra2::StreamItem::Packet(fdp);
-
-                                    // block until sent
-                                    match futures::executor::block_on(futures::sink::SinkExt::send(
-                                        flydra2_stream,
-                                        si,
-                                    )) {
-                                        Ok(()) => {}
-                                        Err(e) => return Err(e.into()),
+                                        let cam_num = 0.into(); // Only one camera, so this must be correct.
+                                        let frame_data = flydra2::FrameData::new(
+                                            raw_cam_name.clone(),
+                                            cam_num,
+                                            flydra_types::SyncFno(
+                                                det_framenumber.try_into().unwrap(),
+                                            ),
+                                            trigger_timestamp,
+                                            cam_received_timestamp,
+                                            tracker_annotation.device_timestamp,
+                                            tracker_annotation.block_id,
+                                        );
+                                        let fdp =
+                                            flydra2::FrameDataAndPoints { frame_data, points };
+                                        let si = flydra2::StreamItem::Packet(fdp);
+
+                                        // block until sent
+                                        match futures::executor::block_on(
+                                            futures::sink::SinkExt::send(flydra2_stream, si),
+                                        ) {
+                                            Ok(()) => {}
+                                            Err(e) => return Err(e.into()),
+                                        }
                                     }
                                 }
-                            }
 
-                            let points = tracker_annotation.points;
+                                let points = tracker_annotation.points;
 
-                            let mut new_state = None;
-                            match csv_save_state {
-                                SavingState::NotSaving => {}
-                                SavingState::Starting(rate_limit) => {
-                                    // create dir if needed
-                                    std::fs::create_dir_all(&csv_save_pathbuf)?;
+                                let mut new_state = None;
+                                match csv_save_state {
+                                    SavingState::NotSaving => {}
+                                    SavingState::Starting(rate_limit) => {
+                                        // create dir if needed
+                                        std::fs::create_dir_all(&csv_save_pathbuf)?;
 
-                                    // start saving tracking
-                                    let base_template = "flytrax%Y%m%d_%H%M%S";
-                                    let now = frame.extra().host_timestamp();
-                                    let local = now.with_timezone(&chrono::Local);
-                                    let base = local.format(base_template).to_string();
+                                        // start saving tracking
+                                        let base_template = "flytrax%Y%m%d_%H%M%S";
+                                        let now = det_host_timestamp;
+                                        let local = now.with_timezone(&chrono::Local);
+                                        let base = local.format(base_template).to_string();
 
-                                    // save jpeg image
-                                    {
-                                        let mut image_path = csv_save_pathbuf.clone();
-                                        image_path.push(base.clone());
-                                        image_path.set_extension("jpg");
-
-                                        let bytes = match_all_dynamic_fmts!(&frame, x, {
-                                            convert_image::frame_to_encoded_buffer(
-                                                x,
-                                                convert_image::EncoderOptions::Jpeg(99),
-                                            )?
-                                        });
-                                        File::create(image_path)?.write_all(&bytes)?;
-                                    }
+                                        // save jpeg image
+                                        {
+                                            let mut image_path = csv_save_pathbuf.clone();
+                                            image_path.push(base.clone());
+                                            image_path.set_extension("jpg");
+
+                                            let bytes = match_all_dynamic_fmts!(&frame, x, {
+                                                convert_image::frame_to_encoded_buffer(
+                                                    x,
+                                                    convert_image::EncoderOptions::Jpeg(99),
+                                                )?
+                                            });
+                                            File::create(image_path)?.write_all(&bytes)?;
+                                        }
 
-                                    let mut csv_path = csv_save_pathbuf.clone();
-                                    csv_path.push(base);
-                                    csv_path.set_extension("csv");
-                                    info!("saving data to {}.", csv_path.display());
-
-                                    if let Some(ref ssa) = shared_store_arc {
-                                        // scope for write lock on ssa
-                                        let new_val =
-                                            RecordingPath::new(csv_path.display().to_string());
-                                        let mut tracker = ssa.write();
-                                        tracker.modify(|shared| {
-                                            shared.is_saving_im_pt_detect_csv = Some(new_val);
-                                        });
-                                    }
+                                        let mut csv_path = csv_save_pathbuf.clone();
+                                        csv_path.push(base);
+                                        csv_path.set_extension("csv");
+                                        info!("saving data to {}.", csv_path.display());
+
+                                        if let Some(ref ssa) = shared_store_arc {
+                                            // scope for write lock on ssa
+                                            let new_val =
+                                                RecordingPath::new(csv_path.display().to_string());
+                                            let mut tracker = ssa.write();
+                                            tracker.modify(|shared| {
+                                                shared.is_saving_im_pt_detect_csv = Some(new_val);
+                                            });
+                                        }
 
-                                    let mut fd = File::create(csv_path)?;
+                                        let mut fd = File::create(csv_path)?;
 
-                                    // save configuration as commented yaml
-                                    {
-                                        let save_cfg =
-                                            strand_cam_csv_config_types::SaveCfgFview2_0_25 {
-                                                name: app_name.to_string(),
-                                                version: env!("CARGO_PKG_VERSION").to_string(),
-                                                git_hash: env!("GIT_HASH").to_string(),
-                                            };
+                                        // save configuration as commented yaml
+                                        {
+                                            let save_cfg =
+                                                strand_cam_csv_config_types::SaveCfgFview2_0_25 {
+                                                    name: app_name.to_string(),
+                                                    version: env!("CARGO_PKG_VERSION").to_string(),
+                                                    git_hash: env!("GIT_HASH").to_string(),
+                                                };
 
-                                        let object_detection_cfg = im_tracker.config();
+                                            let object_detection_cfg = im_tracker.config();
 
-                                        let full_cfg =
-                                            strand_cam_csv_config_types::FullCfgFview2_0_26 {
-                                                app: save_cfg,
-                                                camera: camera_cfg.clone(),
-                                                created_at: local,
-                                                csv_rate_limit: rate_limit,
-                                                object_detection_cfg,
-                                            };
-                                        let cfg_yaml = serde_yaml::to_string(&full_cfg).unwrap();
-                                        writeln!(fd, "# -- start of yaml config --")?;
-                                        for line in cfg_yaml.lines() {
-                                            writeln!(fd, "# {line}")?;
+                                            let full_cfg =
+                                                strand_cam_csv_config_types::FullCfgFview2_0_26 {
+                                                    app: save_cfg,
+                                                    camera: camera_cfg.clone(),
+                                                    created_at: local,
+                                                    csv_rate_limit: rate_limit,
+                                                    object_detection_cfg,
+                                                    provenance: Some(crate::provenance()),
+                                                };
+                                            let cfg_yaml =
+                                                serde_yaml::to_string(&full_cfg).unwrap();
+                                            writeln!(fd, "# -- start of yaml config --")?;
+                                            for line in cfg_yaml.lines() {
+                                                writeln!(fd, "# {line}")?;
+                                            }
+                                            writeln!(fd, "# -- end of yaml config --")?;
                                         }
-                                        writeln!(fd, "# -- end of yaml config --")?;
-                                    }
 
-                                    writeln!(fd, "time_microseconds,frame,x_px,y_px,orientation_radians_mod_pi,central_moment,led_1,led_2,led_3")?;
-                                    fd.flush()?;
+                                        writeln!(fd, "time_microseconds,frame,x_px,y_px,orientation_radians_mod_pi,central_moment,led_1,led_2,led_3")?;
+                                        fd.flush()?;
 
-                                    let min_interval_sec = if let Some(fps) = rate_limit {
-                                        1.0 / fps
-                                    } else {
-                                        0.0
-                                    };
-                                    let min_interval = chrono::Duration::nanoseconds(
-                                        (min_interval_sec * 1e9) as i64,
-                                    );
+                                        let min_interval_sec = if let Some(fps) = rate_limit {
+                                            1.0 / fps
+                                        } else {
+                                            0.0
+                                        };
+                                        let min_interval = chrono::Duration::nanoseconds(
+                                            (min_interval_sec * 1e9) as i64,
+                                        );
 
-                                    let inner = CsvSavingState {
-                                        fd,
-                                        min_interval,
-                                        last_save: now
-                                            .checked_sub_signed(
-                                                chrono::Duration::try_days(1).unwrap(),
-                                            )
-                                            .unwrap(),
-                                        t0: now,
-                                    };
-
-                                    new_state = Some(SavingState::Saving(inner));
-                                }
-                                SavingState::Saving(ref mut inner) => {
-                                    let interval = frame
-                                        .extra()
-                                        .host_timestamp()
-                                        .signed_duration_since(inner.last_save);
-                                    // save found points
-                                    if interval >= inner.min_interval && !points.is_empty() {
-                                        let time_microseconds = frame
-                                            .extra()
-                                            .host_timestamp()
-                                            .signed_duration_since(inner.t0)
-                                            .num_microseconds()
-                                            .unwrap();
-
-                                        let mut led1 = "".to_string();
-                                        let mut led2 = "".to_string();
-                                        let mut led3 = "".to_string();
-                                        {
-                                            if let Some(ref store) = store_cache {
-                                                if let Some(ref device_state) =
-                                                    store.led_box_device_state
-                                                {
-                                                    led1 = format!(
-                                                        "{}",
-                                                        crate::get_intensity(device_state, 1)
-                                                    );
-                                                    led2 = format!(
-                                                        "{}",
-                                                        crate::get_intensity(device_state, 2)
-                                                    );
-                                                    led3 = format!(
-                                                        "{}",
-                                                        crate::get_intensity(device_state, 3)
-                                                    );
+                                        let inner = CsvSavingState {
+                                            fd,
+                                            min_interval,
+                                            last_save: now
+                                                .checked_sub_signed(
+                                                    chrono::Duration::try_days(1).unwrap(),
+                                                )
+                                                .unwrap(),
+                                            t0: now,
+                                        };
+
+                                        new_state = Some(SavingState::Saving(inner));
+                                    }
+                                    SavingState::Saving(ref mut inner) => {
+                                        let interval = det_host_timestamp
+                                            .signed_duration_since(inner.last_save);
+                                        // save found points
+                                        if interval >= inner.min_interval && !points.is_empty() {
+                                            let time_microseconds = det_host_timestamp
+                                                .signed_duration_since(inner.t0)
+                                                .num_microseconds()
+                                                .unwrap();
+
+                                            let mut led1 = "".to_string();
+                                            let mut led2 = "".to_string();
+                                            let mut led3 = "".to_string();
+                                            {
+                                                if let Some(ref store) = store_cache {
+                                                    if let Some(ref device_state) =
+                                                        store.led_box_device_state
+                                                    {
+                                                        led1 = format!(
+                                                            "{}",
+                                                            crate::get_intensity(device_state, 1)
+                                                        );
+                                                        led2 = format!(
+                                                            "{}",
+                                                            crate::get_intensity(device_state, 2)
+                                                        );
+                                                        led3 = format!(
+                                                            "{}",
+                                                            crate::get_intensity(device_state, 3)
+                                                        );
+                                                    }
                                                 }
                                             }
+                                            for pt in points.iter() {
+                                                let orientation_mod_pi =
+                                                    match pt.maybe_slope_eccentricty {
+                                                        Some((slope, _ecc)) => {
+                                                            let orientation_mod_pi =
+                                                                f32::atan(slope as f32);
+                                                            format!("{orientation_mod_pi:.3}")
+                                                        }
+                                                        None => "".to_string(),
+                                                    };
+                                                writeln!(
+                                                    inner.fd,
+                                                    "{},{},{:.1},{:.1},{},{},{},{},{}",
+                                                    time_microseconds,
+                                                    det_framenumber,
+                                                    pt.x0_abs,
+                                                    pt.y0_abs,
+                                                    orientation_mod_pi,
+                                                    pt.area,
+                                                    led1,
+                                                    led2,
+                                                    led3
+                                                )?;
+                                                inner.fd.flush()?;
+                                            }
+                                            inner.last_save = det_host_timestamp;
                                         }
-                                        for pt in points.iter() {
-                                            let orientation_mod_pi =
-                                                match pt.maybe_slope_eccentricty {
-                                                    Some((slope, _ecc)) => {
-                                                        let orientation_mod_pi =
-                                                            f32::atan(slope as f32);
-                                                        format!("{orientation_mod_pi:.3}")
-                                                    }
-                                                    None => "".to_string(),
-                                                };
-                                            writeln!(
-                                                inner.fd,
-                                                "{},{},{:.1},{:.1},{},{},{},{},{}",
-                                                time_microseconds,
-                                                frame.extra().host_framenumber(),
-                                                pt.x0_abs,
-                                                pt.y0_abs,
-                                                orientation_mod_pi,
-                                                pt.area,
-                                                led1,
-                                                led2,
-                                                led3
-                                            )?;
-                                            inner.fd.flush()?;
-                                        }
-                                        inner.last_save = frame.extra().host_timestamp();
                                     }
                                 }
-                            }
-                            if let Some(ns) = new_state {
-                                csv_save_state = ns;
-                            }
+                                if let Some(ns) = new_state {
+                                    csv_save_state = ns;
+                                }
+
+                                let display_points: Vec<_> = points
+                                    .iter()
+                                    .map(|pt| video_streaming::Point {
+                                        x: pt.x0_abs as f32,
+                                        y: pt.y0_abs as f32,
+                                        theta: pt
+                                            .maybe_slope_eccentricty
+                                            .map(|(slope, _ecc)| f32::atan(slope as f32)),
+                                        area: Some(pt.area as f32),
+                                    })
+                                    .collect();
 
-                            let display_points: Vec<_> = points
-                                .iter()
-                                .map(|pt| video_streaming::Point {
-                                    x: pt.x0_abs as f32,
-                                    y: pt.y0_abs as f32,
-                                    theta: pt
-                                        .maybe_slope_eccentricty
-                                        .map(|(slope, _ecc)| f32::atan(slope as f32)),
-                                    area: Some(pt.area as f32),
-                                })
-                                .collect();
-
-                            all_points.extend(display_points);
+                                all_points.extend(display_points);
+                            }
                             blkajdsfads = Some(im_tracker.valid_region())
                         }
                     }
                     (all_points, blkajdsfads)
                 };
 
+                // Based on our recording framerate, do we need to save this
+                // frame to FMF? This decision is made once so that it can be
+                // shared with the MP4 writer when recording both.
+                let fmf_do_save = match &fmf_writer {
+                    Some(inner) => Some(inner.do_save(save_mp4_fmf_stamp)?),
+                    None => None,
+                };
+
                 if let Some(ref mut inner) = my_mp4_writer {
-                    let data = frame.clone(); // copy entire frame data
-                    inner.write(data, save_mp4_fmf_stamp)?;
+                    // Do not open new files while resource usage is critical.
+                    let postpone_segments = store_cache
+                        .as_ref()
+                        .and_then(|x| x.resource_usage.as_ref())
+                        .is_some_and(|x| x.level == resource_watcher::ResourceLevel::Critical);
+                    inner.set_postpone_segments(postpone_segments);
+                    if let Some(ref mut proxy) = my_mp4_proxy_writer {
+                        proxy.set_postpone_segments(postpone_segments);
+                    }
+                    let do_save = if mp4_fmf_synced {
+                        fmf_do_save.unwrap_or(true)
+                    } else {
+                        true
+                    };
+                    if do_save {
+                        let mut data = frame.clone(); // copy entire frame data
+                        if let Some(ref mut watermarker) = my_mp4_watermarker {
+                            watermarker.apply(&mut data, save_mp4_fmf_stamp);
+                        }
+                        if let Some(ref mut proxy) = my_mp4_proxy_writer {
+                            proxy.write(&data, save_mp4_fmf_stamp)?;
+                        }
+                        if let Err(e) = inner.write(data, save_mp4_fmf_stamp) {
+                            // With an NVENC pool, a failed encoding session is
+                            // restarted on the device assigned by the pool.
+                            let is_nvenc = store_cache.as_ref().is_some_and(|x| {
+                                x.mp4_codec == ci2_remote_control::CodecSelection::H264Nvenc
+                            });
+                            let pool = match (&e, nvenc_pool.as_mut()) {
+                                (bg_movie_writer::Error::Mp4WriterError(_), Some(pool))
+                                    if is_nvenc =>
+                                {
+                                    pool
+                                }
+                                _ => return Err(e.into()),
+                            };
+                            error!(
+                                "MP4 encoding on CUDA device {} failed: {e}",
+                                pool.cuda_device()
+                            );
+                            pool.report_failure()?;
+                            restart_mp4_writer(
+                                &mut my_mp4_writer,
+                                &mut my_mp4_proxy_writer,
+                                &mut my_mp4_watermarker,
+                                shared_store_arc.as_ref().unwrap(),
+                                &data_dir,
+                                uploader.as_ref(),
+                                pool,
+                                &output_latency,
+                            )?;
+                        }
+                    }
                 }
 
                 if let Some(ref mut inner) = fmf_writer {
-                    // Based on our recording framerate, do we need to save this frame?
-                    let do_save = match inner.last_saved_stamp {
-                        None => true,
-                        Some(stamp) => {
-                            let elapsed = save_mp4_fmf_stamp - stamp;
-                            elapsed
-                                >= chrono::Duration::from_std(inner.recording_framerate.interval())?
-                        }
-                    };
-                    if do_save {
+                    if fmf_do_save == Some(true) {
                         match_all_dynamic_fmts!(&frame, x, {
                             inner.writer.write(x, save_mp4_fmf_stamp)?
                         });
@@ -1312,6 +1527,14 @@ pub(crate) async fn frame_process_task<'a>(
                     }
                 }
 
+                if let Some(roi_follower) = roi_follower.as_mut() {
+                    roi_follower.process(&frame, &found_points)?;
+                }
+
+                if let Some(adaptive_frame_rate) = adaptive_frame_rate.as_mut() {
+                    adaptive_frame_rate.process(found_points.len(), store_cache.as_ref());
+                }
+
                 let found_points = found_points
                     .iter()
                     .map(
@@ -1324,6 +1547,18 @@ pub(crate) async fn frame_process_task<'a>(
                     )
                     .collect();
 
+                heartbeat_counters.frame_processed(extracted_frame_info.host_timestamp);
+
+                if let Some(ref store_cache_ref) = store_cache {
+                    pipeline_history.push(
+                        extracted_frame_info.host_framenumber as u64,
+                        extracted_frame_info.host_timestamp,
+                        &store_cache_ref.im_pt_detect_cfg,
+                        store_cache_ref.is_doing_object_detection,
+                        detection_summary,
+                    );
+                }
+
                 // check led_box device heartbeat
                 if let Some(reader) = *led_box_heartbeat_update_arc.read() {
                     let elapsed = reader.elapsed();
@@ -1354,6 +1589,24 @@ pub(crate) async fn frame_process_task<'a>(
                 #[cfg(not(feature = "flydratrax"))]
                 let annotations = vec![];
 
+                #[cfg(feature = "flydra_feat_detect")]
+                let annotations = {
+                    let mut annotations = annotations;
+                    if is_doing_object_detection {
+                        if let Some(ref store_cache_ref) = store_cache {
+                            let cfg = &store_cache_ref.im_pt_detect_cfg;
+                            annotations.extend(cfg.exclude_regions.iter().map(|polygon| {
+                                video_streaming::DrawableShape::from_shape(
+                                    &video_streaming::Shape::Polygon(polygon.clone()),
+                                    &exclude_style,
+                                    1.0,
+                                )
+                            }));
+                        }
+                    }
+                    annotations
+                };
+
                 if firehose_tx.capacity() == 0 {
                     trace!("cannot transmit frame for viewing: channel full");
                 } else {
@@ -1366,7 +1619,9 @@ pub(crate) async fn frame_process_task<'a>(
                         })
                         .await;
                     match result {
-                        Ok(()) => {}
+                        Ok(()) => {
+                            output_latency.frame_sent(Output::Preview, save_mp4_fmf_stamp);
+                        }
                         Err(e) => {
                             tracing::error!(
                                 "error while sending frame for display in browser: {e} {e:?}"
@@ -1477,6 +1732,14 @@ pub(crate) async fn frame_process_task<'a>(
             Msg::ClearBackground(value) => {
                 im_tracker.do_clear_background(value)?;
             }
+            #[cfg(feature = "flydra_feat_detect")]
+            Msg::ResetBackgroundModel => {
+                im_tracker.do_reset_background_to_current_image()?;
+            }
+            #[cfg(feature = "flydra_feat_detect")]
+            Msg::SetBackgroundFrozen(frozen) => {
+                im_tracker.set_background_frozen(frozen);
+            }
             Msg::SetFrameOffset(fo) => {
                 opt_frame_offset = Some(fo);
                 #[cfg(feature = "flydra_feat_detect")]
@@ -1485,21 +1748,151 @@ pub(crate) async fn frame_process_task<'a>(
                 }
             }
             Msg::SetTriggerboxClockModel(cm) => {
+                if cm.is_none() {
+                    // Braid is (re)synchronizing the cameras.
+                    if let Some(watchdog) = frame_watchdog.as_mut() {
+                        watchdog.disarm();
+                    }
+                }
                 triggerbox_clock_model = cm;
             }
+            Msg::SetClockSkewModel(cm) => {
+                clock_skew_model = Some(cm);
+            }
+            Msg::RoiOffsetApplied(offset) => {
+                if let Some(roi_follower) = roi_follower.as_mut() {
+                    roi_follower.offset_applied(offset);
+                }
+            }
             Msg::StopMp4 => {
+                stop_mp4_writer(
+                    &mut my_mp4_writer,
+                    &mut my_mp4_proxy_writer,
+                    &shared_store_arc,
+                )?;
+                mp4_fmf_synced = false;
+            }
+            Msg::StopFMF => {
+                stop_fmf_writer(&mut fmf_writer)?;
+                mp4_fmf_synced = false;
+            }
+            Msg::StopMp4AndFMF => {
+                stop_mp4_writer(
+                    &mut my_mp4_writer,
+                    &mut my_mp4_proxy_writer,
+                    &shared_store_arc,
+                )?;
+                stop_fmf_writer(&mut fmf_writer)?;
+                mp4_fmf_synced = false;
+            }
+            Msg::StopAllRecordings => {
+                stop_mp4_writer(
+                    &mut my_mp4_writer,
+                    &mut my_mp4_proxy_writer,
+                    &shared_store_arc,
+                )?;
+                stop_fmf_writer(&mut fmf_writer)?;
+                mp4_fmf_synced = false;
+                #[cfg(feature = "flydra_feat_detect")]
+                {
+                    ufmf_state = Some(flydra_feature_detector::UfmfState::Stopped);
+                }
+            }
+            Msg::Shutdown(shutdown_tx) => {
+                // Send errors are ignored: the shutdown continues even if
+                // nobody waits for the reports anymore.
+                let mut pending = Vec::new();
+                if my_mp4_writer.is_some() {
+                    pending.push("MP4");
+                }
+                if my_mp4_proxy_writer.is_some() {
+                    pending.push("MP4 proxy");
+                }
+                if fmf_writer.is_some() {
+                    pending.push("FMF");
+                }
+                #[cfg(feature = "flydra_feat_detect")]
+                {
+                    if matches!(
+                        ufmf_state,
+                        Some(flydra_feature_detector::UfmfState::Saving(_))
+                    ) {
+                        pending.push("UFMF");
+                    }
+                    if matches!(csv_save_state, SavingState::Saving(_)) {
+                        pending.push("object detection CSV");
+                    }
+                }
+                #[cfg(feature = "fiducial")]
+                if apriltag_writer.is_some() {
+                    pending.push("AprilTag CSV");
+                }
+                let _ = shutdown_tx.send(ShutdownEvent::Pending(pending));
+
+                // The MP4 writers run in their own threads. Wait until they
+                // wrote all queued frames and the index.
                 if let Some(mut inner) = my_mp4_writer.take() {
-                    inner.finish()?;
+                    let result = finish_blocking(move || inner.finish_and_wait()).await;
+                    let _ = shutdown_tx.send(ShutdownEvent::Finished("MP4", result));
                 }
-                if let Some(ref mut store) = shared_store_arc {
-                    let mut tracker = store.write();
-                    tracker.modify(|tracker| {
-                        tracker.is_recording_mp4 = None;
-                    });
+                if let Some(mut inner) = my_mp4_proxy_writer.take() {
+                    let result = finish_blocking(move || inner.finish_and_wait()).await;
+                    let _ = shutdown_tx.send(ShutdownEvent::Finished("MP4 proxy", result));
+                }
+                mp4_fmf_synced = false;
+                if fmf_writer.is_some() {
+                    let result = stop_fmf_writer(&mut fmf_writer);
+                    let _ = shutdown_tx.send(ShutdownEvent::Finished("FMF", result));
+                }
+                #[cfg(feature = "flydra_feat_detect")]
+                {
+                    if let Some(flydra_feature_detector::UfmfState::Saving(mut inner)) =
+                        ufmf_state.replace(flydra_feature_detector::UfmfState::Stopped)
+                    {
+                        let result = inner.close().map(|_| ()).map_err(eyre::Report::from);
+                        let _ = shutdown_tx.send(ShutdownEvent::Finished("UFMF", result));
+                    }
+                    if let SavingState::Saving(mut inner) =
+                        std::mem::replace(&mut csv_save_state, SavingState::NotSaving)
+                    {
+                        let result = inner.fd.flush().map_err(eyre::Report::from);
+                        let _ = shutdown_tx
+                            .send(ShutdownEvent::Finished("object detection CSV", result));
+                    }
+                    #[cfg(feature = "flydratrax")]
+                    if let Some(braidz_write_tx) = opt_braidz_write_tx_weak
+                        .as_ref()
+                        .and_then(|weak| weak.upgrade())
+                    {
+                        // The braidz writer finishes its file when it ends.
+                        let _ = braidz_write_tx
+                            .send(flydra2::SaveToDiskMsg::StopSavingCsv)
+                            .await;
+                    }
+                }
+                #[cfg(feature = "fiducial")]
+                if let Some(mut inner) = apriltag_writer.take() {
+                    // Dropping the writer finishes the compressed file.
+                    let result = inner.wtr.flush().map_err(eyre::Report::from);
+                    let _ = shutdown_tx.send(ShutdownEvent::Finished("AprilTag CSV", result));
                 }
             }
-            Msg::StopFMF => {
-                fmf_writer = None;
+            Msg::SaveBugReport(filename) => {
+                if let (Some(ssa), Some(store_cache_ref)) = (&shared_store_arc, &store_cache) {
+                    let path = data_dir.join(&filename);
+                    match pipeline_history.write_bug_report(&path, store_cache_ref) {
+                        Ok(()) => {
+                            info!("saved bug report to \"{}\"", path.display());
+                            let mut tracker = ssa.write();
+                            tracker.modify(|tracker| {
+                                tracker.bug_report_filename = Some(filename);
+                            });
+                        }
+                        Err(e) => {
+                            error!("could not save bug report \"{}\": {e}", path.display());
+                        }
+                    }
+                }
             }
             #[cfg(feature = "flydra_feat_detect")]
             Msg::StopUFMF => {
@@ -1510,6 +1903,31 @@ pub(crate) async fn frame_process_task<'a>(
                 is_doing_object_detection = value;
             }
         };
+
+        if let Some(event_publisher) = &event_publisher {
+            let recording_state = RecordingState {
+                mp4: my_mp4_writer.is_some(),
+                fmf: fmf_writer.is_some(),
+                #[cfg(feature = "flydra_feat_detect")]
+                ufmf: matches!(
+                    ufmf_state,
+                    Some(
+                        flydra_feature_detector::UfmfState::Starting(_)
+                            | flydra_feature_detector::UfmfState::Saving(_)
+                    )
+                ),
+                #[cfg(not(feature = "flydra_feat_detect"))]
+                ufmf: false,
+                csv: !matches!(csv_save_state, SavingState::NotSaving),
+            };
+            if published_recording_state != Some(recording_state) {
+                event_publisher.publish(Event::RecordingState(RecordingStateEvent::new(
+                    raw_cam_name.as_str(),
+                    recording_state,
+                )));
+                published_recording_state = Some(recording_state);
+            }
+        }
     }
     info!(
         "frame process thread done for camera '{}'",
@@ -1518,6 +1936,246 @@ pub(crate) async fn frame_process_task<'a>(
     Ok(())
 }
 
+/// Describes an MP4 recording with a label, tags or a watermark. Saved next to
+/// the MP4 file with the extension `.yaml`.
+#[derive(Serialize)]
+struct RecordingManifest<'a> {
+    camera_name: &'a str,
+    mp4_filename: &'a str,
+    creation_time: chrono::DateTime<chrono::Local>,
+    label: &'a str,
+    tags: &'a [String],
+    /// The watermark drawn into the frames, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    watermark: Option<&'a ci2_remote_control::ComplianceWatermarkConfig>,
+}
+
+/// Create a new MP4 writer and update the shared store accordingly.
+///
+/// If enabled, a writer for the reduced-resolution proxy file and the
+/// watermark drawn into the frames are also created. Any `frames` given (e.g.
+/// from the post trigger buffer) are written first. With an `uploader`, each
+/// finished file is queued for upload. If `nvenc_pool` is given, NVENC
+/// encoding uses the CUDA device assigned by the pool.
+fn start_mp4_writer(
+    frames: std::collections::VecDeque<DynamicFrame>,
+    shared_store_arc: &Arc<parking_lot::RwLock<ChangeTracker<StoreType>>>,
+    data_dir: &Path,
+    uploader: Option<&Uploader>,
+    nvenc_pool: Option<&NvencPool>,
+    output_latency: &Arc<OutputLatencyCounters>,
+) -> Result<(
+    bg_movie_writer::BgMovieWriter,
+    Option<Mp4ProxyWriter>,
+    Option<Watermarker>,
+)> {
+    let local = chrono::Local::now();
+
+    // Get start time, either from buffered frames if present or current time.
+    let creation_time = if let Some(frame0) = frames.front() {
+        frame0.extra().host_timestamp().into()
+    } else {
+        local
+    };
+
+    let (
+        format_str_mp4,
+        mp4_recording_config,
+        mp4_proxy_enabled,
+        segment_config,
+        camera_name,
+        recording_label,
+        watermark,
+    ) = {
+        // scope for reading cache
+        let tracker = shared_store_arc.read();
+        let shared: &StoreType = tracker.as_ref();
+
+        let mut mp4_recording_config = FinalMp4RecordingConfig::new(shared, creation_time);
+        if let (
+            Some(pool),
+            ci2_remote_control::RecordingConfig::Mp4(ci2_remote_control::Mp4RecordingConfig {
+                codec: ci2_remote_control::Mp4Codec::H264NvEnc(opts),
+                ..
+            }),
+        ) = (nvenc_pool, &mut mp4_recording_config.final_cfg)
+        {
+            opts.cuda_device = pool.cuda_device();
+        }
+
+        (
+            shared.format_str_mp4.clone(),
+            mp4_recording_config,
+            shared.mp4_proxy_enabled,
+            shared.mp4_segment_config.clone(),
+            shared.camera_name.clone(),
+            shared.recording_label.clone(),
+            shared.compliance_watermark.clone(),
+        )
+    };
+
+    let filename = creation_time.format(format_str_mp4.as_str()).to_string();
+    // All segments of the recording share the manifest of the first file.
+    let manifest_path = data_dir.join(Path::new(&filename).with_extension("yaml"));
+    if !recording_label.is_empty() || watermark.is_some() {
+        let manifest = RecordingManifest {
+            camera_name: &camera_name,
+            mp4_filename: &filename,
+            creation_time,
+            label: &recording_label.label,
+            tags: &recording_label.tags,
+            watermark: watermark.as_ref(),
+        };
+        let path = &manifest_path;
+        if let Err(e) = File::create(path)
+            .map_err(eyre::Report::from)
+            .and_then(|fd| Ok(serde_yaml::to_writer(fd, &manifest)?))
+        {
+            error!(
+                "could not save recording manifest \"{}\": {e}",
+                path.display()
+            );
+        }
+    }
+    let is_recording_mp4 = Some(RecordingPath::new(filename.clone()));
+
+    let mut proxy = if mp4_proxy_enabled {
+        // The proxy file is much smaller than the main file, so only split it
+        // by duration to keep the segments of both files aligned.
+        let proxy_segment_config = ci2_remote_control::Mp4SegmentConfig {
+            max_size_bytes: None,
+            ..segment_config.clone()
+        };
+        Some(Mp4ProxyWriter::new(
+            &format_str_mp4,
+            mp4_recording_config.final_cfg.clone(),
+            proxy_segment_config,
+            frames.len() + 100,
+            data_dir.to_path_buf(),
+        ))
+    } else {
+        None
+    };
+
+    let mut watermarker = watermark.map(|cfg| Watermarker::new(cfg, &camera_name));
+
+    let mut raw = bg_movie_writer::BgMovieWriter::new_segmented(
+        format_str_mp4,
+        mp4_recording_config.final_cfg,
+        segment_config,
+        frames.len() + 100,
+        Some(data_dir.to_path_buf()),
+    );
+    if let Some(uploader) = uploader {
+        let uploader = uploader.clone();
+        raw.set_on_file_finished(Box::new(move |path| {
+            uploader.queue(path, manifest_path.clone())
+        }));
+    }
+    {
+        let output_latency = output_latency.clone();
+        raw.set_on_frame_written(Box::new(move |stamp| {
+            output_latency.frame_sent(Output::Mp4, stamp);
+        }));
+    }
+    for mut frame in frames.into_iter() {
+        // Force frame width to be power of 2.
+        let val = 2;
+        let clipped_width = (frame.width() / val as u32) * val as u32;
+        match_all_dynamic_fmts!(&mut frame, x, { x.width = clipped_width });
+        // frame.width = clipped_width;
+        let ts = frame.extra().host_timestamp();
+        if let Some(watermarker) = watermarker.as_mut() {
+            watermarker.apply(&mut frame, ts);
+        }
+        if let Some(proxy) = proxy.as_mut() {
+            proxy.write(&frame, ts)?;
+        }
+        raw.write(frame, ts)?;
+    }
+
+    {
+        let mut tracker = shared_store_arc.write();
+        tracker.modify(|tracker| {
+            tracker.is_recording_mp4 = is_recording_mp4;
+        });
+    }
+    Ok((raw, proxy, watermarker))
+}
+
+/// Replace the MP4 writers after a failed NVENC encoding session by new ones
+/// using the CUDA device now assigned by `nvenc_pool`.
+///
+/// The frames written before the failure are saved by the failed writer; the
+/// frames still queued in it are lost.
+fn restart_mp4_writer(
+    my_mp4_writer: &mut Option<bg_movie_writer::BgMovieWriter>,
+    my_mp4_proxy_writer: &mut Option<Mp4ProxyWriter>,
+    my_mp4_watermarker: &mut Option<Watermarker>,
+    shared_store_arc: &Arc<parking_lot::RwLock<ChangeTracker<StoreType>>>,
+    data_dir: &Path,
+    uploader: Option<&Uploader>,
+    nvenc_pool: &NvencPool,
+    output_latency: &Arc<OutputLatencyCounters>,
+) -> Result<()> {
+    // The writer thread has already ended with the error, which was reported.
+    // Wait until it has finished and renamed the file of the frames written
+    // before the error.
+    if let Some(mut failed) = my_mp4_writer.take() {
+        let _ = failed.finish_and_wait();
+    }
+    if let Some(mut proxy) = my_mp4_proxy_writer.take() {
+        proxy.finish()?;
+    }
+    {
+        let mut tracker = shared_store_arc.write();
+        tracker.modify(|tracker| {
+            tracker.mp4_cuda_device = nvenc_pool.device_name().to_string();
+        });
+    }
+    let (mp4_writer, mp4_proxy_writer, watermarker) = start_mp4_writer(
+        std::collections::VecDeque::with_capacity(0),
+        shared_store_arc,
+        data_dir,
+        uploader,
+        Some(nvenc_pool),
+        output_latency,
+    )?;
+    *my_mp4_writer = Some(mp4_writer);
+    *my_mp4_proxy_writer = mp4_proxy_writer;
+    *my_mp4_watermarker = watermarker;
+    Ok(())
+}
+
+/// Finish the FMF writer, if any.
+fn stop_fmf_writer(fmf_writer: &mut Option<FmfWriteInfo<File>>) -> Result<()> {
+    if let Some(inner) = fmf_writer.take() {
+        inner.finish()?;
+    }
+    Ok(())
+}
+
+/// Finish the MP4 writers, if any, and update the shared store accordingly.
+fn stop_mp4_writer(
+    my_mp4_writer: &mut Option<bg_movie_writer::BgMovieWriter>,
+    my_mp4_proxy_writer: &mut Option<Mp4ProxyWriter>,
+    shared_store_arc: &Option<Arc<parking_lot::RwLock<ChangeTracker<StoreType>>>>,
+) -> Result<()> {
+    if let Some(mut inner) = my_mp4_writer.take() {
+        inner.finish()?;
+    }
+    if let Some(mut inner) = my_mp4_proxy_writer.take() {
+        inner.finish()?;
+    }
+    if let Some(ref store) = shared_store_arc {
+        let mut tracker = store.write();
+        tracker.modify(|tracker| {
+            tracker.is_recording_mp4 = None;
+        });
+    }
+    Ok(())
+}
+
 #[cfg(feature = "fiducial")]
 fn make_family(family: &ci2_remote_control::TagFamily) -> apriltag::Family {
     use ci2_remote_control::TagFamily::*;
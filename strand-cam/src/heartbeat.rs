@@ -0,0 +1,93 @@
+//! Periodic camera health messages ("heartbeats") sent to Braid.
+//!
+//! When running within Braid, a [CamHeartbeat] is sent every
+//! [HEARTBEAT_INTERVAL] with the measured frame rate, the number of dropped
+//! frames, the image processing latency, the latency of each output, the
+//! number of detections rejected by the detection filter and the recordings
+//! currently running.
+//! Braid shows these in its browser UI and its JSON API.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use tracing::debug;
+
+use async_change_tracker::ChangeTracker;
+use flydra_types::{BraidHttpApiCallback, CamHeartbeat, PerCam, RawCamName};
+use strand_cam_storetype::StoreType;
+
+/// How often a heartbeat is sent.
+const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Counters updated by the frame acquisition and processing tasks.
+#[derive(Default)]
+pub(crate) struct HeartbeatCounters {
+    dropped_frames: AtomicU64,
+    filtered_detections: AtomicU64,
+    /// Largest latency since the previous heartbeat, in microseconds. Zero if
+    /// no frame was processed.
+    max_latency_usec: AtomicU64,
+}
+
+impl HeartbeatCounters {
+    /// A frame was dropped because the processing task did not keep up.
+    pub(crate) fn frame_dropped(&self) {
+        self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The detection filter rejected `n` detections.
+    pub(crate) fn detections_filtered(&self, n: u64) {
+        self.filtered_detections.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Processing of the frame acquired at `host_timestamp` finished.
+    pub(crate) fn frame_processed(&self, host_timestamp: chrono::DateTime<chrono::Utc>) {
+        let latency_usec = (chrono::Utc::now() - host_timestamp)
+            .num_microseconds()
+            .unwrap_or(i64::MAX)
+            .max(1) as u64;
+        self.max_latency_usec
+            .fetch_max(latency_usec, Ordering::Relaxed);
+    }
+}
+
+/// Send a heartbeat to Braid every [HEARTBEAT_INTERVAL] until the connection
+/// is closed.
+pub(crate) async fn run(
+    counters: Arc<HeartbeatCounters>,
+    raw_cam_name: RawCamName,
+    shared_store_arc: Arc<parking_lot::RwLock<ChangeTracker<StoreType>>>,
+    transmit_msg_tx: tokio::sync::mpsc::Sender<BraidHttpApiCallback>,
+) {
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let max_latency_usec = counters.max_latency_usec.swap(0, Ordering::Relaxed);
+        let heartbeat = {
+            let tracker = shared_store_arc.read();
+            let shared: &StoreType = tracker.as_ref();
+            CamHeartbeat {
+                host_timestamp: chrono::Utc::now(),
+                measured_fps: shared.measured_fps,
+                dropped_frames: counters.dropped_frames.load(Ordering::Relaxed),
+                max_latency_msec: (max_latency_usec > 0).then(|| max_latency_usec as f64 / 1000.0),
+                filtered_detections: counters.filtered_detections.load(Ordering::Relaxed),
+                output_latency: shared.output_latency.clone(),
+                is_recording_mp4: shared.is_recording_mp4.is_some(),
+                is_recording_fmf: shared.is_recording_fmf.is_some(),
+                is_recording_ufmf: shared.is_recording_ufmf.is_some(),
+                is_saving_csv: shared.is_saving_im_pt_detect_csv.is_some(),
+            }
+        };
+        let msg = BraidHttpApiCallback::Heartbeat(PerCam {
+            raw_cam_name: raw_cam_name.clone(),
+            inner: heartbeat,
+        });
+        if transmit_msg_tx.send(msg).await.is_err() {
+            debug!("connection to Braid closed, stopping heartbeat");
+            break;
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Idle mode: stop acquisition while the camera is not used.
+//!
+//! On battery-powered rigs, acquiring and processing frames which nobody
+//! looks at wastes power. With idle mode enabled, acquisition is stopped once
+//! the camera was not used for [IdleModeConfig::idle_after]. The camera is in
+//! use while a browser UI or MJPEG preview client is connected, while
+//! anything is recorded or saved, while the post-trigger buffer is enabled
+//! and while image operation results are sent. Acquisition is restarted
+//! immediately when a client connects or sends a command, and within
+//! [CHECK_INTERVAL] when the camera is otherwise used again (e.g. by a
+//! scheduled recording). Idle mode is not used with Braid, which requires
+//! frames from all cameras.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_change_tracker::ChangeTracker;
+use tracing::{error, info};
+
+use ci2_async::AcquisitionControl;
+use strand_cam_storetype::StoreType;
+
+/// How often the use of the camera is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Configuration of the idle mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdleModeConfig {
+    /// Acquisition is stopped after the camera was not used for this long.
+    pub idle_after: Duration,
+}
+
+impl Default for IdleModeConfig {
+    fn default() -> Self {
+        Self {
+            idle_after: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// A change of acquisition decided by an [IdlePolicy].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transition {
+    Stop,
+    Start,
+}
+
+/// Decides when acquisition is stopped and restarted.
+struct IdlePolicy {
+    idle_after: Duration,
+    last_used: Instant,
+}
+
+impl IdlePolicy {
+    fn new(idle_after: Duration, now: Instant) -> Self {
+        Self {
+            idle_after,
+            last_used: now,
+        }
+    }
+
+    /// Return the change of acquisition to make, if any, given whether the
+    /// camera is in use and whether acquisition is currently stopped.
+    fn update(&mut self, in_use: bool, is_stopped: bool, now: Instant) -> Option<Transition> {
+        if in_use {
+            self.last_used = now;
+            return is_stopped.then_some(Transition::Start);
+        }
+        if is_stopped || now.duration_since(self.last_used) < self.idle_after {
+            return None;
+        }
+        // If stopping fails, it is tried again only after another
+        // `idle_after`.
+        self.last_used = now;
+        Some(Transition::Stop)
+    }
+}
+
+/// Describe what uses the camera, or return `None` if it is not used.
+fn usage(store: &StoreType, n_clients: usize, n_preview_clients: usize) -> Option<String> {
+    if n_clients > 0 {
+        return Some(format!("{n_clients} browser UI client(s) connected"));
+    }
+    if n_preview_clients > 0 {
+        return Some(format!(
+            "{n_preview_clients} MJPEG preview client(s) connected"
+        ));
+    }
+    let is_recording_csv = store
+        .apriltag_state
+        .as_ref()
+        .map(|state| state.is_recording_csv.is_some())
+        .unwrap_or(false);
+    if store.is_recording_mp4.is_some()
+        || store.is_recording_fmf.is_some()
+        || store.is_recording_ufmf.is_some()
+        || store.is_saving_im_pt_detect_csv.is_some()
+        || is_recording_csv
+    {
+        return Some("recording".to_string());
+    }
+    if store.post_trigger_buffer_size > 0 {
+        return Some("post-trigger buffer enabled".to_string());
+    }
+    if store.im_ops_state.do_detection {
+        return Some("image operations enabled".to_string());
+    }
+    None
+}
+
+/// Stop and restart acquisition according to the use of the camera.
+///
+/// `num_clients` returns the number of connected browser UI clients. `wake`
+/// is notified when a client connects or sends a command, which restarts
+/// acquisition without waiting for the next check. This function never
+/// returns and is intended to be spawned as a task.
+pub(crate) async fn run<C, F>(
+    cfg: IdleModeConfig,
+    control: AcquisitionControl<C>,
+    num_clients: F,
+    mjpeg_preview: http_video_streaming::MjpegPreview,
+    shared_store_arc: Arc<parking_lot::RwLock<ChangeTracker<StoreType>>>,
+    wake: Arc<tokio::sync::Notify>,
+) where
+    C: 'static + ci2::Camera + Send,
+    F: Fn() -> usize,
+{
+    info!(
+        "Idle mode: acquisition is stopped after {} seconds without use.",
+        cfg.idle_after.as_secs_f64()
+    );
+    let mut policy = IdlePolicy::new(cfg.idle_after, Instant::now());
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        let woken = tokio::select! {
+            _ = interval.tick() => false,
+            _ = wake.notified() => true,
+        };
+        let reason = if woken {
+            Some("client request".to_string())
+        } else {
+            let tracker = shared_store_arc.read();
+            usage(
+                tracker.as_ref(),
+                num_clients(),
+                mjpeg_preview.num_subscribers(),
+            )
+        };
+        let transition = policy.update(reason.is_some(), control.is_paused(), Instant::now());
+        match transition {
+            Some(Transition::Stop) => {
+                info!(
+                    "Camera not used for {} seconds, stopping acquisition.",
+                    cfg.idle_after.as_secs_f64()
+                );
+                // Set before stopping so that the stall of frames is not
+                // reported as an anomaly.
+                set_idle(&shared_store_arc, true);
+                let control = control.clone();
+                // Stopping waits for the frame being acquired.
+                let result = tokio::task::spawn_blocking(move || control.pause()).await;
+                if let Err(e) = flatten(result) {
+                    error!("could not stop acquisition: {e}");
+                    set_idle(&shared_store_arc, false);
+                }
+            }
+            Some(Transition::Start) => {
+                info!(
+                    "Restarting acquisition ({}).",
+                    reason.as_deref().unwrap_or_default()
+                );
+                let control = control.clone();
+                let result = tokio::task::spawn_blocking(move || control.resume()).await;
+                match flatten(result) {
+                    Ok(()) => set_idle(&shared_store_arc, false),
+                    Err(e) => error!("could not restart acquisition: {e}"),
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+fn flatten(result: Result<ci2::Result<()>, tokio::task::JoinError>) -> Result<(), String> {
+    match result {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn set_idle(shared_store_arc: &parking_lot::RwLock<ChangeTracker<StoreType>>, idle: bool) {
+    let mut tracker = shared_store_arc.write();
+    tracker.modify(|shared| shared.acquisition_idle = idle);
+}
+
+#[test]
+fn test_idle_policy() {
+    let start = Instant::now();
+    let minute = Duration::from_secs(60);
+    let mut policy = IdlePolicy::new(10 * minute, start);
+
+    // Not stopped before `idle_after`, and use resets the timer.
+    assert_eq!(policy.update(false, false, start + 9 * minute), None);
+    assert_eq!(policy.update(true, false, start + 9 * minute), None);
+    assert_eq!(policy.update(false, false, start + 18 * minute), None);
+    assert_eq!(
+        policy.update(false, false, start + 19 * minute),
+        Some(Transition::Stop)
+    );
+
+    // Restarted immediately when used.
+    assert_eq!(policy.update(false, true, start + 60 * minute), None);
+    assert_eq!(
+        policy.update(true, true, start + 61 * minute),
+        Some(Transition::Start)
+    );
+    assert_eq!(policy.update(false, false, start + 62 * minute), None);
+
+    // A failed stop is retried after another `idle_after`.
+    assert_eq!(
+        policy.update(false, false, start + 71 * minute),
+        Some(Transition::Stop)
+    );
+    assert_eq!(policy.update(false, false, start + 72 * minute), None);
+    assert_eq!(
+        policy.update(false, false, start + 81 * minute),
+        Some(Transition::Stop)
+    );
+}
@@ -0,0 +1,166 @@
+use std::path::PathBuf;
+
+use basic_frame::{match_all_dynamic_fmts, DynamicFrame};
+use formats::{PixFmt, Stride};
+use machine_vision_formats as formats;
+use tracing::warn;
+
+/// Writes a reduced-resolution copy of each frame saved to the main MP4 file.
+///
+/// The proxy file uses the same recording configuration, and thus the same
+/// metadata, as the main file and is given the same timestamps. The underlying
+/// writer is only created once the first frame has been downscaled
+/// successfully.
+pub(crate) struct Mp4ProxyWriter {
+    format_str_mp4: String,
+    recording_config: ci2_remote_control::RecordingConfig,
+    segment_config: ci2_remote_control::Mp4SegmentConfig,
+    queue_size: usize,
+    data_dir: PathBuf,
+    inner: Option<bg_movie_writer::BgMovieWriter>,
+    unsupported: bool,
+    postpone_segments: bool,
+}
+
+impl Mp4ProxyWriter {
+    pub(crate) fn new(
+        format_str_mp4: &str,
+        recording_config: ci2_remote_control::RecordingConfig,
+        segment_config: ci2_remote_control::Mp4SegmentConfig,
+        queue_size: usize,
+        data_dir: PathBuf,
+    ) -> Self {
+        Self {
+            format_str_mp4: proxy_format_str(format_str_mp4),
+            recording_config,
+            segment_config,
+            queue_size,
+            data_dir,
+            inner: None,
+            unsupported: false,
+            postpone_segments: false,
+        }
+    }
+
+    /// See [bg_movie_writer::BgMovieWriter::set_postpone_segments].
+    pub(crate) fn set_postpone_segments(&mut self, postpone: bool) {
+        self.postpone_segments = postpone;
+        if let Some(inner) = self.inner.as_ref() {
+            inner.set_postpone_segments(postpone);
+        }
+    }
+
+    pub(crate) fn write<TS>(
+        &mut self,
+        frame: &DynamicFrame,
+        timestamp: TS,
+    ) -> std::result::Result<(), bg_movie_writer::Error>
+    where
+        TS: Into<chrono::DateTime<chrono::Local>>,
+    {
+        if self.unsupported {
+            return Ok(());
+        }
+        let small = match half_resolution(frame) {
+            Some(small) => small,
+            None => {
+                warn!(
+                    "Not saving proxy MP4: pixel format {} not supported.",
+                    frame.pixel_format()
+                );
+                self.unsupported = true;
+                return Ok(());
+            }
+        };
+        let inner = self.inner.get_or_insert_with(|| {
+            let inner = bg_movie_writer::BgMovieWriter::new_segmented(
+                self.format_str_mp4.clone(),
+                self.recording_config.clone(),
+                self.segment_config.clone(),
+                self.queue_size,
+                Some(self.data_dir.clone()),
+            );
+            inner.set_postpone_segments(self.postpone_segments);
+            inner
+        });
+        inner.write(small, timestamp)
+    }
+
+    pub(crate) fn finish(&mut self) -> std::result::Result<(), bg_movie_writer::Error> {
+        if let Some(inner) = self.inner.as_mut() {
+            inner.finish()?;
+        }
+        Ok(())
+    }
+
+    /// See [bg_movie_writer::BgMovieWriter::finish_and_wait].
+    pub(crate) fn finish_and_wait(&mut self) -> std::result::Result<(), bg_movie_writer::Error> {
+        if let Some(inner) = self.inner.as_mut() {
+            inner.finish_and_wait()?;
+        }
+        Ok(())
+    }
+}
+
+/// Compute the filename template of the proxy file from the MP4 template.
+fn proxy_format_str(format_str_mp4: &str) -> String {
+    match format_str_mp4.strip_suffix(".mp4") {
+        Some(stem) => format!("{stem}_proxy.mp4"),
+        None => format!("{format_str_mp4}_proxy.mp4"),
+    }
+}
+
+/// Return a copy of `frame` with half the width and half the height.
+///
+/// Pixels are kept in blocks so that the Bayer mosaic and the pixel pairs of
+/// packed YUV422 remain valid. The output width and height are even. Returns
+/// `None` for pixel formats which cannot be decimated this way.
+fn half_resolution(frame: &DynamicFrame) -> Option<DynamicFrame> {
+    let pixfmt = frame.pixel_format();
+    let (block_w, block_h) = match pixfmt {
+        PixFmt::Mono8 | PixFmt::Mono32f | PixFmt::RGB8 | PixFmt::YUV444 => (1, 1),
+        PixFmt::BayerRG8
+        | PixFmt::BayerRG32f
+        | PixFmt::BayerGB8
+        | PixFmt::BayerGB32f
+        | PixFmt::BayerGR8
+        | PixFmt::BayerGR32f
+        | PixFmt::BayerBG8
+        | PixFmt::BayerBG32f => (2, 2),
+        PixFmt::YUV422 => (2, 1),
+        _ => return None,
+    };
+    let bytes_per_pixel = pixfmt.bits_per_pixel() as usize / 8;
+    let block_bytes = block_w * bytes_per_pixel;
+
+    let dest_width = (frame.width() as usize / block_w / 2 * block_w) / 2 * 2;
+    let dest_height = (frame.height() as usize / block_h / 2 * block_h) / 2 * 2;
+    if dest_width == 0 || dest_height == 0 {
+        return None;
+    }
+    let dest_stride = dest_width * bytes_per_pixel;
+
+    let src_stride = frame.stride();
+    let src = frame.image_data_without_format();
+    let mut image_data = Vec::with_capacity(dest_stride * dest_height);
+    for dest_row in 0..dest_height {
+        // Keep every other block of rows.
+        let src_row = (dest_row / block_h) * 2 * block_h + dest_row % block_h;
+        let row = &src[src_row * src_stride..];
+        for block in 0..(dest_width / block_w) {
+            // Keep every other block of columns.
+            let start = block * 2 * block_bytes;
+            image_data.extend_from_slice(&row[start..start + block_bytes]);
+        }
+    }
+
+    let extra = match_all_dynamic_fmts!(frame, x, { x.extra.clone() });
+    Some(DynamicFrame::new(
+        dest_width as u32,
+        dest_height as u32,
+        dest_stride as u32,
+        extra,
+        image_data,
+        pixfmt,
+    ))
+}
@@ -0,0 +1,154 @@
+//! Assignment of CUDA devices to NVENC encoder sessions.
+//!
+//! With many cameras on a machine with several GPUs, the cameras are balanced
+//! across the CUDA devices: each camera has a slot number, e.g. its index among
+//! the cameras of the Braid configuration started on this machine, and is
+//! assigned device `slot % n_devices`. When an MP4 encoding session fails on
+//! the assigned device, the failure is recorded and, once
+//! [NvencPoolConfig::max_failures] is reached, the camera is reassigned to the
+//! device with the fewest failures.
+
+use eyre::Result;
+use tracing::{info, warn};
+
+pub use flydra_types::NvencPoolConfig;
+
+/// The NVENC pool configuration and the slot of this camera.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NvencPoolArgs {
+    pub config: NvencPoolConfig,
+    pub slot: usize,
+}
+
+#[derive(Debug)]
+struct PoolDevice {
+    /// The index of the CUDA device.
+    index: i32,
+    name: String,
+    /// The number of failed encoding sessions on this device.
+    failures: u32,
+}
+
+/// The CUDA devices available to this camera and the one currently assigned.
+#[derive(Debug)]
+pub(crate) struct NvencPool {
+    devices: Vec<PoolDevice>,
+    current: usize,
+    max_failures: u32,
+}
+
+impl NvencPool {
+    /// Create the pool from the names of all CUDA devices of this machine.
+    pub(crate) fn new(args: &NvencPoolArgs, cuda_devices: &[String]) -> Result<Self> {
+        let indices: Vec<usize> = if args.config.devices.is_empty() {
+            (0..cuda_devices.len()).collect()
+        } else {
+            args.config.devices.iter().map(|&i| i as usize).collect()
+        };
+        let devices = indices
+            .into_iter()
+            .map(|i| {
+                let name = cuda_devices.get(i).ok_or_else(|| {
+                    eyre::eyre!(
+                        "CUDA device {i} of NVENC pool not found ({} devices available)",
+                        cuda_devices.len()
+                    )
+                })?;
+                Ok(PoolDevice {
+                    index: i.try_into()?,
+                    name: name.clone(),
+                    failures: 0,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if devices.is_empty() {
+            eyre::bail!("NVENC pool configured, but no CUDA devices available");
+        }
+        let current = args.slot % devices.len();
+        info!(
+            "NVENC pool: using CUDA device {} ({}) of {} devices.",
+            devices[current].index,
+            devices[current].name,
+            devices.len()
+        );
+        Ok(Self {
+            devices,
+            current,
+            max_failures: args.config.max_failures.max(1),
+        })
+    }
+
+    /// The index of the assigned CUDA device.
+    pub(crate) fn cuda_device(&self) -> i32 {
+        self.devices[self.current].index
+    }
+
+    /// The name of the assigned CUDA device.
+    pub(crate) fn device_name(&self) -> &str {
+        &self.devices[self.current].name
+    }
+
+    /// Record a failed encoding session on the assigned device.
+    ///
+    /// Reassigns the camera to another device if the maximum number of
+    /// failures is reached. Returns an error if this is the case on all
+    /// devices.
+    pub(crate) fn report_failure(&mut self) -> Result<()> {
+        let n = self.devices.len();
+        let dev = &mut self.devices[self.current];
+        dev.failures += 1;
+        if dev.failures < self.max_failures {
+            return Ok(());
+        }
+        if self.devices.iter().all(|d| d.failures >= self.max_failures) {
+            eyre::bail!("NVENC encoding failed on all CUDA devices of the pool");
+        }
+        // Of the devices with the fewest failures, take the next one after
+        // the current device.
+        let next = (1..n)
+            .map(|i| (self.current + i) % n)
+            .min_by_key(|&i| self.devices[i].failures)
+            .unwrap();
+        warn!(
+            "NVENC pool: reassigning from CUDA device {} to {} ({}).",
+            self.devices[self.current].index, self.devices[next].index, self.devices[next].name
+        );
+        self.current = next;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_nvenc_pool() {
+    let cuda_devices = vec!["gpu0".to_string(), "gpu1".to_string(), "gpu2".to_string()];
+    let args = |slot, devices| NvencPoolArgs {
+        config: NvencPoolConfig {
+            devices,
+            max_failures: 2,
+        },
+        slot,
+    };
+
+    // Cameras are balanced across all devices.
+    let assigned: Vec<i32> = (0..6)
+        .map(|slot| {
+            NvencPool::new(&args(slot, vec![]), &cuda_devices)
+                .unwrap()
+                .cuda_device()
+        })
+        .collect();
+    assert_eq!(assigned, [0, 1, 2, 0, 1, 2]);
+
+    // Only the configured devices are used.
+    let mut pool = NvencPool::new(&args(1, vec![0, 2]), &cuda_devices).unwrap();
+    assert_eq!((pool.cuda_device(), pool.device_name()), (2, "gpu2"));
+    assert!(NvencPool::new(&args(0, vec![3]), &cuda_devices).is_err());
+
+    // Reassigned after the second failure.
+    pool.report_failure().unwrap();
+    assert_eq!(pool.cuda_device(), 2);
+    pool.report_failure().unwrap();
+    assert_eq!(pool.cuda_device(), 0);
+    pool.report_failure().unwrap();
+    assert!(pool.report_failure().is_err());
+}
@@ -0,0 +1,115 @@
+//! Latency from the exposure of a frame until it is sent on each output.
+//!
+//! Closed-loop users need to know how old the data of an output is when it
+//! arrives. The latency is measured from the timestamp of the frame, i.e. the
+//! exposure time computed from the trigger or the camera clock if available
+//! and the time of acquisition by the host otherwise. The largest latency of
+//! each output over [UPDATE_INTERVAL] is shown in the browser UI and sent to
+//! Braid with the heartbeats. The ImOps messages additionally contain the
+//! latency of each frame.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use async_change_tracker::ChangeTracker;
+use rust_cam_bui_types::OutputLatency;
+use strand_cam_storetype::StoreType;
+
+/// How often the latencies in the browser UI are updated.
+const UPDATE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// An output on which frames, or data computed from them, are sent.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Output {
+    Preview,
+    ImOps,
+    Mp4,
+    V4lOut,
+}
+
+/// The largest latency of each output since the previous update, in
+/// microseconds. Zero if no frame was sent.
+#[derive(Default)]
+pub(crate) struct OutputLatencyCounters {
+    preview_usec: AtomicU64,
+    im_ops_usec: AtomicU64,
+    mp4_usec: AtomicU64,
+    v4l_out_usec: AtomicU64,
+}
+
+impl OutputLatencyCounters {
+    /// The frame with timestamp `stamp` was sent on `output`.
+    ///
+    /// Returns the latency in microseconds.
+    pub(crate) fn frame_sent<TZ: chrono::TimeZone>(
+        &self,
+        output: Output,
+        stamp: chrono::DateTime<TZ>,
+    ) -> u64 {
+        let latency_usec = (chrono::Utc::now() - stamp.with_timezone(&chrono::Utc))
+            .num_microseconds()
+            .unwrap_or(i64::MAX)
+            .max(1) as u64;
+        self.counter(output)
+            .fetch_max(latency_usec, Ordering::Relaxed);
+        latency_usec
+    }
+
+    fn counter(&self, output: Output) -> &AtomicU64 {
+        match output {
+            Output::Preview => &self.preview_usec,
+            Output::ImOps => &self.im_ops_usec,
+            Output::Mp4 => &self.mp4_usec,
+            Output::V4lOut => &self.v4l_out_usec,
+        }
+    }
+
+    /// The largest latencies since the previous call.
+    fn take(&self) -> OutputLatency {
+        let take_msec = |output| {
+            let usec = self.counter(output).swap(0, Ordering::Relaxed);
+            (usec > 0).then(|| usec as f64 / 1000.0)
+        };
+        OutputLatency {
+            preview_msec: take_msec(Output::Preview),
+            im_ops_msec: take_msec(Output::ImOps),
+            mp4_msec: take_msec(Output::Mp4),
+            v4l_out_msec: take_msec(Output::V4lOut),
+        }
+    }
+}
+
+/// Update the latencies in the shared store every [UPDATE_INTERVAL].
+pub(crate) async fn run(
+    counters: Arc<OutputLatencyCounters>,
+    shared_store_arc: Arc<parking_lot::RwLock<ChangeTracker<StoreType>>>,
+) {
+    let mut interval = tokio::time::interval(UPDATE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let output_latency = counters.take();
+        let mut tracker = shared_store_arc.write();
+        if tracker.as_ref().output_latency != output_latency {
+            tracker.modify(|shared| shared.output_latency = output_latency);
+        }
+    }
+}
+
+#[test]
+fn test_output_latency_counters() {
+    let counters = OutputLatencyCounters::default();
+    let now = chrono::Utc::now();
+    let latency_usec = counters.frame_sent(Output::Mp4, now - chrono::Duration::milliseconds(50));
+    assert!(latency_usec >= 50_000);
+    counters.frame_sent(Output::Mp4, now - chrono::Duration::milliseconds(10));
+    counters.frame_sent(Output::ImOps, now.with_timezone(&chrono::Local));
+
+    let latency = counters.take();
+    assert!(latency.mp4_msec.unwrap() >= 50.0);
+    assert!(latency.im_ops_msec.unwrap() > 0.0);
+    assert_eq!(latency.preview_msec, None);
+    assert_eq!(latency.v4l_out_msec, None);
+    assert_eq!(counters.take(), OutputLatency::default());
+}
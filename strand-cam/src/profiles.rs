@@ -0,0 +1,326 @@
+//! Named configuration profiles.
+//!
+//! A profile is a set of camera, point detection and recording settings saved
+//! as `<name>.toml` in the profile directory, e.g. one profile for each phase
+//! of an experiment. Settings not given in a profile are left unchanged:
+//!
+//! ```toml
+//! exposure_auto = "Off"
+//! exposure_time = 5000.0
+//! gain = 3.0
+//! mp4_max_framerate = "Fps25"
+//! post_trigger_buffer_size = 100
+//! ```
+//!
+//! [CamArg::ApplyProfile] switches to a profile. Its settings are first
+//! validated against the camera, then converted to the equivalent commands,
+//! which are all handled before the next command. If any of them fails, the
+//! whole profile is reported as failed.
+
+use std::path::{Path, PathBuf};
+
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use async_change_tracker::ChangeTracker;
+use ci2_remote_control::{BitrateSelection, CamArg, CodecSelection, Mp4SegmentConfig};
+use flydra_feature_detector_types::ImPtDetectCfg;
+use flydra_types::{BraidHttpApiCallback, PerCam, RawCamName};
+use strand_cam_storetype::{RangedValue, StoreType};
+use tracing::{error, info};
+
+/// The settings of a profile.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraProfile {
+    pub exposure_auto: Option<ci2_types::AutoMode>,
+    /// The exposure time, in microseconds.
+    pub exposure_time: Option<f64>,
+    pub gain_auto: Option<ci2_types::AutoMode>,
+    pub gain: Option<f64>,
+    pub frame_rate_limit_enabled: Option<bool>,
+    pub frame_rate_limit: Option<f64>,
+    /// Not applied within Braid, which configures the triggering.
+    pub trigger_mode: Option<ci2_types::TriggerMode>,
+    pub point_detection_config: Option<ImPtDetectCfg>,
+    pub mp4_codec: Option<CodecSelection>,
+    pub mp4_bitrate: Option<BitrateSelection>,
+    pub mp4_max_framerate: Option<ci2_remote_control::RecordingFrameRate>,
+    pub mp4_segment_config: Option<Mp4SegmentConfig>,
+    pub post_trigger_buffer_size: Option<usize>,
+}
+
+impl CameraProfile {
+    /// Check that all settings can be applied to the camera described by
+    /// `shared`, so that an invalid profile is not partially applied.
+    ///
+    /// Returns all problems found, not only the first.
+    pub(crate) fn validate(&self, shared: &StoreType, is_braid: bool) -> Result<()> {
+        let mut problems = Vec::new();
+        if self.exposure_auto.is_some() && shared.exposure_auto.is_none() {
+            problems.push("the camera has no automatic exposure".to_string());
+        }
+        check_range(
+            &mut problems,
+            "exposure_time",
+            self.exposure_time,
+            &shared.exposure_time,
+        );
+        if self.gain_auto.is_some() && shared.gain_auto.is_none() {
+            problems.push("the camera has no automatic gain".to_string());
+        }
+        check_range(&mut problems, "gain", self.gain, &shared.gain);
+        match &shared.frame_rate_limit {
+            Some(range) => {
+                check_range(
+                    &mut problems,
+                    "frame_rate_limit",
+                    self.frame_rate_limit,
+                    range,
+                );
+            }
+            None => {
+                if self.frame_rate_limit_enabled.is_some() || self.frame_rate_limit.is_some() {
+                    problems.push("the camera has no frame rate limit".to_string());
+                }
+            }
+        }
+        if self.trigger_mode.is_some() && is_braid {
+            problems.push("the trigger mode is configured by Braid".to_string());
+        }
+        if self.point_detection_config.is_some() && !cfg!(feature = "flydra_feat_detect") {
+            problems.push("point detection is not compiled in".to_string());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            eyre::bail!("{}", problems.join("; "))
+        }
+    }
+
+    /// The commands which apply this profile.
+    ///
+    /// Automatic exposure and gain are set before the exposure time and gain
+    /// so that the latter are not overridden.
+    pub(crate) fn cam_args(&self) -> Result<Vec<CamArg>> {
+        let mut args = Vec::new();
+        args.extend(self.exposure_auto.map(CamArg::SetExposureAuto));
+        args.extend(self.exposure_time.map(CamArg::SetExposureTime));
+        args.extend(self.gain_auto.map(CamArg::SetGainAuto));
+        args.extend(self.gain.map(CamArg::SetGain));
+        args.extend(
+            self.frame_rate_limit_enabled
+                .map(CamArg::SetFrameRateLimitEnabled),
+        );
+        args.extend(self.frame_rate_limit.map(CamArg::SetFrameRateLimit));
+        args.extend(self.trigger_mode.map(CamArg::SetTriggerMode));
+        if let Some(cfg) = &self.point_detection_config {
+            args.push(CamArg::SetObjDetectionConfig(serde_yaml::to_string(cfg)?));
+        }
+        args.extend(self.mp4_codec.clone().map(CamArg::SetMp4Codec));
+        args.extend(self.mp4_bitrate.clone().map(CamArg::SetMp4Bitrate));
+        args.extend(
+            self.mp4_max_framerate
+                .clone()
+                .map(CamArg::SetMp4MaxFramerate),
+        );
+        args.extend(
+            self.mp4_segment_config
+                .clone()
+                .map(CamArg::SetMp4SegmentConfig),
+        );
+        args.extend(
+            self.post_trigger_buffer_size
+                .map(CamArg::SetPostTriggerBufferSize),
+        );
+        Ok(args)
+    }
+}
+
+/// Add a problem if `value` is given but not within `range`.
+fn check_range(problems: &mut Vec<String>, name: &str, value: Option<f64>, range: &RangedValue) {
+    if let Some(value) = value {
+        if !(range.min..=range.max).contains(&value) {
+            problems.push(format!(
+                "{name} {value} is not within {} to {}",
+                range.min, range.max
+            ));
+        }
+    }
+}
+
+/// A profile whose commands are being handled.
+#[derive(Debug)]
+pub(crate) struct ProfileApplication {
+    pub(crate) name: String,
+    /// The errors of the failed commands.
+    pub(crate) failures: Vec<String>,
+}
+
+impl ProfileApplication {
+    pub(crate) fn new(name: String) -> Self {
+        Self {
+            name,
+            failures: Vec::new(),
+        }
+    }
+}
+
+/// Log the failure of a command and, if it is part of a profile being
+/// applied, record it so that the profile is reported as failed.
+pub(crate) fn command_failed(applying: &mut Option<ProfileApplication>, msg: String) {
+    error!("{msg}");
+    if let Some(applying) = applying {
+        applying.failures.push(msg);
+    }
+}
+
+/// Report the result of applying a profile once all its commands were handled,
+/// in the shared store and in the textlog of the `.braidz` file.
+///
+/// If any command failed, the camera may be partially configured, so no
+/// profile is active afterwards.
+pub(crate) async fn finish(
+    applying: ProfileApplication,
+    raw_cam_name: &RawCamName,
+    shared_store_arc: &parking_lot::RwLock<ChangeTracker<StoreType>>,
+    transmit_msg_tx: Option<&tokio::sync::mpsc::Sender<BraidHttpApiCallback>>,
+) {
+    let name = applying.name;
+    let (active_profile, profile_error, textlog) = if applying.failures.is_empty() {
+        info!("Applied profile \"{name}\".");
+        (Some(name.clone()), None, format!("applied profile {name}"))
+    } else {
+        let reasons = applying.failures.join("; ");
+        error!("Applying profile \"{name}\" failed: {reasons}");
+        (
+            None,
+            Some(format!("Applying profile \"{name}\" failed: {reasons}")),
+            format!("applying profile {name} failed: {reasons}"),
+        )
+    };
+    {
+        let mut tracker = shared_store_arc.write();
+        tracker.modify(|shared| {
+            shared.active_profile = active_profile;
+            shared.profile_error = profile_error;
+        });
+    }
+    if let Some(transmit_msg_tx) = transmit_msg_tx {
+        let msg = BraidHttpApiCallback::AddTextlog(PerCam {
+            raw_cam_name: raw_cam_name.clone(),
+            inner: textlog,
+        });
+        if let Err(e) = transmit_msg_tx.send(msg).await {
+            error!("could not send profile change to Braid: {e}");
+        }
+    }
+}
+
+/// The directory with the profiles.
+#[derive(Debug, Clone)]
+pub(crate) struct ProfileDir {
+    path: PathBuf,
+}
+
+impl ProfileDir {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// The names of the profiles in the directory, sorted.
+    pub(crate) fn names(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(&self.path)
+            .with_context(|| format!("reading profile directory {}", self.path.display()))?
+        {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Read the profile `name`.
+    pub(crate) fn load(&self, name: &str) -> Result<CameraProfile> {
+        if !is_valid_name(name) {
+            eyre::bail!("invalid profile name \"{name}\"");
+        }
+        let path = self.path.join(format!("{name}.toml"));
+        read_profile(&path)
+    }
+}
+
+/// Whether `name` can be used as a file name, without selecting another
+/// directory.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+
+fn read_profile(path: &Path) -> Result<CameraProfile> {
+    let buf = std::fs::read_to_string(path)
+        .with_context(|| format!("reading profile {}", path.display()))?;
+    toml::from_str(&buf).with_context(|| format!("parsing profile {}", path.display()))
+}
+
+#[test]
+fn test_profile_cam_args() {
+    let profile: CameraProfile = toml::from_str(
+        r#"
+        exposure_auto = "Off"
+        exposure_time = 5000.0
+        gain = 3.0
+        trigger_mode = "On"
+        mp4_max_framerate = "Fps25"
+        post_trigger_buffer_size = 100
+
+        [mp4_segment_config]
+        max_duration_secs = 600.0
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        profile.cam_args().unwrap(),
+        vec![
+            CamArg::SetExposureAuto(ci2_types::AutoMode::Off),
+            CamArg::SetExposureTime(5000.0),
+            CamArg::SetGain(3.0),
+            CamArg::SetTriggerMode(ci2_types::TriggerMode::On),
+            CamArg::SetMp4MaxFramerate(ci2_remote_control::RecordingFrameRate::Fps25),
+            CamArg::SetMp4SegmentConfig(Mp4SegmentConfig {
+                max_duration_secs: Some(600.0),
+                max_size_bytes: None,
+            }),
+            CamArg::SetPostTriggerBufferSize(100),
+        ]
+    );
+    assert!(toml::from_str::<CameraProfile>("exposure = 1.0").is_err());
+    assert!(CameraProfile::default().cam_args().unwrap().is_empty());
+
+    assert!(is_valid_name("phase-1"));
+    assert!(!is_valid_name("../phase-1"));
+    assert!(!is_valid_name(""));
+}
+
+#[test]
+fn test_check_range() {
+    let range = RangedValue {
+        name: "gain".to_string(),
+        unit: "dB".to_string(),
+        current: 1.0,
+        min: 0.0,
+        max: 12.0,
+    };
+    let mut problems = Vec::new();
+    check_range(&mut problems, "gain", None, &range);
+    check_range(&mut problems, "gain", Some(12.0), &range);
+    assert!(problems.is_empty());
+    check_range(&mut problems, "gain", Some(-1.0), &range);
+    check_range(&mut problems, "gain", Some(f64::NAN), &range);
+    assert_eq!(problems.len(), 2);
+    assert_eq!(problems[0], "gain -1 is not within 0 to 12");
+}
@@ -0,0 +1,232 @@
+//! Recovery of recordings left unfinished, e.g. by a crash.
+//!
+//! MP4 and FMF files are written under a temporary name (see
+//! [bg_movie_writer::tmp_path]) and renamed when finished. At startup,
+//! [recover_orphaned_recordings] looks for such temporary files:
+//!
+//! - An FMF file is truncated to its last complete frame and the number of
+//!   frames in its header is fixed (see [fmf::repair]). It then gets its final
+//!   name.
+//! - An MP4 file which was finished but not yet renamed has a `moov` box and
+//!   gets its final name. Otherwise, the index of the frames, which is written
+//!   when finishing, is missing and the file cannot be played. Such a file is
+//!   moved to the directory `<data_dir>/quarantine/`. Tools such as `untrunc`
+//!   may still recover its frames using a complete recording made with the
+//!   same settings.
+//!
+//! What was done is saved in a report `recovery_<time>.yaml` in the quarantine
+//! directory. Files modified within [MIN_AGE] are left alone because they may
+//! still be written by another instance of Strand Camera.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use eyre::Result;
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// The name of the directory, within the data directory, of unrecoverable
+/// recordings.
+pub(crate) const QUARANTINE_DIRNAME: &str = "quarantine";
+
+/// Temporary files modified more recently than this are not recovered.
+const MIN_AGE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Fmf,
+    Mp4,
+}
+
+#[derive(Debug, Serialize)]
+struct ReportEntry {
+    orphan: PathBuf,
+    outcome: Outcome,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    /// Renamed to its final name.
+    Recovered {
+        path: PathBuf,
+        /// For FMF files, the number of complete frames.
+        n_frames: Option<u64>,
+    },
+    /// Moved to the quarantine directory.
+    Quarantined { path: PathBuf, reason: String },
+}
+
+/// Recover the orphaned temporary recordings in the directories `dirs`.
+///
+/// Errors with individual files are logged and do not stop the scan.
+pub(crate) fn recover_orphaned_recordings(data_dir: &Path, dirs: &[PathBuf]) -> Result<()> {
+    let quarantine_dir = data_dir.join(QUARANTINE_DIRNAME);
+    let mut report = vec![];
+    for dir in dirs.iter() {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("could not scan \"{}\" for recordings: {e}", dir.display());
+                continue;
+            }
+        };
+        for entry in entries {
+            let orphan = entry?.path();
+            let Some(kind) = orphan_kind(&orphan) else {
+                continue;
+            };
+            if !is_stale(&orphan) {
+                continue;
+            }
+            match recover(&orphan, kind, &quarantine_dir) {
+                Ok(outcome) => report.push(ReportEntry { orphan, outcome }),
+                Err(e) => warn!(
+                    "could not recover unfinished recording \"{}\": {e}",
+                    orphan.display()
+                ),
+            }
+        }
+    }
+
+    if report.is_empty() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(&quarantine_dir)?;
+    let report_path = quarantine_dir.join(
+        chrono::Local::now()
+            .format("recovery_%Y%m%d_%H%M%S.yaml")
+            .to_string(),
+    );
+    serde_yaml::to_writer(File::create(&report_path)?, &report)?;
+    info!(
+        "found {} unfinished recording(s), see \"{}\"",
+        report.len(),
+        report_path.display()
+    );
+    Ok(())
+}
+
+/// The kind of temporary recording `path` is, if any.
+fn orphan_kind(path: &Path) -> Option<Kind> {
+    let name = path.file_name()?.to_str()?;
+    let name = name.strip_suffix(bg_movie_writer::TMP_SUFFIX)?;
+    if name.ends_with(".fmf") {
+        Some(Kind::Fmf)
+    } else if name.ends_with(".mp4") {
+        Some(Kind::Mp4)
+    } else {
+        None
+    }
+}
+
+/// Return true if `path` was last modified at least [MIN_AGE] ago.
+fn is_stale(path: &Path) -> bool {
+    let age = std::fs::metadata(path)
+        .and_then(|md| md.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok());
+    matches!(age, Some(age) if age >= MIN_AGE)
+}
+
+fn recover(orphan: &Path, kind: Kind, quarantine_dir: &Path) -> Result<Outcome> {
+    // Remove the temporary suffix.
+    let path = orphan.with_extension("");
+    let result = match kind {
+        Kind::Fmf => fmf::repair(orphan).map(Some).map_err(|e| e.to_string()),
+        Kind::Mp4 => {
+            if has_moov(&mut File::open(orphan)?)? {
+                Ok(None)
+            } else {
+                Err("the MP4 file has no moov box".to_string())
+            }
+        }
+    };
+    let reason = match result {
+        Ok(n_frames) if !path.exists() => {
+            std::fs::rename(orphan, &path)?;
+            info!("recovered unfinished recording \"{}\"", path.display());
+            return Ok(Outcome::Recovered { path, n_frames });
+        }
+        Ok(_) => format!("\"{}\" already exists", path.display()),
+        Err(reason) => reason,
+    };
+
+    std::fs::create_dir_all(quarantine_dir)?;
+    let path = quarantine_dir.join(orphan.file_name().unwrap());
+    std::fs::rename(orphan, &path)?;
+    warn!(
+        "moved unrecoverable recording \"{}\" to \"{}\": {reason}",
+        orphan.display(),
+        path.display()
+    );
+    Ok(Outcome::Quarantined { path, reason })
+}
+
+/// Return true if the MP4 file has a complete top-level `moov` box, which is
+/// written when the file is finished.
+fn has_moov<R: Read + Seek>(f: &mut R) -> std::io::Result<bool> {
+    let len = f.seek(SeekFrom::End(0))?;
+    let mut pos = 0;
+    while pos + 8 <= len {
+        f.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; 8];
+        f.read_exact(&mut header)?;
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            // The box extends to the end of the file.
+            0 => len - pos,
+            // The size follows the box type.
+            1 => {
+                let mut large_size = [0u8; 8];
+                f.read_exact(&mut large_size)?;
+                u64::from_be_bytes(large_size)
+            }
+            size => size.into(),
+        };
+        if size < 8 {
+            return Ok(false);
+        }
+        if &header[4..] == b"moov" {
+            return Ok(pos + size <= len);
+        }
+        pos += size;
+    }
+    Ok(false)
+}
+
+#[test]
+fn test_has_moov() {
+    fn mp4_box(box_type: &[u8; 4], n_bytes: usize) -> Vec<u8> {
+        let mut buf = ((n_bytes + 8) as u32).to_be_bytes().to_vec();
+        buf.extend_from_slice(box_type);
+        buf.resize(n_bytes + 8, 0);
+        buf
+    }
+
+    let mut buf = mp4_box(b"ftyp", 16);
+    buf.extend(mp4_box(b"mdat", 1000));
+    assert!(!has_moov(&mut std::io::Cursor::new(&buf)).unwrap());
+
+    // Truncated during writing of the frames.
+    assert!(!has_moov(&mut std::io::Cursor::new(&buf[..500])).unwrap());
+
+    buf.extend(mp4_box(b"moov", 100));
+    assert!(has_moov(&mut std::io::Cursor::new(&buf)).unwrap());
+
+    // Truncated during writing of the moov box.
+    assert!(!has_moov(&mut std::io::Cursor::new(&buf[..buf.len() - 10])).unwrap());
+
+    assert!(matches!(
+        orphan_kind(Path::new("movie20240101_120000.mp4.tmp")),
+        Some(Kind::Mp4)
+    ));
+    assert!(matches!(
+        orphan_kind(Path::new("movie20240101_120000.fmf.tmp")),
+        Some(Kind::Fmf)
+    ));
+    assert!(orphan_kind(Path::new("movie20240101_120000.mp4")).is_none());
+}
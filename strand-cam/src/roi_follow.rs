@@ -0,0 +1,190 @@
+//! Closed-loop region of interest (ROI) following ("track-and-zoom").
+//!
+//! With a small sensor ROI, many cameras acquire at a much higher frame rate
+//! than with the full sensor. A [RoiFollower] moves such an ROI so that the
+//! detected object stays near its center. After each frame, the ROI is moved
+//! towards the detection with the largest area using
+//! [CamArg::SetRoiOffset]. Only one move is in flight at a time.
+//!
+//! The images saved as usual (e.g. to MP4) contain only the ROI. To
+//! reconstruct full-frame coordinates, the ROI offset of every frame is
+//! written to `roi_offsets<time>_<camera>.csv` in the data directory. Points
+//! in saved detection CSV files and in the browser UI are in ROI
+//! coordinates.
+//!
+//! The camera does not report with which frame a new offset takes effect. A
+//! new offset is assigned from the first frame processed after the camera
+//! acknowledged it, so the frames in transit at that time may have the wrong
+//! offset. Frames are usually processed within a frame period of
+//! acquisition, so this affects at most a few frames after each move.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use eyre::{Result, WrapErr};
+use tracing::{debug, info};
+
+use basic_frame::DynamicFrame;
+use ci2_remote_control::CamArg;
+use flydra_types::RawCamName;
+use timestamped_frame::ExtraTimeData;
+
+/// A requested move which was not acknowledged after this long is abandoned.
+const MOVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Configuration of ROI following.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoiFollowConfig {
+    /// Offsets are multiples of this, in pixels. This must be a multiple of
+    /// the offset increment of the camera.
+    pub offset_step: u32,
+    /// The ROI is only moved if the offset changes by at least this many
+    /// pixels in one direction.
+    pub min_move: u32,
+}
+
+impl Default for RoiFollowConfig {
+    fn default() -> Self {
+        Self {
+            offset_step: 4,
+            min_move: 16,
+        }
+    }
+}
+
+/// Moves the ROI to follow detections and records the ROI offsets.
+pub(crate) struct RoiFollower {
+    cfg: RoiFollowConfig,
+    cam_args_tx: tokio::sync::mpsc::Sender<CamArg>,
+    roi_size: (u32, u32),
+    max_size: (u32, u32),
+    /// The offset of the most recently processed frame.
+    offset: (u32, u32),
+    /// A move which was not yet acknowledged.
+    in_flight: Option<Instant>,
+    fd: File,
+}
+
+impl RoiFollower {
+    pub(crate) fn new(
+        cfg: RoiFollowConfig,
+        cam_args_tx: tokio::sync::mpsc::Sender<CamArg>,
+        cam_name: &RawCamName,
+        roi_size: (u32, u32),
+        max_size: (u32, u32),
+        offset: (u32, u32),
+        data_dir: &Path,
+    ) -> Result<Self> {
+        if cfg.offset_step == 0 {
+            eyre::bail!("ROI offset step must be larger than zero");
+        }
+        if roi_size.0 > max_size.0 || roi_size.1 > max_size.1 {
+            eyre::bail!("ROI {roi_size:?} larger than the sensor {max_size:?}");
+        }
+        let local = chrono::Local::now();
+        let path = data_dir.join(format!(
+            "roi_offsets{}_{}.csv",
+            local.format("%Y%m%d_%H%M%S"),
+            cam_name.as_str()
+        ));
+        let mut fd = File::create(&path)
+            .with_context(|| format!("creating ROI offsets file \"{}\"", path.display()))?;
+        writeln!(
+            fd,
+            "# ROI offsets of camera {}. ROI size {}x{}, sensor size {}x{}.",
+            cam_name.as_str(),
+            roi_size.0,
+            roi_size.1,
+            max_size.0,
+            max_size.1
+        )?;
+        writeln!(fd, "host_framenumber,host_timestamp,offset_x,offset_y")?;
+        info!("Following ROI, saving offsets to \"{}\".", path.display());
+        Ok(Self {
+            cfg,
+            cam_args_tx,
+            roi_size,
+            max_size,
+            offset,
+            in_flight: None,
+            fd,
+        })
+    }
+
+    /// The camera acknowledged a move (or reported its current offset).
+    pub(crate) fn offset_applied(&mut self, offset: (u32, u32)) {
+        self.offset = offset;
+        self.in_flight = None;
+    }
+
+    /// Record the offset of the frame and move the ROI towards the largest
+    /// of the points found in the frame, which are in ROI coordinates.
+    pub(crate) fn process(
+        &mut self,
+        frame: &DynamicFrame,
+        points: &[http_video_streaming_types::Point],
+    ) -> Result<()> {
+        let extra = frame.extra();
+        writeln!(
+            self.fd,
+            "{},{},{},{}",
+            extra.host_framenumber(),
+            datetime_conversion::datetime_to_f64(&extra.host_timestamp()),
+            self.offset.0,
+            self.offset.1
+        )?;
+
+        if let Some(sent) = self.in_flight {
+            if sent.elapsed() < MOVE_TIMEOUT {
+                return Ok(());
+            }
+            debug!("ROI move was not acknowledged, giving up on it");
+            self.in_flight = None;
+        }
+
+        let Some(pt) = points
+            .iter()
+            .max_by(|a, b| a.area.unwrap_or(0.0).total_cmp(&b.area.unwrap_or(0.0)))
+        else {
+            return Ok(());
+        };
+
+        let target = (
+            self.target_offset(0, pt.x as f64),
+            self.target_offset(1, pt.y as f64),
+        );
+        let moved = self
+            .offset
+            .0
+            .abs_diff(target.0)
+            .max(self.offset.1.abs_diff(target.1));
+        if moved < self.cfg.min_move {
+            return Ok(());
+        }
+        let msg = CamArg::SetRoiOffset {
+            offset_x: target.0,
+            offset_y: target.1,
+        };
+        // Skip this frame rather than wait if the queue is full.
+        if self.cam_args_tx.try_send(msg).is_ok() {
+            self.in_flight = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// The offset along `axis` (0: x, 1: y) which centers the ROI on `pos`.
+    fn target_offset(&self, axis: usize, pos: f64) -> u32 {
+        let (offset, roi, max) = if axis == 0 {
+            (self.offset.0, self.roi_size.0, self.max_size.0)
+        } else {
+            (self.offset.1, self.roi_size.1, self.max_size.1)
+        };
+        let center = offset as f64 + pos;
+        let wanted = (center - roi as f64 / 2.0).clamp(0.0, (max - roi) as f64) as u32;
+        (wanted / self.cfg.offset_step) * self.cfg.offset_step
+    }
+}
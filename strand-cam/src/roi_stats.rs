@@ -0,0 +1,264 @@
+//! Per-frame statistics of named regions of interest (ROIs).
+//!
+//! While image operations ("ImOps") detection is enabled, the mean and maximum
+//! pixel value and the centroid of the pixels at or above a threshold are
+//! computed in each ROI of every frame. This allows assays with several zones,
+//! such as phototaxis assays, without external image processing.
+//!
+//! The statistics of all ROIs of a frame are sent as one CBOR message,
+//! [crate::CentroidToDevice::RoiStats], to the ImOps destination. If
+//! configured, they are also saved to `roi_stats<time>_<camera>.csv` in the
+//! data directory with one row per frame and ROI. Only Mono8 images are
+//! supported.
+
+use std::{fs::File, io::Write, path::Path};
+
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use basic_frame::DynamicFrame;
+use flydra_types::RawCamName;
+use machine_vision_formats::{pixel_format::Mono8, ImageStride};
+use timestamped_frame::ExtraTimeData;
+
+pub(crate) const ROI_STATS_SCHEMA_VERSION: u8 = 2;
+
+/// Configuration of the ROI statistics, e.g. read from a TOML file:
+///
+/// ```toml
+/// save_csv = true
+///
+/// [[rois]]
+/// name = "left"
+/// x = 0
+/// y = 0
+/// width = 320
+/// height = 480
+/// threshold = 128
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoiStatsConfig {
+    pub rois: Vec<NamedRoi>,
+    /// If true, save the statistics to a CSV file in the data directory.
+    #[serde(default)]
+    pub save_csv: bool,
+}
+
+/// A rectangular region of interest, in pixels.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NamedRoi {
+    pub name: String,
+    /// The left edge.
+    pub x: u32,
+    /// The top edge.
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Pixels with at least this value are used for the centroid.
+    pub threshold: u8,
+}
+
+/// The statistics of all ROIs in one frame.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct RoiStatsFrame {
+    pub schema_version: u8,
+    pub framenumber: u64,
+    pub timestamp_source: crate::TimestampSource,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub cam_name: String,
+    pub rois: Vec<RoiStats>,
+    /// The time from `timestamp` until the message was sent, in microseconds.
+    #[serde(default)]
+    pub latency_usec: u64,
+}
+
+/// The statistics of one ROI in one frame.
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct RoiStats {
+    pub name: String,
+    /// The mean pixel value. NaN if the ROI is outside the image.
+    pub mean: f32,
+    pub max: u8,
+    /// The number of pixels at or above the threshold.
+    pub n_above_threshold: u64,
+    /// The centroid of the pixels at or above the threshold, in image
+    /// coordinates. NaN if there are no such pixels.
+    pub centroid_x: f32,
+    pub centroid_y: f32,
+}
+
+/// Computes the statistics of each ROI and saves them.
+pub(crate) struct RoiStatsProcessor {
+    rois: Vec<NamedRoi>,
+    fd: Option<File>,
+}
+
+impl RoiStatsProcessor {
+    pub(crate) fn new(cfg: RoiStatsConfig, cam_name: &RawCamName, data_dir: &Path) -> Result<Self> {
+        for (i, roi) in cfg.rois.iter().enumerate() {
+            if roi.name.is_empty() || roi.name.contains([',', '"', '\n']) {
+                eyre::bail!("invalid ROI name \"{}\"", roi.name);
+            }
+            if cfg.rois[..i].iter().any(|other| other.name == roi.name) {
+                eyre::bail!("duplicate ROI name \"{}\"", roi.name);
+            }
+            if roi.width == 0 || roi.height == 0 {
+                eyre::bail!("ROI \"{}\" is empty", roi.name);
+            }
+        }
+
+        let fd = if cfg.save_csv {
+            let local = chrono::Local::now();
+            let path = data_dir.join(format!(
+                "roi_stats{}_{}.csv",
+                local.format("%Y%m%d_%H%M%S"),
+                cam_name.as_str()
+            ));
+            let mut fd = File::create(&path)
+                .with_context(|| format!("creating ROI statistics file \"{}\"", path.display()))?;
+            writeln!(fd, "# ROI statistics of camera {}.", cam_name.as_str())?;
+            for roi in cfg.rois.iter() {
+                writeln!(
+                    fd,
+                    "# ROI {}: x {}, y {}, width {}, height {}, threshold {}",
+                    roi.name, roi.x, roi.y, roi.width, roi.height, roi.threshold
+                )?;
+            }
+            writeln!(
+                fd,
+                "host_framenumber,host_timestamp,roi,mean,max,n_above_threshold,centroid_x,centroid_y"
+            )?;
+            info!("Saving ROI statistics to \"{}\".", path.display());
+            Some(fd)
+        } else {
+            None
+        };
+        Ok(Self { rois: cfg.rois, fd })
+    }
+
+    /// Compute and save the statistics of all ROIs in `frame`.
+    ///
+    /// Returns `None` if the frame is not Mono8.
+    pub(crate) fn process(&mut self, frame: &DynamicFrame) -> Result<Option<Vec<RoiStats>>> {
+        let DynamicFrame::Mono8(image) = frame else {
+            return Ok(None);
+        };
+        let stats: Vec<RoiStats> = self
+            .rois
+            .iter()
+            .map(|roi| compute_roi_stats(roi, image))
+            .collect();
+        if let Some(fd) = self.fd.as_mut() {
+            let extra = frame.extra();
+            let timestamp = datetime_conversion::datetime_to_f64(&extra.host_timestamp());
+            for s in stats.iter() {
+                writeln!(
+                    fd,
+                    "{},{},{},{},{},{},{},{}",
+                    extra.host_framenumber(),
+                    timestamp,
+                    s.name,
+                    s.mean,
+                    s.max,
+                    s.n_above_threshold,
+                    s.centroid_x,
+                    s.centroid_y
+                )?;
+            }
+        }
+        Ok(Some(stats))
+    }
+}
+
+/// Compute the statistics of `roi`, clipped to the bounds of `image`.
+fn compute_roi_stats(roi: &NamedRoi, image: &impl ImageStride<Mono8>) -> RoiStats {
+    let x0 = (roi.x as usize).min(image.width() as usize);
+    let y0 = (roi.y as usize).min(image.height() as usize);
+    let x1 = (roi.x as usize + roi.width as usize).min(image.width() as usize);
+    let y1 = (roi.y as usize + roi.height as usize).min(image.height() as usize);
+
+    let mut sum = 0u64;
+    let mut max = 0u8;
+    let (mut n_above, mut sum_x, mut sum_y) = (0u64, 0u64, 0u64);
+    let data = image.image_data();
+    for y in y0..y1 {
+        let row = &data[y * image.stride() + x0..y * image.stride() + x1];
+        for (x, &value) in (x0..x1).zip(row.iter()) {
+            sum += u64::from(value);
+            max = max.max(value);
+            if value >= roi.threshold {
+                n_above += 1;
+                sum_x += x as u64;
+                sum_y += y as u64;
+            }
+        }
+    }
+
+    let n_pixels = ((x1 - x0) * (y1 - y0)) as u64;
+    let mean = if n_pixels > 0 {
+        (sum as f64 / n_pixels as f64) as f32
+    } else {
+        f32::NAN
+    };
+    let (centroid_x, centroid_y) = if n_above > 0 {
+        (
+            (sum_x as f64 / n_above as f64) as f32,
+            (sum_y as f64 / n_above as f64) as f32,
+        )
+    } else {
+        (f32::NAN, f32::NAN)
+    };
+    RoiStats {
+        name: roi.name.clone(),
+        mean,
+        max,
+        n_above_threshold: n_above,
+        centroid_x,
+        centroid_y,
+    }
+}
+
+#[test]
+fn test_compute_roi_stats() {
+    // A 4x3 image with a stride of 6 bytes.
+    #[rustfmt::skip]
+    let data = vec![
+        0, 0, 0, 0, 99, 99,
+        0, 10, 200, 0, 99, 99,
+        0, 10, 200, 0, 99, 99,
+    ];
+    let image = machine_vision_formats::owned::OImage::<Mono8>::new(4, 3, 6, data).unwrap();
+
+    let roi = NamedRoi {
+        name: "a".into(),
+        x: 1,
+        y: 1,
+        width: 2,
+        height: 2,
+        threshold: 100,
+    };
+    let stats = compute_roi_stats(&roi, &image);
+    assert_eq!(stats.mean, 105.0);
+    assert_eq!(stats.max, 200);
+    assert_eq!(stats.n_above_threshold, 2);
+    assert_eq!((stats.centroid_x, stats.centroid_y), (2.0, 1.5));
+
+    // Clipped to the image, without pixels above the threshold.
+    let roi = NamedRoi {
+        x: 3,
+        width: 10,
+        threshold: 255,
+        ..roi
+    };
+    let stats = compute_roi_stats(&roi, &image);
+    assert_eq!(stats.mean, 0.0);
+    assert_eq!(stats.n_above_threshold, 0);
+    assert!(stats.centroid_x.is_nan());
+
+    // Outside of the image.
+    let roi = NamedRoi { x: 4, ..roi };
+    assert!(compute_roi_stats(&roi, &image).mean.is_nan());
+}
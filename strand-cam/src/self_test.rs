@@ -0,0 +1,401 @@
+//! Optional self-test run at startup.
+//!
+//! Before an experiment, it is useful to know that the camera delivers frames
+//! at the expected rate, that feature detection works and that the disk is
+//! fast enough to save videos. With a [SelfTestConfig], the following checks
+//! are run once acquisition has started:
+//!
+//! - frame rate: the mean frame rate of the first frames, from their host
+//!   timestamps, must be within [SelfTestConfig::max_fps_error] of the
+//!   frame rate expected from the trigger configuration (or the frame rate
+//!   limit of the camera when not using Braid). No frames may be dropped
+//!   because processing was too slow.
+//! - detection: if a test pattern is given, the feature detector, with the
+//!   current settings and a uniform background, must find a plausible number
+//!   of points in it.
+//! - disk: writing a test file to the data directory must be fast enough.
+//!
+//! Until all checks passed, recordings are refused. The result is shown in the
+//! browser UI and sent to Braid, which also refuses to save data. A failure
+//! can be dismissed in the browser UI to allow recording anyway.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_change_tracker::ChangeTracker;
+use tracing::{error, info};
+
+use basic_frame::DynamicFrame;
+use ci2_remote_control::CamArg;
+use flydra_types::{BraidHttpApiCallback, PerCam, RawCamName, TriggerType};
+use rust_cam_bui_types::{SelfTestCheck, SelfTestReport};
+use strand_cam_storetype::StoreType;
+use timestamped_frame::ExtraTimeData;
+
+/// Configuration of the startup self-test.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfTestConfig {
+    /// The number of frames over which the frame rate is measured.
+    pub n_frames: usize,
+    /// The expected frame rate. If `None`, it is taken from the trigger
+    /// configuration or the frame rate limit.
+    pub expected_fps: Option<f64>,
+    /// The maximum relative difference between measured and expected frame
+    /// rate.
+    pub max_fps_error: f64,
+    /// An FMF file whose first frame is used to check feature detection.
+    pub test_pattern: Option<PathBuf>,
+    /// The minimum number of points to be detected in the test pattern.
+    pub min_test_pattern_points: usize,
+    /// The maximum number of points to be detected in the test pattern.
+    pub max_test_pattern_points: usize,
+    /// The size of the file written to measure the disk write speed.
+    pub disk_test_bytes: usize,
+    /// The minimum disk write speed, in bytes per second.
+    pub min_disk_bytes_per_sec: f64,
+    /// The self-test fails if the frames are not received within this time.
+    pub timeout: Duration,
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        Self {
+            n_frames: 100,
+            expected_fps: None,
+            max_fps_error: 0.05,
+            test_pattern: None,
+            min_test_pattern_points: 1,
+            max_test_pattern_points: 10,
+            disk_test_bytes: 64 * 1024 * 1024,
+            min_disk_bytes_per_sec: 50e6,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// The frame rate expected from the trigger configuration, if known.
+pub(crate) fn trigger_fps(trigger_type: &TriggerType) -> Option<f64> {
+    match trigger_type {
+        TriggerType::TriggerboxV1(cfg) => Some(cfg.framerate as f64),
+        TriggerType::FakeSync(cfg) => Some(cfg.framerate),
+        TriggerType::CameraMaster(cfg) => Some(cfg.framerate),
+        TriggerType::PtpSync(cfg) => cfg.periodic_signal_period_usec.map(|p| 1e6 / p),
+        TriggerType::DeviceTimestamp => None,
+    }
+}
+
+/// Whether the camera command starts a recording and is thus refused while
+/// the self-test has not passed.
+pub(crate) fn starts_recording(cam_arg: &CamArg) -> bool {
+    use ci2_remote_control::CsvSaveConfig;
+    match cam_arg {
+        CamArg::SetIsRecordingMp4(v)
+        | CamArg::SetIsRecordingFmf(v)
+        | CamArg::SetIsRecordingMp4AndFmf(v)
+        | CamArg::SetIsRecordingUfmf(v)
+        | CamArg::SetIsRecordingAprilTagCsv(v) => *v,
+        CamArg::SetIsSavingObjDetectionCsv(cfg) => *cfg != CsvSaveConfig::NotSaving,
+        CamArg::PostTrigger
+        | CamArg::StartRecordingFor { .. }
+        | CamArg::StartRecordingUntil { .. } => true,
+        _ => false,
+    }
+}
+
+/// Timing of the first frames, as measured by [FrameRateCheck].
+#[derive(Debug)]
+pub(crate) struct FrameStats {
+    n_frames: usize,
+    duration: chrono::TimeDelta,
+    n_dropped: usize,
+}
+
+/// Collects the timestamps of the first frames for the frame rate check.
+pub(crate) struct FrameRateCheck {
+    n_frames: usize,
+    first: Option<chrono::DateTime<chrono::Utc>>,
+    count: usize,
+    n_dropped: usize,
+    tx: Option<tokio::sync::oneshot::Sender<FrameStats>>,
+}
+
+impl FrameRateCheck {
+    pub(crate) fn new(n_frames: usize) -> (Self, tokio::sync::oneshot::Receiver<FrameStats>) {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let this = Self {
+            n_frames: n_frames.max(2),
+            first: None,
+            count: 0,
+            n_dropped: 0,
+            tx: Some(tx),
+        };
+        (this, rx)
+    }
+
+    /// A frame was received. Returns `true` when enough frames were seen.
+    pub(crate) fn push(&mut self, frame: &DynamicFrame) -> bool {
+        let timestamp = frame.extra().host_timestamp();
+        let first = *self.first.get_or_insert(timestamp);
+        self.count += 1;
+        if self.count < self.n_frames {
+            return false;
+        }
+        if let Some(tx) = self.tx.take() {
+            let stats = FrameStats {
+                n_frames: self.count,
+                duration: timestamp - first,
+                n_dropped: self.n_dropped,
+            };
+            // The self-test may have timed out already.
+            let _ = tx.send(stats);
+        }
+        true
+    }
+
+    /// A frame was dropped because processing was too slow.
+    pub(crate) fn frame_dropped(&mut self) {
+        self.n_dropped += 1;
+    }
+}
+
+/// Run the self-test and publish the result.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn run(
+    cfg: SelfTestConfig,
+    expected_fps: Option<f64>,
+    frame_stats_rx: tokio::sync::oneshot::Receiver<FrameStats>,
+    #[cfg(feature = "flydra_feat_detect")]
+    im_pt_detect_cfg: flydra_feature_detector_types::ImPtDetectCfg,
+    raw_cam_name: RawCamName,
+    data_dir: PathBuf,
+    shared_store_arc: Arc<parking_lot::RwLock<ChangeTracker<StoreType>>>,
+    transmit_msg_tx: Option<tokio::sync::mpsc::Sender<BraidHttpApiCallback>>,
+) {
+    info!("Running startup self-test.");
+    publish(
+        SelfTestReport::running(),
+        &raw_cam_name,
+        &shared_store_arc,
+        transmit_msg_tx.as_ref(),
+    )
+    .await;
+
+    let mut checks = Vec::new();
+
+    let frame_stats = tokio::time::timeout(cfg.timeout, frame_stats_rx).await;
+    checks.push(check_frame_rate(&cfg, expected_fps, frame_stats));
+
+    if let Some(path) = cfg.test_pattern.clone() {
+        #[cfg(feature = "flydra_feat_detect")]
+        let check = {
+            let cfg = cfg.clone();
+            let raw_cam_name = raw_cam_name.clone();
+            tokio::task::spawn_blocking(move || {
+                check_detection(&cfg, &path, &raw_cam_name, im_pt_detect_cfg)
+            })
+            .await
+            .unwrap()
+        };
+        #[cfg(not(feature = "flydra_feat_detect"))]
+        let check = failed_check(
+            "detection",
+            format!(
+                "cannot check test pattern \"{}\": feature detection not compiled",
+                path.display()
+            ),
+        );
+        checks.push(check);
+    }
+
+    {
+        let cfg = cfg.clone();
+        let check = tokio::task::spawn_blocking(move || check_disk(&cfg, &data_dir))
+            .await
+            .unwrap();
+        checks.push(check);
+    }
+
+    for check in checks.iter() {
+        if check.passed {
+            info!("Self-test {} passed: {}", check.name, check.message);
+        } else {
+            error!("Self-test {} failed: {}", check.name, check.message);
+        }
+    }
+
+    let report = SelfTestReport {
+        checks: Some(checks),
+        failure_dismissed: false,
+    };
+    publish(
+        report,
+        &raw_cam_name,
+        &shared_store_arc,
+        transmit_msg_tx.as_ref(),
+    )
+    .await;
+}
+
+/// Store the report for the browser UI and send it to Braid.
+pub(crate) async fn publish(
+    report: SelfTestReport,
+    raw_cam_name: &RawCamName,
+    shared_store_arc: &parking_lot::RwLock<ChangeTracker<StoreType>>,
+    transmit_msg_tx: Option<&tokio::sync::mpsc::Sender<BraidHttpApiCallback>>,
+) {
+    {
+        let mut tracker = shared_store_arc.write();
+        tracker.modify(|shared| shared.self_test = Some(report.clone()));
+    }
+    if let Some(transmit_msg_tx) = transmit_msg_tx {
+        let msg = BraidHttpApiCallback::UpdateSelfTest(PerCam {
+            raw_cam_name: raw_cam_name.clone(),
+            inner: report,
+        });
+        if let Err(e) = transmit_msg_tx.send(msg).await {
+            error!("could not send self-test result to Braid: {e}");
+        }
+    }
+}
+
+fn failed_check(name: &str, message: String) -> SelfTestCheck {
+    SelfTestCheck {
+        name: name.into(),
+        passed: false,
+        message,
+    }
+}
+
+fn check_frame_rate(
+    cfg: &SelfTestConfig,
+    expected_fps: Option<f64>,
+    frame_stats: Result<
+        Result<FrameStats, tokio::sync::oneshot::error::RecvError>,
+        tokio::time::error::Elapsed,
+    >,
+) -> SelfTestCheck {
+    const NAME: &str = "frame rate";
+    let stats = match frame_stats {
+        Ok(Ok(stats)) => stats,
+        Ok(Err(_)) => return failed_check(NAME, "acquisition stopped".into()),
+        Err(_) => {
+            return failed_check(
+                NAME,
+                format!(
+                    "fewer than {} frames received within {} seconds",
+                    cfg.n_frames,
+                    cfg.timeout.as_secs()
+                ),
+            )
+        }
+    };
+    let secs = stats.duration.num_microseconds().unwrap_or(0) as f64 * 1e-6;
+    if secs <= 0.0 {
+        return failed_check(NAME, "frame timestamps are not increasing".into());
+    }
+    let measured = (stats.n_frames - 1) as f64 / secs;
+    let mut message = format!("measured {measured:.2} frames per second");
+    let mut passed = true;
+    if let Some(expected) = expected_fps {
+        let error = (measured - expected).abs() / expected;
+        message.push_str(&format!(", expected {expected:.2}"));
+        passed &= error <= cfg.max_fps_error;
+    } else {
+        message.push_str(", expected frame rate unknown");
+    }
+    if stats.n_dropped > 0 {
+        message.push_str(&format!(", {} frames dropped", stats.n_dropped));
+        passed = false;
+    }
+    SelfTestCheck {
+        name: NAME.into(),
+        passed,
+        message,
+    }
+}
+
+#[cfg(feature = "flydra_feat_detect")]
+fn check_detection(
+    cfg: &SelfTestConfig,
+    path: &Path,
+    raw_cam_name: &RawCamName,
+    im_pt_detect_cfg: flydra_feature_detector_types::ImPtDetectCfg,
+) -> SelfTestCheck {
+    const NAME: &str = "detection";
+    let count = || -> eyre::Result<usize> {
+        let frame = fmf::FMFReader::new(path)?
+            .next()
+            .ok_or_else(|| eyre::eyre!("no frame in \"{}\"", path.display()))??;
+        let mut detector = flydra_feature_detector::FlydraFeatureDetector::new(
+            raw_cam_name,
+            frame.width(),
+            frame.height(),
+            im_pt_detect_cfg,
+            None,
+            None,
+            None,
+        )?;
+        // The first frame sets the background, the second is processed
+        // against it.
+        detector.do_clear_background(0.0)?;
+        let mut packet = None;
+        for _ in 0..2 {
+            let ufmf_state = flydra_feature_detector::UfmfState::Stopped;
+            packet = Some(
+                detector
+                    .process_new_frame(&frame, ufmf_state, None, None, None)?
+                    .0,
+            );
+        }
+        Ok(packet.unwrap().points.len())
+    };
+    match count() {
+        Ok(n_points) => SelfTestCheck {
+            name: NAME.into(),
+            passed: (cfg.min_test_pattern_points..=cfg.max_test_pattern_points).contains(&n_points),
+            message: format!(
+                "{n_points} points detected in test pattern, expected {} to {}",
+                cfg.min_test_pattern_points, cfg.max_test_pattern_points
+            ),
+        },
+        Err(e) => failed_check(NAME, format!("processing test pattern failed: {e}")),
+    }
+}
+
+fn check_disk(cfg: &SelfTestConfig, data_dir: &Path) -> SelfTestCheck {
+    const NAME: &str = "disk";
+    let measure = || -> std::io::Result<f64> {
+        // The file is removed when dropped.
+        let mut file = tempfile::NamedTempFile::new_in(data_dir)?;
+        let buf = vec![0xA5u8; 1024 * 1024];
+        let start = Instant::now();
+        let mut remaining = cfg.disk_test_bytes;
+        while remaining > 0 {
+            let n = remaining.min(buf.len());
+            file.write_all(&buf[..n])?;
+            remaining -= n;
+        }
+        file.as_file().sync_all()?;
+        Ok(cfg.disk_test_bytes as f64 / start.elapsed().as_secs_f64())
+    };
+    match measure() {
+        Ok(bytes_per_sec) => SelfTestCheck {
+            name: NAME.into(),
+            passed: bytes_per_sec >= cfg.min_disk_bytes_per_sec,
+            message: format!(
+                "wrote {:.1} MB/s to \"{}\", required {:.1} MB/s",
+                bytes_per_sec / 1e6,
+                data_dir.display(),
+                cfg.min_disk_bytes_per_sec / 1e6
+            ),
+        },
+        Err(e) => failed_check(
+            NAME,
+            format!("writing to \"{}\" failed: {e}", data_dir.display()),
+        ),
+    }
+}
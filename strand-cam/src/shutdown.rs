@@ -0,0 +1,108 @@
+//! Ordered shutdown of Strand Camera.
+//!
+//! When quitting (a DoQuit command, Ctrl-C, SIGTERM or closing the GUI),
+//! acquisition is stopped first and the frames already acquired are passed on
+//! for processing. Then each sink currently saving data (MP4, FMF and UFMF
+//! files, CSV files) is finalized, e.g. the MP4 index is written and the
+//! number of frames in the FMF header is updated. The completion of each sink
+//! is logged. Only then does the process exit.
+//!
+//! If finalizing takes longer than [SHUTDOWN_TIMEOUT], the sinks which were not
+//! finalized are logged and the process exits anyway. Files left unfinished
+//! this way are recovered at the next start (see [crate::recovery]).
+
+use std::time::Duration;
+
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tracing::{error, info, warn};
+
+/// How long to wait for all sinks to be finalized.
+pub(crate) const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Progress of finalizing the sinks, sent by the frame processing task.
+///
+/// The channel is closed once all sinks are finalized.
+pub(crate) enum ShutdownEvent {
+    /// The names of the sinks which will be finalized.
+    Pending(Vec<&'static str>),
+    /// The sink with this name was finalized.
+    Finished(&'static str, eyre::Result<()>),
+}
+
+pub(crate) type ShutdownTx = UnboundedSender<ShutdownEvent>;
+
+/// Run the blocking function `f`, e.g. waiting for a writer thread, without
+/// blocking the async runtime.
+pub(crate) async fn finish_blocking<F, E>(f: F) -> eyre::Result<()>
+where
+    F: FnOnce() -> Result<(), E> + Send + 'static,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    Ok(tokio::task::spawn_blocking(f).await??)
+}
+
+/// Log the progress of finalizing the sinks until done or `timeout` elapsed.
+///
+/// Returns `false` on timeout.
+pub(crate) async fn wait_for_sinks(
+    mut rx: UnboundedReceiver<ShutdownEvent>,
+    timeout: Duration,
+) -> bool {
+    let mut pending: Option<Vec<&'static str>> = None;
+    let wait = async {
+        while let Some(event) = rx.recv().await {
+            match event {
+                ShutdownEvent::Pending(sinks) => {
+                    if sinks.is_empty() {
+                        info!("no open files to finalize");
+                    } else {
+                        info!("finalizing {}", sinks.join(", "));
+                    }
+                    pending = Some(sinks);
+                }
+                ShutdownEvent::Finished(sink, result) => {
+                    if let Some(pending) = pending.as_mut() {
+                        pending.retain(|s| *s != sink);
+                    }
+                    match result {
+                        Ok(()) => info!("finalized {sink}"),
+                        Err(e) => error!("error finalizing {sink}: {e:?}"),
+                    }
+                }
+            }
+        }
+    };
+    if tokio::time::timeout(timeout, wait).await.is_ok() {
+        return true;
+    }
+    match pending {
+        None => error!("frame processing did not respond within {timeout:?}"),
+        Some(pending) => error!(
+            "timeout after {timeout:?}, not finalized: {}",
+            pending.join(", ")
+        ),
+    }
+    false
+}
+
+/// Resolve when the process receives Ctrl-C or, on unix, SIGTERM.
+pub(crate) async fn quit_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = sigterm.recv() => {},
+                }
+                return;
+            }
+            Err(e) => warn!("cannot handle SIGTERM: {e}"),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!("cannot handle Ctrl-C: {e}");
+        std::future::pending::<()>().await;
+    }
+}
@@ -99,9 +99,48 @@ const BRAID_COOKIE_KEY: &str = "braid-cookie";
 #[cfg(feature = "flydratrax")]
 mod flydratrax_handle_msg;
 
+mod adaptive_fps;
+pub use adaptive_fps::AdaptiveFpsConfig;
+mod bug_report;
 mod clock_model;
+mod clock_skew;
+pub use clock_skew::ClockSkewConfig;
+mod compliance_watermark;
 mod datagram_socket;
+#[cfg(feature = "flydra_feat_detect")]
+mod detection_filter;
+#[cfg(feature = "flydra_feat_detect")]
+pub use detection_filter::{DetectionFilterConfig, FilterPolygon};
+#[cfg(feature = "dnn-detector")]
+mod detector_plugin;
+#[cfg(feature = "dnn-detector")]
+pub use detector_plugin::DnnDetectorConfig;
+mod diagnostics;
+pub use diagnostics::DiagnosticsConfig;
+mod event_publisher;
+pub use event_publisher::EventPublisherConfig;
+mod heartbeat;
+mod idle_mode;
+pub use idle_mode::IdleModeConfig;
+mod mp4_proxy;
+mod nvenc_pool;
+pub use nvenc_pool::{NvencPoolArgs, NvencPoolConfig};
+mod output_latency;
 mod post_trigger_buffer;
+mod profiles;
+pub use profiles::CameraProfile;
+mod recovery;
+mod roi_follow;
+pub use roi_follow::RoiFollowConfig;
+
+mod roi_stats;
+pub use roi_stats::{NamedRoi, RoiStats, RoiStatsConfig, RoiStatsFrame};
+mod self_test;
+pub use self_test::SelfTestConfig;
+mod shutdown;
+mod uploader;
+pub use uploader::{S3Target, UploadConfig, UploadTarget, WebDavTarget};
+pub use video_streaming::MjpegConfig;
 
 #[cfg(feature = "eframe-gui")]
 mod gui_app;
@@ -221,6 +260,16 @@ pub(crate) enum Msg {
     StopMp4,
     StartFMF((String, RecordingFrameRate)),
     StopFMF,
+    /// Start MP4 and FMF recording on the same frame.
+    StartMp4AndFMF((String, RecordingFrameRate)),
+    /// Stop MP4 and FMF recording on the same frame.
+    StopMp4AndFMF,
+    StopAllRecordings,
+    /// Finalize all sinks, reporting the progress on the given channel, which
+    /// is closed when done.
+    Shutdown(shutdown::ShutdownTx),
+    /// Save a bug report bundle with the given filename.
+    SaveBugReport(String),
     #[cfg(feature = "flydra_feat_detect")]
     StartUFMF(String),
     #[cfg(feature = "flydra_feat_detect")]
@@ -239,8 +288,16 @@ pub(crate) enum Msg {
     TakeCurrentImageAsBackground,
     #[cfg(feature = "flydra_feat_detect")]
     ClearBackground(f32),
+    #[cfg(feature = "flydra_feat_detect")]
+    ResetBackgroundModel,
+    #[cfg(feature = "flydra_feat_detect")]
+    SetBackgroundFrozen(bool),
     SetFrameOffset(u64),
     SetTriggerboxClockModel(Option<rust_cam_bui_types::ClockModel>),
+    /// Timestamp frames with their camera timestamp converted to host time.
+    SetClockSkewModel(ci2_remote_control::DeviceClockModel),
+    /// The region of interest is now at this offset.
+    RoiOffsetApplied((u32, u32)),
     StartAprilTagRec(String),
     StopAprilTagRec,
 }
@@ -311,18 +368,45 @@ where
     writer: FMFWriter<T>,
     recording_framerate: RecordingFrameRate,
     last_saved_stamp: Option<chrono::DateTime<chrono::Utc>>,
+    /// The final name of the file.
+    path: PathBuf,
+}
+
+impl FmfWriteInfo<std::fs::File> {
+    /// Create the FMF file `path`. Until finished, it is written under the
+    /// temporary name given by [bg_movie_writer::tmp_path].
+    fn create(path: PathBuf, recording_framerate: RecordingFrameRate) -> Result<Self> {
+        let f = std::fs::File::create(bg_movie_writer::tmp_path(&path))?;
+        Ok(Self {
+            writer: FMFWriter::new(f)?,
+            recording_framerate,
+            last_saved_stamp: None,
+            path,
+        })
+    }
+
+    /// Close the file and rename it to its final name.
+    fn finish(self) -> Result<()> {
+        self.writer.close()?;
+        std::fs::rename(bg_movie_writer::tmp_path(&self.path), &self.path)?;
+        Ok(())
+    }
 }
 
 impl<T> FmfWriteInfo<T>
 where
     T: std::io::Write + std::io::Seek,
 {
-    fn new(writer: FMFWriter<T>, recording_framerate: RecordingFrameRate) -> Self {
-        Self {
-            writer,
-            recording_framerate,
-            last_saved_stamp: None,
-        }
+    /// Based on our recording framerate, do we need to save a frame with this
+    /// timestamp?
+    fn do_save(&self, stamp: chrono::DateTime<chrono::Utc>) -> Result<bool> {
+        Ok(match self.last_saved_stamp {
+            None => true,
+            Some(last_stamp) => {
+                let elapsed = stamp - last_stamp;
+                elapsed >= chrono::Duration::from_std(self.recording_framerate.interval())?
+            }
+        })
     }
 }
 
@@ -429,6 +513,10 @@ struct StrandCamAppState {
     callback_senders: StrandCamCallbackSenders,
     tx_new_connection: tokio::sync::mpsc::Sender<event_stream_types::ConnectionEvent>,
     shared_store_arc: Arc<parking_lot::RwLock<ChangeTracker<StoreType>>>,
+    data_dir: PathBuf,
+    mjpeg_preview: video_streaming::MjpegPreview,
+    /// Notified on client requests to restart acquisition if idle.
+    idle_wake: Arc<tokio::sync::Notify>,
 }
 
 type MyBody = http_body_util::combinators::BoxBody<bytes::Bytes, bui_backend_session::Error>;
@@ -570,9 +658,11 @@ struct ProcessFrameCbData {}
 pub enum TimestampSource {
     BraidTrigger, // TODO: rename to CleverComputation or similar
     HostAcquiredTimestamp,
+    /// The camera timestamp converted to host time, see [ClockSkewConfig].
+    DeviceClockModel,
 }
 
-const MOMENT_CENTROID_SCHEMA_VERSION: u8 = 2;
+const MOMENT_CENTROID_SCHEMA_VERSION: u8 = 3;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct MomentCentroid {
@@ -587,11 +677,24 @@ pub struct MomentCentroid {
     pub center_y: u32,
     #[serde(default)]
     pub cam_name: String,
+    /// The time from `timestamp` until the message was sent, in microseconds.
+    #[serde(default)]
+    pub latency_usec: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 enum CentroidToDevice {
     Centroid(MomentCentroid),
+    RoiStats(RoiStatsFrame),
+}
+
+impl CentroidToDevice {
+    fn set_latency_usec(&mut self, latency_usec: u64) {
+        match self {
+            Self::Centroid(mc) => mc.latency_usec = latency_usec,
+            Self::RoiStats(frame) => frame.latency_usec = latency_usec,
+        }
+    }
 }
 
 /// CLI args for the case when we will connect to Braid.
@@ -603,6 +706,8 @@ enum CentroidToDevice {
 pub struct BraidArgs {
     pub braid_url: String,
     pub camera_name: String,
+    /// If set, used instead of the point detection configuration from Braid.
+    pub point_detection_config: Option<ImPtDetectCfg>,
 }
 
 /// CLI args for the case when we run standalone.
@@ -629,6 +734,16 @@ pub struct StandaloneArgs {
     pub acquisition_duration_allowed_imprecision_msec: Option<f64>,
     /// Filename of vendor-specific camera settings file.
     pub camera_settings_filename: Option<std::path::PathBuf>,
+    /// The user set stored on the camera loaded when it is opened.
+    pub user_set: Option<String>,
+    /// Number of frames buffered by the camera backend.
+    pub frame_buffer_config: Option<ci2::FrameBufferConfig>,
+    /// In-camera image post-processing.
+    pub post_processing: Option<ci2::PostProcessingConfig>,
+    /// Directory with the named configuration profiles.
+    pub profile_dir: Option<std::path::PathBuf>,
+    /// The configuration profile applied at startup.
+    pub profile: Option<String>,
     #[cfg(feature = "flydra_feat_detect")]
     pub tracker_cfg_src: ImPtDetectCfgSource,
 }
@@ -653,6 +768,48 @@ pub struct StrandCamArgs {
     pub secret: Option<String>,
     pub no_browser: bool,
     pub mp4_filename_template: String,
+    /// Initial configuration for splitting MP4 recordings into segments.
+    pub mp4_segment_config: ci2_remote_control::Mp4SegmentConfig,
+    /// Initial watermark drawn into MP4 recordings, if any.
+    pub compliance_watermark: Option<ci2_remote_control::ComplianceWatermarkConfig>,
+    /// Free disk space thresholds at which to warn and to stop recording.
+    pub disk_space_config: disk_space_watcher::DiskSpaceConfig,
+    /// Open file and memory usage at which to warn and to reduce usage.
+    pub resource_limits_config: resource_watcher::ResourceLimitsConfig,
+    /// Advertise the browser UI on the local network with mDNS.
+    pub mdns_advertise: bool,
+    /// Where to publish live detections and recording state changes.
+    pub event_publisher_config: EventPublisherConfig,
+    /// If set, save diagnostic data when frames are dropped or stall.
+    pub diagnostics_config: Option<DiagnosticsConfig>,
+    /// Rate, size and quality of the MJPEG preview stream.
+    pub mjpeg_config: MjpegConfig,
+    /// If set, move the region of interest on the sensor to follow detections.
+    pub roi_follow_config: Option<RoiFollowConfig>,
+    /// If set, compute statistics of named regions of interest while ImOps
+    /// detection is enabled.
+    pub roi_stats_config: Option<RoiStatsConfig>,
+    /// If set, assign the CUDA device for NVENC encoding from a pool of
+    /// devices shared with other cameras.
+    pub nvenc_pool: Option<NvencPoolArgs>,
+    /// If set, lower the frame rate while nothing is detected.
+    pub adaptive_fps_config: Option<AdaptiveFpsConfig>,
+    /// If set, stop acquisition while the camera is not used.
+    pub idle_mode_config: Option<IdleModeConfig>,
+    /// If set, timestamp frames with the camera clock converted to host time.
+    pub clock_skew_config: Option<ClockSkewConfig>,
+    /// If set, run a self-test at startup and refuse recording until it
+    /// passed.
+    pub self_test_config: Option<SelfTestConfig>,
+    /// If set, upload finished MP4 recordings to this server.
+    pub upload_config: Option<UploadConfig>,
+    /// If set, detect points with this detector plugin instead of background
+    /// subtraction.
+    #[cfg(feature = "dnn-detector")]
+    pub dnn_detector_config: Option<DnnDetectorConfig>,
+    /// If set, reject detections before they are sent to Braid and saved.
+    #[cfg(feature = "flydra_feat_detect")]
+    pub detection_filter_config: Option<DetectionFilterConfig>,
     pub fmf_filename_template: String,
     pub ufmf_filename_template: String,
     pub disable_console: bool,
@@ -696,6 +853,26 @@ impl Default for StrandCamArgs {
             secret: None,
             no_browser: true,
             mp4_filename_template: "movie%Y%m%d_%H%M%S.%f_{CAMNAME}.mp4".to_string(),
+            mp4_segment_config: Default::default(),
+            compliance_watermark: None,
+            disk_space_config: Default::default(),
+            resource_limits_config: Default::default(),
+            mdns_advertise: true,
+            event_publisher_config: Default::default(),
+            diagnostics_config: None,
+            mjpeg_config: Default::default(),
+            roi_follow_config: None,
+            roi_stats_config: None,
+            nvenc_pool: None,
+            adaptive_fps_config: None,
+            idle_mode_config: None,
+            clock_skew_config: None,
+            self_test_config: None,
+            upload_config: None,
+            #[cfg(feature = "dnn-detector")]
+            dnn_detector_config: None,
+            #[cfg(feature = "flydra_feat_detect")]
+            detection_filter_config: None,
             fmf_filename_template: "movie%Y%m%d_%H%M%S.%f_{CAMNAME}.fmf".to_string(),
             ufmf_filename_template: "movie%Y%m%d_%H%M%S.%f_{CAMNAME}.ufmf".to_string(),
             disable_console: false,
@@ -729,9 +906,12 @@ fn test_nvenc_save(frame: DynamicFrame) -> Result<bool> {
         codec: Mp4Codec::H264NvEnc(NvidiaH264Options {
             bitrate: 1000,
             cuda_device: 0,
+            ..Default::default()
         }),
         h264_metadata: None,
         max_framerate: RecordingFrameRate::Fps30,
+        container_metadata: None,
+        fragment_duration_secs: None,
     };
     let mut nv_cfg_test = cfg.clone();
 
@@ -793,6 +973,7 @@ async fn events_handler(
 ) -> impl axum::response::IntoResponse {
     session_key.is_present();
     tracing::trace!("events");
+    app_state.idle_wake.notify_one();
     // Connection wants to subscribe to event stream.
 
     let key = ConnectionSessionKey::new(session_key.0, addr);
@@ -865,6 +1046,63 @@ async fn cam_name_handler(
     app_state.cam_name.clone()
 }
 
+/// Download the most recently saved bug report bundle.
+async fn bug_report_handler(
+    axum::extract::State(app_state): axum::extract::State<StrandCamAppState>,
+    session_key: axum_token_auth::SessionKey,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+    session_key.is_present();
+    let filename = {
+        let tracker = app_state.shared_store_arc.read();
+        let shared: &StoreType = tracker.as_ref();
+        shared.bug_report_filename.clone()
+    };
+    let Some(filename) = filename else {
+        return (StatusCode::NOT_FOUND, "no bug report saved").into_response();
+    };
+    match std::fs::read(app_state.data_dir.join(&filename)) {
+        Ok(buf) => (
+            [
+                (http::header::CONTENT_TYPE, "application/zip".to_string()),
+                (
+                    http::header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{filename}\""),
+                ),
+            ],
+            buf,
+        )
+            .into_response(),
+        Err(e) => {
+            error!("could not read bug report \"{filename}\": {e}");
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("{e}")).into_response()
+        }
+    }
+}
+
+/// Stream a preview of the live video as MJPEG, e.g. for viewing in a browser.
+///
+/// This is independent of the browser UI and of recording. Any number of
+/// clients may connect.
+async fn mjpeg_preview_handler(
+    axum::extract::State(app_state): axum::extract::State<StrandCamAppState>,
+    session_key: axum_token_auth::SessionKey,
+) -> impl axum::response::IntoResponse {
+    session_key.is_present();
+    app_state.idle_wake.notify_one();
+    let body = axum::body::Body::from_stream(app_state.mjpeg_preview.subscribe());
+    (
+        [
+            (
+                http::header::CONTENT_TYPE,
+                video_streaming::MjpegPreview::content_type(),
+            ),
+            (http::header::CACHE_CONTROL, "no-cache".to_string()),
+        ],
+        body,
+    )
+}
+
 async fn callback_handler(
     axum::extract::State(app_state): axum::extract::State<StrandCamAppState>,
     session_key: axum_token_auth::SessionKey,
@@ -872,6 +1110,7 @@ async fn callback_handler(
 ) -> impl axum::response::IntoResponse {
     session_key.is_present();
     tracing::trace!("callback");
+    app_state.idle_wake.notify_one();
     match payload {
         CallbackType::ToCamera(cam_arg) => {
             debug!("in cb: {:?}", cam_arg);
@@ -1161,9 +1400,18 @@ where
                 let camdata_udp_port = config_from_braid.camdata_udp_port;
                 let camdata_udp_addr = SocketAddr::new(camdata_udp_ip, camdata_udp_port);
 
-                let tracker_cfg_src = crate::ImPtDetectCfgSource::ChangesNotSavedToDisk(
-                    config_from_braid.config.point_detection_config.clone(),
-                );
+                let point_detection_config = match &braid_args.point_detection_config {
+                    Some(cfg) => {
+                        warn!(
+                            "Using local point detection configuration instead of the \
+                            configuration from Braid."
+                        );
+                        cfg.clone()
+                    }
+                    None => config_from_braid.config.point_detection_config.clone(),
+                };
+                let tracker_cfg_src =
+                    crate::ImPtDetectCfgSource::ChangesNotSavedToDisk(point_detection_config);
 
                 Ok(BraidInfo {
                     mainbrain_session,
@@ -1303,6 +1551,7 @@ where
         quit_rx,
         gui_singleton,
         log_file_info.data_dir,
+        new_log_file_name,
     )
     .await
 }
@@ -1322,7 +1571,8 @@ where
     strand_cam_bui_http_address_string,
     quit_rx,
     gui_singleton,
-    data_dir
+    data_dir,
+    log_file_name
 ))]
 async fn run<M, C, G>(
     mut mymod: ci2_async::ThreadedAsyncCameraModule<M, C, G>,
@@ -1334,6 +1584,7 @@ async fn run<M, C, G>(
     quit_rx: Option<tokio::sync::mpsc::Receiver<()>>,
     gui_singleton: ArcMutGuiSingleton,
     data_dir: PathBuf,
+    log_file_name: PathBuf,
 ) -> Result<ci2_async::ThreadedAsyncCameraModule<M, C, G>>
 where
     M: ci2::CameraModule<CameraType = C, Guard = G>,
@@ -1341,6 +1592,19 @@ where
     G: Send,
 {
     let use_camera_name = cam; // simple arg name important for tracing::instrument
+
+    // FMF files are saved relative to the current directory, MP4 files in the
+    // data directory.
+    let mut recording_dirs = vec![data_dir.clone()];
+    if let Ok(cwd) = std::env::current_dir() {
+        if std::fs::canonicalize(&data_dir).ok().as_ref() != Some(&cwd) {
+            recording_dirs.push(cwd);
+        }
+    }
+    if let Err(e) = recovery::recover_orphaned_recordings(&data_dir, &recording_dirs) {
+        warn!("error recovering unfinished recordings: {e}");
+    }
+
     let frame_info_extractor = mymod.frame_info_extractor();
     let settings_file_ext = mymod.settings_file_extension().to_string();
 
@@ -1357,6 +1621,18 @@ where
     info!("  got camera {}", raw_name);
     let raw_cam_name = RawCamName::new(raw_name);
 
+    // Load the user set before any other setting, so that these are applied on
+    // top of it.
+    let user_set = match &res_braid {
+        Ok(bi) => bi.config_from_braid.config.user_set.clone(),
+        Err(a) => a.user_set.clone(),
+    };
+    if let Some(name) = &user_set {
+        cam.user_set_load(name)
+            .with_context(|| format!("Failed to load user set \"{name}\""))?;
+        info!("loaded user set \"{name}\"");
+    }
+
     let camera_gamma = cam
         .feature_float("Gamma")
         .map_err(|e| warn!("Ignoring error getting gamma: {}", e))
@@ -1469,6 +1745,52 @@ where
         (frame_rate_limit_supported, frame_rate_limit_enabled)
     };
 
+    let frame_buffer_config = match &res_braid {
+        Ok(bi) => bi.config_from_braid.config.frame_buffer_config,
+        Err(a) => a.frame_buffer_config,
+    };
+
+    if let Some(cfg) = frame_buffer_config {
+        info!(
+            "  setting frame buffers: {} driver, {} channel",
+            cfg.n_buffer_frames, cfg.n_channel_frames
+        );
+        cam.set_frame_buffer_config(cfg)?;
+    }
+
+    let post_processing_cfg = match &res_braid {
+        Ok(bi) => bi.config_from_braid.config.post_processing,
+        Err(a) => a.post_processing,
+    };
+
+    let post_processing_support = cam.post_processing_support()?;
+    let post_processing = if !(post_processing_support.denoise || post_processing_support.sharpen) {
+        if post_processing_cfg.is_some() {
+            error!("  post-processing not supported by this camera, ignoring configuration");
+        }
+        None
+    } else {
+        if let Some(cfg) = post_processing_cfg {
+            info!(
+                "  setting post-processing: denoise {:?}, sharpen {:?}",
+                cfg.denoise, cfg.sharpen
+            );
+            if let Err(e) = cam.set_post_processing(cfg) {
+                error!("  setting post-processing: {e}");
+            }
+        }
+        Some(cam.post_processing()?)
+    };
+
+    let (profile_dir, initial_profile) = match &res_braid {
+        Ok(bi) => (
+            bi.config_from_braid.config.profile_dir.clone(),
+            bi.config_from_braid.config.profile.clone(),
+        ),
+        Err(a) => (a.profile_dir.clone(), a.profile.clone()),
+    };
+    let profile_dir = profile_dir.map(profiles::ProfileDir::new);
+
     let settings_on_start = cam.node_map_save()?;
 
     cam.acquisition_start()?;
@@ -1657,7 +1979,12 @@ where
                 cam.feature_float_set(PERIOD_NAME, period)?;
                 tracing::debug!("Set camera parameter {PERIOD_NAME} to {period} microseconds");
             }
-            cam.feature_bool_set("PtpEnable", true)?;
+            cam.feature_bool_set("PtpEnable", true).with_context(|| {
+                format!(
+                    "enabling PTP on camera \"{}\", which must support PTP",
+                    cam.name()
+                )
+            })?;
             // Wait until we are within 1 msec from master.
             const THRESHOLD: i64 = 1_000_000; // Should make this a runtime parameter.
             loop {
@@ -1795,6 +2122,36 @@ where
         flydra_types::start_listener(&strand_cam_bui_http_address_string).await?;
     let listen_addr = listener.local_addr()?;
 
+    // Kept until Strand Cam ends, when the advertisement is withdrawn.
+    let _mdns_advertisement = if args.mdns_advertise {
+        let info = strand_cam_discovery::CameraNodeInfo {
+            camera_name: raw_cam_name.as_str().to_string(),
+            vendor: cam.vendor().into(),
+            model: cam.model().into(),
+            serial: cam.serial().into(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        };
+        match strand_cam_discovery::Advertisement::new(&info, listen_addr) {
+            Ok(advertisement) => {
+                info!(
+                    "Advertising browser UI via mDNS as {}",
+                    strand_cam_discovery::SERVICE_TYPE
+                );
+                Some(advertisement)
+            }
+            Err(e @ strand_cam_discovery::Error::LoopbackAddress(_)) => {
+                debug!("{e}");
+                None
+            }
+            Err(e) => {
+                warn!("Not advertising browser UI via mDNS: {e}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
     let mut transmit_msg_tx = None;
     if let Some(first_msg_tx) = first_msg_tx {
         let new_cam_data = flydra_types::RegisterNewCamera {
@@ -1806,6 +2163,14 @@ where
             }),
             current_image_png: current_image_png.into(),
             camera_periodic_signal_period_usec,
+            provenance: Some(provenance()),
+            feature_detect_settings: if cfg!(feature = "flydra_feat_detect") {
+                Some(flydra_types::UpdateFeatureDetectSettings {
+                    current_feature_detect_settings: im_pt_detect_cfg.clone(),
+                })
+            } else {
+                None
+            },
         };
 
         // Get the generic sender back.
@@ -1926,7 +2291,14 @@ where
             Vec::new()
         }
     };
-    let mp4_cuda_device = if !cuda_devices.is_empty() {
+    let nvenc_pool = args
+        .nvenc_pool
+        .as_ref()
+        .map(|pool_args| nvenc_pool::NvencPool::new(pool_args, &cuda_devices))
+        .transpose()?;
+    let mp4_cuda_device = if let Some(pool) = &nvenc_pool {
+        pool.device_name()
+    } else if !cuda_devices.is_empty() {
         cuda_devices[0].as_str()
     } else {
         ""
@@ -1952,6 +2324,18 @@ where
         StandaloneOrBraid::Standalone(_) => false,
     };
 
+    let profile_names = match &profile_dir {
+        Some(dir) => dir.names()?,
+        None => Vec::new(),
+    };
+    if let Some(name) = initial_profile {
+        // Applied before any other command.
+        cam_args_tx
+            .send(CamArg::ApplyProfile(name))
+            .await
+            .map_err(to_eyre)?;
+    }
+
     // -----------------------------------------------
     // Check if we can use nv h264 and, if so, set that as default.
 
@@ -2002,6 +2386,16 @@ where
     #[cfg(not(feature = "flydratrax"))]
     let has_flydratrax_compiled = false;
 
+    let self_test_expected_fps = args.self_test_config.as_ref().and_then(|cfg| {
+        cfg.expected_fps.or_else(|| match &trigger_type {
+            Some(trigger_type) => self_test::trigger_fps(trigger_type),
+            None => frame_rate_limit
+                .as_ref()
+                .filter(|_| frame_rate_limit_enabled)
+                .map(|frl| frl.current),
+        })
+    });
+
     let shared_store = ChangeTracker::new(StoreType {
         is_braid,
         ffmpeg_version,
@@ -2010,6 +2404,8 @@ where
         is_recording_mp4: None,
         is_recording_fmf: None,
         is_recording_ufmf: None,
+        recording_stop_time: None,
+        recording_label: Default::default(),
         format_str_apriltag_csv,
         format_str_mp4: mp4_filename_template,
         format_str: fmf_filename_template,
@@ -2021,6 +2417,9 @@ where
         mp4_codec,
         mp4_max_framerate: Default::default(),
         mp4_cuda_device,
+        mp4_proxy_enabled: false,
+        mp4_segment_config: args.mp4_segment_config.clone(),
+        compliance_watermark: args.compliance_watermark.clone(),
         gain: gain_ranged,
         gain_auto,
         exposure_time: exposure_ranged,
@@ -2032,7 +2431,13 @@ where
         image_width,
         image_height,
         is_doing_object_detection: false,
+        is_background_frozen: false,
+        background_unfreeze_time: None,
         measured_fps: 0.0,
+        frame_buffer_config,
+        frame_buffer_saturation: None,
+        output_latency: Default::default(),
+        post_processing,
         is_saving_im_pt_detect_csv: None,
         has_image_tracker_compiled,
         im_pt_detect_cfg: im_pt_detect_cfg.clone(),
@@ -2053,6 +2458,19 @@ where
         apriltag_state,
         im_ops_state,
         had_frame_processing_error: false,
+        disk_space: None,
+        disk_space_error: None,
+        resource_usage: None,
+        acquisition_idle: false,
+        device_clock_model: None,
+        self_test: args
+            .self_test_config
+            .as_ref()
+            .map(|_| rust_cam_bui_types::SelfTestReport::running()),
+        bug_report_filename: None,
+        profiles: profile_names,
+        active_profile: None,
+        profile_error: None,
         camera_calibration: None,
     });
 
@@ -2080,6 +2498,8 @@ where
     let shared_state = Arc::new(parking_lot::RwLock::new(shared_store));
     let shared_store_arc = shared_state.clone();
 
+    let (mjpeg_encoder, mjpeg_preview) = video_streaming::mjpeg_preview(args.mjpeg_config.clone());
+
     // Create our app state.
     let app_state = StrandCamAppState {
         cam_name: cam.name().to_string(),
@@ -2087,10 +2507,21 @@ where
         callback_senders,
         tx_new_connection,
         shared_store_arc,
+        data_dir: data_dir.clone(),
+        mjpeg_preview,
+        idle_wake: Arc::new(tokio::sync::Notify::new()),
     };
 
     let shared_store_arc = shared_state.clone();
 
+    let idle_mode_event_broadcaster = app_state.event_broadcaster.clone();
+    let idle_mode_mjpeg_preview = app_state.mjpeg_preview.clone();
+    let idle_mode_wake = app_state.idle_wake.clone();
+
+    // Used to close idle connections when resource usage is critical.
+    let idle_event_broadcaster = app_state.event_broadcaster.clone();
+    let idle_tx_new_connection = app_state.tx_new_connection.clone();
+
     // This future will send state updates to all connected event listeners.
     let event_broadcaster = app_state.event_broadcaster.clone();
     let send_updates_future = async move {
@@ -2149,6 +2580,8 @@ where
         .route("/strand-cam-events", axum::routing::get(events_handler))
         .route("/cam-name", axum::routing::get(cam_name_handler))
         .route("/callback", axum::routing::post(callback_handler))
+        .route("/bug-report", axum::routing::get(bug_report_handler))
+        .route("/preview.mjpeg", axum::routing::get(mjpeg_preview_handler))
         .nest_service("/", serve_dir)
         .layer(
             tower::ServiceBuilder::new()
@@ -2226,6 +2659,28 @@ where
     #[cfg(feature = "checkercal")]
     let collected_corners_arc: CollectedCornersArc = Arc::new(parking_lot::RwLock::new(Vec::new()));
 
+    let (mut frame_rate_check, self_test_future) = match &args.self_test_config {
+        Some(cfg) => {
+            let (check, frame_stats_rx) = self_test::FrameRateCheck::new(cfg.n_frames);
+            let fut = self_test::run(
+                cfg.clone(),
+                self_test_expected_fps,
+                frame_stats_rx,
+                #[cfg(feature = "flydra_feat_detect")]
+                im_pt_detect_cfg.clone(),
+                raw_cam_name.clone(),
+                data_dir.clone(),
+                shared_store_arc.clone(),
+                transmit_msg_tx.clone(),
+            );
+            (Some(check), Some(fut))
+        }
+        None => (None, None),
+    };
+
+    let heartbeat_counters = Arc::new(heartbeat::HeartbeatCounters::default());
+    let output_latency_counters = Arc::new(output_latency::OutputLatencyCounters::default());
+
     let frame_process_task_fut = {
         #[cfg(feature = "flydra_feat_detect")]
         let csv_save_dir = args.csv_save_dir.clone();
@@ -2247,6 +2702,83 @@ where
             model_server_data_tx
         };
 
+        let event_publisher = event_publisher::EventPublisher::new(
+            &args.event_publisher_config,
+            &format!("strand-cam-{}", raw_cam_name.as_str()),
+        )?;
+
+        let uploader = args
+            .upload_config
+            .clone()
+            .map(|cfg| uploader::Uploader::new(cfg, data_dir.clone()))
+            .transpose()?;
+
+        let frame_watchdog = args
+            .diagnostics_config
+            .clone()
+            .map(|cfg| diagnostics::FrameWatchdog::new(cfg, Some(log_file_name)));
+
+        let roi_follower = match &args.roi_follow_config {
+            Some(cfg) => {
+                if is_braid {
+                    // Braid expects detections in full-frame coordinates.
+                    eyre::bail!("Following the ROI is not supported with Braid.");
+                }
+                Some(roi_follow::RoiFollower::new(
+                    cfg.clone(),
+                    cam_args_tx.clone(),
+                    &raw_cam_name,
+                    (image_width, image_height),
+                    cam.roi_max_size()?,
+                    cam.roi_offset()?,
+                    &data_dir,
+                )?)
+            }
+            None => None,
+        };
+
+        let roi_stats = args
+            .roi_stats_config
+            .clone()
+            .map(|cfg| roi_stats::RoiStatsProcessor::new(cfg, &raw_cam_name, &data_dir))
+            .transpose()?;
+
+        let adaptive_frame_rate = match &args.adaptive_fps_config {
+            Some(_) if is_braid => {
+                // Braid triggers the cameras at a fixed rate.
+                warn!("Adaptive frame rate is not used with Braid.");
+                None
+            }
+            Some(_) if !frame_rate_limit_supported => {
+                eyre::bail!("Adaptive frame rate requires a camera with a frame rate limit.");
+            }
+            Some(cfg) => Some(adaptive_fps::AdaptiveFrameRate::new(
+                cfg.clone(),
+                cam_args_tx.clone(),
+            )?),
+            None => None,
+        };
+
+        #[cfg(feature = "flydra_feat_detect")]
+        let detection_filter = args
+            .detection_filter_config
+            .clone()
+            .map(detection_filter::DetectionFilter::new)
+            .transpose()?;
+
+        #[cfg(feature = "dnn-detector")]
+        let dnn_detector = match &args.dnn_detector_config {
+            Some(cfg) => {
+                let detector = detector_plugin::OnnxDetector::new(
+                    cfg,
+                    image_width as usize,
+                    image_height as usize,
+                )?;
+                Some(detector_plugin::AsyncDetector::new(detector, cfg.clone()))
+            }
+            None => None,
+        };
+
         let cam_name2 = raw_cam_name.clone();
         frame_process_task(
             #[cfg(feature = "flydratrax")]
@@ -2293,7 +2825,20 @@ where
             trigger_type,
             #[cfg(target_os = "linux")]
             v4l_out_stream,
-            data_dir,
+            data_dir.clone(),
+            event_publisher,
+            uploader,
+            frame_watchdog,
+            roi_follower,
+            roi_stats,
+            nvenc_pool,
+            adaptive_frame_rate,
+            heartbeat_counters.clone(),
+            output_latency_counters.clone(),
+            #[cfg(feature = "flydra_feat_detect")]
+            detection_filter,
+            #[cfg(feature = "dnn-detector")]
+            dnn_detector,
         )
     };
     debug!("frame_process_task spawned");
@@ -2308,11 +2853,41 @@ where
     // install frame handling
     let n_buffered_frames = 100;
     let mut frame_stream = cam.frames(n_buffered_frames)?;
+
+    match &args.idle_mode_config {
+        Some(_) if is_braid => {
+            // Braid requires frames from all cameras.
+            warn!("Idle mode is not used with Braid.");
+        }
+        Some(cfg) => {
+            tokio::spawn(idle_mode::run(
+                cfg.clone(),
+                cam.acquisition_control(),
+                move || idle_mode_event_broadcaster.num_connections(),
+                idle_mode_mjpeg_preview,
+                shared_store_arc.clone(),
+                idle_mode_wake,
+            ));
+        }
+        None => {}
+    }
+
+    match &args.clock_skew_config {
+        Some(_) if is_braid => {
+            warn!("Clock skew correction is not used with Braid.");
+        }
+        Some(cfg) => {
+            tokio::spawn(clock_skew::run(cfg.clone(), cam_args_tx.clone()));
+        }
+        None => {}
+    }
+
     let cam_stream_future = {
         let shared_store_arc = shared_store_arc.clone();
         let frame_processing_error_state = frame_processing_error_state.clone();
         let mut transmit_msg_tx = transmit_msg_tx.clone();
         let raw_cam_name = raw_cam_name.clone();
+        let heartbeat_counters = heartbeat_counters.clone();
         async move {
             let mut send_current_image_timer =
                 std::time::Instant::now() - send_current_image_interval;
@@ -2326,6 +2901,11 @@ where
                             frame.width(),
                             frame.height()
                         );
+                        if let Some(check) = frame_rate_check.as_mut() {
+                            if check.push(frame) {
+                                frame_rate_check = None;
+                            }
+                        }
                         if tx_frame.capacity() == 0 {
                             let mut tracker = shared_store_arc.write();
                             tracker.modify(|tracker| {
@@ -2347,6 +2927,10 @@ where
                                 }
                             });
                             error!("Channel full sending frame to process thread. Dropping frame data.");
+                            heartbeat_counters.frame_dropped();
+                            if let Some(check) = frame_rate_check.as_mut() {
+                                check.frame_dropped();
+                            }
                         } else {
                             tx_frame
                                 .send(Msg::Mframe(frame.clone()))
@@ -2441,16 +3025,123 @@ where
         debug!("version check future spawned {}:{}", file!(), line!());
     }
 
-    tokio::spawn(Box::pin(cam_stream_future));
+    let cam_stream_jh = tokio::spawn(Box::pin(cam_stream_future));
     debug!("cam_stream_future future spawned {}:{}", file!(), line!());
 
+    if let Some(self_test_future) = self_test_future {
+        tokio::spawn(self_test_future);
+    }
+
+    tokio::spawn(output_latency::run(
+        output_latency_counters,
+        shared_store_arc.clone(),
+    ));
+
+    if let Some(transmit_msg_tx) = &transmit_msg_tx {
+        tokio::spawn(heartbeat::run(
+            heartbeat_counters,
+            raw_cam_name.clone(),
+            shared_store_arc.clone(),
+            transmit_msg_tx.clone(),
+        ));
+    }
+
+    {
+        // Monitor free disk space and stop recording before the disk is full.
+        let shared_store_arc = shared_store_arc.clone();
+        let cam_args_tx = cam_args_tx.clone();
+        let watcher = disk_space_watcher::watch(
+            data_dir.clone(),
+            args.disk_space_config.clone(),
+            disk_space_watcher::DEFAULT_INTERVAL,
+            move |status| {
+                let is_critical = status.level == disk_space_watcher::DiskSpaceLevel::Critical;
+                let mut tracker = shared_store_arc.write();
+                let is_recording_any = {
+                    let shared: &StoreType = tracker.as_ref();
+                    shared.is_recording_mp4.is_some()
+                        || shared.is_recording_fmf.is_some()
+                        || shared.is_recording_ufmf.is_some()
+                };
+                let stop_msg = if is_critical && is_recording_any {
+                    Some(format!(
+                        "Recording stopped because only {:.1} GB of free space remains on \"{}\".",
+                        status.available_bytes as f64 / 1e9,
+                        status.path
+                    ))
+                } else {
+                    None
+                };
+                tracker.modify(|shared| {
+                    shared.disk_space = Some(status);
+                    if stop_msg.is_some() {
+                        shared.disk_space_error = stop_msg.clone();
+                    }
+                });
+                if let Some(msg) = stop_msg {
+                    error!("{msg}");
+                    if let Err(e) = cam_args_tx.try_send(CamArg::StopAllRecordings) {
+                        error!("could not stop recordings: {e}");
+                    }
+                }
+            },
+        );
+        tokio::spawn(watcher);
+    }
+
+    {
+        // Monitor open files, sockets and memory and reduce their use before
+        // a limit is reached. While usage is critical, idle browser
+        // connections are closed and the frame processing task postpones new
+        // MP4 segments.
+        match resource_watcher::raise_open_files_limit() {
+            Ok(limit) => debug!("limit on open files: {limit}"),
+            Err(e) => debug!("could not raise limit on open files: {e}"),
+        }
+        let shared_store_arc = shared_store_arc.clone();
+        let watcher = resource_watcher::watch(
+            args.resource_limits_config.clone(),
+            resource_watcher::DEFAULT_INTERVAL,
+            move |usage| {
+                if usage.level == resource_watcher::ResourceLevel::Critical {
+                    let closed = idle_event_broadcaster.close_idle_connections();
+                    for key in closed.iter() {
+                        // Also stop sending video to the connection.
+                        let evt = ConnectionEvent {
+                            typ: ConnectionEventType::Disconnect,
+                            session_key: SessionKey(key.session_key()),
+                            connection_key: ConnectionKey {
+                                addr: key.connection_key(),
+                            },
+                            path: "/strand-cam-events".to_string(),
+                        };
+                        if let Err(e) = idle_tx_new_connection.try_send(evt) {
+                            warn!("could not close video stream: {e}");
+                        }
+                    }
+                    if !closed.is_empty() {
+                        warn!("closed {} idle browser connection(s)", closed.len());
+                    }
+                }
+                let mut tracker = shared_store_arc.write();
+                tracker.modify(|shared| shared.resource_usage = Some(usage));
+            },
+        );
+        tokio::spawn(watcher);
+    }
+
     let cam_arg_future = {
         let shared_store_arc = shared_store_arc.clone();
+        let cam_args_tx = cam_args_tx.clone();
 
         #[cfg(feature = "checkercal")]
         let cam_name2 = raw_cam_name.clone();
 
         let mut cam_args_rx = tokio_stream::wrappers::ReceiverStream::new(cam_args_rx);
+        let mut device_clock_estimator = args
+            .clock_skew_config
+            .as_ref()
+            .map(|cfg| clock_skew::DeviceClockEstimator::new(cfg.window));
 
         async move {
             // We do not put cam_args_rx behind a stream_cancel::Valve because
@@ -2462,8 +3153,83 @@ where
             // or if it is run within Braid, in which Braid will send it a DoQuit
             // message. Finally, when other threads panic, they should also send a
             // DoQuit message.
-            while let Some(cam_args) = cam_args_rx.next().await {
+            // Task which will stop all recordings at a scheduled time.
+            let mut scheduled_stop: Option<tokio::task::JoinHandle<()>> = None;
+            // Task which will resume the background model updates at a
+            // scheduled time.
+            #[cfg(feature = "flydra_feat_detect")]
+            let mut scheduled_unfreeze: Option<tokio::task::JoinHandle<()>> = None;
+            // Commands of an applied profile, handled before the next received
+            // command.
+            let mut profile_cam_args = std::collections::VecDeque::new();
+            // The profile whose commands are in `profile_cam_args`.
+            let mut applying_profile: Option<profiles::ProfileApplication> = None;
+            loop {
+                let cam_args = match profile_cam_args.pop_front() {
+                    Some(cam_args) => cam_args,
+                    None => {
+                        // All commands of the profile were handled.
+                        if let Some(applying) = applying_profile.take() {
+                            profiles::finish(
+                                applying,
+                                &raw_cam_name,
+                                &shared_store_arc,
+                                transmit_msg_tx.as_ref(),
+                            )
+                            .await;
+                        }
+                        match cam_args_rx.next().await {
+                            Some(cam_args) => cam_args,
+                            None => break,
+                        }
+                    }
+                };
                 debug!("handling camera command {:?}", cam_args);
+                if self_test::starts_recording(&cam_args) {
+                    let label = {
+                        let tracker = shared_store_arc.read();
+                        let shared: &StoreType = tracker.as_ref();
+                        if let Some(reason) =
+                            shared.self_test.as_ref().and_then(|r| r.blocks_recording())
+                        {
+                            error!("Not starting recording because {reason}.");
+                            continue;
+                        }
+                        shared.recording_label.clone()
+                    };
+                    if !label.is_empty() {
+                        info!("Starting recording with label {label}.");
+                        // Save the label in the textlog of the `.braidz` file.
+                        if let Some(transmit_msg_tx) = &transmit_msg_tx {
+                            let msg = flydra_types::BraidHttpApiCallback::AddTextlog(
+                                flydra_types::PerCam {
+                                    raw_cam_name: raw_cam_name.clone(),
+                                    inner: format!("recording started with label {label}"),
+                                },
+                            );
+                            if let Err(e) = transmit_msg_tx.send(msg).await {
+                                error!("could not send recording label to Braid: {e}");
+                            }
+                        }
+                    }
+                }
+                // Starting or stopping a recording by hand replaces a scheduled
+                // stop, which would otherwise stop a later recording.
+                if matches!(
+                    cam_args,
+                    CamArg::SetIsRecordingMp4(_)
+                        | CamArg::SetIsRecordingFmf(_)
+                        | CamArg::SetIsRecordingMp4AndFmf(_)
+                        | CamArg::SetIsRecordingUfmf(_)
+                        | CamArg::PostTrigger
+                ) {
+                    if let Some(handle) = scheduled_stop.take() {
+                        info!("Cancelling scheduled stop of recordings");
+                        handle.abort();
+                        let mut tracker = shared_store_arc.write();
+                        tracker.modify(|shared| shared.recording_stop_time = None);
+                    }
+                }
                 #[allow(unused_variables)]
                 match cam_args {
                     CamArg::SetIngoreFutureFrameProcessingErrors(v) => {
@@ -2486,6 +3252,80 @@ where
                         let mut tracker = shared_store_arc.write();
                         tracker.modify(|tracker| tracker.had_frame_processing_error = false);
                     }
+                    CamArg::DismissDiskSpaceError => {
+                        let mut tracker = shared_store_arc.write();
+                        tracker.modify(|tracker| tracker.disk_space_error = None);
+                    }
+                    CamArg::DismissSelfTestFailure => {
+                        let report = {
+                            let tracker = shared_store_arc.read();
+                            let shared: &StoreType = tracker.as_ref();
+                            shared.self_test.clone()
+                        };
+                        if let Some(mut report) = report {
+                            report.failure_dismissed = true;
+                            self_test::publish(
+                                report,
+                                &raw_cam_name,
+                                &shared_store_arc,
+                                transmit_msg_tx.as_ref(),
+                            )
+                            .await;
+                        }
+                    }
+                    CamArg::ApplyProfile(name) => {
+                        let Some(profile_dir) = profile_dir.as_ref() else {
+                            error!("Cannot apply profile \"{name}\": no profile directory set.");
+                            continue;
+                        };
+                        // Validate all settings first, so that an invalid
+                        // profile is not partially applied.
+                        let cam_args = profile_dir.load(&name).and_then(|p| {
+                            let tracker = shared_store_arc.read();
+                            p.validate(tracker.as_ref(), is_braid)?;
+                            p.cam_args()
+                        });
+                        // Also show profiles added since startup.
+                        let names = profile_dir.names();
+                        let mut tracker = shared_store_arc.write();
+                        match cam_args {
+                            Ok(cam_args) => {
+                                info!("Applying profile \"{name}\".");
+                                profile_cam_args.extend(cam_args);
+                                applying_profile = Some(profiles::ProfileApplication::new(name));
+                                tracker.modify(|shared| {
+                                    if let Ok(names) = names {
+                                        shared.profiles = names;
+                                    }
+                                    shared.profile_error = None;
+                                });
+                            }
+                            Err(e) => {
+                                // The camera keeps its settings.
+                                error!("Not applying profile \"{name}\": {e:#}");
+                                tracker.modify(|shared| {
+                                    if let Ok(names) = names {
+                                        shared.profiles = names;
+                                    }
+                                    shared.profile_error =
+                                        Some(format!("Not applying profile \"{name}\": {e:#}"));
+                                });
+                            }
+                        }
+                    }
+                    CamArg::SaveBugReport => {
+                        let filename = chrono::Local::now()
+                            .format(
+                                &bug_report::BUG_REPORT_FILENAME_TEMPLATE
+                                    .replace("{CAMNAME}", raw_cam_name.as_str()),
+                            )
+                            .to_string();
+                        info!("Saving bug report to \"{filename}\"");
+                        tx_frame2
+                            .send(Msg::SaveBugReport(filename))
+                            .await
+                            .map_err(to_eyre)?;
+                    }
                     CamArg::SetExposureTime(v) => match cam.set_exposure_time(v) {
                         Ok(()) => {
                             if let Some(transmit_msg_tx) = &transmit_msg_tx {
@@ -2502,7 +3342,10 @@ where
                             tracker.modify(|tracker| tracker.exposure_time.current = v);
                         }
                         Err(e) => {
-                            error!("setting exposure_time: {:?}", e);
+                            profiles::command_failed(
+                                &mut applying_profile,
+                                format!("setting exposure_time: {e:?}"),
+                            );
                         }
                     },
                     CamArg::SetGain(v) => match cam.set_gain(v) {
@@ -2521,7 +3364,31 @@ where
                             tracker.modify(|tracker| tracker.gain.current = v);
                         }
                         Err(e) => {
-                            error!("setting gain: {:?}", e);
+                            profiles::command_failed(
+                                &mut applying_profile,
+                                format!("setting gain: {e:?}"),
+                            );
+                        }
+                    },
+                    CamArg::SetFrameBufferConfig(v) => match cam.set_frame_buffer_config(v) {
+                        Ok(()) => {
+                            let mut tracker = shared_store_arc.write();
+                            tracker.modify(|shared| {
+                                shared.frame_buffer_config = Some(v);
+                                shared.frame_buffer_saturation = None;
+                            });
+                        }
+                        Err(e) => {
+                            error!("setting frame buffer config: {:?}", e);
+                        }
+                    },
+                    CamArg::SetPostProcessing(v) => match cam.set_post_processing(v) {
+                        Ok(()) => {
+                            let mut tracker = shared_store_arc.write();
+                            tracker.modify(|shared| shared.post_processing = Some(v));
+                        }
+                        Err(e) => {
+                            error!("setting post-processing: {:?}", e);
                         }
                     },
                     CamArg::SetGainAuto(v) => match cam.set_gain_auto(v) {
@@ -2548,9 +3415,34 @@ where
                             });
                         }
                         Err(e) => {
-                            error!("setting gain_auto: {:?}", e);
+                            profiles::command_failed(
+                                &mut applying_profile,
+                                format!("setting gain_auto: {e:?}"),
+                            );
                         }
                     },
+                    CamArg::SetTriggerMode(v) => {
+                        if is_braid {
+                            profiles::command_failed(
+                                &mut applying_profile,
+                                "Not setting trigger mode: the trigger is configured by Braid."
+                                    .to_string(),
+                            );
+                            continue;
+                        }
+                        match cam.set_trigger_mode(v) {
+                            Ok(()) => {
+                                let mut tracker = shared_store_arc.write();
+                                tracker.modify(|shared| shared.trigger_mode = v);
+                            }
+                            Err(e) => {
+                                profiles::command_failed(
+                                    &mut applying_profile,
+                                    format!("setting trigger_mode: {e:?}"),
+                                );
+                            }
+                        }
+                    }
                     CamArg::SetRecordingFps(v) => {
                         let mut tracker = shared_store_arc.write();
                         tracker.modify(|tracker| tracker.mp4_max_framerate = v);
@@ -2563,6 +3455,22 @@ where
                         let mut tracker = shared_store_arc.write();
                         tracker.modify(|tracker| tracker.mp4_max_framerate = v);
                     }
+                    CamArg::SetMp4ProxyEnabled(v) => {
+                        let mut tracker = shared_store_arc.write();
+                        tracker.modify(|tracker| tracker.mp4_proxy_enabled = v);
+                    }
+                    CamArg::SetMp4SegmentConfig(v) => {
+                        let mut tracker = shared_store_arc.write();
+                        tracker.modify(|tracker| tracker.mp4_segment_config = v);
+                    }
+                    CamArg::SetComplianceWatermark(v) => {
+                        let mut tracker = shared_store_arc.write();
+                        tracker.modify(|tracker| tracker.compliance_watermark = v);
+                    }
+                    CamArg::SetRecordingLabel(v) => {
+                        let mut tracker = shared_store_arc.write();
+                        tracker.modify(|tracker| tracker.recording_label = v);
+                    }
                     CamArg::SetMp4Bitrate(v) => {
                         let mut tracker = shared_store_arc.write();
                         tracker.modify(|tracker| tracker.mp4_bitrate = v);
@@ -2595,7 +3503,10 @@ where
                             });
                         }
                         Err(e) => {
-                            error!("setting exposure_auto: {:?}", e);
+                            profiles::command_failed(
+                                &mut applying_profile,
+                                format!("setting exposure_auto: {e:?}"),
+                            );
                         }
                     },
                     CamArg::SetFrameRateLimitEnabled(v) => {
@@ -2624,7 +3535,10 @@ where
                             });
                             }
                             Err(e) => {
-                                error!("setting frame_rate_limit_enabled: {:?}", e);
+                                profiles::command_failed(
+                                    &mut applying_profile,
+                                    format!("setting frame_rate_limit_enabled: {e:?}"),
+                                );
                             }
                         }
                     }
@@ -2658,9 +3572,45 @@ where
                             });
                         }
                         Err(e) => {
-                            error!("setting frame_rate_limit: {:?}", e);
+                            profiles::command_failed(
+                                &mut applying_profile,
+                                format!("setting frame_rate_limit: {e:?}"),
+                            );
                         }
                     },
+                    CamArg::MeasureDeviceClock => {
+                        let Some(estimator) = device_clock_estimator.as_mut() else {
+                            continue;
+                        };
+                        match measure_times(&cam) {
+                            Ok((host_time, device_time)) => {
+                                estimator.push(host_time, device_time.try_into().unwrap());
+                            }
+                            Err(e) => {
+                                warn!("measuring camera clock: {e}");
+                                continue;
+                            }
+                        }
+                        match estimator.model() {
+                            Some(Ok(cm)) => {
+                                debug!("camera clock model: {cm:?}");
+                                {
+                                    let mut tracker = shared_store_arc.write();
+                                    tracker.modify(|shared| {
+                                        shared.device_clock_model = Some(cm.clone())
+                                    });
+                                }
+                                tx_frame2
+                                    .send(Msg::SetClockSkewModel(cm))
+                                    .await
+                                    .map_err(to_eyre)?;
+                            }
+                            Some(Err(e)) => {
+                                warn!("{e}");
+                            }
+                            None => {}
+                        }
+                    }
                     CamArg::SetFrameOffset(fo) => {
                         tx_frame2
                             .send(Msg::SetFrameOffset(fo))
@@ -2673,6 +3623,23 @@ where
                             .await
                             .map_err(to_eyre)?;
                     }
+                    CamArg::SetRoiOffset { offset_x, offset_y } => {
+                        if let Err(e) = cam.set_roi_offset(offset_x, offset_y) {
+                            error!("setting ROI offset: {e:?}");
+                        }
+                        // Report the actual offset also if setting failed.
+                        match cam.roi_offset() {
+                            Ok(offset) => {
+                                tx_frame2
+                                    .send(Msg::RoiOffsetApplied(offset))
+                                    .await
+                                    .map_err(to_eyre)?;
+                            }
+                            Err(e) => {
+                                error!("getting ROI offset: {e:?}");
+                            }
+                        }
+                    }
                     CamArg::SetFormatStr(v) => {
                         let mut tracker = shared_store_arc.write();
                         tracker.modify(|tracker| tracker.format_str = v);
@@ -2858,6 +3825,122 @@ where
                             });
                         }
                     }
+                    CamArg::SetIsRecordingMp4AndFmf(do_recording) => {
+                        // Copy values from cache and release the lock immediately.
+                        let (is_recording_mp4, is_recording_fmf, format_str, recording_framerate) = {
+                            let tracker = shared_store_arc.read();
+                            let shared: &StoreType = tracker.as_ref();
+                            (
+                                shared.is_recording_mp4.is_some(),
+                                shared.is_recording_fmf.is_some(),
+                                shared.format_str.clone(),
+                                shared.mp4_max_framerate.clone(),
+                            )
+                        };
+
+                        if do_recording {
+                            if is_recording_mp4 || is_recording_fmf {
+                                error!(
+                                    "Not starting synchronized MP4 and FMF recording: \
+                                    a recording is already in progress."
+                                );
+                            } else {
+                                info!("Start synchronized MP4 and FMF recording");
+                                let local: chrono::DateTime<chrono::Local> = chrono::Local::now();
+                                let filename = local.format(format_str.as_str()).to_string();
+
+                                // Send the command.
+                                tx_frame2
+                                    .send(Msg::StartMp4AndFMF((
+                                        filename.clone(),
+                                        recording_framerate,
+                                    )))
+                                    .await
+                                    .map_err(to_eyre)?;
+
+                                // Save the new recording state. (The MP4 state
+                                // is saved by the frame processing task.)
+                                let mut tracker = shared_store_arc.write();
+                                tracker.modify(|shared| {
+                                    shared.is_recording_fmf = Some(RecordingPath::new(filename));
+                                });
+                            }
+                        } else if is_recording_mp4 || is_recording_fmf {
+                            info!("Stopping synchronized MP4 and FMF recording");
+                            tx_frame2.send(Msg::StopMp4AndFMF).await.map_err(to_eyre)?;
+                            let mut tracker = shared_store_arc.write();
+                            tracker.modify(|shared| {
+                                shared.is_recording_fmf = None;
+                            });
+                        }
+                    }
+                    CamArg::StopAllRecordings => {
+                        info!("Stopping all recordings");
+                        if let Some(handle) = scheduled_stop.take() {
+                            handle.abort();
+                        }
+                        tx_frame2
+                            .send(Msg::StopAllRecordings)
+                            .await
+                            .map_err(to_eyre)?;
+                        let mut tracker = shared_store_arc.write();
+                        tracker.modify(|shared| {
+                            shared.is_recording_fmf = None;
+                            shared.is_recording_ufmf = None;
+                            shared.recording_stop_time = None;
+                        });
+                    }
+                    CamArg::StartRecordingFor { .. } | CamArg::StartRecordingUntil { .. } => {
+                        let now = chrono::Utc::now();
+                        let end_time = match cam_args {
+                            CamArg::StartRecordingFor { duration_secs } => {
+                                std::time::Duration::try_from_secs_f64(duration_secs)
+                                    .ok()
+                                    .and_then(|dur| chrono::Duration::from_std(dur).ok())
+                                    .map(|dur| now + dur)
+                            }
+                            CamArg::StartRecordingUntil { end_time } => Some(end_time),
+                            _ => unreachable!(),
+                        };
+                        let end_time = match end_time {
+                            Some(end_time) if end_time > now => end_time,
+                            _ => {
+                                error!("not recording: invalid end time in {:?}", cam_args);
+                                continue;
+                            }
+                        };
+
+                        // Copy values from cache and release the lock immediately.
+                        let is_recording_any = {
+                            let tracker = shared_store_arc.read();
+                            let shared: &StoreType = tracker.as_ref();
+                            shared.is_recording_mp4.is_some()
+                                || shared.is_recording_fmf.is_some()
+                                || shared.is_recording_ufmf.is_some()
+                        };
+
+                        if !is_recording_any {
+                            info!("Start MP4 recording");
+                            tx_frame2.send(Msg::StartMp4).await.map_err(to_eyre)?;
+                        }
+
+                        // Replace any previously scheduled stop.
+                        if let Some(handle) = scheduled_stop.take() {
+                            handle.abort();
+                        }
+                        info!("All recordings will stop at {}", end_time);
+                        let sleep_dur = (end_time - now).to_std().unwrap_or_default();
+                        let cam_args_tx = cam_args_tx.clone();
+                        scheduled_stop = Some(tokio::spawn(async move {
+                            tokio::time::sleep(sleep_dur).await;
+                            if let Err(e) = cam_args_tx.send(CamArg::StopAllRecordings).await {
+                                error!("could not stop scheduled recording: {e}");
+                            }
+                        }));
+
+                        let mut tracker = shared_store_arc.write();
+                        tracker.modify(|shared| shared.recording_stop_time = Some(end_time));
+                    }
                     CamArg::SetIsRecordingUfmf(do_recording) => {
                         #[cfg(feature = "flydra_feat_detect")]
                         {
@@ -2919,6 +4002,89 @@ where
                                 .map_err(to_eyre)?;
                         }
                     }
+                    CamArg::ResetBackgroundModel => {
+                        #[cfg(feature = "flydra_feat_detect")]
+                        {
+                            info!("Resetting background model to current image");
+                            tx_frame2
+                                .send(Msg::ResetBackgroundModel)
+                                .await
+                                .map_err(to_eyre)?;
+                        }
+                    }
+                    CamArg::FreezeBackgroundModel { duration_secs } => {
+                        #[cfg(feature = "flydra_feat_detect")]
+                        {
+                            let now = chrono::Utc::now();
+                            let unfreeze_time = match duration_secs {
+                                Some(secs) => {
+                                    match std::time::Duration::try_from_secs_f64(secs)
+                                        .ok()
+                                        .and_then(|dur| chrono::Duration::from_std(dur).ok())
+                                    {
+                                        Some(dur) => Some(now + dur),
+                                        None => {
+                                            error!(
+                                                "not freezing background model: invalid duration {secs}"
+                                            );
+                                            continue;
+                                        }
+                                    }
+                                }
+                                None => None,
+                            };
+
+                            // Replace any previously scheduled unfreeze.
+                            if let Some(handle) = scheduled_unfreeze.take() {
+                                handle.abort();
+                            }
+                            if let Some(unfreeze_time) = unfreeze_time {
+                                info!("Background model frozen until {}", unfreeze_time);
+                                let sleep_dur = (unfreeze_time - now).to_std().unwrap_or_default();
+                                let cam_args_tx = cam_args_tx.clone();
+                                scheduled_unfreeze = Some(tokio::spawn(async move {
+                                    tokio::time::sleep(sleep_dur).await;
+                                    if let Err(e) =
+                                        cam_args_tx.send(CamArg::UnfreezeBackgroundModel).await
+                                    {
+                                        error!("could not unfreeze background model: {e}");
+                                    }
+                                }));
+                            } else {
+                                info!("Background model frozen");
+                            }
+
+                            tx_frame2
+                                .send(Msg::SetBackgroundFrozen(true))
+                                .await
+                                .map_err(to_eyre)?;
+                            let mut tracker = shared_store_arc.write();
+                            tracker.modify(|shared| {
+                                shared.is_background_frozen = true;
+                                shared.background_unfreeze_time = unfreeze_time;
+                            });
+                        }
+                        #[cfg(not(feature = "flydra_feat_detect"))]
+                        let _ = duration_secs;
+                    }
+                    CamArg::UnfreezeBackgroundModel => {
+                        #[cfg(feature = "flydra_feat_detect")]
+                        {
+                            if let Some(handle) = scheduled_unfreeze.take() {
+                                handle.abort();
+                            }
+                            info!("Background model updates resumed");
+                            tx_frame2
+                                .send(Msg::SetBackgroundFrozen(false))
+                                .await
+                                .map_err(to_eyre)?;
+                            let mut tracker = shared_store_arc.write();
+                            tracker.modify(|shared| {
+                                shared.is_background_frozen = false;
+                                shared.background_unfreeze_time = None;
+                            });
+                        }
+                    }
                     CamArg::DoQuit => {
                         break;
                     }
@@ -2935,7 +4101,10 @@ where
                         #[cfg(feature = "flydra_feat_detect")]
                         match serde_yaml::from_str::<ImPtDetectCfg>(&yaml_buf) {
                             Err(e) => {
-                                error!("ignoring ImPtDetectCfg with parse error: {:?}", e)
+                                profiles::command_failed(
+                                    &mut applying_profile,
+                                    format!("ignoring ImPtDetectCfg with parse error: {e:?}"),
+                                );
                             }
                             Ok(cfg) => {
                                 let cfg2 = cfg.clone();
@@ -3202,46 +4371,16 @@ where
                                 &goodcorners,
                             ) {
                                 Ok(raw_opencv_cal) => {
-                                    let cal_dir = directories::BaseDirs::new()
-                                        .as_ref()
-                                        .map(|bd| {
-                                            bd.config_dir().join(APP_INFO.name).join("camera_info")
-                                        })
-                                        .unwrap();
-
-                                    if !cal_dir.exists() {
-                                        std::fs::create_dir_all(&cal_dir)?;
-                                    }
-
-                                    info!(
-                                        "Using calibration directory at \"{}\"",
-                                        cal_dir.display()
-                                    );
-
-                                    let format_str =
-                                        format!("{}.%Y%m%d_%H%M%S.yaml", raw_cam_name.as_str());
-                                    let stamped = local.format(&format_str).to_string();
-                                    let cam_info_file_stamped = cal_dir.join(stamped);
-
-                                    let mut cam_info_file = cal_dir.clone();
-                                    cam_info_file.push(raw_cam_name.as_str());
-                                    cam_info_file.set_extension("yaml");
-
-                                    // Save timestamped version first for backup purposes (since below
-                                    // we overwrite the non-timestamped file).
-                                    camcal::save_yaml(
-                                        &cam_info_file_stamped,
-                                        env!["CARGO_PKG_NAME"],
-                                        local,
-                                        &raw_opencv_cal,
-                                        raw_cam_name.as_str(),
-                                    )?;
-
-                                    // Now copy the successfully saved file into
-                                    // the non-timestamped name. This will
-                                    // overwrite an existing file.
-                                    std::fs::copy(&cam_info_file_stamped, &cam_info_file)
-                                        .expect("copy file");
+                                    let cam_info_file =
+                                        save_camera_info(&raw_cam_name, local, |path| {
+                                            camcal::save_yaml(
+                                                path,
+                                                env!["CARGO_PKG_NAME"],
+                                                local,
+                                                &raw_opencv_cal,
+                                                raw_cam_name.as_str(),
+                                            )
+                                        })?;
 
                                     info!(
                                         "Saved camera calibration to file: {}",
@@ -3254,22 +4393,56 @@ where
                             };
                         }
                     }
+                    CamArg::ExportCameraCalibration { path, format } => {
+                        #[cfg(feature = "checkercal")]
+                        {
+                            let path = data_dir.join(path);
+                            match export_camera_info(&raw_cam_name, &path, format) {
+                                Ok(()) => {
+                                    info!("Exported camera calibration to \"{}\"", path.display());
+                                }
+                                Err(e) => {
+                                    error!(
+                                        "could not export camera calibration to \"{}\": {e}",
+                                        path.display()
+                                    );
+                                }
+                            }
+                        }
+                    }
+                    CamArg::ImportCameraCalibration { path } => {
+                        #[cfg(feature = "checkercal")]
+                        {
+                            let path = data_dir.join(path);
+                            let result =
+                                camcal::load_intrinsics(&path).and_then(|named_intrinsics| {
+                                    save_camera_info(&raw_cam_name, chrono::Local::now(), |dest| {
+                                        camcal::save_ros_yaml(dest, &named_intrinsics)
+                                    })
+                                });
+                            match result {
+                                Ok(cam_info_file) => {
+                                    info!(
+                                        "Imported camera calibration from \"{}\" to \"{}\"",
+                                        path.display(),
+                                        cam_info_file.display(),
+                                    );
+                                }
+                                Err(e) => {
+                                    error!(
+                                        "could not import camera calibration from \"{}\": {e}",
+                                        path.display()
+                                    );
+                                }
+                            }
+                        }
+                    }
                 }
             }
 
-            // We get here iff DoQuit broke us out of infinite loop.
-
-            // In theory, all things currently being saved should nicely stop themselves when dropped.
-            // For now, while we are working on ctrlc handling, we manually stop them.
-            tx_frame2.send(Msg::StopFMF).await.map_err(to_eyre)?;
-            tx_frame2.send(Msg::StopMp4).await.map_err(to_eyre)?;
-            #[cfg(feature = "flydra_feat_detect")]
-            tx_frame2.send(Msg::StopUFMF).await.map_err(to_eyre)?;
-            #[cfg(feature = "flydra_feat_detect")]
-            tx_frame2
-                .send(Msg::SetIsSavingObjDetectionCsv(CsvSaveConfig::NotSaving))
-                .await
-                .map_err(to_eyre)?;
+            // We get here iff DoQuit broke us out of infinite loop. Shut down
+            // in order (see the `shutdown` module): stop acquisition, process
+            // the frames already acquired and then finalize all files.
 
             info!("attempting to nicely stop camera");
             if let Some((control, join_handle)) = cam.control_and_join_handle() {
@@ -3284,6 +4457,26 @@ where
                 error!("camera thread not running!?");
             }
 
+            // The camera stream ends once the camera thread stopped. Frames
+            // are processed in order, so all of them are processed before
+            // the shutdown message below.
+            match tokio::time::timeout(shutdown::SHUTDOWN_TIMEOUT, cam_stream_jh).await {
+                Ok(Ok(Ok(()))) => {}
+                Ok(Ok(Err(e))) => error!("camera stream ended with error: {e:?}"),
+                Ok(Err(e)) => error!("camera stream task failed: {e}"),
+                Err(_) => warn!("camera stream did not end, the last frames may not be saved"),
+            }
+
+            let (shutdown_tx, shutdown_rx) = tokio::sync::mpsc::unbounded_channel();
+            tx_frame2
+                .send(Msg::Shutdown(shutdown_tx))
+                .await
+                .map_err(to_eyre)?;
+            if !shutdown::wait_for_sinks(shutdown_rx, shutdown::SHUTDOWN_TIMEOUT).await {
+                error!("forcing exit");
+                std::process::exit(1);
+            }
+
             info!("cam_args_rx future is resolved");
             Ok::<_, eyre::Report>(())
         }
@@ -3310,9 +4503,14 @@ where
     let firehose_task_join_handle = tokio::spawn(async {
         // The first thing this task does is pop a frame from firehose_rx, so we
         // should ensure there is one present.
-        video_streaming::firehose_task(connection_callback_rx, firehose_rx, firehose_callback_rx)
-            .await
-            .unwrap();
+        video_streaming::firehose_task(
+            connection_callback_rx,
+            firehose_rx,
+            firehose_callback_rx,
+            Some(mjpeg_encoder),
+        )
+        .await
+        .unwrap();
     });
 
     #[cfg(feature = "plugin-process-frame")]
@@ -3506,12 +4704,27 @@ where
             tokio::spawn(heartbeat_task); // todo: keep join handle
         }
     }
-    // _dummy_tx is not dropped until after `select!` below. It will never send.
-    let (_dummy_tx, dummy_rx) = tokio::sync::mpsc::channel(1);
-    let mut quit_rx = match quit_rx {
-        None => dummy_rx,
-        Some(fut) => fut,
-    };
+    // Closing the GUI, Ctrl-C and SIGTERM quit like a DoQuit command such that
+    // all files are finalized. A second Ctrl-C or SIGTERM exits immediately.
+    if let Some(mut quit_rx) = quit_rx {
+        let cam_args_tx = cam_args_tx.clone();
+        tokio::spawn(async move {
+            if quit_rx.recv().await.is_some() {
+                let _ = cam_args_tx.send(CamArg::DoQuit).await;
+            }
+        });
+    }
+    {
+        let cam_args_tx = cam_args_tx.clone();
+        tokio::spawn(async move {
+            shutdown::quit_signal().await;
+            info!("received signal to quit, finalizing files");
+            let _ = cam_args_tx.send(CamArg::DoQuit).await;
+            shutdown::quit_signal().await;
+            error!("received signal to quit again, exiting immediately");
+            std::process::exit(1);
+        });
+    }
 
     // Now run until first future returns, then exit.
     info!("Strand Cam launched.");
@@ -3523,7 +4736,6 @@ where
         _ = send_updates_future => {},
         res = frame_process_task_fut => {res?},
         res = firehose_task_join_handle => {res?},
-        _ = quit_rx.recv() => {},
     }
     info!("Strand Cam ending nicely. :)");
 
@@ -3643,6 +4855,11 @@ async fn send_cam_settings_to_braid(
     transmit_msg_tx.send(msg).await
 }
 
+/// The provenance of this program, saved in its recordings.
+pub(crate) fn provenance() -> provenance::Provenance {
+    provenance::Provenance::current("strand-cam", env!("CARGO_PKG_VERSION"), env!("GIT_HASH"))
+}
+
 fn bitrate_to_u32(br: &ci2_remote_control::BitrateSelection) -> u32 {
     use ci2_remote_control::BitrateSelection::*;
     match br {
@@ -3657,6 +4874,78 @@ fn bitrate_to_u32(br: &ci2_remote_control::BitrateSelection) -> u32 {
     }
 }
 
+/// The directory in which camera calibrations are saved.
+#[cfg(feature = "checkercal")]
+fn camera_info_dir() -> Result<PathBuf> {
+    let cal_dir = directories::BaseDirs::new()
+        .as_ref()
+        .map(|bd| bd.config_dir().join(APP_INFO.name).join("camera_info"))
+        .ok_or_else(|| eyre!("could not determine configuration directory"))?;
+
+    if !cal_dir.exists() {
+        std::fs::create_dir_all(&cal_dir)?;
+    }
+
+    info!("Using calibration directory at \"{}\"", cal_dir.display());
+    Ok(cal_dir)
+}
+
+/// The file in `cal_dir` with the current calibration of a camera.
+#[cfg(feature = "checkercal")]
+fn camera_info_file(cal_dir: &std::path::Path, raw_cam_name: &RawCamName) -> PathBuf {
+    let mut cam_info_file = cal_dir.join(raw_cam_name.as_str());
+    cam_info_file.set_extension("yaml");
+    cam_info_file
+}
+
+/// Save a new calibration of a camera with `save` and make it current.
+///
+/// Returns the path of the current calibration.
+#[cfg(feature = "checkercal")]
+fn save_camera_info<F>(
+    raw_cam_name: &RawCamName,
+    local: chrono::DateTime<chrono::Local>,
+    save: F,
+) -> Result<PathBuf>
+where
+    F: FnOnce(&std::path::Path) -> Result<()>,
+{
+    let format_str = format!("{}.%Y%m%d_%H%M%S.yaml", raw_cam_name.as_str());
+    let stamped = local.format(&format_str).to_string();
+    let cal_dir = camera_info_dir()?;
+    let cam_info_file_stamped = cal_dir.join(stamped);
+    let cam_info_file = camera_info_file(&cal_dir, raw_cam_name);
+
+    // Save timestamped version first for backup purposes (since below
+    // we overwrite the non-timestamped file).
+    save(&cam_info_file_stamped)?;
+
+    // Now copy the successfully saved file into the non-timestamped name. This
+    // will overwrite an existing file.
+    std::fs::copy(&cam_info_file_stamped, &cam_info_file)?;
+
+    Ok(cam_info_file)
+}
+
+/// Save the current calibration of a camera to `path` in `format`.
+#[cfg(feature = "checkercal")]
+fn export_camera_info(
+    raw_cam_name: &RawCamName,
+    path: &std::path::Path,
+    format: ci2_remote_control::CalibrationFileFormat,
+) -> Result<()> {
+    use ci2_remote_control::CalibrationFileFormat;
+
+    let cam_info_file = camera_info_file(&camera_info_dir()?, raw_cam_name);
+    let named_intrinsics = camcal::load_intrinsics(&cam_info_file)
+        .with_context(|| format!("loading \"{}\"", cam_info_file.display()))?;
+    match format {
+        CalibrationFileFormat::RosYaml => camcal::save_ros_yaml(path, &named_intrinsics),
+        CalibrationFileFormat::OpenCvYaml => camcal::save_opencv_yaml(path, &named_intrinsics),
+        CalibrationFileFormat::OpenCvXml => camcal::save_opencv_xml(path, &named_intrinsics),
+    }
+}
+
 struct FinalMp4RecordingConfig {
     final_cfg: ci2_remote_control::RecordingConfig,
 }
@@ -3674,6 +4963,7 @@ impl FinalMp4RecordingConfig {
                 Some(Mp4Codec::H264NvEnc(NvidiaH264Options {
                     bitrate: bitrate_to_u32(&shared.mp4_bitrate),
                     cuda_device,
+                    ..Default::default()
                 }))
             }
             CodecSelection::H264OpenH264 => {
@@ -3695,10 +4985,18 @@ impl FinalMp4RecordingConfig {
                 ci2_remote_control::H264Metadata::new("strand-cam", creation_time.into());
             h264_metadata.camera_name = Some(shared.camera_name.clone());
             h264_metadata.gamma = shared.camera_gamma;
+            if !shared.recording_label.label.is_empty() {
+                h264_metadata.recording_label = Some(shared.recording_label.label.clone());
+            }
+            h264_metadata.recording_tags = shared.recording_label.tags.clone();
+            h264_metadata.provenance = Some(provenance());
+            h264_metadata.device_clock_model = shared.device_clock_model.clone();
             let final_cfg = Mp4RecordingConfig {
                 codec,
                 max_framerate: shared.mp4_max_framerate.clone(),
                 h264_metadata: Some(h264_metadata),
+                container_metadata: None,
+                fragment_duration_secs: None,
             };
             ci2_remote_control::RecordingConfig::Mp4(final_cfg)
         } else {
@@ -25,7 +25,7 @@ use ads_webasm::components::{EnumToggle, VecToggle};
 
 use http_video_streaming_types::ToClient as FirehoseImageData;
 
-use ci2_remote_control::{BitrateSelection, CodecSelection};
+use ci2_remote_control::{BitrateSelection, CodecSelection, RecordingLabel};
 use strand_cam_storetype::{
     CallbackType, KalmanTrackingConfig, LedProgramConfig, StoreType as ServerState,
 };
@@ -34,6 +34,7 @@ use yew_tincture::components::CheckboxLabel;
 
 use ci2_remote_control::{RecordingFrameRate, TagFamily};
 use ci2_types::AutoMode;
+use rust_cam_bui_types::{DiskSpaceLevel, ResourceLevel, SelfTestReport};
 
 use flydra_feature_detector_types::ImPtDetectCfg;
 use yew_tincture::components::{TypedInput, TypedInputStorage};
@@ -63,6 +64,10 @@ enum Msg {
 
     DismissProcessingErrorModal,
     SetIgnoreAllFutureErrors(bool),
+    DismissDiskSpaceError,
+    DismissSelfTestFailure,
+    SaveBugReport,
+    ApplyProfile(String),
 
     SetGainAuto(AutoMode),
     SetGainValue(f64),
@@ -102,7 +107,10 @@ enum Msg {
     ToggleUfmfSave(bool),
 
     ToggleMp4Save(bool),
+    StartMp4AndFmfSave,
+    StopAllRecordings,
     ToggleMp4RecordingFrameRate(RecordingFrameRate),
+    ToggleMp4Proxy(bool),
     ToggleMp4Bitrate(BitrateSelection),
     ToggleMp4Codec(String),
     ToggleCudaDevice(String),
@@ -111,6 +119,12 @@ enum Msg {
     TakeCurrentImageAsBackground,
     // only used when image-tracker crate used
     ClearBackground(f32),
+    // only used when image-tracker crate used
+    ResetBackgroundModel,
+    // only used when image-tracker crate used
+    FreezeBackgroundModel(Option<f64>),
+    // only used when image-tracker crate used
+    UnfreezeBackgroundModel,
 
     LedBoxControlEvent(ToLedBoxDevice),
 
@@ -124,6 +138,10 @@ enum Msg {
     SetPostTriggerBufferSize(usize),
     PostTriggerMp4Recording,
 
+    SetRecordingLabel(String),
+    /// Comma-separated tags
+    SetRecordingTags(String),
+
     SendMessageFetchState(FetchState),
     RenderView,
     SetVideoFieldFullWindow(bool),
@@ -172,9 +190,14 @@ struct Model {
     _listeners: Vec<EventListener>,
 
     csv_recording_rate: RecordingFrameRate,
+    csv_recording_rate_local: TypedInputStorage<RecordingFrameRate>,
+    mp4_max_framerate_local: TypedInputStorage<RecordingFrameRate>,
+    fmf_recording_framerate_local: TypedInputStorage<RecordingFrameRate>,
     checkerboard_width: TypedInputStorage<u32>,
     checkerboard_height: TypedInputStorage<u32>,
     post_trigger_buffer_size_local: TypedInputStorage<usize>,
+    recording_label_local: TypedInputStorage<String>,
+    recording_tags_local: TypedInputStorage<String>,
 
     im_ops_destination_local: TypedInputStorage<SocketAddr>,
     im_ops_source_local: TypedInputStorage<IpAddr>,
@@ -272,9 +295,14 @@ impl Component for Model {
             es,
             _listeners,
             csv_recording_rate: RecordingFrameRate::Unlimited,
+            csv_recording_rate_local: TypedInputStorage::empty(),
+            mp4_max_framerate_local: TypedInputStorage::empty(),
+            fmf_recording_framerate_local: TypedInputStorage::empty(),
             checkerboard_width: TypedInputStorage::empty(),
             checkerboard_height: TypedInputStorage::empty(),
             post_trigger_buffer_size_local: TypedInputStorage::empty(),
+            recording_label_local: TypedInputStorage::empty(),
+            recording_tags_local: TypedInputStorage::empty(),
 
             im_ops_destination_local: TypedInputStorage::empty(),
             im_ops_source_local: TypedInputStorage::empty(),
@@ -327,6 +355,11 @@ impl Component for Model {
                 self.post_trigger_buffer_size_local
                     .set_if_not_focused(response.post_trigger_buffer_size);
 
+                self.recording_label_local
+                    .set_if_not_focused(response.recording_label.label.clone());
+                self.recording_tags_local
+                    .set_if_not_focused(response.recording_label.tags.join(", "));
+
                 self.im_ops_destination_local
                     .set_if_not_focused(response.im_ops_state.destination);
 
@@ -342,6 +375,12 @@ impl Component for Model {
                 self.im_ops_threshold
                     .set_if_not_focused(response.im_ops_state.threshold);
 
+                self.mp4_max_framerate_local
+                    .set_if_not_focused(response.mp4_max_framerate.clone());
+
+                self.fmf_recording_framerate_local
+                    .set_if_not_focused(response.mp4_max_framerate.clone());
+
                 // Update our cache of the server state
                 self.server_state = Some(response);
             }
@@ -366,6 +405,22 @@ impl Component for Model {
             Msg::SetIgnoreAllFutureErrors(val) => {
                 self.ignore_all_future_frame_processing_errors = val;
             }
+            Msg::DismissDiskSpaceError => {
+                self.send_cam_message(CamArg::DismissDiskSpaceError, ctx);
+                return false; // don't update DOM, do that on return
+            }
+            Msg::DismissSelfTestFailure => {
+                self.send_cam_message(CamArg::DismissSelfTestFailure, ctx);
+                return false; // don't update DOM, do that on return
+            }
+            Msg::SaveBugReport => {
+                self.send_cam_message(CamArg::SaveBugReport, ctx);
+                return false; // don't update DOM, do that on return
+            }
+            Msg::ApplyProfile(name) => {
+                self.send_cam_message(CamArg::ApplyProfile(name), ctx);
+                return false; // don't update DOM, do that on return
+            }
             Msg::SetGainAuto(v) => {
                 self.send_cam_message(CamArg::SetGainAuto(v), ctx);
                 return false; // don't update DOM, do that on return
@@ -420,6 +475,7 @@ impl Component for Model {
             }
             // only used when image-tracker crate used
             Msg::ToggleCsvRecordingRate(v) => {
+                self.csv_recording_rate_local.set_if_not_focused(v.clone());
                 self.csv_recording_rate = v;
                 return false; // don't update DOM, do that on return
             }
@@ -482,6 +538,10 @@ impl Component for Model {
                 }
                 return false; // don't update DOM, do that on return
             }
+            Msg::ToggleMp4Proxy(v) => {
+                self.send_cam_message(CamArg::SetMp4ProxyEnabled(v), ctx);
+                return false; // don't update DOM, do that on return
+            }
             Msg::ToggleCudaDevice(v) => {
                 self.send_cam_message(CamArg::SetMp4CudaDevice(v), ctx);
                 return false; // don't update DOM, do that on return
@@ -499,6 +559,14 @@ impl Component for Model {
                 self.send_cam_message(CamArg::SetIsRecordingMp4(v), ctx);
                 return false; // don't update DOM, do that on return
             }
+            Msg::StartMp4AndFmfSave => {
+                self.send_cam_message(CamArg::SetIsRecordingMp4AndFmf(true), ctx);
+                return false; // don't update DOM, do that on return
+            }
+            Msg::StopAllRecordings => {
+                self.send_cam_message(CamArg::StopAllRecordings, ctx);
+                return false; // don't update DOM, do that on return
+            }
             // only used when image-tracker crate used
             Msg::TakeCurrentImageAsBackground => {
                 self.send_message(CallbackType::TakeCurrentImageAsBackground, ctx);
@@ -509,6 +577,21 @@ impl Component for Model {
                 self.send_message(CallbackType::ClearBackground(value), ctx);
                 return false; // don't update DOM, do that on return
             }
+            // only used when image-tracker crate used
+            Msg::ResetBackgroundModel => {
+                self.send_cam_message(CamArg::ResetBackgroundModel, ctx);
+                return false; // don't update DOM, do that on return
+            }
+            // only used when image-tracker crate used
+            Msg::FreezeBackgroundModel(duration_secs) => {
+                self.send_cam_message(CamArg::FreezeBackgroundModel { duration_secs }, ctx);
+                return false; // don't update DOM, do that on return
+            }
+            // only used when image-tracker crate used
+            Msg::UnfreezeBackgroundModel => {
+                self.send_cam_message(CamArg::UnfreezeBackgroundModel, ctx);
+                return false; // don't update DOM, do that on return
+            }
             Msg::LedBoxControlEvent(command) => {
                 self.send_message(CallbackType::ToLedBox(command), ctx);
                 return false; // don't update DOM, do that on return
@@ -547,6 +630,33 @@ impl Component for Model {
                 self.send_cam_message(CamArg::PostTrigger, ctx);
                 return false; // don't update DOM, do that on return
             }
+
+            Msg::SetRecordingLabel(label) => {
+                if let Some(ref shared) = self.server_state {
+                    let v = RecordingLabel {
+                        label: label.trim().to_string(),
+                        tags: shared.recording_label.tags.clone(),
+                    };
+                    self.send_cam_message(CamArg::SetRecordingLabel(v), ctx);
+                }
+                return false;
+            }
+
+            Msg::SetRecordingTags(tags) => {
+                if let Some(ref shared) = self.server_state {
+                    let v = RecordingLabel {
+                        label: shared.recording_label.label.clone(),
+                        tags: tags
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|t| !t.is_empty())
+                            .map(String::from)
+                            .collect(),
+                    };
+                    self.send_cam_message(CamArg::SetRecordingLabel(v), ctx);
+                }
+                return false;
+            }
         }
         true
     }
@@ -563,6 +673,9 @@ impl Component for Model {
                 <img src="strand-camera-no-text.png" width="521" height="118" class="center logo-img" alt="Strand Camera logo"/>
                 { self.disconnected_dialog() }
                 { self.frame_processing_error_dialog(ctx) }
+                { self.disk_space_error_dialog(ctx) }
+                { self.view_resource_warning() }
+                { self.self_test_failed_dialog(ctx) }
                 { self.led_box_failed() }
                 <div class="wrapper">
                     { self.view_video(ctx) }
@@ -585,10 +698,14 @@ impl Component for Model {
                             { self.view_gain(ctx) }
                             { self.view_exposure(ctx) }
                             { self.view_frame_rate_limit(ctx) }
+                            { self.view_frame_buffers() }
+                            { self.view_output_latency() }
                         </div>
                     </div>
+                    { self.view_profiles(ctx) }
                     { self.view_fmf_recording_options(ctx) }
                     { self.view_kalman_tracking(ctx) }
+                    { self.view_bug_report(ctx) }
                 </div>
                 <footer id="footer">
                 {format!(
@@ -720,6 +837,180 @@ impl Model {
         }
     }
 
+    fn disk_space_error_dialog(&self, ctx: &Context<Self>) -> Html {
+        if let Some(ref shared) = self.server_state {
+            if let Some(ref msg) = shared.disk_space_error {
+                return html! {
+                    <div class="modal-container">
+                        <h1> { "Error: disk almost full" } </h1>
+                        <p>{msg}</p>
+                        <p>{"Free disk space before recording again."}</p>
+                        <p><Button title={"Dismiss"} onsignal={ctx.link().callback(|_| Msg::DismissDiskSpaceError)} /></p>
+                    </div>
+                };
+            }
+        }
+        html! {
+            <div>
+            </div>
+        }
+    }
+
+    fn view_resource_warning(&self) -> Html {
+        if let Some(ref shared) = self.server_state {
+            if let Some(ref usage) = shared.resource_usage {
+                let warning = match usage.level {
+                    ResourceLevel::Ok => None,
+                    ResourceLevel::High => Some("Warning: high resource usage."),
+                    ResourceLevel::Critical => Some(
+                        "Resource usage near limit: idle connections are closed \
+                        and new MP4 segments are postponed.",
+                    ),
+                };
+                if let Some(warning) = warning {
+                    return html! {
+                        <div class="resource-warning">
+                            {format!("{warning} {}.", usage.summary())}
+                        </div>
+                    };
+                }
+            }
+        }
+        html! {
+            <div></div>
+        }
+    }
+
+    fn self_test_failed_dialog(&self, ctx: &Context<Self>) -> Html {
+        if let Some(ref shared) = self.server_state {
+            if let Some(ref report) = shared.self_test {
+                if report.passed() == Some(false) && !report.failure_dismissed {
+                    return html! {
+                        <div class="modal-container">
+                            <h1> { "Error: self-test failed" } </h1>
+                            { view_self_test_checks(report) }
+                            <p>{"Recording is disabled. Fix the problem and restart, or dismiss to record anyway."}</p>
+                            <p><Button title={"Dismiss"} onsignal={ctx.link().callback(|_| Msg::DismissSelfTestFailure)} /></p>
+                        </div>
+                    };
+                }
+            }
+        }
+        html! {
+            <div>
+            </div>
+        }
+    }
+
+    fn view_self_test(&self) -> Html {
+        if let Some(ref shared) = self.server_state {
+            if let Some(ref report) = shared.self_test {
+                return html! {
+                    <div>
+                        {report.summary()}
+                        { view_self_test_checks(report) }
+                    </div>
+                };
+            }
+        }
+        html! {
+            <div></div>
+        }
+    }
+
+    fn view_profiles(&self, ctx: &Context<Self>) -> Html {
+        let Some(shared) = self.server_state.as_ref() else {
+            return html! {
+                <div></div>
+            };
+        };
+        if shared.profiles.is_empty() {
+            return html! {
+                <div></div>
+            };
+        }
+        let active = match &shared.active_profile {
+            Some(name) => format!("Active profile: {name}."),
+            None => "No profile applied.".to_string(),
+        };
+        let error = match &shared.profile_error {
+            Some(msg) => html! { <p>{msg}</p> },
+            None => html! {},
+        };
+        html! {
+            <div class="wrap-collapsible">
+                <CheckboxLabel label="Configuration Profiles" initially_checked=false />
+                <div>
+                    <p>{"Apply the camera, detection and recording settings of a profile."}</p>
+                    <p>{active}</p>
+                    {error}
+                </div>
+                <div>
+                    {for shared.profiles.iter().map(|name| {
+                        let name = name.clone();
+                        html! {
+                            <Button
+                                title={name.clone()}
+                                onsignal={ctx.link().callback(move |_| Msg::ApplyProfile(name.clone()))}
+                            />
+                        }
+                    })}
+                </div>
+            </div>
+        }
+    }
+
+    fn view_bug_report(&self, ctx: &Context<Self>) -> Html {
+        let download = if let Some(filename) = self
+            .server_state
+            .as_ref()
+            .and_then(|shared| shared.bug_report_filename.as_ref())
+        {
+            html! {
+                <div>
+                    <a href="bug-report" download={filename.clone()}>{format!("Download {filename}")}</a>
+                </div>
+            }
+        } else {
+            html! {
+                <div></div>
+            }
+        };
+        html! {
+            <div class="wrap-collapsible">
+                <CheckboxLabel label="Bug Report" initially_checked=false />
+                <div>
+                    <p>{"Save the processing state of recent frames and the current settings \
+                        to a zip file. Please attach this file when reporting a problem."}</p>
+                </div>
+                <div>
+                    <Button title={"Save bug report"} onsignal={ctx.link().callback(|_| Msg::SaveBugReport)}/>
+                    {download}
+                </div>
+            </div>
+        }
+    }
+
+    fn view_disk_space(&self) -> Html {
+        if let Some(ref shared) = self.server_state {
+            if let Some(ref status) = shared.disk_space {
+                let warning = match status.level {
+                    DiskSpaceLevel::Ok => "",
+                    DiskSpaceLevel::Low => " Warning: disk space is low.",
+                    DiskSpaceLevel::Critical => " Recording will be stopped: disk almost full.",
+                };
+                return html! {
+                    <div>
+                        {format!("{}.{}", status.summary(), warning)}
+                    </div>
+                };
+            }
+        }
+        html! {
+            <div></div>
+        }
+    }
+
     fn led_box_failed(&self) -> Html {
         let led_box_device_lost = if let Some(ref shared) = self.server_state {
             shared.led_box_device_lost
@@ -801,6 +1092,11 @@ impl Model {
                 }
             };
 
+            let recording_stop_time = match &shared.recording_stop_time {
+                Some(t) => format!("(All recordings will stop at {}.)", t),
+                None => "".to_string(),
+            };
+
             html! {
                 <div class="wrap-collapsible">
                     <CheckboxLabel label="MP4 Recording Options" initially_checked=true />
@@ -816,12 +1112,48 @@ impl Model {
                                 ontoggle={ctx.link().callback(|checked| {Msg::ToggleMp4Save(checked)})}
                                 />
                         </div>
+                        <div>
+                            <Button title={"Record MP4 + FMF"} onsignal={ctx.link().callback(|_| Msg::StartMp4AndFmfSave)}/>
+                            {"(Records identical frames to MP4 and FMF files.)"}
+                            <Button title={"Stop all recordings"} onsignal={ctx.link().callback(|_| Msg::StopAllRecordings)}/>
+                            {recording_stop_time}
+                        </div>
+                        <div>
+                            <label>{"Recording label "}
+                                <TypedInput<String>
+                                    storage={self.recording_label_local.clone()}
+                                    on_send_valid={ctx.link().callback(Msg::SetRecordingLabel)}
+                                    />
+                            </label>
+                            <label>{"Tags (comma separated) "}
+                                <TypedInput<String>
+                                    storage={self.recording_tags_local.clone()}
+                                    on_send_valid={ctx.link().callback(Msg::SetRecordingTags)}
+                                    />
+                            </label>
+                            {"(Saved with recordings started afterwards.)"}
+                        </div>
+                        { self.view_disk_space() }
+                        { self.view_self_test() }
                         <div>
                             <h5>{"MP4 Max Framerate"}</h5>
                             <EnumToggle<RecordingFrameRate>
                                 value={shared.mp4_max_framerate.clone()}
                                 onsignal={ctx.link().callback(Msg::ToggleMp4RecordingFrameRate)}
                             />
+                            <label>{"Other (e.g. 12.5 or 25/2)"}
+                                <TypedInput<RecordingFrameRate>
+                                    storage={self.mp4_max_framerate_local.clone()}
+                                    on_send_valid={ctx.link().callback(Msg::ToggleMp4RecordingFrameRate)}
+                                    />
+                            </label>
+                        </div>
+                        <div>
+                            <h5>{"Half Resolution Proxy MP4"}</h5>
+                            <EnumToggle<bool>
+                                value={shared.mp4_proxy_enabled}
+                                onsignal={ctx.link().callback(Msg::ToggleMp4Proxy)}
+                            />
                         </div>
 
                         <div>
@@ -908,6 +1240,12 @@ impl Model {
                                 value={shared.mp4_max_framerate.clone()}
                                 onsignal={ctx.link().callback(Msg::ToggleFmfRecordingFrameRate)}
                             />
+                            <label>{"Other (e.g. 12.5 or 25/2)"}
+                                <TypedInput<RecordingFrameRate>
+                                    storage={self.fmf_recording_framerate_local.clone()}
+                                    on_send_valid={ctx.link().callback(Msg::ToggleFmfRecordingFrameRate)}
+                                    />
+                            </label>
                         </div>
                     </div>
                 </div>
@@ -1048,6 +1386,14 @@ impl Model {
         if let Some(ref shared) = self.server_state {
             if shared.has_image_tracker_compiled {
                 let cfg_clone = shared.im_pt_detect_cfg.clone();
+                let background_freeze_state = match (
+                    shared.is_background_frozen,
+                    &shared.background_unfreeze_time,
+                ) {
+                    (true, Some(t)) => format!("(Background frozen until {}.)", t),
+                    (true, None) => "(Background frozen.)".to_string(),
+                    (false, _) => "".to_string(),
+                };
                 return html! {
                     <div class="wrap-collapsible">
                         <CheckboxLabel label="Object Detection" initially_checked=true />
@@ -1075,6 +1421,12 @@ impl Model {
                                     value={self.csv_recording_rate.clone()}
                                     onsignal={ctx.link().callback(Msg::ToggleCsvRecordingRate)}
                                 />
+                                <label>{"Other (e.g. 12.5 or 25/2)"}
+                                    <TypedInput<RecordingFrameRate>
+                                        storage={self.csv_recording_rate_local.clone()}
+                                        on_send_valid={ctx.link().callback(Msg::ToggleCsvRecordingRate)}
+                                        />
+                                </label>
                             </div>
 
                             <div>
@@ -1099,6 +1451,13 @@ impl Model {
                                 <div class="reset-background-btn">
                                     <Button title={"Take Current Image As Background"} onsignal={ctx.link().callback(|_| Msg::TakeCurrentImageAsBackground)}/>
                                     <Button title={"Set background to mid-gray"} onsignal={ctx.link().callback(|_| Msg::ClearBackground(127.0))}/>
+                                    <Button title={"Reset background to current image"} onsignal={ctx.link().callback(|_| Msg::ResetBackgroundModel)}/>
+                                </div>
+                                <div class="reset-background-btn">
+                                    <Button title={"Freeze background"} onsignal={ctx.link().callback(|_| Msg::FreezeBackgroundModel(None))}/>
+                                    <Button title={"Freeze background for 30 seconds"} onsignal={ctx.link().callback(|_| Msg::FreezeBackgroundModel(Some(30.0)))}/>
+                                    <Button title={"Unfreeze background"} onsignal={ctx.link().callback(|_| Msg::UnfreezeBackgroundModel)}/>
+                                    {background_freeze_state}
                                 </div>
                             </div>
                         </div>
@@ -1342,23 +1701,55 @@ impl Model {
             }
         }
     }
+
+    fn view_frame_buffers(&self) -> Html {
+        if let Some(ref shared) = self.server_state {
+            if let Some(ref sat) = shared.frame_buffer_saturation {
+                let driver_buffers = match &shared.frame_buffer_config {
+                    Some(cfg) => format!("{}", cfg.n_buffer_frames),
+                    None => "default".to_string(),
+                };
+                return html! {
+                    <div>
+                        <h3>{ "Frame Buffers" }</h3>
+                        <p>
+                            {format!(
+                                "Driver buffers: {}. Queued frames: {} of {} (maximum {}). \
+                                Frames arriving to full buffer: {}.",
+                                driver_buffers, sat.n_queued, sat.capacity, sat.max_queued, sat.n_full,
+                            )}
+                        </p>
+                    </div>
+                };
+            }
+        }
+        html! {
+            <div></div>
+        }
+    }
+
+    fn view_output_latency(&self) -> Html {
+        if let Some(ref shared) = self.server_state {
+            let summary = shared.output_latency.summary();
+            if !summary.is_empty() {
+                return html! {
+                    <div>
+                        <h3>{ "Output Latency" }</h3>
+                        <p>
+                            {format!("Maximum time from exposure until sent: {summary}.")}
+                        </p>
+                    </div>
+                };
+            }
+        }
+        html! {
+            <div></div>
+        }
+    }
 }
 
 fn to_rate(rate_enum: &RecordingFrameRate) -> Option<f32> {
-    match rate_enum {
-        RecordingFrameRate::Fps1 => Some(1.0),
-        RecordingFrameRate::Fps2 => Some(2.0),
-        RecordingFrameRate::Fps5 => Some(5.0),
-        RecordingFrameRate::Fps10 => Some(10.0),
-        RecordingFrameRate::Fps20 => Some(20.0),
-        RecordingFrameRate::Fps25 => Some(25.0),
-        RecordingFrameRate::Fps30 => Some(30.0),
-        RecordingFrameRate::Fps40 => Some(40.0),
-        RecordingFrameRate::Fps50 => Some(50.0),
-        RecordingFrameRate::Fps60 => Some(60.0),
-        RecordingFrameRate::Fps100 => Some(100.0),
-        RecordingFrameRate::Unlimited => None,
-    }
+    rate_enum.as_f64().map(|x| x as f32)
 }
 
 // -----------------------------------------------------------------------------
@@ -1440,6 +1831,17 @@ fn match_avail(avail: &[CodecSelection], selected: &CodecSelection) -> Option<Co
     }
 }
 
+fn view_self_test_checks(report: &SelfTestReport) -> Html {
+    let checks = report.checks.as_deref().unwrap_or_default();
+    html! {
+        <ul>
+            { for checks.iter().map(|check| html! {
+                <li>{format!("{}: {} ({})", check.name, if check.passed {"passed"} else {"failed"}, check.message)}</li>
+            }) }
+        </ul>
+    }
+}
+
 #[wasm_bindgen(start)]
 pub fn run_app() {
     wasm_logger::init(wasm_logger::Config::default());
@@ -16,6 +16,9 @@ pub type UFMFResult<M> = std::result::Result<M, UFMFError>;
 
 mod save_indices;
 
+pub mod reader;
+pub use crate::reader::UFMFReader;
+
 #[derive(Debug, thiserror::Error)]
 pub enum UFMFError {
     #[error("unimplemented pixel_format {0}")]
@@ -27,6 +30,13 @@ pub enum UFMFError {
     #[error("the pixel format changed")]
     FormatChanged,
 
+    #[error("unimplemented UFMF file version {0}. Only UFMF v3 files supported.")]
+    UnimplementedVersion(u32),
+    #[error("unknown coding {0}")]
+    UnknownCoding(String),
+    #[error("invalid UFMF file: {0}")]
+    InvalidFile(String),
+
     #[error("{source}")]
     Io {
         #[from]
@@ -642,6 +652,39 @@ mod tests {
         assert_eq!(&buf[0..], expected);
     }
 
+    #[test]
+    fn test_reading_regions() {
+        let arr = arange(0, 123.456);
+        let f = std::io::Cursor::new(Vec::new());
+        let mut writer = UFMFWriter::new(f, 10, 10, PixFmt::Mono8, Some(&arr)).unwrap();
+        let arr2 = arange(100, 124.0);
+        let point_data = vec![RectFromCenter::from_xy_wh(4, 4, 4, 4)];
+        writer.add_frame(&arr2, &point_data).unwrap();
+        let buf = writer.close().unwrap().into_inner();
+
+        let reader = UFMFReader::new(std::io::Cursor::new(buf)).unwrap();
+        assert_eq!((reader.width(), reader.height()), (10, 10));
+        assert_eq!(reader.format(), PixFmt::Mono8);
+        let frames: Vec<_> = reader.map(|f| f.unwrap()).collect();
+        assert_eq!(frames.len(), 1);
+        let frame = &frames[0];
+        assert_eq!(
+            datetime_conversion::datetime_to_f64(&frame.extra().host_timestamp()),
+            124.0
+        );
+        // The region (x0 2, y0 2, w 4, h 4) is from the frame, the rest
+        // from the background "frame0" keyframe.
+        let data = frame.image_data_without_format();
+        for y in 0..10 {
+            for x in 0..10 {
+                let i = y * 10 + x;
+                let in_region = (2..6).contains(&x) && (2..6).contains(&y);
+                let expected = if in_region { 100 + i } else { i };
+                assert_eq!(data[i], expected as u8, "pixel ({x}, {y})");
+            }
+        }
+    }
+
     #[test]
     fn test_float_keyframe() {
         use formats::pixel_format::Mono32f;
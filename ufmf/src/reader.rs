@@ -0,0 +1,247 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use basic_frame::{BasicExtra, DynamicFrame};
+use formats::{pixel_format::PixFmt, PixelFormat};
+
+use crate::{UFMFError, UFMFResult, FRAME_CHUNK, INDEX_DICT_CHUNK, KEYFRAME_CHUNK};
+
+/// Keyframe type of the first full frame saved when a recording starts.
+const FRAME0_KEYFRAME: &[u8] = b"frame0";
+/// Keyframe type of the mean background image.
+const MEAN_KEYFRAME: &[u8] = b"mean";
+
+fn get_pixel_format(coding: &[u8]) -> UFMFResult<PixFmt> {
+    use PixFmt::*;
+    let r = match coding {
+        b"MONO8" => Mono8,
+        b"RAW8:RGGB" => BayerRG8,
+        b"RAW8:GBRG" => BayerGB8,
+        b"RAW8:GRBG" => BayerGR8,
+        b"RAW8:BGGR" => BayerBG8,
+        b"YUV422" => YUV422,
+        b"RGB8" => RGB8,
+        c => {
+            return Err(UFMFError::UnknownCoding(
+                String::from_utf8_lossy(c).into_owned(),
+            ));
+        }
+    };
+    Ok(r)
+}
+
+/// Reads UFMF (micro fly movie format) movie files.
+///
+/// UFMF files save full keyframes, such as the mean background image, and
+/// only small regions around detected features of all other frames. When
+/// iterating, each frame is reconstructed by drawing its regions onto the most
+/// recent background image. The "mean" keyframe is used as background, or
+/// the "frame0" keyframe before the first "mean" keyframe. Areas outside of
+/// the regions of a frame thus do not show the actual image data of that
+/// frame.
+///
+/// Only UFMF v3 files are supported.
+pub struct UFMFReader<R: Read> {
+    f: R,
+    width: u16,
+    height: u16,
+    pixel_format: PixFmt,
+    bytes_per_pixel: usize,
+    background: Vec<u8>,
+    have_mean: bool,
+    count: usize,
+    done: bool,
+}
+
+impl UFMFReader<BufReader<File>> {
+    /// Open the UFMF file at `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> UFMFResult<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> UFMFReader<R> {
+    /// Read the header of a UFMF file from `f`.
+    pub fn new(mut f: R) -> UFMFResult<Self> {
+        let mut magic = [0u8; 4];
+        f.read_exact(&mut magic)?;
+        if &magic != b"ufmf" {
+            return Err(UFMFError::InvalidFile("not a UFMF file".into()));
+        }
+        let version = f.read_u32::<LittleEndian>()?;
+        if version != 3 {
+            return Err(UFMFError::UnimplementedVersion(version));
+        }
+        let _index_loc = f.read_u64::<LittleEndian>()?;
+        let width = f.read_u16::<LittleEndian>()?;
+        let height = f.read_u16::<LittleEndian>()?;
+        let coding_len = f.read_u8()?;
+        let mut coding = vec![0u8; coding_len.into()];
+        f.read_exact(&mut coding)?;
+        let pixel_format = get_pixel_format(&coding)?;
+        let bytes_per_pixel = usize::from(pixel_format.bits_per_pixel() / 8);
+        // Until the first keyframe, the background is black.
+        let background = vec![0; usize::from(width) * usize::from(height) * bytes_per_pixel];
+
+        Ok(Self {
+            f,
+            width,
+            height,
+            pixel_format,
+            bytes_per_pixel,
+            background,
+            have_mean: false,
+            count: 0,
+            done: false,
+        })
+    }
+
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.width.into()
+    }
+
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.height.into()
+    }
+
+    #[inline]
+    pub fn format(&self) -> PixFmt {
+        self.pixel_format
+    }
+
+    fn stride(&self) -> usize {
+        usize::from(self.width) * self.bytes_per_pixel
+    }
+
+    fn read_keyframe(&mut self) -> UFMFResult<()> {
+        let type_len = self.f.read_u8()?;
+        let mut keyframe_type = vec![0u8; type_len.into()];
+        self.f.read_exact(&mut keyframe_type)?;
+        let dtype = self.f.read_u8()?;
+        let width = self.f.read_u16::<LittleEndian>()?;
+        let height = self.f.read_u16::<LittleEndian>()?;
+        let _timestamp = self.f.read_f64::<LittleEndian>()?;
+        let n_pixels = usize::from(width) * usize::from(height);
+
+        let data = match dtype {
+            b'B' => {
+                let mut data = vec![0u8; n_pixels * self.bytes_per_pixel];
+                self.f.read_exact(&mut data)?;
+                data
+            }
+            b'f' => {
+                let mut values = vec![0f32; n_pixels];
+                self.f.read_f32_into::<LittleEndian>(&mut values)?;
+                if self.bytes_per_pixel != 1 {
+                    // Keep reading, but the keyframe cannot be used as
+                    // background.
+                    return Ok(());
+                }
+                values
+                    .iter()
+                    .map(|v| v.round().clamp(0.0, 255.0) as u8)
+                    .collect()
+            }
+            d => {
+                return Err(UFMFError::InvalidFile(format!(
+                    "unknown keyframe dtype {:?}",
+                    char::from(d)
+                )));
+            }
+        };
+
+        let is_frame0 = keyframe_type == FRAME0_KEYFRAME && !self.have_mean;
+        let is_mean = keyframe_type == MEAN_KEYFRAME;
+        if (is_frame0 || is_mean) && width == self.width && height == self.height {
+            self.background = data;
+            self.have_mean |= is_mean;
+        }
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> UFMFResult<DynamicFrame> {
+        let timestamp = self.f.read_f64::<LittleEndian>()?;
+        let n_regions = self.f.read_u16::<LittleEndian>()?;
+        let stride = self.stride();
+        let mut image_data = self.background.clone();
+        for _ in 0..n_regions {
+            let x0 = usize::from(self.f.read_u16::<LittleEndian>()?);
+            let y0 = usize::from(self.f.read_u16::<LittleEndian>()?);
+            let w = usize::from(self.f.read_u16::<LittleEndian>()?);
+            let h = usize::from(self.f.read_u16::<LittleEndian>()?);
+            if x0 + w > usize::from(self.width) || y0 + h > usize::from(self.height) {
+                return Err(UFMFError::InvalidFile(format!(
+                    "region ({x0}, {y0}, {w}, {h}) outside of image"
+                )));
+            }
+            let row_bytes = w * self.bytes_per_pixel;
+            for y in y0..y0 + h {
+                let start = y * stride + x0 * self.bytes_per_pixel;
+                self.f
+                    .read_exact(&mut image_data[start..start + row_bytes])?;
+            }
+        }
+
+        let extra = Box::new(BasicExtra {
+            host_timestamp: datetime_conversion::f64_to_datetime(timestamp),
+            host_framenumber: self.count,
+        });
+        self.count += 1;
+        Ok(DynamicFrame::new(
+            self.width.into(),
+            self.height.into(),
+            stride.try_into().unwrap(),
+            extra,
+            image_data,
+            self.pixel_format,
+        ))
+    }
+
+    /// Read chunks until the next frame.
+    fn next_frame(&mut self) -> UFMFResult<Option<DynamicFrame>> {
+        loop {
+            let chunk_id = match self.f.read_u8() {
+                Ok(chunk_id) => chunk_id,
+                // A file which was not closed has no index.
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            match chunk_id {
+                KEYFRAME_CHUNK => self.read_keyframe()?,
+                FRAME_CHUNK => return Ok(Some(self.read_frame()?)),
+                INDEX_DICT_CHUNK => return Ok(None),
+                c => {
+                    return Err(UFMFError::InvalidFile(format!("unknown chunk id {c}")));
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for UFMFReader<R> {
+    type Item = UFMFResult<DynamicFrame>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_frame() {
+            Ok(Some(frame)) => Some(Ok(frame)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                // Encountered error. Do not read more.
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
@@ -7,6 +7,8 @@ use std::{convert::TryInto, pin::Pin};
 
 use machine_vision_formats as formats;
 
+pub mod packed;
+
 use vmbc_sys::{
     VmbCameraInfo_t, VmbErrorType, VmbFeaturePersistSettings_t, VmbFrameCallback,
     VmbFrameStatusType, VmbFrame_t, VmbHandle_t, VmbVersionInfo_t,
@@ -588,6 +590,19 @@ impl<'lib> Camera<'lib> {
         Ok(())
     }
 
+    /// Return `true` if the command started with [Self::command_run] has
+    /// finished.
+    pub fn command_is_done(&self, command_name: &str) -> Result<bool> {
+        let mut result = 0;
+        let data = std::ffi::CString::new(command_name)?;
+        vimba_call!(self.vimba_lib.VmbFeatureCommandIsDone(
+            self.handle,
+            data.as_ptr(),
+            &mut result
+        ))?;
+        Ok(result != 0)
+    }
+
     pub fn pixel_format(&self) -> Result<formats::pixel_format::PixFmt> {
         let pixel_format = self.feature_enum("PixelFormat")?;
         str_to_pixel_format(pixel_format)
@@ -820,6 +835,13 @@ impl Frame {
     pub fn pixel_format(&self) -> Result<formats::PixFmt> {
         pixel_format_code(self.frame.pixelFormat)
     }
+    /// The pixels unpacked to 16 bit values if the pixel format is packed,
+    /// see [packed].
+    pub fn unpacked(&self) -> Option<Vec<u16>> {
+        let format = packed::PackedFormat::from_pixel_format_code(self.frame.pixelFormat)?;
+        let n_pixels = self.width() as usize * self.height() as usize;
+        packed::unpack(format, self.buffer(), n_pixels)
+    }
 }
 
 pub fn pixel_format_code(code: u32) -> Result<formats::PixFmt> {
@@ -833,6 +855,16 @@ pub fn pixel_format_code(code: u32) -> Result<formats::PixFmt> {
         VmbPixelFormatBayerGB8 => BayerGB8,
         VmbPixelFormatBayerBG8 => BayerBG8,
         VmbPixelFormatRgb8 => RGB8,
+        #[cfg(feature = "packed-pixel-formats")]
+        VmbPixelFormatMono10p => Mono10p,
+        #[cfg(feature = "packed-pixel-formats")]
+        VmbPixelFormatMono12p => Mono12p,
+        #[cfg(feature = "packed-pixel-formats")]
+        VmbPixelFormatMono12Packed => Mono12Packed,
+        #[cfg(feature = "packed-pixel-formats")]
+        VmbPixelFormatBayerRG12p => BayerRG12p,
+        #[cfg(feature = "packed-pixel-formats")]
+        VmbPixelFormatBayerRG12Packed => BayerRG12Packed,
         // VmbPixelFormatMono10 => Mono10,
         // VmbPixelFormatMono10p => Mono10p,
         // VmbPixelFormatMono12 => Mono12,
@@ -855,6 +887,16 @@ pub fn str_to_pixel_format(pixel_format: &str) -> Result<formats::pixel_format::
         "Mono8" => Mono8,
         "RGB8" => RGB8,
         "BayerRG8" => BayerRG8,
+        #[cfg(feature = "packed-pixel-formats")]
+        "Mono10p" => Mono10p,
+        #[cfg(feature = "packed-pixel-formats")]
+        "Mono12p" => Mono12p,
+        #[cfg(feature = "packed-pixel-formats")]
+        "Mono12Packed" => Mono12Packed,
+        #[cfg(feature = "packed-pixel-formats")]
+        "BayerRG12p" => BayerRG12p,
+        #[cfg(feature = "packed-pixel-formats")]
+        "BayerRG12Packed" => BayerRG12Packed,
         // "Mono10" => Mono10,
         // "Mono10p" => Mono10p,
         // "Mono12" => Mono12,
@@ -875,6 +917,16 @@ pub fn pixel_format_to_str(pixfmt: formats::pixel_format::PixFmt) -> Result<&'st
     Ok(match pixfmt {
         Mono8 => "Mono8",
         RGB8 => "RGB8",
+        #[cfg(feature = "packed-pixel-formats")]
+        Mono10p => "Mono10p",
+        #[cfg(feature = "packed-pixel-formats")]
+        Mono12p => "Mono12p",
+        #[cfg(feature = "packed-pixel-formats")]
+        Mono12Packed => "Mono12Packed",
+        #[cfg(feature = "packed-pixel-formats")]
+        BayerRG12p => "BayerRG12p",
+        #[cfg(feature = "packed-pixel-formats")]
+        BayerRG12Packed => "BayerRG12Packed",
         // Mono10 => "Mono10",
         // Mono10p => "Mono10p",
         // Mono12 => "Mono12",
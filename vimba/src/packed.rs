@@ -0,0 +1,135 @@
+//! Unpacking of packed 10 and 12 bit pixel formats.
+//!
+//! Each pixel is unpacked to a 16 bit value with the original bit depth, i.e.
+//! the pixel layout of `Mono16`. The Bayer variants of these formats are
+//! packed in the same way as the mono formats, so their unpacked data keeps
+//! the Bayer pattern.
+
+use vmbc_sys::VmbPixelFormatType::*;
+
+/// A packed pixel format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackedFormat {
+    /// 10 bit pixels packed without padding, least significant bit first
+    /// (GenICam `Mono10p`, `BayerRG10p`, ...).
+    Bits10p,
+    /// 12 bit pixels packed without padding, least significant bit first
+    /// (GenICam `Mono12p`, `BayerRG12p`, ...).
+    Bits12p,
+    /// Pairs of 12 bit pixels in 3 bytes, with the upper 8 bits of each pixel
+    /// in the first and last byte and the lower 4 bits in the middle byte
+    /// (GigE Vision `Mono12Packed`, `BayerRG12Packed`, ...).
+    Bits12Packed,
+}
+
+impl PackedFormat {
+    /// The packed format of a Vimba pixel format code, if packed.
+    #[allow(non_upper_case_globals)]
+    pub fn from_pixel_format_code(code: u32) -> Option<Self> {
+        Some(match code {
+            VmbPixelFormatMono10p
+            | VmbPixelFormatBayerGR10p
+            | VmbPixelFormatBayerRG10p
+            | VmbPixelFormatBayerGB10p
+            | VmbPixelFormatBayerBG10p => Self::Bits10p,
+            VmbPixelFormatMono12p
+            | VmbPixelFormatBayerGR12p
+            | VmbPixelFormatBayerRG12p
+            | VmbPixelFormatBayerGB12p
+            | VmbPixelFormatBayerBG12p => Self::Bits12p,
+            VmbPixelFormatMono12Packed
+            | VmbPixelFormatBayerGR12Packed
+            | VmbPixelFormatBayerRG12Packed
+            | VmbPixelFormatBayerGB12Packed
+            | VmbPixelFormatBayerBG12Packed => Self::Bits12Packed,
+            _ => return None,
+        })
+    }
+
+    /// The number of significant bits of each pixel.
+    pub fn bit_depth(&self) -> u32 {
+        match self {
+            Self::Bits10p => 10,
+            Self::Bits12p | Self::Bits12Packed => 12,
+        }
+    }
+
+    /// The number of bytes of `n_pixels` packed pixels.
+    pub fn packed_len(&self, n_pixels: usize) -> usize {
+        (n_pixels * self.bit_depth() as usize).div_ceil(8)
+    }
+}
+
+/// Unpack `n_pixels` pixels of `format` from `buf`.
+///
+/// Returns `None` if `buf` is shorter than the packed pixels.
+pub fn unpack(format: PackedFormat, buf: &[u8], n_pixels: usize) -> Option<Vec<u16>> {
+    let buf = buf.get(..format.packed_len(n_pixels))?;
+    let mut result = Vec::with_capacity(n_pixels);
+    match format {
+        PackedFormat::Bits10p | PackedFormat::Bits12p => {
+            let bit_depth = format.bit_depth();
+            let mask = (1u32 << bit_depth) - 1;
+            let mut acc = 0u32;
+            let mut n_bits = 0;
+            for byte in buf {
+                acc |= u32::from(*byte) << n_bits;
+                n_bits += 8;
+                while n_bits >= bit_depth && result.len() < n_pixels {
+                    result.push((acc & mask) as u16);
+                    acc >>= bit_depth;
+                    n_bits -= bit_depth;
+                }
+            }
+        }
+        PackedFormat::Bits12Packed => {
+            for chunk in buf.chunks(3) {
+                let b0 = u16::from(chunk[0]);
+                let b1 = u16::from(chunk[1]);
+                result.push((b0 << 4) | (b1 & 0x0F));
+                if let Some(b2) = chunk.get(2) {
+                    result.push((u16::from(*b2) << 4) | (b1 >> 4));
+                }
+            }
+            result.truncate(n_pixels);
+        }
+    }
+    Some(result)
+}
+
+#[test]
+fn test_unpack() {
+    // 4 pixels in 5 bytes.
+    let pixels = [0x3FF, 0x001, 0x155, 0x2AA];
+    let mut bits = 0u64;
+    for (i, px) in pixels.iter().enumerate() {
+        bits |= (*px as u64) << (10 * i);
+    }
+    let buf = bits.to_le_bytes();
+    assert_eq!(unpack(PackedFormat::Bits10p, &buf[..5], 4).unwrap(), pixels);
+    assert_eq!(unpack(PackedFormat::Bits10p, &buf[..4], 4), None);
+
+    // 2 pixels in 3 bytes.
+    assert_eq!(
+        unpack(PackedFormat::Bits12p, &[0x23, 0x41, 0xAB], 2).unwrap(),
+        [0x123, 0xAB4]
+    );
+    assert_eq!(
+        unpack(
+            PackedFormat::Bits12Packed,
+            &[0x12, 0x43, 0xAB, 0xCD, 0x0E],
+            3
+        )
+        .unwrap(),
+        [0x123, 0xAB4, 0xCDE]
+    );
+
+    assert_eq!(
+        PackedFormat::from_pixel_format_code(VmbPixelFormatBayerRG12Packed),
+        Some(PackedFormat::Bits12Packed)
+    );
+    assert_eq!(
+        PackedFormat::from_pixel_format_code(VmbPixelFormatMono8),
+        None
+    );
+}